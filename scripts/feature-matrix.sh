@@ -0,0 +1,23 @@
+#!/bin/sh
+# Checks that every optional subsystem builds on its own, without the others and all together
+set -eu
+
+FEATURES="models server sso-github sso-apple sso-twitch sso-steam sso-discord sso-oidc federation mail-mailgun mail-smtp metrics hooks wasm-policies cache selftest sdk verify layer"
+
+check() {
+    echo "==> features: ${1:-none}"
+    cargo clippy --all-targets --no-default-features --features "$1" -- -D warnings
+}
+
+check ""
+for feature in $FEATURES; do
+    check "$feature"
+done
+
+if rustup target list --installed 2>/dev/null | grep -q wasm32-unknown-unknown; then
+    echo "==> features: verify (wasm32)"
+    cargo clippy --target wasm32-unknown-unknown --no-default-features --features verify -- -D warnings
+fi
+
+echo "==> features: all"
+cargo clippy --all-targets --all-features -- -D warnings
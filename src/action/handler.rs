@@ -1,14 +1,17 @@
 use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
     authentication::{
+        credential::CredentialHasher,
         password::{self, Hibp},
         token::TokenConfig,
     },
+    config::GlobalConfig,
     database::Database,
-    extract::{Query, SizedJson, TokenData},
+    extract::{ClientInfo, Query, SizedJson, TokenData},
     mail,
     model::Status,
-    user::{Role, UserDocument, UserError},
-    utils, GlobalConfig,
+    user::{AccountFlag, Role, UserDocument, UserError},
+    utils,
 };
 
 use super::{send_reset_mail, send_verification_mail, ActionClaims, ActionError, ActionType};
@@ -31,6 +34,7 @@ pub async fn register(
     Extension(db): Extension<Database>,
     Extension(global): Extension<GlobalConfig>,
     Extension(hibp): Extension<Hibp>,
+    Extension(hasher): Extension<CredentialHasher>,
     Extension(mail): Extension<mail::Client>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Status> {
@@ -44,7 +48,8 @@ pub async fn register(
         return Err(UserError::AlreadyExists.into());
     }
 
-    let password_hash = password::validate_and_hash(&body.password)?;
+    let password_hash =
+        password::validate_and_hash(&hasher, &global.password_policy, &body.password)?;
 
     if global.hibp_check_enabled {
         hibp.check_password(&body.password).await?;
@@ -127,9 +132,12 @@ pub struct ResetRequest {
 }
 
 pub async fn reset_password(
+    client: ClientInfo,
     TokenData(claims): TokenData<ActionClaims>,
     SizedJson(body): SizedJson<ResetRequest>,
     Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(hasher): Extension<CredentialHasher>,
 ) -> crate::Result<Status> {
     if claims.r#type != ActionType::Reset {
         return Err(ActionError::InvalidToken.into());
@@ -137,10 +145,18 @@ pub async fn reset_password(
 
     let user_id = ObjectId::parse_str(claims.sub).unwrap();
 
-    let password_hash = password::validate_and_hash(&body.password)?;
+    let password_hash =
+        password::validate_and_hash(&hasher, &global.password_policy, &body.password)?;
 
     db.update_user_by_id(user_id, doc! { "password": password_hash })
         .await?;
+    // Whoever caused the lockout doesn't know the new password
+    db.reset_login_failures(user_id).await?;
+    db.remove_user_flag(user_id, AccountFlag::PasswordResetRequired)
+        .await?;
+
+    let event = AuditEventDocument::new(user_id, SecurityEvent::PasswordChanged, &client);
+    db.insert_audit_event(&event).await?;
 
     Ok(Status::new(StatusCode::OK, "new password set"))
 }
@@ -13,6 +13,7 @@ use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
 use hyper::StatusCode;
 use serde::{Deserialize, Serialize};
 
+pub use handler::verify_email;
 pub use routes::routes;
 
 #[derive(Debug, thiserror::Error)]
@@ -71,14 +72,14 @@ pub struct ActionClaims {
 }
 
 impl ActionClaims {
-    fn new<A>(aud: A, sub: &str, r#type: ActionType) -> Self
+    fn new<A>(aud: A, sub: &str, r#type: ActionType, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
     {
         Self {
             aud: aud.into_iter().collect(),
-            exp: Utc::now() + Duration::from_std(r#type.expiration_time()).unwrap(),
-            iat: Utc::now(),
+            exp: now + Duration::from_std(r#type.expiration_time()).unwrap(),
+            iat: now,
             sub: sub.into(),
             email: None,
             r#type,
@@ -86,11 +87,11 @@ impl ActionClaims {
         }
     }
 
-    fn with_email<A>(aud: A, sub: &str, email: &str) -> Self
+    fn with_email<A>(aud: A, sub: &str, email: &str, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
     {
-        let mut claims = Self::new(aud, sub, ActionType::Verify);
+        let mut claims = Self::new(aud, sub, ActionType::Verify, now);
         claims.email = Some(email.into());
 
         claims
@@ -103,6 +104,10 @@ impl TokenClaims for ActionClaims {
     fn get_type(&self) -> &TokenType {
         &self.token_type
     }
+
+    fn subject(&self) -> Option<&str> {
+        Some(&self.sub)
+    }
 }
 
 pub async fn send_verification_mail(
@@ -112,7 +117,7 @@ pub async fn send_verification_mail(
     config: TokenConfig,
 ) -> crate::Result<()> {
     let audience = config.validation.aud.clone().unwrap();
-    let claims = ActionClaims::with_email(audience, user_id, addr);
+    let claims = ActionClaims::with_email(audience, user_id, addr, config.clock.now());
 
     let token = claims.encode(&config)?;
 
@@ -129,14 +134,14 @@ pub async fn send_verification_mail(
     Ok(())
 }
 
-async fn send_reset_mail(
+pub async fn send_reset_mail(
     addr: &str,
     user_id: &str,
     client: mail::Client,
     config: TokenConfig,
 ) -> crate::Result<()> {
     let audience = config.validation.aud.clone().unwrap();
-    let claims = ActionClaims::new(audience, user_id, ActionType::Reset);
+    let claims = ActionClaims::new(audience, user_id, ActionType::Reset, config.clock.now());
 
     let token = claims.encode(&config)?;
 
@@ -0,0 +1,51 @@
+use crate::{
+    authentication::AuthenticationError,
+    database::Database,
+    extract::{Query, TokenData},
+    model::{List, ListOptions, Response},
+    session::SessionClaims,
+    user::UserError,
+};
+
+use super::AuditEventResponse;
+
+use axum::extract::Extension;
+use mongodb::bson::{oid::ObjectId, Document};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Filter {
+    user: Option<String>,
+    actor: Option<String>,
+    /// Event type, e.g. `loginFailed`
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+pub async fn list(
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(filter): Query<Filter>,
+    Query(opts): Query<ListOptions>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<AuditEventResponse>>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let mut f = Document::new();
+    if let Some(id) = filter.user {
+        let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+        f.insert("user", id);
+    }
+    if let Some(v) = filter.actor {
+        f.insert("actor", v);
+    }
+    if let Some(v) = filter.kind {
+        f.insert("event.type", v);
+    }
+
+    let (events, total) = db.get_audit_events(f, opts).await?;
+
+    Ok(Response::new(List::new(total, events)))
+}
@@ -0,0 +1,255 @@
+//! Audit trail of security relevant events.
+//!
+//! Events are only ever inserted, there is no API to change or delete them. Each event names
+//! the account it concerns and, if known, the actor whose token caused it.
+
+mod handler;
+mod routes;
+
+use crate::{
+    database::Database, extract::ClientInfo, model::ListOptions, revision, user::Role, Result,
+};
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+pub use routes::routes;
+
+/// Security relevant event of a user account
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum SecurityEvent {
+    #[serde(rename_all = "camelCase")]
+    Login {
+        method: String,
+        new_device: bool,
+    },
+    /// A login was rejected because of wrong credentials
+    LoginFailed {
+        method: String,
+    },
+    PasswordChanged,
+    EmailChanged,
+    SessionsRevoked,
+    RefreshTokenReused,
+    LoginLocked,
+    MfaEnabled,
+    MfaDisabled,
+    RecoveryCodeUsed,
+    /// All logins are rejected until an admin unlocks the account
+    AccountLocked,
+    /// All logins are rejected until the given date
+    #[serde(rename_all = "camelCase")]
+    AccountBanned {
+        #[serde(with = "ts_seconds")]
+        until: DateTime<Utc>,
+    },
+    AccountUnlocked,
+    /// The roles were replaced by an admin
+    RolesChanged {
+        roles: Vec<Role>,
+    },
+    /// A role was granted for a limited time
+    #[serde(rename_all = "camelCase")]
+    RoleElevated {
+        role: Role,
+        #[serde(with = "ts_seconds")]
+        expires_at: DateTime<Utc>,
+    },
+    /// A temporarily granted role was taken back
+    ElevationExpired {
+        role: Role,
+    },
+    /// Grants of sensitive roles wait for approval
+    RoleChangeRequested {
+        roles: Vec<Role>,
+    },
+    RoleChangeApproved {
+        roles: Vec<Role>,
+    },
+    RoleChangeRejected {
+        roles: Vec<Role>,
+    },
+    /// Access was rejected outside of the schedule of a role or client
+    #[serde(rename_all = "camelCase")]
+    OutsideSchedule {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client: Option<String>,
+    },
+    /// A token was issued for a client of the account
+    TokenIssued {
+        client: String,
+        grant: String,
+    },
+    ClientCreated {
+        client: String,
+    },
+    ClientUpdated {
+        client: String,
+    },
+    ClientDeleted {
+        client: String,
+    },
+    ClientSecretRotated {
+        client: String,
+    },
+    /// The user authorized a client for a part of its scope
+    ClientAuthorized {
+        client: String,
+        scope: Vec<String>,
+    },
+    /// The user revoked the consent to a client
+    ConsentRevoked {
+        client: String,
+    },
+    /// A single session was ended by the user or an admin
+    SessionRevoked {
+        jti: String,
+    },
+    /// A canary client was used
+    CanaryTriggered {
+        client: String,
+    },
+    /// Tokens issued before were invalidated, of the whole deployment or only of the user
+    TokenEpochBumped {
+        epoch: u32,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        deployment: bool,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    /// Subject of the token of the request, if it wasn't anonymous
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    pub event: SecurityEvent,
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub date: DateTime<Utc>,
+}
+
+impl AuditEventDocument {
+    pub fn new(user: ObjectId, event: SecurityEvent, client: &ClientInfo) -> Self {
+        Self {
+            id: ObjectId::new(),
+            user,
+            actor: revision::current_actor(),
+            event,
+            addr: client.addr.map(|v| v.to_string()),
+            user_agent: client.user_agent.clone(),
+            date: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventResponse {
+    pub id: String,
+    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    #[serde(flatten)]
+    pub event: SecurityEvent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+}
+
+impl From<AuditEventDocument> for AuditEventResponse {
+    fn from(doc: AuditEventDocument) -> Self {
+        Self {
+            id: doc.id.to_hex(),
+            user: doc.user.to_hex(),
+            actor: doc.actor,
+            event: doc.event,
+            addr: doc.addr,
+            user_agent: doc.user_agent,
+            date: doc.date,
+        }
+    }
+}
+
+const COLLECTION: &str = "audit_events";
+
+impl Database {
+    /// Returns the events matching the filter, newest first
+    pub async fn get_audit_events(
+        &self,
+        filter: Document,
+        opts: ListOptions,
+    ) -> Result<(Vec<AuditEventDocument>, u64)> {
+        let coll = self.collection::<AuditEventDocument>(COLLECTION);
+
+        let total = coll.count_documents(filter.clone(), None).await?;
+
+        if total == 0 {
+            return Ok((Vec::new(), 0));
+        }
+
+        let opts = FindOptions::builder()
+            .batch_size(opts.limit as u32)
+            .skip(opts.offset)
+            .limit(opts.limit)
+            .sort(doc! { "date": -1 })
+            .build();
+
+        let cursor = coll.find(filter, opts).await?;
+
+        let events = cursor.try_collect().await?;
+
+        Ok((events, total))
+    }
+
+    pub async fn insert_audit_event(&self, doc: &AuditEventDocument) -> Result<()> {
+        self.collection::<AuditEventDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a login, devices are identified by their user agent
+    pub async fn record_login(
+        &self,
+        user: ObjectId,
+        method: &str,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        let new_device = match &client.user_agent {
+            Some(ua) => self
+                .collection::<AuditEventDocument>(COLLECTION)
+                .find_one(
+                    doc! { "user": user, "event.type": "login", "userAgent": ua },
+                    None,
+                )
+                .await?
+                .is_none(),
+            None => false,
+        };
+
+        let event = SecurityEvent::Login {
+            method: method.to_string(),
+            new_device,
+        };
+
+        self.insert_audit_event(&AuditEventDocument::new(user, event, client))
+            .await
+    }
+}
@@ -0,0 +1,8 @@
+use super::handler;
+
+use axum::routing::get;
+
+/// Audit trail routes
+pub fn routes() -> axum::Router {
+    axum::Router::new().route("/", get(handler::list))
+}
@@ -0,0 +1,53 @@
+//! Secrets of clients for the client credentials grant.
+//!
+//! Secrets are random and returned only once on rotation, only their hashes are stored.
+
+use crate::Result;
+
+use super::{credential::CredentialHasher, AuthenticationError};
+
+use tracing::error;
+
+const SECRET_LEN: usize = 32;
+
+/// Generates a new secret, returns it with its hash
+pub fn generate(hasher: &CredentialHasher) -> Result<(String, String)> {
+    let secret = base64::encode_config(rand::random::<[u8; SECRET_LEN]>(), base64::URL_SAFE_NO_PAD);
+
+    let hash = hasher.hash(&secret).map_err(|e| {
+        error!("Error while hashing client secret: {:?}", e);
+        AuthenticationError::BadClientCredentials
+    })?;
+
+    Ok((secret, hash))
+}
+
+/// Verifies the secret of a client.
+///
+/// Returns a new hash if the stored one is outdated.
+pub fn verify(
+    hasher: &CredentialHasher,
+    secret: &str,
+    hash: Option<&str>,
+) -> Result<Option<String>> {
+    let hash = hash.ok_or(AuthenticationError::BadClientCredentials)?;
+
+    hasher
+        .verify(secret, hash)
+        .map_err(|_| AuthenticationError::BadClientCredentials.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_generated() {
+        let hasher = CredentialHasher::new(None, Vec::new()).unwrap();
+        let (secret, hash) = generate(&hasher).unwrap();
+
+        assert!(verify(&hasher, &secret, Some(&hash)).is_ok());
+        assert!(verify(&hasher, "wrong", Some(&hash)).is_err());
+        assert!(verify(&hasher, &secret, None).is_err());
+    }
+}
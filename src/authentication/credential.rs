@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use argon2::{
+    password_hash::{
+        Error as A2Error, PasswordHash, PasswordHasher, PasswordVerifier, Result as A2Result,
+        SaltString,
+    },
+    Algorithm, Argon2, Params, ParamsBuilder, Version,
+};
+use rand::rngs::OsRng;
+
+// Argon2 config, hashes with other parameters are upgraded on verification
+const ALGO: Algorithm = Algorithm::Argon2id;
+const VERSION: Version = Version::V0x13;
+const M_COST: u32 = 4 << 10;
+const T_COST: u32 = 3;
+const P_COST: u32 = 2;
+
+/// Server-side secret mixed into every hash, identified by its version
+#[derive(Clone)]
+pub struct Pepper {
+    pub version: u8,
+    pub secret: Vec<u8>,
+}
+
+/// Credential hasher with versioned parameters and peppers.
+///
+/// The parameters and the pepper version are recorded in every hash, so hashes created
+/// with outdated parameters or peppers are still verifiable and get rehashed on verification.
+#[derive(Clone)]
+pub struct CredentialHasher {
+    pepper: Option<Pepper>,
+    previous: Arc<Vec<Pepper>>,
+}
+
+impl CredentialHasher {
+    pub fn new(pepper: Option<Pepper>, previous: Vec<Pepper>) -> A2Result<Self> {
+        for p in pepper.iter().chain(previous.iter()) {
+            Argon2::new_with_secret(&p.secret, ALGO, VERSION, Params::default())?;
+        }
+
+        Ok(Self {
+            pepper,
+            previous: Arc::new(previous),
+        })
+    }
+
+    fn params(&self) -> A2Result<Params> {
+        let mut builder = ParamsBuilder::new();
+        builder.m_cost(M_COST)?.t_cost(T_COST)?.p_cost(P_COST)?;
+
+        if let Some(p) = &self.pepper {
+            builder.keyid(&[p.version])?;
+        }
+
+        Ok(builder.params()?)
+    }
+
+    fn find_pepper(&self, keyid: &[u8]) -> A2Result<Option<&Pepper>> {
+        match keyid {
+            [] => Ok(None),
+            [version] => self
+                .pepper
+                .iter()
+                .chain(self.previous.iter())
+                .find(|p| p.version == *version)
+                .map(Some)
+                .ok_or(A2Error::Password),
+            _ => Err(A2Error::ParamValueInvalid(
+                argon2::password_hash::errors::InvalidValue::Malformed,
+            )),
+        }
+    }
+
+    pub fn hash<S: AsRef<[u8]>>(&self, password: S) -> A2Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        let argon2 = match &self.pepper {
+            Some(p) => Argon2::new_with_secret(&p.secret, ALGO, VERSION, self.params()?)?,
+            None => Argon2::new(ALGO, VERSION, self.params()?),
+        };
+
+        let hash = argon2.hash_password(password.as_ref(), salt.as_ref())?;
+
+        Ok(hash.to_string())
+    }
+
+    /// Verifies the password against the hash.
+    ///
+    /// Returns a new hash if the hash was created with outdated parameters or pepper.
+    pub fn verify<S, H>(&self, password: S, hash: H) -> A2Result<Option<String>>
+    where
+        S: AsRef<[u8]>,
+        H: AsRef<str>,
+    {
+        let parsed_hash = PasswordHash::new(hash.as_ref())?;
+        let params = Params::try_from(&parsed_hash)?;
+
+        let argon2 = match self.find_pepper(params.keyid())? {
+            Some(p) => Argon2::new_with_secret(&p.secret, ALGO, VERSION, params.clone())?,
+            None => Argon2::new(ALGO, VERSION, params.clone()),
+        };
+
+        argon2.verify_password(password.as_ref(), &parsed_hash)?;
+
+        if self.is_current(&parsed_hash, &params)? {
+            return Ok(None);
+        }
+
+        self.hash(password).map(Some)
+    }
+
+    fn is_current(&self, hash: &PasswordHash, params: &Params) -> A2Result<bool> {
+        let current = self.params()?;
+
+        Ok(hash.algorithm == ALGO.ident()
+            && hash.version == Some(VERSION.into())
+            && params.m_cost() == current.m_cost()
+            && params.t_cost() == current.t_cost()
+            && params.p_cost() == current.p_cost()
+            && params.keyid() == current.keyid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "2Uj8wK3nZ6pQ9rT4vX7y";
+
+    fn pepper(version: u8) -> Pepper {
+        Pepper {
+            version,
+            secret: format!("pepper-{}", version).into_bytes(),
+        }
+    }
+
+    #[test]
+    fn rehash_on_verify() {
+        let unpeppered = CredentialHasher::new(None, Vec::new()).unwrap();
+        let v1 = CredentialHasher::new(Some(pepper(1)), Vec::new()).unwrap();
+        let v2 = CredentialHasher::new(Some(pepper(2)), vec![pepper(1)]).unwrap();
+
+        let hash = unpeppered.hash(PASSWORD).unwrap();
+        assert!(unpeppered.verify(PASSWORD, &hash).unwrap().is_none());
+
+        let hash = v1.verify(PASSWORD, &hash).unwrap().unwrap();
+        assert!(v1.verify(PASSWORD, &hash).unwrap().is_none());
+        assert!(v1.verify("wrong", &hash).is_err());
+
+        let hash = v2.verify(PASSWORD, &hash).unwrap().unwrap();
+        assert!(v2.verify(PASSWORD, &hash).unwrap().is_none());
+        assert!(v1.verify(PASSWORD, &hash).is_err());
+    }
+}
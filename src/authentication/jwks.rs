@@ -0,0 +1,298 @@
+use crate::{
+    http::{HttpClient, SendTimed},
+    utils, Result,
+};
+
+use super::{token::TokenError, AuthenticationError};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, Jwk, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{sync::Mutex, task::JoinHandle};
+use tracing::{error, warn};
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwksError {
+    #[error("token header has no key id")]
+    MissingKeyId,
+    #[error("key \"{0}\" is unknown")]
+    UnknownKey(String),
+    #[error("key algorithm does not match")]
+    AlgorithmMismatch,
+}
+
+#[derive(Clone)]
+struct Key {
+    alg: Option<Algorithm>,
+    key: DecodingKey,
+}
+
+/// Cached key set of an external issuer.
+///
+/// Keys are refreshed periodically in the background and refetched on demand
+/// if a token references an unknown key id. On-demand refetches are rate limited
+/// to protect the issuer from tokens with random key ids.
+#[derive(Clone)]
+pub struct JwksCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    uri: Url,
+    client: HttpClient,
+    keys: RwLock<HashMap<String, Key>>,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl JwksCache {
+    pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+    const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+    pub fn new(uri: Url, client: HttpClient) -> Self {
+        let inner = Inner {
+            uri,
+            client,
+            keys: Default::default(),
+            last_fetch: Default::default(),
+        };
+
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Fetches the key set and replaces the cached keys
+    pub async fn refresh(&self) -> Result<()> {
+        let mut last_fetch = self.inner.last_fetch.lock().await;
+
+        self.fetch(&mut last_fetch).await
+    }
+
+    /// Spawns a task that refreshes the key set in the given interval
+    pub fn spawn_refresh(&self, interval: Duration) -> JoinHandle<()> {
+        let cache = self.clone();
+
+        utils::spawn_named("jwks-refresh", async move {
+            let mut interval = tokio::time::interval(interval);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = cache.refresh().await {
+                    error!(error = %e, uri = %cache.inner.uri, "error while refreshing key set");
+                }
+            }
+        })
+    }
+
+    /// Decodes and validates a token with the matching key of the set.
+    ///
+    /// The token algorithm has to be part of `validation.algorithms`.
+    pub async fn decode<T>(&self, token: &str, validation: &Validation) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| AuthenticationError::from(TokenError::from(e)))?;
+
+        if !validation.algorithms.contains(&header.alg) {
+            return Err(AuthenticationError::from(TokenError::Invalid).into());
+        }
+
+        let kid = header
+            .kid
+            .ok_or_else(|| AuthenticationError::from(JwksError::MissingKeyId))?;
+
+        let Key { alg, key } = self.get(&kid).await?;
+
+        if matches!(alg, Some(alg) if alg != header.alg) {
+            return Err(AuthenticationError::from(JwksError::AlgorithmMismatch).into());
+        }
+
+        let mut validation = validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        let data = jsonwebtoken::decode::<T>(token, &key, &validation)
+            .map_err(|e| AuthenticationError::from(TokenError::from(e)))?;
+
+        Ok(data.claims)
+    }
+
+    async fn get(&self, kid: &str) -> Result<Key> {
+        if let Some(key) = self.find(kid) {
+            return Ok(key);
+        }
+
+        let mut last_fetch = self.inner.last_fetch.lock().await;
+
+        // The set may have been refetched while waiting for the lock
+        if let Some(key) = self.find(kid) {
+            return Ok(key);
+        }
+
+        if last_fetch.map_or(true, |t| t.elapsed() >= Self::MIN_REFETCH_INTERVAL) {
+            self.fetch(&mut last_fetch).await?;
+        }
+
+        self.find(kid)
+            .ok_or_else(|| AuthenticationError::from(JwksError::UnknownKey(kid.to_string())).into())
+    }
+
+    /// Looks up a key of the cached set without fetching, for synchronous validation
+    pub fn cached(&self, kid: &str) -> Option<(Option<Algorithm>, DecodingKey)> {
+        self.find(kid).map(|Key { alg, key }| (alg, key))
+    }
+
+    fn find(&self, kid: &str) -> Option<Key> {
+        self.inner.keys.read().unwrap().get(kid).cloned()
+    }
+
+    async fn fetch(&self, last_fetch: &mut Option<Instant>) -> Result<()> {
+        *last_fetch = Some(Instant::now());
+
+        let set = self
+            .inner
+            .client
+            .get(self.inner.uri.clone())
+            .send_timed()
+            .await?
+            .error_for_status()?
+            .json::<KeySet>()
+            .await?;
+
+        let keys = parse_set(set);
+        if keys.is_empty() {
+            warn!(uri = %self.inner.uri, "key set contains no usable keys");
+        }
+
+        *self.inner.keys.write().unwrap() = keys;
+
+        Ok(())
+    }
+}
+
+/// Reads the issuer of a token without validating it, to select its key set
+pub fn peek_issuer(token: &str) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Claims {
+        iss: String,
+    }
+
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    let data = jsonwebtoken::decode::<Claims>(token, &DecodingKey::from_secret(&[]), &validation)
+        .map_err(|_| AuthenticationError::from(TokenError::Invalid))?;
+
+    Ok(data.claims.iss)
+}
+
+/// Discovers the key set URI of an issuer via its OpenID configuration
+pub async fn discover_uri(issuer: &str, client: &HttpClient) -> Result<Url> {
+    #[derive(Deserialize)]
+    struct Configuration {
+        jwks_uri: Url,
+    }
+
+    let config = discover::<Configuration>(issuer, client).await?;
+
+    Ok(config.jwks_uri)
+}
+
+/// Fetches the OpenID configuration of an issuer
+pub async fn discover<T>(issuer: &str, client: &HttpClient) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+
+    let config = client
+        .get(url)
+        .send_timed()
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await?;
+
+    Ok(config)
+}
+
+/// Key set with unparsed keys, so that a single unsupported key doesn't invalidate the whole set
+#[derive(Debug, Deserialize)]
+struct KeySet {
+    keys: Vec<serde_json::Value>,
+}
+
+fn parse_set(set: KeySet) -> HashMap<String, Key> {
+    set.keys
+        .into_iter()
+        .filter_map(|v| serde_json::from_value::<Jwk>(v).ok())
+        .filter(|jwk| !matches!(jwk.common.public_key_use, Some(ref u) if u != &PublicKeyUse::Signature))
+        .filter_map(|jwk| {
+            let key = Key {
+                alg: jwk.common.algorithm,
+                key: decoding_key(&jwk)?,
+            };
+
+            Some((jwk.common.key_id?, key))
+        })
+        .collect()
+}
+
+fn decoding_key(jwk: &Jwk) -> Option<DecodingKey> {
+    let decode = |v: &str| base64::decode_config(v, base64::URL_SAFE_NO_PAD).ok();
+
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(p) => DecodingKey::from_rsa_components(&p.n, &p.e).ok(),
+        AlgorithmParameters::EllipticCurve(p) => {
+            // Uncompressed point encoding as expected by ring
+            let mut point = vec![0x04];
+            point.append(&mut decode(&p.x)?);
+            point.append(&mut decode(&p.y)?);
+
+            Some(DecodingKey::from_ec_der(&point))
+        }
+        AlgorithmParameters::OctetKeyPair(p) => Some(DecodingKey::from_ed_der(&decode(&p.x)?)),
+        // Symmetric keys are never accepted from an external source
+        AlgorithmParameters::OctetKey(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_SET: &str = r#"{"keys": [
+        {"kty": "RSA", "kid": "rsa", "use": "sig", "alg": "RS256", "n": "sXchDaQebHnPiGvyDOAT4saGEUetSyo9MKLOoWFsueri23bOdgWp4Dy1WlUzewbgBHod5pcM9H95GQRV3JDXboIRROSBigeC5yjU1hGzHHyXss8UDprecbAYxknTcQkhslANGRUZmdTOQ5qTRsLAt6BTYuyvVRdhS8exSZEy_c4gs_7svlJJQ4H9_NxsiIoLwAEk7-Q3UXERGYw_75IDrGA84-lA_-Ct4eTlXHBIY2EaV7t7LjJaynVJCpkv4LKjTTAumiGUIuQhrNhZLuF_RJLqHpM2kgWFLU7-VTdL1VbC2tejvcI2BlMkEpk1BzBZI0KQB0GaDWFLN-aEAw3vRw", "e": "AQAB"},
+        {"kty": "EC", "kid": "ec", "crv": "P-256", "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU", "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"},
+        {"kty": "EC", "kid": "enc", "use": "enc", "crv": "P-256", "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU", "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"},
+        {"kty": "oct", "kid": "oct", "value": "c2VjcmV0"},
+        {"kty": "OKP", "kid": "okp", "crv": "X25519", "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}
+    ]}"#;
+
+    #[test]
+    fn parse_key_set() {
+        let set: KeySet = serde_json::from_str(KEY_SET).unwrap();
+        let keys = parse_set(set);
+
+        let mut ids = keys.keys().map(String::as_str).collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        assert_eq!(ids, ["ec", "rsa"]);
+        assert_eq!(keys["rsa"].alg, Some(Algorithm::RS256));
+    }
+}
@@ -1,5 +1,11 @@
+pub mod client_secret;
+pub mod credential;
+pub mod jwks;
 pub mod password;
+pub mod rotation;
+pub mod signature;
 pub mod token;
+pub mod totp;
 
 use crate::{error, model::Status};
 
@@ -9,12 +15,18 @@ use hyper::StatusCode;
 pub enum AuthenticationError {
     #[error("insufficient permission")]
     InsufficientPermission,
+    #[error("key set error: {0}")]
+    Jwks(#[from] jwks::JwksError),
     #[error("header error: {0}")]
     InvalidHeader(String),
     #[error("password error: {0}")]
     Password(#[from] password::PasswordError),
     #[error("token error: {0}")]
     Token(#[from] token::TokenError),
+    #[error("signature error: {0}")]
+    Signature(#[from] signature::SignatureError),
+    #[error("client credentials are invalid")]
+    BadClientCredentials,
 }
 
 impl error::ErrorResponse for AuthenticationError {
@@ -24,8 +36,14 @@ impl error::ErrorResponse for AuthenticationError {
         match self {
             AuthenticationError::InsufficientPermission => StatusCode::FORBIDDEN,
             AuthenticationError::InvalidHeader(_) => StatusCode::UNAUTHORIZED,
-            AuthenticationError::Token(_) => StatusCode::UNAUTHORIZED,
+            AuthenticationError::Token(_)
+            | AuthenticationError::Jwks(_)
+            | AuthenticationError::BadClientCredentials => StatusCode::UNAUTHORIZED,
             AuthenticationError::Password(_) => StatusCode::BAD_REQUEST,
+            AuthenticationError::Signature(signature::SignatureError::BodyTooLarge) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            AuthenticationError::Signature(_) => StatusCode::UNAUTHORIZED,
         }
     }
 
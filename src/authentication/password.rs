@@ -1,28 +1,18 @@
-use crate::{http::HttpClient, Result};
+use crate::{
+    http::{HttpClient, SendTimed},
+    Result,
+};
 
-use super::AuthenticationError;
+use super::{credential::CredentialHasher, AuthenticationError};
 
-use argon2::{
-    password_hash::{
-        PasswordHash, PasswordHasher, PasswordVerifier, Result as A2Result, SaltString,
-    },
-    Algorithm, Argon2, Params, Version,
-};
+use chrono::Duration;
 use passwords::{analyzer, scorer};
-use rand::rngs::OsRng;
 use reqwest::Url;
 use sha1::{Digest, Sha1};
 use tracing::error;
 
-/// Minimum password score
-const SCORE_MIN: f64 = 85.0;
-
-// Argon2 config
-const ALGO: Algorithm = Algorithm::Argon2id;
-const VERSION: Version = Version::V0x13;
-const M_COST: u32 = 4 << 10;
-const T_COST: u32 = 3;
-const P_COST: u32 = 2;
+/// Longer passwords only add hashing cost
+const MAX_LENGTH: usize = 80;
 
 #[derive(Debug, thiserror::Error)]
 pub enum PasswordError {
@@ -34,87 +24,88 @@ pub enum PasswordError {
     Pwned(u64),
 }
 
-pub fn hash_password<S: AsRef<[u8]>>(password: S) -> A2Result<String> {
-    let salt = SaltString::generate(&mut OsRng);
-
-    let params = Params::new(M_COST, T_COST, P_COST, None)?;
-
-    let argon2 = Argon2::new(ALGO, VERSION, params);
-
-    let hash = argon2.hash_password(password.as_ref(), salt.as_ref())?;
-
-    Ok(hash.to_string())
+/// Requirements of new passwords and the lockout after failed logins
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Requires uppercase and lowercase characters and digits
+    pub require_classes: bool,
+    pub min_score: f64,
+    /// Failed logins after which password logins are locked, never locked if zero
+    pub max_failures: u32,
+    pub lockout: Duration,
 }
 
-pub fn verify_password<S, H>(password: S, hash: H) -> A2Result<()>
-where
-    S: AsRef<[u8]>,
-    H: AsRef<str>,
-{
-    let params = Params::new(M_COST, T_COST, P_COST, None)?;
-
-    let argon2 = Argon2::new(ALGO, VERSION, params);
-
-    let parsed_hash = PasswordHash::new(hash.as_ref())?;
-
-    argon2.verify_password(password.as_ref(), &parsed_hash)?;
-
-    Ok(())
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 16,
+            require_classes: true,
+            min_score: 85.0,
+            max_failures: 5,
+            lockout: Duration::minutes(15),
+        }
+    }
 }
 
-pub fn validate_password(password: &str) -> std::result::Result<(), PasswordError> {
-    if !password.is_ascii() {
-        return Err(PasswordError::InvalidPassword(
-            "password has invalid characters".to_string(),
-        ));
-    }
+impl PasswordPolicy {
+    pub fn validate(&self, password: &str) -> std::result::Result<(), PasswordError> {
+        if !password.is_ascii() {
+            return Err(PasswordError::InvalidPassword(
+                "password has invalid characters".to_string(),
+            ));
+        }
 
-    let analysis = analyzer::analyze(password);
+        let analysis = analyzer::analyze(password);
 
-    if analysis.length() < 16 {
-        return Err(PasswordError::InvalidPassword(
-            "password must have at least 16 characters".to_string(),
-        ));
-    }
-    if analysis.length() > 80 {
-        return Err(PasswordError::InvalidPassword(
-            "password cannot exceed 80 characters".to_string(),
-        ));
-    }
-    if analysis.uppercase_letters_count() < 1 {
-        return Err(PasswordError::InvalidPassword(
-            "password must have at least one uppercase character".to_string(),
-        ));
-    }
-    if analysis.lowercase_letters_count() < 1 {
-        return Err(PasswordError::InvalidPassword(
-            "password must have at least one lowercase character".to_string(),
-        ));
-    }
-    if analysis.lowercase_letters_count() < 1 {
-        return Err(PasswordError::InvalidPassword(
-            "password must have at least one lowercase character".to_string(),
-        ));
-    }
-    if analysis.numbers_count() < 1 {
-        return Err(PasswordError::InvalidPassword(
-            "password must have at least one digit".to_string(),
-        ));
-    }
+        if analysis.length() < self.min_length {
+            return Err(PasswordError::InvalidPassword(format!(
+                "password must have at least {} characters",
+                self.min_length
+            )));
+        }
+        if analysis.length() > MAX_LENGTH {
+            return Err(PasswordError::InvalidPassword(format!(
+                "password cannot exceed {} characters",
+                MAX_LENGTH
+            )));
+        }
+        if self.require_classes {
+            if analysis.uppercase_letters_count() < 1 {
+                return Err(PasswordError::InvalidPassword(
+                    "password must have at least one uppercase character".to_string(),
+                ));
+            }
+            if analysis.lowercase_letters_count() < 1 {
+                return Err(PasswordError::InvalidPassword(
+                    "password must have at least one lowercase character".to_string(),
+                ));
+            }
+            if analysis.numbers_count() < 1 {
+                return Err(PasswordError::InvalidPassword(
+                    "password must have at least one digit".to_string(),
+                ));
+            }
+        }
 
-    if scorer::score(&analysis) < SCORE_MIN {
-        return Err(PasswordError::BadScore);
-    }
+        if scorer::score(&analysis) < self.min_score {
+            return Err(PasswordError::BadScore);
+        }
 
-    Ok(())
+        Ok(())
+    }
 }
 
-pub fn validate_and_hash(password: &str) -> Result<String> {
-    if let Err(e) = validate_password(password) {
+pub fn validate_and_hash(
+    hasher: &CredentialHasher,
+    policy: &PasswordPolicy,
+    password: &str,
+) -> Result<String> {
+    if let Err(e) = policy.validate(password) {
         return Err(AuthenticationError::from(e).into());
     }
 
-    let hash = match hash_password(password) {
+    let hash = match hasher.hash(password) {
         Ok(h) => h,
         Err(e) => {
             error!("Error while hashing password: {:?}", e);
@@ -161,7 +152,7 @@ impl Hibp {
         let hashes = self
             .client
             .get(url)
-            .send()
+            .send_timed()
             .await?
             .error_for_status()?
             .text()
@@ -182,6 +173,24 @@ mod tests {
 
     const PWNED_PASSWORD: &str = "foobar";
 
+    #[test]
+    fn policy_is_configurable() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.validate("2Uj8wK3nZ6pQ9rT4vX7y").is_ok());
+        assert!(policy.validate("uj8wk3nz6pq9rt4vx7yb").is_err());
+        assert!(policy.validate("2Uj8wK3nZ6pQ").is_err());
+
+        let relaxed = PasswordPolicy {
+            min_length: 12,
+            require_classes: false,
+            min_score: 0.0,
+            ..Default::default()
+        };
+        assert!(relaxed.validate("uj8wk3nz6pq9rt4vx7yb").is_ok());
+        assert!(relaxed.validate("2Uj8wK3nZ6pQ").is_ok());
+        assert!(relaxed.validate(&"a".repeat(MAX_LENGTH + 1)).is_err());
+    }
+
     #[tokio::test]
     async fn hibp_password_check() {
         let hibp = Hibp::default();
@@ -0,0 +1,334 @@
+//! Rotation of the token signing key.
+//!
+//! Keys are generated by the primary and shared with all instances via the database, their
+//! private parts encrypted. A new key is published some time before it signs tokens, so that
+//! every instance and resource server knows it in time. Replaced keys stay published until the
+//! tokens they signed are expired.
+
+use crate::{
+    database::Database,
+    error::Error,
+    token::ClientClaims,
+    utils::{self, crypto::Aead256},
+    Result,
+};
+
+use super::token::{key_id, TokenConfig};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::FindOptions,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyDocument {
+    /// Key id, derived from the public key
+    #[serde(rename = "_id")]
+    id: String,
+    /// Encrypted PKCS#8 document, base64 encoded
+    private_key: String,
+    /// Uncompressed public point, base64 encoded
+    public_key: String,
+    /// Time from which tokens are signed with the key
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    active_from: DateTime<Utc>,
+}
+
+impl KeyDocument {
+    fn generate(enc: &Aead256, active_from: DateTime<Utc>) -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .expect("key generation failed");
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())
+            .expect("generated key is invalid");
+        let public_key = pair.public_key().as_ref();
+
+        Self {
+            id: key_id(public_key),
+            private_key: base64::encode_config(enc.encrypt(pkcs8.as_ref()), base64::STANDARD),
+            public_key: base64::encode_config(public_key, base64::STANDARD),
+            active_from,
+        }
+    }
+}
+
+/// Keys in order of activation, split by their use
+#[derive(Debug, PartialEq)]
+struct Selection<'a> {
+    active: &'a KeyDocument,
+    /// Replaced keys whose tokens may still be valid and upcoming keys
+    retained: Vec<&'a KeyDocument>,
+    /// Replaced keys whose tokens are expired
+    expired: Vec<&'a KeyDocument>,
+}
+
+/// Time after which a new key is used for signing, longer than the interval of the task
+fn publish_delay() -> Duration {
+    Duration::minutes(15)
+}
+
+/// Time a replaced key is kept, the longest lifetime of issued tokens
+fn retention() -> Duration {
+    Duration::days(ClientClaims::DEFAULT_EXP_DAYS)
+}
+
+fn needs_rotation(keys: &[KeyDocument], interval: Duration, now: DateTime<Utc>) -> bool {
+    keys.last()
+        .map_or(true, |k| k.active_from + interval <= now)
+}
+
+/// Splits the keys, which must be ordered by activation. Returns `None` if none is active yet.
+fn select(keys: &[KeyDocument], now: DateTime<Utc>) -> Option<Selection<'_>> {
+    let active = keys.iter().rposition(|k| k.active_from <= now)?;
+
+    let mut selection = Selection {
+        active: &keys[active],
+        retained: Vec::new(),
+        expired: Vec::new(),
+    };
+    for (i, key) in keys.iter().enumerate().filter(|(i, _)| *i != active) {
+        if i < active && keys[i + 1].active_from + retention() <= now {
+            selection.expired.push(key);
+        } else {
+            selection.retained.push(key);
+        }
+    }
+
+    Some(selection)
+}
+
+const COLLECTION: &str = "token_keys";
+
+/// Interval in which keys are reloaded and rotated
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+impl Database {
+    async fn get_token_keys(&self) -> Result<Vec<KeyDocument>> {
+        let options = FindOptions::builder()
+            .sort(doc! { "activeFrom": 1 })
+            .build();
+
+        let keys = self
+            .collection::<KeyDocument>(COLLECTION)
+            .find(None, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(keys)
+    }
+
+    /// Re-encrypts the token signing keys with the current key
+    pub async fn reencrypt_token_keys(&self, enc: &Aead256) -> Result<u64> {
+        let coll = self.collection::<KeyDocument>(COLLECTION);
+
+        let mut count = 0;
+        for key in self.get_token_keys().await? {
+            let private_key = base64::decode_config(key.private_key, base64::STANDARD).unwrap();
+
+            if let Some(private_key) = enc.reencrypt(private_key)? {
+                let private_key = base64::encode_config(private_key, base64::STANDARD);
+                coll.update_one(
+                    doc! { "_id": key.id },
+                    doc! { "$set": { "privateKey": private_key } },
+                    None,
+                )
+                .await?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[derive(Clone)]
+pub struct KeyRotation {
+    db: Database,
+    config: TokenConfig,
+    enc: Aead256,
+    interval: Duration,
+    /// Only the primary generates and removes keys
+    writer: bool,
+}
+
+impl KeyRotation {
+    pub fn new(
+        db: Database,
+        config: TokenConfig,
+        enc: Aead256,
+        interval_days: u32,
+        writer: bool,
+    ) -> Self {
+        Self {
+            db,
+            config,
+            enc,
+            interval: Duration::days(interval_days.into()),
+            writer,
+        }
+    }
+
+    /// Loads the keys into the token config, rotating them if due
+    pub async fn refresh(&self) -> Result<()> {
+        let now = self.config.clock.now();
+        let coll = self.db.collection::<KeyDocument>(COLLECTION);
+
+        let mut keys = self.db.get_token_keys().await?;
+        if self.writer && needs_rotation(&keys, self.interval, now) {
+            // The first key has no predecessor whose tokens must stay valid
+            let active_from = if keys.is_empty() {
+                now
+            } else {
+                now + publish_delay()
+            };
+            let key = KeyDocument::generate(&self.enc, active_from);
+            coll.insert_one(&key, None).await?;
+            info!(kid = %key.id, %active_from, "token signing key generated");
+
+            keys.push(key);
+        }
+
+        let selection = select(&keys, now)
+            .ok_or_else(|| Error::Config("no token signing key is active yet".into()))?;
+
+        if self.writer && !selection.expired.is_empty() {
+            let ids: Vec<_> = selection.expired.iter().map(|k| k.id.as_str()).collect();
+            coll.delete_many(doc! { "_id": { "$in": &ids } }, None)
+                .await?;
+            info!(kids = ?ids, "expired token signing keys removed");
+        }
+
+        let private_key =
+            base64::decode_config(&selection.active.private_key, base64::STANDARD).unwrap();
+        let private_key = self.enc.decrypt(private_key)?;
+        let retained = selection
+            .retained
+            .iter()
+            .map(|k| {
+                let point = base64::decode_config(&k.public_key, base64::STANDARD).unwrap();
+                (k.id.clone(), point)
+            })
+            .collect();
+
+        self.config
+            .rotate_keys(&private_key, retained)
+            .map_err(|e| Error::Config(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Refreshes the keys periodically, e.g. to pick up keys generated by the primary
+    pub fn spawn(self) {
+        utils::spawn_named("token-key-rotation", async move {
+            let mut interval = tokio::time::interval(INTERVAL);
+            // The keys were loaded at startup
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = self.refresh().await {
+                    error!(error = %e, "token signing keys could not be refreshed");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2024, 3, 1).and_hms(12, 0, 0)
+    }
+
+    fn key(id: &str, active_from: DateTime<Utc>) -> KeyDocument {
+        KeyDocument {
+            id: id.to_string(),
+            private_key: String::new(),
+            public_key: String::new(),
+            active_from,
+        }
+    }
+
+    #[test]
+    fn rotates_after_interval() {
+        let now = now();
+        let interval = Duration::days(30);
+
+        assert!(needs_rotation(&[], interval, now));
+        assert!(!needs_rotation(
+            &[key("a", now - Duration::days(29))],
+            interval,
+            now
+        ));
+        assert!(needs_rotation(
+            &[key("a", now - Duration::days(30))],
+            interval,
+            now
+        ));
+    }
+
+    #[test]
+    fn selects_latest_active_key() {
+        let now = now();
+        let keys = [
+            key("a", now - Duration::days(60)),
+            key("b", now - Duration::days(30)),
+            key("c", now + publish_delay()),
+        ];
+
+        let selection = select(&keys, now).unwrap();
+        assert_eq!(selection.active.id, "b");
+        assert_eq!(
+            selection.retained.iter().map(|k| &k.id).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+        assert!(selection.expired.is_empty());
+
+        assert_eq!(select(&keys[2..], now), None);
+    }
+
+    #[test]
+    fn expires_keys_after_retention() {
+        let now = now();
+        let replaced = now - retention();
+        let keys = [
+            key("a", replaced - Duration::days(30)),
+            key("b", replaced),
+            key("c", replaced + Duration::days(1)),
+        ];
+
+        let selection = select(&keys, now).unwrap();
+        assert_eq!(selection.active.id, "c");
+        assert_eq!(selection.expired[0].id, "a");
+        assert_eq!(selection.retained[0].id, "b");
+    }
+
+    #[test]
+    fn generates_encrypted_key() {
+        let enc = Aead256::new(1, "Dhh0uAQDDQO90882bbZbyz1jWf4MrxI2").unwrap();
+        let doc = KeyDocument::generate(&enc, now());
+
+        let private_key = base64::decode_config(&doc.private_key, base64::STANDARD).unwrap();
+        let config = TokenConfig::from_secret("secret", ["aud"]).with_key_rotation();
+        config
+            .rotate_keys(&enc.decrypt(private_key).unwrap(), Vec::new())
+            .unwrap();
+
+        assert_eq!(config.jwks().keys[0].kid, doc.id);
+    }
+}
@@ -0,0 +1,360 @@
+//! HMAC signed requests for server-to-server automation.
+//!
+//! Instead of a bearer token a signed request carries these headers:
+//! - `X-Signature-Key`: id of the user the key belongs to, its roles define the scope
+//! - `X-Signature-Timestamp`: unix timestamp in seconds
+//! - `X-Content-SHA256`: hex encoded SHA-256 digest of the body
+//! - `X-Signature`: hex encoded HMAC-SHA256 of [`string_to_sign`]
+//!
+//! Every signature is only accepted once within the timestamp window.
+
+use crate::{
+    authentication::{
+        token::{TokenClaims, TokenConfig},
+        AuthenticationError,
+    },
+    database::Database,
+    error::Error,
+    session::SessionClaims,
+    user::UserError,
+};
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use axum::{
+    body::Body,
+    extract::OriginalUri,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use hyper::{body::HttpBody, header::AUTHORIZATION, Method, Request};
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    error::{ErrorKind, WriteFailure},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const KEY_HEADER: &str = "X-Signature-Key";
+pub const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+pub const DIGEST_HEADER: &str = "X-Content-SHA256";
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Maximum age of a signed request, in either direction to tolerate clock skew
+const WINDOW_SECS: i64 = 5 * 60;
+
+/// Signed requests are admin calls, larger bodies are not expected
+const BODY_LIMIT: u64 = 64 * 1024;
+
+/// Lifetime of the session a signed request is served with
+const SESSION_SECS: i64 = 60;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("header \"{0}\" is missing or invalid")]
+    InvalidHeader(&'static str),
+    #[error("signing key is unknown")]
+    UnknownKey,
+    #[error("signature is expired")]
+    Expired,
+    #[error("content digest does not match the body")]
+    DigestMismatch,
+    #[error("signature is invalid")]
+    Invalid,
+    #[error("signature was already used")]
+    Replayed,
+    #[error("body is too large")]
+    BodyTooLarge,
+}
+
+impl From<SignatureError> for Error {
+    fn from(e: SignatureError) -> Self {
+        AuthenticationError::from(e).into()
+    }
+}
+
+/// Secrets of the signing keys by the id of their user
+#[derive(Clone, Default)]
+pub struct SigningKeys(Arc<HashMap<ObjectId, Vec<u8>>>);
+
+impl SigningKeys {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for SigningKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
+}
+
+/// Parses comma-separated `user:secret` pairs
+pub fn de_keys<'de, D>(d: D) -> Result<SigningKeys, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let keys = input
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let (user, secret) = v
+                .split_once(':')
+                .ok_or_else(|| serde::de::Error::custom("signing key user is missing"))?;
+            let user = ObjectId::parse_str(user).map_err(serde::de::Error::custom)?;
+
+            Ok((user, secret.as_bytes().to_vec()))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(SigningKeys(Arc::new(keys)))
+}
+
+/// Message that is signed, each part on its own line
+pub fn string_to_sign(method: &Method, path: &str, timestamp: i64, digest: &str) -> String {
+    format!("{}\n{}\n{}\n{}", method, path, timestamp, digest)
+}
+
+fn mac(secret: &[u8], message: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// Middleware that authenticates signed requests with a short-lived session.
+///
+/// Requests with a bearer token or without signature are passed through.
+pub async fn authenticate_signed(
+    req: Request<Body>,
+    next: Next<Body>,
+    keys: SigningKeys,
+) -> Response {
+    if !req.headers().contains_key(SIGNATURE_HEADER) || req.headers().contains_key(AUTHORIZATION) {
+        return next.run(req).await;
+    }
+
+    match verify(req, &keys).await {
+        Ok(req) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn verify(req: Request<Body>, keys: &SigningKeys) -> crate::Result<Request<Body>> {
+    let db = req
+        .extensions()
+        .get::<Database>()
+        .expect("database missing")
+        .clone();
+    let config = req
+        .extensions()
+        .get::<TokenConfig>()
+        .expect("token config missing")
+        .clone();
+
+    let (mut parts, body) = req.into_parts();
+
+    let header = |name: &'static str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(SignatureError::InvalidHeader(name))
+    };
+
+    let user_id =
+        ObjectId::parse_str(header(KEY_HEADER)?).map_err(|_| SignatureError::UnknownKey)?;
+    let secret = keys.0.get(&user_id).ok_or(SignatureError::UnknownKey)?;
+
+    let timestamp = header(TIMESTAMP_HEADER)?
+        .parse::<i64>()
+        .map_err(|_| SignatureError::InvalidHeader(TIMESTAMP_HEADER))?;
+    let now = config.clock.now();
+    if (now.timestamp() - timestamp).abs() > WINDOW_SECS {
+        return Err(SignatureError::Expired.into());
+    }
+
+    if body.size_hint().lower() > BODY_LIMIT {
+        return Err(SignatureError::BodyTooLarge.into());
+    }
+    let body = hyper::body::to_bytes(body).await?;
+    if body.len() as u64 > BODY_LIMIT {
+        return Err(SignatureError::BodyTooLarge.into());
+    }
+
+    let digest = hex::encode(Sha256::digest(&body));
+    if !header(DIGEST_HEADER)?.eq_ignore_ascii_case(&digest) {
+        return Err(SignatureError::DigestMismatch.into());
+    }
+
+    let signature = hex::decode(header(SIGNATURE_HEADER)?).map_err(|_| SignatureError::Invalid)?;
+    // The client signs the path it sent, including a base path of the routes
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |uri| &uri.0);
+    let path = uri
+        .path_and_query()
+        .map_or_else(|| uri.path(), |p| p.as_str());
+    let message = string_to_sign(&parts.method, path, timestamp, &digest);
+    mac(secret, &message)
+        .verify_slice(&signature)
+        .map_err(|_| SignatureError::Invalid)?;
+
+    let expires_at = Utc.timestamp(timestamp + WINDOW_SECS, 0);
+    db.record_signature(&signature, expires_at).await?;
+
+    let user = match db.get_user(doc! { "_id": user_id }).await {
+        Ok(user) if user.can_login => user,
+        Ok(_) | Err(Error::User(UserError::NotFound)) => {
+            return Err(SignatureError::UnknownKey.into())
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut claims = SessionClaims::issue_for_user(&config, &user, now).await?;
+    claims.set_expiration(claims.exp.min(now + Duration::seconds(SESSION_SECS)));
+
+    let token = claims.encode(&config).map_err(AuthenticationError::from)?;
+    parts
+        .headers
+        .insert(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+
+    Ok(Request::from_parts(parts, Body::from(body)))
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SignatureDocument {
+    /// The signature itself, so that a replay fails on the unique id
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    expires_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "request_signatures";
+
+/// Code of a write error on a duplicate key
+const DUPLICATE_KEY: i32 = 11000;
+
+impl Database {
+    /// Expires used signatures when their timestamp is no longer accepted
+    pub async fn init_signatures(&self) -> crate::Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        self.collection::<SignatureDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a used signature, fails if it was used before
+    async fn record_signature(
+        &self,
+        signature: &[u8],
+        expires_at: DateTime<Utc>,
+    ) -> crate::Result<()> {
+        let doc = SignatureDocument {
+            id: hex::encode(signature),
+            expires_at,
+        };
+
+        match self
+            .collection::<SignatureDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == DUPLICATE_KEY => {
+                    Err(SignatureError::Replayed.into())
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::value::{Error as DeError, StrDeserializer};
+    use serde::de::IntoDeserializer;
+
+    const USER: &str = "62a3c0a5e2a1f3b4c5d6e7f8";
+
+    #[test]
+    fn parses_keys() {
+        let input = format!("{}:secret,", USER);
+        let de: StrDeserializer<DeError> = input.as_str().into_deserializer();
+        let keys = de_keys(de).unwrap();
+
+        let user = ObjectId::parse_str(USER).unwrap();
+        assert_eq!(keys.0.get(&user).map(Vec::as_slice), Some(&b"secret"[..]));
+    }
+
+    #[test]
+    fn rejects_keys_without_user() {
+        let de: StrDeserializer<DeError> = "secret".into_deserializer();
+
+        assert!(de_keys(de).is_err());
+    }
+
+    #[test]
+    fn signature_covers_request() {
+        let digest = hex::encode(Sha256::digest(b"{}"));
+        let message = string_to_sign(&Method::PATCH, "/v1/client/1", 1_650_000_000, &digest);
+        let signature = mac(b"secret", &message).finalize().into_bytes();
+
+        let verify = |method: &Method, path: &str, timestamp: i64, secret: &[u8]| {
+            let message = string_to_sign(method, path, timestamp, &digest);
+            mac(secret, &message).verify_slice(&signature).is_ok()
+        };
+
+        assert!(verify(
+            &Method::PATCH,
+            "/v1/client/1",
+            1_650_000_000,
+            b"secret"
+        ));
+        assert!(!verify(
+            &Method::DELETE,
+            "/v1/client/1",
+            1_650_000_000,
+            b"secret"
+        ));
+        assert!(!verify(
+            &Method::PATCH,
+            "/v1/client/2",
+            1_650_000_000,
+            b"secret"
+        ));
+        assert!(!verify(
+            &Method::PATCH,
+            "/v1/client/1",
+            1_650_000_001,
+            b"secret"
+        ));
+        assert!(!verify(
+            &Method::PATCH,
+            "/v1/client/1",
+            1_650_000_000,
+            b"other"
+        ));
+    }
+}
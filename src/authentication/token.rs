@@ -1,11 +1,33 @@
-use crate::{error, model::Status};
+use crate::{
+    clock::{SharedClock, SystemClock},
+    database::Database,
+    epoch::TokenEpoch,
+    error,
+    extract::ClientInfo,
+    metrics::Metrics,
+    model::Status,
+    session::SessionLimits,
+    token::ClaimRules,
+};
+
+use super::jwks::JwksCache;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
+use axum::async_trait;
 use hyper::StatusCode;
 use jsonwebtoken::{
     errors::{Error as JwtError, ErrorKind},
-    Algorithm, DecodingKey, EncodingKey, Validation,
+    Algorithm, DecodingKey, EncodingKey, TokenData, Validation,
+};
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::error;
 
 #[derive(Debug, thiserror::Error)]
@@ -18,10 +40,22 @@ pub enum TokenError {
     WrongType,
     #[error("token is invalid")]
     Invalid,
+    #[error("token is revoked")]
+    Revoked,
     #[error("Token could not be encoded: {0}")]
     EncodingFailed(JwtError),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum SigningKeyError {
+    #[error("signing key is not PEM encoded")]
+    Pem(#[from] pem::PemError),
+    #[error("signing key was rejected: {0}")]
+    Rejected(String),
+    #[error("algorithm {0:?} is not supported for signing keys")]
+    Unsupported(Algorithm),
+}
+
 impl From<JwtError> for TokenError {
     fn from(error: JwtError) -> Self {
         match *error.kind() {
@@ -43,7 +77,8 @@ impl error::ErrorResponse for TokenError {
             TokenError::Expired
             | TokenError::Immature
             | TokenError::WrongType
-            | TokenError::Invalid => StatusCode::UNAUTHORIZED,
+            | TokenError::Invalid
+            | TokenError::Revoked => StatusCode::UNAUTHORIZED,
             TokenError::EncodingFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -59,33 +94,252 @@ pub enum TokenType {
     Session,
     Client,
     Action,
+    Registration,
+    Mfa,
+    LoginLink,
 }
 
+#[async_trait]
 pub trait TokenClaims
 where
-    Self: Serialize + DeserializeOwned + Sized,
+    Self: Serialize + DeserializeOwned + Sized + Send + Sync,
 {
     const TOKEN_TYPE: TokenType;
 
     fn get_type(&self) -> &TokenType;
 
-    fn encode(&self, config: &TokenConfig) -> Result<String, TokenError> {
-        let header = jsonwebtoken::Header::new(config.alg);
-        let token = jsonwebtoken::encode(&header, self, &config.enc_key).map_err(|e| {
-            error!("Error while encoding token: {:?}", e);
-            TokenError::EncodingFailed(e)
-        })?;
+    /// Subject of the token, if it identifies one
+    fn subject(&self) -> Option<&str> {
+        None
+    }
 
-        Ok(token)
+    /// Checks the claims against the stored state after the token was decoded
+    async fn validate(
+        &self,
+        _db: &Database,
+        _config: &TokenConfig,
+        _client: &ClientInfo,
+    ) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn encode(&self, config: &TokenConfig) -> Result<String, TokenError> {
+        config.encode(self)
     }
 }
 
 #[derive(Clone)]
 pub struct TokenConfig {
-    pub alg: Algorithm,
-    pub enc_key: EncodingKey,
-    pub dec_key: DecodingKey,
     pub validation: Validation,
+    pub clock: SharedClock,
+    /// Region of this instance, issued tokens carry it as key id
+    pub region: Option<String>,
+    /// Shared by all clones, so that rotated keys apply everywhere
+    keys: Arc<RwLock<Keys>>,
+    /// Keys of sibling regions whose tokens are accepted as well
+    siblings: Arc<HashMap<String, DecodingKey>>,
+    /// Published key sets of sibling regions that sign with a private key
+    sibling_keys: Arc<Vec<(String, JwksCache)>>,
+    /// Issued and validated tokens are counted by region
+    metrics: Metrics,
+    /// Lifetime and scope ceilings of sessions by role
+    pub session_limits: SessionLimits,
+    /// Claims derived by expressions, by service
+    pub claim_rules: ClaimRules,
+    /// Tokens of earlier epochs of the deployment are invalid
+    pub epoch: TokenEpoch,
+    /// Issued tokens carry a `sandbox` claim
+    sandbox: bool,
+}
+
+/// Claims of a token issued by a sandbox deployment
+#[derive(Serialize)]
+struct SandboxClaims<'a, T> {
+    #[serde(flatten)]
+    claims: &'a T,
+    sandbox: bool,
+}
+
+struct Keys {
+    alg: Algorithm,
+    /// Key id of issued tokens
+    kid: Option<String>,
+    enc_key: EncodingKey,
+    dec_key: DecodingKey,
+    /// `None` if tokens are signed with the shared secret
+    public_key: Option<PublicKey>,
+    /// Previous and upcoming keys by key id, their tokens are accepted
+    retained: HashMap<String, (DecodingKey, PublicKey)>,
+}
+
+impl Keys {
+    fn new(alg: Algorithm, enc_key: EncodingKey, public_key: PublicKey) -> Self {
+        Self {
+            alg,
+            kid: Some(public_key.id()),
+            enc_key,
+            dec_key: public_key.decoding_key(),
+            public_key: Some(public_key),
+            retained: HashMap::new(),
+        }
+    }
+
+    fn from_pem(alg: Algorithm, pem: &str) -> Result<Self, SigningKeyError> {
+        let doc = pem::parse(pem)?;
+        let rejected = |e: &dyn ToString| SigningKeyError::Rejected(e.to_string());
+
+        match alg {
+            Algorithm::ES256 => Self::from_ec_pkcs8(&doc.contents),
+            Algorithm::RS256 => {
+                let pair = if doc.tag == "RSA PRIVATE KEY" {
+                    RsaKeyPair::from_der(&doc.contents)
+                } else {
+                    RsaKeyPair::from_pkcs8(&doc.contents)
+                }
+                .map_err(|e| rejected(&e))?;
+                let public_key = PublicKey::Rsa {
+                    n: pair
+                        .public_key()
+                        .modulus()
+                        .big_endian_without_leading_zero()
+                        .to_vec(),
+                    e: pair
+                        .public_key()
+                        .exponent()
+                        .big_endian_without_leading_zero()
+                        .to_vec(),
+                };
+                // Signing requires PKCS#1, which is extracted from PKCS#8 documents
+                let enc_key =
+                    EncodingKey::from_rsa_pem(pem.as_bytes()).map_err(|e| rejected(&e))?;
+
+                Ok(Self::new(alg, enc_key, public_key))
+            }
+            Algorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&doc.contents)
+                    .map_err(|e| rejected(&e))?;
+                let public_key = PublicKey::Ed(pair.public_key().as_ref().to_vec());
+
+                Ok(Self::new(
+                    alg,
+                    EncodingKey::from_ed_der(&doc.contents),
+                    public_key,
+                ))
+            }
+            _ => Err(SigningKeyError::Unsupported(alg)),
+        }
+    }
+
+    fn from_ec_pkcs8(der: &[u8]) -> Result<Self, SigningKeyError> {
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der)
+            .map_err(|e| SigningKeyError::Rejected(e.to_string()))?;
+        let public_key = PublicKey::Ec(pair.public_key().as_ref().to_vec());
+
+        Ok(Self::new(
+            Algorithm::ES256,
+            EncodingKey::from_ec_der(der),
+            public_key,
+        ))
+    }
+}
+
+/// Public part of a signing key
+#[derive(Debug, Clone, PartialEq)]
+enum PublicKey {
+    /// Uncompressed P-256 point
+    Ec(Vec<u8>),
+    /// Big-endian modulus and exponent
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    Ed(Vec<u8>),
+}
+
+impl PublicKey {
+    fn id(&self) -> String {
+        match self {
+            PublicKey::Ec(k) | PublicKey::Ed(k) => key_id(k),
+            PublicKey::Rsa { n, e } => key_id(&[n.as_slice(), e.as_slice()].concat()),
+        }
+    }
+
+    fn decoding_key(&self) -> DecodingKey {
+        match self {
+            PublicKey::Ec(point) => DecodingKey::from_ec_der(point),
+            PublicKey::Rsa { n, e } => DecodingKey::from_rsa_raw_components(n, e),
+            PublicKey::Ed(k) => DecodingKey::from_ed_der(k),
+        }
+    }
+
+    fn jwk(&self, alg: Algorithm, kid: &str) -> Jwk {
+        let b64 = |v: &[u8]| base64::encode_config(v, base64::URL_SAFE_NO_PAD);
+
+        let params = match self {
+            PublicKey::Ec(point) => {
+                // Uncompressed point, a tag byte followed by both coordinates
+                let (x, y) = point[1..].split_at(32);
+
+                JwkParameters::Ec {
+                    crv: "P-256",
+                    x: b64(x),
+                    y: b64(y),
+                }
+            }
+            PublicKey::Rsa { n, e } => JwkParameters::Rsa {
+                n: b64(n),
+                e: b64(e),
+            },
+            PublicKey::Ed(k) => JwkParameters::Okp {
+                crv: "Ed25519",
+                x: b64(k),
+            },
+        };
+
+        Jwk {
+            kid: kid.to_string(),
+            alg,
+            key_use: "sig",
+            params,
+        }
+    }
+}
+
+/// Derives the key id from the public key
+pub fn key_id(public_key: &[u8]) -> String {
+    hex::encode(&Sha256::digest(public_key)[..8])
+}
+
+/// Public key to verify issued tokens, RFC 7517
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kid: String,
+    pub alg: Algorithm,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    #[serde(flatten)]
+    pub params: JwkParameters,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kty")]
+pub enum JwkParameters {
+    #[serde(rename = "EC")]
+    Ec {
+        crv: &'static str,
+        x: String,
+        y: String,
+    },
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+    /// Octet key pair of EdDSA, RFC 8037
+    #[serde(rename = "OKP")]
+    Okp { crv: &'static str, x: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
 }
 
 impl TokenConfig {
@@ -101,11 +355,498 @@ impl TokenConfig {
         validation.leeway = Self::LEEWAY;
         validation.set_audience(audience.as_ref());
 
-        Self {
+        let keys = Keys {
             alg: Algorithm::HS256,
+            kid: None,
             enc_key: EncodingKey::from_secret(secret.as_ref()),
             dec_key: DecodingKey::from_secret(secret.as_ref()),
+            public_key: None,
+            retained: HashMap::new(),
+        };
+
+        Self {
             validation,
+            clock: Arc::new(SystemClock),
+            region: None,
+            keys: Arc::new(RwLock::new(keys)),
+            siblings: Default::default(),
+            sibling_keys: Default::default(),
+            metrics: Default::default(),
+            session_limits: Default::default(),
+            claim_rules: Default::default(),
+            epoch: Default::default(),
+            sandbox: false,
+        }
+    }
+
+    /// Signs tokens with a private key instead of the shared secret, so that they can be
+    /// verified with the published public key. Supports ES256 (P-256), RS256 and EdDSA
+    /// (Ed25519) with PKCS#8 documents, RSA keys may also be PKCS#1.
+    pub fn with_signing_key(mut self, pem: &str, alg: Algorithm) -> Result<Self, SigningKeyError> {
+        let mut keys = Keys::from_pem(alg, pem)?;
+
+        // Regions take precedence, see `with_region`
+        if self.region.is_some() {
+            keys.kid = self.region.clone();
+        }
+
+        self.validation.algorithms = vec![alg];
+        self.keys = Arc::new(RwLock::new(keys));
+
+        Ok(self)
+    }
+
+    /// Expects P-256 keys that are set with `rotate_keys`
+    pub fn with_key_rotation(mut self) -> Self {
+        self.validation.algorithms = vec![Algorithm::ES256];
+        self
+    }
+
+    /// Replaces the signing key and the other keys whose tokens are accepted
+    pub fn rotate_keys(
+        &self,
+        pkcs8: &[u8],
+        retained: Vec<(String, Vec<u8>)>,
+    ) -> Result<(), SigningKeyError> {
+        let mut keys = Keys::from_ec_pkcs8(pkcs8)?;
+        keys.retained = retained
+            .into_iter()
+            .map(|(kid, point)| {
+                let key = PublicKey::Ec(point);
+                (kid, (key.decoding_key(), key))
+            })
+            .collect();
+
+        *self.keys.write().unwrap() = keys;
+
+        Ok(())
+    }
+
+    /// Signs the claims with the current key
+    pub fn encode<T>(&self, claims: &T) -> Result<String, TokenError>
+    where
+        T: Serialize,
+    {
+        let keys = self.keys.read().unwrap();
+
+        let mut header = jsonwebtoken::Header::new(keys.alg);
+        header.kid = keys.kid.clone();
+        let token = if self.sandbox {
+            let claims = SandboxClaims {
+                claims,
+                sandbox: true,
+            };
+            jsonwebtoken::encode(&header, &claims, &keys.enc_key)
+        } else {
+            jsonwebtoken::encode(&header, claims, &keys.enc_key)
+        };
+        let token = token.map_err(|e| {
+            error!("Error while encoding token: {:?}", e);
+            TokenError::EncodingFailed(e)
+        })?;
+        self.metrics
+            .record_token_issued(self.region.as_deref().unwrap_or_default());
+
+        Ok(token)
+    }
+
+    /// Public keys to verify issued tokens, empty if they are signed with the shared secret
+    pub fn jwks(&self) -> JwkSet {
+        let keys = self.keys.read().unwrap();
+
+        let current = keys
+            .public_key
+            .as_ref()
+            .zip(keys.kid.as_ref())
+            .map(|(key, kid)| key.jwk(keys.alg, kid));
+        let retained = keys
+            .retained
+            .iter()
+            .map(|(kid, (_, key))| key.jwk(keys.alg, kid));
+
+        JwkSet {
+            keys: current.into_iter().chain(retained).collect(),
+        }
+    }
+
+    /// Sets the region of this instance and the sibling regions with their secrets
+    pub fn with_region<S>(mut self, region: String, siblings: Vec<(String, S)>) -> Self
+    where
+        S: AsRef<[u8]>,
+    {
+        self.siblings = Arc::new(
+            siblings
+                .into_iter()
+                .map(|(r, s)| (r, DecodingKey::from_secret(s.as_ref())))
+                .collect(),
+        );
+        self.keys.write().unwrap().kid = Some(region.clone());
+        self.region = Some(region);
+        self
+    }
+
+    /// Accepts the tokens of sibling regions verified with their published key sets.
+    ///
+    /// The key sets are looked up without fetching, they have to be refreshed in the background.
+    pub fn with_sibling_keys(mut self, sets: Vec<(String, JwksCache)>) -> Self {
+        self.sibling_keys = Arc::new(sets);
+        self
+    }
+
+    /// Counts issued and validated tokens in the metrics
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Region that issued a token, judged by its key id
+    pub fn issuer_region(&self, token: &str) -> Option<String> {
+        let header = jsonwebtoken::decode_header(token).ok()?;
+        let keys = self.keys.read().unwrap();
+
+        match header.kid {
+            Some(ref kid) if Some(kid) != keys.kid.as_ref() && !keys.retained.contains_key(kid) => {
+                if self.siblings.contains_key(kid) {
+                    return Some(kid.clone());
+                }
+                self.sibling_keys
+                    .iter()
+                    .find(|(_, set)| set.cached(kid).is_some())
+                    .map(|(region, _)| region.clone())
+            }
+            _ => self.region.clone(),
+        }
+    }
+
+    /// Counts a token validated for a request by the region that issued it
+    pub fn record_validation(&self, token: &str, valid: bool) {
+        let region = self.issuer_region(token).unwrap_or_default();
+
+        self.metrics.record_token_validation(&region, valid);
+    }
+
+    /// Decodes a token issued by this instance or a sibling region.
+    ///
+    /// Tokens without a key id were issued before regions were configured.
+    pub fn decode<T>(&self, token: &str) -> Result<TokenData<T>, TokenError>
+    where
+        T: DeserializeOwned,
+    {
+        self.decode_with(token, &self.validation)
+    }
+
+    /// Decodes a token with other validation, e.g. of a service audience
+    pub fn decode_with<T>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<TokenData<T>, TokenError>
+    where
+        T: DeserializeOwned,
+    {
+        let header = jsonwebtoken::decode_header(token)?;
+        let keys = self.keys.read().unwrap();
+
+        // Pinned per key, a public key must never be accepted as HMAC secret
+        let published;
+        let (key, alg) = match header.kid {
+            Some(ref kid) if Some(kid) != keys.kid.as_ref() => {
+                if let Some((key, _)) = keys.retained.get(kid) {
+                    (key, keys.alg)
+                } else if let Some(key) = self.siblings.get(kid) {
+                    (key, Algorithm::HS256)
+                } else {
+                    let (alg, key) = self
+                        .sibling_keys
+                        .iter()
+                        .find_map(|(_, set)| set.cached(kid))
+                        .ok_or(TokenError::Invalid)?;
+                    published = key;
+                    // Published keys are never HMAC keys, the key family is checked on decoding
+                    (&published, alg.unwrap_or(header.alg))
+                }
+            }
+            _ => (&keys.dec_key, keys.alg),
+        };
+
+        if header.alg != alg {
+            return Err(TokenError::Invalid);
+        }
+
+        // Siblings may sign with another algorithm than this instance
+        let mut validation = validation.clone();
+        validation.algorithms = vec![alg];
+
+        Ok(jsonwebtoken::decode(token, key, &validation)?)
+    }
+
+    pub fn with_session_limits(mut self, limits: SessionLimits) -> Self {
+        self.session_limits = limits;
+        self
+    }
+
+    pub fn with_claim_rules(mut self, rules: ClaimRules) -> Self {
+        self.claim_rules = rules;
+        self
+    }
+
+    /// Marks issued tokens with a `sandbox` claim, so that they are told apart from production
+    pub fn with_sandbox(mut self) -> Self {
+        self.sandbox = true;
+        self
+    }
+
+    /// Replaces the clock used for claims
+    #[cfg(any(test, feature = "test-util"))]
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::session::SessionClaims;
+
+    use chrono::Utc;
+
+    fn config(region: &str, secret: &str, siblings: Vec<(String, &str)>) -> TokenConfig {
+        TokenConfig::from_secret(secret, ["test"]).with_region(region.into(), siblings)
+    }
+
+    fn token(config: &TokenConfig) -> String {
+        SessionClaims::new(["test".to_string()], "user", Utc::now())
+            .encode(config)
+            .unwrap()
+    }
+
+    #[test]
+    fn accepts_tokens_of_siblings() {
+        let eu = config("eu", "eu-secret", vec![("us".into(), "us-secret")]);
+        let us = config("us", "us-secret", vec![("eu".into(), "eu-secret")]);
+
+        let data = eu.decode::<SessionClaims>(&token(&us)).unwrap();
+
+        assert_eq!(data.header.kid.as_deref(), Some("us"));
+        assert!(us.decode::<SessionClaims>(&token(&eu)).is_ok());
+    }
+
+    #[test]
+    fn accepts_siblings_with_shared_secret() {
+        let eu = config("eu", "secret", vec![("us".into(), "secret")]);
+        let us = config("us", "secret", vec![]);
+
+        assert!(eu.decode::<SessionClaims>(&token(&us)).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_regions() {
+        let eu = config("eu", "secret", vec![]);
+        let ap = config("ap", "secret", vec![]);
+
+        assert!(matches!(
+            eu.decode::<SessionClaims>(&token(&ap)),
+            Err(TokenError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn accepts_tokens_without_region() {
+        let eu = config("eu", "secret", vec![]);
+        let legacy = TokenConfig::from_secret("secret", ["test"]);
+
+        assert!(eu.decode::<SessionClaims>(&token(&legacy)).is_ok());
+    }
+
+    #[test]
+    fn marks_sandbox_tokens() {
+        let production = TokenConfig::from_secret("secret", ["test"]);
+        let sandbox = TokenConfig::from_secret("secret", ["test"]).with_sandbox();
+
+        let claims = |config: &TokenConfig, token: &str| {
+            config
+                .decode::<serde_json::Map<String, serde_json::Value>>(token)
+                .unwrap()
+                .claims
+        };
+        assert_eq!(claims(&sandbox, &token(&sandbox))["sandbox"], true);
+        assert_eq!(claims(&sandbox, &token(&sandbox))["sub"], "user");
+        assert!(!claims(&production, &token(&production)).contains_key("sandbox"));
+    }
+
+    fn signing_key() -> String {
+        let rng = ring::rand::SystemRandom::new();
+        let der = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+
+        pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: der.as_ref().to_vec(),
+        })
+    }
+
+    #[test]
+    fn publishes_signing_key() {
+        let config = TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(&signing_key(), Algorithm::ES256)
+            .unwrap();
+
+        let data = config.decode::<SessionClaims>(&token(&config)).unwrap();
+        assert_eq!(data.header.alg, Algorithm::ES256);
+
+        let jwks = config.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(Some(&jwks.keys[0].kid), data.header.kid.as_ref());
+    }
+
+    #[test]
+    fn publishes_no_shared_secret() {
+        let config = TokenConfig::from_secret("secret", ["test"]);
+
+        assert!(config.jwks().keys.is_empty());
+        assert!(TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key("secret", Algorithm::ES256)
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_secret_tokens_with_signing_key() {
+        let legacy = TokenConfig::from_secret("secret", ["test"]);
+        let config = TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(&signing_key(), Algorithm::ES256)
+            .unwrap();
+
+        assert!(config.decode::<SessionClaims>(&token(&legacy)).is_err());
+    }
+
+    fn pkcs8(pem: &str) -> Vec<u8> {
+        pem::parse(pem).unwrap().contents
+    }
+
+    #[test]
+    fn accepts_tokens_of_rotated_keys() {
+        let (old, new) = (pkcs8(&signing_key()), pkcs8(&signing_key()));
+        let config = TokenConfig::from_secret("secret", ["test"]).with_key_rotation();
+
+        config.rotate_keys(&old, Vec::new()).unwrap();
+        let previous = config.jwks().keys[0].kid.clone();
+        let point = match Keys::from_ec_pkcs8(&old).unwrap().public_key {
+            Some(PublicKey::Ec(point)) => point,
+            _ => unreachable!(),
+        };
+        let token = token(&config);
+
+        config.rotate_keys(&new, vec![(previous, point)]).unwrap();
+        assert!(config.decode::<SessionClaims>(&token).is_ok());
+        assert_eq!(config.jwks().keys.len(), 2);
+
+        config.rotate_keys(&new, Vec::new()).unwrap();
+        assert!(config.decode::<SessionClaims>(&token).is_err());
+    }
+
+    #[test]
+    fn signs_with_rsa_key() {
+        let pem = include_str!("testdata/rsa-2048.pem");
+        let config = TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(pem, Algorithm::RS256)
+            .unwrap();
+
+        let data = config.decode::<SessionClaims>(&token(&config)).unwrap();
+        assert_eq!(data.header.alg, Algorithm::RS256);
+
+        let jwk = serde_json::to_value(&config.jwks().keys[0]).unwrap();
+        assert_eq!(jwk["kty"], "RSA");
+        assert_eq!(jwk["e"], "AQAB");
+    }
+
+    #[test]
+    fn signs_with_ed25519_key() {
+        let rng = ring::rand::SystemRandom::new();
+        let der = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let pem = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: der.as_ref().to_vec(),
+        });
+
+        let config = TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(&pem, Algorithm::EdDSA)
+            .unwrap();
+        let data = config.decode::<SessionClaims>(&token(&config)).unwrap();
+        assert_eq!(data.header.alg, Algorithm::EdDSA);
+
+        let jwk = serde_json::to_value(&config.jwks().keys[0]).unwrap();
+        assert_eq!(jwk["kty"], "OKP");
+        assert_eq!(jwk["crv"], "Ed25519");
+        assert_eq!(jwk["use"], "sig");
+
+        // The key must match the algorithm
+        assert!(TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(&pem, Algorithm::ES256)
+            .is_err());
+        assert!(matches!(
+            TokenConfig::from_secret("secret", ["test"]).with_signing_key(&pem, Algorithm::HS256),
+            Err(SigningKeyError::Unsupported(_))
+        ));
+    }
+
+    /// Serves the key set of the config and returns a cache of it
+    async fn publish(config: &TokenConfig) -> JwksCache {
+        use axum::{routing::get, Json, Router};
+
+        let set = serde_json::to_value(config.jwks()).unwrap();
+        let app = Router::new().route("/", get(move || async move { Json(set) }));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let cache = JwksCache::new(uri.parse().unwrap(), crate::http::HttpClient::allow_http());
+        cache.refresh().await.unwrap();
+        cache
+    }
+
+    #[tokio::test]
+    async fn accepts_siblings_by_key_set() {
+        let us = TokenConfig::from_secret("us-secret", ["test"])
+            .with_signing_key(&signing_key(), Algorithm::ES256)
+            .unwrap()
+            .with_region("us".into(), Vec::<(String, &str)>::new());
+        let ap = TokenConfig::from_secret("ap-secret", ["test"])
+            .with_signing_key(include_str!("testdata/rsa-2048.pem"), Algorithm::RS256)
+            .unwrap()
+            .with_region("ap".into(), Vec::<(String, &str)>::new());
+        let metrics = Metrics::default();
+        let eu = TokenConfig::from_secret("eu-secret", ["test"])
+            .with_key_rotation()
+            .with_region("eu".into(), vec![("ap".to_string(), "ap-secret")])
+            .with_sibling_keys(vec![("us".into(), publish(&us).await)])
+            .with_metrics(metrics.clone());
+        eu.rotate_keys(&pkcs8(&signing_key()), Vec::new()).unwrap();
+
+        let sibling = token(&us);
+        assert!(eu.decode::<SessionClaims>(&sibling).is_ok());
+        assert_eq!(eu.issuer_region(&sibling).as_deref(), Some("us"));
+        eu.record_validation(&sibling, true);
+
+        // Signed with a private key, the shared secret of the region does not apply
+        assert!(eu.decode::<SessionClaims>(&token(&ap)).is_err());
+
+        let own = token(&eu);
+        assert!(eu.decode::<SessionClaims>(&own).is_ok());
+        assert_eq!(eu.issuer_region(&own).as_deref(), Some("eu"));
+
+        #[cfg(feature = "metrics")]
+        {
+            let out = metrics.render();
+            assert!(out.contains("identity_tokens_issued_total{region=\"eu\"} 1"));
+            assert!(
+                out.contains("identity_tokens_validated_total{region=\"us\",result=\"valid\"} 1")
+            );
         }
     }
 }
@@ -0,0 +1,149 @@
+//! Time-based one-time passwords (RFC 6238) of authenticator apps
+
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha1::Sha1;
+
+/// Seconds per code
+const PERIOD: i64 = 30;
+const DIGITS: u32 = 6;
+/// Codes of the adjacent periods are accepted too, for clocks that drifted
+const SKEW: i64 = 1;
+/// Same length as the SHA-1 output, as recommended by RFC 4226
+const SECRET_LEN: usize = 20;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes without padding, as expected in otpauth URIs
+pub fn base32(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8 + 4) / 5);
+
+    for chunk in data.chunks(5) {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let bits = buf.iter().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+
+        let chars = (chunk.len() * 8 + 4) / 5;
+        for i in 0..chars {
+            let index = (bits >> (35 - i * 5)) & 0x1f;
+            out.push(BASE32_ALPHABET[index as usize] as char);
+        }
+    }
+
+    out
+}
+
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    /// Generates a new random secret
+    pub fn generate() -> Self {
+        Self::from_secret(rand::random::<[u8; SECRET_LEN]>().to_vec())
+    }
+
+    pub fn from_secret(secret: Vec<u8>) -> Self {
+        Self { secret }
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Secret as entered manually into an authenticator app
+    pub fn encoded_secret(&self) -> String {
+        base32(&self.secret)
+    }
+
+    /// Key URI of the secret, rendered as QR code for authenticator apps to scan
+    pub fn uri(&self, issuer: &str, account: &str) -> Url {
+        let mut url = Url::parse("otpauth://totp").unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .push(&format!("{}:{}", issuer, account));
+        url.query_pairs_mut()
+            .append_pair("secret", &self.encoded_secret())
+            .append_pair("issuer", issuer)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &DIGITS.to_string())
+            .append_pair("period", &PERIOD.to_string());
+
+        // Not all apps decode `+` as space, literal ones are already encoded as `%2B`
+        let query = url.query().unwrap().replace('+', "%20");
+        url.set_query(Some(&query));
+
+        url
+    }
+
+    fn code(&self, step: i64) -> u32 {
+        let mut mac = Hmac::<Sha1>::new_from_slice(&self.secret).unwrap();
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Dynamic truncation of RFC 4226
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+
+        value % 10u32.pow(DIGITS)
+    }
+
+    /// Returns the time step of the code if it is valid at `now`, in seconds since the epoch
+    pub fn verify(&self, code: &str, now: i64) -> Option<i64> {
+        if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        let current = now / PERIOD;
+
+        (current - SKEW..=current + SKEW).find(|step| {
+            let expected = format!("{:0width$}", self.code(*step), width = DIGITS as usize);
+            ring::constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes())
+                .is_ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Secret of the SHA-1 test vectors of RFC 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_rfc_test_vectors() {
+        let totp = Totp::from_secret(SECRET.to_vec());
+
+        // Last six digits of the eight digit vectors
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(totp.verify(code, time), Some(time / PERIOD));
+        }
+
+        assert_eq!(totp.verify("287082", 59 + 2 * PERIOD), None);
+        assert_eq!(totp.verify("28708", 59), None);
+    }
+
+    #[test]
+    fn encodes_secret_as_base32() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            Totp::from_secret(SECRET.to_vec()).encoded_secret(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+        );
+
+        let uri = Totp::from_secret(SECRET.to_vec()).uri("The Issuer", "user@example.com");
+        assert_eq!(
+            uri.as_str(),
+            "otpauth://totp/The%20Issuer:user@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=The%20Issuer&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Load test against a running instance.
+//!
+//! Measures the throughput of the authentication hot path and exits with an error
+//! if a scenario misses its performance budget:
+//!
+//! | Scenario         | Endpoint                              | min req/s | max p99 |
+//! |------------------|---------------------------------------|-----------|---------|
+//! | token validation | `GET /v1/user/:id`                    | 2000      | 25 ms   |
+//! | client list      | `GET /v1/client`                      | 1000      | 50 ms   |
+//! | user list        | `GET /v1/user`                        | 1000      | 50 ms   |
+//! | SSO callback     | `GET /v1/sso/sandbox/authorize{,d}`   | 250       | 100 ms  |
+//!
+//! The budget assumes a release build with a local MongoDB. The SSO callback runs against the
+//! fake provider of sandbox mode, each request is a whole login from the authorization to the
+//! issued session. It is skipped unless `BENCH_SSO_EMAIL` is set. Build with
+//! `cargo build --profile loadtest --features bench` to profile the server under load.
+//!
+//! Configured with the environment:
+//! - `BENCH_URL`: base URL of the instance, e.g. `http://localhost:8080/v1/`
+//! - `BENCH_TOKEN`: session token of a user with `userRead` and `clientRead` scope
+//! - `BENCH_SSO_EMAIL`: address the sandbox provider signs in, of an allowed domain
+//! - `BENCH_DURATION`: seconds per scenario, defaults to 10
+//! - `BENCH_CONCURRENCY`: concurrent requests, defaults to 64
+
+use std::{
+    process,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    header::{COOKIE, LOCATION, SET_COOKIE},
+    redirect::Policy,
+    Client, StatusCode, Url,
+};
+use serde::Deserialize;
+
+const fn default_duration() -> u64 {
+    10
+}
+
+const fn default_concurrency() -> usize {
+    64
+}
+
+#[derive(Debug, Deserialize)]
+struct BenchConfig {
+    url: Url,
+    token: String,
+    sso_email: Option<String>,
+    #[serde(default = "default_duration")]
+    duration: u64,
+    #[serde(default = "default_concurrency")]
+    concurrency: usize,
+}
+
+#[derive(Clone)]
+enum Request {
+    /// Authenticated request to the path
+    Get(String),
+    /// Login with the sandbox provider, the authorization and its callback
+    SsoLogin { email: String },
+}
+
+struct Scenario {
+    name: &'static str,
+    request: Option<Request>,
+    min_rps: f64,
+    max_p99: Duration,
+}
+
+struct Report {
+    requests: usize,
+    errors: usize,
+    rps: f64,
+    p50: Duration,
+    p99: Duration,
+}
+
+impl Report {
+    fn within(&self, scenario: &Scenario) -> bool {
+        self.errors == 0 && self.rps >= scenario.min_rps && self.p99 <= scenario.max_p99
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let config: BenchConfig = match envy::prefixed("BENCH_").from_env() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let user = match token_subject(&config.token) {
+        Some(s) => s,
+        None => {
+            eprintln!("token is not a valid JWT");
+            process::exit(1);
+        }
+    };
+
+    let scenarios = [
+        Scenario {
+            name: "token validation",
+            request: Some(Request::Get(format!("user/{}", user))),
+            min_rps: 2000.0,
+            max_p99: Duration::from_millis(25),
+        },
+        Scenario {
+            name: "client list",
+            request: Some(Request::Get("client".into())),
+            min_rps: 1000.0,
+            max_p99: Duration::from_millis(50),
+        },
+        Scenario {
+            name: "user list",
+            request: Some(Request::Get("user".into())),
+            min_rps: 1000.0,
+            max_p99: Duration::from_millis(50),
+        },
+        Scenario {
+            name: "SSO callback",
+            request: config
+                .sso_email
+                .clone()
+                .map(|email| Request::SsoLogin { email }),
+            min_rps: 250.0,
+            max_p99: Duration::from_millis(100),
+        },
+    ];
+
+    // The callback is requested by the scenario itself, with the state cookie
+    let client = Client::builder().redirect(Policy::none()).build().unwrap();
+    let mut passed = true;
+
+    println!(
+        "{:<18} {:>9} {:>7} {:>10} {:>10} {:>10}  budget",
+        "scenario", "requests", "errors", "req/s", "p50", "p99"
+    );
+
+    for scenario in scenarios {
+        let request = match scenario.request {
+            Some(ref r) => r,
+            None => {
+                println!("{:<18} skipped", scenario.name);
+                continue;
+            }
+        };
+        let report = run(&client, &config, request).await;
+        let within = report.within(&scenario);
+        passed &= within;
+
+        println!(
+            "{:<18} {:>9} {:>7} {:>10.0} {:>10.2?} {:>10.2?}  {}",
+            scenario.name,
+            report.requests,
+            report.errors,
+            report.rps,
+            report.p50,
+            report.p99,
+            if within { "ok" } else { "MISSED" }
+        );
+    }
+
+    if !passed {
+        process::exit(1);
+    }
+}
+
+/// Sends the request, returns if it succeeded
+async fn send(client: &Client, base: &Url, token: &str, request: &Request) -> bool {
+    match request {
+        Request::Get(path) => {
+            let res = client
+                .get(base.join(path).unwrap())
+                .bearer_auth(token)
+                .send()
+                .await;
+
+            matches!(res, Ok(r) if r.status() == StatusCode::OK)
+        }
+        Request::SsoLogin { email } => {
+            let res = client
+                .get(base.join("sso/sandbox/authorize").unwrap())
+                .query(&[("email", email)])
+                .send()
+                .await;
+            let res = match res {
+                Ok(r) if r.status() == StatusCode::SEE_OTHER => r,
+                _ => return false,
+            };
+
+            let header = |name| res.headers().get(name).and_then(|v| v.to_str().ok());
+            let (location, cookie) = match (header(LOCATION), header(SET_COOKIE)) {
+                (Some(l), Some(c)) => (l, c.split(';').next().unwrap_or_default()),
+                _ => return false,
+            };
+            let callback = match base.join(location) {
+                Ok(url) => url,
+                Err(_) => return false,
+            };
+
+            let res = client.get(callback).header(COOKIE, cookie).send().await;
+
+            matches!(res, Ok(r) if r.status() == StatusCode::CREATED)
+        }
+    }
+}
+
+async fn run(client: &Client, config: &BenchConfig, request: &Request) -> Report {
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(config.duration);
+
+    let workers = (0..config.concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let base = config.url.clone();
+            let token = config.token.clone();
+            let request = request.clone();
+
+            tokio::spawn(async move {
+                let mut latencies = Vec::new();
+                let mut errors = 0;
+
+                while Instant::now() < deadline {
+                    let req_start = Instant::now();
+                    let ok = send(&client, &base, &token, &request).await;
+                    latencies.push(req_start.elapsed());
+
+                    if !ok {
+                        errors += 1;
+                    }
+                }
+
+                (latencies, errors)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    for worker in workers {
+        let (l, e) = worker.await.unwrap();
+        latencies.extend(l);
+        errors += e;
+    }
+
+    let elapsed = start.elapsed();
+    latencies.sort_unstable();
+
+    Report {
+        requests: latencies.len(),
+        errors,
+        rps: latencies.len() as f64 / elapsed.as_secs_f64(),
+        p50: percentile(&latencies, 50),
+        p99: percentile(&latencies, 99),
+    }
+}
+
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+/// Reads the subject of a token without validating it
+fn token_subject(token: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Claims {
+        sub: String,
+    }
+
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+
+    Some(claims.sub)
+}
@@ -0,0 +1,45 @@
+use crate::{
+    authentication::{token::TokenConfig, AuthenticationError},
+    database::Database,
+    extract::{ClientInfo, SizedJson, TokenData},
+    model::Response,
+    session::SessionClaims,
+};
+
+use super::{start, BulkError, Criteria, RevocationJobResponse};
+
+use axum::extract::{Extension, Path};
+use hyper::StatusCode;
+use mongodb::bson::oid::ObjectId;
+
+/// Starts revoking the matching sessions, the response tracks the progress of the job
+pub async fn revoke(
+    client: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(criteria): SizedJson<Criteria>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<RevocationJobResponse>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let job = start(&db, criteria, &claims.sub, client, config.clock).await?;
+
+    Ok(Response::with_status(StatusCode::ACCEPTED, job.into()))
+}
+
+pub async fn get_job(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<RevocationJobResponse>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| BulkError::NotFound)?;
+    let job = db.get_revocation_job(id).await?;
+
+    Ok(Response::new(job.into()))
+}
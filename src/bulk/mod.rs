@@ -0,0 +1,422 @@
+//! Revocation of many sessions at once, e.g. after a key compromise or a misconfigured role.
+//!
+//! The criteria select tracked sessions, all of them have to match. Matching sessions are
+//! revoked in batches by a background job, which records its progress so that admins can
+//! follow it on any instance.
+
+mod handler;
+mod routes;
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    clock::SharedClock,
+    database::Database,
+    error,
+    extract::ClientInfo,
+    model::Status,
+    user::{Role, UserError},
+    utils, Result,
+};
+
+use std::{net::IpAddr, str::FromStr};
+
+use chrono::{serde::ts_seconds_option, DateTime, Utc};
+use hyper::StatusCode;
+use mongodb::bson::{
+    self, doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+pub use routes::routes;
+
+/// Sessions revoked per batch
+const BATCH_SIZE: i64 = 200;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BulkError {
+    #[error("at least one criterion is required")]
+    NoCriteria,
+    #[error("IP range is invalid")]
+    InvalidIpRange,
+    #[error("revocation job not found")]
+    NotFound,
+}
+
+impl error::ErrorResponse for BulkError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BulkError::NoCriteria | BulkError::InvalidIpRange => StatusCode::BAD_REQUEST,
+            BulkError::NotFound => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Network in CIDR notation, a single address without prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        fn masked(bits: u128, prefix: u8, len: u8) -> u128 {
+            let shift = len - prefix;
+            if shift >= 128 {
+                0
+            } else {
+                bits >> shift
+            }
+        }
+
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                masked(u32::from(net).into(), self.prefix, 32)
+                    == masked(u32::from(addr).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                masked(net.into(), self.prefix, 128) == masked(addr.into(), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = BulkError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| BulkError::InvalidIpRange)?;
+        let len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(v) => v.parse().map_err(|_| BulkError::InvalidIpRange)?,
+            None => len,
+        };
+        if prefix > len {
+            return Err(BulkError::InvalidIpRange);
+        }
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for IpRange {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Sessions to revoke, all given criteria have to match
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Criteria {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// Sessions that started before
+    #[serde(
+        default,
+        with = "ts_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub issued_before: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_range: Option<IpRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+}
+
+impl Criteria {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+            && self.role.is_none()
+            && self.issued_before.is_none()
+            && self.ip_range.is_none()
+            && self.audience.is_none()
+    }
+
+    /// Filter of the criteria the database can match, IP ranges are matched per batch
+    async fn filter(&self, db: &Database) -> Result<Document> {
+        let mut filter = Document::new();
+
+        let mut users = self
+            .users
+            .iter()
+            .map(|id| ObjectId::parse_str(id).map_err(|_| UserError::InvalidId))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if let Some(role) = &self.role {
+            let with_role = db.get_user_ids_with_role(role).await?;
+            users = if self.users.is_empty() {
+                with_role
+            } else {
+                users.retain(|u| with_role.contains(u));
+                users
+            };
+        }
+        if !self.users.is_empty() || self.role.is_some() {
+            filter.insert("user", doc! { "$in": users });
+        }
+
+        if let Some(before) = self.issued_before {
+            filter.insert(
+                "createdAt",
+                doc! { "$lt": bson::DateTime::from_chrono(before) },
+            );
+        }
+        if let Some(aud) = &self.audience {
+            filter.insert("audience", aud);
+        }
+
+        Ok(filter)
+    }
+
+    fn matches_ip(&self, ip: Option<&str>) -> bool {
+        match &self.ip_range {
+            Some(range) => ip
+                .and_then(|v| IpAddr::from_str(v).ok())
+                .map_or(false, |v| range.contains(v)),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationJobDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub criteria: Criteria,
+    pub status: JobStatus,
+    /// Sessions matching the criteria except the IP range, when the job started
+    pub total: u64,
+    pub scanned: u64,
+    pub revoked: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Admin that started the job
+    pub created_by: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<bson::DateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationJobResponse {
+    pub id: String,
+    pub criteria: Criteria,
+    pub status: JobStatus,
+    pub total: u64,
+    pub scanned: u64,
+    pub revoked: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_by: String,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds_option", skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<RevocationJobDocument> for RevocationJobResponse {
+    fn from(doc: RevocationJobDocument) -> Self {
+        Self {
+            id: doc.id.to_hex(),
+            criteria: doc.criteria,
+            status: doc.status,
+            total: doc.total,
+            scanned: doc.scanned,
+            revoked: doc.revoked,
+            error: doc.error,
+            created_by: doc.created_by,
+            created_at: doc.created_at,
+            finished_at: doc.finished_at.map(|v| v.to_chrono()),
+        }
+    }
+}
+
+const COLLECTION: &str = "revocation_jobs";
+
+impl Database {
+    pub async fn insert_revocation_job(&self, doc: &RevocationJobDocument) -> Result<()> {
+        self.collection::<RevocationJobDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_revocation_job(&self, id: ObjectId) -> Result<RevocationJobDocument> {
+        let job = self
+            .collection::<RevocationJobDocument>(COLLECTION)
+            .find_one(doc! { "_id": id }, None)
+            .await?
+            .ok_or(BulkError::NotFound)?;
+
+        Ok(job)
+    }
+
+    async fn update_revocation_job(&self, id: ObjectId, update: Document) -> Result<()> {
+        self.collection::<RevocationJobDocument>(COLLECTION)
+            .update_one(doc! { "_id": id }, update, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Starts a job that revokes all sessions matching the criteria
+pub async fn start(
+    db: &Database,
+    criteria: Criteria,
+    admin: &str,
+    client: ClientInfo,
+    clock: SharedClock,
+) -> Result<RevocationJobDocument> {
+    if criteria.is_empty() {
+        return Err(BulkError::NoCriteria.into());
+    }
+
+    let filter = criteria.filter(db).await?;
+    let job = RevocationJobDocument {
+        id: ObjectId::new(),
+        total: db.count_sessions(filter.clone()).await?,
+        criteria,
+        status: JobStatus::Running,
+        scanned: 0,
+        revoked: 0,
+        error: None,
+        created_by: admin.to_string(),
+        created_at: clock.now(),
+        finished_at: None,
+    };
+    db.insert_revocation_job(&job).await?;
+
+    info!(job = %job.id, total = job.total, "bulk session revocation started");
+
+    let (db, id, criteria) = (db.clone(), job.id, job.criteria.clone());
+    utils::spawn_named("session-revocation", async move {
+        let result = run(&db, id, &criteria, filter, &client, &clock).await;
+
+        let finished_at = bson::DateTime::from_chrono(clock.now());
+        let update = match &result {
+            Ok(()) => {
+                doc! { "$set": { "status": "completed", "finishedAt": finished_at } }
+            }
+            Err(e) => {
+                error!(job = %id, error = %e, "bulk session revocation failed");
+                doc! {
+                    "$set": {
+                        "status": "failed",
+                        "error": e.to_string(),
+                        "finishedAt": finished_at,
+                    }
+                }
+            }
+        };
+        if let Err(e) = db.update_revocation_job(id, update).await {
+            error!(job = %id, error = %e, "revocation job could not be finished");
+        }
+    });
+
+    Ok(job)
+}
+
+async fn run(
+    db: &Database,
+    id: ObjectId,
+    criteria: &Criteria,
+    filter: Document,
+    client: &ClientInfo,
+    clock: &SharedClock,
+) -> Result<()> {
+    let mut after = None;
+
+    loop {
+        let batch = db.find_sessions(filter.clone(), after, BATCH_SIZE).await?;
+        let last = match batch.last() {
+            Some(s) => s.id,
+            None => return Ok(()),
+        };
+
+        let mut revoked = 0;
+        for session in batch
+            .iter()
+            .filter(|s| criteria.matches_ip(s.ip.as_deref()))
+        {
+            db.revoke_active_session(session, clock.now()).await?;
+
+            let event = SecurityEvent::SessionRevoked {
+                jti: session.jti.clone(),
+            };
+            db.insert_audit_event(&AuditEventDocument::new(session.user, event, client))
+                .await?;
+            revoked += 1;
+        }
+
+        let update = doc! { "$inc": { "scanned": batch.len() as i64, "revoked": revoked } };
+        db.update_revocation_job(id, update).await?;
+
+        after = Some(last);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_ranges() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains("10.1.255.3".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let any: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("192.0.2.1".parse().unwrap()));
+
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert_eq!(single.to_string(), "2001:db8::1/128");
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("host/8".parse::<IpRange>().is_err());
+    }
+}
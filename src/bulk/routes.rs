@@ -0,0 +1,10 @@
+use super::handler;
+
+use axum::routing::{get, post};
+
+/// Bulk session revocation routes
+pub fn routes() -> axum::Router {
+    axum::Router::new()
+        .route("/revoke", post(handler::revoke))
+        .route("/revoke/:id", get(handler::get_job))
+}
@@ -0,0 +1,148 @@
+//! Shared fast storage of the replicas in Redis, e.g. for rate limits and revoked tokens.
+//!
+//! Values are stored as JSON and expire after a TTL. The cache only speeds up or shares state
+//! that callers can do without, so its errors are returned to fall back to the database or to
+//! skip the cache, not to fail requests.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use redis::{aio::ConnectionManager, AsyncCommands, FromRedisValue, Script};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::info;
+
+/// Prefix of all keys, so that the server can share Redis with others
+const KEY_PREFIX: &str = "identity:";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("cache is unavailable: {0}")]
+    Unavailable(#[from] redis::RedisError),
+    #[error("cached value is invalid: {0}")]
+    InvalidValue(#[from] serde_json::Error),
+}
+
+/// Pool of connections, each of them multiplexed and reconnected on failure
+#[derive(Clone)]
+pub struct Cache {
+    pool: Arc<Vec<ConnectionManager>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("size", &self.pool.len())
+            .finish()
+    }
+}
+
+impl Cache {
+    pub async fn connect(url: &str, size: usize) -> crate::Result<Self> {
+        let config_error =
+            |e: redis::RedisError| crate::error::Error::Config(format!("cache: {}", e));
+
+        let client = redis::Client::open(url).map_err(config_error)?;
+        let mut pool = Vec::with_capacity(size);
+        for _ in 0..size.max(1) {
+            let conn = ConnectionManager::new(client.clone())
+                .await
+                .map_err(config_error)?;
+            pool.push(conn);
+        }
+        info!(size = pool.len(), "cache connected");
+
+        Ok(Self {
+            pool: Arc::new(pool),
+            next: Arc::default(),
+        })
+    }
+
+    /// Connection of the pool, in turns
+    fn conn(&self) -> ConnectionManager {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        self.pool[i].clone()
+    }
+
+    fn key(key: &str) -> String {
+        format!("{}{}", KEY_PREFIX, key)
+    }
+
+    pub async fn get<T>(&self, key: &str) -> Result<Option<T>, CacheError>
+    where
+        T: DeserializeOwned,
+    {
+        let value: Option<String> = self.conn().get(Self::key(key)).await?;
+
+        Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+
+    /// Stores the value until the TTL passed
+    pub async fn set<T>(&self, key: &str, value: &T, ttl: Duration) -> Result<(), CacheError>
+    where
+        T: Serialize,
+    {
+        let value = serde_json::to_string(value)?;
+
+        redis::cmd("SET")
+            .arg(Self::key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .query_async::<_, ()>(&mut self.conn())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Stores the value like [`Self::set`] unless the key exists, returns `false` if it did
+    pub async fn insert<T>(&self, key: &str, value: &T, ttl: Duration) -> Result<bool, CacheError>
+    where
+        T: Serialize,
+    {
+        let value = serde_json::to_string(value)?;
+
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(Self::key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl_millis(ttl))
+            .arg("NX")
+            .query_async(&mut self.conn())
+            .await?;
+
+        Ok(reply.is_some())
+    }
+
+    /// Round trip to Redis on the next connection of the pool
+    pub async fn ping(&self) -> Result<(), CacheError> {
+        redis::cmd("PING")
+            .query_async::<_, ()>(&mut self.conn())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Runs the script on the key, e.g. to read and update it atomically
+    pub async fn eval<T>(&self, script: &Script, key: &str, args: &[u64]) -> Result<T, CacheError>
+    where
+        T: FromRedisValue,
+    {
+        let mut invocation = script.key(Self::key(key));
+        for arg in args {
+            invocation.arg(*arg);
+        }
+
+        Ok(invocation.invoke_async(&mut self.conn()).await?)
+    }
+}
+
+/// Redis rejects a TTL of zero
+fn ttl_millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
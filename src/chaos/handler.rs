@@ -0,0 +1,36 @@
+use crate::{
+    authentication::AuthenticationError,
+    database::Database,
+    extract::{SizedJson, TokenData},
+    model::Response,
+    session::SessionClaims,
+};
+
+use super::Faults;
+
+use axum::extract::Extension;
+use tracing::warn;
+
+pub async fn get(TokenData(claims): TokenData<SessionClaims>) -> crate::Result<Response<Faults>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    Ok(Response::new(Faults::current()))
+}
+
+pub async fn update(
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<Faults>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<Faults>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    warn!(faults = ?body, user = %claims.sub, "fault injection changed");
+
+    body.clone().apply(&db).await?;
+
+    Ok(Response::new(body))
+}
@@ -0,0 +1,97 @@
+//! Fault injection to verify the behavior of the service when its dependencies misbehave.
+//!
+//! Database latency is injected with the `failCommand` fail point of MongoDB, which requires
+//! the server to run with `enableTestCommands=1`.
+
+mod handler;
+mod routes;
+
+use crate::{database::Database, Result};
+
+use std::sync::RwLock;
+
+use mongodb::bson::doc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+pub use routes::routes;
+
+#[cfg(not(debug_assertions))]
+compile_error!("fault injection is not meant for release builds");
+
+static FAULTS: Lazy<RwLock<Faults>> = Lazy::new(Default::default);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Faults {
+    /// Share of upstream calls that fail, from 0 to 1
+    pub upstream_failure_rate: f64,
+    /// Latency added to database commands
+    pub database_latency_ms: u64,
+    /// Share of mail deliveries that are dropped silently, from 0 to 1
+    pub mail_drop_rate: f64,
+}
+
+impl Faults {
+    const FAIL_POINT_COMMANDS: [&'static str; 7] = [
+        "find",
+        "insert",
+        "update",
+        "delete",
+        "findAndModify",
+        "aggregate",
+        "count",
+    ];
+
+    fn current() -> Self {
+        FAULTS.read().unwrap().clone()
+    }
+
+    /// Replaces the active faults
+    async fn apply(self, db: &Database) -> Result<()> {
+        let fail_point = if self.database_latency_ms > 0 {
+            doc! {
+                "configureFailPoint": "failCommand",
+                "mode": "alwaysOn",
+                "data": {
+                    "failCommands": Self::FAIL_POINT_COMMANDS.to_vec(),
+                    "blockConnection": true,
+                    "blockTimeMS": self.database_latency_ms as i64,
+                },
+            }
+        } else {
+            doc! { "configureFailPoint": "failCommand", "mode": "off" }
+        };
+
+        db.admin().run_command(fail_point, None).await?;
+
+        *FAULTS.write().unwrap() = self;
+
+        Ok(())
+    }
+}
+
+fn hit(rate: f64) -> bool {
+    rate > 0.0 && rand::random::<f64>() < rate
+}
+
+/// Decides if the next upstream call fails
+pub fn fail_upstream() -> bool {
+    hit(FAULTS.read().unwrap().upstream_failure_rate)
+}
+
+/// Decides if the next mail is dropped
+pub fn drop_mail() -> bool {
+    hit(FAULTS.read().unwrap().mail_drop_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit_rate_bounds() {
+        assert!(!hit(0.0));
+        assert!(hit(1.0));
+    }
+}
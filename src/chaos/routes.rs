@@ -0,0 +1,8 @@
+use super::handler;
+
+use axum::routing::get;
+
+/// Fault injection routes
+pub fn routes() -> axum::Router {
+    axum::Router::new().route("/", get(handler::get).put(handler::update))
+}
@@ -0,0 +1,112 @@
+//! Periodic confirmation of client ownership, so that clients of people who left or of
+//! retired integrations don't stay around unnoticed
+
+use crate::{clock::SharedClock, database::Database, error::Error, mail, utils, Result};
+
+use super::{ClientDocument, ClientError};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::{self, doc, Document};
+use tracing::{error, info, warn};
+
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+const DAYS_PER_MONTH: i64 = 30;
+
+/// Asks the owners of clients that were neither used nor confirmed for the given number of
+/// months to confirm them, the clients are flagged as unconfirmed until they do
+pub fn spawn_attestation(db: Database, mail: mail::Client, months: u32, clock: SharedClock) {
+    utils::spawn_named("client-attestation", async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let cutoff = clock.now() - Duration::days(DAYS_PER_MONTH * i64::from(months));
+            match request_confirmations(&db, &mail, cutoff).await {
+                Ok(0) => {}
+                Ok(count) => info!(count, "requested client ownership confirmations"),
+                Err(e) => error!(error = %e, "client ownership confirmation failed"),
+            }
+        }
+    });
+}
+
+/// Query matching unflagged clients that were neither used nor confirmed since the cutoff
+fn stale_filter(cutoff: DateTime<Utc>) -> Document {
+    let cutoff = bson::DateTime::from_chrono(cutoff);
+
+    doc! {
+        "unconfirmed": { "$ne": true },
+        "lastIssued": { "$lt": cutoff },
+        "$or": [
+            { "confirmedAt": { "$lt": cutoff } },
+            { "confirmedAt": { "$exists": false } },
+        ],
+    }
+}
+
+async fn request_confirmations(
+    db: &Database,
+    mail: &mail::Client,
+    cutoff: DateTime<Utc>,
+) -> Result<u64> {
+    let filter = stale_filter(cutoff);
+    let mut count = 0;
+
+    // Clients are claimed one at a time, so that concurrent instances never mail twice
+    while let Some(client) = db.flag_unconfirmed(filter.clone()).await? {
+        warn!(client = %client.id, "client flagged as unconfirmed");
+        count += 1;
+
+        let addr = match client.contact {
+            Some(ref addr) => addr.clone(),
+            None => match db.get_user(doc! { "_id": client.user }).await {
+                Ok(user) => user.email,
+                Err(e) => {
+                    warn!(client = %client.id, error = %e, "client owner not found");
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = send_confirmation_mail(&addr, &client, mail).await {
+            warn!(client = %client.id, error = %e, "confirmation mail could not be sent");
+        }
+    }
+
+    Ok(count)
+}
+
+async fn send_confirmation_mail(
+    addr: &str,
+    client: &ClientDocument,
+    mail: &mail::Client,
+) -> Result<()> {
+    const TEMPLATE_NAME: &str = "identity.client.confirm";
+    const SUBJECT: &str = "Please confirm that your client is still needed";
+
+    let mut vars = HashMap::with_capacity(2);
+    vars.insert("clientId".to_string(), client.id.to_hex());
+    vars.insert("clientName".to_string(), client.name.clone());
+
+    mail.send_template(addr, SUBJECT, TEMPLATE_NAME, vars)
+        .await?;
+
+    Ok(())
+}
+
+impl Database {
+    /// Flags the first client matching the filter as unconfirmed, `None` if none matches
+    async fn flag_unconfirmed(&self, filter: Document) -> Result<Option<ClientDocument>> {
+        let update = doc! { "$set": { "unconfirmed": true } };
+
+        match self.modify_client(filter, update).await {
+            Ok(client) => Ok(Some(client)),
+            Err(Error::Client(ClientError::NotFound)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Canary clients, planted credentials that no legitimate caller ever uses.
+//!
+//! Any use of a canary client alerts the security contacts. With auto-lock, the other clients of
+//! its owner are locked and the owner's sessions are revoked, as credentials stored next to the
+//! canary are likely leaked as well. Callers only see a locked client.
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    database::Database,
+    extract::ClientInfo,
+    user::SecurityNotifier,
+    Result,
+};
+
+use super::{ClientDocument, COLLECTION};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId};
+use tracing::warn;
+
+/// Records and reports the use of a canary client
+pub async fn trip(
+    db: &Database,
+    notifier: &SecurityNotifier,
+    client: &ClientDocument,
+    info: &ClientInfo,
+    auto_lock: bool,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    warn!(client = %client.id, addr = ?info.addr, "canary client used");
+
+    let event = SecurityEvent::CanaryTriggered {
+        client: client.id.to_hex(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, info))
+        .await?;
+
+    if auto_lock {
+        db.lock_user_clients(client.user).await?;
+        db.revoke_user_sessions(client.user, now).await?;
+    }
+
+    notify(notifier, client, info, auto_lock).await;
+
+    Ok(())
+}
+
+async fn notify(
+    notifier: &SecurityNotifier,
+    client: &ClientDocument,
+    info: &ClientInfo,
+    locked: bool,
+) {
+    const TEMPLATE_NAME: &str = "identity.security.canary";
+
+    let mut vars = HashMap::new();
+    vars.insert("client".to_string(), client.id.to_hex());
+    vars.insert("user".to_string(), client.user.to_hex());
+    if let Some(addr) = info.addr {
+        vars.insert("addr".to_string(), addr.to_string());
+    }
+    if let Some(ua) = &info.user_agent {
+        vars.insert("userAgent".to_string(), ua.clone());
+    }
+    vars.insert("locked".to_string(), locked.to_string());
+
+    notifier
+        .send("Canary credentials used", TEMPLATE_NAME, vars)
+        .await;
+}
+
+impl Database {
+    /// Locks all clients of the user, bypassing the revision history
+    async fn lock_user_clients(&self, user: ObjectId) -> Result<()> {
+        self.collection::<ClientDocument>(COLLECTION)
+            .update_many(
+                doc! { "user": user },
+                doc! {
+                    "$set": { "unlocked": false },
+                    "$currentDate": { "lastModified": true },
+                },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,36 +1,28 @@
 use crate::{
-    authentication::AuthenticationError,
+    audit::{AuditEventDocument, SecurityEvent},
+    authentication::{client_secret, credential::CredentialHasher, AuthenticationError},
     database::Database,
     error::QueryError,
-    extract::{Query, SizedJson, TokenData},
+    extract::{ClientInfo, Query, SizedJson, TokenData},
+    label,
     model::{List, ListOptions, Response, Status},
+    models::client::{ClientResponse, CreateRequest, SecretResponse, UpdateRequest},
+    revision::RevisionResponse,
     service::ServiceError,
-    session::{self, SessionClaims},
+    session::{Access, Resource, SessionClaims},
     user::UserError,
 };
 
 use super::{ClientDocument, ClientError};
 
 use axum::extract::{Extension, Path};
-use chrono::{serde::ts_seconds, DateTime, TimeZone, Utc};
+use chrono::{TimeZone, Utc};
 use hyper::StatusCode;
-use mongodb::bson::{doc, oid::ObjectId, to_document, Document};
+use mongodb::bson::{doc, oid::ObjectId, to_bson, to_document, Document};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ClientResponse {
-    pub id: String,
-    pub user: String,
-    pub service: String,
-    pub name: String,
-    pub scope: Vec<String>,
-    pub unlocked: bool,
-    #[serde(with = "ts_seconds")]
-    pub last_issued: DateTime<Utc>,
-    #[serde(with = "ts_seconds")]
-    pub last_modified: DateTime<Utc>,
-}
+const MAX_DESCRIPTION_LEN: usize = 1024;
 
 impl From<ClientDocument> for ClientResponse {
     fn from(doc: ClientDocument) -> Self {
@@ -41,6 +33,17 @@ impl From<ClientDocument> for ClientResponse {
             name: doc.name,
             scope: doc.scope,
             unlocked: doc.unlocked,
+            labels: doc.labels,
+            description: doc.description,
+            contact: doc.contact,
+            documentation_url: doc.documentation_url,
+            redirect_uris: doc.redirect_uris,
+            schedule: doc.schedule,
+            confirmed_at: doc.confirmed_at,
+            unconfirmed: doc.unconfirmed,
+            legacy_token_disabled: doc.legacy_token_disabled,
+            canary: doc.canary,
+            has_secret: doc.secret.is_some(),
             last_issued: doc.last_issued,
             last_modified: doc.last_modified,
         }
@@ -56,6 +59,11 @@ pub struct Filter {
     approved: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     service: Option<String>,
+    /// Comma-separated `key:value` selectors
+    #[serde(skip_serializing)]
+    label: Option<String>,
+    #[serde(skip_serializing)]
+    unconfirmed: Option<bool>,
 }
 
 pub async fn list(
@@ -64,7 +72,7 @@ pub async fn list(
     Query(opts): Query<ListOptions>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<List<ClientResponse>>> {
-    let user = if !claims.scope.contains(&session::Scope::ClientRead) {
+    let user = if !claims.is_global(Resource::Client, Access::Read) {
         Some(&claims.sub)
     } else {
         filter.user.as_ref()
@@ -77,6 +85,14 @@ pub async fn list(
     if let Some(id) = filter.service {
         f.insert("service", ObjectId::parse_str(id).unwrap());
     }
+    if let Some(v) = filter.label {
+        f.extend(label::filter(&v)?);
+    }
+    match filter.unconfirmed {
+        Some(true) => f.insert("unconfirmed", true),
+        Some(false) => f.insert("unconfirmed", doc! { "$ne": true }),
+        None => None,
+    };
 
     let (clients, total) = db.get_clients(f, opts).await?;
     let list = List::new(total, clients);
@@ -92,7 +108,7 @@ pub async fn get_by_id(
     let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
 
     let mut filter = doc! { "_id": id };
-    if !claims.scope.contains(&session::Scope::ClientRead) {
+    if !claims.is_global(Resource::Client, Access::Read) {
         let id = ObjectId::parse_str(&claims.sub).unwrap();
         filter.insert("user", id);
     }
@@ -102,28 +118,74 @@ pub async fn get_by_id(
     Ok(Response::with_status(StatusCode::OK, client.into()))
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct CreateRequest {
-    user: Option<String>,
-    name: String,
-    service: String,
-    scope: Option<Vec<String>>,
+/// Validates the descriptive fields of a client
+fn validate_details(
+    description: Option<&str>,
+    contact: Option<&str>,
+    documentation_url: Option<&Url>,
+) -> Result<(), ClientError> {
+    if description.map_or(false, |v| v.chars().count() > MAX_DESCRIPTION_LEN) {
+        return Err(ClientError::InvalidDetail("description"));
+    }
+
+    let is_addr = |v: &str| {
+        v.split_once('@').map_or(false, |(local, domain)| {
+            !local.is_empty() && domain.contains('.')
+        })
+    };
+    if contact.map_or(false, |v| !is_addr(v)) {
+        return Err(ClientError::InvalidDetail("contact"));
+    }
+
+    if documentation_url.map_or(false, |v| !matches!(v.scheme(), "https" | "http")) {
+        return Err(ClientError::InvalidDetail("documentation URL"));
+    }
+
+    Ok(())
+}
+
+/// Validates the redirect URIs, plain HTTP is only accepted for loopback addresses
+fn validate_redirect_uris(uris: &[Url]) -> Result<(), ClientError> {
+    let is_valid = |v: &Url| {
+        v.fragment().is_none()
+            && match v.scheme() {
+                "https" => true,
+                "http" => matches!(v.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")),
+                _ => false,
+            }
+    };
+    if !uris.iter().all(is_valid) {
+        return Err(ClientError::InvalidDetail("redirect URI"));
+    }
+
+    Ok(())
 }
 
 pub async fn create(
+    info: ClientInfo,
     TokenData(claims): TokenData<SessionClaims>,
     SizedJson(body): SizedJson<CreateRequest>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<ClientResponse>> {
     let user_id = if let Some(id) = body.user {
-        if !claims.scope.contains(&session::Scope::ClientWrite) && claims.sub != id {
+        if !claims.is_permitted(Resource::Client, Access::Write, &id) {
             return Err(AuthenticationError::InsufficientPermission.into());
         }
         ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?
     } else {
         ObjectId::parse_str(&claims.sub).unwrap()
     };
+    if body.canary && !claims.is_global(Resource::Client, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    label::validate(&body.labels)?;
+    validate_details(
+        body.description.as_deref(),
+        body.contact.as_deref(),
+        body.documentation_url.as_ref(),
+    )?;
+    validate_redirect_uris(&body.redirect_uris)?;
 
     let svc_id = ObjectId::parse_str(&body.service).map_err(|_| ServiceError::InvalidId)?;
 
@@ -136,25 +198,34 @@ pub async fn create(
         service: svc_id,
         scope: svc.scope_default,
         unlocked: false,
+        labels: body.labels,
+        description: body.description,
+        contact: body.contact,
+        documentation_url: body.documentation_url,
+        redirect_uris: body.redirect_uris,
+        schedule: None,
+        confirmed_at: Utc::now(),
+        unconfirmed: false,
+        legacy_token_disabled: false,
+        canary: body.canary,
+        secret: None,
         last_issued: Utc.timestamp(0, 0),
         last_modified: Utc::now(),
     };
 
     db.insert_client(&client).await?;
 
-    Ok(Response::with_status(StatusCode::CREATED, client.into()))
-}
+    let event = SecurityEvent::ClientCreated {
+        client: client.id.to_hex(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, &info))
+        .await?;
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct UpdateRequest {
-    user: Option<String>,
-    name: Option<String>,
-    scope: Option<Vec<String>>,
-    unlocked: Option<bool>,
+    Ok(Response::with_status(StatusCode::CREATED, client.into()))
 }
 
 pub async fn update(
+    info: ClientInfo,
     Path(id): Path<String>,
     TokenData(claims): TokenData<SessionClaims>,
     SizedJson(body): SizedJson<UpdateRequest>,
@@ -163,7 +234,7 @@ pub async fn update(
     let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
 
     let mut doc = Document::new();
-    if claims.scope.contains(&session::Scope::ClientWrite) {
+    if claims.is_global(Resource::Client, Access::Write) {
         if let Some(v) = body.user {
             let id = ObjectId::parse_str(&v).map_err(|_| UserError::InvalidId)?;
             doc.insert("user", id);
@@ -171,6 +242,9 @@ pub async fn update(
         if let Some(v) = body.unlocked {
             doc.insert("unlocked", v);
         }
+        if let Some(v) = body.schedule {
+            doc.insert("schedule", to_bson(&v).unwrap());
+        }
     }
     if let Some(v) = body.name {
         doc.insert("name", v);
@@ -178,11 +252,37 @@ pub async fn update(
     if let Some(v) = body.scope {
         doc.insert("scope", v);
     }
+    if let Some(v) = body.legacy_token_disabled {
+        doc.insert("legacyTokenDisabled", v);
+    }
+    if let Some(v) = body.labels {
+        label::validate(&v)?;
+        doc.insert("labels", to_document(&v).unwrap());
+    }
+    validate_details(
+        body.description.as_deref(),
+        body.contact.as_deref(),
+        body.documentation_url.as_ref(),
+    )?;
+    if let Some(v) = body.description {
+        doc.insert("description", v);
+    }
+    if let Some(v) = body.contact {
+        doc.insert("contact", v);
+    }
+    if let Some(v) = body.documentation_url {
+        doc.insert("documentationUrl", v.as_str());
+    }
+    if let Some(v) = body.redirect_uris {
+        validate_redirect_uris(&v)?;
+        let uris: Vec<&str> = v.iter().map(Url::as_str).collect();
+        doc.insert("redirectUris", uris);
+    }
     if doc.is_empty() {
         return Err(QueryError::InvalidBody.into());
     }
 
-    let user = if !claims.scope.contains(&session::Scope::ClientWrite) {
+    let user = if !claims.is_global(Resource::Client, Access::Write) {
         Some(ObjectId::parse_str(&claims.sub).unwrap())
     } else {
         None
@@ -190,21 +290,129 @@ pub async fn update(
 
     let doc = db.update_client(id, user, doc).await?;
 
+    let event = SecurityEvent::ClientUpdated {
+        client: doc.id.to_hex(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(doc.user, event, &info))
+        .await?;
+
     Ok(Response::with_status(StatusCode::OK, doc.into()))
 }
 
 pub async fn delete(
+    info: ClientInfo,
     Path(id): Path<String>,
     TokenData(claims): TokenData<SessionClaims>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Status> {
-    if !claims.scope.contains(&session::Scope::ClientWrite) {
+    if !claims.is_global(Resource::Client, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
     let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
 
+    let client = db.get_client(doc! { "_id": id }).await?;
     db.delete_client(id).await?;
 
+    let event = SecurityEvent::ClientDeleted {
+        client: id.to_hex(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, &info))
+        .await?;
+
     Ok(Status::new(StatusCode::OK, "client deleted"))
 }
+
+pub async fn history(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(opts): Query<ListOptions>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<RevisionResponse>>> {
+    let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
+
+    let mut filter = doc! { "_id": id };
+    if !claims.is_global(Resource::Client, Access::Read) {
+        let id = ObjectId::parse_str(&claims.sub).unwrap();
+        filter.insert("user", id);
+    }
+
+    // Fails if the client does not exist or is not of the user
+    db.get_client(filter).await?;
+
+    let (revisions, total) = db.get_revisions(Resource::Client, id, opts).await?;
+
+    Ok(Response::new(List::new(total, revisions)))
+}
+
+pub async fn revert(
+    Path((id, revision)): Path<(String, String)>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<ClientResponse>> {
+    if !claims.is_global(Resource::Client, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
+
+    let update = db
+        .revert_update(Resource::Client, id, &revision, db.clock().now())
+        .await?;
+    let client = db.modify_client(doc! { "_id": id }, update).await?;
+
+    Ok(Response::new(client.into()))
+}
+
+/// Confirms that the client is still needed, clears the unconfirmed flag
+pub async fn confirm(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<ClientResponse>> {
+    let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
+
+    let user = if !claims.is_global(Resource::Client, Access::Write) {
+        Some(ObjectId::parse_str(&claims.sub).unwrap())
+    } else {
+        None
+    };
+
+    let update = doc! { "confirmedAt": Utc::now(), "unconfirmed": false };
+    let client = db.update_client(id, user, update).await?;
+
+    Ok(Response::new(client.into()))
+}
+
+/// Replaces the secret of the client, the new secret is only returned once
+pub async fn rotate_secret(
+    info: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(hasher): Extension<CredentialHasher>,
+) -> crate::Result<Response<SecretResponse>> {
+    let id = ObjectId::parse_str(&id).map_err(|_| ClientError::InvalidId)?;
+
+    let user = if !claims.is_global(Resource::Client, Access::Write) {
+        Some(ObjectId::parse_str(&claims.sub).unwrap())
+    } else {
+        None
+    };
+
+    let (secret, hash) = client_secret::generate(&hasher)?;
+    let client = db.update_client(id, user, doc! { "secret": hash }).await?;
+
+    let event = SecurityEvent::ClientSecretRotated {
+        client: client.id.to_hex(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, &info))
+        .await?;
+
+    let response = SecretResponse {
+        client: client.id.to_hex(),
+        secret,
+    };
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
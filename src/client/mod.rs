@@ -1,24 +1,41 @@
+mod attestation;
+mod canary;
 mod handler;
 mod routes;
+mod usage;
 
 use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
     database::Database,
     error,
+    extract::ClientInfo,
+    label::Labels,
     model::{ListOptions, Status},
+    models::AccessSchedule,
     service::ServiceError,
+    session::Resource,
     Result,
 };
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use futures::stream::TryStreamExt;
 use hyper::StatusCode;
 use mongodb::{
-    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document},
+    bson::{
+        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime,
+        DateTime as BsonDateTime, Document,
+    },
     options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    IndexModel,
 };
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
+pub use attestation::spawn_attestation;
+pub use canary::trip as trip_canary;
 pub use routes::routes;
+pub use usage::{ClientUsage, UnusedScopeResponse, UsageReportResponse};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ClientError {
@@ -28,6 +45,12 @@ pub enum ClientError {
     InvalidId,
     #[error("client is locked")]
     Locked,
+    #[error("{0} is invalid")]
+    InvalidDetail(&'static str),
+    #[error("client is not allowed at this time")]
+    OutsideSchedule,
+    #[error("legacy token flow is disabled for this client")]
+    LegacyFlowDisabled,
 }
 
 impl error::ErrorResponse for ClientError {
@@ -37,7 +60,9 @@ impl error::ErrorResponse for ClientError {
         match self {
             ClientError::NotFound => StatusCode::NOT_FOUND,
             ClientError::InvalidId => StatusCode::BAD_REQUEST,
-            ClientError::Locked => StatusCode::FORBIDDEN,
+            ClientError::Locked | ClientError::OutsideSchedule => StatusCode::FORBIDDEN,
+            ClientError::InvalidDetail(_) => StatusCode::BAD_REQUEST,
+            ClientError::LegacyFlowDisabled => StatusCode::GONE,
         }
     }
 
@@ -56,15 +81,89 @@ pub struct ClientDocument {
     pub name: String,
     pub scope: Vec<String>,
     pub unlocked: bool,
+    #[serde(default)]
+    pub labels: Labels,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Address of the owner for questions about the client, the user's address if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation_url: Option<Url>,
+    /// Redirect URIs of the authorization code grant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirect_uris: Vec<Url>,
+    /// Window in which tokens are issued for the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<AccessSchedule>,
+    /// Last time the owner confirmed that the client is still needed
+    #[serde(
+        default = "default_confirmed_at",
+        with = "chrono_datetime_as_bson_datetime"
+    )]
+    pub confirmed_at: DateTime<Utc>,
+    /// Set if the owner was asked to confirm the client and has not done so yet
+    #[serde(default)]
+    pub unconfirmed: bool,
+    /// Opt-in of the owner to reject tokens of the legacy `/v1/token` flow
+    #[serde(default)]
+    pub legacy_token_disabled: bool,
+    /// Planted credentials, any use raises an alert
+    #[serde(default)]
+    pub canary: bool,
+    /// Hash of the secret of the client credentials grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub last_issued: DateTime<Utc>,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub last_modified: DateTime<Utc>,
 }
 
+/// Clients created before ownership confirmations count as never confirmed
+fn default_confirmed_at() -> DateTime<Utc> {
+    Utc.timestamp(0, 0)
+}
+
 const COLLECTION: &str = "clients";
 
 impl Database {
+    /// Finds the clients of a user
+    pub async fn index_client_users(&self) -> Result<()> {
+        let index = IndexModel::builder().keys(doc! { "user": 1 }).build();
+
+        self.collection::<ClientDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Rejects clients whose schedule doesn't allow access at `now`, the rejection is recorded
+    /// as security event of the owner
+    pub async fn check_client_schedule(
+        &self,
+        client: &ClientDocument,
+        info: &ClientInfo,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        match &client.schedule {
+            Some(s) if !s.allows(now) => {}
+            _ => return Ok(()),
+        }
+
+        warn!(client = %client.id, "client access outside of schedule rejected");
+
+        let event = SecurityEvent::OutsideSchedule {
+            role: None,
+            client: Some(client.id.to_hex()),
+        };
+        self.insert_audit_event(&AuditEventDocument::new(client.user, event, info))
+            .await?;
+
+        Err(ClientError::OutsideSchedule.into())
+    }
+
     async fn get_clients<F>(
         &self,
         filter: F,
@@ -115,6 +214,7 @@ impl Database {
 
     async fn insert_client(&self, doc: &ClientDocument) -> Result<()> {
         self.get_user(doc! { "_id": doc.user }).await?;
+        self.check_quota(Resource::Client).await?;
 
         self.collection::<ClientDocument>(COLLECTION)
             .insert_one(doc, None)
@@ -123,6 +223,26 @@ impl Database {
         Ok(())
     }
 
+    /// Inserts the client unless its user has one of the service with the same name, returns
+    /// the stored one. Used for internal clients, which don't count towards the quota.
+    #[cfg(feature = "selftest")]
+    pub async fn insert_client_if_new(&self, doc: &ClientDocument) -> Result<ClientDocument> {
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let client = self
+            .collection::<ClientDocument>(COLLECTION)
+            .find_one_and_update(
+                doc! { "user": doc.user, "service": doc.service, "name": &doc.name },
+                doc! { "$setOnInsert": mongodb::bson::to_document(doc).unwrap() },
+                opts,
+            )
+            .await?;
+
+        client.ok_or_else(|| ClientError::NotFound.into())
+    }
+
     async fn update_client(
         &self,
         id: ObjectId,
@@ -145,25 +265,32 @@ impl Database {
             filter.insert("user", id);
         }
 
-        let doc = doc! {
-            "$currentDate": { "lastModified": true },
-            "$set": update,
-        };
+        self.modify_client(filter, doc! { "$set": update }).await
+    }
+
+    /// Applies update operators to the client matching the filter and records the revision
+    async fn modify_client(&self, filter: Document, mut doc: Document) -> Result<ClientDocument> {
+        doc.insert("$currentDate", doc! { "lastModified": true });
 
         let opts = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
+            .return_document(ReturnDocument::Before)
             .build();
 
-        let result = self
+        let before = match self
             .collection::<ClientDocument>(COLLECTION)
             .find_one_and_update(filter, doc, opts)
-            .await?;
+            .await?
+        {
+            Some(v) => v,
+            None => return Err(ClientError::NotFound.into()),
+        };
 
-        if result.is_none() {
-            return Err(ClientError::NotFound.into());
-        }
+        let after = self.get_client(doc! { "_id": before.id }).await?;
 
-        Ok(result.unwrap())
+        self.record_revision(Resource::Client, before.id, &before, &after)
+            .await?;
+
+        Ok(after)
     }
 
     async fn delete_client(&self, id: ObjectId) -> Result<()> {
@@ -179,7 +306,25 @@ impl Database {
         Ok(())
     }
 
+    /// Replaces an outdated hash of the secret, bypassing the revision history
+    pub async fn rehash_client_secret(&self, id: ObjectId, hash: String) -> Result<()> {
+        self.collection::<ClientDocument>(COLLECTION)
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "secret": hash } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn set_client_issued(&self, id: ObjectId) -> Result<()> {
+        if let Some(timestamps) = self.timestamps() {
+            timestamps.client_issued(id, Utc::now());
+            return Ok(());
+        }
+
         let doc = doc! {
             "$currentDate": { "lastIssued": true },
         };
@@ -195,4 +340,17 @@ impl Database {
 
         Ok(())
     }
+
+    /// Moves the last issuance forward to the date, written behind of the issuance
+    pub async fn advance_client_issued(&self, id: ObjectId, date: DateTime<Utc>) -> Result<()> {
+        self.collection::<ClientDocument>(COLLECTION)
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$max": { "lastIssued": BsonDateTime::from_chrono(date) } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
 }
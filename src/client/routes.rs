@@ -1,6 +1,6 @@
 use super::handler;
 
-use axum::routing::get;
+use axum::routing::{get, post};
 
 /// User routes
 pub fn routes() -> axum::Router {
@@ -12,4 +12,8 @@ pub fn routes() -> axum::Router {
                 .patch(handler::update)
                 .delete(handler::delete),
         )
+        .route("/:id/confirm", post(handler::confirm))
+        .route("/:id/secret", post(handler::rotate_secret))
+        .route("/:id/history", get(handler::history))
+        .route("/:id/revert/:revision", post(handler::revert))
 }
@@ -0,0 +1,197 @@
+//! Scopes that clients actually exercise, as reported by the services that accept their tokens.
+//!
+//! Clients holding privileged scopes of a service they haven't used for a while are listed in
+//! a report for least-privilege cleanup. Reported usage of scopes a client doesn't hold is
+//! anomalous and logged.
+
+use crate::{database::Database, service::ServiceDocument, Result};
+
+use super::{ClientDocument, COLLECTION as CLIENTS};
+
+use std::collections::HashMap;
+
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::UpdateOptions,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const COLLECTION: &str = "scope_usage";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeUsageDocument {
+    client: ObjectId,
+    scope: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    last_used: DateTime<Utc>,
+}
+
+/// Scopes a client exercised since the last report
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientUsage {
+    pub client: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReportResponse {
+    pub recorded: usize,
+    /// Scopes reported for clients that don't hold them or belong to another service
+    pub anomalies: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnusedScopeResponse {
+    pub client: String,
+    pub user: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    pub last_issued: DateTime<Utc>,
+}
+
+impl Database {
+    /// Records the reported usage of the clients of the service
+    pub async fn record_scope_usage(
+        &self,
+        service: &ServiceDocument,
+        usage: Vec<ClientUsage>,
+        now: DateTime<Utc>,
+    ) -> Result<UsageReportResponse> {
+        let mut response = UsageReportResponse::default();
+        let opts = UpdateOptions::builder().upsert(true).build();
+
+        for entry in usage {
+            let client = match ObjectId::parse_str(&entry.client) {
+                Ok(id) => {
+                    self.collection::<ClientDocument>(CLIENTS)
+                        .find_one(doc! { "_id": id, "service": service.id }, None)
+                        .await?
+                }
+                Err(_) => None,
+            };
+            let client = match client {
+                Some(c) => c,
+                None => {
+                    warn!(service = %service.id, client = %entry.client, "usage reported for unknown client");
+                    response.anomalies += entry.scopes.len();
+                    continue;
+                }
+            };
+
+            for scope in entry.scopes {
+                if !client.scope.contains(&scope) {
+                    warn!(client = %client.id, scope = %scope, "anomalous usage of a scope the client doesn't hold");
+                    response.anomalies += 1;
+                    continue;
+                }
+
+                self.collection::<ScopeUsageDocument>(COLLECTION)
+                    .update_one(
+                        doc! { "client": client.id, "scope": &scope },
+                        doc! { "$max": { "lastUsed": now } },
+                        opts.clone(),
+                    )
+                    .await?;
+                response.recorded += 1;
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Lists the clients of the service with privileged scopes unused since `days`
+    pub async fn get_unused_scopes(
+        &self,
+        service: &ServiceDocument,
+        days: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<UnusedScopeResponse>> {
+        if service.scope_privileged.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let clients: Vec<ClientDocument> = self
+            .collection::<ClientDocument>(CLIENTS)
+            .find(
+                doc! {
+                    "service": service.id,
+                    "scope": { "$in": &service.scope_privileged },
+                },
+                None,
+            )
+            .await?
+            .try_collect()
+            .await?;
+
+        let since = now - Duration::days(days);
+        let mut report = Vec::new();
+
+        for client in clients {
+            let used: HashMap<String, DateTime<Utc>> = self
+                .collection::<ScopeUsageDocument>(COLLECTION)
+                .find(doc! { "client": client.id }, None)
+                .await?
+                .map_ok(|u| (u.scope, u.last_used))
+                .try_collect()
+                .await?;
+
+            let scopes = unused_scopes(&client.scope, &service.scope_privileged, &used, since);
+            if scopes.is_empty() {
+                continue;
+            }
+
+            report.push(UnusedScopeResponse {
+                client: client.id.to_hex(),
+                user: client.user.to_hex(),
+                name: client.name,
+                scopes,
+                last_issued: client.last_issued,
+            });
+        }
+
+        Ok(report)
+    }
+}
+
+/// Privileged scopes of the client without usage since `since`
+fn unused_scopes(
+    held: &[String],
+    privileged: &[String],
+    used: &HashMap<String, DateTime<Utc>>,
+    since: DateTime<Utc>,
+) -> Vec<String> {
+    held.iter()
+        .filter(|s| privileged.contains(s))
+        .filter(|s| !matches!(used.get(*s), Some(t) if *t >= since))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_stale_privileged_scopes() {
+        let now = Utc::now();
+        let held = vec!["read".to_string(), "write".to_string(), "admin".to_string()];
+        let privileged = vec!["write".to_string(), "admin".to_string()];
+
+        let mut used = HashMap::new();
+        used.insert("write".to_string(), now - Duration::days(1));
+        used.insert("admin".to_string(), now - Duration::days(40));
+
+        assert_eq!(
+            unused_scopes(&held, &privileged, &used, now - Duration::days(30)),
+            vec!["admin".to_string()]
+        );
+    }
+}
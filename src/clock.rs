@@ -0,0 +1,82 @@
+use std::{fmt::Debug, sync::Arc};
+
+use chrono::{DateTime, Utc};
+
+/// Source of the current time, so that time dependent behavior can be controlled in tests
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Clock of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use mock::MockClock;
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+mod mock {
+    use super::Clock;
+
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{DateTime, Duration, Utc};
+
+    /// Clock that only moves when told to
+    #[derive(Debug, Clone)]
+    pub struct MockClock {
+        now: Arc<Mutex<DateTime<Utc>>>,
+    }
+
+    impl MockClock {
+        pub fn new(now: DateTime<Utc>) -> Self {
+            Self {
+                now: Arc::new(Mutex::new(now)),
+            }
+        }
+
+        pub fn set(&self, now: DateTime<Utc>) {
+            *self.now.lock().unwrap() = now;
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = *now + duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    #[test]
+    fn mock_clock_advances() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let shared: SharedClock = Arc::new(clock.clone());
+
+        clock.advance(Duration::minutes(5));
+
+        assert_eq!(shared.now(), start + Duration::minutes(5));
+    }
+}
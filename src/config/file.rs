@@ -0,0 +1,226 @@
+//! Configuration file in TOML, given with `--config <path>` or in `CONFIG_FILE`.
+//!
+//! Keys are the names of the variables in lower case without prefix, e.g. `jwt_secret` for
+//! `IDENTITY_JWT_SECRET`, and become variables themselves: arrays of values are joined to
+//! comma-separated lists, tables and arrays of tables are passed as JSON. Variables of the
+//! environment and the `.env` file take precedence over the file.
+
+use super::{env_name, schema, secret::FILE_SUFFIX};
+
+use std::{fs, path::PathBuf};
+
+use toml::Value;
+
+/// Variable with the path of the file, if it isn't given as argument
+pub const FILE_VAR: &str = "CONFIG_FILE";
+
+const ARG: &str = "--config";
+
+type Vars = Vec<(String, String)>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileError {
+    #[error("{} requires the path of the config file", ARG)]
+    MissingPath,
+    #[error("config file {0} can not be read: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("config file {0} is invalid: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("config file {0} has unknown key \"{1}\"{2}")]
+    UnknownKey(PathBuf, String, Hint),
+}
+
+/// Suggestion of the key that was meant
+#[derive(Debug)]
+pub struct Hint(Option<String>);
+
+impl std::fmt::Display for Hint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(key) => write!(f, ", did you mean \"{}\"?", key),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Takes the path given with `--config <path>` or `--config=<path>` out of the arguments, so
+/// that commands keep their position
+pub fn take_path(args: &mut Vec<String>) -> Result<Option<PathBuf>, FileError> {
+    let prefix = format!("{}=", ARG);
+
+    let i = match args.iter().position(|a| a == ARG || a.starts_with(&prefix)) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    let arg = args.remove(i);
+    let path = match arg.strip_prefix(&prefix) {
+        Some(path) => path.to_string(),
+        None if i < args.len() => args.remove(i),
+        None => return Err(FileError::MissingPath),
+    };
+
+    Ok(Some(path.into()))
+}
+
+/// Adds the variables of the file that the given ones don't set, a secret counts as set if
+/// either its value or its `*_FILE` variable is
+pub fn merge(path: PathBuf, mut vars: Vars) -> Result<Vars, FileError> {
+    let contents = fs::read_to_string(&path).map_err(|e| FileError::Read(path.clone(), e))?;
+
+    let table = match contents.parse::<Value>() {
+        Ok(Value::Table(table)) => table,
+        Ok(_) => unreachable!("documents are tables"),
+        Err(e) => return Err(FileError::Parse(path, e)),
+    };
+
+    let schema = schema();
+    let known = schema["properties"].as_object().unwrap();
+
+    let base = |name: &str| name.trim_end_matches(FILE_SUFFIX).to_string();
+    let set: Vec<_> = vars.iter().map(|(k, _)| base(k)).collect();
+
+    for (key, value) in table {
+        let name = env_name(&key);
+        if !known.contains_key(&name) {
+            let hint = known
+                .keys()
+                .filter_map(|k| k.strip_prefix(super::ENV_PREFIX))
+                .map(str::to_lowercase)
+                .min_by_key(|k| distance(k, &key))
+                .filter(|k| distance(k, &key) <= 2);
+            return Err(FileError::UnknownKey(path, key, Hint(hint)));
+        }
+        if set.contains(&base(&name)) {
+            continue;
+        }
+
+        match value {
+            Value::Array(list) if list.is_empty() => {}
+            value => vars.push((name, to_var(value))),
+        }
+    }
+
+    Ok(vars)
+}
+
+fn to_var(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Array(list) if list.iter().all(is_scalar) => {
+            list.into_iter().map(to_var).collect::<Vec<_>>().join(",")
+        }
+        Value::Array(_) | Value::Table(_) => serde_json::to_string(&value).unwrap(),
+        v => v.to_string(),
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !matches!(value, Value::Array(_) | Value::Table(_))
+}
+
+/// Edit distance of the keys
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("identity-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn takes_path_argument() {
+        let mut args = vec!["identity-server".into(), "--config".into(), "a.toml".into()];
+        assert_eq!(take_path(&mut args).unwrap(), Some("a.toml".into()));
+        assert_eq!(args, ["identity-server"]);
+
+        let mut args = vec![
+            "identity-server".into(),
+            "--config=b.toml".into(),
+            "fsck".into(),
+        ];
+        assert_eq!(take_path(&mut args).unwrap(), Some("b.toml".into()));
+        assert_eq!(args, ["identity-server", "fsck"]);
+
+        let mut args = vec!["identity-server".into(), "--config".into()];
+        assert!(matches!(take_path(&mut args), Err(FileError::MissingPath)));
+    }
+
+    #[test]
+    fn merges_file_below_environment() {
+        let path = config_file(
+            "merge",
+            r#"
+            mongo_db = "identity"
+            server_port = 9000
+            allowed_domains = ["example.com", "example.org"]
+            jwt_secret = "from file"
+            realms = []
+            session_limits = { admin = 3 }
+            "#,
+        );
+        let vars = vec![
+            var("IDENTITY_SERVER_PORT", "8080"),
+            var("IDENTITY_JWT_SECRET_FILE", "/run/secrets/jwt"),
+        ];
+
+        let mut vars = merge(path.clone(), vars).unwrap();
+        vars.sort();
+
+        assert_eq!(
+            vars,
+            [
+                var("IDENTITY_ALLOWED_DOMAINS", "example.com,example.org"),
+                var("IDENTITY_JWT_SECRET_FILE", "/run/secrets/jwt"),
+                var("IDENTITY_MONGO_DB", "identity"),
+                var("IDENTITY_SERVER_PORT", "8080"),
+                var("IDENTITY_SESSION_LIMITS", r#"{"admin":3}"#),
+            ]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let path = config_file("unknown", "mongo_bd = \"identity\"\n");
+
+        let err = merge(path.clone(), Vec::new()).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("unknown key \"mongo_bd\", did you mean \"mongo_db\"?"),
+            "{}",
+            err
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
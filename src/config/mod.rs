@@ -0,0 +1,559 @@
+#[cfg(feature = "federation")]
+use crate::federation;
+#[cfg(any(feature = "mail-mailgun", feature = "mail-smtp"))]
+use crate::mail;
+#[cfg(feature = "sso-oidc")]
+use crate::sso;
+use crate::{
+    authentication::{
+        password::PasswordPolicy,
+        signature::{self, SigningKeys},
+    },
+    database, keys, session, token,
+    user::Role,
+    utils::crypto,
+};
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use jsonwebtoken::Algorithm;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+
+mod file;
+mod reload;
+mod schema;
+mod secret;
+
+pub use file::{merge as merge_file, take_path as take_file_path, FILE_VAR};
+pub use reload::{Live, Reloadable, Reloader};
+pub use schema::schema;
+pub use secret::{resolve_files, watch as watch_secret_files, SecretFile};
+
+/// Prefix of all configuration variables
+pub const ENV_PREFIX: &str = "IDENTITY_";
+
+/// Name of the environment variable of a config field
+fn env_name(field: &str) -> String {
+    format!("{}{}", ENV_PREFIX, field.to_uppercase())
+}
+
+/// Loads the configuration from the variables, errors name the variable and the file key
+pub fn load(vars: Vec<(String, String)>) -> crate::Result<AppConfig> {
+    envy::prefixed(ENV_PREFIX)
+        .from_iter(vars)
+        .map_err(|e| match e {
+            envy::Error::MissingValue(field) => crate::error::Error::Config(format!(
+                "{} is required, set it in the environment or as \"{}\" in the config file",
+                env_name(field),
+                field
+            )),
+            envy::Error::Custom(msg) => crate::error::Error::Config(msg),
+        })
+}
+
+const fn default_addr() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+const fn default_port() -> u16 {
+    8080
+}
+
+const fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+const fn default_hibp_check() -> bool {
+    true
+}
+
+const fn default_password_min_length() -> usize {
+    16
+}
+
+const fn default_password_require_classes() -> bool {
+    true
+}
+
+const fn default_password_min_score() -> f64 {
+    85.0
+}
+
+const fn default_mail_attempts() -> u32 {
+    3
+}
+
+const fn default_login_max_failures() -> u32 {
+    5
+}
+
+const fn default_login_lockout_minutes() -> u32 {
+    15
+}
+
+const fn default_slow_request_sample_rate() -> f64 {
+    0.1
+}
+
+const fn default_otlp_sample_ratio() -> f64 {
+    1.0
+}
+
+const fn default_crypto_key_version() -> u8 {
+    1
+}
+
+const fn default_secret_file_interval() -> u64 {
+    30
+}
+
+#[cfg(feature = "wasm-policies")]
+const fn default_policy_reload_secs() -> u64 {
+    30
+}
+
+const fn default_rate_limit_window_secs() -> u64 {
+    60
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    ["authorization", "content-type"].map(String::from).to_vec()
+}
+
+const fn default_cors_max_age_secs() -> u64 {
+    600
+}
+
+#[cfg(feature = "cache")]
+const fn default_cache_pool_size() -> usize {
+    4
+}
+
+const fn default_jwt_signing_algorithm() -> Algorithm {
+    Algorithm::ES256
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppConfig {
+    // HTTP server
+    #[serde(default = "default_addr")]
+    pub server_addr: IpAddr,
+    #[serde(default = "default_port")]
+    pub server_port: u16,
+    /// Prefix of all routes, e.g. `/auth`, empty if they are served at the root
+    #[serde(default, deserialize_with = "de_base_path")]
+    pub base_path: String,
+    /// Seconds in-flight requests may take to complete after a shutdown signal
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+
+    /// Requests taking longer are traced with their upstream and database timings
+    pub slow_request_threshold_ms: Option<u64>,
+    #[serde(default = "default_slow_request_sample_rate")]
+    pub slow_request_sample_rate: f64,
+    /// Bearer token of scrapers of `/metrics`, public if not set
+    #[cfg(feature = "metrics")]
+    pub metrics_token: Option<String>,
+    /// Serves a Swagger UI of `/v1/openapi.json` at `/v1/docs`
+    #[serde(default)]
+    pub openapi_docs: bool,
+    /// Serves the portal for integrators at `/docs`, which lists the scopes of all services
+    #[serde(default)]
+    pub docs_portal: bool,
+    /// OTLP/HTTP traces endpoint of an OpenTelemetry collector, spans aren't exported if not set
+    pub otlp_endpoint: Option<Url>,
+    /// Share of new traces that are exported, traces of callers keep their decision
+    #[serde(default = "default_otlp_sample_ratio")]
+    pub otlp_sample_ratio: f64,
+
+    /// Rejects all mutating requests, e.g. for a replica reading from a secondary
+    #[serde(default)]
+    pub read_only: bool,
+    /// Primary instance that read-only replicas refer to
+    pub primary_url: Option<Url>,
+    /// Deployment for integrators: a fake SSO provider signs in any address, mails are captured
+    /// instead of delivered and tokens carry a `sandbox` claim
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Public URL of this instance, the issuer of OpenID Connect
+    pub issuer_url: Option<Url>,
+    /// Page where users enter the code of the device flow, the flow is disabled if not set
+    pub device_verification_url: Option<Url>,
+    /// Page where users fill in profile fields required by services, authorizations of clients
+    /// of such services are rejected for incomplete profiles if not set
+    pub profile_url: Option<Url>,
+
+    // Rate limits
+    /// Requests per window to logins and token endpoints by address and by account, unlimited if
+    /// not set
+    pub rate_limit_requests: Option<u32>,
+    #[serde(default = "default_rate_limit_window_secs")]
+    pub rate_limit_window_secs: u64,
+    /// Requests that may be sent at once, the requests per window if not set
+    pub rate_limit_burst: Option<u32>,
+
+    // CORS
+    /// Origins of frontends allowed to call the API from browsers, `*` for any, disabled if empty
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_allowed_methods")]
+    pub cors_allowed_methods: Vec<String>,
+    #[serde(default = "default_cors_allowed_headers")]
+    pub cors_allowed_headers: Vec<String>,
+    /// Lets the origins send cookies and read responses to them, not allowed with `*`
+    #[serde(default)]
+    pub cors_allow_credentials: bool,
+    /// Seconds browsers may cache the answer to a preflight request
+    #[serde(default = "default_cors_max_age_secs")]
+    pub cors_max_age_secs: u64,
+
+    // Cache
+    /// Redis shared by the replicas, e.g. for rate limits and revoked tokens
+    #[cfg(feature = "cache")]
+    pub cache_url: Option<String>,
+    /// Connections to the cache, each of them multiplexed
+    #[cfg(feature = "cache")]
+    #[serde(default = "default_cache_pool_size")]
+    pub cache_pool_size: usize,
+
+    // MongoDB client
+    pub mongo_uri: String,
+    pub mongo_db: String,
+    #[serde(default)]
+    pub mongo_tls: bool,
+    pub mongo_cert_key: Option<PathBuf>,
+    pub mongo_ca: Option<PathBuf>,
+    /// Realms with their own database, selected by the host of the request
+    #[serde(default, deserialize_with = "database::de_realms")]
+    pub realms: Vec<database::RealmConfig>,
+    /// Caps on users, clients and services, per realm unless a realm has its own
+    #[serde(default, deserialize_with = "database::de_quotas")]
+    pub quotas: database::Quotas,
+    /// Last issuance of clients and last sessions of users are written behind in batches this
+    /// often instead of with every request
+    pub timestamp_flush_ms: Option<u64>,
+
+    // Email client, SMTP is used if a host is set, Mailgun otherwise
+    #[cfg(any(feature = "mail-mailgun", feature = "mail-smtp"))]
+    pub mail_from: String,
+    /// Deliveries failing temporarily are retried, the delay doubles from one second
+    #[serde(default = "default_mail_attempts")]
+    pub mail_attempts: u32,
+    #[cfg(feature = "mail-mailgun")]
+    pub mg_region: Option<mail::Region>,
+    #[cfg(feature = "mail-mailgun")]
+    pub mg_domain: Option<String>,
+    #[cfg(feature = "mail-mailgun")]
+    pub mg_key: Option<String>,
+    #[cfg(feature = "mail-mailgun")]
+    pub mg_base_url: Option<Url>,
+    #[cfg(feature = "mail-smtp")]
+    pub smtp_host: Option<String>,
+    #[cfg(feature = "mail-smtp")]
+    pub smtp_port: Option<u16>,
+    #[cfg(feature = "mail-smtp")]
+    #[serde(default)]
+    pub smtp_tls: mail::SmtpTls,
+    #[cfg(feature = "mail-smtp")]
+    pub smtp_username: Option<String>,
+    #[cfg(feature = "mail-smtp")]
+    pub smtp_password: Option<String>,
+    /// Directory of the templates rendered for SMTP
+    #[cfg(feature = "mail-smtp")]
+    pub mail_template_dir: Option<PathBuf>,
+
+    // GitHub OAuth
+    #[cfg(feature = "sso-github")]
+    pub gh_client_id: String,
+    #[cfg(feature = "sso-github")]
+    pub gh_client_secret: String,
+    #[cfg(feature = "sso-github")]
+    pub gh_redirect_uri: Url,
+
+    // Federation
+    #[cfg(feature = "federation")]
+    #[serde(default, deserialize_with = "federation::de_issuers")]
+    pub federation_issuers: Vec<federation::IssuerConfig>,
+
+    // WASM policies
+    /// Directory of the `*.wasm` policies, evaluated on login, issuance and consent
+    #[cfg(feature = "wasm-policies")]
+    pub policy_dir: Option<PathBuf>,
+    /// Seconds between checks of the policy directory for changes
+    #[cfg(feature = "wasm-policies")]
+    #[serde(default = "default_policy_reload_secs")]
+    pub policy_reload_secs: u64,
+
+    // CI token exchange
+    #[serde(default, deserialize_with = "token::de_ci_policies")]
+    pub ci_policies: Vec<token::CiPolicy>,
+
+    // Claims of service tokens derived by expressions
+    #[serde(default, deserialize_with = "token::de_claim_rules")]
+    pub claim_rules: token::ClaimRules,
+
+    // Session limits by role
+    #[serde(default, deserialize_with = "session::de_session_limits")]
+    pub session_limits: session::SessionLimits,
+
+    // Sign in with Apple
+    #[cfg(feature = "sso-apple")]
+    pub apple_client_id: Option<String>,
+    #[cfg(feature = "sso-apple")]
+    pub apple_team_id: Option<String>,
+    #[cfg(feature = "sso-apple")]
+    pub apple_key_id: Option<String>,
+    #[cfg(feature = "sso-apple")]
+    pub apple_key_path: Option<PathBuf>,
+    #[cfg(feature = "sso-apple")]
+    pub apple_redirect_uri: Option<Url>,
+
+    // Twitch OAuth
+    #[cfg(feature = "sso-twitch")]
+    pub twitch_client_id: Option<String>,
+    #[cfg(feature = "sso-twitch")]
+    pub twitch_client_secret: Option<String>,
+    #[cfg(feature = "sso-twitch")]
+    pub twitch_redirect_uri: Option<Url>,
+
+    // Steam OpenID
+    #[cfg(feature = "sso-steam")]
+    pub steam_redirect_uri: Option<Url>,
+
+    // Discord OAuth
+    #[cfg(feature = "sso-discord")]
+    pub discord_client_id: Option<String>,
+    #[cfg(feature = "sso-discord")]
+    pub discord_client_secret: Option<String>,
+    #[cfg(feature = "sso-discord")]
+    pub discord_redirect_uri: Option<Url>,
+
+    // OpenID Connect providers
+    #[cfg(feature = "sso-oidc")]
+    #[serde(default, deserialize_with = "sso::de_oidc_providers")]
+    pub oidc_providers: Vec<sso::OidcProviderConfig>,
+
+    // JWT
+    pub jwt_secret: String,
+    pub jwt_audience: Vec<String>,
+    /// PEM encoded key to sign tokens with instead of the secret, published as JWKS
+    pub jwt_signing_key: Option<String>,
+    /// Algorithm of the signing key
+    #[serde(default = "default_jwt_signing_algorithm")]
+    pub jwt_signing_algorithm: Algorithm,
+    /// Days after which the signing key is replaced, keys are generated and shared via the database
+    pub jwt_key_rotation_days: Option<u32>,
+
+    /// Keys of users that may authenticate with signed requests instead of a token
+    #[serde(default, deserialize_with = "signature::de_keys")]
+    pub signing_keys: SigningKeys,
+
+    // Regions
+    /// Region of this instance, embedded in issued tokens and session records
+    pub region: Option<String>,
+    /// Regions whose tokens are accepted, with their JWT secret if it differs
+    #[serde(default, deserialize_with = "de_regions")]
+    pub region_siblings: Vec<(String, Option<String>)>,
+    /// Regions whose tokens are accepted, verified with their published key set
+    #[serde(default, deserialize_with = "de_region_keys")]
+    pub region_sibling_keys: Vec<(String, Url)>,
+
+    // Crypto
+    pub crypto_key: String,
+    #[serde(default = "default_crypto_key_version")]
+    pub crypto_key_version: u8,
+    /// Previous keys, only used for decryption until all values are re-encrypted
+    #[serde(default, deserialize_with = "crypto::de_keys")]
+    pub crypto_previous_keys: Vec<(u8, String)>,
+
+    // Credential hashing
+    pub pepper: Option<String>,
+    #[serde(default = "default_crypto_key_version")]
+    pub pepper_version: u8,
+    /// Previous peppers, only used to verify hashes until they are rehashed
+    #[serde(default, deserialize_with = "crypto::de_keys")]
+    pub previous_peppers: Vec<(u8, String)>,
+
+    /// Expiry dates of keys the server can't renew, admins are alerted before they pass
+    #[serde(default, deserialize_with = "keys::de_expiries")]
+    pub key_expiry: Vec<keys::KeyExpiry>,
+    /// Receives key expiry alerts in addition to the editor addresses
+    pub key_alert_webhook: Option<Url>,
+
+    /// Seconds between checks of secret files for rotation
+    #[serde(default = "default_secret_file_interval")]
+    pub secret_file_interval: u64,
+
+    /// Months without use or confirmation after which client owners have to confirm them
+    pub client_attestation_months: Option<u32>,
+
+    /// Hours between refreshes of the connection data with the stored provider tokens, disabled
+    /// if not set
+    #[cfg(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord"))]
+    pub connection_refresh_hours: Option<u64>,
+
+    /// Hours between checks for orphaned documents, disabled if not set
+    pub integrity_check_hours: Option<u64>,
+    /// Removes the orphans found by the checks
+    #[serde(default)]
+    pub integrity_fix: bool,
+
+    // Global vars
+    pub editor_mail_address: Vec<String>,
+    /// Notified about temporary role grants, the editor addresses if not set
+    #[serde(default)]
+    pub security_contacts: Vec<String>,
+    /// Grants of these roles wait for the approval of a second admin
+    #[serde(default)]
+    pub sensitive_roles: Vec<Role>,
+    /// Locks the clients and sessions of the owner of a canary client once it is used
+    #[serde(default)]
+    pub canary_auto_lock: bool,
+    /// Logins accepted while an SSO provider is unavailable
+    #[serde(default)]
+    pub sso_fallback: Vec<session::FallbackMethod>,
+    /// Accounts that may log in with their password during an outage
+    #[serde(default)]
+    pub break_glass_accounts: Vec<String>,
+    pub allowed_domains: Vec<String>,
+    #[serde(default = "default_hibp_check")]
+    pub hibp_check: bool,
+
+    // Password policy
+    #[serde(default = "default_password_min_length")]
+    pub password_min_length: usize,
+    /// Requires uppercase and lowercase characters and digits
+    #[serde(default = "default_password_require_classes")]
+    pub password_require_classes: bool,
+    #[serde(default = "default_password_min_score")]
+    pub password_min_score: f64,
+    /// Failed logins after which password logins are locked, never locked if zero
+    #[serde(default = "default_login_max_failures")]
+    pub login_max_failures: u32,
+    #[serde(default = "default_login_lockout_minutes")]
+    pub login_lockout_minutes: u32,
+}
+
+/// Parses comma-separated regions, each optionally followed by `:secret`
+fn de_regions<'de, D>(d: D) -> Result<Vec<(String, Option<String>)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    Ok(input
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| match v.split_once(':') {
+            Some((region, secret)) => (region.to_string(), Some(secret.to_string())),
+            None => (v.to_string(), None),
+        })
+        .collect())
+}
+
+/// Parses comma-separated `region:uri` entries
+fn de_region_keys<'de, D>(d: D) -> Result<Vec<(String, Url)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    input
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let (region, uri) = v
+                .split_once(':')
+                .ok_or_else(|| serde::de::Error::custom(format!("{} has no key set URI", v)))?;
+            let uri = Url::parse(uri).map_err(serde::de::Error::custom)?;
+
+            Ok((region.to_string(), uri))
+        })
+        .collect()
+}
+
+fn de_base_path<'de, D>(d: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+    let path = input.trim_end_matches('/');
+
+    if !path.is_empty() && !path.starts_with('/') || path.contains(['?', '#', '{', '}', ':', '*']) {
+        return Err(serde::de::Error::custom(format!(
+            "base path {} has to be an absolute path without parameters",
+            input
+        )));
+    }
+
+    Ok(path.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub struct GlobalConfig {
+    pub hibp_check_enabled: bool,
+    pub password_policy: PasswordPolicy,
+    /// Reloaded on `SIGHUP`, see [`Reloader`]
+    pub allowed_domains: Live<Vec<String>>,
+    pub editor_mail_addrs: Vec<String>,
+    pub sensitive_roles: Vec<Role>,
+    pub canary_auto_lock: bool,
+    pub issuer_url: Option<Url>,
+    pub device_verification_url: Option<Url>,
+    pub profile_url: Option<Url>,
+    /// Prefix of all routes, for paths sent to clients
+    pub base_path: String,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Reloaded on `SIGHUP`, see [`Reloader`]
+    pub cors: Live<Option<CorsConfig>>,
+}
+
+/// Rate of requests to logins and token endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub requests: u32,
+    pub window: Duration,
+    pub burst: u32,
+}
+
+/// Cross-origin requests of browsers, see [`crate::cors`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsConfig {
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Duration,
+}
+
+impl GlobalConfig {
+    pub fn is_allowed_domain<D>(&self, domain: D) -> bool
+    where
+        D: AsRef<str>,
+    {
+        let domain = domain.as_ref();
+        self.allowed_domains
+            .get()
+            .iter()
+            .any(|d| d == domain || d == "*")
+    }
+
+    pub fn is_editor_address<A>(&self, addr: A) -> bool
+    where
+        A: AsRef<str>,
+    {
+        let addr = addr.as_ref();
+        self.editor_mail_addrs.iter().any(|d| d == addr)
+    }
+}
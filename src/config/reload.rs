@@ -0,0 +1,163 @@
+//! Settings that are applied again without a restart when the process receives `SIGHUP`: the
+//! allowed email domains, the CORS origins and the credentials of the SSO providers. A rotated
+//! secret file of a provider credential reloads them as well, see [`RELOADED_SECRETS`].
+//!
+//! The configuration is read like at startup from the environment, the config file and the
+//! secret files, the `.env` file isn't read again. A configuration that is invalid is rejected
+//! as a whole and the previous one stays in effect. Providers can't be added or removed, their
+//! routes are only mounted at startup.
+
+use super::{load, merge_file, resolve_files, AppConfig, CorsConfig};
+
+use crate::{cors, error::Error, http::HttpClient, sso::Providers};
+
+use std::{
+    env,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+
+/// Secrets whose files are reloaded in place when they are rotated, the others need a restart
+pub const RELOADED_SECRETS: &[&str] = &[
+    #[cfg(feature = "sso-github")]
+    "gh_client_secret",
+    #[cfg(feature = "sso-twitch")]
+    "twitch_client_secret",
+    #[cfg(feature = "sso-discord")]
+    "discord_client_secret",
+    #[cfg(feature = "sso-oidc")]
+    "oidc_providers",
+];
+
+/// Value that is swapped as a whole, readers keep the one they got until they are done
+#[derive(Debug)]
+pub struct Live<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Clone for Live<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+/// Settings of a configuration that can be reloaded
+pub struct Reloadable {
+    pub allowed_domains: Vec<String>,
+    pub cors: Option<CorsConfig>,
+    pub providers: Providers,
+}
+
+impl Reloadable {
+    pub async fn from_config(config: &AppConfig, client: &HttpClient) -> crate::Result<Self> {
+        let cors = (!config.cors_allowed_origins.is_empty()).then(|| CorsConfig {
+            origins: config.cors_allowed_origins.clone(),
+            methods: config.cors_allowed_methods.clone(),
+            headers: config.cors_allowed_headers.clone(),
+            credentials: config.cors_allow_credentials,
+            max_age: Duration::from_secs(config.cors_max_age_secs),
+        });
+        if let Some(cors) = &cors {
+            cors::validate(cors)?;
+        }
+
+        Ok(Self {
+            allowed_domains: config.allowed_domains.clone(),
+            cors,
+            providers: Providers::from_config(config, client).await?,
+        })
+    }
+}
+
+/// Settings in effect and the sources to read them from again
+#[derive(Clone)]
+pub struct Reloader {
+    pub allowed_domains: Live<Vec<String>>,
+    pub cors: Live<Option<CorsConfig>>,
+    pub providers: Live<Providers>,
+    config_file: Option<PathBuf>,
+    client: HttpClient,
+}
+
+impl Reloader {
+    pub fn new(settings: Reloadable, config_file: Option<PathBuf>, client: HttpClient) -> Self {
+        Self {
+            allowed_domains: Live::new(settings.allowed_domains),
+            cors: Live::new(settings.cors),
+            providers: Live::new(settings.providers),
+            config_file,
+            client,
+        }
+    }
+
+    pub(super) async fn reload(&self) -> crate::Result<()> {
+        let vars = env::vars().collect();
+        let vars = match &self.config_file {
+            Some(path) => {
+                merge_file(path.clone(), vars).map_err(|e| Error::Config(e.to_string()))?
+            }
+            None => vars,
+        };
+        let (vars, _) = resolve_files(vars).map_err(|e| Error::Config(e.to_string()))?;
+        let settings = Reloadable::from_config(&load(vars)?, &self.client).await?;
+
+        let providers = settings.providers.names();
+        if providers != self.providers.get().names() {
+            return Err(Error::Config(
+                "SSO providers can only be added or removed with a restart".into(),
+            ));
+        }
+
+        self.allowed_domains.set(settings.allowed_domains);
+        self.cors.set(settings.cors);
+        self.providers.set(settings.providers);
+        info!(?providers, "configuration reloaded");
+
+        Ok(())
+    }
+
+    /// Reloads the settings on each `SIGHUP`
+    pub fn spawn(self) {
+        crate::utils::spawn_named("config-reload", async move {
+            let mut hangup = signal(SignalKind::hangup()).unwrap();
+
+            while hangup.recv().await.is_some() {
+                if let Err(e) = self.reload().await {
+                    warn!(error = %e, "configuration can not be reloaded, keeping the previous one");
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_keep_their_value() {
+        let live = Live::new(vec!["example.com".to_string()]);
+        let before = live.get();
+
+        live.clone().set(vec!["example.org".to_string()]);
+
+        assert_eq!(*before, ["example.com"]);
+        assert_eq!(*live.get(), ["example.org"]);
+    }
+}
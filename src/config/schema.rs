@@ -0,0 +1,860 @@
+//! JSON Schema of the accepted configuration, printed by `identity-server config-schema`
+
+use super::{
+    default_addr, default_cors_allowed_headers, default_cors_allowed_methods,
+    default_cors_max_age_secs, default_crypto_key_version, default_hibp_check,
+    default_login_lockout_minutes, default_login_max_failures, default_mail_attempts,
+    default_otlp_sample_ratio, default_password_min_length, default_password_min_score,
+    default_password_require_classes, default_port, default_rate_limit_window_secs,
+    default_secret_file_interval, default_shutdown_grace_secs, default_slow_request_sample_rate,
+    env_name, secret::FILE_SUFFIX,
+};
+
+use serde_json::{json, Map, Value};
+
+/// Builds the schema of all variables read from the environment, the `.env` file and the
+/// configuration file
+pub fn schema() -> Value {
+    let mut s = Schema::default();
+
+    // HTTP server
+    s.optional(
+        "server_addr",
+        json!({ "type": "string", "anyOf": [{ "format": "ipv4" }, { "format": "ipv6" }], "default": default_addr() }),
+    )
+    .optional(
+        "server_port",
+        json!({ "type": "integer", "minimum": 0, "maximum": 65535, "default": default_port() }),
+    )
+    .optional(
+        "base_path",
+        json!({ "type": "string", "pattern": "^/", "description": "Prefix of all routes, e.g. /auth, to share a domain with other services behind path-based routing. It applies to the discovery documents and cookies as well, the issuer URL has to end with it" }),
+    )
+    .optional(
+        "shutdown_grace_secs",
+        json!({ "type": "integer", "minimum": 0, "default": default_shutdown_grace_secs(), "description": "Seconds in-flight requests may take to complete after SIGTERM or SIGINT, remaining connections are closed afterwards" }),
+    )
+    .optional(
+        "slow_request_threshold_ms",
+        json!({ "type": "integer", "minimum": 0, "description": "Requests taking longer are traced with their upstream and database timings" }),
+    )
+    .optional(
+        "slow_request_sample_rate",
+        json!({ "type": "number", "minimum": 0, "maximum": 1, "default": default_slow_request_sample_rate() }),
+    );
+
+    s.optional(
+        "otlp_endpoint",
+        json!({ "type": "string", "format": "uri", "description": "OTLP/HTTP traces endpoint of an OpenTelemetry collector, e.g. http://localhost:4318/v1/traces. Spans aren't exported if not set" }),
+    )
+    .optional(
+        "otlp_sample_ratio",
+        json!({ "type": "number", "minimum": 0, "maximum": 1, "default": default_otlp_sample_ratio(), "description": "Share of new traces that are exported, requests with a traceparent header keep the decision of the caller" }),
+    );
+
+    #[cfg(feature = "metrics")]
+    s.optional_secret(
+        "metrics_token",
+        json!({ "type": "string", "writeOnly": true, "description": "Bearer token expected from scrapers of /metrics, which is public if not set" }),
+    );
+    s.optional(
+        "openapi_docs",
+        json!({ "type": "boolean", "default": false, "description": "Serves a Swagger UI of the OpenAPI description at /v1/docs, its assets are loaded from unpkg.com" }),
+    )
+    .optional(
+        "docs_portal",
+        json!({ "type": "boolean", "default": false, "description": "Serves a portal for integrators at /docs with the API operations, the OpenID Connect values, the scopes of all services and the webhook bodies" }),
+    );
+
+    s.optional(
+        "read_only",
+        json!({ "type": "boolean", "default": false, "description": "Rejects all mutating requests with a pointer to the primary, requires IDENTITY_PRIMARY_URL" }),
+    )
+    .optional("primary_url", url())
+    .optional(
+        "sandbox",
+        json!({ "type": "boolean", "default": false, "description": "Sandbox deployment for integrators: a fake SSO provider at /v1/sso/sandbox signs in any email address, mails are captured instead of delivered and listed at /v1/admin/outbox, rate limits are off and tokens carry a sandbox claim. Never enable it on a database with real accounts" }),
+    )
+    .optional(
+        "issuer_url",
+        json!({ "type": "string", "format": "uri", "description": "Public URL of this instance, enables OpenID Connect discovery" }),
+    )
+    .optional(
+        "device_verification_url",
+        json!({ "type": "string", "format": "uri", "description": "Page where users enter the code of the OAuth device flow, which is disabled if not set" }),
+    )
+    .optional(
+        "profile_url",
+        json!({ "type": "string", "format": "uri", "description": "Page where users fill in profile fields required by services before authorizing their clients, receives the missing fields and the authorization parameters" }),
+    );
+
+    // Rate limits
+    s.optional(
+        "rate_limit_requests",
+        json!({ "type": "integer", "minimum": 1, "description": "Requests per window to logins, SSO callbacks and token endpoints by client address and by account, answered with 429 beyond. Unlimited if not set" }),
+    )
+    .optional(
+        "rate_limit_window_secs",
+        json!({ "type": "integer", "minimum": 1, "default": default_rate_limit_window_secs() }),
+    )
+    .optional(
+        "rate_limit_burst",
+        json!({ "type": "integer", "minimum": 1, "description": "Requests that may be sent at once, defaults to the requests per window" }),
+    );
+
+    // CORS
+    s.optional(
+        "cors_allowed_origins",
+        json!({ "type": "string", "description": "Comma-separated origins of frontends allowed to call the API from browsers, e.g. https://tarkov-database.com, or * for any. SSO endpoints never allow other origins. Disabled if not set" }),
+    )
+    .optional(
+        "cors_allowed_methods",
+        json!({ "type": "string", "default": default_cors_allowed_methods().join(","), "description": "Comma-separated methods of cross-origin requests" }),
+    )
+    .optional(
+        "cors_allowed_headers",
+        json!({ "type": "string", "default": default_cors_allowed_headers().join(","), "description": "Comma-separated headers of cross-origin requests" }),
+    )
+    .optional(
+        "cors_allow_credentials",
+        json!({ "type": "boolean", "default": false, "description": "Lets the origins send cookies, not allowed with the wildcard origin" }),
+    )
+    .optional(
+        "cors_max_age_secs",
+        json!({ "type": "integer", "minimum": 0, "default": default_cors_max_age_secs(), "description": "Seconds browsers may cache the answer to a preflight request" }),
+    );
+
+    // Cache
+    #[cfg(feature = "cache")]
+    s.optional_secret(
+        "cache_url",
+        json!({ "type": "string", "format": "uri", "writeOnly": true, "description": "Redis shared by the replicas for rate limits and lookups of revoked tokens, e.g. redis://localhost:6379. Rate limits are kept per instance if not set" }),
+    )
+    .optional(
+        "cache_pool_size",
+        json!({ "type": "integer", "minimum": 1, "default": super::default_cache_pool_size(), "description": "Connections to the cache, each of them multiplexed" }),
+    );
+
+    // MongoDB client
+    s.required_secret("mongo_uri", secret())
+        .required("mongo_db", json!({ "type": "string" }))
+        .optional("mongo_tls", json!({ "type": "boolean", "default": false }))
+        .optional("mongo_cert_key", path())
+        .optional("mongo_ca", path())
+        .optional(
+            "timestamp_flush_ms",
+            json!({ "type": "integer", "minimum": 1, "description": "Writes the last issuance of clients and the last sessions of users behind, in batches this often, instead of with every request. Updates may be lost if the queue is full or the process ends" }),
+        );
+    s.optional_secret(
+        "realms",
+        json!({
+            "type": "string",
+            "writeOnly": true,
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["name", "hosts", "mongoUri", "mongoDb"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "hosts": { "type": "array", "minItems": 1, "items": { "type": "string" } },
+                        "mongoUri": { "type": "string" },
+                        "mongoDb": { "type": "string" },
+                        "quotas": { "type": "object", "description": "Replaces IDENTITY_QUOTAS for the realm" }
+                    }
+                }
+            },
+            "description": "JSON array of realms whose data is stored in their own database, requests are routed by host"
+        }),
+    );
+    s.optional(
+        "quotas",
+        json!({
+            "type": "string",
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "object",
+                "properties": {
+                    "users": { "type": "object", "properties": { "warn": { "type": "integer", "minimum": 0 }, "max": { "type": "integer", "minimum": 0 } } },
+                    "clients": { "type": "object", "properties": { "warn": { "type": "integer", "minimum": 0 }, "max": { "type": "integer", "minimum": 0 } } },
+                    "services": { "type": "object", "properties": { "warn": { "type": "integer", "minimum": 0 }, "max": { "type": "integer", "minimum": 0 } } }
+                }
+            },
+            "description": "JSON object of caps on the number of users, clients and services of each realm. Creations past `warn` are reported, creations at `max` are rejected"
+        }),
+    );
+
+    // Email client
+    #[cfg(any(feature = "mail-mailgun", feature = "mail-smtp"))]
+    s.required("mail_from", json!({ "type": "string", "format": "email" }));
+    s.optional(
+        "mail_attempts",
+        json!({ "type": "integer", "minimum": 1, "default": default_mail_attempts(), "description": "Delivery attempts per message, the delay between them doubles from one second" }),
+    );
+    #[cfg(feature = "mail-mailgun")]
+    s.optional(
+        "mg_region",
+        json!({ "type": "string", "enum": ["us", "eu"], "description": "Required for Mailgun" }),
+    )
+    .optional(
+        "mg_domain",
+        json!({ "type": "string", "description": "Required for Mailgun" }),
+    )
+    .optional_secret("mg_key", secret())
+    .optional("mg_base_url", url());
+    #[cfg(feature = "mail-smtp")]
+    s.optional(
+        "smtp_host",
+        json!({ "type": "string", "description": "SMTP relay, used instead of Mailgun if set" }),
+    )
+    .optional(
+        "smtp_port",
+        json!({ "type": "integer", "minimum": 1, "maximum": 65535, "description": "465 for TLS, 587 for STARTTLS and 25 otherwise if not set" }),
+    )
+    .optional(
+        "smtp_tls",
+        json!({ "type": "string", "enum": ["starttls", "tls", "none"], "default": "starttls" }),
+    )
+    .optional("smtp_username", json!({ "type": "string" }))
+    .optional_secret("smtp_password", secret())
+    .optional(
+        "mail_template_dir",
+        json!({ "type": "string", "description": "Directory of the templates for SMTP, as `<name>.txt` and optionally `<name>.html`. Required for SMTP" }),
+    );
+
+    // GitHub OAuth
+    #[cfg(feature = "sso-github")]
+    s.required("gh_client_id", json!({ "type": "string" }))
+        .required_secret("gh_client_secret", secret())
+        .required("gh_redirect_uri", url());
+
+    // Federation
+    #[cfg(feature = "federation")]
+    s.optional(
+        "federation_issuers",
+        json!({
+            "type": "string",
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["issuer", "audience"],
+                    "properties": {
+                        "issuer": { "type": "string" },
+                        "audience": { "type": "array", "items": { "type": "string" } },
+                        "jwksUri": { "type": "string", "format": "uri" },
+                        "claims": {
+                            "type": "object",
+                            "properties": {
+                                "subject": { "type": "string" },
+                                "email": { "type": "string" },
+                                "emailVerified": { "type": "string" }
+                            }
+                        }
+                    }
+                }
+            },
+            "description": "JSON array of trusted external issuers"
+        }),
+    );
+
+    // WASM policies
+    #[cfg(feature = "wasm-policies")]
+    s.optional("policy_dir", path()).optional(
+        "policy_reload_secs",
+        json!({ "type": "integer", "minimum": 1, "default": super::default_policy_reload_secs(), "description": "Seconds between checks of the policy directory, changed policies are swapped in once all of them compile" }),
+    );
+
+    // CI token exchange
+    s.optional(
+        "ci_policies",
+        json!({
+            "type": "string",
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["issuer", "audience", "conditions", "grant"],
+                    "properties": {
+                        "issuer": { "type": "string", "format": "uri" },
+                        "audience": { "type": "string" },
+                        "conditions": {
+                            "type": "object",
+                            "minProperties": 1,
+                            "additionalProperties": { "type": "string" }
+                        },
+                        "grant": {
+                            "oneOf": [
+                                {
+                                    "type": "object",
+                                    "required": ["session"],
+                                    "properties": {
+                                        "session": {
+                                            "type": "object",
+                                            "required": ["user", "scope"],
+                                            "properties": {
+                                                "user": { "type": "string" },
+                                                "scope": { "type": "array", "items": { "type": "string" } }
+                                            }
+                                        }
+                                    }
+                                },
+                                {
+                                    "type": "object",
+                                    "required": ["client"],
+                                    "properties": { "client": { "type": "string" } }
+                                }
+                            ]
+                        },
+                        "ttlSecs": { "type": "integer", "minimum": 1, "maximum": 3600, "default": 900 }
+                    }
+                }
+            },
+            "description": "JSON array of policies to exchange OIDC tokens of CI pipelines for short-lived tokens"
+        }),
+    );
+
+    // Claims of service tokens derived by expressions
+    s.optional(
+        "claim_rules",
+        json!({
+            "type": "string",
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "object",
+                "propertyNames": { "pattern": "^[0-9a-f]{24}$" },
+                "additionalProperties": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" }
+                }
+            },
+            "description": "JSON object of claim expressions by service ID, e.g. {\"tier\": \"roles.contains('userEditor') ? 'internal' : 'external'\"}, checked on startup"
+        }),
+    );
+
+    // Session limits by role
+    s.optional(
+        "session_limits",
+        json!({
+            "type": "string",
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "object",
+                "propertyNames": {
+                    "enum": [
+                        "userEditor",
+                        "userViewer",
+                        "clientEditor",
+                        "clientViewer",
+                        "serviceEditor",
+                        "serviceViewer"
+                    ]
+                },
+                "additionalProperties": {
+                    "type": "object",
+                    "properties": {
+                        "maxSessionSecs": { "type": "integer", "minimum": 1 },
+                        "maxTokenSecs": { "type": "integer", "minimum": 1 },
+                        "maxScope": { "type": "array", "items": { "type": "string" } },
+                        "schedule": {
+                            "type": "object",
+                            "required": ["start", "end"],
+                            "properties": {
+                                "days": {
+                                    "type": "array",
+                                    "items": { "enum": ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"] }
+                                },
+                                "start": { "type": "string", "format": "time" },
+                                "end": { "type": "string", "format": "time" },
+                                "utcOffset": { "type": "string", "pattern": "^[+-][0-9]{2}:[0-9]{2}$" }
+                            }
+                        }
+                    }
+                }
+            },
+            "description": "JSON object of the lifetime and scope ceilings and access schedules of sessions by role"
+        }),
+    );
+
+    // Sign in with Apple, all or none
+    #[cfg(feature = "sso-apple")]
+    s.optional("apple_client_id", json!({ "type": "string" }))
+        .optional("apple_team_id", json!({ "type": "string" }))
+        .optional("apple_key_id", json!({ "type": "string" }))
+        .optional("apple_key_path", path())
+        .optional("apple_redirect_uri", url());
+
+    // Twitch OAuth, all or none
+    #[cfg(feature = "sso-twitch")]
+    s.optional("twitch_client_id", json!({ "type": "string" }))
+        .optional_secret("twitch_client_secret", secret())
+        .optional("twitch_redirect_uri", url());
+
+    // Steam OpenID
+    #[cfg(feature = "sso-steam")]
+    s.optional("steam_redirect_uri", url());
+
+    // Discord OAuth, all or none
+    #[cfg(feature = "sso-discord")]
+    s.optional("discord_client_id", json!({ "type": "string" }))
+        .optional_secret("discord_client_secret", secret())
+        .optional("discord_redirect_uri", url());
+
+    // OpenID Connect providers
+    #[cfg(feature = "sso-oidc")]
+    s.optional_secret(
+        "oidc_providers",
+        json!({
+            "type": "string",
+            "writeOnly": true,
+            "contentMediaType": "application/json",
+            "contentSchema": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["name", "issuer", "clientId", "clientSecret", "redirectUri"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "issuer": { "type": "string" },
+                        "clientId": { "type": "string" },
+                        "clientSecret": { "type": "string" },
+                        "redirectUri": { "type": "string", "format": "uri" },
+                        "scope": { "type": "string", "default": "openid email" },
+                        "claims": {
+                            "type": "object",
+                            "properties": {
+                                "email": { "type": "string" },
+                                "emailVerified": { "type": "string" }
+                            }
+                        }
+                    }
+                }
+            },
+            "description": "JSON array of OpenID Connect providers to sign in with"
+        }),
+    );
+
+    // JWT
+    s.required_secret("jwt_secret", secret())
+        .required("jwt_audience", list())
+        .optional_secret(
+            "jwt_signing_key",
+            json!({
+                "type": "string",
+                "writeOnly": true,
+                "description": "PEM encoded private key to sign tokens with instead of the JWT secret, PKCS#8 or PKCS#1 for RSA. The public key is served at /.well-known/jwks.json",
+            }),
+        )
+        .optional(
+            "jwt_signing_algorithm",
+            json!({ "type": "string", "enum": ["ES256", "RS256", "EdDSA"], "default": "ES256", "description": "Algorithm of jwt_signing_key: ES256 for P-256, RS256 for RSA or EdDSA for Ed25519 keys" }),
+        )
+        .optional(
+            "jwt_key_rotation_days",
+            json!({ "type": "integer", "minimum": 1, "description": "Days after which a new ES256 signing key is generated, replaced keys are published until the tokens they signed are expired. Excludes jwt_signing_key" }),
+        );
+
+    s.optional_secret(
+        "signing_keys",
+        json!({
+            "type": "string",
+            "pattern": "^([0-9a-f]{24}:[^,]+)?(,[0-9a-f]{24}:[^,]+)*$",
+            "writeOnly": true,
+            "description": "Keys for HMAC signed requests, as comma-separated `user:secret` pairs. The roles of the user define the scope",
+        }),
+    );
+
+    // Regions
+    s.optional(
+        "region",
+        json!({ "type": "string", "pattern": "^[^,:]+$", "description": "Region of this instance, embedded in issued tokens and session records" }),
+    )
+    .optional_secret(
+        "region_siblings",
+        json!({
+            "type": "string",
+            "pattern": "^([^,:]+(:[^,]+)?)?(,[^,:]+(:[^,]+)?)*$",
+            "writeOnly": true,
+            "description": "Regions whose tokens are accepted, as comma-separated `region` or `region:secret` entries. Without a secret the region shares the JWT secret",
+        }),
+    )
+    .optional(
+        "region_sibling_keys",
+        json!({
+            "type": "string",
+            "pattern": "^([^,:]+:[^,]+)?(,[^,:]+:[^,]+)*$",
+            "description": "Regions whose tokens are accepted, as comma-separated `region:uri` entries with the URI of their published key set, e.g. `us:https://us.example.com/.well-known/jwks.json`. For regions that sign with a private key or rotate their keys",
+        }),
+    );
+
+    // Crypto
+    s.required_secret("crypto_key", secret())
+        .optional("crypto_key_version", version())
+        .optional_secret(
+            "crypto_previous_keys",
+            versioned_secrets(
+                "Previous keys, only used for decryption until all values are re-encrypted",
+            ),
+        );
+
+    // Credential hashing
+    s.optional_secret("pepper", secret())
+        .optional("pepper_version", version())
+        .optional_secret(
+            "previous_peppers",
+            versioned_secrets(
+                "Previous peppers, only used to verify hashes until they are rehashed",
+            ),
+        );
+
+    s.optional(
+        "key_expiry",
+        json!({
+            "type": "string",
+            "pattern": "^([^,:]+:[0-9]{4}-[0-9]{2}-[0-9]{2})?(,[^,:]+:[0-9]{4}-[0-9]{2}-[0-9]{2})*$",
+            "description": "Comma-separated keys with their expiry date, e.g. apple:2025-03-01. The editor addresses are alerted 30, 7 and 1 days before and once a key expired",
+        }),
+    )
+    .optional_secret("key_alert_webhook", url());
+
+    s.optional(
+        "secret_file_interval",
+        json!({ "type": "integer", "minimum": 1, "default": default_secret_file_interval(), "description": "Seconds between checks of secret files for rotation. Rotated credentials of SSO providers are reloaded in place, for any other rotated secret the server shuts down gracefully and has to be restarted by its supervisor" }),
+    );
+
+    s.optional(
+        "client_attestation_months",
+        json!({ "type": "integer", "minimum": 1, "description": "Months without use or confirmation after which client owners are asked to confirm their clients, disabled if not set" }),
+    );
+
+    #[cfg(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord"))]
+    s.optional(
+        "connection_refresh_hours",
+        json!({ "type": "integer", "minimum": 1, "description": "Hours between refreshes of the connection data of Apple, Twitch and Discord users with their stored refresh tokens, so that flags like a disabled second factor at the provider are updated without a login. Disabled if not set" }),
+    );
+
+    s.optional(
+        "integrity_check_hours",
+        json!({ "type": "integer", "minimum": 1, "description": "Hours between checks for documents referencing deleted users, clients or services, exported as metrics. Disabled if not set" }),
+    )
+    .optional(
+        "integrity_fix",
+        json!({ "type": "boolean", "default": false, "description": "Removes the orphaned documents found by the checks" }),
+    );
+
+    // Global vars
+    s.required("editor_mail_address", list())
+        .optional("security_contacts", list())
+        .optional(
+            "sensitive_roles",
+            json!({ "type": "string", "pattern": "^([a-zA-Z]+(,[a-zA-Z]+)*)?$", "description": "Comma-separated roles, e.g. userEditor. Grants of them wait for the approval of a second admin" }),
+        )
+        .optional(
+            "canary_auto_lock",
+            json!({ "type": "boolean", "default": false, "description": "Locks all clients and revokes the sessions of the owner of a canary client once it is used" }),
+        )
+        .optional(
+            "sso_fallback",
+            json!({ "type": "string", "pattern": "^((password|recoveryCode|breakGlass)(,(password|recoveryCode|breakGlass))*)?$", "description": "Comma-separated logins accepted while an SSO provider is unavailable: password, recoveryCode or breakGlass. Disabled if not set" }),
+        )
+        .optional("break_glass_accounts", list())
+        .required("allowed_domains", list())
+        .optional(
+            "hibp_check",
+            json!({ "type": "boolean", "default": default_hibp_check() }),
+        );
+
+    // Password policy
+    s.optional(
+        "password_min_length",
+        json!({ "type": "integer", "minimum": 8, "maximum": 80, "default": default_password_min_length() }),
+    )
+    .optional(
+        "password_require_classes",
+        json!({ "type": "boolean", "default": default_password_require_classes(), "description": "Requires uppercase and lowercase characters and digits" }),
+    )
+    .optional(
+        "password_min_score",
+        json!({ "type": "number", "minimum": 0, "maximum": 100, "default": default_password_min_score() }),
+    )
+    .optional(
+        "login_max_failures",
+        json!({ "type": "integer", "minimum": 0, "default": default_login_max_failures(), "description": "Failed password logins after which the account is locked for password logins, never locked if 0" }),
+    )
+    .optional(
+        "login_lockout_minutes",
+        json!({ "type": "integer", "minimum": 1, "default": default_login_lockout_minutes() }),
+    );
+
+    s.into_value()
+}
+
+#[derive(Default)]
+struct Schema {
+    properties: Map<String, Value>,
+    required: Vec<String>,
+    /// Required secrets, set either directly or with a file
+    required_secrets: Vec<String>,
+}
+
+impl Schema {
+    fn required(&mut self, field: &str, schema: Value) -> &mut Self {
+        self.required.push(env_name(field));
+        self.optional(field, schema)
+    }
+
+    fn optional(&mut self, field: &str, schema: Value) -> &mut Self {
+        self.properties.insert(env_name(field), schema);
+        self
+    }
+
+    fn required_secret(&mut self, field: &str, schema: Value) -> &mut Self {
+        self.required_secrets.push(env_name(field));
+        self.optional_secret(field, schema)
+    }
+
+    /// Adds the secret and its `*_FILE` variant, see [`super::secret`]
+    fn optional_secret(&mut self, field: &str, schema: Value) -> &mut Self {
+        self.properties.insert(
+            format!("{}{}", env_name(field), FILE_SUFFIX),
+            json!({ "type": "string", "description": "Path of a file containing the secret" }),
+        );
+        self.optional(field, schema)
+    }
+
+    fn into_value(self) -> Value {
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "identity-server configuration",
+            "description": "Environment variables, also read from a .env file in the working directory and from a TOML file given with --config or CONFIG_FILE, whose keys are the names in lower case without prefix",
+            "type": "object",
+            "properties": self.properties,
+            "required": self.required,
+            "allOf": self.required_secrets.iter().map(|name| json!({
+                "oneOf": [{ "required": [name] }, { "required": [format!("{}{}", name, FILE_SUFFIX)] }]
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn url() -> Value {
+    json!({ "type": "string", "format": "uri" })
+}
+
+fn path() -> Value {
+    json!({ "type": "string", "description": "File path" })
+}
+
+fn secret() -> Value {
+    json!({ "type": "string", "writeOnly": true })
+}
+
+fn list() -> Value {
+    json!({ "type": "string", "description": "Comma-separated list" })
+}
+
+fn version() -> Value {
+    json!({ "type": "integer", "minimum": 0, "maximum": 255, "default": default_crypto_key_version() })
+}
+
+fn versioned_secrets(description: &str) -> Value {
+    json!({
+        "type": "string",
+        "pattern": "^([0-9]+:[^,]+)?(,[0-9]+:[^,]+)*$",
+        "writeOnly": true,
+        "description": format!("{}, as comma-separated `version:secret` pairs", description),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{secret::SECRETS, AppConfig, ENV_PREFIX};
+
+    /// Builds a value that is valid for the property schema
+    fn sample(schema: &Value) -> String {
+        if let Some(v) = schema["enum"].get(0) {
+            return v.as_str().unwrap().to_string();
+        }
+
+        match (schema["type"].as_str(), schema["format"].as_str()) {
+            (Some("string"), Some("uri")) => "https://example.com/".into(),
+            (Some("string"), Some("email")) => "mail@example.com".into(),
+            (Some("integer"), _) | (Some("number"), _) => "1".into(),
+            (Some("boolean"), _) => "true".into(),
+            _ => "value".into(),
+        }
+    }
+
+    /// Required properties, with the direct variant of secrets
+    fn required_env(schema: &Value) -> Vec<(String, String)> {
+        let secrets = schema["allOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| &s["oneOf"][0]["required"][0]);
+
+        schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .chain(secrets)
+            .map(|name| {
+                let name = name.as_str().unwrap();
+                (name.to_string(), sample(&schema["properties"][name]))
+            })
+            .collect()
+    }
+
+    fn load(env: Vec<(String, String)>) -> Result<AppConfig, envy::Error> {
+        envy::prefixed(ENV_PREFIX).from_iter(env)
+    }
+
+    #[test]
+    fn required_properties_are_sufficient() {
+        let schema = schema();
+
+        assert!(load(required_env(&schema)).is_ok());
+    }
+
+    #[test]
+    fn required_properties_are_necessary() {
+        let schema = schema();
+        let env = required_env(&schema);
+
+        for i in 0..env.len() {
+            let mut missing = env.clone();
+            let (name, _) = missing.remove(i);
+
+            assert!(load(missing).is_err(), "{} is not required", name);
+        }
+    }
+
+    #[test]
+    fn optional_properties_are_accepted() {
+        let schema = schema();
+        let mut env = required_env(&schema);
+
+        for (name, property) in schema["properties"].as_object().unwrap() {
+            let value = match name.as_str() {
+                "IDENTITY_FEDERATION_ISSUERS"
+                | "IDENTITY_CI_POLICIES"
+                | "IDENTITY_OIDC_PROVIDERS"
+                | "IDENTITY_REALMS" => "[]".into(),
+                "IDENTITY_SESSION_LIMITS" | "IDENTITY_QUOTAS" | "IDENTITY_CLAIM_RULES" => {
+                    "{}".into()
+                }
+                "IDENTITY_CRYPTO_PREVIOUS_KEYS" | "IDENTITY_PREVIOUS_PEPPERS" => "1:value".into(),
+                "IDENTITY_SERVER_ADDR" => "::1".into(),
+                "IDENTITY_BASE_PATH" => "/auth/".into(),
+                "IDENTITY_SIGNING_KEYS" => "62a3c0a5e2a1f3b4c5d6e7f8:value".into(),
+                "IDENTITY_KEY_EXPIRY" => "apple:2025-03-01".into(),
+                "IDENTITY_SENSITIVE_ROLES" => "userEditor,serviceEditor".into(),
+                "IDENTITY_SSO_FALLBACK" => "password,recoveryCode".into(),
+                "IDENTITY_REGION_SIBLING_KEYS" => "us:https://example.com/jwks.json".into(),
+                _ => sample(property),
+            };
+            if !name.ends_with(FILE_SUFFIX) && !env.iter().any(|(n, _)| n == name) {
+                env.push((name.clone(), value));
+            }
+        }
+
+        assert!(load(env).is_ok());
+    }
+
+    #[test]
+    fn secrets_can_be_read_from_files() {
+        let schema = schema();
+        let properties = schema["properties"].as_object().unwrap();
+
+        let mut files = properties
+            .keys()
+            .filter_map(|name| name.strip_suffix(FILE_SUFFIX))
+            .collect::<Vec<_>>();
+        let mut secrets = SECRETS.iter().map(|f| env_name(f)).collect::<Vec<_>>();
+        files.sort_unstable();
+        secrets.sort_unstable();
+
+        assert_eq!(files, secrets);
+    }
+
+    /// Records the field names of a struct, so that they can be compared with the schema
+    struct Fields(Vec<&'static str>);
+
+    impl<'de> serde::Deserializer<'de> for &mut Fields {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V>(self, _: V) -> Result<V::Value, Self::Error>
+        where
+            V: serde::de::Visitor<'de>,
+        {
+            Err(serde::de::Error::custom("only structs are supported"))
+        }
+
+        fn deserialize_struct<V>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error>
+        where
+            V: serde::de::Visitor<'de>,
+        {
+            self.0.extend(fields);
+            Err(serde::de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    #[test]
+    fn properties_match_fields() {
+        use serde::Deserialize;
+
+        let mut fields = Fields(Vec::new());
+        let _ = AppConfig::deserialize(&mut fields);
+        let mut fields = fields.0.into_iter().map(env_name).collect::<Vec<_>>();
+        fields.sort_unstable();
+
+        let schema = schema();
+        let mut properties = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .filter(|name| !name.ends_with(FILE_SUFFIX))
+            .cloned()
+            .collect::<Vec<_>>();
+        properties.sort_unstable();
+
+        assert_eq!(properties, fields);
+    }
+
+    /// Setting a variable to its documented default must not change the configuration
+    #[test]
+    fn defaults_match_fields() {
+        let schema = schema();
+        let env = required_env(&schema);
+        let defaults = format!("{:?}", load(env.clone()).unwrap());
+
+        for (name, property) in schema["properties"].as_object().unwrap() {
+            let value = match &property["default"] {
+                Value::Null => continue,
+                Value::String(v) => v.clone(),
+                v => v.to_string(),
+            };
+            let mut explicit = env.clone();
+            explicit.push((name.clone(), value));
+
+            let explicit = format!("{:?}", load(explicit).unwrap());
+            assert_eq!(explicit, defaults, "default of {} differs", name);
+        }
+    }
+}
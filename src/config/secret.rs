@@ -0,0 +1,255 @@
+//! Secrets read from files, e.g. Kubernetes secret mounts or Docker secrets.
+//!
+//! Every secret can be set with a `*_FILE` variable holding the path of a file instead,
+//! like `IDENTITY_JWT_SECRET_FILE` for `IDENTITY_JWT_SECRET`.
+
+use super::{env_name, reload::RELOADED_SECRETS, Reloader};
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use tokio::sync::broadcast::Sender;
+use tracing::{debug, info, warn};
+
+pub(super) const FILE_SUFFIX: &str = "_FILE";
+
+type Vars = Vec<(String, String)>;
+
+/// Fields that can be read from a file
+pub const SECRETS: &[&str] = &[
+    "mongo_uri",
+    "realms",
+    #[cfg(feature = "mail-mailgun")]
+    "mg_key",
+    #[cfg(feature = "mail-smtp")]
+    "smtp_password",
+    #[cfg(feature = "sso-github")]
+    "gh_client_secret",
+    #[cfg(feature = "sso-twitch")]
+    "twitch_client_secret",
+    #[cfg(feature = "sso-discord")]
+    "discord_client_secret",
+    #[cfg(feature = "sso-oidc")]
+    "oidc_providers",
+    "jwt_secret",
+    "jwt_signing_key",
+    "signing_keys",
+    "region_siblings",
+    "crypto_key",
+    "crypto_previous_keys",
+    "pepper",
+    "previous_peppers",
+    "key_alert_webhook",
+    #[cfg(feature = "metrics")]
+    "metrics_token",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("{0} and {0}_FILE are both set")]
+    Ambiguous(String),
+    #[error("secret file {0} can not be read: {1}")]
+    Read(PathBuf, std::io::Error),
+}
+
+/// Secret file with the contents it had when the configuration was loaded
+#[derive(Debug)]
+pub struct SecretFile {
+    field: &'static str,
+    path: PathBuf,
+    contents: String,
+}
+
+impl SecretFile {
+    fn read(field: &'static str, path: PathBuf) -> Result<Self, SecretError> {
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(Self {
+                field,
+                contents: trim_newline(contents),
+                path,
+            }),
+            Err(e) => Err(SecretError::Read(path, e)),
+        }
+    }
+
+    /// Takes the current contents as the ones in effect
+    fn refresh(&mut self) {
+        if let Ok(contents) = fs::read_to_string(&self.path) {
+            self.contents = trim_newline(contents);
+        }
+    }
+
+    fn has_changed(&self) -> bool {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => trim_newline(contents) != self.contents,
+            // Mounts are swapped atomically, but the file may vanish for a moment
+            Err(e) => {
+                debug!(path = %self.path.display(), error = %e, "secret file not readable");
+                false
+            }
+        }
+    }
+}
+
+/// Editors and `kubectl create secret --from-file` leave a trailing newline
+fn trim_newline(mut s: String) -> String {
+    let len = s.trim_end_matches(['\n', '\r']).len();
+    s.truncate(len);
+    s
+}
+
+/// Replaces the `*_FILE` variables of secrets with the contents of the files
+pub fn resolve_files<I>(vars: I) -> Result<(Vars, Vec<SecretFile>), SecretError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut vars = vars.into_iter().collect::<Vec<_>>();
+    let mut files = Vec::new();
+
+    for field in SECRETS {
+        let name = env_name(field);
+        let file_name = format!("{}{}", name, FILE_SUFFIX);
+
+        let path = match vars.iter().position(|(k, _)| *k == file_name) {
+            Some(i) => PathBuf::from(vars.remove(i).1),
+            None => continue,
+        };
+        if vars.iter().any(|(k, _)| *k == name) {
+            return Err(SecretError::Ambiguous(name));
+        }
+
+        let file = SecretFile::read(field, path)?;
+        vars.push((name, file.contents.clone()));
+        files.push(file);
+    }
+
+    Ok((vars, files))
+}
+
+/// Watches the secret files. Rotated credentials of SSO providers are reloaded in place by the
+/// [`Reloader`], any other rotated secret shuts the server down gracefully, so that it is
+/// restarted with the new secret.
+pub fn watch(
+    mut files: Vec<SecretFile>,
+    interval: Duration,
+    reloader: Reloader,
+    shutdown: Sender<()>,
+) {
+    if files.is_empty() {
+        return;
+    }
+
+    crate::utils::spawn_named("secret-files", async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let mut changed = files
+                .iter_mut()
+                .filter(|f| f.has_changed())
+                .collect::<Vec<_>>();
+            if changed.is_empty() {
+                continue;
+            }
+
+            if let Some(file) = changed
+                .iter()
+                .find(|f| !RELOADED_SECRETS.contains(&f.field))
+            {
+                warn!(path = %file.path.display(), "secret file changed, shutting down to reload");
+                let _ = shutdown.send(());
+                break;
+            }
+
+            // A rejected configuration isn't retried until the files change again
+            for file in &mut changed {
+                file.refresh();
+            }
+            match reloader.reload().await {
+                Ok(()) => info!("secret file changed, configuration reloaded"),
+                Err(e) => {
+                    warn!(error = %e, "secret file changed, configuration can not be reloaded")
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn secret_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("identity-{}-{}", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn var(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn reads_secret_from_file() {
+        let path = secret_file("jwt", "secret\n");
+        let vars = vec![
+            var("IDENTITY_MONGO_DB", "identity"),
+            var("IDENTITY_JWT_SECRET_FILE", path.to_str().unwrap()),
+        ];
+
+        let (vars, files) = resolve_files(vars).unwrap();
+
+        assert_eq!(
+            vars,
+            [
+                var("IDENTITY_MONGO_DB", "identity"),
+                var("IDENTITY_JWT_SECRET", "secret")
+            ]
+        );
+        assert!(!files[0].has_changed());
+
+        fs::write(&path, "rotated").unwrap();
+        assert!(files[0].has_changed());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn ignores_files_of_other_fields() {
+        let vars = vec![var("IDENTITY_MONGO_DB_FILE", "/nonexistent")];
+
+        let (vars, files) = resolve_files(vars).unwrap();
+
+        assert_eq!(vars, [var("IDENTITY_MONGO_DB_FILE", "/nonexistent")]);
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn rejects_value_and_file() {
+        let vars = vec![
+            var("IDENTITY_PEPPER", "pepper"),
+            var("IDENTITY_PEPPER_FILE", "/nonexistent"),
+        ];
+
+        assert!(matches!(
+            resolve_files(vars),
+            Err(SecretError::Ambiguous(name)) if name == "IDENTITY_PEPPER"
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_file() {
+        let vars = vec![var("IDENTITY_PEPPER_FILE", "/nonexistent")];
+
+        assert!(matches!(resolve_files(vars), Err(SecretError::Read(..))));
+    }
+
+    #[test]
+    fn reloaded_secrets_are_secrets() {
+        for field in RELOADED_SECRETS {
+            assert!(SECRETS.contains(field), "{} is not a secret", field);
+        }
+    }
+}
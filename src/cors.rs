@@ -0,0 +1,206 @@
+//! Cross-origin requests of frontends on other origins
+//!
+//! Requests of origins that aren't allowed pass through without CORS headers, so browsers keep
+//! their responses from the page, while requests without a browser are unaffected. SSO endpoints
+//! are never shared with other origins, their callbacks are only navigated to.
+
+use crate::{
+    config::{CorsConfig, Live},
+    error::Error,
+};
+
+use axum::{
+    body::Body,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN, VARY,
+    },
+    Method, Request, StatusCode,
+};
+use reqwest::Url;
+
+/// Paths that are never shared with other origins
+const LOCKED_PATHS: &[&str] = &["/v1/sso"];
+
+/// Any origin, without credentials
+const ANY: &str = "*";
+
+fn is_locked(path: &str) -> bool {
+    LOCKED_PATHS.iter().any(|p| {
+        path.strip_prefix(p)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Checks that the origins are bare origins and the methods and headers are valid
+pub fn validate(config: &CorsConfig) -> crate::Result<()> {
+    for origin in &config.origins {
+        if origin == ANY {
+            if config.credentials {
+                return Err(Error::Config(
+                    "CORS credentials are not allowed for any origin".into(),
+                ));
+            }
+            continue;
+        }
+
+        let url = Url::parse(origin)
+            .map_err(|_| Error::Config(format!("CORS origin {} is invalid", origin)))?;
+        if !matches!(url.scheme(), "https" | "http")
+            || url.origin().ascii_serialization() != *origin
+        {
+            return Err(Error::Config(format!(
+                "CORS origin {} has to be scheme, host and port only",
+                origin
+            )));
+        }
+    }
+
+    for method in &config.methods {
+        Method::from_bytes(method.as_bytes())
+            .map_err(|_| Error::Config(format!("CORS method {} is invalid", method)))?;
+    }
+    for header in &config.headers {
+        HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| Error::Config(format!("CORS header {} is invalid", header)))?;
+    }
+
+    Ok(())
+}
+
+/// Value of `Access-Control-Allow-Origin` if the origin is allowed
+fn allowed_origin(config: &CorsConfig, origin: &HeaderValue) -> Option<HeaderValue> {
+    if config.origins.iter().any(|o| o == ANY) {
+        return Some(HeaderValue::from_static(ANY));
+    }
+
+    let origin_str = origin.to_str().ok()?;
+    config
+        .origins
+        .iter()
+        .any(|o| o == origin_str)
+        .then(|| origin.clone())
+}
+
+fn insert_common(headers: &mut HeaderMap, config: &CorsConfig, origin: HeaderValue) {
+    if origin != ANY {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    if config.credentials {
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+fn join(values: &[String]) -> HeaderValue {
+    HeaderValue::from_str(&values.join(", ")).expect("validated when loaded")
+}
+
+/// Middleware that answers preflight requests and shares responses with the allowed origins of
+/// the current configuration, if any
+pub async fn handle(
+    req: Request<Body>,
+    next: Next<Body>,
+    config: Live<Option<CorsConfig>>,
+) -> Response {
+    let config = config.get();
+    let config = match config.as_ref() {
+        Some(config) => config,
+        None => return next.run(req).await,
+    };
+
+    let origin = match req.headers().get(ORIGIN) {
+        Some(v) if !is_locked(req.uri().path()) => allowed_origin(config, v),
+        _ => None,
+    };
+    let origin = match origin {
+        Some(v) => v,
+        None => return next.run(req).await,
+    };
+
+    let preflight = req.method() == Method::OPTIONS
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+        let mut res = StatusCode::NO_CONTENT.into_response();
+        let headers = res.headers_mut();
+        insert_common(headers, config, origin);
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, join(&config.methods));
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, join(&config.headers));
+        headers.insert(ACCESS_CONTROL_MAX_AGE, config.max_age.as_secs().into());
+
+        return res;
+    }
+
+    let mut res = next.run(req).await;
+    let headers = res.headers_mut();
+    insert_common(headers, config, origin);
+    headers.insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("retry-after"),
+    );
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn config(origins: &[&str], credentials: bool) -> CorsConfig {
+        CorsConfig {
+            origins: origins.iter().map(|v| v.to_string()).collect(),
+            methods: vec!["GET".into(), "POST".into()],
+            headers: vec!["authorization".into()],
+            credentials,
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn validates_origins() {
+        assert!(validate(&config(&["https://tarkov-database.com"], true)).is_ok());
+        assert!(validate(&config(&["http://localhost:3000"], false)).is_ok());
+        assert!(validate(&config(&["*"], false)).is_ok());
+
+        assert!(validate(&config(&["*"], true)).is_err());
+        assert!(validate(&config(&["https://tarkov-database.com/"], false)).is_err());
+        assert!(validate(&config(&["tarkov-database.com"], false)).is_err());
+        assert!(validate(&config(&["ftp://tarkov-database.com"], false)).is_err());
+    }
+
+    #[test]
+    fn allows_listed_origins() {
+        let listed = config(&["https://tarkov-database.com"], true);
+        let origin = HeaderValue::from_static("https://tarkov-database.com");
+        assert_eq!(allowed_origin(&listed, &origin), Some(origin));
+        assert_eq!(
+            allowed_origin(&listed, &HeaderValue::from_static("https://evil.example")),
+            None
+        );
+
+        let any = config(&["*"], false);
+        assert_eq!(
+            allowed_origin(&any, &HeaderValue::from_static("https://evil.example")),
+            Some(HeaderValue::from_static("*"))
+        );
+    }
+
+    #[test]
+    fn locks_sso_endpoints() {
+        assert!(is_locked("/v1/sso"));
+        assert!(is_locked("/v1/sso/github/authorized"));
+        assert!(!is_locked("/v1/ssox"));
+        assert!(!is_locked("/v1/session"));
+    }
+}
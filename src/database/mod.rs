@@ -0,0 +1,121 @@
+mod quota;
+mod realm;
+mod timestamps;
+
+#[cfg(feature = "cache")]
+use crate::cache::Cache;
+use crate::{
+    clock::{SharedClock, SystemClock},
+    Result,
+};
+
+use crate::metrics::Metrics;
+
+use std::{sync::Arc, time::Duration};
+
+use mongodb::{bson::doc, options::ClientOptions, Client, Collection};
+
+pub use quota::{de_quotas, spawn_quota_alerts, QuotaError, Quotas};
+pub use realm::{de_realms, route_realm, RealmConfig, Realms};
+pub use timestamps::{Timestamps, MAX_SESSIONS};
+
+#[derive(Debug, Clone)]
+pub struct Database {
+    client: Client,
+    db_name: String,
+    /// Name of the realm, none for the default database
+    realm: Option<String>,
+    quotas: Arc<Quotas>,
+    #[cfg(feature = "cache")]
+    cache: Option<Cache>,
+    timestamps: Option<Timestamps>,
+    clock: SharedClock,
+}
+
+impl Database {
+    pub fn new(opts: ClientOptions, db: &str) -> Result<Self> {
+        let client = Client::with_options(opts)?;
+
+        Ok(Self {
+            client,
+            db_name: db.to_string(),
+            realm: None,
+            quotas: Arc::default(),
+            #[cfg(feature = "cache")]
+            cache: None,
+            timestamps: None,
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = Arc::new(quotas);
+        self
+    }
+
+    #[cfg(feature = "cache")]
+    pub fn with_cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    #[cfg(feature = "cache")]
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Clock of the dates this database records itself, like those of revisions
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Writes timestamp-only updates behind, flushed every interval
+    pub fn with_timestamps(mut self, interval: Duration, metrics: Metrics) -> Self {
+        self.timestamps = Some(Timestamps::spawn(self.clone(), interval, metrics));
+        self
+    }
+
+    /// Queue of timestamp-only updates, they are written right away if there is none
+    pub fn timestamps(&self) -> Option<&Timestamps> {
+        self.timestamps.as_ref()
+    }
+
+    /// Key of the cache entry, separate for every database so that realms don't share entries
+    #[cfg(feature = "cache")]
+    pub fn cache_key(&self, key: &str) -> String {
+        format!("{}:{}", self.db_name, key)
+    }
+
+    fn with_realm(mut self, realm: String) -> Self {
+        self.realm = Some(realm);
+        self
+    }
+
+    pub fn realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+
+    /// Round trip to the server, e.g. to check that it is reachable
+    pub async fn ping(&self) -> Result<()> {
+        self.client
+            .database("admin")
+            .run_command(doc! { "ping": 1 }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "chaos")]
+    pub fn admin(&self) -> mongodb::Database {
+        self.client.database("admin")
+    }
+
+    pub fn collection<T>(&self, name: &str) -> Collection<T> {
+        self.client.database(&self.db_name).collection(name)
+    }
+}
@@ -0,0 +1,185 @@
+//! Caps on the number of users, clients and services of a database, i.e. of a realm.
+//!
+//! Creations past the soft limit are logged and reported to the security contacts, creations
+//! at the hard limit are rejected. This protects shared deployments from runaway automation.
+
+use crate::{error, model::Status, session::Resource, user::SecurityNotifier, utils, Result};
+
+use super::Database;
+
+use std::{collections::HashMap, time::Duration};
+
+use hyper::StatusCode;
+use mongodb::bson::Document;
+use serde::{Deserialize, Deserializer};
+use tracing::{error, warn};
+
+const ALERT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("quota of {1} {0} is exhausted")]
+    Exhausted(&'static str, u64),
+}
+
+impl error::ErrorResponse for QuotaError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            QuotaError::Exhausted(..) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Limit {
+    /// Count from which creations are reported
+    pub warn: Option<u64>,
+    /// Count from which creations are rejected
+    pub max: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quotas {
+    #[serde(default)]
+    pub users: Limit,
+    #[serde(default)]
+    pub clients: Limit,
+    #[serde(default)]
+    pub services: Limit,
+}
+
+impl Quotas {
+    fn limit(&self, resource: Resource) -> Limit {
+        match resource {
+            Resource::User => self.users,
+            Resource::Client => self.clients,
+            Resource::Service => self.services,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        [self.users, self.clients, self.services]
+            .iter()
+            .all(|l| l.warn.is_none() && l.max.is_none())
+    }
+}
+
+/// Deserializes the quotas from a JSON string
+pub fn de_quotas<'de, D>(d: D) -> std::result::Result<Quotas, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    serde_json::from_str(&input).map_err(serde::de::Error::custom)
+}
+
+/// Collection of the resource
+const fn collection(resource: Resource) -> &'static str {
+    match resource {
+        Resource::User => "users",
+        Resource::Client => "clients",
+        Resource::Service => "services",
+    }
+}
+
+const RESOURCES: [Resource; 3] = [Resource::User, Resource::Client, Resource::Service];
+
+impl Database {
+    /// Checks that another resource may be created
+    pub async fn check_quota(&self, resource: Resource) -> Result<()> {
+        let limit = self.quotas.limit(resource);
+        if limit.warn.is_none() && limit.max.is_none() {
+            return Ok(());
+        }
+
+        let count = self
+            .collection::<Document>(collection(resource))
+            .estimated_document_count(None)
+            .await?;
+
+        if let Some(max) = limit.max {
+            if count >= max {
+                warn!(?resource, count, realm = ?self.realm(), "creation rejected by quota");
+                return Err(QuotaError::Exhausted(collection(resource), max).into());
+            }
+        }
+        if let Some(warn) = limit.warn {
+            if count >= warn {
+                warn!(?resource, count, realm = ?self.realm(), "quota soft limit exceeded");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reports resources over their soft limit to the security contacts, once per crossing
+pub fn spawn_quota_alerts(db: Database, notifier: SecurityNotifier) {
+    if db.quotas.is_empty() {
+        return;
+    }
+
+    utils::spawn_named("quota-alerts", async move {
+        let mut interval = tokio::time::interval(ALERT_INTERVAL);
+        let mut exceeded = HashMap::new();
+
+        loop {
+            interval.tick().await;
+
+            for resource in RESOURCES {
+                let warn = match db.quotas.limit(resource).warn {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                let count = match db
+                    .collection::<Document>(collection(resource))
+                    .estimated_document_count(None)
+                    .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(error = %e, "quota usage could not be counted");
+                        continue;
+                    }
+                };
+
+                let was_exceeded = exceeded.insert(collection(resource), count >= warn);
+                if count >= warn && was_exceeded != Some(true) {
+                    notify(&db, &notifier, resource, count, warn).await;
+                }
+            }
+        }
+    });
+}
+
+async fn notify(
+    db: &Database,
+    notifier: &SecurityNotifier,
+    resource: Resource,
+    count: u64,
+    warn: u64,
+) {
+    const TEMPLATE_NAME: &str = "identity.security.quota";
+
+    let mut vars = HashMap::new();
+    vars.insert("resource".to_string(), collection(resource).to_string());
+    vars.insert("count".to_string(), count.to_string());
+    vars.insert("limit".to_string(), warn.to_string());
+    if let Some(realm) = db.realm() {
+        vars.insert("realm".to_string(), realm.to_string());
+    }
+
+    notifier
+        .send("Quota soft limit exceeded", TEMPLATE_NAME, vars)
+        .await;
+}
@@ -0,0 +1,218 @@
+//! Storage routing of realms, e.g. tenants whose identity data has to stay in a region.
+//!
+//! Requests are assigned to a realm by their host and handled with the database of the realm,
+//! requests of other hosts use the default database. Responses of a realm are tagged with
+//! its name.
+
+#[cfg(feature = "cache")]
+use crate::cache::Cache;
+use crate::{clock::SharedClock, metrics::Metrics, Result};
+
+use super::{Database, Quotas};
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use axum::{middleware::Next, response::Response};
+use hyper::{
+    header::{HeaderName, HeaderValue, HOST},
+    Request,
+};
+use mongodb::options::ClientOptions;
+use serde::{Deserialize, Deserializer};
+
+/// Response header with the realm whose database served the request
+pub const REALM_HEADER: &str = "x-data-realm";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RealmConfig {
+    pub name: String,
+    /// Hosts of the realm, without port
+    pub hosts: Vec<String>,
+    pub mongo_uri: String,
+    pub mongo_db: String,
+    /// Quotas of the realm instead of the default ones
+    pub quotas: Option<Quotas>,
+}
+
+/// Deserializes the realms from a JSON string
+pub fn de_realms<'de, D>(d: D) -> std::result::Result<Vec<RealmConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let realms: Vec<RealmConfig> =
+        serde_json::from_str(&input).map_err(serde::de::Error::custom)?;
+
+    let mut hosts = realms.iter().flat_map(|r| &r.hosts).collect::<Vec<_>>();
+    let total = hosts.len();
+    hosts.sort_unstable();
+    hosts.dedup();
+    if hosts.len() != total {
+        return Err(serde::de::Error::custom("host assigned to several realms"));
+    }
+    if realms.iter().any(|r| r.hosts.is_empty()) {
+        return Err(serde::de::Error::custom("realm without hosts"));
+    }
+
+    Ok(realms)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Realms {
+    by_host: Arc<HashMap<String, Database>>,
+    databases: Arc<Vec<Database>>,
+}
+
+impl Realms {
+    /// Connects to the databases of the realms, with the TLS and monitoring options of the default
+    pub async fn connect(
+        configs: Vec<RealmConfig>,
+        defaults: &ClientOptions,
+        quotas: &Quotas,
+    ) -> Result<Self> {
+        let mut by_host = HashMap::new();
+        let mut databases = Vec::new();
+
+        for config in configs {
+            let mut opts = ClientOptions::parse(&config.mongo_uri).await?;
+            opts.tls = defaults.tls.clone();
+            opts.command_event_handler = defaults.command_event_handler.clone();
+
+            let db = Database::new(opts, &config.mongo_db)?
+                .with_realm(config.name)
+                .with_quotas(config.quotas.unwrap_or_else(|| quotas.clone()));
+            for host in config.hosts {
+                by_host.insert(host.to_ascii_lowercase(), db.clone());
+            }
+            databases.push(db);
+        }
+
+        Ok(Self {
+            by_host: Arc::new(by_host),
+            databases: Arc::new(databases),
+        })
+    }
+
+    /// Shares the cache with the databases of all realms
+    #[cfg(feature = "cache")]
+    pub fn with_cache(self, cache: &Cache) -> Self {
+        let with_cache = |db: &Database| db.clone().with_cache(cache.clone());
+
+        Self {
+            by_host: Arc::new(
+                self.by_host
+                    .iter()
+                    .map(|(host, db)| (host.clone(), with_cache(db)))
+                    .collect(),
+            ),
+            databases: Arc::new(self.databases.iter().map(with_cache).collect()),
+        }
+    }
+
+    /// Shares the clock with the databases of all realms
+    pub fn with_clock(self, clock: &SharedClock) -> Self {
+        let with_clock = |db: &Database| db.clone().with_clock(clock.clone());
+
+        Self {
+            by_host: Arc::new(
+                self.by_host
+                    .iter()
+                    .map(|(host, db)| (host.clone(), with_clock(db)))
+                    .collect(),
+            ),
+            databases: Arc::new(self.databases.iter().map(with_clock).collect()),
+        }
+    }
+
+    /// Writes timestamp-only updates of the databases of all realms behind
+    pub fn with_timestamps(self, interval: Duration, metrics: &Metrics) -> Self {
+        let databases: Vec<_> = self
+            .databases
+            .iter()
+            .map(|db| db.clone().with_timestamps(interval, metrics.clone()))
+            .collect();
+        let by_host = self
+            .by_host
+            .iter()
+            .map(|(host, db)| {
+                let db = databases.iter().find(|d| d.realm() == db.realm()).unwrap();
+                (host.clone(), db.clone())
+            })
+            .collect();
+
+        Self {
+            by_host: Arc::new(by_host),
+            databases: Arc::new(databases),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.databases.is_empty()
+    }
+
+    /// Databases of all realms, without the default one
+    pub fn databases(&self) -> impl Iterator<Item = &Database> {
+        self.databases.iter()
+    }
+
+    fn resolve(&self, host: &str) -> Option<&Database> {
+        let host = match host.rsplit_once(':') {
+            // IPv6 addresses without port end with a bracket
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => host,
+        };
+
+        self.by_host.get(&host.to_ascii_lowercase())
+    }
+}
+
+/// Middleware that replaces the default database with the one of the realm of the host
+pub async fn route_realm<B>(mut req: Request<B>, next: Next<B>, realms: Realms) -> Response {
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| req.uri().host());
+
+    let db = match host.and_then(|h| realms.resolve(h)) {
+        Some(db) => db.clone(),
+        None => return next.run(req).await,
+    };
+
+    let realm = db.realm().map(HeaderValue::from_str);
+    req.extensions_mut().insert(db);
+
+    let mut response = next.run(req).await;
+    if let Some(Ok(v)) = realm {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(REALM_HEADER), v);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolves_hosts() {
+        let configs: Vec<RealmConfig> = serde_json::from_str(
+            r#"[{"name":"eu","hosts":["EU.example.com"],"mongoUri":"mongodb://localhost","mongoDb":"identity"}]"#,
+        )
+        .unwrap();
+        let realms = Realms::connect(configs, &ClientOptions::default(), &Quotas::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            realms.resolve("eu.example.com").unwrap().realm(),
+            Some("eu")
+        );
+        assert!(realms.resolve("eu.example.com:8080").is_some());
+        assert!(realms.resolve("example.com").is_none());
+    }
+}
@@ -0,0 +1,182 @@
+//! Write-behind of timestamp-only updates: the last issuance of clients and the last sessions
+//! of users.
+//!
+//! Updates are queued in a bounded channel and written once per interval, updates of the same
+//! document in between are coalesced into one write. They may be lost: updates are dropped
+//! while the queue is full and pending ones end with the process, in exchange requests don't
+//! wait for them.
+
+use super::Database;
+
+use crate::{metrics::Metrics, user::SessionDocument};
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use mongodb::bson::oid::ObjectId;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+/// Updates waiting for the next flush, beyond that they are dropped
+const QUEUE_SIZE: usize = 10_000;
+
+/// Writes running at the same time during a flush
+const CONCURRENCY: usize = 16;
+
+/// Last sessions kept per user
+pub const MAX_SESSIONS: usize = 5;
+
+#[derive(Debug)]
+enum Update {
+    ClientIssued(ObjectId, DateTime<Utc>),
+    UserSession(ObjectId, SessionDocument),
+}
+
+/// Updates since the last flush, coalesced by document
+#[derive(Debug, Default)]
+struct Pending {
+    clients: HashMap<ObjectId, DateTime<Utc>>,
+    sessions: HashMap<ObjectId, Vec<SessionDocument>>,
+    /// Arrival of the oldest update, for the lag of the flush
+    since: Option<Instant>,
+}
+
+impl Pending {
+    fn add(&mut self, update: Update) {
+        self.since.get_or_insert_with(Instant::now);
+
+        match update {
+            Update::ClientIssued(id, date) => {
+                let last = self.clients.entry(id).or_insert(date);
+                *last = date.max(*last);
+            }
+            Update::UserSession(id, session) => {
+                let sessions = self.sessions.entry(id).or_default();
+                sessions.push(session);
+                if sessions.len() > MAX_SESSIONS {
+                    sessions.remove(0);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timestamps {
+    tx: mpsc::Sender<Update>,
+    metrics: Metrics,
+}
+
+impl Timestamps {
+    /// Starts the flushes to the database, which must not write behind itself
+    pub(super) fn spawn(db: Database, interval: Duration, metrics: Metrics) -> Self {
+        let (tx, mut rx) = mpsc::channel(QUEUE_SIZE);
+
+        let flush_metrics = metrics.clone();
+        crate::utils::spawn_named("timestamp-flush", async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut pending = Pending::default();
+
+            loop {
+                tokio::select! {
+                    update = rx.recv() => match update {
+                        Some(update) => pending.add(update),
+                        // The database is gone, the last updates are written once more
+                        None => break,
+                    },
+                    _ = interval.tick() => {
+                        flush(&db, std::mem::take(&mut pending), &flush_metrics).await;
+                    }
+                }
+            }
+            flush(&db, pending, &flush_metrics).await;
+        });
+
+        Self { tx, metrics }
+    }
+
+    fn push(&self, update: Update) {
+        match self.tx.try_send(update) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.metrics.record_timestamp_update("dropped"),
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    pub fn client_issued(&self, id: ObjectId, date: DateTime<Utc>) {
+        self.push(Update::ClientIssued(id, date));
+    }
+
+    pub fn user_session(&self, id: ObjectId, session: SessionDocument) {
+        self.push(Update::UserSession(id, session));
+    }
+}
+
+async fn flush(db: &Database, pending: Pending, metrics: &Metrics) {
+    let since = match pending.since {
+        Some(since) => since,
+        None => return,
+    };
+
+    let clients = pending
+        .clients
+        .into_iter()
+        .map(|(id, date)| async move { db.advance_client_issued(id, date).await });
+    let sessions = pending
+        .sessions
+        .into_iter()
+        .map(|(id, sessions)| async move { db.push_user_sessions(id, sessions).await });
+
+    let results: Vec<_> = stream::iter(clients)
+        .buffer_unordered(CONCURRENCY)
+        .chain(stream::iter(sessions).buffer_unordered(CONCURRENCY))
+        .collect()
+        .await;
+
+    for result in results {
+        match result {
+            Ok(()) => metrics.record_timestamp_update("written"),
+            Err(e) => {
+                warn!(error = %e, "timestamp update failed");
+                metrics.record_timestamp_update("failed");
+            }
+        }
+    }
+    metrics.record_timestamp_flush(since.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn coalesces_updates() {
+        let client = ObjectId::new();
+        let user = ObjectId::new();
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+
+        let mut pending = Pending::default();
+
+        pending.add(Update::ClientIssued(client, now));
+        pending.add(Update::ClientIssued(client, now - Duration::seconds(1)));
+        for i in 0..=MAX_SESSIONS {
+            let session = SessionDocument {
+                date: now + Duration::seconds(i as i64),
+                region: None,
+            };
+            pending.add(Update::UserSession(user, session));
+        }
+
+        assert!(pending.since.is_some());
+        assert_eq!(pending.clients[&client], now);
+        let sessions = &pending.sessions[&user];
+        assert_eq!(sessions.len(), MAX_SESSIONS);
+        assert_eq!(sessions[0].date, now + Duration::seconds(1));
+    }
+}
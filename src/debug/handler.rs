@@ -0,0 +1,48 @@
+use crate::{
+    authentication::AuthenticationError, extract::TokenData, model::Response,
+    session::SessionClaims,
+};
+
+use super::HeapStats;
+
+pub async fn heap(
+    TokenData(claims): TokenData<SessionClaims>,
+) -> crate::Result<Response<HeapStats>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    Ok(Response::new(HeapStats::read()?))
+}
+
+pub async fn heap_stats(TokenData(claims): TokenData<SessionClaims>) -> crate::Result<String> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    Ok(super::stats_report())
+}
+
+#[cfg(feature = "heap-profiling")]
+pub async fn heap_profile(TokenData(claims): TokenData<SessionClaims>) -> crate::Result<Vec<u8>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let profile = tokio::task::spawn_blocking(super::dump_profile)
+        .await
+        .expect("heap profile task panicked")?;
+
+    Ok(profile)
+}
+
+#[cfg(tokio_unstable)]
+pub async fn runtime(
+    TokenData(claims): TokenData<SessionClaims>,
+) -> crate::Result<Response<super::RuntimeStats>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    Ok(Response::new(super::RuntimeStats::read()))
+}
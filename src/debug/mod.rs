@@ -0,0 +1,159 @@
+//! Allocator and runtime diagnostics to debug a running instance
+
+mod handler;
+mod routes;
+#[cfg(tokio_unstable)]
+mod runtime;
+
+use std::{
+    ffi::c_void,
+    mem,
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use serde::Serialize;
+
+pub use routes::routes;
+#[cfg(tokio_unstable)]
+pub use runtime::RuntimeStats;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DebugError {
+    #[error("allocator control \"{name}\" failed with code {code}")]
+    Mallctl { name: &'static str, code: c_int },
+    #[cfg(feature = "heap-profiling")]
+    #[error("heap profile could not be read: {0}")]
+    Profile(#[from] std::io::Error),
+}
+
+/// Reads a value of the allocator, the name has to be NUL terminated
+fn read<T: Copy + Default>(name: &'static str) -> Result<T, DebugError> {
+    let mut value = T::default();
+    let mut len = mem::size_of::<T>();
+
+    let code = unsafe {
+        jemalloc_sys::mallctl(
+            name.as_ptr() as *const c_char,
+            &mut value as *mut T as *mut c_void,
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+
+    match code {
+        0 => Ok(value),
+        code => Err(DebugError::Mallctl {
+            name: name.trim_end_matches('\0'),
+            code,
+        }),
+    }
+}
+
+/// Writes a value of the allocator, the name has to be NUL terminated
+fn write<T>(name: &'static str, mut value: T) -> Result<(), DebugError> {
+    let code = unsafe {
+        jemalloc_sys::mallctl(
+            name.as_ptr() as *const c_char,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut value as *mut T as *mut c_void,
+            mem::size_of::<T>(),
+        )
+    };
+
+    match code {
+        0 => Ok(()),
+        code => Err(DebugError::Mallctl {
+            name: name.trim_end_matches('\0'),
+            code,
+        }),
+    }
+}
+
+/// Heap statistics in bytes, as described in the jemalloc manual
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HeapStats {
+    pub allocated: usize,
+    pub active: usize,
+    pub metadata: usize,
+    pub resident: usize,
+    pub mapped: usize,
+    pub retained: usize,
+}
+
+impl HeapStats {
+    pub fn read() -> Result<Self, DebugError> {
+        // Statistics are cached until the epoch is advanced
+        write("epoch\0", 1u64)?;
+
+        Ok(Self {
+            allocated: read("stats.allocated\0")?,
+            active: read("stats.active\0")?,
+            metadata: read("stats.metadata\0")?,
+            resident: read("stats.resident\0")?,
+            mapped: read("stats.mapped\0")?,
+            retained: read("stats.retained\0")?,
+        })
+    }
+}
+
+/// Returns the detailed human readable statistics of the allocator
+pub fn stats_report() -> String {
+    extern "C" fn append(out: *mut c_void, msg: *const c_char) {
+        let out = unsafe { &mut *(out as *mut String) };
+        let msg = unsafe { std::ffi::CStr::from_ptr(msg) };
+
+        out.push_str(&msg.to_string_lossy());
+    }
+
+    let mut report = String::new();
+
+    unsafe {
+        jemalloc_sys::malloc_stats_print(
+            Some(append),
+            &mut report as *mut String as *mut c_void,
+            ptr::null(),
+        )
+    };
+
+    report
+}
+
+/// Dumps a heap profile in the format of `jeprof`.
+///
+/// Profiling has to be enabled at startup with `_RJEM_MALLOC_CONF=prof:true`.
+#[cfg(feature = "heap-profiling")]
+pub fn dump_profile() -> Result<Vec<u8>, DebugError> {
+    use std::ffi::CString;
+
+    let path = std::env::temp_dir().join(format!(
+        "identity-heap-{}-{}.prof",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos()
+    ));
+    let c_path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+
+    write("prof.dump\0", c_path.as_ptr())?;
+
+    let profile = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_heap_stats() {
+        let stats = HeapStats::read().unwrap();
+
+        assert!(stats.allocated > 0);
+        assert!(stats.resident >= stats.active);
+        assert!(!stats_report().is_empty());
+    }
+}
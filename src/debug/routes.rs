@@ -0,0 +1,18 @@
+use super::handler;
+
+use axum::routing::get;
+
+/// Debug routes
+pub fn routes() -> axum::Router {
+    let router = axum::Router::new()
+        .route("/heap", get(handler::heap))
+        .route("/heap/stats", get(handler::heap_stats));
+
+    #[cfg(feature = "heap-profiling")]
+    let router = router.route("/heap/profile", get(handler::heap_profile));
+
+    #[cfg(tokio_unstable)]
+    let router = router.route("/runtime", get(handler::runtime));
+
+    router
+}
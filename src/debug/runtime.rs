@@ -0,0 +1,50 @@
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+/// Counters of a runtime worker since the start of the runtime
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerStats {
+    pub busy_ms: u128,
+    pub polls: u64,
+    pub steals: u64,
+    pub parks: u64,
+    pub noops: u64,
+    pub local_schedules: u64,
+    pub overflows: u64,
+    pub local_queue_depth: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeStats {
+    pub workers: Vec<WorkerStats>,
+    pub remote_schedules: u64,
+    pub injection_queue_depth: usize,
+}
+
+impl RuntimeStats {
+    /// Reads the metrics of the current runtime
+    pub fn read() -> Self {
+        let metrics = Handle::current().metrics();
+
+        let workers = (0..metrics.num_workers())
+            .map(|i| WorkerStats {
+                busy_ms: metrics.worker_total_busy_duration(i).as_millis(),
+                polls: metrics.worker_poll_count(i),
+                steals: metrics.worker_steal_count(i),
+                parks: metrics.worker_park_count(i),
+                noops: metrics.worker_noop_count(i),
+                local_schedules: metrics.worker_local_schedule_count(i),
+                overflows: metrics.worker_overflow_count(i),
+                local_queue_depth: metrics.worker_local_queue_depth(i),
+            })
+            .collect();
+
+        Self {
+            workers,
+            remote_schedules: metrics.remote_schedule_count(),
+            injection_queue_depth: metrics.injection_queue_depth(),
+        }
+    }
+}
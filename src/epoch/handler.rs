@@ -0,0 +1,78 @@
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    authentication::{token::TokenConfig, AuthenticationError},
+    database::Database,
+    extract::{ClientInfo, TokenData},
+    model::Response,
+    session::SessionClaims,
+    user::UserError,
+};
+
+use super::{EpochResponse, EpochStore};
+
+use axum::extract::{Extension, Path};
+use mongodb::bson::oid::ObjectId;
+
+pub async fn get(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(store): Extension<EpochStore>,
+) -> crate::Result<Response<EpochResponse>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    Ok(Response::new(EpochResponse {
+        epoch: store.current(),
+    }))
+}
+
+/// Invalidates all session and client tokens of the deployment, including the one of the request
+pub async fn bump(
+    client: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(store): Extension<EpochStore>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<EpochResponse>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+    let admin = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let epoch = store.bump(config.clock.now()).await?;
+
+    let event = SecurityEvent::TokenEpochBumped {
+        epoch,
+        deployment: true,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(admin, event, &client))
+        .await?;
+
+    Ok(Response::new(EpochResponse { epoch }))
+}
+
+/// Invalidates all session tokens of the user
+pub async fn bump_user(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<EpochResponse>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.bump_user_token_epoch(id).await?;
+
+    let event = SecurityEvent::TokenEpochBumped {
+        epoch: user.token_epoch,
+        deployment: false,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(id, event, &client))
+        .await?;
+
+    Ok(Response::new(EpochResponse {
+        epoch: user.token_epoch,
+    }))
+}
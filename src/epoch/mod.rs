@@ -0,0 +1,183 @@
+//! Emergency invalidation of all tokens, without rotating the signing keys.
+//!
+//! Session and client tokens carry the token epoch of the deployment they were issued in,
+//! session tokens also the epoch of their user. Bumping an epoch invalidates all tokens issued
+//! before and revokes the refresh tokens, in the databases of all realms too. Every instance caches the epoch of the deployment and
+//! reloads it periodically, so the other instances follow a bump within [`INTERVAL`]. Epochs of
+//! users are read along with the user.
+
+mod handler;
+mod routes;
+
+use crate::{
+    database::{Database, Realms},
+    utils, Result,
+};
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+pub use routes::routes;
+
+/// Interval in which the epoch of the deployment is reloaded
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Cached token epoch of the deployment, shared by all clones
+#[derive(Debug, Clone, Default)]
+pub struct TokenEpoch(Arc<AtomicU32>);
+
+impl TokenEpoch {
+    pub fn current(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Epochs only grow, so that a stale read doesn't undo a bump
+    fn update(&self, epoch: u32) {
+        self.0.fetch_max(epoch, Ordering::Relaxed);
+    }
+
+    /// Checks if tokens issued in the epoch are still valid
+    pub fn is_valid(&self, epoch: u32) -> bool {
+        epoch >= self.current()
+    }
+}
+
+/// Tokens issued before any bump carry no epoch
+pub fn is_initial(epoch: &u32) -> bool {
+    *epoch == 0
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EpochDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    epoch: u32,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    bumped_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EpochResponse {
+    pub epoch: u32,
+}
+
+const COLLECTION: &str = "token_epochs";
+
+/// ID of the epoch document of the deployment
+const DEPLOYMENT: &str = "deployment";
+
+impl Database {
+    async fn get_token_epoch(&self) -> Result<u32> {
+        let epoch = self
+            .collection::<EpochDocument>(COLLECTION)
+            .find_one(doc! { "_id": DEPLOYMENT }, None)
+            .await?
+            .map_or(0, |d| d.epoch);
+
+        Ok(epoch)
+    }
+
+    async fn bump_token_epoch(&self, now: DateTime<Utc>) -> Result<u32> {
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let doc = self
+            .collection::<EpochDocument>(COLLECTION)
+            .find_one_and_update(
+                doc! { "_id": DEPLOYMENT },
+                doc! { "$inc": { "epoch": 1 }, "$set": { "bumpedAt": now } },
+                opts,
+            )
+            .await?
+            .expect("epoch is upserted");
+
+        Ok(doc.epoch)
+    }
+}
+
+/// Epoch of the deployment, stored in the database of the deployment even with realms
+#[derive(Clone)]
+pub struct EpochStore {
+    db: Database,
+    realms: Realms,
+    epoch: TokenEpoch,
+}
+
+impl EpochStore {
+    /// Loads the current epoch into the cache of the token config
+    pub async fn load(db: Database, realms: Realms, epoch: TokenEpoch) -> Result<Self> {
+        epoch.update(db.get_token_epoch().await?);
+
+        Ok(Self { db, realms, epoch })
+    }
+
+    pub fn current(&self) -> u32 {
+        self.epoch.current()
+    }
+
+    /// Invalidates all tokens of the deployment, effective on this instance immediately.
+    ///
+    /// Refresh tokens don't carry an epoch, so they are revoked in every database.
+    pub async fn bump(&self, now: DateTime<Utc>) -> Result<u32> {
+        let epoch = self.db.bump_token_epoch(now).await?;
+        self.epoch.update(epoch);
+
+        for db in std::iter::once(&self.db).chain(self.realms.databases()) {
+            db.revoke_all_refresh_tokens().await?;
+            db.end_all_sessions().await?;
+        }
+        info!(epoch, "token epoch bumped");
+
+        Ok(epoch)
+    }
+
+    /// Reloads the epoch periodically, to pick up bumps of other instances
+    pub fn spawn_refresh(self) {
+        utils::spawn_named("token-epoch-refresh", async move {
+            let mut interval = tokio::time::interval(INTERVAL);
+            // The epoch was loaded at startup
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                match self.db.get_token_epoch().await {
+                    Ok(epoch) => self.epoch.update(epoch),
+                    Err(e) => error!(error = %e, "token epoch could not be reloaded"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidates_older_epochs() {
+        let epoch = TokenEpoch::default();
+        assert!(epoch.is_valid(0));
+
+        epoch.clone().update(2);
+        assert!(!epoch.is_valid(1));
+        assert!(epoch.is_valid(2));
+
+        // A stale read keeps the bump
+        epoch.update(1);
+        assert_eq!(epoch.current(), 2);
+    }
+}
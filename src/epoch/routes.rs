@@ -0,0 +1,10 @@
+use super::handler;
+
+use axum::routing::{get, post};
+
+/// Token epoch routes
+pub fn routes() -> axum::Router {
+    axum::Router::new()
+        .route("/", get(handler::get).post(handler::bump))
+        .route("/user/:id", post(handler::bump_user))
+}
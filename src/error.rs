@@ -1,13 +1,19 @@
 use crate::{
     action::ActionError,
     authentication::{token::TokenError as AuthTokenError, AuthenticationError},
+    bulk::BulkError,
     client::ClientError,
+    database::QuotaError,
+    label::LabelError,
+    mail::MailError,
     model::Status,
+    oauth::OAuthError,
+    revision::RevisionError,
     service::ServiceError,
     session::SessionError,
     sso::SsoError,
     token::TokenError,
-    user::UserError,
+    user::{ApprovalError, MfaError, UserError},
     utils::crypto::CryptoError,
 };
 
@@ -29,20 +35,50 @@ pub enum Error {
     Client(#[from] ClientError),
     #[error("service error: {0}")]
     Service(#[from] ServiceError),
+    #[error("label error: {0}")]
+    Label(#[from] LabelError),
+    #[error("revision error: {0}")]
+    Revision(#[from] RevisionError),
     #[error("session error: {0}")]
     Session(#[from] SessionError),
+    #[error("mfa error: {0}")]
+    Mfa(#[from] MfaError),
+    #[error("approval error: {0}")]
+    Approval(#[from] ApprovalError),
     #[error("MongoDB error: {0}")]
     Action(#[from] ActionError),
     #[error("action error: {0}")]
     Token(#[from] TokenError),
     #[error("sso error: {0}")]
     Sso(#[from] SsoError),
+    #[error("OAuth error: {0}")]
+    OAuth(#[from] OAuthError),
+    #[error("bulk revocation error: {0}")]
+    Bulk(#[from] BulkError),
+    #[cfg(feature = "federation")]
+    #[error("federation error: {0}")]
+    Federation(#[from] crate::federation::FederationError),
+    #[error("mail error: {0}")]
+    Mail(#[from] MailError),
     #[error("Http error: {0}")]
     Http(#[from] http::Error),
     #[error("crypto error: {0}")]
     Crypto(#[from] CryptoError),
     #[error("database error: {0}")]
     Database(#[from] mongodb::error::Error),
+    #[cfg(feature = "cache")]
+    #[error("cache error: {0}")]
+    Cache(#[from] crate::cache::CacheError),
+    #[error("quota error: {0}")]
+    Quota(#[from] QuotaError),
+    #[cfg(feature = "hooks")]
+    #[error("vetoed by hook: {0}")]
+    Hook(#[from] crate::hooks::Veto),
+    #[cfg(feature = "debug-endpoints")]
+    #[error("debug error: {0}")]
+    Debug(#[from] crate::debug::DebugError),
+    #[error("configuration error: {0}")]
+    Config(String),
     #[error("Envy error: {0}")]
     Envy(#[from] envy::Error),
     #[error("Reqwest error: {0}")]
@@ -59,11 +95,24 @@ impl axum::response::IntoResponse for Error {
             Error::User(e) => e.error_response(),
             Error::Client(e) => e.error_response(),
             Error::Session(e) => e.error_response(),
+            // Challenges have a body of their own
+            Error::Mfa(e) => return e.error_response(),
+            Error::Approval(e) => e.error_response(),
             Error::Service(e) => e.error_response(),
+            Error::Label(e) => e.error_response(),
+            Error::Revision(e) => e.error_response(),
             Error::Action(e) => e.error_response(),
             Error::Token(e) => e.error_response(),
             Error::Sso(e) => e.error_response(),
+            // Errors of RFC 6749 have a body of their own
+            Error::OAuth(e) => return e.error_response(),
+            Error::Bulk(e) => e.error_response(),
+            #[cfg(feature = "federation")]
+            Error::Federation(e) => e.error_response(),
             Error::AuthToken(e) => e.error_response(),
+            Error::Quota(e) => e.error_response(),
+            #[cfg(feature = "hooks")]
+            Error::Hook(e) => e.error_response(),
             _ => {
                 error!(error = %self, "internal error");
                 Status::new(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
@@ -3,11 +3,13 @@ use crate::{
         token::{TokenClaims, TokenConfig, TokenError},
         AuthenticationError,
     },
+    database::Database,
     error::Error,
     model::Status,
+    revision, timing,
 };
 
-use std::{borrow::Cow, net};
+use std::{borrow::Cow, convert::Infallible, net};
 
 use axum::{
     async_trait,
@@ -19,7 +21,7 @@ use axum::{
     BoxError,
 };
 use headers::{authorization::Bearer, Authorization};
-use hyper::StatusCode;
+use hyper::{header::USER_AGENT, StatusCode};
 use serde::de::DeserializeOwned;
 
 const CONTENT_LENGTH_LIMIT: u64 = 2048;
@@ -170,12 +172,35 @@ where
                     AuthenticationError::InvalidHeader("authorization header missing".to_string())
                 })?;
 
-        let token_data =
-            jsonwebtoken::decode::<T>(bearer.token(), &config.dec_key, &config.validation)
-                .map_err(|e| AuthenticationError::from(TokenError::from(e)))?;
+        let Extension(db) = Extension::<Database>::from_request(req)
+            .await
+            .expect("database missing");
+
+        let client = ClientInfo::from_request(req).await.unwrap();
+
+        let result = async {
+            let token_data = config
+                .decode::<T>(bearer.token())
+                .map_err(AuthenticationError::from)?;
+
+            if token_data.claims.get_type() != &T::TOKEN_TYPE {
+                return Err(AuthenticationError::from(TokenError::WrongType).into());
+            }
+
+            token_data.claims.validate(&db, &config, &client).await?;
 
-        if token_data.claims.get_type() != &T::TOKEN_TYPE {
-            return Err(AuthenticationError::from(TokenError::WrongType).into());
+            Ok::<_, Error>(token_data)
+        }
+        .await;
+        config.record_validation(bearer.token(), result.is_ok());
+        let token_data = result?;
+
+        if let Some(sub) = token_data.claims.subject() {
+            timing::record_subject(sub);
+            revision::record_actor(sub);
+        }
+        if let Some(region) = config.issuer_region(bearer.token()) {
+            timing::record_region(&region);
         }
 
         Ok(Self(token_data.claims))
@@ -257,3 +282,33 @@ where
         }
     }
 }
+
+/// Information about the client of the request, used for auditing
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub addr: Option<net::IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    const USER_AGENT_MAX_LEN: usize = 256;
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for ClientInfo
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let addr = RemoteAddr::from_request(req).await.ok().map(|v| v.0);
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(Self::USER_AGENT_MAX_LEN).collect());
+
+        Ok(Self { addr, user_agent })
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    authentication::jwks::{self, JwksCache},
+    error,
+    http::HttpClient,
+    model::Status,
+    user::Connection,
+    Result,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+use hyper::StatusCode;
+use jsonwebtoken::{Algorithm, Validation};
+use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FederationError {
+    #[error("issuer is not trusted")]
+    UntrustedIssuer,
+    #[error("claim \"{0}\" is missing or invalid")]
+    InvalidClaim(String),
+    #[error("email address is not verified")]
+    EmailNotVerified,
+}
+
+impl error::ErrorResponse for FederationError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FederationError::UntrustedIssuer => StatusCode::UNAUTHORIZED,
+            FederationError::InvalidClaim(_) | FederationError::EmailNotVerified => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Configuration of a trusted external issuer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuerConfig {
+    pub issuer: String,
+    pub audience: Vec<String>,
+    /// Discovered via the OpenID configuration of the issuer if not set
+    pub jwks_uri: Option<Url>,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+/// Names of the claims used to map an external identity to a user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClaimMapping {
+    pub subject: String,
+    pub email: String,
+    /// The email address is trusted as verified if not set
+    pub email_verified: Option<String>,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: Some("email_verified".to_string()),
+        }
+    }
+}
+
+impl ClaimMapping {
+    /// Maps the claims of a validated token to the identity of the user
+    fn identity(&self, issuer: String, claims: &Map<String, Value>) -> Result<Identity> {
+        let get_str = |name: &str| {
+            claims
+                .get(name)
+                .and_then(Value::as_str)
+                .map(ToString::to_string)
+                .ok_or_else(|| FederationError::InvalidClaim(name.to_string()))
+        };
+
+        let subject = get_str(&self.subject)?;
+        let email = get_str(&self.email)?;
+
+        if let Some(name) = &self.email_verified {
+            if claims.get(name).and_then(Value::as_bool) != Some(true) {
+                return Err(FederationError::EmailNotVerified.into());
+            }
+        }
+
+        let connection = Connection::Federated { issuer, subject };
+
+        Ok(Identity { email, connection })
+    }
+}
+
+/// Deserializes the issuer configurations from a JSON string
+pub fn de_issuers<'de, D>(d: D) -> std::result::Result<Vec<IssuerConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    serde_json::from_str(&input).map_err(serde::de::Error::custom)
+}
+
+/// Identity of an external user
+#[derive(Debug)]
+pub struct Identity {
+    pub email: String,
+    pub connection: Connection,
+}
+
+struct Issuer {
+    claims: ClaimMapping,
+    keys: JwksCache,
+    validation: Validation,
+}
+
+#[derive(Clone, Default)]
+pub struct Federation {
+    issuers: Arc<HashMap<String, Issuer>>,
+}
+
+impl Federation {
+    const ALGORITHMS: [Algorithm; 7] = [
+        Algorithm::RS256,
+        Algorithm::RS384,
+        Algorithm::RS512,
+        Algorithm::PS256,
+        Algorithm::PS384,
+        Algorithm::ES256,
+        Algorithm::ES384,
+    ];
+
+    const LEEWAY: u64 = 10;
+
+    pub async fn new(configs: Vec<IssuerConfig>, client: HttpClient) -> Result<Self> {
+        let mut issuers = HashMap::with_capacity(configs.len());
+
+        for config in configs {
+            let uri = match config.jwks_uri {
+                Some(uri) => uri,
+                None => jwks::discover_uri(&config.issuer, &client).await?,
+            };
+
+            let keys = JwksCache::new(uri, client.clone());
+            keys.spawn_refresh(JwksCache::REFRESH_INTERVAL);
+
+            let mut validation = Validation::default();
+            validation.algorithms = Self::ALGORITHMS.to_vec();
+            validation.leeway = Self::LEEWAY;
+            validation.set_audience(&config.audience);
+            validation.set_issuer(&[&config.issuer]);
+
+            let issuer = Issuer {
+                claims: config.claims,
+                keys,
+                validation,
+            };
+
+            issuers.insert(config.issuer, issuer);
+        }
+
+        Ok(Self {
+            issuers: Arc::new(issuers),
+        })
+    }
+
+    /// Validates an external token and returns the identity it represents
+    pub async fn verify(&self, token: &str) -> Result<Identity> {
+        let iss = jwks::peek_issuer(token)?;
+        let issuer = self
+            .issuers
+            .get(&iss)
+            .ok_or(FederationError::UntrustedIssuer)?;
+
+        let claims = issuer
+            .keys
+            .decode::<Map<String, Value>>(token, &issuer.validation)
+            .await?;
+
+        issuer.claims.identity(iss, &claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::Error;
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://login.example.com";
+
+    fn to_map(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn maps_custom_claim_names() {
+        let mapping: ClaimMapping =
+            serde_json::from_str(r#"{"subject": "oid", "email": "upn", "emailVerified": null}"#)
+                .unwrap();
+        let claims = to_map(json!({ "sub": "ignored", "oid": "abc", "upn": "user@example.com" }));
+
+        let identity = mapping.identity(ISSUER.to_string(), &claims).unwrap();
+
+        assert_eq!(identity.email, "user@example.com");
+        assert_eq!(
+            identity.connection,
+            Connection::Federated {
+                issuer: ISSUER.to_string(),
+                subject: "abc".to_string(),
+            }
+        );
+
+        let claims = to_map(json!({ "sub": "abc", "upn": "user@example.com" }));
+        assert!(matches!(
+            mapping.identity(ISSUER.to_string(), &claims),
+            Err(Error::Federation(FederationError::InvalidClaim(name))) if name == "oid"
+        ));
+    }
+
+    #[test]
+    fn keeps_default_claim_names() {
+        let mapping: ClaimMapping = serde_json::from_str(r#"{"subject": "oid"}"#).unwrap();
+
+        assert_eq!(mapping.subject, "oid");
+        assert_eq!(mapping.email, "email");
+        assert_eq!(mapping.email_verified.as_deref(), Some("email_verified"));
+    }
+
+    #[test]
+    fn rejects_unverified_emails() {
+        let mapping = ClaimMapping::default();
+
+        for verified in [json!(false), json!("true"), Value::Null] {
+            let claims = to_map(json!({
+                "sub": "abc",
+                "email": "user@example.com",
+                "email_verified": verified,
+            }));
+
+            assert!(matches!(
+                mapping.identity(ISSUER.to_string(), &claims),
+                Err(Error::Federation(FederationError::EmailNotVerified))
+            ));
+        }
+
+        let claims = to_map(json!({ "sub": "abc", "email": "user@example.com" }));
+        assert!(matches!(
+            mapping.identity(ISSUER.to_string(), &claims),
+            Err(Error::Federation(FederationError::EmailNotVerified))
+        ));
+
+        let claims = to_map(json!({
+            "sub": "abc",
+            "email": "user@example.com",
+            "email_verified": true,
+        }));
+        assert!(mapping.identity(ISSUER.to_string(), &claims).is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_untrusted_issuers() {
+        let token = jsonwebtoken::encode(
+            &Header::default(),
+            &json!({ "iss": "https://untrusted.example.com", "sub": "abc" }),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Federation::default().verify(&token).await,
+            Err(Error::Federation(FederationError::UntrustedIssuer))
+        ));
+    }
+}
@@ -0,0 +1,23 @@
+use super::{Probes, Readiness, State};
+
+use crate::model::Response;
+
+use axum::extract::Extension;
+use http::StatusCode;
+use serde_json::{json, Value};
+
+/// Liveness, doesn't depend on anything but the server
+pub async fn live() -> Response<Value> {
+    Response::new(json!({ "status": State::Up }))
+}
+
+/// Readiness, unavailable while a dependency is down
+pub async fn ready(Extension(probes): Extension<Probes>) -> Response<Readiness> {
+    let readiness = probes.readiness().await;
+    let status = match readiness.status {
+        State::Up => StatusCode::OK,
+        State::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    Response::with_status(status, readiness)
+}
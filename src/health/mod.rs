@@ -0,0 +1,168 @@
+//! Probes for orchestrators like Kubernetes: `/healthz` answers as long as the server runs,
+//! `/readyz` only while the databases and, if configured, Redis and the SMTP server are
+//! reachable. Dependencies are checked at the same time, each within [`CHECK_TIMEOUT`].
+
+mod handler;
+mod routes;
+
+use crate::database::Database;
+#[cfg(feature = "mail-smtp")]
+use crate::mail::Smtp;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use serde::Serialize;
+
+pub use routes::routes;
+
+/// Time a dependency has to answer before it counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Up,
+    Down,
+}
+
+/// Result of the check of a dependency
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub status: State,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    async fn run<F, E>(check: F) -> Self
+    where
+        F: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(_) => Err("timed out".to_string()),
+        };
+
+        Self {
+            status: if result.is_ok() {
+                State::Up
+            } else {
+                State::Down
+            },
+            latency_ms: start.elapsed().as_millis() as u64,
+            error: result.err(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub status: State,
+    /// Checks by dependency, e.g. `mongo`, `mongo:<realm>`, `redis` or `smtp`
+    pub checks: BTreeMap<String, Check>,
+}
+
+impl Readiness {
+    fn new(checks: BTreeMap<String, Check>) -> Self {
+        let status = if checks.values().all(|c| c.status == State::Up) {
+            State::Up
+        } else {
+            State::Down
+        };
+
+        Self { status, checks }
+    }
+}
+
+type Pending<'a> = Pin<Box<dyn Future<Output = Check> + Send + 'a>>;
+
+/// Dependencies that have to be reachable to serve requests
+#[derive(Debug, Clone)]
+pub struct Probes {
+    databases: Vec<Database>,
+    #[cfg(feature = "mail-smtp")]
+    smtp: Option<Smtp>,
+}
+
+impl Probes {
+    /// Probes of the default database and the ones of the realms
+    pub fn new<'a, I>(db: &'a Database, realms: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Database>,
+    {
+        let databases = std::iter::once(db).chain(realms).cloned().collect();
+
+        Self {
+            databases,
+            #[cfg(feature = "mail-smtp")]
+            smtp: None,
+        }
+    }
+
+    #[cfg(feature = "mail-smtp")]
+    pub fn set_smtp(&mut self, smtp: Smtp) {
+        self.smtp = Some(smtp);
+    }
+
+    pub async fn readiness(&self) -> Readiness {
+        let mut pending: Vec<(String, Pending<'_>)> = Vec::new();
+
+        for db in &self.databases {
+            let name = match db.realm() {
+                Some(realm) => format!("mongo:{}", realm),
+                None => "mongo".to_string(),
+            };
+            pending.push((name, Box::pin(Check::run(db.ping()))));
+        }
+        // Realms share the cache of the default database
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.databases.first().and_then(|db| db.cache()) {
+            pending.push(("redis".to_string(), Box::pin(Check::run(cache.ping()))));
+        }
+        #[cfg(feature = "mail-smtp")]
+        if let Some(smtp) = &self.smtp {
+            pending.push(("smtp".to_string(), Box::pin(Check::run(smtp.check()))));
+        }
+
+        let (names, checks): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+        let checks = join_all(checks).await;
+
+        Readiness::new(names.into_iter().zip(checks).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_failed_checks() {
+        let up = Check::run(async { Ok::<_, String>(()) }).await;
+        let down = Check::run(async { Err("connection refused") }).await;
+        assert_eq!(up.status, State::Up);
+        assert_eq!(down.error.as_deref(), Some("connection refused"));
+
+        let checks = BTreeMap::from([("mongo".to_string(), up.clone())]);
+        assert_eq!(Readiness::new(checks).status, State::Up);
+
+        let checks = BTreeMap::from([("mongo".to_string(), up), ("smtp".to_string(), down)]);
+        let readiness = Readiness::new(checks);
+        assert_eq!(readiness.status, State::Down);
+
+        let value = serde_json::to_value(&readiness).unwrap();
+        assert_eq!(value["status"], "down");
+        assert_eq!(value["checks"]["smtp"]["error"], "connection refused");
+        assert!(value["checks"]["mongo"].get("error").is_none());
+    }
+}
@@ -0,0 +1,12 @@
+use super::{handler, Probes};
+
+use axum::routing::get;
+use tower_http::add_extension::AddExtensionLayer;
+
+/// Liveness and readiness routes
+pub fn routes(probes: Probes) -> axum::Router {
+    axum::Router::new()
+        .route("/healthz", get(handler::live))
+        .route("/readyz", get(handler::ready))
+        .layer(AddExtensionLayer::new(probes))
+}
@@ -0,0 +1,200 @@
+//! Extension points for deployments that need custom claims or login rules without a fork.
+//!
+//! Hooks are registered statically before the server starts, every hook runs in the order of
+//! its registration:
+//!
+//! ```no_run
+//! use identity_server::hooks::{self, async_trait, Claims, Hook, Subject, Veto};
+//!
+//! struct Tenant;
+//!
+//! #[async_trait]
+//! impl Hook for Tenant {
+//!     async fn pre_issuance(&self, user: &Subject) -> Result<Claims, Veto> {
+//!         let mut claims = Claims::new();
+//!         let domain = user.email.rsplit('@').next().unwrap_or_default();
+//!         claims.insert("tenant".into(), domain.into());
+//!         Ok(claims)
+//!     }
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() -> identity_server::Result<()> {
+//!     hooks::register(Tenant);
+//!     identity_server::run().await
+//! }
+//! ```
+
+use crate::{error, model::Status, user::UserDocument};
+
+use std::sync::{Arc, RwLock};
+
+use hyper::StatusCode;
+use once_cell::sync::Lazy;
+use serde_json::{Map, Value};
+
+pub use axum::async_trait;
+
+static HOOKS: Lazy<RwLock<Vec<Arc<dyn Hook>>>> = Lazy::new(Default::default);
+
+/// Claims of the session token that hooks can't set
+const RESERVED_CLAIMS: [&str; 9] = [
+    "aud",
+    "exp",
+    "iat",
+    "sub",
+    "jti",
+    "scope",
+    "fallback",
+    "tokenType",
+    "sandbox",
+];
+
+/// Custom claims of a session token
+pub type Claims = Map<String, Value>;
+
+/// Rejection by a hook, the message is returned to the client
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct Veto(pub String);
+
+impl error::ErrorResponse for Veto {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// User a hook is called for
+#[derive(Debug, Clone)]
+pub struct Subject {
+    pub id: String,
+    pub email: String,
+    pub roles: Vec<String>,
+}
+
+impl From<&UserDocument> for Subject {
+    fn from(user: &UserDocument) -> Self {
+        let roles = serde_json::to_value(&user.roles)
+            .and_then(serde_json::from_value)
+            .unwrap_or_default();
+
+        Self {
+            id: user.id.to_hex(),
+            email: user.email.clone(),
+            roles,
+        }
+    }
+}
+
+#[async_trait]
+pub trait Hook: Send + Sync + 'static {
+    /// Custom claims of a session token that is about to be issued, reserved claims are ignored
+    async fn pre_issuance(&self, _user: &Subject) -> Result<Claims, Veto> {
+        Ok(Claims::new())
+    }
+
+    /// Called once the credentials of a login are verified, before the session is issued
+    async fn post_login(&self, _user: &Subject, _method: &str) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    /// Called before a user is stored, by registration or by a first SSO login
+    async fn pre_user_create(&self, _email: &str) -> Result<(), Veto> {
+        Ok(())
+    }
+
+    /// Called before the user authorizes a client of OAuth for the scope
+    async fn pre_consent(
+        &self,
+        _user: &Subject,
+        _client: &str,
+        _scope: &[String],
+    ) -> Result<(), Veto> {
+        Ok(())
+    }
+}
+
+/// Registers a hook, before the server is started
+pub fn register<H: Hook>(hook: H) {
+    HOOKS.write().unwrap().push(Arc::new(hook));
+}
+
+fn hooks() -> Vec<Arc<dyn Hook>> {
+    HOOKS.read().unwrap().clone()
+}
+
+/// Adds the claims of a hook, later hooks override earlier ones
+fn merge(claims: &mut Claims, custom: Claims) {
+    claims.extend(
+        custom
+            .into_iter()
+            .filter(|(k, _)| !RESERVED_CLAIMS.contains(&k.as_str())),
+    );
+}
+
+pub(crate) async fn pre_issuance(user: &UserDocument) -> Result<Claims, Veto> {
+    let subject = Subject::from(user);
+    let mut claims = Claims::new();
+
+    for hook in hooks() {
+        merge(&mut claims, hook.pre_issuance(&subject).await?);
+    }
+
+    Ok(claims)
+}
+
+pub(crate) async fn post_login(user: &UserDocument, method: &str) -> Result<(), Veto> {
+    let subject = Subject::from(user);
+
+    for hook in hooks() {
+        hook.post_login(&subject, method).await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn pre_user_create(email: &str) -> Result<(), Veto> {
+    for hook in hooks() {
+        hook.pre_user_create(email).await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn pre_consent(
+    user: &UserDocument,
+    client: &str,
+    scope: &[String],
+) -> Result<(), Veto> {
+    let subject = Subject::from(user);
+
+    for hook in hooks() {
+        hook.pre_consent(&subject, client, scope).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn ignores_reserved_claims() {
+        let mut claims = Claims::new();
+        let custom = json!({ "tenant": "eu", "sub": "admin", "scope": ["userWrite"] });
+
+        merge(&mut claims, custom.as_object().unwrap().clone());
+
+        assert_eq!(claims.len(), 1);
+        assert_eq!(claims["tenant"], "eu");
+    }
+}
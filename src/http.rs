@@ -1,6 +1,13 @@
-use std::{ops::Deref, time::Duration};
+use crate::{telemetry, timing};
 
-use reqwest::{redirect, tls};
+use std::{
+    ops::Deref,
+    time::{Duration, Instant},
+};
+
+use axum::async_trait;
+use reqwest::{redirect, tls, RequestBuilder, Response};
+use tracing::{field, Instrument};
 
 #[derive(Debug, Clone)]
 pub struct HttpClient(reqwest::Client);
@@ -21,19 +28,77 @@ impl Deref for HttpClient {
     }
 }
 
-impl Default for HttpClient {
-    fn default() -> Self {
-        let client = reqwest::Client::builder()
-            .https_only(true)
+impl HttpClient {
+    /// Client that also allows plain HTTP, only meant for local endpoints
+    pub fn allow_http() -> Self {
+        Self(Self::builder().build().unwrap())
+    }
+
+    fn builder() -> reqwest::ClientBuilder {
+        reqwest::Client::builder()
             .use_rustls_tls()
             .max_tls_version(tls::Version::TLS_1_2)
             .redirect(redirect::Policy::none())
             .tcp_keepalive(Self::KEEP_ALIVE_TIMEOUT)
             .timeout(Self::DEFAULT_TIMEOUT)
             .user_agent(Self::USER_AGENT)
-            .build()
-            .unwrap();
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        let client = Self::builder().https_only(true).build().unwrap();
 
         Self(client)
     }
 }
+
+#[async_trait]
+pub trait SendTimed {
+    /// Sends the request in a span of its own, records its duration for slow request traces
+    /// and passes the trace context on
+    async fn send_timed(self) -> reqwest::Result<Response>;
+}
+
+#[async_trait]
+impl SendTimed for RequestBuilder {
+    async fn send_timed(self) -> reqwest::Result<Response> {
+        // Fails the call with a real timeout error
+        #[cfg(feature = "chaos")]
+        let this = if crate::chaos::fail_upstream() {
+            self.timeout(Duration::from_nanos(1))
+        } else {
+            self
+        };
+        #[cfg(not(feature = "chaos"))]
+        let this = self;
+
+        let span = tracing::debug_span!(
+            "upstream request",
+            otel.kind = "client",
+            host = field::Empty,
+            status = field::Empty,
+        );
+        let this = match span.in_scope(telemetry::current_context) {
+            Some(ctx) => this.header(telemetry::TRACEPARENT, ctx.header()),
+            None => this,
+        };
+
+        let start = Instant::now();
+        let result = this.send().instrument(span.clone()).await;
+
+        let host = match &result {
+            Ok(res) => res.url().host_str(),
+            Err(e) => e.url().and_then(|u| u.host_str()),
+        };
+        let host = host.unwrap_or("unknown");
+        timing::record_upstream(host, start.elapsed());
+
+        span.record("host", &host);
+        if let Ok(res) = &result {
+            span.record("status", &res.status().as_u16());
+        }
+
+        result
+    }
+}
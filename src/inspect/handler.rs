@@ -0,0 +1,36 @@
+use crate::{
+    authentication::{token::TokenConfig, AuthenticationError},
+    database::Database,
+    extract::{SizedJson, TokenData},
+    model::Response,
+    session::SessionClaims,
+    utils::crypto::Aead256,
+};
+
+use super::{inspect, TokenReport};
+
+use axum::extract::Extension;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyRequest {
+    token: String,
+}
+
+/// Reports where a token diverges from the current state of its user, client and service
+pub async fn verify(
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<VerifyRequest>,
+    Extension(db): Extension<Database>,
+    Extension(enc): Extension<Aead256>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<TokenReport>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let report = inspect(&db, &config, &enc, &body.token).await?;
+
+    Ok(Response::new(report))
+}
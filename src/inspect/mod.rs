@@ -0,0 +1,416 @@
+//! Cross-check of an issued token against the current state of the database, to answer
+//! support cases like "why does this token still work".
+//!
+//! The token is decoded without checking its expiry or audience, then every claim that
+//! depends on stored state is compared with it. Each difference is reported as divergence.
+
+mod handler;
+mod routes;
+
+use crate::{
+    authentication::{
+        token::{TokenConfig, TokenError, TokenType},
+        AuthenticationError,
+    },
+    client::{ClientDocument, ClientError},
+    database::Database,
+    error::Error,
+    service::ServiceError,
+    session::{check_access, SessionError},
+    user::{UserDocument, UserError},
+    utils::crypto::Aead256,
+    Result,
+};
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+pub use routes::routes;
+
+/// Difference between a claim of the token and the database
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Divergence {
+    InvalidSignature,
+    Expired,
+    /// Revoked by its ID
+    TokenRevoked,
+    /// Issued before all sessions of the user were revoked
+    SessionsRevoked,
+    /// Issued before the token epoch of the deployment was bumped
+    EpochBumped,
+    /// Issued before the token epoch of the user was bumped
+    UserEpochBumped,
+    UserNotFound,
+    UserLocked,
+    UserBanned {
+        #[serde(with = "ts_seconds")]
+        until: DateTime<Utc>,
+    },
+    ClientNotFound,
+    ClientLocked,
+    LegacyFlowDisabled,
+    ServiceNotFound,
+    /// Scope of the token that is no longer granted
+    ScopeRevoked {
+        scope: Vec<String>,
+    },
+    /// Scope that is granted now but missing in the token
+    ScopeAdded {
+        scope: Vec<String>,
+    },
+    AudienceMismatch {
+        expected: Vec<String>,
+    },
+}
+
+/// Claims of any token, as far as they are checked
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnyClaims {
+    sub: String,
+    #[serde(default)]
+    aud: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    exp: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    iat: DateTime<Utc>,
+    jti: Option<String>,
+    azp: Option<String>,
+    #[serde(default)]
+    scope: Vec<String>,
+    #[serde(default)]
+    epoch: u32,
+    #[serde(default)]
+    user_epoch: u32,
+    /// Service tokens have none
+    token_type: Option<TokenType>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenReport {
+    /// Token type, `service` for tokens of a service
+    pub kind: String,
+    pub subject: String,
+    #[serde(with = "ts_seconds")]
+    pub issued_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+    /// Whether the token matches the database and would be accepted
+    pub valid: bool,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Scope of `token` missing in `current` and the other way round
+fn diff_scope(token: &[String], current: &[String]) -> (Vec<String>, Vec<String>) {
+    let revoked = token
+        .iter()
+        .filter(|s| !current.contains(s))
+        .cloned()
+        .collect();
+    let added = current
+        .iter()
+        .filter(|s| !token.contains(s))
+        .cloned()
+        .collect();
+
+    (revoked, added)
+}
+
+/// Serialized name of a unit variant, like a scope or a token type
+fn name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(v)) => v,
+        _ => String::new(),
+    }
+}
+
+/// Validation of the signature only, the other claims are compared with the database
+fn lenient(mut validation: Validation) -> Validation {
+    validation.validate_exp = false;
+    validation.aud = None;
+
+    validation
+}
+
+/// Checks the signature against the keys of the server
+fn signed_by_server(config: &TokenConfig, token: &str) -> bool {
+    config
+        .decode_with::<AnyClaims>(token, &lenient(config.validation.clone()))
+        .is_ok()
+}
+
+/// Reads the claims without validating them
+fn peek(token: &str) -> Result<AnyClaims> {
+    let mut validation = lenient(Validation::default());
+    validation.insecure_disable_signature_validation();
+
+    let data =
+        jsonwebtoken::decode::<AnyClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+            .map_err(|_| AuthenticationError::from(TokenError::Invalid))?;
+
+    Ok(data.claims)
+}
+
+/// Checks the token and reports every divergence from the database
+pub async fn inspect(
+    db: &Database,
+    config: &TokenConfig,
+    enc: &Aead256,
+    token: &str,
+) -> Result<TokenReport> {
+    let claims = peek(token)?;
+    let now = config.clock.now();
+    let default_aud = Vec::from_iter(config.validation.aud.clone().unwrap_or_default());
+
+    let mut divergences = Vec::new();
+    if claims.exp <= now {
+        divergences.push(Divergence::Expired);
+    }
+
+    let kind = match &claims.token_type {
+        Some(token_type) => {
+            if !signed_by_server(config, token) {
+                divergences.push(Divergence::InvalidSignature);
+            }
+            if claims.aud != default_aud {
+                divergences.push(Divergence::AudienceMismatch {
+                    expected: default_aud,
+                });
+            }
+
+            if matches!(token_type, TokenType::Session | TokenType::Client)
+                && !config.epoch.is_valid(claims.epoch)
+            {
+                divergences.push(Divergence::EpochBumped);
+            }
+
+            match token_type {
+                TokenType::Session => check_session(db, config, &claims, &mut divergences).await?,
+                TokenType::Client => {
+                    if let Some(client) = find_client(db, &claims.sub, &mut divergences).await? {
+                        if client.legacy_token_disabled {
+                            divergences.push(Divergence::LegacyFlowDisabled);
+                        }
+                    }
+                }
+                // Short-lived tokens of a single flow, only their signature is checked
+                _ => {}
+            }
+
+            name(token_type)
+        }
+        None => {
+            check_service(
+                db,
+                config,
+                enc,
+                token,
+                &claims,
+                &default_aud,
+                &mut divergences,
+            )
+            .await?;
+            "service".to_string()
+        }
+    };
+
+    Ok(TokenReport {
+        kind,
+        subject: claims.sub,
+        issued_at: claims.iat,
+        expires_at: claims.exp,
+        valid: divergences.is_empty(),
+        divergences,
+    })
+}
+
+async fn check_session(
+    db: &Database,
+    config: &TokenConfig,
+    claims: &AnyClaims,
+    divergences: &mut Vec<Divergence>,
+) -> Result<()> {
+    if let Some(jti) = &claims.jti {
+        if db.is_token_revoked(jti, config.clock.now()).await? {
+            divergences.push(Divergence::TokenRevoked);
+        }
+    }
+
+    let user = match check_user(db, config, &claims.sub, divergences).await? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    if matches!(user.sessions_valid_after, Some(v) if claims.iat <= v.to_chrono()) {
+        divergences.push(Divergence::SessionsRevoked);
+    }
+    if claims.user_epoch < user.token_epoch {
+        divergences.push(Divergence::UserEpochBumped);
+    }
+
+    let scope: Vec<String> = config
+        .session_limits
+        .scope(&user.roles)
+        .iter()
+        .map(name)
+        .collect();
+    let (revoked, added) = diff_scope(&claims.scope, &scope);
+    if !revoked.is_empty() {
+        divergences.push(Divergence::ScopeRevoked { scope: revoked });
+    }
+    if !added.is_empty() {
+        divergences.push(Divergence::ScopeAdded { scope: added });
+    }
+
+    Ok(())
+}
+
+async fn check_service(
+    db: &Database,
+    config: &TokenConfig,
+    enc: &Aead256,
+    token: &str,
+    claims: &AnyClaims,
+    default_aud: &[String],
+    divergences: &mut Vec<Divergence>,
+) -> Result<()> {
+    // Tokens of the authorization code grant name the client as authorized party
+    let client_id = claims.azp.as_deref().unwrap_or(&claims.sub);
+    let client = match find_client(db, client_id, divergences).await? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let svc = match db.get_service(doc! { "_id": client.service }).await {
+        Ok(v) => v,
+        Err(Error::Service(ServiceError::NotFound)) => {
+            divergences.push(Divergence::ServiceNotFound);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let signed = match &svc.secret {
+        Some(s) => {
+            let secret = enc.decrypt(base64::decode_config(s, base64::STANDARD).unwrap())?;
+            let validation = lenient(Validation::new(Algorithm::HS256));
+            jsonwebtoken::decode::<AnyClaims>(
+                token,
+                &DecodingKey::from_secret(&secret),
+                &validation,
+            )
+            .is_ok()
+        }
+        None => signed_by_server(config, token),
+    };
+    if !signed {
+        divergences.push(Divergence::InvalidSignature);
+    }
+
+    let audience = if svc.audience.is_empty() {
+        default_aud.to_vec()
+    } else {
+        svc.audience
+    };
+    if claims.aud != audience {
+        divergences.push(Divergence::AudienceMismatch { expected: audience });
+    }
+
+    let (revoked, added) = diff_scope(&claims.scope, &client.scope);
+    let revoked: Vec<_> = revoked
+        .into_iter()
+        .filter(|s| claims.azp.is_none() || s != "openid")
+        .collect();
+    if !revoked.is_empty() {
+        divergences.push(Divergence::ScopeRevoked { scope: revoked });
+    }
+    // Users authorize a part of the scope, only tokens of the client itself carry all of it
+    if claims.azp.is_none() && !added.is_empty() {
+        divergences.push(Divergence::ScopeAdded { scope: added });
+    }
+
+    if claims.azp.is_some() {
+        check_user(db, config, &claims.sub, divergences).await?;
+    }
+
+    Ok(())
+}
+
+/// Looks up the user of the token and checks that it may still log in
+async fn check_user(
+    db: &Database,
+    config: &TokenConfig,
+    id: &str,
+    divergences: &mut Vec<Divergence>,
+) -> Result<Option<UserDocument>> {
+    let user = match ObjectId::parse_str(id) {
+        Ok(id) => match db.get_user(doc! { "_id": id }).await {
+            Ok(v) => v,
+            Err(Error::User(UserError::NotFound)) => {
+                divergences.push(Divergence::UserNotFound);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        },
+        Err(_) => {
+            divergences.push(Divergence::UserNotFound);
+            return Ok(None);
+        }
+    };
+
+    match check_access(&user, config.clock.now()) {
+        Err(SessionError::Banned(until)) => divergences.push(Divergence::UserBanned { until }),
+        Err(_) => divergences.push(Divergence::UserLocked),
+        Ok(()) => {}
+    }
+
+    Ok(Some(user))
+}
+
+/// Looks up the client of the token and checks that it is unlocked
+async fn find_client(
+    db: &Database,
+    id: &str,
+    divergences: &mut Vec<Divergence>,
+) -> Result<Option<ClientDocument>> {
+    let client = match ObjectId::parse_str(id) {
+        Ok(id) => match db.get_client(doc! { "_id": id }).await {
+            Ok(v) => v,
+            Err(Error::Client(ClientError::NotFound)) => {
+                divergences.push(Divergence::ClientNotFound);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        },
+        Err(_) => {
+            divergences.push(Divergence::ClientNotFound);
+            return Ok(None);
+        }
+    };
+
+    if !client.unlocked || client.canary {
+        divergences.push(Divergence::ClientLocked);
+    }
+
+    Ok(Some(client))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_scope() {
+        let token = vec!["read".to_string(), "write".to_string()];
+        let current = vec!["read".to_string(), "admin".to_string()];
+
+        let (revoked, added) = diff_scope(&token, &current);
+
+        assert_eq!(revoked, vec!["write".to_string()]);
+        assert_eq!(added, vec!["admin".to_string()]);
+        assert_eq!(diff_scope(&token, &token), (vec![], vec![]));
+    }
+}
@@ -0,0 +1,8 @@
+use super::handler;
+
+use axum::routing::post;
+
+/// Token inspection routes
+pub fn routes() -> axum::Router {
+    axum::Router::new().route("/", post(handler::verify))
+}
@@ -0,0 +1,192 @@
+//! Integrity checks for documents that reference deleted users, clients or services.
+//!
+//! Orphans are counted on a schedule and exported as metrics, and removed if cleanup is
+//! enabled. `identity-server fsck [--fix]` runs the same checks once.
+
+use crate::{database::Database, metrics::Metrics, utils, Result};
+
+use std::time::Duration;
+
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use tracing::{error, info, warn};
+
+/// Kind of orphan, the collection and field of the reference and the referenced collection
+struct Check {
+    kind: &'static str,
+    collection: &'static str,
+    field: &'static str,
+    target: &'static str,
+}
+
+const CHECKS: &[Check] = &[
+    Check {
+        kind: "clientWithoutUser",
+        collection: "clients",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "clientWithoutService",
+        collection: "clients",
+        field: "service",
+        target: "services",
+    },
+    Check {
+        kind: "sessionWithoutUser",
+        collection: "refresh_tokens",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "elevationWithoutUser",
+        collection: "elevations",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "approvalWithoutUser",
+        collection: "role_approvals",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "authorizationCodeWithoutClient",
+        collection: "authorization_codes",
+        field: "client",
+        target: "clients",
+    },
+    Check {
+        kind: "sessionWithoutUser",
+        collection: "sessions",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "consentWithoutUser",
+        collection: "consents",
+        field: "user",
+        target: "users",
+    },
+    Check {
+        kind: "consentWithoutClient",
+        collection: "consents",
+        field: "client",
+        target: "clients",
+    },
+    Check {
+        kind: "deviceCodeWithoutClient",
+        collection: "device_codes",
+        field: "client",
+        target: "clients",
+    },
+    Check {
+        kind: "scopeUsageWithoutClient",
+        collection: "scope_usage",
+        field: "client",
+        target: "clients",
+    },
+    Check {
+        kind: "healthWithoutService",
+        collection: "service_health",
+        field: "_id",
+        target: "services",
+    },
+];
+
+/// Number of orphans by kind
+#[derive(Debug, Clone, Default)]
+pub struct Report(pub Vec<(&'static str, u64)>);
+
+impl Report {
+    pub fn total(&self) -> u64 {
+        self.0.iter().map(|(_, n)| n).sum()
+    }
+}
+
+impl Database {
+    async fn find_orphans(&self, check: &Check) -> Result<Vec<Bson>> {
+        let pipeline = vec![
+            doc! {
+                "$lookup": {
+                    "from": check.target,
+                    "localField": check.field,
+                    "foreignField": "_id",
+                    "as": "target",
+                }
+            },
+            doc! { "$match": { "target": { "$size": 0 } } },
+            doc! { "$project": { "_id": 1 } },
+        ];
+
+        let ids = self
+            .collection::<Document>(check.collection)
+            .aggregate(pipeline, None)
+            .await?
+            .try_filter_map(|d| async move { Ok(d.get("_id").cloned()) })
+            .try_collect()
+            .await?;
+
+        Ok(ids)
+    }
+}
+
+/// Counts the orphans of all kinds, and removes them if `fix` is set
+pub async fn check(db: &Database, fix: bool) -> Result<Report> {
+    let mut report = Report::default();
+
+    for check in CHECKS {
+        let ids = db.find_orphans(check).await?;
+
+        if fix && !ids.is_empty() {
+            let result = db
+                .collection::<Document>(check.collection)
+                .delete_many(doc! { "_id": { "$in": &ids } }, None)
+                .await?;
+            info!(
+                kind = check.kind,
+                count = result.deleted_count,
+                realm = ?db.realm(),
+                "orphans removed"
+            );
+        }
+
+        report.0.push((check.kind, ids.len() as u64));
+    }
+
+    Ok(report)
+}
+
+/// Runs the checks once, for the `fsck` command
+pub async fn fsck(db: &Database, fix: bool) -> Result<()> {
+    let report = check(db, fix).await?;
+
+    for (kind, count) in &report.0 {
+        info!(kind, count, realm = ?db.realm(), "orphans found");
+    }
+
+    Ok(())
+}
+
+/// Runs the checks periodically and exports the number of orphans
+pub fn spawn_integrity_checks(db: Database, metrics: Metrics, interval: Duration, fix: bool) {
+    utils::spawn_named("integrity-checks", async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match check(&db, fix).await {
+                Ok(report) => {
+                    if report.total() > 0 {
+                        warn!(count = report.total(), realm = ?db.realm(), "orphaned documents found");
+                    }
+                    for (kind, count) in report.0 {
+                        metrics.set_orphans(db.realm().unwrap_or_default(), kind, count);
+                    }
+                }
+                Err(e) => error!(error = %e, "integrity check failed"),
+            }
+        }
+    });
+}
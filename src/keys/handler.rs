@@ -0,0 +1,26 @@
+use crate::{
+    authentication::{token::TokenConfig, AuthenticationError},
+    database::Database,
+    extract::TokenData,
+    model::{List, Response},
+    session::SessionClaims,
+};
+
+use super::{KeyAlerts, KeyStatusResponse};
+
+use axum::extract::Extension;
+
+pub async fn list(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(alerts): Extension<KeyAlerts>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<List<KeyStatusResponse>>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let status = alerts.status(&db, config.clock.now()).await?;
+
+    Ok(Response::new(List::new(status.len() as u64, status)))
+}
@@ -0,0 +1,403 @@
+//! Expiry of key material the server can't renew itself, like the Apple client key, keys
+//! managed in a KMS or an uploaded token signing key.
+//!
+//! The dates are configured with the keys. Admins are alerted by mail and webhook 30, 7 and 1
+//! days before a key expires and once it expired.
+
+mod handler;
+mod routes;
+
+use crate::{
+    clock::SharedClock,
+    database::Database,
+    http::{HttpClient, SendTimed},
+    mail, utils, Result,
+};
+
+use std::{collections::HashMap, sync::Arc, time::Duration as StdDuration};
+
+use chrono::{serde::ts_seconds, DateTime, Duration, NaiveDate, TimeZone, Utc};
+use mongodb::{
+    bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime},
+    error::{ErrorKind, WriteFailure},
+    options::FindOneOptions,
+};
+use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::{error, warn};
+
+pub use routes::routes;
+
+const CHECK_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+
+/// Days before the expiry at which admins are alerted, zero once it expired
+const ALERT_DAYS: [i64; 4] = [30, 7, 1, 0];
+
+/// Configured expiry of a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyExpiry {
+    pub name: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl KeyExpiry {
+    /// Alert that is due at `now`, by its days before the expiry
+    fn alert_due(&self, now: DateTime<Utc>) -> Option<i64> {
+        let left = self.expires_at - now;
+
+        ALERT_DAYS
+            .into_iter()
+            .filter(|d| left <= Duration::days(*d))
+            .min()
+    }
+
+    fn state(&self, now: DateTime<Utc>) -> KeyState {
+        match self.alert_due(now) {
+            Some(0) => KeyState::Expired,
+            Some(_) => KeyState::Expiring,
+            None => KeyState::Valid,
+        }
+    }
+}
+
+/// Parses comma-separated keys with their expiry date, e.g. `apple:2025-03-01`
+pub fn de_expiries<'de, D>(d: D) -> std::result::Result<Vec<KeyExpiry>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    input
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let (name, date) = v
+                .split_once(':')
+                .ok_or_else(|| serde::de::Error::custom(format!("key {} has no date", v)))?;
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|e| {
+                serde::de::Error::custom(format!("date of key {} is invalid: {}", name, e))
+            })?;
+
+            Ok(KeyExpiry {
+                name: name.to_string(),
+                expires_at: Utc.from_utc_datetime(&date.and_hms(0, 0, 0)),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyState {
+    Valid,
+    /// Expires within the alert period
+    Expiring,
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyStatusResponse {
+    pub name: String,
+    pub state: KeyState,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+    /// Negative once expired
+    pub days_left: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_alert: Option<KeyAlertResponse>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyAlertResponse {
+    pub days_before: i64,
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+}
+
+/// Alert that was sent, one per key, expiry and threshold, so that instances don't repeat it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AlertDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    key: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    expires_at: DateTime<Utc>,
+    days_before: i64,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    date: DateTime<Utc>,
+}
+
+impl AlertDocument {
+    fn new(key: &KeyExpiry, days_before: i64, now: DateTime<Utc>) -> Self {
+        Self {
+            id: format!(
+                "{}:{}:{}",
+                key.name,
+                key.expires_at.format("%Y-%m-%d"),
+                days_before
+            ),
+            key: key.name.clone(),
+            expires_at: key.expires_at,
+            days_before,
+            date: now,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    key: &'a str,
+    #[serde(with = "ts_seconds")]
+    expires_at: DateTime<Utc>,
+    days_before: i64,
+}
+
+/// JSON schema of the body posted to the key expiry webhook
+pub fn webhook_schema() -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "required": ["key", "expiresAt", "daysBefore"],
+        "properties": {
+            "key": { "type": "string", "description": "Name of the key" },
+            "expiresAt": { "type": "integer", "description": "Unix time in seconds the key expires at" },
+            "daysBefore": { "type": "integer", "description": "Days until the expiry, 0 or less once it expired" },
+        },
+    })
+}
+
+/// Tracks the configured keys and alerts admins before they expire
+#[derive(Clone)]
+pub struct KeyAlerts {
+    keys: Arc<Vec<KeyExpiry>>,
+    admins: Arc<Vec<String>>,
+    webhook: Option<Url>,
+    mail: mail::Client,
+    client: HttpClient,
+}
+
+impl KeyAlerts {
+    pub fn new(
+        keys: Vec<KeyExpiry>,
+        admins: Vec<String>,
+        webhook: Option<Url>,
+        mail: mail::Client,
+        client: HttpClient,
+    ) -> Self {
+        Self {
+            keys: Arc::new(keys),
+            admins: Arc::new(admins),
+            webhook,
+            mail,
+            client,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Status of all keys with their latest alert
+    pub async fn status(
+        &self,
+        db: &Database,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<KeyStatusResponse>> {
+        let mut status = Vec::with_capacity(self.keys.len());
+
+        for key in self.keys.iter() {
+            let last_alert = db.get_last_key_alert(key).await?;
+
+            status.push(KeyStatusResponse {
+                name: key.name.clone(),
+                state: key.state(now),
+                expires_at: key.expires_at,
+                days_left: (key.expires_at - now).num_days(),
+                last_alert: last_alert.map(|a| KeyAlertResponse {
+                    days_before: a.days_before,
+                    date: a.date,
+                }),
+            });
+        }
+
+        Ok(status)
+    }
+
+    /// Checks the keys periodically, only one of all instances sends each alert
+    pub fn spawn(self, db: Database, clock: SharedClock) {
+        utils::spawn_named("key-expiry", async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(e) = self.check(&db, clock.now()).await {
+                    error!(error = %e, "key expiry check failed");
+                }
+            }
+        });
+    }
+
+    async fn check(&self, db: &Database, now: DateTime<Utc>) -> Result<()> {
+        for key in self.keys.iter() {
+            let days_before = match key.alert_due(now) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            if !db.claim_key_alert(key, days_before, now).await? {
+                continue;
+            }
+
+            warn!(key = %key.name, expires_at = %key.expires_at, days_before, "key expires");
+            self.alert(key, days_before).await;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the alert to every channel, failures are only logged
+    async fn alert(&self, key: &KeyExpiry, days_before: i64) {
+        for addr in self.admins.iter() {
+            if let Err(e) = send_expiry_mail(addr, key, days_before, &self.mail).await {
+                warn!(key = %key.name, error = %e, "key expiry mail could not be sent");
+            }
+        }
+
+        if let Some(url) = &self.webhook {
+            let payload = WebhookPayload {
+                key: &key.name,
+                expires_at: key.expires_at,
+                days_before,
+            };
+            let result = self
+                .client
+                .post(url.clone())
+                .json(&payload)
+                .send_timed()
+                .await
+                .and_then(|r| r.error_for_status());
+
+            if let Err(e) = result {
+                warn!(key = %key.name, error = %e, "key expiry webhook failed");
+            }
+        }
+    }
+}
+
+async fn send_expiry_mail(
+    addr: &str,
+    key: &KeyExpiry,
+    days_before: i64,
+    mail: &mail::Client,
+) -> Result<()> {
+    const TEMPLATE_NAME: &str = "identity.admin.key-expiry";
+
+    let subject = match days_before {
+        0 => format!("Key {} has expired", key.name),
+        1 => format!("Key {} expires tomorrow", key.name),
+        d => format!("Key {} expires in {} days", key.name, d),
+    };
+
+    let mut vars = HashMap::with_capacity(3);
+    vars.insert("keyName".to_string(), key.name.clone());
+    vars.insert(
+        "expiresAt".to_string(),
+        key.expires_at.format("%Y-%m-%d").to_string(),
+    );
+    vars.insert("daysBefore".to_string(), days_before.to_string());
+
+    mail.send_template(addr, &subject, TEMPLATE_NAME, vars)
+        .await?;
+
+    Ok(())
+}
+
+const COLLECTION: &str = "key_alerts";
+
+/// Code of a write error on a duplicate key
+const DUPLICATE_KEY: i32 = 11000;
+
+impl Database {
+    /// Records the alert, returns `false` if it was already sent
+    async fn claim_key_alert(
+        &self,
+        key: &KeyExpiry,
+        days_before: i64,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        match self
+            .collection::<AlertDocument>(COLLECTION)
+            .insert_one(AlertDocument::new(key, days_before, now), None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == DUPLICATE_KEY => {
+                    Ok(false)
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    /// Latest alert for the current expiry of the key
+    async fn get_last_key_alert(&self, key: &KeyExpiry) -> Result<Option<AlertDocument>> {
+        let filter = doc! { "key": &key.name, "expiresAt": key.expires_at };
+        let opts = FindOneOptions::builder().sort(doc! { "date": -1 }).build();
+
+        let alert = self
+            .collection::<AlertDocument>(COLLECTION)
+            .find_one(filter, opts)
+            .await?;
+
+        Ok(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::value::{Error as DeError, StrDeserializer};
+    use serde::de::IntoDeserializer;
+
+    fn key(expires_at: DateTime<Utc>) -> KeyExpiry {
+        KeyExpiry {
+            name: "apple".to_string(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn alerts_at_thresholds() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+
+        assert_eq!(key(now + Duration::days(31)).alert_due(now), None);
+        assert_eq!(key(now + Duration::days(30)).alert_due(now), Some(30));
+        assert_eq!(key(now + Duration::days(8)).alert_due(now), Some(30));
+        assert_eq!(key(now + Duration::days(5)).alert_due(now), Some(7));
+        assert_eq!(key(now + Duration::hours(20)).alert_due(now), Some(1));
+        assert_eq!(key(now).alert_due(now), Some(0));
+        assert_eq!(key(now - Duration::days(3)).state(now), KeyState::Expired);
+        assert_eq!(key(now + Duration::days(3)).state(now), KeyState::Expiring);
+    }
+
+    #[test]
+    fn parses_expiries() {
+        let de: StrDeserializer<DeError> = "apple:2025-03-01,kms:2026-01-31".into_deserializer();
+        let keys = de_expiries(de).unwrap();
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].name, "apple");
+        assert_eq!(keys[0].expires_at, Utc.ymd(2025, 3, 1).and_hms(0, 0, 0));
+
+        let de: StrDeserializer<DeError> = "apple".into_deserializer();
+        assert!(de_expiries(de).is_err());
+        let de: StrDeserializer<DeError> = "apple:03/01/2025".into_deserializer();
+        assert!(de_expiries(de).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Free-form key/value labels to organize clients and services, e.g. by team or environment
+
+use crate::{error, model::Status};
+
+use hyper::StatusCode;
+use mongodb::bson::{doc, Document};
+
+pub use crate::models::Labels;
+
+const MAX_LABELS: usize = 32;
+const MAX_KEY_LEN: usize = 63;
+const MAX_VALUE_LEN: usize = 255;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LabelError {
+    #[error("too many labels, at most {} are allowed", MAX_LABELS)]
+    TooMany,
+    #[error("label key \"{0}\" is invalid")]
+    InvalidKey(String),
+    #[error("value of label \"{0}\" is invalid")]
+    InvalidValue(String),
+}
+
+impl error::ErrorResponse for LabelError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            LabelError::TooMany | LabelError::InvalidKey(_) | LabelError::InvalidValue(_) => {
+                StatusCode::BAD_REQUEST
+            }
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Keys start with an alphanumeric character and may contain `-`, `_` and `/`, dots would
+/// be read as a path in filters
+fn is_valid_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_LEN
+        && key.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '/'))
+}
+
+/// Values can not contain the separators of the filter syntax
+fn is_valid_value(value: &str) -> bool {
+    value.len() <= MAX_VALUE_LEN && !value.contains([',', ':']) && !value.contains(char::is_control)
+}
+
+pub fn validate(labels: &Labels) -> Result<(), LabelError> {
+    if labels.len() > MAX_LABELS {
+        return Err(LabelError::TooMany);
+    }
+
+    for (key, value) in labels {
+        if !is_valid_key(key) {
+            return Err(LabelError::InvalidKey(key.clone()));
+        }
+        if !is_valid_value(value) {
+            return Err(LabelError::InvalidValue(key.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a filter from comma-separated `key:value` selectors, a key alone matches any value
+pub fn filter(selectors: &str) -> Result<Document, LabelError> {
+    let mut filter = Document::new();
+
+    for selector in selectors.split(',').filter(|v| !v.is_empty()) {
+        let (key, value) = match selector.split_once(':') {
+            Some((k, v)) => (k, Some(v)),
+            None => (selector, None),
+        };
+
+        if !is_valid_key(key) {
+            return Err(LabelError::InvalidKey(key.to_string()));
+        }
+
+        let field = format!("labels.{}", key);
+        match value {
+            Some(v) => filter.insert(field, v),
+            None => filter.insert(field, doc! { "$exists": true }),
+        };
+    }
+
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_labels() {
+        assert!(validate(&labels(&[("env", "prod"), ("team/api", "")])).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_labels() {
+        assert!(matches!(
+            validate(&labels(&[("-env", "prod")])),
+            Err(LabelError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            validate(&labels(&[("labels.env", "prod")])),
+            Err(LabelError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            validate(&labels(&[("env", "prod,dev")])),
+            Err(LabelError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            validate(&labels(&[("env", &"a".repeat(MAX_VALUE_LEN + 1))])),
+            Err(LabelError::InvalidValue(_))
+        ));
+
+        let many = (0..=MAX_LABELS)
+            .map(|i| (i.to_string(), String::new()))
+            .collect();
+        assert!(matches!(validate(&many), Err(LabelError::TooMany)));
+    }
+
+    #[test]
+    fn builds_filter() {
+        assert_eq!(
+            filter("env:prod,team").unwrap(),
+            doc! { "labels.env": "prod", "labels.team": { "$exists": true } }
+        );
+        assert!(filter("env:prod,$where:1").is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Authentication layer for downstream axum services.
+//!
+//! Verifies session tokens with the key set of the instance, like the server does, and
+//! inserts the verified [`SessionToken`] into the request extensions. Rejections have the
+//! same `Status` body as responses of the server.
+//!
+//! ```no_run
+//! use axum::{routing::get, Extension, Router};
+//! use identity_server::{layer::AuthLayer, verify::SessionToken};
+//!
+//! async fn handler(Extension(session): Extension<SessionToken>) -> String {
+//!     session.sub
+//! }
+//!
+//! let auth = AuthLayer::new(
+//!     "https://id.example.com/.well-known/jwks.json".parse().unwrap(),
+//!     ["api.example.com"],
+//! )
+//! .require_scope("clientRead");
+//!
+//! let app: Router = Router::new().route("/", get(handler)).layer(auth);
+//! ```
+
+use crate::{
+    models::Status,
+    verify::{SessionToken, Verifier, VerifyError},
+};
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    http::{header::AUTHORIZATION, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reqwest::Url;
+use tokio::sync::RwLock;
+use tower::{Layer, Service};
+
+#[derive(Debug, thiserror::Error)]
+enum AuthError {
+    #[error("authorization header is missing or invalid")]
+    MissingToken,
+    #[error("{0}")]
+    Token(#[from] VerifyError),
+    #[error("insufficient permission")]
+    InsufficientScope,
+    #[error("key set is unavailable")]
+    KeySetUnavailable,
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let code = match self {
+            AuthError::MissingToken | AuthError::Token(_) => StatusCode::UNAUTHORIZED,
+            AuthError::InsufficientScope => StatusCode::FORBIDDEN,
+            AuthError::KeySetUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        (code, Json(Status::new(code, self))).into_response()
+    }
+}
+
+enum Source {
+    /// Fetched from the instance and refreshed periodically
+    Remote { url: Url, client: reqwest::Client },
+    /// Fixed key set, e.g. for tests
+    Static,
+}
+
+struct Cached {
+    verifier: Option<Arc<Verifier>>,
+    fetched_at: Option<Instant>,
+}
+
+/// Key set shared by all clones of the layer
+struct KeySet {
+    source: Source,
+    audience: Vec<String>,
+    cached: RwLock<Cached>,
+}
+
+impl KeySet {
+    /// Age after which the key set is refreshed, rotated keys are published in advance
+    const MAX_AGE: Duration = Duration::from_secs(10 * 60);
+    /// Minimum age for a refresh on an unknown key, so that forged key ids can't cause load
+    const MIN_AGE: Duration = Duration::from_secs(30);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    async fn fetch(&self, min_age: Duration) -> Result<Arc<Verifier>, AuthError> {
+        let mut cached = self.cached.write().await;
+
+        // Refreshed concurrently
+        if let (Some(v), Some(at)) = (&cached.verifier, cached.fetched_at) {
+            if at.elapsed() < min_age {
+                return Ok(v.clone());
+            }
+        }
+
+        let (url, client) = match &self.source {
+            Source::Remote { url, client } => (url, client),
+            Source::Static => return cached.verifier.clone().ok_or(AuthError::KeySetUnavailable),
+        };
+
+        let audience: Vec<_> = self.audience.iter().map(String::as_str).collect();
+        let result = async {
+            let body = client
+                .get(url.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Verifier::from_jwks(
+                &body, &audience,
+            )?)
+        }
+        .await;
+
+        match result {
+            Ok(verifier) => {
+                let verifier = Arc::new(verifier);
+                cached.verifier = Some(verifier.clone());
+                cached.fetched_at = Some(Instant::now());
+
+                Ok(verifier)
+            }
+            Err(e) => {
+                tracing::error!(error = %e, %url, "key set could not be fetched");
+
+                // Keys stay valid until they are removed, so a stale set is still usable
+                cached.verifier.clone().ok_or(AuthError::KeySetUnavailable)
+            }
+        }
+    }
+
+    async fn verifier(&self) -> Result<Arc<Verifier>, AuthError> {
+        {
+            let cached = self.cached.read().await;
+            let fresh = matches!(self.source, Source::Static)
+                || cached
+                    .fetched_at
+                    .map_or(false, |at| at.elapsed() < Self::MAX_AGE);
+
+            if let (true, Some(v)) = (fresh, &cached.verifier) {
+                return Ok(v.clone());
+            }
+        }
+
+        self.fetch(Self::MAX_AGE).await
+    }
+
+    async fn verify(&self, token: &str) -> Result<SessionToken, AuthError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_secs();
+
+        match self.verifier().await?.verify(token, now) {
+            // Signed with a key that was published after the last fetch
+            Err(VerifyError::UnknownKey) => {
+                Ok(self.fetch(Self::MIN_AGE).await?.verify(token, now)?)
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Layer that requires a valid session token on every request
+#[derive(Clone)]
+pub struct AuthLayer {
+    keys: Arc<KeySet>,
+    scope: Arc<Vec<String>>,
+}
+
+impl AuthLayer {
+    /// Verifies tokens issued for one of the audiences with the key set at `jwks_url`
+    pub fn new<A, T>(jwks_url: Url, audience: A) -> Self
+    where
+        A: IntoIterator<Item = T>,
+        T: ToString,
+    {
+        let client = reqwest::Client::builder()
+            .timeout(KeySet::TIMEOUT)
+            .build()
+            .unwrap();
+
+        Self::with_source(
+            Source::Remote {
+                url: jwks_url,
+                client,
+            },
+            audience,
+            None,
+        )
+    }
+
+    /// Verifies tokens with a fixed key set, it is never refreshed
+    pub fn from_jwks<A, T>(jwks: &str, audience: A) -> Result<Self, VerifyError>
+    where
+        A: IntoIterator<Item = T>,
+        T: ToString,
+    {
+        let audience: Vec<_> = audience.into_iter().map(|a| a.to_string()).collect();
+        let refs: Vec<_> = audience.iter().map(String::as_str).collect();
+        let verifier = Verifier::from_jwks(jwks, &refs)?;
+
+        Ok(Self::with_source(Source::Static, audience, Some(verifier)))
+    }
+
+    fn with_source<A, T>(source: Source, audience: A, verifier: Option<Verifier>) -> Self
+    where
+        A: IntoIterator<Item = T>,
+        T: ToString,
+    {
+        let keys = KeySet {
+            source,
+            audience: audience.into_iter().map(|a| a.to_string()).collect(),
+            cached: RwLock::new(Cached {
+                verifier: verifier.map(Arc::new),
+                fetched_at: None,
+            }),
+        };
+
+        Self {
+            keys: Arc::new(keys),
+            scope: Default::default(),
+        }
+    }
+
+    /// Requires the scope in addition to the previous ones, e.g. `clientRead`
+    pub fn require_scope<S>(mut self, scope: S) -> Self
+    where
+        S: ToString,
+    {
+        Arc::make_mut(&mut self.scope).push(scope.to_string());
+        self
+    }
+
+    async fn authenticate(&self, headers: &HeaderMap) -> Result<SessionToken, AuthError> {
+        let token = bearer_token(headers).ok_or(AuthError::MissingToken)?;
+        let session = self.keys.verify(token).await?;
+
+        if !self.scope.iter().all(|s| session.scope.contains(s)) {
+            return Err(AuthError::InsufficientScope);
+        }
+
+        Ok(session)
+    }
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = Auth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Auth {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+impl<S, B> Service<Request<B>> for Auth<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // The clone may not be ready, the ready service is taken instead
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            match layer.authenticate(req.headers()).await {
+                Ok(session) => {
+                    req.extensions_mut().insert(session);
+                    inner.call(req).await
+                }
+                Err(e) => Ok(e.into_response()),
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    use crate::{
+        authentication::token::{TokenClaims, TokenConfig},
+        session::{Scope, SessionClaims},
+    };
+
+    use axum::{body::Body, routing::get, Extension, Router};
+    use chrono::Utc;
+    use jsonwebtoken::Algorithm;
+    use tower::ServiceExt;
+
+    fn config() -> TokenConfig {
+        TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(
+                include_str!("authentication/testdata/rsa-2048.pem"),
+                Algorithm::RS256,
+            )
+            .unwrap()
+    }
+
+    fn app(config: &TokenConfig, scope: &str) -> Router {
+        let jwks = serde_json::to_string(&config.jwks()).unwrap();
+        let auth = AuthLayer::from_jwks(&jwks, ["test"])
+            .unwrap()
+            .require_scope(scope);
+
+        Router::new()
+            .route(
+                "/",
+                get(|Extension(s): Extension<SessionToken>| async move { s.sub }),
+            )
+            .layer(auth)
+    }
+
+    async fn status(app: Router, token: Option<&str>) -> StatusCode {
+        let mut req = Request::builder().uri("/");
+        if let Some(token) = token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+        res.status()
+    }
+
+    #[tokio::test]
+    async fn injects_session() {
+        let config = config();
+        let token = SessionClaims::with_scope(
+            ["test".to_string()],
+            "user",
+            [Scope::ClientRead],
+            Utc::now(),
+        )
+        .encode(&config)
+        .unwrap();
+
+        assert_eq!(
+            status(app(&config, "clientRead"), Some(&token)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(app(&config, "clientWrite"), Some(&token)).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_and_foreign_tokens() {
+        let config = config();
+        let foreign = SessionClaims::new(["test".to_string()], "user", Utc::now())
+            .encode(&TokenConfig::from_secret("secret", ["test"]))
+            .unwrap();
+
+        assert_eq!(
+            status(app(&config, "clientRead"), None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(app(&config, "clientRead"), Some(&foreign)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,114 @@
+#[cfg(feature = "server")]
+mod action;
+#[cfg(feature = "server")]
+mod audit;
+#[cfg(feature = "server")]
+mod authentication;
+#[cfg(feature = "server")]
+mod bulk;
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "chaos")]
+mod chaos;
+#[cfg(feature = "server")]
+mod client;
+#[cfg(feature = "server")]
+mod clock;
+#[cfg(feature = "server")]
+mod config;
+#[cfg(feature = "server")]
+mod cors;
+#[cfg(feature = "server")]
+mod database;
+#[cfg(feature = "debug-endpoints")]
+mod debug;
+#[cfg(feature = "server")]
+mod epoch;
+#[cfg(feature = "server")]
+mod error;
+#[cfg(feature = "server")]
+mod extract;
+#[cfg(feature = "federation")]
+mod federation;
+#[cfg(feature = "server")]
+mod health;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+#[cfg(feature = "server")]
+mod http;
+#[cfg(feature = "server")]
+mod inspect;
+#[cfg(feature = "server")]
+mod integrity;
+#[cfg(feature = "server")]
+mod keys;
+#[cfg(feature = "server")]
+mod label;
+#[cfg(feature = "layer")]
+pub mod layer;
+#[cfg(feature = "server")]
+mod mail;
+#[cfg(feature = "server")]
+mod metrics;
+#[cfg(feature = "server")]
+mod migration;
+#[cfg(feature = "server")]
+mod model;
+#[cfg(feature = "models")]
+pub mod models;
+#[cfg(feature = "server")]
+mod oauth;
+#[cfg(feature = "server")]
+mod openapi;
+#[cfg(feature = "server")]
+mod outbox;
+#[cfg(feature = "wasm-policies")]
+mod policy;
+#[cfg(feature = "server")]
+mod portal;
+#[cfg(feature = "server")]
+mod ratelimit;
+#[cfg(feature = "server")]
+mod replica;
+#[cfg(feature = "server")]
+mod revision;
+#[cfg(feature = "sdk")]
+pub mod sdk;
+#[cfg(feature = "selftest")]
+mod selftest;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "server")]
+mod service;
+#[cfg(feature = "server")]
+mod session;
+#[cfg(feature = "server")]
+mod smoke;
+#[cfg(feature = "server")]
+mod sso;
+#[cfg(feature = "server")]
+mod state;
+#[cfg(feature = "server")]
+mod telemetry;
+#[cfg(feature = "server")]
+mod timing;
+#[cfg(feature = "server")]
+mod token;
+#[cfg(feature = "server")]
+mod user;
+#[cfg(feature = "server")]
+mod utils;
+#[cfg(feature = "verify")]
+pub mod verify;
+#[cfg(feature = "server")]
+mod well_known;
+
+#[cfg(feature = "server")]
+pub use server::{run, Server, ServerBuilder};
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[cfg(feature = "server")]
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,92 @@
+//! Inbox of sandbox deployments: messages are captured in memory instead of being delivered, so
+//! that tests of integrators can follow the links of verification and login mails.
+
+use super::{Message, Transport};
+
+use crate::Result;
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+/// Captured messages, the oldest are dropped beyond
+const MAX_MESSAGES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captured {
+    pub message: Message,
+    pub date: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Inbox(Arc<Mutex<VecDeque<Captured>>>);
+
+impl Inbox {
+    /// Captured messages, newest first, only the ones to the address if given
+    pub fn messages(&self, to: Option<&str>) -> Vec<Captured> {
+        let messages = self.0.lock().unwrap();
+        messages
+            .iter()
+            .rev()
+            .filter(|c| to.map_or(true, |to| c.message.to.eq_ignore_ascii_case(to)))
+            .cloned()
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Transport for Inbox {
+    async fn send(&self, message: &Message) -> Result<()> {
+        info!(to = %message.to, subject = %message.subject, "mail captured");
+
+        let mut messages = self.0.lock().unwrap();
+        if messages.len() >= MAX_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(Captured {
+            message: message.clone(),
+            date: Utc::now(),
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mail::Body;
+
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn keeps_the_latest_messages() {
+        let inbox = Inbox::default();
+        for i in 0..=MAX_MESSAGES {
+            let message = Message {
+                to: "user@example.com".into(),
+                subject: format!("Message {}", i),
+                body: Body::Template {
+                    name: "identity.action.login".into(),
+                    vars: HashMap::new(),
+                },
+            };
+            inbox.send(&message).await.unwrap();
+        }
+
+        let messages = inbox.messages(Some("User@example.com"));
+        assert_eq!(messages.len(), MAX_MESSAGES);
+        assert_eq!(
+            messages[0].message.subject,
+            format!("Message {}", MAX_MESSAGES)
+        );
+        assert_eq!(messages[MAX_MESSAGES - 1].message.subject, "Message 1");
+        assert!(inbox.messages(Some("other@example.com")).is_empty());
+    }
+}
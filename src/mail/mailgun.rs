@@ -0,0 +1,189 @@
+use super::{Body, Message, Transport};
+
+use crate::{
+    error::Error,
+    http::{HttpClient, SendTimed},
+    Result,
+};
+
+use axum::async_trait;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use url::Host;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    US,
+    EU,
+}
+
+impl Region {
+    fn base_url(&self) -> Url {
+        match self {
+            Region::US => Url::parse("https://api.mailgun.net/").unwrap(),
+            Region::EU => Url::parse("https://api.eu.mailgun.net/").unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TemplateMessage<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    template: &'a str,
+    #[serde(
+        rename = "h:X-Mailgun-Variables",
+        skip_serializing_if = "Option::is_none"
+    )]
+    variables: Option<&'a str>,
+}
+
+/// Mailgun API, templates are stored and rendered by Mailgun
+#[derive(Debug, Clone)]
+pub struct Mailgun {
+    from: String,
+    base: Url,
+    api_key: String,
+    domain: String,
+    client: HttpClient,
+}
+
+impl Mailgun {
+    pub fn new<K, D, F>(
+        api_key: K,
+        region: Region,
+        domain: D,
+        from: F,
+        client: HttpClient,
+    ) -> Result<Self>
+    where
+        K: AsRef<str>,
+        D: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let base = region.base_url().join("v3/").unwrap();
+
+        Ok(Self {
+            from: from.as_ref().to_string(),
+            base,
+            api_key: api_key.as_ref().to_string(),
+            domain: domain.as_ref().to_string(),
+            client,
+        })
+    }
+
+    /// Sends the messages to a Mailgun compatible API at the given address instead.
+    ///
+    /// Plain HTTP is only allowed for loopback addresses, e.g. a mock API in tests, the API key
+    /// would be sent in cleartext otherwise.
+    pub fn with_base_url(mut self, base: Url) -> Result<Self> {
+        if base.scheme() == "http" {
+            if !is_loopback(&base) {
+                return Err(Error::Config(
+                    "mail base URL has to use HTTPS unless it is a loopback address".to_string(),
+                ));
+            }
+
+            warn!(url = %base, "mail API is used without TLS");
+            self.client = HttpClient::allow_http();
+        }
+
+        self.base = base
+            .join("v3/")
+            .map_err(|e| Error::Config(format!("mail base URL is invalid: {}", e)))?;
+
+        Ok(self)
+    }
+
+    #[inline]
+    async fn post<T>(&self, form: &T) -> Result<()>
+    where
+        T: Serialize + Sync,
+    {
+        let path = format!("{}/messages", self.domain);
+        let res = self
+            .client
+            .post(self.base.join(&path).unwrap())
+            .basic_auth("api", Some(&self.api_key))
+            .form(form)
+            .send_timed()
+            .await?;
+
+        res.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+fn is_loopback(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(host)) => host.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+#[async_trait]
+impl Transport for Mailgun {
+    async fn send(&self, message: &Message) -> Result<()> {
+        match &message.body {
+            Body::Template { name, vars } => {
+                let vars = serde_json::to_string(vars).unwrap();
+                let form = TemplateMessage {
+                    from: &self.from,
+                    to: &message.to,
+                    subject: &message.subject,
+                    template: name,
+                    variables: Some(&vars),
+                };
+
+                self.post(&form).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mailgun() -> Mailgun {
+        Mailgun::new(
+            "key",
+            Region::EU,
+            "example.com",
+            "identity@example.com",
+            HttpClient::allow_http(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plain_http_only_for_loopback() {
+        for url in [
+            "http://127.0.0.1:8080/",
+            "http://localhost/",
+            "http://[::1]/",
+        ] {
+            assert!(
+                mailgun().with_base_url(url.parse().unwrap()).is_ok(),
+                "{}",
+                url
+            );
+        }
+        assert!(mailgun()
+            .with_base_url("https://mail.example.com/".parse().unwrap())
+            .is_ok());
+
+        assert!(mailgun()
+            .with_base_url("http://mail.example.com/".parse().unwrap())
+            .is_err());
+        assert!(mailgun()
+            .with_base_url("http://10.0.0.1/".parse().unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Outbound email.
+//!
+//! Messages are delivered by a [`Transport`], either the Mailgun API, which renders its own
+//! templates, or an SMTP server with templates rendered from local files. Failed deliveries
+//! are retried with a growing delay. Sandbox deployments capture them in an [`Inbox`].
+//!
+//! The transports are built with the `mail-mailgun` and `mail-smtp` features, without either
+//! only sandbox deployments can start.
+
+mod inbox;
+#[cfg(feature = "mail-mailgun")]
+mod mailgun;
+#[cfg(feature = "mail-smtp")]
+mod smtp;
+#[cfg(feature = "mail-smtp")]
+mod template;
+
+use crate::Result;
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use tracing::warn;
+
+pub use inbox::{Captured, Inbox};
+#[cfg(feature = "mail-mailgun")]
+pub use mailgun::{Mailgun, Region};
+#[cfg(feature = "mail-smtp")]
+pub use smtp::{Smtp, SmtpTls};
+#[cfg(feature = "mail-smtp")]
+pub use template::Templates;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("mail template {0} not found")]
+    TemplateNotFound(String),
+    #[error("mail template can not be read: {0}")]
+    Template(#[from] std::io::Error),
+    #[error("{0} contains a line break")]
+    LineBreak(&'static str),
+    #[error("SMTP server replied {0}: {1}")]
+    Rejected(u16, String),
+    #[error("SMTP connection failed: {0}")]
+    Connection(std::io::Error),
+    #[error("SMTP server timed out")]
+    Timeout,
+}
+
+impl MailError {
+    /// Retrying doesn't help, e.g. the template doesn't exist or the server rejects the address
+    fn is_permanent(&self) -> bool {
+        match self {
+            MailError::Rejected(code, _) => *code >= 500,
+            MailError::TemplateNotFound(_) | MailError::Template(_) | MailError::LineBreak(_) => {
+                true
+            }
+            MailError::Connection(_) | MailError::Timeout => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// Named template with its variables
+    Template {
+        name: String,
+        vars: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: Body,
+}
+
+/// Delivers messages, e.g. to an API or an SMTP server
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    async fn send(&self, message: &Message) -> Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    transport: Arc<dyn Transport>,
+    attempts: u32,
+    retry_delay: Duration,
+}
+
+impl Client {
+    const DEFAULT_ATTEMPTS: u32 = 3;
+    const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    pub fn new<T>(transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        Self {
+            transport: Arc::new(transport),
+            attempts: Self::DEFAULT_ATTEMPTS,
+            retry_delay: Self::DEFAULT_RETRY_DELAY,
+        }
+    }
+
+    /// Sends each message up to `attempts` times, the delay doubles after every attempt
+    pub fn with_retries(mut self, attempts: u32, delay: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.retry_delay = delay;
+        self
+    }
+
+    pub async fn send_template(
+        &self,
+        addr: &str,
+        sub: &str,
+        tmpl: &str,
+        vars: HashMap<String, String>,
+    ) -> Result<()> {
+        let message = Message {
+            to: addr.to_string(),
+            subject: sub.to_string(),
+            body: Body::Template {
+                name: tmpl.to_string(),
+                vars,
+            },
+        };
+
+        self.send(&message).await
+    }
+
+    async fn send(&self, message: &Message) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        if crate::chaos::drop_mail() {
+            warn!("mail dropped by fault injection");
+            return Ok(());
+        }
+
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+
+        loop {
+            match self.transport.send(message).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.attempts && is_transient(&e) => {
+                    warn!(error = %e, attempt, "mail delivery failed, retrying");
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn is_transient(err: &crate::error::Error) -> bool {
+    use crate::error::Error;
+
+    match err {
+        Error::Mail(e) => !e.is_permanent(),
+        Error::Reqwest(e) => e.status().map_or(true, |s| s.is_server_error()),
+        _ => false,
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use mock::MockTransport;
+
+#[cfg(any(test, feature = "test-util"))]
+#[cfg_attr(not(test), allow(dead_code))]
+mod mock {
+    use super::{MailError, Message, Transport};
+
+    use crate::Result;
+
+    use std::sync::{Arc, Mutex};
+
+    use axum::async_trait;
+
+    /// Records messages instead of sending them
+    #[derive(Debug, Clone, Default)]
+    pub struct MockTransport {
+        sent: Arc<Mutex<Vec<Message>>>,
+        failures: Arc<Mutex<u32>>,
+    }
+
+    impl MockTransport {
+        /// Fails the next `count` messages with a transient error
+        pub fn fail_next(&self, count: u32) {
+            *self.failures.lock().unwrap() = count;
+        }
+
+        pub fn sent(&self) -> Vec<Message> {
+            self.sent.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn send(&self, message: &Message) -> Result<()> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(MailError::Timeout.into());
+            }
+
+            self.sent.lock().unwrap().push(message.clone());
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(transport: &MockTransport) -> Client {
+        Client::new(transport.clone()).with_retries(3, Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let transport = MockTransport::default();
+        let mut vars = HashMap::new();
+        vars.insert("token".to_string(), "abc".to_string());
+
+        transport.fail_next(2);
+        client(&transport)
+            .send_template("a@b.c", "Sign in", "identity.action.login", vars.clone())
+            .await
+            .unwrap();
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            sent[0].body,
+            Body::Template {
+                name: "identity.action.login".to_string(),
+                vars
+            }
+        );
+
+        transport.fail_next(3);
+        assert!(client(&transport)
+            .send_template("a@b.c", "Sign in", "identity.action.login", HashMap::new())
+            .await
+            .is_err());
+        assert_eq!(transport.sent().len(), 1);
+    }
+}
@@ -0,0 +1,482 @@
+//! Minimal SMTP client for submitting messages to a relay.
+//!
+//! Every message is sent over a new connection, secured with implicit TLS or STARTTLS, and
+//! authenticated with `AUTH PLAIN` if credentials are set. Bodies are base64 encoded, so
+//! they never need dot-stuffing.
+
+use super::{Body, MailError, Message, Templates, Transport};
+
+use crate::{error::Error, Result};
+
+use std::{convert::TryFrom, fmt, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName},
+    TlsConnector,
+};
+use tracing::warn;
+
+/// Time limit of a whole delivery
+const TIMEOUT: Duration = Duration::from_secs(30);
+const LINE_LENGTH: usize = 76;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// Upgrade of a plain connection, usually on port 587
+    Starttls,
+    /// Unencrypted, only for local relays
+    None,
+}
+
+impl Default for SmtpTls {
+    fn default() -> Self {
+        SmtpTls::Starttls
+    }
+}
+
+impl SmtpTls {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            SmtpTls::Tls => 465,
+            SmtpTls::Starttls => 587,
+            SmtpTls::None => 25,
+        }
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T> Io for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
+
+#[derive(Clone)]
+pub struct Smtp {
+    host: String,
+    port: u16,
+    tls: SmtpTls,
+    credentials: Option<(String, String)>,
+    from: String,
+    templates: Arc<Templates>,
+    connector: TlsConnector,
+}
+
+impl fmt::Debug for Smtp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Smtp")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("tls", &self.tls)
+            .field("from", &self.from)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Smtp {
+    pub fn new<H, F>(
+        host: H,
+        port: Option<u16>,
+        tls: SmtpTls,
+        from: F,
+        templates: Templates,
+    ) -> Result<Self>
+    where
+        H: Into<String>,
+        F: Into<String>,
+    {
+        let host = host.into();
+        if tls != SmtpTls::None && ServerName::try_from(host.as_str()).is_err() {
+            return Err(Error::Config(format!("SMTP host {} is invalid", host)));
+        }
+
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            host,
+            port: port.unwrap_or_else(|| tls.default_port()),
+            tls,
+            credentials: None,
+            from: from.into(),
+            templates: Arc::new(templates),
+            connector: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    pub fn with_credentials<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        if self.tls == SmtpTls::None {
+            warn!(host = %self.host, "SMTP credentials are sent without TLS");
+        }
+
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Domain of the sender address, used to greet and in message IDs
+    fn domain(&self) -> &str {
+        self.from.rsplit('@').next().unwrap_or("localhost")
+    }
+
+    async fn deliver(&self, to: &str, data: &str) -> std::result::Result<(), MailError> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(MailError::Connection)?;
+
+        let mut conn = match self.tls {
+            SmtpTls::Tls => Connection::new(self.handshake(Box::new(tcp)).await?),
+            SmtpTls::Starttls | SmtpTls::None => Connection::new(Box::new(tcp)),
+        };
+        let ehlo = format!("EHLO {}", self.domain());
+
+        conn.reply(2).await?;
+        conn.command(&ehlo, 2).await?;
+
+        if self.tls == SmtpTls::Starttls {
+            conn.command("STARTTLS", 2).await?;
+            conn = Connection::new(self.handshake(conn.into_inner()).await?);
+            conn.command(&ehlo, 2).await?;
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            let auth = base64::encode(format!("\0{}\0{}", username, password));
+            conn.command(&format!("AUTH PLAIN {}", auth), 2).await?;
+        }
+
+        conn.command(&format!("MAIL FROM:<{}>", self.from), 2)
+            .await?;
+        conn.command(&format!("RCPT TO:<{}>", to), 2).await?;
+        conn.command("DATA", 3).await?;
+        conn.command(&format!("{}\r\n.", data), 2).await?;
+
+        // The message is accepted already
+        let _ = conn.command("QUIT", 2).await;
+
+        Ok(())
+    }
+
+    /// Connects and waits for the greeting of the server, without sending a message
+    pub async fn check(&self) -> std::result::Result<(), MailError> {
+        let greeting = async {
+            let tcp = TcpStream::connect((self.host.as_str(), self.port))
+                .await
+                .map_err(MailError::Connection)?;
+            let mut conn = match self.tls {
+                SmtpTls::Tls => Connection::new(self.handshake(Box::new(tcp)).await?),
+                SmtpTls::Starttls | SmtpTls::None => Connection::new(Box::new(tcp)),
+            };
+
+            conn.reply(2).await?;
+            let _ = conn.command("QUIT", 2).await;
+
+            Ok(())
+        };
+
+        tokio::time::timeout(TIMEOUT, greeting)
+            .await
+            .map_err(|_| MailError::Timeout)?
+    }
+
+    async fn handshake(&self, stream: Box<dyn Io>) -> std::result::Result<Box<dyn Io>, MailError> {
+        let name = ServerName::try_from(self.host.as_str()).unwrap();
+        let stream = self
+            .connector
+            .connect(name, stream)
+            .await
+            .map_err(MailError::Connection)?;
+
+        Ok(Box::new(stream))
+    }
+
+    fn format(
+        &self,
+        message: &Message,
+        text: &str,
+        html: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> String {
+        let mut data = String::with_capacity(text.len() * 2);
+
+        data.push_str(&format!("From: {}\r\n", self.from));
+        data.push_str(&format!("To: {}\r\n", message.to));
+        data.push_str(&format!("Subject: {}\r\n", encode_header(&message.subject)));
+        data.push_str(&format!("Date: {}\r\n", now.to_rfc2822()));
+        data.push_str(&format!(
+            "Message-ID: <{}@{}>\r\n",
+            ObjectId::new().to_hex(),
+            self.domain()
+        ));
+        data.push_str("MIME-Version: 1.0\r\n");
+
+        match html {
+            None => push_part(&mut data, "text/plain", text),
+            Some(html) => {
+                // Can't be part of base64 encoded content
+                let boundary = format!("=_{}", ObjectId::new().to_hex());
+                data.push_str(&format!(
+                    "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+                    boundary
+                ));
+                data.push_str(&format!("--{}\r\n", boundary));
+                push_part(&mut data, "text/plain", text);
+                data.push_str(&format!("--{}\r\n", boundary));
+                push_part(&mut data, "text/html", html);
+                data.push_str(&format!("--{}--", boundary));
+            }
+        }
+
+        data
+    }
+}
+
+#[async_trait]
+impl Transport for Smtp {
+    async fn send(&self, message: &Message) -> Result<()> {
+        if message.to.contains(['\r', '\n']) {
+            return Err(MailError::LineBreak("recipient").into());
+        }
+        if message.subject.contains(['\r', '\n']) {
+            return Err(MailError::LineBreak("subject").into());
+        }
+
+        let (text, html) = match &message.body {
+            Body::Template { name, vars } => {
+                let rendered = self.templates.render(name, vars)?;
+                (rendered.text, rendered.html)
+            }
+        };
+        let data = self.format(message, &text, html.as_deref(), Utc::now());
+
+        tokio::time::timeout(TIMEOUT, self.deliver(&message.to, &data))
+            .await
+            .map_err(|_| MailError::Timeout)??;
+
+        Ok(())
+    }
+}
+
+/// Encodes non-ASCII header values as RFC 2047 encoded word
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", base64::encode(value))
+    }
+}
+
+fn push_part(data: &mut String, content_type: &str, content: &str) {
+    data.push_str(&format!(
+        "Content-Type: {}; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+        content_type
+    ));
+
+    let encoded = base64::encode(content);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        data.push_str(std::str::from_utf8(line).unwrap());
+        data.push_str("\r\n");
+    }
+}
+
+struct Connection {
+    stream: BufReader<Box<dyn Io>>,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Io>) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    fn into_inner(self) -> Box<dyn Io> {
+        self.stream.into_inner()
+    }
+
+    async fn command(&mut self, line: &str, class: u16) -> std::result::Result<(), MailError> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .await
+            .map_err(MailError::Connection)?;
+        stream.flush().await.map_err(MailError::Connection)?;
+
+        self.reply(class).await
+    }
+
+    /// Reads a possibly multiline reply, which has to be of the class, e.g. 2 for 2xx
+    async fn reply(&mut self, class: u16) -> std::result::Result<(), MailError> {
+        let mut text = Vec::new();
+
+        let code = loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(MailError::Connection)?;
+
+            let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+            let code = match (read, code) {
+                (0, _) | (_, None) => {
+                    return Err(MailError::Connection(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "malformed SMTP reply",
+                    )))
+                }
+                (_, Some(code)) => code,
+            };
+
+            text.push(line.get(4..).unwrap_or_default().trim_end().to_string());
+
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break code;
+            }
+        };
+
+        if code / 100 != class {
+            return Err(MailError::Rejected(code, text.join(" ")));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use tokio::net::TcpListener;
+
+    /// Accepts one message and returns the received lines
+    async fn serve(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+        let mut lines = Vec::new();
+        let mut data = false;
+
+        write.write_all(b"220 test ESMTP\r\n").await.unwrap();
+
+        loop {
+            let mut line = String::new();
+            if read.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+
+            let reply = if data {
+                if line == "." {
+                    data = false;
+                    "250 queued\r\n"
+                } else {
+                    ""
+                }
+            } else {
+                match line.split(' ').next().unwrap() {
+                    "EHLO" => "250-test\r\n250 AUTH PLAIN\r\n",
+                    "AUTH" => "235 authenticated\r\n",
+                    "DATA" => {
+                        data = true;
+                        "354 go ahead\r\n"
+                    }
+                    "QUIT" => "221 bye\r\n",
+                    _ => "250 ok\r\n",
+                }
+            };
+
+            write.write_all(reply.as_bytes()).await.unwrap();
+            let quit = line == "QUIT";
+            lines.push(line);
+            if quit {
+                break;
+            }
+        }
+
+        lines
+    }
+
+    #[tokio::test]
+    async fn submits_rendered_template() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve(listener));
+
+        let mut templates = Templates::default();
+        templates.insert_text("identity.action.login", "Token: {{token}}".to_string());
+
+        let smtp = Smtp::new(
+            "127.0.0.1",
+            Some(port),
+            SmtpTls::None,
+            "identity@example.com",
+            templates,
+        )
+        .unwrap()
+        .with_credentials("user", "pass");
+
+        let mut vars = HashMap::new();
+        vars.insert("token".to_string(), "abc".to_string());
+        let message = Message {
+            to: "a@b.c".to_string(),
+            subject: "Anmeldung bestätigen".to_string(),
+            body: Body::Template {
+                name: "identity.action.login".to_string(),
+                vars,
+            },
+        };
+        smtp.send(&message).await.unwrap();
+
+        let lines = server.await.unwrap();
+        assert_eq!(lines[0], "EHLO example.com");
+        assert_eq!(
+            lines[1],
+            format!("AUTH PLAIN {}", base64::encode("\0user\0pass"))
+        );
+        assert_eq!(lines[2], "MAIL FROM:<identity@example.com>");
+        assert_eq!(lines[3], "RCPT TO:<a@b.c>");
+        assert!(lines.contains(&format!(
+            "Subject: =?utf-8?B?{}?=",
+            base64::encode("Anmeldung bestätigen")
+        )));
+
+        let body = lines
+            .iter()
+            .skip_while(|l| !l.is_empty())
+            .skip(1)
+            .take_while(|l| *l != ".")
+            .cloned()
+            .collect::<String>();
+        assert_eq!(base64::decode(body).unwrap(), b"Token: abc");
+        assert_eq!(lines.last().unwrap(), "QUIT");
+
+        let injected = Message {
+            to: "a@b.c\r\nBcc: x@y.z".to_string(),
+            ..message
+        };
+        assert!(smtp.send(&injected).await.is_err());
+    }
+}
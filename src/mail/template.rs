@@ -0,0 +1,141 @@
+//! Local mail templates for transports that can't render them.
+//!
+//! A template consists of `<name>.txt` and optionally `<name>.html` in the template directory,
+//! e.g. `identity.action.login.txt`. Placeholders use the Handlebars syntax of the Mailgun
+//! templates: `{{token}}` is HTML-escaped in HTML parts, `{{{token}}}` is inserted as is.
+//! Unknown variables are left empty.
+
+use super::MailError;
+
+use crate::utils::push_escaped;
+
+use std::{collections::HashMap, fs, path::Path};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    pub html: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    text: HashMap<String, String>,
+    html: HashMap<String, String>,
+}
+
+impl Templates {
+    /// Reads all templates of the directory, so that missing files fail at startup
+    pub fn load<P>(dir: P) -> Result<Self, MailError>
+    where
+        P: AsRef<Path>,
+    {
+        let mut templates = Self::default();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let (name, ext) = match (path.file_stem(), path.extension()) {
+                (Some(name), Some(ext)) => (name.to_string_lossy(), ext.to_string_lossy()),
+                _ => continue,
+            };
+
+            match ext.as_ref() {
+                "txt" => templates.insert_text(&name, fs::read_to_string(&path)?),
+                "html" => templates.insert_html(&name, fs::read_to_string(&path)?),
+                _ => {}
+            }
+        }
+
+        Ok(templates)
+    }
+
+    pub fn insert_text(&mut self, name: &str, source: String) {
+        self.text.insert(name.to_string(), source);
+    }
+
+    pub fn insert_html(&mut self, name: &str, source: String) {
+        self.html.insert(name.to_string(), source);
+    }
+
+    pub fn render(
+        &self,
+        name: &str,
+        vars: &HashMap<String, String>,
+    ) -> Result<Rendered, MailError> {
+        let text = self
+            .text
+            .get(name)
+            .ok_or_else(|| MailError::TemplateNotFound(name.to_string()))?;
+
+        Ok(Rendered {
+            text: render(text, vars, false),
+            html: self.html.get(name).map(|h| render(h, vars, true)),
+        })
+    }
+}
+
+fn render(source: &str, vars: &HashMap<String, String>, escape: bool) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+
+        let (raw, open, close) = if tail.starts_with("{{{") {
+            (true, 3, "}}}")
+        } else {
+            (false, 2, "}}")
+        };
+
+        let end = match tail[open..].find(close) {
+            Some(end) => end,
+            // Not a placeholder
+            None => {
+                out.push_str(tail);
+                return out;
+            }
+        };
+
+        let name = tail[open..open + end].trim();
+        if let Some(value) = vars.get(name) {
+            if escape && !raw {
+                push_escaped(&mut out, value);
+            } else {
+                out.push_str(value);
+            }
+        }
+
+        rest = &tail[open + end + close.len()..];
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_placeholders() {
+        let mut templates = Templates::default();
+        templates.insert_text("login", "Sign in: {{ token }}{{missing}} {{".to_string());
+        templates.insert_html("login", "<a href=\"{{{url}}}\">{{token}}</a>".to_string());
+
+        let mut vars = HashMap::new();
+        vars.insert("token".to_string(), "<a&b>".to_string());
+        vars.insert("url".to_string(), "https://x/?a=1&b=2".to_string());
+
+        let rendered = templates.render("login", &vars).unwrap();
+        assert_eq!(rendered.text, "Sign in: <a&b> {{");
+        assert_eq!(
+            rendered.html.unwrap(),
+            "<a href=\"https://x/?a=1&b=2\">&lt;a&amp;b&gt;</a>"
+        );
+
+        assert!(matches!(
+            templates.render("unknown", &vars),
+            Err(MailError::TemplateNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,549 @@
+//! Prometheus metrics of requests, SSO logins, tokens and database operations.
+//!
+//! The registry is kept in memory and rendered in the text exposition format under
+//! `/metrics`. Object IDs in paths are replaced by `:id` and the paths of requests answered
+//! with 404 or 405 are dropped, so that the number of series stays bounded.
+//!
+//! Builds with `--cfg tokio_unstable` add the worker and queue metrics of the Tokio runtime,
+//! read at each scrape.
+//!
+//! Without the `metrics` feature nothing is recorded and the endpoint is not mounted, the
+//! recording calls of the other modules stay in place as no-ops.
+
+#[cfg(feature = "metrics")]
+use crate::sso::callback_provider;
+
+use std::{collections::BTreeMap, time::Duration};
+#[cfg(feature = "metrics")]
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+#[cfg(feature = "metrics")]
+use axum::{
+    extract::Extension,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    TypedHeader,
+};
+#[cfg(feature = "metrics")]
+use headers::{authorization::Bearer, Authorization};
+#[cfg(feature = "metrics")]
+use hyper::{header::CONTENT_TYPE, Request, StatusCode};
+
+/// Upper bounds of the latency buckets in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+
+        for (count, bound) in self.counts.iter_mut().zip(BUCKETS) {
+            if secs <= bound {
+                *count += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    #[cfg(feature = "metrics")]
+    requests: BTreeMap<(String, String, u16), u64>,
+    #[cfg(feature = "metrics")]
+    request_duration: BTreeMap<(String, String), Histogram>,
+    #[cfg(feature = "metrics")]
+    sso_logins: BTreeMap<(String, &'static str), u64>,
+    legacy_tokens: BTreeMap<(String, &'static str), u64>,
+    tokens_issued: BTreeMap<String, u64>,
+    tokens_validated: BTreeMap<(String, &'static str), u64>,
+    database_duration: BTreeMap<(String, &'static str), Histogram>,
+    orphans: BTreeMap<(String, &'static str), u64>,
+    timestamp_updates: BTreeMap<&'static str, u64>,
+    timestamp_flush_lag: Histogram,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Metrics(#[cfg(feature = "metrics")] Arc<Mutex<Registry>>);
+
+impl Metrics {
+    /// Applies the change to the registry, which only exists with the `metrics` feature
+    fn update(&self, f: impl FnOnce(&mut Registry)) {
+        #[cfg(feature = "metrics")]
+        f(&mut self.0.lock().unwrap());
+        #[cfg(not(feature = "metrics"))]
+        let _ = f;
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn record_request(&self, method: &str, path: &str, status: u16, duration: Duration) {
+        self.update(|registry| {
+            *registry
+                .requests
+                .entry((method.to_string(), path.to_string(), status))
+                .or_default() += 1;
+            registry
+                .request_duration
+                .entry((method.to_string(), path.to_string()))
+                .or_default()
+                .observe(duration);
+        });
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn record_sso_login(&self, provider: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+
+        self.update(|registry| {
+            *registry
+                .sso_logins
+                .entry((provider.to_string(), result))
+                .or_default() += 1;
+        });
+    }
+
+    /// Counts tokens issued through the bespoke `/v1/token` flow, so that its remaining
+    /// callers can be migrated
+    pub fn record_legacy_token(&self, client: &str, grant: &'static str) {
+        self.update(|registry| {
+            *registry
+                .legacy_tokens
+                .entry((client.to_string(), grant))
+                .or_default() += 1;
+        });
+    }
+
+    /// Counts signed tokens by the region of this instance
+    pub fn record_token_issued(&self, region: &str) {
+        self.update(|registry| {
+            *registry
+                .tokens_issued
+                .entry(region.to_string())
+                .or_default() += 1;
+        });
+    }
+
+    /// Counts tokens validated for requests by the region that issued them
+    pub fn record_token_validation(&self, region: &str, valid: bool) {
+        let result = if valid { "valid" } else { "invalid" };
+
+        self.update(|registry| {
+            *registry
+                .tokens_validated
+                .entry((region.to_string(), result))
+                .or_default() += 1;
+        });
+    }
+
+    pub fn record_database(&self, command: &str, success: bool, duration: Duration) {
+        let result = if success { "success" } else { "failure" };
+
+        self.update(|registry| {
+            registry
+                .database_duration
+                .entry((command.to_string(), result))
+                .or_default()
+                .observe(duration);
+        });
+    }
+
+    /// Sets the number of orphaned documents found by the last integrity check
+    pub fn set_orphans(&self, realm: &str, kind: &'static str, count: u64) {
+        self.update(|registry| {
+            registry.orphans.insert((realm.to_string(), kind), count);
+        });
+    }
+
+    /// Counts timestamp updates written behind by outcome: written, failed or dropped
+    pub fn record_timestamp_update(&self, outcome: &'static str) {
+        self.update(|registry| {
+            *registry.timestamp_updates.entry(outcome).or_default() += 1;
+        });
+    }
+
+    /// Records the age of the oldest update of a flush
+    pub fn record_timestamp_flush(&self, lag: Duration) {
+        self.update(|registry| registry.timestamp_flush_lag.observe(lag));
+    }
+
+    /// Renders all metrics in the Prometheus text format
+    #[cfg(feature = "metrics")]
+    pub fn render(&self) -> String {
+        let registry = self.0.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP identity_http_requests_total Handled HTTP requests\n");
+        out.push_str("# TYPE identity_http_requests_total counter\n");
+        for ((method, path, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "identity_http_requests_total{{method=\"{}\",path=\"{}\",status=\"{}\"}} {}",
+                method, path, status, count
+            );
+        }
+
+        out.push_str("# HELP identity_http_request_duration_seconds Latency of HTTP requests\n");
+        out.push_str("# TYPE identity_http_request_duration_seconds histogram\n");
+        for ((method, path), histogram) in &registry.request_duration {
+            let labels = format!("method=\"{}\",path=\"{}\"", method, path);
+            histogram.render(&mut out, "identity_http_request_duration_seconds", &labels);
+        }
+
+        out.push_str("# HELP identity_sso_logins_total Completed SSO callbacks by provider\n");
+        out.push_str("# TYPE identity_sso_logins_total counter\n");
+        for ((provider, result), count) in &registry.sso_logins {
+            let _ = writeln!(
+                out,
+                "identity_sso_logins_total{{provider=\"{}\",result=\"{}\"}} {}",
+                provider, result, count
+            );
+        }
+
+        out.push_str(
+            "# HELP identity_legacy_token_issuance_total Tokens issued through the legacy flow by client\n",
+        );
+        out.push_str("# TYPE identity_legacy_token_issuance_total counter\n");
+        for ((client, grant), count) in &registry.legacy_tokens {
+            let _ = writeln!(
+                out,
+                "identity_legacy_token_issuance_total{{client=\"{}\",grant=\"{}\"}} {}",
+                client, grant, count
+            );
+        }
+
+        out.push_str("# HELP identity_tokens_issued_total Signed tokens by region\n");
+        out.push_str("# TYPE identity_tokens_issued_total counter\n");
+        for (region, count) in &registry.tokens_issued {
+            let _ = writeln!(
+                out,
+                "identity_tokens_issued_total{{region=\"{}\"}} {}",
+                region, count
+            );
+        }
+
+        out.push_str(
+            "# HELP identity_tokens_validated_total Tokens of requests by issuing region\n",
+        );
+        out.push_str("# TYPE identity_tokens_validated_total counter\n");
+        for ((region, result), count) in &registry.tokens_validated {
+            let _ = writeln!(
+                out,
+                "identity_tokens_validated_total{{region=\"{}\",result=\"{}\"}} {}",
+                region, result, count
+            );
+        }
+
+        out.push_str(
+            "# HELP identity_mongodb_command_duration_seconds Duration of MongoDB commands\n",
+        );
+        out.push_str("# TYPE identity_mongodb_command_duration_seconds histogram\n");
+        for ((command, result), histogram) in &registry.database_duration {
+            let labels = format!("command=\"{}\",result=\"{}\"", command, result);
+            histogram.render(
+                &mut out,
+                "identity_mongodb_command_duration_seconds",
+                &labels,
+            );
+        }
+
+        out.push_str(
+            "# HELP identity_orphaned_documents Documents referencing deleted documents, by realm\n",
+        );
+        out.push_str("# TYPE identity_orphaned_documents gauge\n");
+        for ((realm, kind), count) in &registry.orphans {
+            let _ = writeln!(
+                out,
+                "identity_orphaned_documents{{realm=\"{}\",kind=\"{}\"}} {}",
+                realm, kind, count
+            );
+        }
+
+        out.push_str(
+            "# HELP identity_timestamp_updates_total Timestamp updates written behind by outcome\n",
+        );
+        out.push_str("# TYPE identity_timestamp_updates_total counter\n");
+        for (outcome, count) in &registry.timestamp_updates {
+            let _ = writeln!(
+                out,
+                "identity_timestamp_updates_total{{outcome=\"{}\"}} {}",
+                outcome, count
+            );
+        }
+
+        if registry.timestamp_flush_lag.count > 0 {
+            out.push_str(
+                "# HELP identity_timestamp_flush_lag_seconds Age of the oldest update of a flush\n",
+            );
+            out.push_str("# TYPE identity_timestamp_flush_lag_seconds histogram\n");
+            registry.timestamp_flush_lag.render(
+                &mut out,
+                "identity_timestamp_flush_lag_seconds",
+                "",
+            );
+        }
+
+        out
+    }
+}
+
+/// Replaces object IDs, so that all requests of a route share a series
+#[cfg(feature = "metrics")]
+fn normalize_path(path: &str) -> String {
+    path.split('/')
+        .map(|s| {
+            if s.len() == 24 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+                ":id"
+            } else {
+                s
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Middleware that counts requests and records their latency
+#[cfg(feature = "metrics")]
+pub async fn track_requests<B>(req: Request<B>, next: Next<B>, metrics: Metrics) -> Response {
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let duration = start.elapsed();
+
+    let status = res.status();
+    let label = if status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED {
+        "unmatched".to_string()
+    } else {
+        normalize_path(&path)
+    };
+    metrics.record_request(&method, &label, status.as_u16(), duration);
+
+    if let Some(provider) = callback_provider(&path) {
+        if status != StatusCode::NOT_FOUND {
+            metrics.record_sso_login(
+                provider,
+                !(status.is_client_error() || status.is_server_error()),
+            );
+        }
+    }
+
+    res
+}
+
+/// Writes a series per worker of the runtime
+#[cfg(all(feature = "metrics", tokio_unstable))]
+fn render_workers<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    workers: usize,
+    value: impl Fn(usize) -> T,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for worker in 0..workers {
+        let _ = writeln!(out, "{}{{worker=\"{}\"}} {}", name, worker, value(worker));
+    }
+}
+
+/// Metrics of the Tokio runtime that serves the request
+#[cfg(all(feature = "metrics", tokio_unstable))]
+fn render_runtime(out: &mut String) {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    let workers = runtime.num_workers();
+
+    out.push_str("# HELP identity_tokio_workers Worker threads of the runtime\n");
+    out.push_str("# TYPE identity_tokio_workers gauge\n");
+    let _ = writeln!(out, "identity_tokio_workers {}", workers);
+
+    render_workers(
+        out,
+        "identity_tokio_worker_busy_seconds_total",
+        "counter",
+        "Time the worker spent polling tasks",
+        workers,
+        |i| runtime.worker_total_busy_duration(i).as_secs_f64(),
+    );
+    render_workers(
+        out,
+        "identity_tokio_worker_polls_total",
+        "counter",
+        "Tasks polled by the worker",
+        workers,
+        |i| runtime.worker_poll_count(i),
+    );
+    render_workers(
+        out,
+        "identity_tokio_worker_steals_total",
+        "counter",
+        "Tasks the worker stole from other workers",
+        workers,
+        |i| runtime.worker_steal_count(i),
+    );
+    render_workers(
+        out,
+        "identity_tokio_worker_parks_total",
+        "counter",
+        "Times the worker parked without work",
+        workers,
+        |i| runtime.worker_park_count(i),
+    );
+    render_workers(
+        out,
+        "identity_tokio_worker_overflows_total",
+        "counter",
+        "Times the local queue of the worker overflowed into the injection queue",
+        workers,
+        |i| runtime.worker_overflow_count(i),
+    );
+    render_workers(
+        out,
+        "identity_tokio_worker_local_queue_depth",
+        "gauge",
+        "Tasks in the local queue of the worker",
+        workers,
+        |i| runtime.worker_local_queue_depth(i),
+    );
+
+    out.push_str(
+        "# HELP identity_tokio_remote_schedules_total Tasks scheduled from outside the runtime\n",
+    );
+    out.push_str("# TYPE identity_tokio_remote_schedules_total counter\n");
+    let _ = writeln!(
+        out,
+        "identity_tokio_remote_schedules_total {}",
+        runtime.remote_schedule_count()
+    );
+
+    out.push_str("# HELP identity_tokio_injection_queue_depth Tasks in the injection queue\n");
+    out.push_str("# TYPE identity_tokio_injection_queue_depth gauge\n");
+    let _ = writeln!(
+        out,
+        "identity_tokio_injection_queue_depth {}",
+        runtime.injection_queue_depth()
+    );
+}
+
+/// Token expected from scrapers, `/metrics` is public if not set
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct MetricsToken(pub Option<String>);
+
+#[cfg(feature = "metrics")]
+pub async fn handler(
+    auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Extension(metrics): Extension<Metrics>,
+    Extension(MetricsToken(token)): Extension<MetricsToken>,
+) -> Response {
+    if let Some(token) = token {
+        let valid = auth.map_or(false, |TypedHeader(Authorization(bearer))| {
+            ring::constant_time::verify_slices_are_equal(
+                bearer.token().as_bytes(),
+                token.as_bytes(),
+            )
+            .is_ok()
+        });
+        if !valid {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    #[allow(unused_mut)]
+    let mut out = metrics.render();
+    #[cfg(tokio_unstable)]
+    render_runtime(&mut out);
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_series() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/v1/user/:id", 200, Duration::from_millis(20));
+        metrics.record_request("GET", "/v1/user/:id", 200, Duration::from_millis(300));
+        metrics.record_sso_login("github", false);
+        metrics.record_legacy_token("62a3c0a5e2a1f3b4c5d6e7f8", "service");
+        metrics.record_token_issued("eu");
+        metrics.record_token_validation("us", false);
+        metrics.record_database("find", true, Duration::from_millis(2));
+        metrics.record_timestamp_update("dropped");
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "identity_http_requests_total{method=\"GET\",path=\"/v1/user/:id\",status=\"200\"} 2"
+        ));
+        assert!(out.contains(
+            "identity_http_request_duration_seconds_bucket{method=\"GET\",path=\"/v1/user/:id\",le=\"0.025\"} 1"
+        ));
+        assert!(out.contains(
+            "identity_http_request_duration_seconds_count{method=\"GET\",path=\"/v1/user/:id\"} 2"
+        ));
+        assert!(out.contains("identity_sso_logins_total{provider=\"github\",result=\"failure\"} 1"));
+        assert!(out.contains(
+            "identity_legacy_token_issuance_total{client=\"62a3c0a5e2a1f3b4c5d6e7f8\",grant=\"service\"} 1"
+        ));
+        assert!(out.contains("identity_tokens_issued_total{region=\"eu\"} 1"));
+        assert!(out.contains("identity_tokens_validated_total{region=\"us\",result=\"invalid\"} 1"));
+        assert!(out.contains(
+            "identity_mongodb_command_duration_seconds_bucket{command=\"find\",result=\"success\",le=\"0.005\"} 1"
+        ));
+        assert!(out.contains("identity_timestamp_updates_total{outcome=\"dropped\"} 1"));
+        assert!(!out.contains("identity_timestamp_flush_lag_seconds"));
+    }
+
+    #[cfg(tokio_unstable)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn renders_runtime() {
+        let mut out = String::new();
+        render_runtime(&mut out);
+
+        assert!(out.contains("identity_tokio_workers 2\n"));
+        assert!(out.contains("identity_tokio_worker_polls_total{worker=\"1\"}"));
+        assert!(out.contains("identity_tokio_injection_queue_depth "));
+    }
+
+    #[test]
+    fn paths_are_bounded() {
+        assert_eq!(
+            normalize_path("/v1/user/62a3c0a5e2a1f3b4c5d6e7f8/revert/62a3c0a5e2a1f3b4c5d6e7f9"),
+            "/v1/user/:id/revert/:id"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{database::Database, utils::crypto::Aead256, Result};
+
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime},
+    error::{ErrorKind, WriteFailure},
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Changes of the schema by version, each is applied once and in order
+const MIGRATIONS: &[(u32, &str)] = &[
+    (1, "unique email addresses of users"),
+    (2, "users by connected account"),
+    (3, "clients by user"),
+    (4, "unique token IDs of sessions"),
+];
+
+/// Migration that was applied to the database
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MigrationDocument {
+    #[serde(rename = "_id")]
+    version: u32,
+    description: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    applied_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "_migrations";
+
+/// Code of a write error on a duplicate key
+const DUPLICATE_KEY: i32 = 11000;
+
+async fn apply(db: &Database, version: u32) -> Result<()> {
+    match version {
+        1 => db.index_user_emails().await,
+        2 => db.index_user_connections().await,
+        3 => db.index_client_users().await,
+        4 => db.index_session_tokens().await,
+        _ => unreachable!("migration {} is not defined", version),
+    }
+}
+
+/// Applies the migrations the database is missing.
+///
+/// Instances that start at the same time may apply a migration twice, so each has to be
+/// idempotent, like creating an index.
+pub async fn migrate(db: &Database) -> Result<()> {
+    let coll = db.collection::<MigrationDocument>(COLLECTION);
+    let applied: Vec<u32> = coll
+        .find(None, None)
+        .await?
+        .map_ok(|doc| doc.version)
+        .try_collect()
+        .await?;
+
+    for (version, description) in MIGRATIONS {
+        if applied.contains(version) {
+            continue;
+        }
+
+        apply(db, *version).await?;
+
+        let doc = MigrationDocument {
+            version: *version,
+            description: description.to_string(),
+            applied_at: Utc::now(),
+        };
+        match coll.insert_one(doc, None).await {
+            Ok(_) => {}
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == DUPLICATE_KEY => {}
+                _ => return Err(e.into()),
+            },
+        }
+        info!(version, description, realm = ?db.realm(), "migration applied");
+    }
+
+    Ok(())
+}
+
+/// Re-encrypts all encrypted fields with the current key.
+///
+/// Previous keys can be removed from the configuration once this has completed.
+pub async fn reencrypt(db: &Database, enc: &Aead256) -> Result<()> {
+    let services = db.reencrypt_service_secrets(enc).await?;
+    info!(count = services, "re-encrypted service secrets");
+
+    let users = db.reencrypt_provider_tokens(enc).await?;
+    info!(count = users, "re-encrypted provider tokens");
+
+    let users = db.reencrypt_mfa_secrets(enc).await?;
+    info!(count = users, "re-encrypted TOTP secrets");
+
+    let keys = db.reencrypt_token_keys(enc).await?;
+    info!(count = keys, "re-encrypted token signing keys");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_ascending() {
+        let versions: Vec<_> = MIGRATIONS.iter().map(|(v, _)| *v).collect();
+        let expected: Vec<_> = (1..=MIGRATIONS.len() as u32).collect();
+
+        assert_eq!(versions, expected);
+    }
+}
@@ -1,6 +1,8 @@
 use hyper::StatusCode;
 use mongodb::bson::Document;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Deserializer};
+
+pub use crate::models::{List, Status};
 
 #[derive(Debug)]
 pub struct Response<T>(StatusCode, T)
@@ -34,26 +36,6 @@ where
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Status {
-    #[serde(serialize_with = "se_status_code_as_u16")]
-    pub code: StatusCode,
-    pub message: String,
-}
-
-impl Status {
-    pub fn new<S>(code: StatusCode, message: S) -> Self
-    where
-        S: ToString,
-    {
-        Self {
-            code,
-            message: message.to_string(),
-        }
-    }
-}
-
 impl axum::response::IntoResponse for Status {
     fn into_response(self) -> axum::response::Response {
         let mut res = axum::Json(&self).into_response();
@@ -63,26 +45,6 @@ impl axum::response::IntoResponse for Status {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct List<T: Serialize> {
-    total: u64,
-    data: Vec<T>,
-}
-
-impl<T: Serialize> List<T> {
-    pub fn new<D>(total: u64, data: D) -> Self
-    where
-        D: IntoIterator,
-        D::Item: Into<T>,
-    {
-        Self {
-            total,
-            data: data.into_iter().map(|d| d.into()).collect(),
-        }
-    }
-}
-
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ListOptions {
@@ -134,10 +96,3 @@ where
 
     Ok(output)
 }
-
-fn se_status_code_as_u16<S>(x: &StatusCode, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    s.serialize_u16(x.as_u16())
-}
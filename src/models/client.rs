@@ -0,0 +1,113 @@
+use super::{AccessSchedule, Labels};
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use url::Url;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientResponse {
+    pub id: String,
+    pub user: String,
+    pub service: String,
+    pub name: String,
+    pub scope: Vec<String>,
+    pub unlocked: bool,
+    pub labels: Labels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation_url: Option<Url>,
+    /// Redirect URIs of the authorization code grant
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redirect_uris: Vec<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<AccessSchedule>,
+    #[serde(with = "ts_seconds")]
+    pub confirmed_at: DateTime<Utc>,
+    pub unconfirmed: bool,
+    /// Tokens of the legacy `/v1/token` flow are rejected
+    #[serde(default)]
+    pub legacy_token_disabled: bool,
+    /// Planted credentials that raise an alert when used
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub canary: bool,
+    /// A secret for the client credentials grant is set
+    #[serde(default)]
+    pub has_secret: bool,
+    #[serde(with = "ts_seconds")]
+    pub last_issued: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub last_modified: DateTime<Utc>,
+}
+
+/// New secret of a client, only returned once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretResponse {
+    pub client: String,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRequest {
+    pub user: Option<String>,
+    pub name: String,
+    pub service: String,
+    pub scope: Option<Vec<String>>,
+    #[serde(default)]
+    pub labels: Labels,
+    pub description: Option<String>,
+    pub contact: Option<String>,
+    pub documentation_url: Option<Url>,
+    #[serde(default)]
+    pub redirect_uris: Vec<Url>,
+    /// Creates planted credentials that raise an alert when used, requires global client access
+    #[serde(default)]
+    pub canary: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unlocked: Option<bool>,
+    /// Opts the client out of the legacy `/v1/token` flow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legacy_token_disabled: Option<bool>,
+    /// Replaces the access schedule, `null` removes it
+    #[serde(
+        default,
+        deserialize_with = "de_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub schedule: Option<Option<AccessSchedule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Labels>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation_url: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_uris: Option<Vec<Url>>,
+}
+
+/// Distinguishes a present `null` from a missing field
+fn de_present<'de, D, T>(d: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(d).map(Some)
+}
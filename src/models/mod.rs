@@ -0,0 +1,98 @@
+//! Request and response types of the API.
+//!
+//! They only depend on serde, chrono, http and url, so downstream services can share them
+//! without the server: `default-features = false, features = ["models"]`.
+
+pub mod client;
+pub mod schedule;
+pub mod session;
+pub mod user;
+
+use std::collections::BTreeMap;
+
+use http::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub use client::ClientResponse;
+pub use schedule::AccessSchedule;
+pub use session::SessionResponse;
+pub use user::UserResponse;
+
+/// Labels by key, ordered so that equal labels serialize equally
+pub type Labels = BTreeMap<String, String>;
+
+/// Body of error responses and of responses without content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    #[serde(
+        serialize_with = "se_status_code_as_u16",
+        deserialize_with = "de_status_code_from_u16"
+    )]
+    pub code: StatusCode,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new<S>(code: StatusCode, message: S) -> Self
+    where
+        S: ToString,
+    {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct List<T: Serialize> {
+    pub total: u64,
+    pub data: Vec<T>,
+}
+
+impl<T: Serialize> List<T> {
+    pub fn new<D>(total: u64, data: D) -> Self
+    where
+        D: IntoIterator,
+        D::Item: Into<T>,
+    {
+        Self {
+            total,
+            data: data.into_iter().map(|d| d.into()).collect(),
+        }
+    }
+}
+
+fn se_status_code_as_u16<S>(x: &StatusCode, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_u16(x.as_u16())
+}
+
+fn de_status_code_from_u16<'de, D>(d: D) -> Result<StatusCode, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let code = u16::deserialize(d)?;
+
+    StatusCode::from_u16(code).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_code_as_number() {
+        let status = Status::new(StatusCode::NOT_FOUND, "user not found");
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["code"], 404);
+
+        let status: Status = serde_json::from_value(json).unwrap();
+        assert_eq!(status.code, StatusCode::NOT_FOUND);
+    }
+}
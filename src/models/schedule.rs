@@ -0,0 +1,131 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Weekly window in which access is allowed, e.g. weekdays from 08:00 to 18:00
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessSchedule {
+    /// Days on which the window starts, every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Local start time, e.g. `08:00:00`
+    pub start: NaiveTime,
+    /// Local end time, the window ends on the next day if it is before the start
+    pub end: NaiveTime,
+    /// Offset of the local time to UTC, e.g. `+01:00`
+    #[serde(
+        default = "default_offset",
+        serialize_with = "se_offset",
+        deserialize_with = "de_offset"
+    )]
+    pub utc_offset: FixedOffset,
+}
+
+impl AccessSchedule {
+    /// Checks if `now` is within the window
+    pub fn allows(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.utc_offset);
+        let (day, time) = (local.weekday(), local.time());
+        let on = |d: Weekday| self.days.is_empty() || self.days.contains(&d);
+
+        if self.start <= self.end {
+            on(day) && self.start <= time && time < self.end
+        } else {
+            (on(day) && time >= self.start) || (on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+fn default_offset() -> FixedOffset {
+    FixedOffset::east(0)
+}
+
+fn se_offset<S>(x: &FixedOffset, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(&x.to_string())
+}
+
+fn de_offset<'de, D>(d: D) -> Result<FixedOffset, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let invalid = || serde::de::Error::custom(format!("UTC offset {} is invalid", input));
+
+    let (sign, rest) = if let Some(rest) = input.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = input.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+    let (hours, minutes) = rest.split_once(':').ok_or_else(invalid)?;
+    let hours = hours.parse::<u8>().map_err(|_| invalid())?;
+    let minutes = minutes.parse::<u8>().map_err(|_| invalid())?;
+    if hours > 14 || minutes > 59 {
+        return Err(invalid());
+    }
+
+    let secs = i32::from(hours) * 3600 + i32::from(minutes) * 60;
+
+    FixedOffset::east_opt(sign * secs).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn schedule(value: serde_json::Value) -> AccessSchedule {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn allows_within_window() {
+        let schedule = schedule(json!({
+            "days": ["Mon", "Tue", "Wed", "Thu", "Fri"],
+            "start": "08:00:00",
+            "end": "18:00:00",
+            "utcOffset": "+01:00"
+        }));
+
+        // Monday, 2022-06-06
+        assert!(schedule.allows(Utc.ymd(2022, 6, 6).and_hms(7, 0, 0)));
+        assert!(!schedule.allows(Utc.ymd(2022, 6, 6).and_hms(6, 59, 59)));
+        assert!(!schedule.allows(Utc.ymd(2022, 6, 6).and_hms(17, 0, 0)));
+        // Saturday
+        assert!(!schedule.allows(Utc.ymd(2022, 6, 11).and_hms(10, 0, 0)));
+    }
+
+    #[test]
+    fn window_may_end_next_day() {
+        let schedule = schedule(json!({
+            "days": ["Fri"],
+            "start": "22:00:00",
+            "end": "06:00:00"
+        }));
+
+        assert!(schedule.allows(Utc.ymd(2022, 6, 10).and_hms(23, 0, 0)));
+        assert!(schedule.allows(Utc.ymd(2022, 6, 11).and_hms(5, 0, 0)));
+        assert!(!schedule.allows(Utc.ymd(2022, 6, 11).and_hms(23, 0, 0)));
+        assert!(!schedule.allows(Utc.ymd(2022, 6, 10).and_hms(5, 0, 0)));
+        assert_eq!(schedule.utc_offset, FixedOffset::east(0));
+    }
+
+    #[test]
+    fn rejects_invalid_offset() {
+        for offset in ["01:00", "+1", "+15:00", "+01:60"] {
+            let result = serde_json::from_value::<AccessSchedule>(json!({
+                "start": "08:00:00",
+                "end": "18:00:00",
+                "utcOffset": offset
+            }));
+            assert!(result.is_err(), "{}", offset);
+        }
+    }
+}
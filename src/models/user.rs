@@ -0,0 +1,202 @@
+use chrono::{
+    serde::{ts_seconds, ts_seconds_option},
+    DateTime, NaiveDate, Utc,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserResponse {
+    pub id: String,
+    pub email: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// ISO 3166-1 alpha-2 code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// BCP 47 language tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    pub roles: Vec<Role>,
+    pub verified: bool,
+    pub pending: bool,
+    pub connections: Vec<Connection>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<AccountFlag>,
+    /// Password logins are locked after failed attempts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+    /// Unset if an admin locked the account
+    #[serde(default = "default_can_login")]
+    pub can_login: bool,
+    /// Logins are rejected until then
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub banned_until: Option<DateTime<Utc>>,
+    /// Logins require a code of an authenticator app
+    #[serde(default)]
+    pub mfa_enabled: bool,
+    pub last_sessions: Vec<SessionResponse>,
+    #[serde(with = "ts_seconds")]
+    pub last_modified: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollmentResponse {
+    /// Secret for manual entry into the authenticator app
+    pub secret: String,
+    /// Key URI of the secret, the payload of the QR code to scan
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodesResponse {
+    /// Each code signs in once instead of a code of the authenticator app
+    pub recovery_codes: Vec<String>,
+}
+
+const fn default_can_login() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BanRequest {
+    /// End of the ban
+    #[serde(with = "ts_seconds")]
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileRequest {
+    pub name: Option<String>,
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    /// BCP 47 language tag
+    pub locale: Option<String>,
+    /// Set once by the user, only derived claims of the age are shared with services
+    pub birthdate: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElevateRequest {
+    pub role: Role,
+    /// Minutes until the role is taken back
+    pub duration_min: i64,
+    /// Reason of the grant, recorded and sent to the security contacts
+    pub justification: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElevationResponse {
+    pub id: String,
+    pub user: String,
+    pub role: Role,
+    pub justification: String,
+    pub granted_by: String,
+    #[serde(with = "ts_seconds")]
+    pub granted_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApprovalResponse {
+    pub id: String,
+    pub user: String,
+    /// Roles added once approved
+    pub roles: Vec<Role>,
+    pub requested_by: String,
+    #[serde(with = "ts_seconds")]
+    pub requested_at: DateTime<Utc>,
+    /// Set for a temporary grant, the roles are taken back then
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_seconds_option"
+    )]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    UserEditor,
+    UserViewer,
+    ClientEditor,
+    ClientViewer,
+    ServiceEditor,
+    ServiceViewer,
+}
+
+/// Profile fields services may require before users authorize their clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProfileField {
+    Name,
+    Country,
+    Locale,
+    Birthdate,
+}
+
+/// Flags raised by policy checks to mark accounts for review
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountFlag {
+    /// Two-factor authentication is disabled at a connected provider
+    ProviderTwoFactorDisabled,
+    /// The password has to be reset before the next login
+    PasswordResetRequired,
+    /// The next login has to happen through a connected provider
+    RelinkRequired,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum Connection {
+    #[serde(rename_all = "camelCase")]
+    GitHub {
+        user_id: i64,
+        login: String,
+        two_factor_enabled: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Apple {
+        user_id: String,
+        private_email: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Twitch { user_id: String, login: String },
+    #[serde(rename_all = "camelCase")]
+    Steam { steam_id: String },
+    #[serde(rename_all = "camelCase")]
+    Discord {
+        user_id: String,
+        username: String,
+        discriminator: String,
+        mfa_enabled: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Federated { issuer: String, subject: String },
+    /// Fake provider of sandbox deployments
+    #[serde(rename_all = "camelCase")]
+    Sandbox { user_id: String },
+}
@@ -0,0 +1,152 @@
+//! Consents of users to clients, so that repeated authorizations skip the prompt.
+//!
+//! A consent covers the scope the user granted so far, an authorization that requests more
+//! prompts again and extends it.
+
+use crate::{database::Database, Result};
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::{FindOptions, IndexOptions, UpdateOptions},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsentDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    pub client: ObjectId,
+    pub scope: Vec<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub granted_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ConsentDocument {
+    /// Checks if the consent covers the scope
+    pub fn covers(&self, scope: &[String]) -> bool {
+        scope.iter().all(|s| self.scope.contains(s))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrantedConsentResponse {
+    pub client: String,
+    /// Name of the client, unless it was deleted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub scope: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    pub granted_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "consents";
+
+impl Database {
+    /// One consent per user and client
+    pub async fn init_consents(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "user": 1, "client": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.collection::<ConsentDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_consent(
+        &self,
+        user: ObjectId,
+        client: ObjectId,
+    ) -> Result<Option<ConsentDocument>> {
+        let consent = self
+            .collection::<ConsentDocument>(COLLECTION)
+            .find_one(doc! { "user": user, "client": client }, None)
+            .await?;
+
+        Ok(consent)
+    }
+
+    /// Records the consent, extending the scope of an earlier one
+    pub async fn grant_consent(
+        &self,
+        user: ObjectId,
+        client: ObjectId,
+        scope: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let update = doc! {
+            "$addToSet": { "scope": { "$each": scope } },
+            "$set": { "updatedAt": now },
+            "$setOnInsert": { "_id": ObjectId::new(), "grantedAt": now },
+        };
+        let opts = UpdateOptions::builder().upsert(true).build();
+
+        self.collection::<ConsentDocument>(COLLECTION)
+            .update_one(doc! { "user": user, "client": client }, update, opts)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_consents(&self, user: ObjectId) -> Result<Vec<ConsentDocument>> {
+        let opts = FindOptions::builder()
+            .sort(doc! { "grantedAt": -1 })
+            .build();
+
+        let consents = self
+            .collection::<ConsentDocument>(COLLECTION)
+            .find(doc! { "user": user }, opts)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(consents)
+    }
+
+    /// Removes the consent, returns `false` if there was none
+    pub async fn revoke_consent(&self, user: ObjectId, client: ObjectId) -> Result<bool> {
+        let result = self
+            .collection::<ConsentDocument>(COLLECTION)
+            .delete_one(doc! { "user": user, "client": client }, None)
+            .await?;
+
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn covers_granted_scope() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let consent = ConsentDocument {
+            id: ObjectId::new(),
+            user: ObjectId::new(),
+            client: ObjectId::new(),
+            scope: vec!["read".to_string(), "openid".to_string()],
+            granted_at: now,
+            updated_at: now,
+        };
+
+        assert!(consent.covers(&["read".to_string()]));
+        assert!(consent.covers(&[]));
+        assert!(!consent.covers(&["read".to_string(), "write".to_string()]));
+    }
+}
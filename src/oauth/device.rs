@@ -0,0 +1,259 @@
+//! Device authorization grant of RFC 8628, for CLI tools without a browser.
+//!
+//! The device shows a short user code, the user enters it on the verification page and
+//! approves the client with a session. Meanwhile the device polls the token endpoint with the
+//! device code until the approval or the expiry.
+
+use crate::{database::Database, Result};
+
+use super::hash_code;
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+/// Lifetime of a device code
+pub const DEVICE_CODE_TTL_MIN: i64 = 10;
+
+/// Seconds between two polls, raised by five on each poll that comes too early
+pub const POLL_INTERVAL_SEC: i64 = 5;
+
+/// Consonants only, so that codes can't spell words and are easy to type
+const USER_CODE_CHARS: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LEN: usize = 8;
+
+/// Random user code, formatted as `XXXX-XXXX`
+pub fn generate_user_code() -> String {
+    let code: String = (0..USER_CODE_LEN)
+        .map(|_| USER_CODE_CHARS[rand::random::<usize>() % USER_CODE_CHARS.len()] as char)
+        .collect();
+
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+/// Normalizes a user code as entered, ignoring case and separators
+pub fn normalize_user_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl DeviceStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeviceStatus::Pending => "pending",
+            DeviceStatus::Approved => "approved",
+            DeviceStatus::Denied => "denied",
+        }
+    }
+}
+
+/// Device code, identified by its hash
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCodeDocument {
+    #[serde(rename = "_id")]
+    pub id: String,
+    /// Normalized user code
+    pub user_code: String,
+    pub client: ObjectId,
+    pub scope: Vec<String>,
+    pub status: DeviceStatus,
+    /// User that approved or denied the code
+    pub user: Option<ObjectId>,
+    pub interval: i64,
+    pub polled_at: Option<bson::DateTime>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl DeviceCodeDocument {
+    pub fn new(
+        device_code: &str,
+        user_code: &str,
+        client: ObjectId,
+        scope: Vec<String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: hash_code(device_code),
+            user_code: normalize_user_code(user_code),
+            client,
+            scope,
+            status: DeviceStatus::Pending,
+            user: None,
+            interval: POLL_INTERVAL_SEC,
+            polled_at: None,
+            expires_at: now + Duration::minutes(DEVICE_CODE_TTL_MIN),
+        }
+    }
+
+    /// Checks if the device polls faster than its interval
+    pub fn is_too_early(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.polled_at, Some(v) if now - v.to_chrono() < Duration::seconds(self.interval))
+    }
+}
+
+const COLLECTION: &str = "device_codes";
+
+impl Database {
+    /// Removes device codes once they are expired, user codes are unique while pending
+    pub async fn init_device_codes(&self) -> Result<()> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc! { "expiresAt": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(std::time::Duration::ZERO)
+                        .build(),
+                )
+                .build(),
+            IndexModel::builder()
+                .keys(doc! { "userCode": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ];
+
+        self.collection::<DeviceCodeDocument>(COLLECTION)
+            .create_indexes(indexes, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_device_code(&self, doc: &DeviceCodeDocument) -> Result<()> {
+        self.collection::<DeviceCodeDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Pending code for the user code as entered
+    pub async fn get_pending_device_code(
+        &self,
+        user_code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DeviceCodeDocument>> {
+        let filter = doc! {
+            "userCode": normalize_user_code(user_code),
+            "status": "pending",
+            "expiresAt": { "$gt": now },
+        };
+
+        let doc = self
+            .collection::<DeviceCodeDocument>(COLLECTION)
+            .find_one(filter, None)
+            .await?;
+
+        Ok(doc)
+    }
+
+    /// Approves or denies a pending code, returns `false` if it was already decided
+    pub async fn decide_device_code(
+        &self,
+        id: &str,
+        user: ObjectId,
+        status: DeviceStatus,
+    ) -> Result<bool> {
+        let filter = doc! { "_id": id, "status": "pending" };
+        let update = doc! {
+            "$set": {
+                "status": status.as_str(),
+                "user": user,
+            }
+        };
+
+        let result = self
+            .collection::<DeviceCodeDocument>(COLLECTION)
+            .update_one(filter, update, None)
+            .await?;
+
+        Ok(result.modified_count > 0)
+    }
+
+    /// Removes the codes a user approved and those not redeemed yet, so no device signs in with them
+    pub async fn delete_user_device_codes(&self, user: ObjectId) -> Result<()> {
+        self.collection::<DeviceCodeDocument>(COLLECTION)
+            .delete_many(
+                doc! { "user": user, "status": { "$in": ["pending", "approved"] } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a poll of the device and returns the code as it was before
+    pub async fn poll_device_code(
+        &self,
+        device_code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DeviceCodeDocument>> {
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::Before)
+            .build();
+
+        let doc = self
+            .collection::<DeviceCodeDocument>(COLLECTION)
+            .find_one_and_update(
+                doc! { "_id": hash_code(device_code) },
+                doc! { "$set": { "polledAt": now } },
+                opts,
+            )
+            .await?;
+
+        Ok(doc)
+    }
+
+    /// Raises the interval of a device that polls too fast
+    pub async fn slow_down_device_code(&self, id: &str) -> Result<()> {
+        self.collection::<DeviceCodeDocument>(COLLECTION)
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$inc": { "interval": POLL_INTERVAL_SEC } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes an approved code, returns `false` if it was already exchanged
+    pub async fn take_device_code(&self, id: &str) -> Result<bool> {
+        let result = self
+            .collection::<DeviceCodeDocument>(COLLECTION)
+            .delete_one(doc! { "_id": id, "status": "approved" }, None)
+            .await?;
+
+        Ok(result.deleted_count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_codes() {
+        let code = generate_user_code();
+
+        assert_eq!(code.len(), 9);
+        assert_eq!(&code[4..5], "-");
+        assert_eq!(normalize_user_code(&code).len(), USER_CODE_LEN);
+        assert_eq!(normalize_user_code("bcdf - ghjk"), "BCDFGHJK");
+    }
+}
@@ -0,0 +1,859 @@
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    authentication::{client_secret, credential::CredentialHasher, token::TokenConfig},
+    client::{self, ClientDocument, ClientError},
+    config::GlobalConfig,
+    database::Database,
+    error::Error,
+    extract::{ClientInfo, Query, SizedJson, TokenData},
+    model::{List, Response, Status},
+    service::ServiceDocument,
+    session::{check_access, SessionClaims},
+    token::issue_service_token,
+    user::{age_over_claim, ProfileField, SecurityNotifier, UserDocument, UserError},
+    utils::crypto::Aead256,
+};
+
+use super::{
+    consent::GrantedConsentResponse,
+    device::{
+        generate_user_code, DeviceCodeDocument, DeviceStatus, DEVICE_CODE_TTL_MIN,
+        POLL_INTERVAL_SEC,
+    },
+    hash_code, verify_challenge, AuthorizationCodeDocument, IdTokenClaims, OAuthError,
+    DEVICE_CODE_GRANT, OPENID_SCOPE,
+};
+
+use axum::{
+    extract::{Extension, Form, Path, TypedHeader},
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use headers::{authorization::Bearer, Authorization};
+use hyper::{
+    header::{HeaderName, CACHE_CONTROL},
+    StatusCode,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use mongodb::bson::{doc, oid::ObjectId};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// Lifetime of an authorization code
+const CODE_TTL_MIN: i64 = 10;
+
+/// Parameters of an authorization request, named as in RFC 6749
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizeRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: Url,
+    /// Space-separated, the default scope of the service if missing
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: String,
+    code_challenge_method: String,
+    /// Returned in the ID token
+    nonce: Option<String>,
+}
+
+impl AuthorizeRequest {
+    /// Form of the missing profile fields, which resumes the authorization with the parameters
+    fn profile_redirect(&self, profile_url: &Url, fields: &[ProfileField]) -> Url {
+        let fields: Vec<_> = fields.iter().map(ProfileField::as_str).collect();
+
+        let mut url = profile_url.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("fields", &fields.join(","))
+                .append_pair("response_type", &self.response_type)
+                .append_pair("client_id", &self.client_id)
+                .append_pair("redirect_uri", self.redirect_uri.as_str())
+                .append_pair("code_challenge", &self.code_challenge)
+                .append_pair("code_challenge_method", &self.code_challenge_method);
+            for (k, v) in [
+                ("scope", &self.scope),
+                ("state", &self.state),
+                ("nonce", &self.nonce),
+            ] {
+                if let Some(v) = v {
+                    query.append_pair(k, v);
+                }
+            }
+        }
+
+        url
+    }
+}
+
+/// Validates the request, returns the client and the requested scope
+async fn validate_request(
+    db: &Database,
+    global: &GlobalConfig,
+    req: &AuthorizeRequest,
+) -> crate::Result<(ClientDocument, ServiceDocument, Vec<String>)> {
+    if req.response_type != "code" {
+        return Err(OAuthError::InvalidRequest("response type must be code").into());
+    }
+    if req.code_challenge_method != "S256" {
+        return Err(OAuthError::InvalidRequest("code challenge method must be S256").into());
+    }
+    if req.code_challenge.len() != 43 {
+        return Err(OAuthError::InvalidRequest("code challenge is invalid").into());
+    }
+
+    let client_id = ObjectId::parse_str(&req.client_id).map_err(|_| OAuthError::InvalidClient)?;
+    let client = match db.get_client(doc! { "_id": client_id }).await {
+        Ok(c) if !c.canary => c,
+        Ok(_) | Err(Error::Client(ClientError::NotFound)) => {
+            return Err(OAuthError::InvalidClient.into())
+        }
+        Err(e) => return Err(e),
+    };
+    if !client.unlocked {
+        return Err(ClientError::Locked.into());
+    }
+    if !client.redirect_uris.contains(&req.redirect_uri) {
+        return Err(OAuthError::InvalidRequest("redirect URI is not registered").into());
+    }
+
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+
+    let scope = resolve_scope(req.scope.as_deref(), &client, &svc, global)?;
+
+    Ok((client, svc, scope))
+}
+
+/// Requested scope, space-separated, or the default scope of the service within the client's
+fn resolve_scope(
+    requested: Option<&str>,
+    client: &ClientDocument,
+    svc: &ServiceDocument,
+    global: &GlobalConfig,
+) -> crate::Result<Vec<String>> {
+    let scope: Vec<String> = match requested {
+        Some(v) => v.split_whitespace().map(String::from).collect(),
+        None => svc
+            .scope_default
+            .iter()
+            .filter(|s| client.scope.contains(s))
+            .cloned()
+            .collect(),
+    };
+    let openid = scope.iter().any(|s| s == OPENID_SCOPE);
+    if openid && global.issuer_url.is_none() {
+        return Err(OAuthError::InvalidScope.into());
+    }
+    if !scope
+        .iter()
+        .all(|s| s == OPENID_SCOPE || client.scope.contains(s))
+    {
+        return Err(OAuthError::InvalidScope.into());
+    }
+
+    Ok(scope)
+}
+
+/// Client details shown to the user for consent
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsentResponse {
+    client: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    service: String,
+    scope: Vec<String>,
+    /// Whether the user has to be prompted, i.e. the scope exceeds an earlier consent
+    consent_required: bool,
+    /// Fields the user has to fill in first, see [`AuthorizeResponse`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    profile_required: Vec<ProfileField>,
+}
+
+pub async fn get_consent(
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(req): Query<AuthorizeRequest>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<Response<ConsentResponse>> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let (client, svc, scope) = validate_request(&db, &global, &req).await?;
+
+    let consent = db.get_consent(user_id, client.id).await?;
+    let consent_required = !consent.map_or(false, |c| c.covers(&scope));
+
+    let required = svc.required_profile_fields();
+    let profile_required = if required.is_empty() {
+        Vec::new()
+    } else {
+        let user = db.get_user(doc! { "_id": user_id }).await?;
+        user.missing_profile_fields(&required)
+    };
+
+    let response = ConsentResponse {
+        client: client.id.to_hex(),
+        name: client.name,
+        description: client.description,
+        service: svc.name,
+        scope,
+        consent_required,
+        profile_required,
+    };
+
+    Ok(Response::new(response))
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizeResponse {
+    /// Redirect URI of the client with the code and the state, or the profile form if the user
+    /// has to fill in fields required by the service first
+    redirect_uri: Url,
+}
+
+/// Lets the hooks of the deployment veto the authorization of the client
+#[cfg(feature = "hooks")]
+async fn check_consent_hooks(
+    db: &Database,
+    user_id: ObjectId,
+    client: ObjectId,
+    scope: &[String],
+) -> crate::Result<()> {
+    let user = db.get_user(doc! { "_id": user_id }).await?;
+    crate::hooks::pre_consent(&user, &client.to_hex(), scope).await?;
+
+    Ok(())
+}
+
+/// Rejects users below the minimum age of the service, returns the claim derived for the gate
+fn check_age(
+    user: &UserDocument,
+    svc: &ServiceDocument,
+    now: DateTime<Utc>,
+) -> Result<Option<String>, OAuthError> {
+    let min_age = match svc.min_age {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let age = user
+        .age(now.date().naive_utc())
+        .ok_or(OAuthError::ProfileIncomplete)?;
+    if age < u32::from(min_age) {
+        return Err(OAuthError::AgeRestricted);
+    }
+
+    Ok(Some(age_over_claim(min_age)))
+}
+
+/// Authorizes the client on behalf of the user, after the user consented
+pub async fn authorize(
+    info: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(req): SizedJson<AuthorizeRequest>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<AuthorizeResponse>> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+    let now = config.clock.now();
+
+    let (client, svc, scope) = validate_request(&db, &global, &req).await?;
+
+    let required = svc.required_profile_fields();
+    if !required.is_empty() {
+        let user = db.get_user(doc! { "_id": user_id }).await?;
+        let missing = user.missing_profile_fields(&required);
+        if !missing.is_empty() {
+            let profile_url = global
+                .profile_url
+                .as_ref()
+                .ok_or(OAuthError::ProfileIncomplete)?;
+            let redirect_uri = req.profile_redirect(profile_url, &missing);
+
+            return Ok(Response::new(AuthorizeResponse { redirect_uri }));
+        }
+        check_age(&user, &svc, now)?;
+    }
+
+    #[cfg(feature = "hooks")]
+    check_consent_hooks(&db, user_id, client.id, &scope).await?;
+
+    let code = base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD);
+    let doc = AuthorizationCodeDocument {
+        id: hash_code(&code),
+        client: client.id,
+        user: user_id,
+        scope: scope.clone(),
+        redirect_uri: req.redirect_uri.clone(),
+        code_challenge: req.code_challenge,
+        nonce: req.nonce,
+        expires_at: now + Duration::minutes(CODE_TTL_MIN),
+    };
+    db.insert_authorization_code(&doc).await?;
+    db.grant_consent(user_id, client.id, &scope, now).await?;
+
+    let event = SecurityEvent::ClientAuthorized {
+        client: client.id.to_hex(),
+        scope,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user_id, event, &info))
+        .await?;
+
+    let mut redirect_uri = req.redirect_uri;
+    {
+        let mut query = redirect_uri.query_pairs_mut();
+        query.append_pair("code", &code);
+        if let Some(state) = &req.state {
+            query.append_pair("state", state);
+        }
+    }
+
+    Ok(Response::new(AuthorizeResponse { redirect_uri }))
+}
+
+/// Device authorization request, form-encoded as in RFC 8628
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCodeRequest {
+    client_id: String,
+    client_secret: Option<String>,
+    /// Space-separated, the default scope of the service if missing
+    scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: Url,
+    verification_uri_complete: Url,
+    expires_in: i64,
+    interval: i64,
+}
+
+/// Issues a device code, which the device polls with until the user approved it
+pub async fn device_code(
+    Form(req): Form<DeviceCodeRequest>,
+    Extension(db): Extension<Database>,
+    Extension(hasher): Extension<CredentialHasher>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<(
+    StatusCode,
+    [(HeaderName, &'static str); 1],
+    Json<DeviceCodeResponse>,
+)> {
+    let verification_uri = global
+        .device_verification_url
+        .clone()
+        .ok_or(OAuthError::UnsupportedGrantType)?;
+
+    let client =
+        authenticate_client(&db, &hasher, &req.client_id, req.client_secret.as_deref()).await?;
+    if client.canary {
+        return Err(OAuthError::InvalidClient.into());
+    }
+    if !client.unlocked {
+        return Err(ClientError::Locked.into());
+    }
+
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+    let scope = resolve_scope(req.scope.as_deref(), &client, &svc, &global)?;
+
+    let device_code = base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD);
+    let user_code = generate_user_code();
+    db.insert_device_code(&DeviceCodeDocument::new(
+        &device_code,
+        &user_code,
+        client.id,
+        scope,
+        config.clock.now(),
+    ))
+    .await?;
+
+    let mut verification_uri_complete = verification_uri.clone();
+    verification_uri_complete
+        .query_pairs_mut()
+        .append_pair("user_code", &user_code);
+
+    let response = DeviceCodeResponse {
+        device_code,
+        user_code,
+        verification_uri,
+        verification_uri_complete,
+        expires_in: DEVICE_CODE_TTL_MIN * 60,
+        interval: POLL_INTERVAL_SEC,
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(CACHE_CONTROL, "no-store")],
+        Json(response),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceConsentQuery {
+    user_code: String,
+}
+
+/// Client details of a pending user code, for the verification page
+pub async fn get_device_consent(
+    TokenData(_): TokenData<SessionClaims>,
+    Query(query): Query<DeviceConsentQuery>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<ConsentResponse>> {
+    let code = db
+        .get_pending_device_code(&query.user_code, config.clock.now())
+        .await?
+        .ok_or(OAuthError::InvalidGrant("user code is invalid or expired"))?;
+
+    let client = db.get_client(doc! { "_id": code.client }).await?;
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+
+    let response = ConsentResponse {
+        client: client.id.to_hex(),
+        name: client.name,
+        description: client.description,
+        service: svc.name,
+        scope: code.scope,
+        // Device codes may be phished, users confirm each of them
+        consent_required: true,
+        profile_required: Vec::new(),
+    };
+
+    Ok(Response::new(response))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceDecisionRequest {
+    user_code: String,
+    approve: bool,
+}
+
+/// Approves or denies a user code on behalf of the user
+pub async fn decide_device(
+    info: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<DeviceDecisionRequest>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let code = db
+        .get_pending_device_code(&body.user_code, config.clock.now())
+        .await?
+        .ok_or(OAuthError::InvalidGrant("user code is invalid or expired"))?;
+
+    #[cfg(feature = "hooks")]
+    if body.approve {
+        check_consent_hooks(&db, user_id, code.client, &code.scope).await?;
+    }
+
+    let status = if body.approve {
+        DeviceStatus::Approved
+    } else {
+        DeviceStatus::Denied
+    };
+    if !db.decide_device_code(&code.id, user_id, status).await? {
+        return Err(OAuthError::InvalidGrant("user code is invalid or expired").into());
+    }
+
+    if !body.approve {
+        return Ok(Status::new(StatusCode::OK, "device denied"));
+    }
+
+    db.grant_consent(user_id, code.client, &code.scope, config.clock.now())
+        .await?;
+
+    let event = SecurityEvent::ClientAuthorized {
+        client: code.client.to_hex(),
+        scope: code.scope,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user_id, event, &info))
+        .await?;
+
+    Ok(Status::new(StatusCode::OK, "device approved"))
+}
+
+/// Token request, form-encoded as in RFC 6749 and RFC 8628
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    client_id: String,
+    client_secret: Option<String>,
+    code: Option<String>,
+    redirect_uri: Option<Url>,
+    code_verifier: Option<String>,
+    device_code: Option<String>,
+}
+
+/// Looks up the client, confidential clients authenticate with their secret
+async fn authenticate_client(
+    db: &Database,
+    hasher: &CredentialHasher,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> crate::Result<ClientDocument> {
+    let client_id = ObjectId::parse_str(client_id).map_err(|_| OAuthError::InvalidClient)?;
+    let client = match db.get_client(doc! { "_id": client_id }).await {
+        Ok(c) => c,
+        Err(Error::Client(ClientError::NotFound)) => return Err(OAuthError::InvalidClient.into()),
+        Err(e) => return Err(e),
+    };
+
+    if client.secret.is_some() {
+        let secret = client_secret.ok_or(OAuthError::InvalidClient)?;
+        let rehash = client_secret::verify(hasher, secret, client.secret.as_deref())
+            .map_err(|_| OAuthError::InvalidClient)?;
+        if let Some(hash) = rehash {
+            db.rehash_client_secret(client_id, hash).await?;
+        }
+    }
+
+    Ok(client)
+}
+
+/// Authorization of a user that is exchanged for a token
+struct Grant {
+    user: ObjectId,
+    scope: Vec<String>,
+    nonce: Option<String>,
+    /// Name of the grant in the audit log
+    name: &'static str,
+}
+
+async fn exchange_code(
+    db: &Database,
+    client: &ClientDocument,
+    req: TokenRequest,
+    now: DateTime<Utc>,
+) -> crate::Result<Grant> {
+    let (code, redirect_uri, verifier) = match (req.code, req.redirect_uri, req.code_verifier) {
+        (Some(c), Some(r), Some(v)) => (c, r, v),
+        _ => {
+            return Err(OAuthError::InvalidRequest(
+                "code, redirect URI and code verifier are required",
+            )
+            .into())
+        }
+    };
+
+    let code = db
+        .take_authorization_code(&code, now)
+        .await?
+        .ok_or(OAuthError::InvalidGrant("code is invalid or expired"))?;
+    if code.client != client.id || code.redirect_uri != redirect_uri {
+        return Err(OAuthError::InvalidGrant("code was issued for another client").into());
+    }
+    if !verify_challenge(&verifier, &code.code_challenge) {
+        return Err(OAuthError::InvalidGrant("code verifier does not match").into());
+    }
+
+    Ok(Grant {
+        user: code.user,
+        scope: code.scope,
+        nonce: code.nonce,
+        name: "authorizationCode",
+    })
+}
+
+async fn exchange_device_code(
+    db: &Database,
+    client: &ClientDocument,
+    req: TokenRequest,
+    now: DateTime<Utc>,
+) -> crate::Result<Grant> {
+    let device_code = req
+        .device_code
+        .ok_or(OAuthError::InvalidRequest("device code is required"))?;
+
+    let code = db
+        .poll_device_code(&device_code, now)
+        .await?
+        .ok_or(OAuthError::InvalidGrant("device code is invalid"))?;
+    if code.client != client.id {
+        return Err(OAuthError::InvalidGrant("code was issued for another client").into());
+    }
+    if code.expires_at <= now {
+        return Err(OAuthError::ExpiredToken.into());
+    }
+    if code.is_too_early(now) {
+        db.slow_down_device_code(&code.id).await?;
+        return Err(OAuthError::SlowDown.into());
+    }
+
+    let user = match (code.status, code.user) {
+        (DeviceStatus::Pending, _) => return Err(OAuthError::AuthorizationPending.into()),
+        (DeviceStatus::Denied, _) => return Err(OAuthError::AccessDenied.into()),
+        (DeviceStatus::Approved, Some(user)) => user,
+        (DeviceStatus::Approved, None) => {
+            return Err(OAuthError::InvalidGrant("device code is invalid").into())
+        }
+    };
+    if !db.take_device_code(&code.id).await? {
+        return Err(OAuthError::InvalidGrant("device code was already used").into());
+    }
+
+    Ok(Grant {
+        user,
+        scope: code.scope,
+        nonce: None,
+        name: "deviceCode",
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn token(
+    info: ClientInfo,
+    Form(req): Form<TokenRequest>,
+    Extension(db): Extension<Database>,
+    Extension(enc): Extension<Aead256>,
+    Extension(hasher): Extension<CredentialHasher>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<(
+    StatusCode,
+    [(HeaderName, &'static str); 1],
+    Json<TokenResponse>,
+)> {
+    if req.grant_type != "authorization_code" && req.grant_type != DEVICE_CODE_GRANT {
+        return Err(OAuthError::UnsupportedGrantType.into());
+    }
+
+    let client =
+        authenticate_client(&db, &hasher, &req.client_id, req.client_secret.as_deref()).await?;
+    let client_id = client.id;
+
+    let now = config.clock.now();
+    let grant = if req.grant_type == DEVICE_CODE_GRANT {
+        exchange_device_code(&db, &client, req, now).await?
+    } else {
+        exchange_code(&db, &client, req, now).await?
+    };
+
+    if client.canary {
+        client::trip_canary(
+            &db,
+            &notifier,
+            &client,
+            &info,
+            global.canary_auto_lock,
+            config.clock.now(),
+        )
+        .await?;
+        return Err(ClientError::Locked.into());
+    }
+    if !client.unlocked {
+        return Err(ClientError::Locked.into());
+    }
+    db.check_client_schedule(&client, &info, now).await?;
+
+    // The user may have been locked since the authorization
+    let user = db.get_user(doc! { "_id": grant.user }).await?;
+    check_access(&user, now)?;
+
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+    db.ensure_service_alive(&svc, now).await?;
+    // The gate may have been added since the authorization
+    let age_claim = check_age(&user, &svc, now)?;
+
+    let scope = grant.scope.join(" ");
+    let openid = grant.scope.iter().any(|s| s == OPENID_SCOPE);
+    let (access_token, expires_at) = issue_service_token(
+        &enc,
+        &config,
+        svc,
+        &user.id.to_hex(),
+        Some(&client_id.to_hex()),
+        Some(&user),
+        grant.scope,
+    )?;
+
+    let id_token = match &global.issuer_url {
+        Some(iss) if openid => {
+            let mut claims =
+                IdTokenClaims::new(iss, user.id.to_hex(), client_id.to_hex(), grant.nonce, now);
+            claims.age = age_claim.into_iter().map(|c| (c, true)).collect();
+            Some(config.encode(&claims)?)
+        }
+        _ => None,
+    };
+
+    db.set_client_issued(client_id).await?;
+
+    let event = SecurityEvent::TokenIssued {
+        client: client_id.to_hex(),
+        grant: grant.name.to_string(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user.id, event, &info))
+        .await?;
+
+    let response = TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: (expires_at - now).num_seconds(),
+        scope,
+        id_token,
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(CACHE_CONTROL, "no-store")],
+        Json(response),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessClaims {
+    sub: String,
+    azp: Option<String>,
+    #[serde(default)]
+    scope: Vec<String>,
+}
+
+/// Reads the client of an access token without validating it, to select its service key
+fn peek_client(token: &str) -> Option<ObjectId> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    let data =
+        jsonwebtoken::decode::<AccessClaims>(token, &DecodingKey::from_secret(&[]), &validation)
+            .ok()?;
+
+    ObjectId::parse_str(data.claims.azp?).ok()
+}
+
+/// Standard claims of OpenID Connect
+#[derive(Debug, Clone, Serialize)]
+pub struct UserInfoResponse {
+    sub: String,
+    email: String,
+    email_verified: bool,
+}
+
+/// Claims of the user an access token of the authorization code grant was issued for
+pub async fn userinfo(
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Extension(db): Extension<Database>,
+    Extension(enc): Extension<Aead256>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Json<UserInfoResponse>> {
+    if global.issuer_url.is_none() {
+        return Err(OAuthError::InvalidToken.into());
+    }
+
+    let token = bearer.token();
+    let client_id = peek_client(token).ok_or(OAuthError::InvalidToken)?;
+    let client = match db.get_client(doc! { "_id": client_id }).await {
+        Ok(c) => c,
+        Err(Error::Client(ClientError::NotFound)) => return Err(OAuthError::InvalidToken.into()),
+        Err(e) => return Err(e),
+    };
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+
+    // Verified the same way as by the service itself
+    let claims = if let Some(s) = svc.secret {
+        let secret = enc.decrypt(base64::decode_config(&s, base64::STANDARD).unwrap())?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        if svc.audience.is_empty() {
+            validation.aud = config.validation.aud.clone();
+        } else {
+            validation.set_audience(&svc.audience);
+        }
+        jsonwebtoken::decode::<AccessClaims>(token, &DecodingKey::from_secret(&secret), &validation)
+            .ok()
+    } else if !svc.audience.is_empty() {
+        let mut validation = config.validation.clone();
+        validation.set_audience(&svc.audience);
+        config.decode_with::<AccessClaims>(token, &validation).ok()
+    } else {
+        config.decode::<AccessClaims>(token).ok()
+    };
+    let claims = claims.ok_or(OAuthError::InvalidToken)?.claims;
+
+    if claims.azp != Some(client.id.to_hex()) || !claims.scope.iter().any(|s| s == OPENID_SCOPE) {
+        return Err(OAuthError::InvalidToken.into());
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| OAuthError::InvalidToken)?;
+    let user = db.get_user(doc! { "_id": user_id }).await?;
+    check_access(&user, config.clock.now())?;
+
+    let response = UserInfoResponse {
+        sub: user.id.to_hex(),
+        email: user.email,
+        email_verified: user.verified,
+    };
+
+    Ok(Json(response))
+}
+
+pub async fn list_consents(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<GrantedConsentResponse>>> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let consents = db.get_consents(user_id).await?;
+
+    let mut list = Vec::with_capacity(consents.len());
+    for consent in consents {
+        let name = match db.get_client(doc! { "_id": consent.client }).await {
+            Ok(c) => Some(c.name),
+            Err(Error::Client(ClientError::NotFound)) => None,
+            Err(e) => return Err(e),
+        };
+
+        list.push(GrantedConsentResponse {
+            client: consent.client.to_hex(),
+            name,
+            scope: consent.scope,
+            granted_at: consent.granted_at,
+            updated_at: consent.updated_at,
+        });
+    }
+
+    Ok(Response::new(List::new(list.len() as u64, list)))
+}
+
+/// Revokes the consent, the next authorization of the client prompts again
+pub async fn revoke_consent(
+    info: ClientInfo,
+    Path(client): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Status> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+    let client_id = ObjectId::parse_str(&client).map_err(|_| ClientError::InvalidId)?;
+
+    if !db.revoke_consent(user_id, client_id).await? {
+        return Err(ClientError::NotFound.into());
+    }
+
+    let event = SecurityEvent::ConsentRevoked { client };
+    db.insert_audit_event(&AuditEventDocument::new(user_id, event, &info))
+        .await?;
+
+    Ok(Status::new(StatusCode::OK, "consent revoked"))
+}
@@ -0,0 +1,244 @@
+//! OAuth 2.0 authorization code grant with PKCE.
+//!
+//! Users authorize a client for a part of its scope, the client exchanges the code for a token
+//! of its service. Only the S256 challenge method is accepted, clients with a secret have to
+//! authenticate in addition to the code verifier.
+//!
+//! Devices without a browser use the device authorization grant instead, see [`device`].
+//!
+//! With the `openid` scope the client receives an ID token as well and may query the
+//! userinfo endpoint, as an OpenID Connect relying party.
+
+mod consent;
+mod device;
+mod handler;
+mod routes;
+
+use crate::{database::Database, error, Result};
+
+use std::collections::BTreeMap;
+
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use hyper::StatusCode;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::IndexOptions,
+    IndexModel,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use handler::{list_consents, revoke_consent};
+pub use routes::routes;
+
+/// Errors of the token endpoint, with the codes of RFC 6749 and RFC 8628
+#[derive(Debug, thiserror::Error)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(&'static str),
+    #[error("client authentication failed")]
+    InvalidClient,
+    #[error("{0}")]
+    InvalidGrant(&'static str),
+    #[error("grant type is not supported")]
+    UnsupportedGrantType,
+    #[error("scope is not granted to the client")]
+    InvalidScope,
+    #[error("access token is invalid")]
+    InvalidToken,
+    #[error("authorization is pending")]
+    AuthorizationPending,
+    #[error("polling too fast")]
+    SlowDown,
+    #[error("user denied the authorization")]
+    AccessDenied,
+    #[error("device code is expired")]
+    ExpiredToken,
+    #[error("profile is missing fields required by the service")]
+    ProfileIncomplete,
+    #[error("user is below the minimum age of the service")]
+    AgeRestricted,
+}
+
+impl OAuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::InvalidToken => "invalid_token",
+            OAuthError::AuthorizationPending => "authorization_pending",
+            OAuthError::SlowDown => "slow_down",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::ExpiredToken => "expired_token",
+            OAuthError::ProfileIncomplete | OAuthError::AgeRestricted => "access_denied",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OAuthErrorResponse {
+    error: &'static str,
+    error_description: String,
+}
+
+impl error::ErrorResponse for OAuthError {
+    type Response = Response;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            OAuthError::InvalidClient | OAuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        let body = OAuthErrorResponse {
+            error: self.code(),
+            error_description: self.to_string(),
+        };
+
+        (self.status_code(), Json(body)).into_response()
+    }
+}
+
+/// Grant type of the device flow, RFC 8628
+pub const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Scope of OpenID Connect, granted regardless of the scope of the client
+pub const OPENID_SCOPE: &str = "openid";
+
+/// ID token of OpenID Connect, for the client
+#[derive(Debug, Serialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    #[serde(with = "ts_seconds")]
+    exp: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    iat: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    /// `age_over_<n>` of the age gate of the service, see [`crate::user::age_over_claim`]
+    #[serde(flatten)]
+    age: BTreeMap<String, bool>,
+}
+
+impl IdTokenClaims {
+    const EXP_MIN: i64 = 10;
+
+    fn new(iss: &Url, sub: String, aud: String, nonce: Option<String>, now: DateTime<Utc>) -> Self {
+        Self {
+            iss: iss.as_str().trim_end_matches('/').to_string(),
+            sub,
+            aud,
+            exp: now + Duration::minutes(Self::EXP_MIN),
+            iat: now,
+            nonce,
+            age: BTreeMap::new(),
+        }
+    }
+}
+
+/// Checks the code verifier against the S256 challenge
+fn verify_challenge(verifier: &str, challenge: &str) -> bool {
+    // RFC 7636, section 4.1
+    let valid = (43..=128).contains(&verifier.len())
+        && verifier
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+
+    valid
+        && base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+            == challenge
+}
+
+fn hash_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+/// Authorization code, identified by its hash and valid once
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizationCodeDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    client: ObjectId,
+    user: ObjectId,
+    scope: Vec<String>,
+    redirect_uri: Url,
+    code_challenge: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    expires_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "authorization_codes";
+
+impl Database {
+    /// Removes authorization codes once they are expired
+    pub async fn init_authorization_codes(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        self.collection::<AuthorizationCodeDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_authorization_code(&self, doc: &AuthorizationCodeDocument) -> Result<()> {
+        self.collection::<AuthorizationCodeDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes the code and returns it, if it wasn't used before
+    async fn take_authorization_code(
+        &self,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<AuthorizationCodeDocument>> {
+        let doc = self
+            .collection::<AuthorizationCodeDocument>(COLLECTION)
+            .find_one_and_delete(doc! { "_id": hash_code(code) }, None)
+            .await?;
+
+        // The TTL monitor only runs once a minute
+        Ok(doc.filter(|d| d.expires_at > now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s256_challenge() {
+        // RFC 7636, appendix B
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        assert!(verify_challenge(verifier, challenge));
+        assert!(!verify_challenge(&verifier[1..], challenge));
+        assert!(!verify_challenge("short", challenge));
+    }
+}
@@ -0,0 +1,19 @@
+use super::handler;
+
+use axum::routing::{get, post};
+
+/// OAuth routes
+pub fn routes() -> axum::Router {
+    axum::Router::new()
+        .route(
+            "/authorize",
+            get(handler::get_consent).post(handler::authorize),
+        )
+        .route("/token", post(handler::token))
+        .route(
+            "/device",
+            get(handler::get_device_consent).post(handler::decide_device),
+        )
+        .route("/device/code", post(handler::device_code))
+        .route("/userinfo", get(handler::userinfo))
+}
@@ -0,0 +1,37 @@
+use crate::config::GlobalConfig;
+
+use axum::{extract::Extension, response::Html, Json};
+use serde_json::{json, Value};
+
+/// Description of the API, with the base path as server if the routes have one
+pub async fn spec(Extension(global): Extension<GlobalConfig>) -> Json<Value> {
+    let mut spec = super::spec();
+    if !global.base_path.is_empty() {
+        spec["servers"] = json!([{ "url": global.base_path }]);
+    }
+
+    Json(spec)
+}
+
+/// Swagger UI of the description, its assets are loaded from a CDN
+pub async fn docs(Extension(global): Extension<GlobalConfig>) -> Html<String> {
+    Html(format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>identity-server API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@4/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@4/swagger-ui-bundle.js"></script>
+    <script>
+        SwaggerUIBundle({{ url: "{}/v1/openapi.json", dom_id: "#swagger-ui" }});
+    </script>
+</body>
+</html>
+"##,
+        global.base_path
+    ))
+}
@@ -0,0 +1,283 @@
+//! OpenAPI 3 description of the API, served at `/v1/openapi.json` and printed by
+//! `identity-server openapi` to generate client SDKs.
+//!
+//! Like the configuration schema it is built by hand: operations are listed by tag in [`paths`]
+//! next to the schemas of their bodies in [`schemas`]. Path parameters are taken from the
+//! templated paths, every operation may answer with a [`crate::models::Status`] on errors.
+//! A test of the server requests every documented operation, so that paths can't drift from
+//! the mounted routes.
+
+mod handler;
+mod paths;
+mod routes;
+mod schemas;
+
+use serde_json::{json, Map, Value};
+
+pub use routes::routes;
+
+/// Builds the description of all operations of the enabled features
+pub fn spec() -> Value {
+    let mut spec = Spec::default();
+    paths::client(&mut spec);
+    paths::service(&mut spec);
+    paths::session(&mut spec);
+    paths::sso(&mut spec);
+    paths::user(&mut spec);
+    paths::token(&mut spec);
+    paths::action(&mut spec);
+    paths::audit(&mut spec);
+    paths::admin(&mut spec);
+    paths::oauth(&mut spec);
+    paths::well_known(&mut spec);
+
+    spec.into_value()
+}
+
+#[derive(Default)]
+struct Spec {
+    paths: Map<String, Value>,
+}
+
+impl Spec {
+    fn add(&mut self, method: &str, path: &str, op: Op) -> &mut Self {
+        let item = self
+            .paths
+            .entry(path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .unwrap();
+        item.insert(method.to_string(), op.into_value(path));
+        self
+    }
+
+    fn into_value(self) -> Value {
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": "identity-server",
+                "version": env!("CARGO_PKG_VERSION"),
+                "description": "Accounts, sessions, clients and services of Tarkov Database",
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas::schemas(),
+                "securitySchemes": {
+                    "bearer": {
+                        "type": "http",
+                        "scheme": "bearer",
+                        "bearerFormat": "JWT",
+                        "description": "Session token, or the token of the flow for links and second factors",
+                    },
+                },
+            },
+        })
+    }
+}
+
+/// Operation of a path
+struct Op {
+    value: Map<String, Value>,
+    parameters: Vec<Value>,
+    responses: Map<String, Value>,
+}
+
+impl Op {
+    fn new(tag: &str, id: &str, summary: &str) -> Self {
+        let mut value = Map::new();
+        value.insert("tags".into(), json!([tag]));
+        value.insert("operationId".into(), json!(id));
+        value.insert("summary".into(), json!(summary));
+
+        Self {
+            value,
+            parameters: Vec::new(),
+            responses: Map::new(),
+        }
+    }
+
+    /// Requires a bearer token
+    fn auth(mut self) -> Self {
+        self.value
+            .insert("security".into(), json!([{ "bearer": [] }]));
+        self
+    }
+
+    fn query(mut self, name: &str, schema: Value, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "schema": schema,
+            "description": description,
+        }));
+        self
+    }
+
+    /// Pagination of list operations, see [`crate::model::ListOptions`]
+    fn paginated(self) -> Self {
+        self.query(
+            "limit",
+            json!({ "type": "integer", "minimum": 1, "maximum": 100, "default": 20 }),
+            "Items per page",
+        )
+        .query(
+            "offset",
+            json!({ "type": "integer", "minimum": 0, "default": 0 }),
+            "Items to skip",
+        )
+    }
+
+    fn body(mut self, schema: Value) -> Self {
+        self.value.insert(
+            "requestBody".into(),
+            json!({ "required": true, "content": { "application/json": { "schema": schema } } }),
+        );
+        self
+    }
+
+    fn form(mut self, schema: Value) -> Self {
+        self.value.insert(
+            "requestBody".into(),
+            json!({ "required": true, "content": { "application/x-www-form-urlencoded": { "schema": schema } } }),
+        );
+        self
+    }
+
+    fn response(mut self, code: u16, description: &str, schema: Value) -> Self {
+        self.responses.insert(
+            code.to_string(),
+            json!({ "description": description, "content": { "application/json": { "schema": schema } } }),
+        );
+        self
+    }
+
+    /// Response without content besides the status
+    fn status(self, code: u16, description: &str) -> Self {
+        self.response(code, description, schemas::reference("Status"))
+    }
+
+    /// Redirect to the provider, with the state in a cookie
+    #[cfg_attr(
+        not(any(
+            feature = "sso-github",
+            feature = "sso-apple",
+            feature = "sso-twitch",
+            feature = "sso-steam",
+            feature = "sso-discord",
+            feature = "sso-oidc"
+        )),
+        allow(dead_code)
+    )]
+    fn redirect(mut self) -> Self {
+        self.responses.insert(
+            "303".into(),
+            json!({
+                "description": "Redirect to the provider",
+                "headers": {
+                    "Location": { "schema": { "type": "string", "format": "uri" } },
+                    "Set-Cookie": { "schema": { "type": "string" }, "description": "State of the flow" },
+                },
+            }),
+        );
+        self
+    }
+
+    fn into_value(mut self, path: &str) -> Value {
+        let params = path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{')?.strip_suffix('}'));
+        let mut parameters: Vec<_> = params
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+            .collect();
+        parameters.append(&mut self.parameters);
+        if !parameters.is_empty() {
+            self.value.insert("parameters".into(), parameters.into());
+        }
+
+        self.responses.insert(
+            "default".into(),
+            json!({ "description": "Error", "content": { "application/json": { "schema": schemas::reference("Status") } } }),
+        );
+        self.value.insert("responses".into(), self.responses.into());
+
+        self.value.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(r)) = map.get("$ref") {
+                    found.push(r);
+                }
+                map.values().for_each(|v| refs(v, found));
+            }
+            Value::Array(list) => list.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn references_resolve() {
+        let spec = spec();
+
+        let mut found = Vec::new();
+        refs(&spec, &mut found);
+        assert!(!found.is_empty());
+        for r in found {
+            let name = r.strip_prefix("#/components/schemas/").unwrap();
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "{} is not defined",
+                r
+            );
+        }
+    }
+
+    #[test]
+    fn operations_are_unique() {
+        let spec = spec();
+
+        let mut ids = HashSet::new();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            assert!(
+                ["/v1/", "/oauth/", "/.well-known/"]
+                    .iter()
+                    .any(|prefix| path.starts_with(prefix)),
+                "{}",
+                path
+            );
+            for (method, op) in item.as_object().unwrap() {
+                let id = op["operationId"].as_str().unwrap();
+                assert!(
+                    ids.insert(id.to_string()),
+                    "{} {} reuses {}",
+                    method,
+                    path,
+                    id
+                );
+                assert!(op["responses"].get("default").is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn declares_path_parameters() {
+        let spec = spec();
+
+        let op = &spec["paths"]["/v1/user/{id}/sessions/{jti}"]["delete"];
+        let names: Vec<_> = op["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["id", "jti"]);
+    }
+}
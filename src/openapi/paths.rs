@@ -0,0 +1,963 @@
+//! Operations by tag, in the order of their routes
+
+use super::{
+    schemas::{list, reference},
+    Op, Spec,
+};
+
+use serde_json::json;
+
+fn boolean() -> serde_json::Value {
+    json!({ "type": "boolean" })
+}
+
+fn string() -> serde_json::Value {
+    json!({ "type": "string" })
+}
+
+fn labels(op: Op) -> Op {
+    op.query(
+        "label",
+        string(),
+        "Comma-separated `key:value` selectors that all have to match",
+    )
+}
+
+pub(super) fn client(spec: &mut Spec) {
+    const TAG: &str = "client";
+
+    spec.add(
+        "get",
+        "/v1/client",
+        labels(
+            Op::new(TAG, "listClients", "List clients")
+                .auth()
+                .query("user", string(), "Owner of the clients")
+                .query("approved", boolean(), "Clients with unlocked scopes")
+                .query("service", string(), "Service of the clients")
+                .query("unconfirmed", boolean(), "Clients due for confirmation"),
+        )
+        .paginated()
+        .response(200, "Clients", list("ClientResponse")),
+    )
+    .add(
+        "post",
+        "/v1/client",
+        Op::new(TAG, "createClient", "Create a client")
+            .auth()
+            .body(reference("ClientCreateRequest"))
+            .response(201, "Created client", reference("ClientResponse")),
+    )
+    .add(
+        "get",
+        "/v1/client/{id}",
+        Op::new(TAG, "getClient", "Get a client").auth().response(
+            200,
+            "Client",
+            reference("ClientResponse"),
+        ),
+    )
+    .add(
+        "patch",
+        "/v1/client/{id}",
+        Op::new(TAG, "updateClient", "Update a client")
+            .auth()
+            .body(reference("ClientUpdateRequest"))
+            .response(200, "Updated client", reference("ClientResponse")),
+    )
+    .add(
+        "delete",
+        "/v1/client/{id}",
+        Op::new(TAG, "deleteClient", "Delete a client")
+            .auth()
+            .status(200, "Client deleted"),
+    )
+    .add(
+        "post",
+        "/v1/client/{id}/confirm",
+        Op::new(
+            TAG,
+            "confirmClient",
+            "Confirm that a client is still in use",
+        )
+        .auth()
+        .response(200, "Confirmed client", reference("ClientResponse")),
+    )
+    .add(
+        "post",
+        "/v1/client/{id}/secret",
+        Op::new(TAG, "rotateClientSecret", "Set a new secret of a client")
+            .auth()
+            .response(201, "New secret", reference("SecretResponse")),
+    )
+    .add(
+        "get",
+        "/v1/client/{id}/history",
+        Op::new(TAG, "listClientRevisions", "List the revisions of a client")
+            .auth()
+            .paginated()
+            .response(200, "Revisions", list("RevisionResponse")),
+    )
+    .add(
+        "post",
+        "/v1/client/{id}/revert/{revision}",
+        Op::new(TAG, "revertClient", "Revert a client to a revision")
+            .auth()
+            .response(200, "Reverted client", reference("ClientResponse")),
+    );
+}
+
+pub(super) fn service(spec: &mut Spec) {
+    const TAG: &str = "service";
+
+    spec.add(
+        "get",
+        "/v1/service",
+        labels(
+            Op::new(TAG, "listServices", "List services")
+                .auth()
+                .query("name", string(), "Name of the service")
+                .query("audience", string(), "Audience the service accepts"),
+        )
+        .paginated()
+        .response(200, "Services", list("ServiceResponse")),
+    )
+    .add(
+        "post",
+        "/v1/service",
+        Op::new(TAG, "createService", "Create a service")
+            .auth()
+            .body(reference("ServiceCreateRequest"))
+            .response(201, "Created service", reference("ServiceResponse")),
+    )
+    .add(
+        "get",
+        "/v1/service/{id}",
+        Op::new(TAG, "getService", "Get a service").auth().response(
+            200,
+            "Service",
+            reference("ServiceResponse"),
+        ),
+    )
+    .add(
+        "patch",
+        "/v1/service/{id}",
+        Op::new(TAG, "updateService", "Update a service")
+            .auth()
+            .body(reference("ServiceUpdateRequest"))
+            .response(200, "Updated service", reference("ServiceResponse")),
+    )
+    .add(
+        "delete",
+        "/v1/service/{id}",
+        Op::new(TAG, "deleteService", "Delete a service")
+            .auth()
+            .status(200, "Service deleted"),
+    )
+    .add(
+        "get",
+        "/v1/service/{id}/status",
+        Op::new(TAG, "getServiceStatus", "Get the health of a service")
+            .auth()
+            .response(200, "Health", reference("ServiceStatusResponse")),
+    )
+    .add(
+        "get",
+        "/v1/service/{id}/history",
+        Op::new(
+            TAG,
+            "listServiceRevisions",
+            "List the revisions of a service",
+        )
+        .auth()
+        .paginated()
+        .response(200, "Revisions", list("RevisionResponse")),
+    )
+    .add(
+        "post",
+        "/v1/service/{id}/revert/{revision}",
+        Op::new(TAG, "revertService", "Revert a service to a revision")
+            .auth()
+            .response(200, "Reverted service", reference("ServiceResponse")),
+    )
+    .add(
+        "post",
+        "/v1/service/{id}/usage",
+        Op::new(TAG, "reportScopeUsage", "Report the scopes clients used")
+            .auth()
+            .body(reference("UsageReport"))
+            .response(200, "Recorded usage", reference("UsageReportResponse")),
+    )
+    .add(
+        "get",
+        "/v1/service/{id}/unused-scopes",
+        Op::new(
+            TAG,
+            "listUnusedScopes",
+            "List scopes clients hold but don't use",
+        )
+        .auth()
+        .query(
+            "days",
+            json!({ "type": "integer", "minimum": 1, "default": 30 }),
+            "Days without usage until a scope counts as unused",
+        )
+        .response(200, "Unused scopes", list("UnusedScopeResponse")),
+    );
+}
+
+pub(super) fn session(spec: &mut Spec) {
+    const TAG: &str = "session";
+
+    let login = |id: &str| {
+        Op::new(TAG, id, "Sign in with email and password")
+            .body(reference("LoginRequest"))
+            .response(201, "New session", reference("SessionResponse"))
+            .response(
+                403,
+                "Second factor required",
+                reference("MfaChallengeResponse"),
+            )
+    };
+
+    spec.add("post", "/v1/session", login("createSession"))
+        .add(
+            "get",
+            "/v1/session",
+            Op::new(TAG, "refreshSession", "Issue a new token of the session")
+                .auth()
+                .response(201, "Refreshed session", reference("SessionResponse")),
+        )
+        .add(
+            "delete",
+            "/v1/session",
+            Op::new(TAG, "logout", "Revoke the session")
+                .auth()
+                .status(200, "Session revoked"),
+        )
+        .add(
+            "post",
+            "/v1/session/refresh",
+            Op::new(TAG, "exchangeRefreshToken", "Exchange a refresh token")
+                .body(reference("ExchangeRequest"))
+                .response(201, "Refreshed session", reference("SessionResponse")),
+        )
+        .add(
+            "post",
+            "/v1/session/mfa",
+            Op::new(TAG, "verifyMfa", "Complete a sign-in with a second factor")
+                .auth()
+                .body(reference("MfaRequest"))
+                .response(201, "New session", reference("SessionResponse")),
+        )
+        .add("post", "/v1/login", login("login"))
+        .add(
+            "post",
+            "/v1/login/email",
+            Op::new(TAG, "requestLoginLink", "Send a sign-in link by email")
+                .body(reference("EmailLoginRequest"))
+                .status(202, "Link sent if the account exists"),
+        )
+        .add(
+            "post",
+            "/v1/login/email/callback",
+            Op::new(TAG, "loginWithLink", "Sign in with the token of a link")
+                .auth()
+                .response(201, "New session", reference("SessionResponse")),
+        )
+        .add(
+            "post",
+            "/v1/login/recovery",
+            Op::new(TAG, "loginWithRecoveryCode", "Sign in with a recovery code")
+                .body(reference("RecoveryLoginRequest"))
+                .response(201, "New session", reference("SessionResponse")),
+        );
+}
+
+/// Authorize and callback operations of a provider whose callback takes `code` and `state`
+#[cfg(any(
+    feature = "sso-github",
+    feature = "sso-twitch",
+    feature = "sso-discord",
+    feature = "sso-oidc"
+))]
+fn oauth_provider(spec: &mut Spec, path: &str, id: &str, name: &str) {
+    spec.add(
+        "get",
+        &format!("/v1/sso/{}/authorize", path),
+        Op::new(
+            "sso",
+            &format!("authorize{}", id),
+            &format!("Sign in with {}", name),
+        )
+        .redirect(),
+    )
+    .add(
+        "get",
+        &format!("/v1/sso/{}/authorized", path),
+        Op::new(
+            "sso",
+            &format!("authorized{}", id),
+            &format!("Callback of {}", name),
+        )
+        .query("code", string(), "Authorization code")
+        .query("state", string(), "State of the flow")
+        .response(201, "New session", reference("SessionResponse")),
+    );
+}
+
+#[cfg_attr(
+    not(any(
+        feature = "sso-github",
+        feature = "sso-apple",
+        feature = "sso-twitch",
+        feature = "sso-steam",
+        feature = "sso-discord",
+        feature = "sso-oidc"
+    )),
+    allow(unused_variables)
+)]
+pub(super) fn sso(spec: &mut Spec) {
+    const TAG: &str = "sso";
+
+    spec.add(
+        "post",
+        "/v1/sso/register",
+        Op::new(
+            TAG,
+            "completeRegistration",
+            "Complete a registration with an email address",
+        )
+        .body(reference("RegisterRequest"))
+        .status(201, "User registered"),
+    );
+
+    #[cfg(feature = "sso-github")]
+    oauth_provider(spec, "github", "GitHub", "GitHub");
+
+    #[cfg(feature = "sso-apple")]
+    spec.add(
+        "get",
+        "/v1/sso/apple/authorize",
+        Op::new(TAG, "authorizeApple", "Sign in with Apple").redirect(),
+    )
+    .add(
+        "post",
+        "/v1/sso/apple/authorized",
+        Op::new(TAG, "authorizedApple", "Callback of Apple")
+            .form(json!({
+                "type": "object",
+                "required": ["code", "state"],
+                "properties": {
+                    "code": string(),
+                    "state": string(),
+                    "user": { "type": "string", "description": "Name and email as JSON, on the first sign-in only" },
+                },
+            }))
+            .response(201, "New session", reference("SessionResponse")),
+    );
+
+    #[cfg(feature = "sso-twitch")]
+    oauth_provider(spec, "twitch", "Twitch", "Twitch");
+
+    #[cfg(feature = "sso-steam")]
+    spec.add(
+        "get",
+        "/v1/sso/steam/authorize",
+        Op::new(TAG, "authorizeSteam", "Sign in with Steam").redirect(),
+    )
+    .add(
+        "get",
+        "/v1/sso/steam/authorized",
+        Op::new(TAG, "authorizedSteam", "Callback of Steam")
+            .query(
+                "openid",
+                json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+                "Positive assertion of the OpenID 2.0 provider, as `openid.*` parameters",
+            )
+            .response(201, "New session", reference("SessionResponse"))
+            .response(
+                202,
+                "Registration pending, an email address is required",
+                reference("RegistrationResponse"),
+            ),
+    );
+
+    #[cfg(feature = "sso-discord")]
+    oauth_provider(spec, "discord", "Discord", "Discord");
+
+    #[cfg(feature = "sso-oidc")]
+    oauth_provider(
+        spec,
+        "oidc/{provider}",
+        "Oidc",
+        "an OpenID Connect provider",
+    );
+}
+
+pub(super) fn user(spec: &mut Spec) {
+    const TAG: &str = "user";
+
+    let user_op = |id: &str, summary: &str| {
+        Op::new(TAG, id, summary)
+            .auth()
+            .response(200, "User", reference("UserResponse"))
+    };
+
+    spec.add(
+        "get",
+        "/v1/user",
+        Op::new(TAG, "listUsers", "List users")
+            .auth()
+            .query("email", string(), "Email address")
+            .query("verified", boolean(), "Users with a verified email address")
+            .query("pending", boolean(), "Pre-registered users")
+            .query("role", reference("Role"), "Users with the role")
+            .query("locked", boolean(), "Locked users")
+            .paginated()
+            .response(200, "Users", list("UserResponse")),
+    )
+    .add(
+        "post",
+        "/v1/user",
+        Op::new(TAG, "createUser", "Create a user")
+            .auth()
+            .body(reference("UserCreateRequest"))
+            .response(201, "Created user", reference("UserResponse")),
+    )
+    .add(
+        "get",
+        "/v1/user/verify",
+        Op::new(
+            TAG,
+            "verifyEmail",
+            "Verify the email address with the token of a link",
+        )
+        .auth()
+        .status(200, "Account verified"),
+    )
+    .add(
+        "post",
+        "/v1/user/verify",
+        Op::new(
+            TAG,
+            "resendVerification",
+            "Send the verification email again",
+        )
+        .auth()
+        .status(200, "Verification email sent"),
+    )
+    .add(
+        "get",
+        "/v1/user/approvals",
+        Op::new(TAG, "listApprovals", "List pending role approvals")
+            .auth()
+            .response(200, "Approvals", list("ApprovalResponse")),
+    )
+    .add(
+        "post",
+        "/v1/user/approvals/{id}/approve",
+        Op::new(TAG, "approveRoles", "Approve a role change")
+            .auth()
+            .response(200, "Approved change", reference("ApprovalResponse")),
+    )
+    .add(
+        "post",
+        "/v1/user/approvals/{id}/reject",
+        Op::new(TAG, "rejectRoles", "Reject a role change")
+            .auth()
+            .response(200, "Rejected change", reference("ApprovalResponse")),
+    )
+    .add(
+        "get",
+        "/v1/user/me/security-events",
+        Op::new(
+            TAG,
+            "listSecurityEvents",
+            "List security events of the account",
+        )
+        .auth()
+        .paginated()
+        .response(200, "Events", list("AuditEventResponse")),
+    )
+    .add(
+        "post",
+        "/v1/user/me/sessions/revoke-all",
+        Op::new(
+            TAG,
+            "revokeAllSessions",
+            "Revoke all sessions of the account",
+        )
+        .auth()
+        .body(reference("RevokeSessionsRequest"))
+        .response(200, "Sessions revoked", reference("RevokeSessionsResponse")),
+    )
+    .add(
+        "get",
+        "/v1/user/me/consents",
+        Op::new(TAG, "listConsents", "List clients the account authorized")
+            .auth()
+            .response(200, "Consents", list("GrantedConsentResponse")),
+    )
+    .add(
+        "delete",
+        "/v1/user/me/consents/{client}",
+        Op::new(TAG, "revokeConsent", "Revoke the authorization of a client")
+            .auth()
+            .status(200, "Consent revoked"),
+    )
+    .add("get", "/v1/user/{id}", user_op("getUser", "Get a user"))
+    .add(
+        "patch",
+        "/v1/user/{id}",
+        user_op("updateUser", "Update a user")
+            .body(reference("UserUpdateRequest"))
+            .response(
+                202,
+                "Role change pending approval",
+                reference("UserResponse"),
+            ),
+    )
+    .add(
+        "delete",
+        "/v1/user/{id}",
+        Op::new(TAG, "deleteUser", "Delete a user")
+            .auth()
+            .status(200, "User deleted"),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/force-password-reset",
+        user_op(
+            "forcePasswordReset",
+            "Require a new password on the next login",
+        ),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/force-relink",
+        user_op("forceRelink", "Require the next login through a provider"),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/lock",
+        user_op("lockUser", "Lock a user"),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/unlock",
+        user_op("unlockUser", "Unlock a user"),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/ban",
+        user_op("banUser", "Ban a user until a date").body(reference("BanRequest")),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/elevate",
+        Op::new(TAG, "elevateUser", "Grant a role for a limited time")
+            .auth()
+            .body(reference("ElevateRequest"))
+            .response(201, "Elevation", reference("ElevationResponse"))
+            .response(
+                202,
+                "Elevation pending approval",
+                reference("ElevationResponse"),
+            ),
+    )
+    .add(
+        "patch",
+        "/v1/user/{id}/profile",
+        user_op("updateProfile", "Update profile fields").body(reference("ProfileRequest")),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/mfa/totp",
+        Op::new(TAG, "enrollTotp", "Enroll an authenticator app")
+            .auth()
+            .response(
+                201,
+                "Secret of the app",
+                reference("TotpEnrollmentResponse"),
+            ),
+    )
+    .add(
+        "delete",
+        "/v1/user/{id}/mfa/totp",
+        user_op("disableTotp", "Disable the authenticator app")
+            .body(reference("TotpDisableRequest")),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/mfa/totp/confirm",
+        Op::new(TAG, "confirmTotp", "Enable the enrolled authenticator app")
+            .auth()
+            .body(reference("TotpConfirmRequest"))
+            .response(
+                200,
+                "Recovery codes, only returned once",
+                reference("RecoveryCodesResponse"),
+            ),
+    )
+    .add(
+        "get",
+        "/v1/user/{id}/sessions",
+        Op::new(TAG, "listSessions", "List active sessions of a user")
+            .auth()
+            .response(200, "Sessions", list("ActiveSessionResponse")),
+    )
+    .add(
+        "delete",
+        "/v1/user/{id}/sessions/{jti}",
+        Op::new(TAG, "revokeSession", "Revoke a session of a user")
+            .auth()
+            .status(200, "Session revoked"),
+    )
+    .add(
+        "get",
+        "/v1/user/{id}/history",
+        Op::new(TAG, "listUserRevisions", "List the revisions of a user")
+            .auth()
+            .paginated()
+            .response(200, "Revisions", list("RevisionResponse")),
+    )
+    .add(
+        "post",
+        "/v1/user/{id}/revert/{revision}",
+        user_op("revertUser", "Revert a user to a revision").response(
+            202,
+            "Role change pending approval",
+            reference("UserResponse"),
+        ),
+    );
+}
+
+pub(super) fn token(spec: &mut Spec) {
+    const TAG: &str = "token";
+
+    spec.add(
+        "get",
+        "/v1/token",
+        Op::new(
+            TAG,
+            "getServiceToken",
+            "Issue a service token for a client token",
+        )
+        .auth()
+        .response(201, "Service token", reference("TokenResponse")),
+    )
+    .add(
+        "post",
+        "/v1/token",
+        Op::new(TAG, "createClientToken", "Issue a token of an own client")
+            .auth()
+            .body(reference("TokenCreateRequest"))
+            .response(201, "Client token", reference("TokenResponse")),
+    )
+    .add(
+        "post",
+        "/v1/token/canary",
+        Op::new(TAG, "createCanaryToken", "Issue a token of a canary client")
+            .auth()
+            .body(reference("TokenCreateRequest"))
+            .response(201, "Canary token", reference("TokenResponse")),
+    )
+    .add(
+        "post",
+        "/v1/token/client-credentials",
+        Op::new(
+            TAG,
+            "clientCredentials",
+            "Issue a service token for the secret of a client",
+        )
+        .body(reference("ClientCredentialsRequest"))
+        .response(201, "Service token", reference("TokenResponse")),
+    )
+    .add(
+        "post",
+        "/v1/token/ci",
+        Op::new(
+            TAG,
+            "exchangeCiToken",
+            "Exchange the OIDC token of a CI pipeline",
+        )
+        .body(reference("CiRequest"))
+        .response(201, "Short-lived token", reference("TokenResponse")),
+    );
+
+    #[cfg(feature = "federation")]
+    spec.add(
+        "post",
+        "/v1/token/federate",
+        Op::new(TAG, "federate", "Exchange the token of a trusted issuer")
+            .body(reference("FederateRequest"))
+            .response(201, "New session", reference("SessionResponse")),
+    );
+}
+
+pub(super) fn action(spec: &mut Spec) {
+    const TAG: &str = "action";
+
+    spec.add(
+        "post",
+        "/v1/action/register",
+        Op::new(TAG, "register", "Register with email and password")
+            .body(reference("ActionRegisterRequest"))
+            .status(201, "User registered"),
+    )
+    .add(
+        "get",
+        "/v1/action/verify",
+        Op::new(
+            TAG,
+            "confirmEmail",
+            "Verify the email address with the token of the link",
+        )
+        .auth()
+        .status(200, "Account verified"),
+    )
+    .add(
+        "get",
+        "/v1/action/reset",
+        Op::new(TAG, "requestReset", "Send a password reset link")
+            .query("email", string(), "Email address of the account")
+            .status(200, "Reset email sent"),
+    )
+    .add(
+        "post",
+        "/v1/action/reset",
+        Op::new(
+            TAG,
+            "resetPassword",
+            "Set a new password with the token of the link",
+        )
+        .auth()
+        .body(reference("ResetRequest"))
+        .status(200, "New password set"),
+    );
+}
+
+pub(super) fn audit(spec: &mut Spec) {
+    spec.add(
+        "get",
+        "/v1/audit",
+        Op::new("audit", "listAuditEvents", "List security events")
+            .auth()
+            .query("user", string(), "User the events concern")
+            .query("actor", string(), "User that caused the events")
+            .query("type", string(), "Event type, e.g. `loginFailed`")
+            .paginated()
+            .response(200, "Events, newest first", list("AuditEventResponse")),
+    );
+}
+
+pub(super) fn admin(spec: &mut Spec) {
+    const TAG: &str = "admin";
+
+    spec.add(
+        "post",
+        "/v1/admin/verify-token",
+        Op::new(
+            TAG,
+            "verifyToken",
+            "Report where a token diverges from the database",
+        )
+        .auth()
+        .body(reference("VerifyRequest"))
+        .response(200, "Report", reference("TokenReport")),
+    )
+    .add(
+        "post",
+        "/v1/admin/sessions/revoke",
+        Op::new(
+            TAG,
+            "revokeSessions",
+            "Revoke the sessions matching all criteria",
+        )
+        .auth()
+        .body(reference("RevocationCriteria"))
+        .response(202, "Started job", reference("RevocationJobResponse")),
+    )
+    .add(
+        "get",
+        "/v1/admin/sessions/revoke/{id}",
+        Op::new(TAG, "getRevocationJob", "Get a revocation job")
+            .auth()
+            .response(200, "Job", reference("RevocationJobResponse")),
+    )
+    .add(
+        "get",
+        "/v1/admin/token-epoch",
+        Op::new(
+            TAG,
+            "getTokenEpoch",
+            "Get the token epoch of the deployment",
+        )
+        .auth()
+        .response(200, "Epoch", reference("EpochResponse")),
+    )
+    .add(
+        "post",
+        "/v1/admin/token-epoch",
+        Op::new(
+            TAG,
+            "bumpTokenEpoch",
+            "Invalidate all session and client tokens of the deployment",
+        )
+        .auth()
+        .response(200, "New epoch", reference("EpochResponse")),
+    )
+    .add(
+        "post",
+        "/v1/admin/token-epoch/user/{id}",
+        Op::new(
+            TAG,
+            "bumpUserTokenEpoch",
+            "Invalidate all session tokens of a user",
+        )
+        .auth()
+        .response(200, "New epoch of the user", reference("EpochResponse")),
+    )
+    .add(
+        "get",
+        "/v1/admin/keys",
+        Op::new(TAG, "listKeys", "List the expiry of provider keys")
+            .auth()
+            .response(200, "Keys", list("KeyStatusResponse")),
+    )
+    .add(
+        "get",
+        "/v1/admin/outbox",
+        Op::new(
+            TAG,
+            "listOutbox",
+            "List captured mails, on sandbox deployments only",
+        )
+        .auth()
+        .query("to", string(), "Recipient address")
+        .paginated()
+        .response(200, "Mails, newest first", list("OutboxMessageResponse")),
+    );
+
+    #[cfg(feature = "selftest")]
+    spec.add(
+        "post",
+        "/v1/admin/selftest/oidc",
+        Op::new(
+            TAG,
+            "selfTestOidc",
+            "Run an authorization code flow against the deployment",
+        )
+        .auth()
+        .response(200, "Report", reference("SelfTestReport")),
+    );
+}
+
+pub(super) fn oauth(spec: &mut Spec) {
+    const TAG: &str = "oauth";
+
+    let authorize = |op: Op| {
+        op.query("response_type", string(), "`code`")
+            .query("client_id", string(), "Client to authorize")
+            .query("redirect_uri", string(), "Registered redirect URI")
+            .query("scope", string(), "Space-separated scope")
+            .query("state", string(), "State of the client")
+            .query("code_challenge", string(), "PKCE challenge")
+            .query("code_challenge_method", string(), "`S256`")
+            .query("nonce", string(), "Returned in the ID token")
+    };
+
+    spec.add(
+        "get",
+        "/oauth/authorize",
+        authorize(Op::new(
+            TAG,
+            "getConsent",
+            "Get the client details for consent",
+        ))
+        .auth()
+        .response(200, "Client details", reference("ConsentResponse")),
+    )
+    .add(
+        "post",
+        "/oauth/authorize",
+        Op::new(TAG, "authorize", "Authorize a client on behalf of the user")
+            .auth()
+            .body(reference("AuthorizeRequest"))
+            .response(
+                200,
+                "Redirect of the client",
+                reference("AuthorizeResponse"),
+            ),
+    )
+    .add(
+        "post",
+        "/oauth/token",
+        Op::new(TAG, "token", "Exchange an authorization or device code")
+            .form(reference("OAuthTokenRequest"))
+            .response(200, "Access token", reference("OAuthTokenResponse")),
+    )
+    .add(
+        "get",
+        "/oauth/device",
+        Op::new(
+            TAG,
+            "getDeviceConsent",
+            "Get the client details of a user code",
+        )
+        .auth()
+        .query("user_code", string(), "Code shown on the device")
+        .response(200, "Client details", reference("ConsentResponse")),
+    )
+    .add(
+        "post",
+        "/oauth/device",
+        Op::new(TAG, "decideDevice", "Approve or deny a user code")
+            .auth()
+            .body(reference("DeviceDecisionRequest"))
+            .status(200, "Device approved or denied"),
+    )
+    .add(
+        "post",
+        "/oauth/device/code",
+        Op::new(TAG, "deviceCode", "Issue a device code")
+            .form(reference("DeviceCodeRequest"))
+            .response(200, "Device code", reference("DeviceCodeResponse")),
+    )
+    .add(
+        "get",
+        "/oauth/userinfo",
+        Op::new(
+            TAG,
+            "userinfo",
+            "Get the claims of the user of an access token",
+        )
+        .auth()
+        .response(200, "Claims", reference("UserInfoResponse")),
+    );
+}
+
+pub(super) fn well_known(spec: &mut Spec) {
+    const TAG: &str = "discovery";
+
+    spec.add(
+        "get",
+        "/.well-known/jwks.json",
+        Op::new(TAG, "getJwks", "Get the public keys of issued tokens").response(
+            200,
+            "Key set, empty if tokens are signed with the shared secret",
+            reference("JwkSet"),
+        ),
+    )
+    .add(
+        "get",
+        "/.well-known/openid-configuration",
+        Op::new(
+            TAG,
+            "getOpenIdConfiguration",
+            "Get the OpenID Connect provider metadata, if an issuer is configured",
+        )
+        .response(200, "Provider metadata", reference("OpenIdConfiguration")),
+    );
+}
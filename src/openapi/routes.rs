@@ -0,0 +1,14 @@
+use super::handler;
+
+use axum::routing::get;
+
+/// OpenAPI routes, with the Swagger UI if enabled
+pub fn routes(docs: bool) -> axum::Router {
+    let router = axum::Router::new().route("/openapi.json", get(handler::spec));
+
+    if docs {
+        router.route("/docs", get(handler::docs))
+    } else {
+        router
+    }
+}
@@ -0,0 +1,943 @@
+//! Schemas of the request and response bodies, named after their types
+
+use serde_json::{json, Map, Value};
+
+pub fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// Page of a list, see [`crate::models::List`]
+pub fn list(name: &str) -> Value {
+    json!({
+        "type": "object",
+        "required": ["total", "data"],
+        "properties": {
+            "total": { "type": "integer", "format": "int64" },
+            "data": { "type": "array", "items": reference(name) },
+        },
+    })
+}
+
+fn timestamp() -> Value {
+    json!({ "type": "integer", "format": "int64", "description": "Unix time in seconds" })
+}
+
+fn id() -> Value {
+    json!({ "type": "string", "pattern": "^[0-9a-f]{24}$" })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn strings() -> Value {
+    json!({ "type": "array", "items": { "type": "string" } })
+}
+
+fn url() -> Value {
+    json!({ "type": "string", "format": "uri" })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({ "type": "object", "required": required, "properties": properties })
+}
+
+pub fn schemas() -> Map<String, Value> {
+    let mut s = Map::new();
+    let mut add = |name: &str, schema: Value| {
+        s.insert(name.to_string(), schema);
+    };
+
+    // Shared
+    add(
+        "Status",
+        object(
+            &["code", "message"],
+            json!({ "code": { "type": "integer" }, "message": string() }),
+        ),
+    );
+    add(
+        "Labels",
+        json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+    );
+    add(
+        "RevisionResponse",
+        object(
+            &["id", "changes", "date"],
+            json!({
+                "id": id(),
+                "actor": id(),
+                "changes": array(object(&["field"], json!({ "field": string(), "from": {}, "to": {} }))),
+                "date": timestamp(),
+            }),
+        ),
+    );
+
+    // Clients
+    add(
+        "AccessSchedule",
+        object(
+            &["start", "end"],
+            json!({
+                "days": array(json!({ "type": "string", "enum": ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"] })),
+                "start": { "type": "string", "format": "time", "example": "08:00:00" },
+                "end": { "type": "string", "format": "time", "example": "18:00:00" },
+                "utcOffset": { "type": "string", "pattern": "^[+-][0-9]{2}:[0-9]{2}$", "default": "+00:00" },
+            }),
+        ),
+    );
+    add(
+        "ClientResponse",
+        object(
+            &[
+                "id",
+                "user",
+                "service",
+                "name",
+                "scope",
+                "unlocked",
+                "labels",
+                "confirmedAt",
+                "unconfirmed",
+                "legacyTokenDisabled",
+                "hasSecret",
+                "lastIssued",
+                "lastModified",
+            ],
+            json!({
+                "id": id(),
+                "user": id(),
+                "service": id(),
+                "name": string(),
+                "scope": strings(),
+                "unlocked": boolean(),
+                "labels": reference("Labels"),
+                "description": string(),
+                "contact": string(),
+                "documentationUrl": url(),
+                "redirectUris": array(url()),
+                "schedule": reference("AccessSchedule"),
+                "confirmedAt": timestamp(),
+                "unconfirmed": boolean(),
+                "legacyTokenDisabled": boolean(),
+                "canary": boolean(),
+                "hasSecret": boolean(),
+                "lastIssued": timestamp(),
+                "lastModified": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "ClientCreateRequest",
+        object(
+            &["name", "service"],
+            json!({
+                "user": { "type": "string", "description": "Owner, the session user if not set" },
+                "name": string(),
+                "service": id(),
+                "scope": strings(),
+                "labels": reference("Labels"),
+                "description": string(),
+                "contact": string(),
+                "documentationUrl": url(),
+                "redirectUris": array(url()),
+                "canary": { "type": "boolean", "description": "Planted credentials that raise an alert when used" },
+            }),
+        ),
+    );
+    add(
+        "ClientUpdateRequest",
+        object(
+            &[],
+            json!({
+                "user": id(),
+                "name": string(),
+                "scope": strings(),
+                "unlocked": boolean(),
+                "legacyTokenDisabled": boolean(),
+                "schedule": { "allOf": [reference("AccessSchedule")], "nullable": true, "description": "null removes the schedule" },
+                "labels": reference("Labels"),
+                "description": string(),
+                "contact": string(),
+                "documentationUrl": url(),
+                "redirectUris": array(url()),
+            }),
+        ),
+    );
+    add(
+        "SecretResponse",
+        object(
+            &["client", "secret"],
+            json!({ "client": id(), "secret": { "type": "string", "description": "Only returned once" } }),
+        ),
+    );
+
+    // Services
+    add(
+        "ProfileField",
+        json!({ "type": "string", "enum": ["name", "country", "locale", "birthdate"] }),
+    );
+    add(
+        "ServiceResponse",
+        object(
+            &[
+                "id",
+                "name",
+                "audience",
+                "scope",
+                "defaultScope",
+                "privilegedScope",
+                "labels",
+                "profileFields",
+                "lastModified",
+            ],
+            json!({
+                "id": id(),
+                "name": string(),
+                "audience": strings(),
+                "scope": strings(),
+                "defaultScope": strings(),
+                "privilegedScope": strings(),
+                "labels": reference("Labels"),
+                "healthUrl": url(),
+                "profileFields": array(reference("ProfileField")),
+                "minAge": { "type": "integer", "minimum": 1 },
+                "lastModified": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "ServiceCreateRequest",
+        object(
+            &["name", "audience", "scope", "scopeDefault"],
+            json!({
+                "name": string(),
+                "audience": strings(),
+                "scope": strings(),
+                "scopeDefault": strings(),
+                "scopePrivileged": strings(),
+                "secret": { "type": "string", "writeOnly": true },
+                "labels": reference("Labels"),
+                "healthUrl": url(),
+                "profileFields": array(reference("ProfileField")),
+                "minAge": { "type": "integer", "minimum": 0 },
+            }),
+        ),
+    );
+    add(
+        "ServiceUpdateRequest",
+        object(
+            &[],
+            json!({
+                "name": string(),
+                "audience": string(),
+                "scope": strings(),
+                "scopeDefault": strings(),
+                "scopePrivileged": strings(),
+                "secret": { "type": "string", "writeOnly": true },
+                "labels": reference("Labels"),
+                "healthUrl": url(),
+                "profileFields": array(reference("ProfileField")),
+                "minAge": { "type": "integer", "minimum": 0, "description": "0 removes the age gate" },
+            }),
+        ),
+    );
+    add(
+        "ServiceStatusResponse",
+        object(
+            &["status"],
+            json!({
+                "status": { "type": "string", "enum": ["unknown", "healthy", "unhealthy", "dead"] },
+                "since": timestamp(),
+                "checkedAt": timestamp(),
+                "error": string(),
+            }),
+        ),
+    );
+    add(
+        "UsageReport",
+        object(
+            &["usage"],
+            json!({ "usage": array(object(&["client", "scopes"], json!({ "client": id(), "scopes": strings() }))) }),
+        ),
+    );
+    add(
+        "UsageReportResponse",
+        object(
+            &["recorded", "anomalies"],
+            json!({ "recorded": { "type": "integer" }, "anomalies": { "type": "integer" } }),
+        ),
+    );
+    add(
+        "UnusedScopeResponse",
+        object(
+            &["client", "user", "name", "scopes", "lastIssued"],
+            json!({
+                "client": id(),
+                "user": id(),
+                "name": string(),
+                "scopes": strings(),
+                "lastIssued": timestamp(),
+            }),
+        ),
+    );
+
+    // Sessions
+    add(
+        "SessionResponse",
+        object(
+            &["user", "token", "expiresAt"],
+            json!({ "user": id(), "token": string(), "refreshToken": string(), "expiresAt": timestamp() }),
+        ),
+    );
+    add(
+        "LoginRequest",
+        object(
+            &["email", "password"],
+            json!({ "email": { "type": "string", "format": "email" }, "password": { "type": "string", "writeOnly": true } }),
+        ),
+    );
+    add(
+        "EmailLoginRequest",
+        object(
+            &["email"],
+            json!({ "email": { "type": "string", "format": "email" } }),
+        ),
+    );
+    add(
+        "RecoveryLoginRequest",
+        object(
+            &["email", "code"],
+            json!({ "email": { "type": "string", "format": "email" }, "code": string() }),
+        ),
+    );
+    add(
+        "ExchangeRequest",
+        object(&["refreshToken"], json!({ "refreshToken": string() })),
+    );
+    add(
+        "MfaRequest",
+        object(
+            &["code"],
+            json!({ "code": { "type": "string", "description": "Code of the authenticator app or a recovery code" } }),
+        ),
+    );
+    add(
+        "MfaChallengeResponse",
+        object(
+            &["code", "message", "mfaToken", "expiresAt"],
+            json!({
+                "code": { "type": "integer" },
+                "message": string(),
+                "mfaToken": { "type": "string", "description": "Exchanged for the session together with the code" },
+                "expiresAt": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "ActiveSessionResponse",
+        object(
+            &[
+                "jti",
+                "method",
+                "createdAt",
+                "lastUsedAt",
+                "expiresAt",
+                "current",
+            ],
+            json!({
+                "jti": string(),
+                "method": string(),
+                "ip": string(),
+                "userAgent": string(),
+                "createdAt": timestamp(),
+                "lastUsedAt": timestamp(),
+                "expiresAt": timestamp(),
+                "current": { "type": "boolean", "description": "Session of the request" },
+            }),
+        ),
+    );
+
+    // SSO
+    add(
+        "RegistrationResponse",
+        object(
+            &["token", "expiresAt"],
+            json!({ "token": { "type": "string", "description": "Sent with an email address to complete the registration" }, "expiresAt": timestamp() }),
+        ),
+    );
+    add(
+        "RegisterRequest",
+        object(
+            &["token", "email"],
+            json!({ "token": string(), "email": { "type": "string", "format": "email" } }),
+        ),
+    );
+
+    // Users
+    add(
+        "Role",
+        json!({ "type": "string", "enum": ["userEditor", "userViewer", "clientEditor", "clientViewer", "serviceEditor", "serviceViewer"] }),
+    );
+    add(
+        "AccountFlag",
+        json!({ "type": "string", "enum": ["providerTwoFactorDisabled", "passwordResetRequired", "relinkRequired"] }),
+    );
+    add(
+        "Connection",
+        json!({
+            "type": "object",
+            "required": ["type"],
+            "properties": {
+                "type": { "type": "string", "enum": ["github", "apple", "twitch", "steam", "discord", "federated", "sandbox"] },
+            },
+            "additionalProperties": true,
+        }),
+    );
+    add(
+        "UserResponse",
+        object(
+            &[
+                "id",
+                "email",
+                "roles",
+                "verified",
+                "pending",
+                "connections",
+                "canLogin",
+                "mfaEnabled",
+                "lastSessions",
+                "lastModified",
+            ],
+            json!({
+                "id": id(),
+                "email": { "type": "string", "format": "email" },
+                "name": string(),
+                "country": { "type": "string", "description": "ISO 3166-1 alpha-2 code" },
+                "locale": { "type": "string", "description": "BCP 47 language tag" },
+                "roles": array(reference("Role")),
+                "verified": boolean(),
+                "pending": boolean(),
+                "connections": array(reference("Connection")),
+                "flags": array(reference("AccountFlag")),
+                "locked": boolean(),
+                "canLogin": boolean(),
+                "bannedUntil": timestamp(),
+                "mfaEnabled": boolean(),
+                "lastSessions": array(object(&["date"], json!({ "date": timestamp(), "region": string() }))),
+                "lastModified": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "UserCreateRequest",
+        object(
+            &["email"],
+            json!({
+                "email": { "type": "string", "format": "email" },
+                "password": { "type": "string", "writeOnly": true, "description": "Pre-registers the user if not set" },
+                "roles": array(reference("Role")),
+            }),
+        ),
+    );
+    add(
+        "UserUpdateRequest",
+        object(
+            &[],
+            json!({
+                "email": { "type": "string", "format": "email" },
+                "password": { "type": "string", "writeOnly": true },
+                "verified": boolean(),
+                "roles": array(reference("Role")),
+                "flags": array(reference("AccountFlag")),
+            }),
+        ),
+    );
+    add(
+        "ProfileRequest",
+        object(
+            &[],
+            json!({
+                "name": string(),
+                "country": { "type": "string", "pattern": "^[A-Za-z]{2}$" },
+                "locale": { "type": "string", "example": "de-AT" },
+                "birthdate": { "type": "string", "format": "date", "description": "Set once, only admins may change it" },
+            }),
+        ),
+    );
+    add(
+        "BanRequest",
+        object(&["until"], json!({ "until": timestamp() })),
+    );
+    add(
+        "ElevateRequest",
+        object(
+            &["role", "durationMin", "justification"],
+            json!({ "role": reference("Role"), "durationMin": { "type": "integer", "minimum": 1 }, "justification": string() }),
+        ),
+    );
+    add(
+        "ElevationResponse",
+        object(
+            &[
+                "id",
+                "user",
+                "role",
+                "justification",
+                "grantedBy",
+                "grantedAt",
+                "expiresAt",
+            ],
+            json!({
+                "id": id(),
+                "user": id(),
+                "role": reference("Role"),
+                "justification": string(),
+                "grantedBy": id(),
+                "grantedAt": timestamp(),
+                "expiresAt": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "ApprovalResponse",
+        object(
+            &["id", "user", "roles", "requestedBy", "requestedAt"],
+            json!({
+                "id": id(),
+                "user": id(),
+                "roles": array(reference("Role")),
+                "requestedBy": id(),
+                "requestedAt": timestamp(),
+                "expiresAt": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "AuditEventResponse",
+        json!({
+            "type": "object",
+            "required": ["id", "user", "type", "date"],
+            "properties": {
+                "id": id(),
+                "user": id(),
+                "actor": id(),
+                "type": { "type": "string", "description": "Kind of the event, its fields follow" },
+                "addr": string(),
+                "userAgent": string(),
+                "date": timestamp(),
+            },
+            "additionalProperties": true,
+        }),
+    );
+    add(
+        "TotpEnrollmentResponse",
+        object(
+            &["secret", "uri"],
+            json!({ "secret": string(), "uri": { "type": "string", "description": "Key URI, the payload of the QR code" } }),
+        ),
+    );
+    add(
+        "TotpConfirmRequest",
+        object(&["code"], json!({ "code": string() })),
+    );
+    add(
+        "TotpDisableRequest",
+        object(
+            &[],
+            json!({ "code": { "type": "string", "description": "Not required for admins disabling it for another user" } }),
+        ),
+    );
+    add(
+        "RecoveryCodesResponse",
+        object(&["recoveryCodes"], json!({ "recoveryCodes": strings() })),
+    );
+    add(
+        "RevokeSessionsRequest",
+        object(
+            &["excludeCurrent"],
+            json!({ "excludeCurrent": { "type": "boolean", "description": "Issues a new token for the current session" } }),
+        ),
+    );
+    add(
+        "RevokeSessionsResponse",
+        object(&[], json!({ "session": reference("SessionResponse") })),
+    );
+    add(
+        "GrantedConsentResponse",
+        object(
+            &["client", "scope", "grantedAt", "updatedAt"],
+            json!({
+                "client": id(),
+                "name": string(),
+                "scope": strings(),
+                "grantedAt": timestamp(),
+                "updatedAt": timestamp(),
+            }),
+        ),
+    );
+
+    // Tokens
+    add(
+        "TokenResponse",
+        object(
+            &["token", "expiresAt"],
+            json!({ "token": string(), "expiresAt": timestamp() }),
+        ),
+    );
+    add(
+        "TokenCreateRequest",
+        object(&["client"], json!({ "client": id() })),
+    );
+    add(
+        "ClientCredentialsRequest",
+        object(
+            &["client", "secret"],
+            json!({ "client": id(), "secret": { "type": "string", "writeOnly": true } }),
+        ),
+    );
+    add(
+        "CiRequest",
+        object(
+            &["token"],
+            json!({ "token": { "type": "string", "description": "OIDC token of the pipeline" } }),
+        ),
+    );
+    add(
+        "FederateRequest",
+        object(
+            &["token"],
+            json!({ "token": { "type": "string", "description": "Token of a trusted issuer" } }),
+        ),
+    );
+
+    // Actions
+    add(
+        "ActionRegisterRequest",
+        object(
+            &["email", "password"],
+            json!({ "email": { "type": "string", "format": "email" }, "password": { "type": "string", "writeOnly": true } }),
+        ),
+    );
+    add(
+        "ResetRequest",
+        object(
+            &["password"],
+            json!({ "password": { "type": "string", "writeOnly": true } }),
+        ),
+    );
+
+    // Administration
+    add(
+        "VerifyRequest",
+        object(&["token"], json!({ "token": string() })),
+    );
+    add(
+        "Divergence",
+        object(
+            &["kind"],
+            json!({
+                "kind": {
+                    "type": "string",
+                    "enum": [
+                        "invalidSignature", "expired", "tokenRevoked", "sessionsRevoked",
+                        "epochBumped", "userEpochBumped", "userNotFound", "userLocked",
+                        "userBanned", "clientNotFound", "clientLocked", "legacyFlowDisabled",
+                        "serviceNotFound", "scopeRevoked", "scopeAdded", "audienceMismatch",
+                    ],
+                },
+                "until": timestamp(),
+                "scope": strings(),
+                "expected": strings(),
+            }),
+        ),
+    );
+    add(
+        "TokenReport",
+        object(
+            &[
+                "kind",
+                "subject",
+                "issuedAt",
+                "expiresAt",
+                "valid",
+                "divergences",
+            ],
+            json!({
+                "kind": { "type": "string", "description": "Token type, `service` for tokens of a service" },
+                "subject": string(),
+                "issuedAt": timestamp(),
+                "expiresAt": timestamp(),
+                "valid": boolean(),
+                "divergences": array(reference("Divergence")),
+            }),
+        ),
+    );
+    add(
+        "RevocationCriteria",
+        object(
+            &[],
+            json!({
+                "users": array(id()),
+                "role": reference("Role"),
+                "issuedBefore": timestamp(),
+                "ipRange": { "type": "string", "description": "Network in CIDR notation" },
+                "audience": string(),
+            }),
+        ),
+    );
+    add(
+        "RevocationJobResponse",
+        object(
+            &[
+                "id",
+                "criteria",
+                "status",
+                "total",
+                "scanned",
+                "revoked",
+                "createdBy",
+                "createdAt",
+            ],
+            json!({
+                "id": id(),
+                "criteria": reference("RevocationCriteria"),
+                "status": { "type": "string", "enum": ["running", "completed", "failed"] },
+                "total": { "type": "integer" },
+                "scanned": { "type": "integer" },
+                "revoked": { "type": "integer" },
+                "error": string(),
+                "createdBy": id(),
+                "createdAt": timestamp(),
+                "finishedAt": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "EpochResponse",
+        object(&["epoch"], json!({ "epoch": { "type": "integer" } })),
+    );
+    add(
+        "KeyStatusResponse",
+        object(
+            &["name", "state", "expiresAt", "daysLeft"],
+            json!({
+                "name": string(),
+                "state": { "type": "string", "enum": ["valid", "expiring", "expired"] },
+                "expiresAt": timestamp(),
+                "daysLeft": { "type": "integer", "description": "Negative once expired" },
+                "lastAlert": object(
+                    &["daysBefore", "date"],
+                    json!({ "daysBefore": { "type": "integer" }, "date": timestamp() }),
+                ),
+            }),
+        ),
+    );
+    add(
+        "OutboxMessageResponse",
+        object(
+            &["to", "subject", "body", "date"],
+            json!({
+                "to": string(),
+                "subject": string(),
+                "body": object(
+                    &["type", "template", "vars"],
+                    json!({
+                        "type": { "type": "string", "enum": ["template"] },
+                        "template": string(),
+                        "vars": { "type": "object", "additionalProperties": { "type": "string" } },
+                    }),
+                ),
+                "date": timestamp(),
+            }),
+        ),
+    );
+    add(
+        "SelfTestReport",
+        object(
+            &["passed", "steps"],
+            json!({
+                "passed": boolean(),
+                "steps": array(object(
+                    &["name", "status", "durationMs"],
+                    json!({
+                        "name": string(),
+                        "status": { "type": "string", "enum": ["passed", "failed", "skipped"] },
+                        "detail": string(),
+                        "durationMs": { "type": "integer" },
+                    }),
+                )),
+            }),
+        ),
+    );
+
+    // OAuth, named as in RFC 6749
+    add(
+        "AuthorizeRequest",
+        object(
+            &[
+                "response_type",
+                "client_id",
+                "redirect_uri",
+                "code_challenge",
+                "code_challenge_method",
+            ],
+            json!({
+                "response_type": { "type": "string", "enum": ["code"] },
+                "client_id": id(),
+                "redirect_uri": url(),
+                "scope": { "type": "string", "description": "Space-separated, the default scope of the service if missing" },
+                "state": string(),
+                "code_challenge": string(),
+                "code_challenge_method": { "type": "string", "enum": ["S256"] },
+                "nonce": { "type": "string", "description": "Returned in the ID token" },
+            }),
+        ),
+    );
+    add(
+        "AuthorizeResponse",
+        object(
+            &["redirectUri"],
+            json!({ "redirectUri": { "type": "string", "format": "uri", "description": "Redirect URI of the client with the code and the state, or the profile form if fields are missing" } }),
+        ),
+    );
+    add(
+        "ConsentResponse",
+        object(
+            &["client", "name", "service", "scope", "consentRequired"],
+            json!({
+                "client": id(),
+                "name": string(),
+                "description": string(),
+                "service": string(),
+                "scope": strings(),
+                "consentRequired": boolean(),
+                "profileRequired": array(reference("ProfileField")),
+            }),
+        ),
+    );
+    add(
+        "DeviceCodeRequest",
+        object(
+            &["client_id"],
+            json!({
+                "client_id": id(),
+                "client_secret": { "type": "string", "writeOnly": true },
+                "scope": { "type": "string", "description": "Space-separated, the default scope of the service if missing" },
+            }),
+        ),
+    );
+    add(
+        "DeviceCodeResponse",
+        object(
+            &[
+                "device_code",
+                "user_code",
+                "verification_uri",
+                "verification_uri_complete",
+                "expires_in",
+                "interval",
+            ],
+            json!({
+                "device_code": string(),
+                "user_code": string(),
+                "verification_uri": url(),
+                "verification_uri_complete": url(),
+                "expires_in": { "type": "integer" },
+                "interval": { "type": "integer" },
+            }),
+        ),
+    );
+    add(
+        "DeviceDecisionRequest",
+        object(
+            &["userCode", "approve"],
+            json!({ "userCode": string(), "approve": boolean() }),
+        ),
+    );
+    add(
+        "OAuthTokenRequest",
+        object(
+            &["grant_type", "client_id"],
+            json!({
+                "grant_type": {
+                    "type": "string",
+                    "enum": ["authorization_code", "urn:ietf:params:oauth:grant-type:device_code"],
+                },
+                "client_id": id(),
+                "client_secret": { "type": "string", "writeOnly": true },
+                "code": string(),
+                "redirect_uri": url(),
+                "code_verifier": string(),
+                "device_code": string(),
+            }),
+        ),
+    );
+    add(
+        "OAuthTokenResponse",
+        object(
+            &["access_token", "token_type", "expires_in", "scope"],
+            json!({
+                "access_token": string(),
+                "token_type": { "type": "string", "enum": ["Bearer"] },
+                "expires_in": { "type": "integer" },
+                "scope": string(),
+                "id_token": { "type": "string", "description": "With the `openid` scope" },
+            }),
+        ),
+    );
+    add(
+        "UserInfoResponse",
+        object(
+            &["sub", "email", "email_verified"],
+            json!({ "sub": id(), "email": string(), "email_verified": boolean() }),
+        ),
+    );
+
+    // Discovery
+    add(
+        "JwkSet",
+        object(
+            &["keys"],
+            json!({
+                "keys": array(json!({
+                    "type": "object",
+                    "required": ["kty", "kid", "alg", "use"],
+                    "properties": {
+                        "kty": { "type": "string", "enum": ["EC", "RSA", "OKP"] },
+                        "kid": string(),
+                        "alg": string(),
+                        "use": { "type": "string", "enum": ["sig"] },
+                    },
+                    "additionalProperties": true,
+                })),
+            }),
+        ),
+    );
+    add(
+        "OpenIdConfiguration",
+        json!({
+            "type": "object",
+            "required": ["issuer", "authorization_endpoint", "token_endpoint", "userinfo_endpoint", "jwks_uri"],
+            "properties": {
+                "issuer": string(),
+                "authorization_endpoint": url(),
+                "token_endpoint": url(),
+                "userinfo_endpoint": url(),
+                "device_authorization_endpoint": url(),
+                "jwks_uri": url(),
+            },
+            "additionalProperties": true,
+        }),
+    );
+
+    s
+}
@@ -0,0 +1,39 @@
+use crate::{
+    authentication::AuthenticationError,
+    extract::{Query, TokenData},
+    mail::Inbox,
+    model::{List, ListOptions, Response},
+    session::SessionClaims,
+};
+
+use super::OutboxMessageResponse;
+
+use axum::extract::Extension;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Filter {
+    /// Recipient address
+    to: Option<String>,
+}
+
+/// Captured mails, newest first
+pub async fn list(
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(filter): Query<Filter>,
+    Query(opts): Query<ListOptions>,
+    Extension(inbox): Extension<Inbox>,
+) -> crate::Result<Response<List<OutboxMessageResponse>>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let messages = inbox.messages(filter.to.as_deref());
+    let total = messages.len() as u64;
+    let page = messages
+        .into_iter()
+        .skip(opts.offset as usize)
+        .take(opts.limit.max(0) as usize);
+
+    Ok(Response::new(List::new(total, page)))
+}
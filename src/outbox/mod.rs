@@ -0,0 +1,53 @@
+//! Outbox of sandbox deployments at `/v1/admin/outbox`: the mails captured by the
+//! [`Inbox`](crate::mail::Inbox), so that end-to-end tests of sign-up and password reset can
+//! follow the links without a mail server.
+
+mod handler;
+mod routes;
+
+use crate::mail::{Body, Captured};
+
+use std::collections::HashMap;
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use serde::Serialize;
+
+pub use routes::routes;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BodyResponse {
+    /// Template rendered by the transport, its variables hold the links and tokens
+    Template {
+        template: String,
+        vars: HashMap<String, String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxMessageResponse {
+    pub to: String,
+    pub subject: String,
+    pub body: BodyResponse,
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+}
+
+impl From<Captured> for OutboxMessageResponse {
+    fn from(captured: Captured) -> Self {
+        let body = match captured.message.body {
+            Body::Template { name, vars } => BodyResponse::Template {
+                template: name,
+                vars,
+            },
+        };
+
+        Self {
+            to: captured.message.to,
+            subject: captured.message.subject,
+            body,
+            date: captured.date,
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use super::handler;
+
+use crate::mail::Inbox;
+
+use axum::routing::get;
+use tower_http::add_extension::AddExtensionLayer;
+
+/// Outbox routes
+pub fn routes(inbox: Inbox) -> axum::Router {
+    axum::Router::new()
+        .route("/", get(handler::list))
+        .layer(AddExtensionLayer::new(inbox))
+}
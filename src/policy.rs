@@ -0,0 +1,369 @@
+//! Login, issuance and consent rules as sandboxed WASM modules, so that security teams can
+//! ship rules independently of server releases.
+//!
+//! Every `*.wasm` file of the policy directory is a policy, evaluated in the order of the file
+//! names. The directory is watched and the policies are replaced once all of them compiled.
+//!
+//! A policy exports its `memory`, `alloc(len: i32) -> i32` and
+//! `decide(ptr: i32, len: i32) -> i64`. The host writes the context as JSON to the memory
+//! returned by `alloc` and calls `decide`, which returns the address of the decision in the
+//! upper and its length in the lower 32 bits:
+//!
+//! ```json
+//! {"event": "login", "user": {"id": "…", "email": "…", "roles": []}, "method": "password"}
+//! {"decision": "deny", "reason": "logins from contractors are paused"}
+//! ```
+//!
+//! Events are `login`, `issuance` and `consent` with `client` and `scope`. Decisions are
+//! `allow`, `deny` and `modify`, which adds `claims` to the session token on issuance.
+//! Policies can't import anything and run with limited fuel and memory. A policy that fails
+//! denies the request.
+
+use crate::{
+    hooks::{async_trait, Claims, Hook, Subject, Veto},
+    utils,
+};
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Instructions a policy may execute per decision, roughly
+const FUEL: u64 = 10_000_000;
+const MAX_MEMORY: usize = 16 << 20;
+const MAX_DECISION_LEN: usize = 64 << 10;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum Event {
+    Login,
+    Issuance,
+    Consent,
+}
+
+#[derive(Debug, Serialize)]
+struct UserContext<'a> {
+    id: &'a str,
+    email: &'a str,
+    roles: &'a [String],
+}
+
+#[derive(Debug, Serialize)]
+struct Context<'a> {
+    event: Event,
+    user: UserContext<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<&'a [String]>,
+}
+
+impl<'a> Context<'a> {
+    fn new(event: Event, user: &'a Subject) -> Self {
+        Self {
+            event,
+            user: UserContext {
+                id: &user.id,
+                email: &user.email,
+                roles: &user.roles,
+            },
+            method: None,
+            client: None,
+            scope: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Verdict {
+    Allow,
+    Deny,
+    Modify,
+}
+
+#[derive(Debug, Deserialize)]
+struct Decision {
+    decision: Verdict,
+    reason: Option<String>,
+    #[serde(default)]
+    claims: Claims,
+}
+
+struct Policy {
+    name: String,
+    module: Module,
+}
+
+impl Policy {
+    fn decide(&self, engine: &Engine, ctx: &Context) -> wasmtime::Result<Decision> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store: Store<StoreLimits> = Store::new(engine, limits);
+        store.limiter(|l| l);
+        store.set_fuel(FUEL)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("memory is not exported"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let decide = instance.get_typed_func::<(i32, i32), i64>(&mut store, "decide")?;
+
+        let input = serde_json::to_vec(ctx)?;
+        let ptr = alloc.call(&mut store, input.len() as i32)?;
+        memory.write(&mut store, ptr as u32 as usize, &input)?;
+
+        let packed = decide.call(&mut store, (ptr, input.len() as i32))?;
+        let (ptr, len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+        if len > MAX_DECISION_LEN {
+            return Err(wasmtime::Error::msg("decision is too long"));
+        }
+
+        let mut output = vec![0; len];
+        memory.read(&store, ptr, &mut output)?;
+
+        Ok(serde_json::from_slice(&output)?)
+    }
+}
+
+/// Files of the policies with their modification time, to detect changes
+fn fingerprint(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "wasm") {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            files.push((path, modified));
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+fn compile(engine: &Engine, files: &[(PathBuf, SystemTime)]) -> wasmtime::Result<Vec<Policy>> {
+    files
+        .iter()
+        .map(|(path, _)| {
+            let name = path
+                .file_stem()
+                .map(|v| v.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let module = Module::from_file(engine, path)
+                .map_err(|e| e.context(format!("policy {} is invalid", name)))?;
+
+            Ok(Policy { name, module })
+        })
+        .collect()
+}
+
+/// Policies of the directory, registered as hook
+#[derive(Clone)]
+pub struct WasmPolicies {
+    engine: Engine,
+    dir: PathBuf,
+    policies: Arc<RwLock<Arc<Vec<Policy>>>>,
+}
+
+impl WasmPolicies {
+    /// Compiles the policies of the directory, fails if any is invalid
+    pub fn load(dir: PathBuf) -> crate::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(config_error)?;
+
+        let files = fingerprint(&dir).map_err(|e| config_error(e.into()))?;
+        let policies = compile(&engine, &files).map_err(config_error)?;
+        info!(count = policies.len(), dir = %dir.display(), "policies loaded");
+
+        Ok(Self {
+            engine,
+            dir,
+            policies: Arc::new(RwLock::new(Arc::new(policies))),
+        })
+    }
+
+    /// Recompiles the policies when files of the directory change, the previous ones stay in
+    /// effect while any of them is invalid
+    pub fn spawn_reload(self, interval: Duration) {
+        utils::spawn_named("policy-reload", async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut last = fingerprint(&self.dir).unwrap_or_default();
+
+            loop {
+                interval.tick().await;
+
+                let files = match fingerprint(&self.dir) {
+                    Ok(v) if v != last => v,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        error!(error = %e, "policy directory could not be read");
+                        continue;
+                    }
+                };
+
+                let engine = self.engine.clone();
+                let compiled = {
+                    let files = files.clone();
+                    tokio::task::spawn_blocking(move || compile(&engine, &files)).await
+                };
+
+                last = files;
+
+                match compiled {
+                    Ok(Ok(policies)) => {
+                        info!(count = policies.len(), "policies reloaded");
+                        *self.policies.write().unwrap() = Arc::new(policies);
+                    }
+                    Ok(Err(e)) => error!(error = %e, "policies could not be reloaded"),
+                    Err(e) => error!(error = %e, "policy compilation panicked"),
+                }
+            }
+        });
+    }
+
+    /// Evaluates all policies, the first denial wins
+    fn evaluate(&self, ctx: &Context) -> Result<Claims, Veto> {
+        let policies = self.policies.read().unwrap().clone();
+        let mut claims = Claims::new();
+
+        for policy in policies.iter() {
+            let decision = match policy.decide(&self.engine, ctx) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(policy = %policy.name, error = %e, "policy failed");
+                    return Err(Veto(format!("policy {} failed", policy.name)));
+                }
+            };
+
+            match decision.decision {
+                Verdict::Allow => {}
+                Verdict::Deny => {
+                    let reason = decision
+                        .reason
+                        .unwrap_or_else(|| format!("denied by policy {}", policy.name));
+                    return Err(Veto(reason));
+                }
+                Verdict::Modify => claims.extend(decision.claims),
+            }
+        }
+
+        Ok(claims)
+    }
+}
+
+fn config_error(e: wasmtime::Error) -> crate::error::Error {
+    crate::error::Error::Config(format!("{:#}", e))
+}
+
+#[async_trait]
+impl Hook for WasmPolicies {
+    async fn pre_issuance(&self, user: &Subject) -> Result<Claims, Veto> {
+        self.evaluate(&Context::new(Event::Issuance, user))
+    }
+
+    async fn post_login(&self, user: &Subject, method: &str) -> Result<(), Veto> {
+        let mut ctx = Context::new(Event::Login, user);
+        ctx.method = Some(method);
+
+        self.evaluate(&ctx).map(|_| ())
+    }
+
+    async fn pre_consent(
+        &self,
+        user: &Subject,
+        client: &str,
+        scope: &[String],
+    ) -> Result<(), Veto> {
+        let mut ctx = Context::new(Event::Consent, user);
+        ctx.client = Some(client);
+        ctx.scope = Some(scope);
+
+        self.evaluate(&ctx).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Policy that returns a constant decision from its data segment
+    fn constant(decision: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param i32) (result i32) i32.const 1024)
+                (func (export "decide") (param i32 i32) (result i64) i64.const {}))"#,
+            decision.replace('"', "\\\""),
+            decision.len()
+        )
+    }
+
+    fn policies(sources: &[String]) -> WasmPolicies {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).unwrap();
+
+        let policies = sources
+            .iter()
+            .enumerate()
+            .map(|(i, s)| Policy {
+                name: i.to_string(),
+                module: Module::new(&engine, s).unwrap(),
+            })
+            .collect();
+
+        WasmPolicies {
+            engine,
+            dir: PathBuf::new(),
+            policies: Arc::new(RwLock::new(Arc::new(policies))),
+        }
+    }
+
+    fn subject() -> Subject {
+        Subject {
+            id: "5f1c0e0e0e0e0e0e0e0e0e0e".to_string(),
+            email: "user@example.com".to_string(),
+            roles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn applies_decisions() {
+        let user = subject();
+        let ctx = Context::new(Event::Issuance, &user);
+
+        let modify = constant(r#"{"decision":"modify","claims":{"tier":"gold"}}"#);
+        let claims = policies(std::slice::from_ref(&modify))
+            .evaluate(&ctx)
+            .unwrap();
+        assert_eq!(claims["tier"], "gold");
+
+        let deny = constant(r#"{"decision":"deny","reason":"blocked"}"#);
+        let veto = policies(&[modify, deny]).evaluate(&ctx).unwrap_err();
+        assert_eq!(veto.0, "blocked");
+    }
+
+    #[test]
+    fn denies_on_failure() {
+        let user = subject();
+        let ctx = Context::new(Event::Login, &user);
+
+        let endless = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "decide") (param i32 i32) (result i64) (loop (br 0)) i64.const 0))"#;
+
+        assert!(policies(&[endless.to_string()]).evaluate(&ctx).is_err());
+    }
+}
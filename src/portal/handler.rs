@@ -0,0 +1,24 @@
+use super::Settings;
+
+use crate::{
+    authentication::token::TokenConfig, config::GlobalConfig, database::Database, well_known,
+};
+
+use axum::{extract::Extension, response::Html};
+
+pub async fn portal(
+    Extension(settings): Extension<Settings>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Html<String>> {
+    let services = db.get_all_services().await?;
+    let configuration = well_known::configuration(&global, &config);
+
+    Ok(Html(super::render(
+        settings,
+        &global.base_path,
+        configuration.as_ref(),
+        &services,
+    )))
+}
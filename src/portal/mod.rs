@@ -0,0 +1,247 @@
+//! Portal for integrators at `/docs`, rendered on each request from the configuration and the
+//! services of this deployment: the operations of the OpenAPI description, the OpenID Connect
+//! discovery values, the scopes of every service and the bodies of webhooks.
+
+mod handler;
+mod routes;
+
+use crate::{
+    keys, openapi, service::ServiceDocument, utils::push_escaped, well_known::Configuration,
+};
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde_json::Value;
+
+pub use routes::routes;
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:960px;margin:2em auto;padding:0 1em}\
+table{border-collapse:collapse;width:100%;margin-bottom:1.5em}\
+th,td{border-bottom:1px solid #ddd;padding:.3em .5em;text-align:left;vertical-align:top}\
+code{font-size:.9em}";
+
+/// Pushes the text HTML-escaped
+fn text(out: &mut String, value: &str) {
+    push_escaped(out, value);
+}
+
+/// Plain value of a JSON document, arrays comma-separated
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(list) => list.iter().map(plain).collect::<Vec<_>>().join(", "),
+        v => v.to_string(),
+    }
+}
+
+fn operations(out: &mut String, spec: &Value, base: &str, swagger_ui: bool) {
+    out.push_str("<h2 id=\"api\">API</h2>\n<p>The <a href=\"");
+    text(out, base);
+    out.push_str("/v1/openapi.json\">OpenAPI description</a>");
+    if swagger_ui {
+        out.push_str(" can also be explored in the <a href=\"");
+        text(out, base);
+        out.push_str("/v1/docs\">Swagger UI</a>");
+    }
+    out.push_str(" and used to generate clients.</p>\n");
+
+    let mut by_tag: BTreeMap<&str, Vec<(&str, String, &str)>> = BTreeMap::new();
+    if let Some(paths) = spec["paths"].as_object() {
+        for (path, item) in paths {
+            for (method, op) in item.as_object().into_iter().flatten() {
+                let tag = op["tags"][0].as_str().unwrap_or_default();
+                let summary = op["summary"].as_str().unwrap_or_default();
+                by_tag
+                    .entry(tag)
+                    .or_default()
+                    .push((method, format!("{}{}", base, path), summary));
+            }
+        }
+    }
+
+    for (tag, ops) in by_tag {
+        out.push_str("<h3>");
+        text(out, tag);
+        out.push_str("</h3>\n<table>\n");
+        for (method, path, summary) in ops {
+            out.push_str("<tr><td><code>");
+            text(out, &method.to_uppercase());
+            out.push_str("</code></td><td><code>");
+            text(out, &path);
+            out.push_str("</code></td><td>");
+            text(out, summary);
+            out.push_str("</td></tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+}
+
+fn discovery(out: &mut String, configuration: Option<&Configuration>, base: &str) {
+    out.push_str("<h2 id=\"oidc\">OpenID Connect</h2>\n");
+
+    let value = configuration.and_then(|c| serde_json::to_value(c).ok());
+    let values = match value.as_ref().and_then(Value::as_object) {
+        Some(v) => v,
+        None => {
+            out.push_str("<p>OpenID Connect is not enabled on this deployment.</p>\n");
+            return;
+        }
+    };
+
+    out.push_str("<p>Values of the <a href=\"");
+    text(out, base);
+    out.push_str("/.well-known/openid-configuration\">discovery document</a>.</p>\n<table>\n");
+    for (name, value) in values {
+        out.push_str("<tr><th><code>");
+        text(out, name);
+        out.push_str("</code></th><td>");
+        text(out, &plain(value));
+        out.push_str("</td></tr>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+fn scopes(out: &mut String, services: &[ServiceDocument]) {
+    out.push_str("<h2 id=\"scopes\">Scopes</h2>\n");
+    if services.is_empty() {
+        out.push_str("<p>No services are registered yet.</p>\n");
+        return;
+    }
+
+    for svc in services {
+        out.push_str("<h3>");
+        text(out, &svc.name);
+        out.push_str("</h3>\n<p>Audience: <code>");
+        text(out, &svc.audience.join(", "));
+        out.push_str("</code>");
+        let fields = svc.required_profile_fields();
+        if !fields.is_empty() {
+            out.push_str("<br>Required profile fields: ");
+            let names: Vec<_> = fields.iter().map(|f| f.as_str()).collect();
+            text(out, &names.join(", "));
+        }
+        if let Some(age) = svc.min_age {
+            let _ = write!(out, "<br>Minimum age: {}", age);
+        }
+        out.push_str("</p>\n<table>\n");
+
+        for scope in &svc.scope {
+            let mut notes = Vec::new();
+            if svc.scope_default.contains(scope) {
+                notes.push("granted by default");
+            }
+            if svc.scope_privileged.contains(scope) {
+                notes.push("privileged");
+            }
+
+            out.push_str("<tr><td><code>");
+            text(out, scope);
+            out.push_str("</code></td><td>");
+            text(out, &notes.join(", "));
+            out.push_str("</td></tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+}
+
+fn webhooks(out: &mut String, key_alerts: bool) {
+    out.push_str("<h2 id=\"webhooks\">Webhooks</h2>\n<h3>Key expiry</h3>\n<p>");
+    out.push_str(if key_alerts {
+        "Posted to the configured URL 30, 7 and 1 days before a key expires and once it expired."
+    } else {
+        "Not configured on this deployment. Posted 30, 7 and 1 days before a key expires and once it expired."
+    });
+    out.push_str("</p>\n<table>\n");
+
+    let schema = keys::webhook_schema();
+    for (name, prop) in schema["properties"].as_object().into_iter().flatten() {
+        out.push_str("<tr><td><code>");
+        text(out, name);
+        out.push_str("</code></td><td>");
+        text(out, prop["type"].as_str().unwrap_or_default());
+        out.push_str("</td><td>");
+        text(out, prop["description"].as_str().unwrap_or_default());
+        out.push_str("</td></tr>\n");
+    }
+    out.push_str("</table>\n");
+}
+
+/// Settings of the deployment that aren't part of the global configuration
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// The Swagger UI is served at `/v1/docs`
+    pub swagger_ui: bool,
+    /// A key expiry webhook is configured
+    pub key_alerts: bool,
+}
+
+/// Renders the portal page, with links below the base path of the routes
+pub fn render(
+    settings: Settings,
+    base: &str,
+    configuration: Option<&Configuration>,
+    services: &[ServiceDocument],
+) -> String {
+    let mut out = String::new();
+
+    out.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str("<title>identity-server for integrators</title>\n<style>");
+    out.push_str(STYLE);
+    out.push_str("</style>\n</head>\n<body>\n<h1>identity-server for integrators</h1>\n");
+    out.push_str("<p><a href=\"#api\">API</a> · <a href=\"#oidc\">OpenID Connect</a> · <a href=\"#scopes\">Scopes</a> · <a href=\"#webhooks\">Webhooks</a></p>\n");
+
+    operations(&mut out, &openapi::spec(), base, settings.swagger_ui);
+    discovery(&mut out, configuration, base);
+    scopes(&mut out, services);
+    webhooks(&mut out, settings.key_alerts);
+
+    out.push_str("</body>\n</html>\n");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Utc;
+    use mongodb::bson::oid::ObjectId;
+
+    fn service() -> ServiceDocument {
+        ServiceDocument {
+            id: ObjectId::new(),
+            name: "Items <API>".into(),
+            audience: vec!["items".into()],
+            scope: vec!["item:read".into(), "item:write".into()],
+            scope_default: vec!["item:read".into()],
+            scope_privileged: vec!["item:write".into()],
+            secret: None,
+            labels: Default::default(),
+            health_url: None,
+            profile_fields: Vec::new(),
+            min_age: Some(18),
+            last_modified: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn renders_the_deployment() {
+        let settings = Settings {
+            swagger_ui: false,
+            key_alerts: false,
+        };
+        let page = render(settings, "", None, &[service()]);
+
+        assert!(page.contains("<code>/v1/client/{id}</code>"));
+        assert!(!page.contains("/v1/docs"));
+        assert!(page.contains("OpenID Connect is not enabled"));
+        assert!(page.contains("<h3>Items &lt;API&gt;</h3>"));
+        assert!(page.contains("Required profile fields: birthdate<br>Minimum age: 18"));
+        assert!(page.contains("<tr><td><code>item:write</code></td><td>privileged</td></tr>"));
+        assert!(page.contains("<code>daysBefore</code>"));
+
+        let page = render(settings, "/auth", None, &[]);
+        assert!(page.contains("<a href=\"/auth/v1/openapi.json\">"));
+        assert!(page.contains("<code>/auth/v1/client/{id}</code>"));
+    }
+}
@@ -0,0 +1,11 @@
+use super::{handler, Settings};
+
+use axum::routing::get;
+use tower_http::add_extension::AddExtensionLayer;
+
+/// Portal routes
+pub fn routes(settings: Settings) -> axum::Router {
+    axum::Router::new()
+        .route("/", get(handler::portal))
+        .layer(AddExtensionLayer::new(settings))
+}
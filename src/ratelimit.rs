@@ -0,0 +1,378 @@
+//! Rate limits of logins, SSO callbacks and token endpoints by client address and by account,
+//! against credential stuffing and brute force
+//!
+//! Limits follow the generic cell rate algorithm: a key earns `requests` per window and may spend
+//! up to `burst` of them at once. The theoretical arrival time of the next request is all that is
+//! stored per key, in memory of the instance or in the cache to share limits between instances.
+
+use crate::{config::RateLimitConfig, extract::RemoteAddr, model::Status};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "cache")]
+use crate::cache::Cache;
+use axum::{
+    body::Body,
+    extract::{FromRequest, RequestParts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    body::HttpBody,
+    header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER},
+    Method, Request, StatusCode,
+};
+#[cfg(feature = "cache")]
+use redis::Script;
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+/// Keys of the memory store after which expired ones are dropped
+const MAX_KEYS: usize = 100_000;
+/// Bodies read to find the account, larger ones are limited by address only. Bodies without a
+/// length are rejected beyond it, they would bypass the limit of the account otherwise.
+const MAX_BODY_LEN: u64 = 16 << 10;
+
+/// Checks if requests to the path are rate limited
+fn is_limited(method: &Method, path: &str) -> bool {
+    let under = |prefix: &str| {
+        path.strip_prefix(prefix)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+    };
+
+    if under("/v1/login") || under("/oauth/token") || under("/oauth/device/code") {
+        return true;
+    }
+    if path.starts_with("/v1/sso/") && path.ends_with("/authorized") {
+        return true;
+    }
+
+    method == Method::POST && (under("/v1/session") || under("/v1/token"))
+}
+
+/// Next arrival time if the request conforms, or the time to wait until it does
+fn gcra(tat: Option<u64>, now: u64, interval: u64, tolerance: u64) -> Result<u64, u64> {
+    let tat = tat.map_or(now, |v| v.max(now));
+    let allowed_at = tat.saturating_sub(tolerance);
+
+    if now < allowed_at {
+        Err(allowed_at - now)
+    } else {
+        Ok(tat + interval)
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |v| v.as_millis() as u64)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore(Arc<Mutex<HashMap<String, u64>>>);
+
+impl MemoryStore {
+    fn acquire(&self, key: &str, interval: u64, tolerance: u64) -> Option<u64> {
+        let now = now_millis();
+        let mut keys = self.0.lock().unwrap();
+
+        if keys.len() >= MAX_KEYS {
+            keys.retain(|_, tat| *tat > now);
+        }
+
+        match gcra(keys.get(key).copied(), now, interval, tolerance) {
+            Ok(tat) => {
+                keys.insert(key.to_string(), tat);
+                None
+            }
+            Err(wait) => Some(wait),
+        }
+    }
+}
+
+/// Store shared between instances, requests are let through while the cache is unavailable
+#[cfg(feature = "cache")]
+#[derive(Clone)]
+pub struct CacheStore {
+    cache: Cache,
+    script: Arc<Script>,
+}
+
+#[cfg(feature = "cache")]
+impl CacheStore {
+    /// Same as [`gcra`], with the clock of Redis
+    const SCRIPT: &'static str = r#"
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local tat = math.max(tonumber(redis.call('GET', KEYS[1]) or now), now)
+        local wait = tat - tonumber(ARGV[2]) - now
+        if wait > 0 then
+            return wait
+        end
+        tat = tat + tonumber(ARGV[1])
+        redis.call('SET', KEYS[1], tat, 'PX', tat - now)
+        return 0
+    "#;
+
+    pub fn new(cache: Cache) -> Self {
+        Self {
+            cache,
+            script: Arc::new(Script::new(Self::SCRIPT)),
+        }
+    }
+
+    async fn acquire(&self, key: &str, interval: u64, tolerance: u64) -> Option<u64> {
+        let wait = self
+            .cache
+            .eval::<u64>(
+                &self.script,
+                &format!("ratelimit:{}", key),
+                &[interval, tolerance],
+            )
+            .await;
+
+        match wait {
+            Ok(0) => None,
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!(error = %e, "rate limit could not be checked");
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Store {
+    Memory(MemoryStore),
+    #[cfg(feature = "cache")]
+    Cache(CacheStore),
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// Milliseconds between conforming requests
+    interval: u64,
+    /// Milliseconds a key may run ahead of its rate
+    tolerance: u64,
+    store: Store,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, store: Store) -> Self {
+        let interval =
+            (config.window.as_millis() as u64 / u64::from(config.requests.max(1))).max(1);
+
+        Self {
+            interval,
+            tolerance: interval * u64::from(config.burst.max(1) - 1),
+            store,
+        }
+    }
+
+    /// Takes a request of the key, returns the time to wait if it exceeds the limit
+    async fn acquire(&self, key: &str) -> Option<Duration> {
+        let wait = match &self.store {
+            Store::Memory(s) => s.acquire(key, self.interval, self.tolerance),
+            #[cfg(feature = "cache")]
+            Store::Cache(s) => s.acquire(key, self.interval, self.tolerance).await,
+        };
+
+        wait.map(Duration::from_millis)
+    }
+}
+
+/// Account the request is sent for, the `email` of JSON and the `client_id` of form bodies
+fn account(content_type: Option<&HeaderValue>, body: &[u8]) -> Option<String> {
+    let content_type = content_type
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    if content_type.starts_with("application/json") {
+        let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+        value["email"].as_str().map(|v| v.trim().to_lowercase())
+    } else if content_type.starts_with("application/x-www-form-urlencoded") {
+        url::form_urlencoded::parse(body)
+            .find(|(k, _)| k == "client_id")
+            .map(|(_, v)| v.into_owned())
+    } else {
+        None
+    }
+}
+
+/// Reads the body up to `limit` bytes, returns `None` if it is longer
+async fn read_body(mut body: Body, limit: u64) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if (buf.len() + chunk.len()) as u64 > limit {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(buf))
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    let secs = (wait.as_millis() as u64 + 999) / 1000;
+
+    let mut res = Status::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        format!("too many requests, retry in {} seconds", secs),
+    )
+    .into_response();
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(secs));
+
+    res
+}
+
+/// Middleware that limits requests by client address and by the account they are sent for
+pub async fn limit(req: Request<Body>, next: Next<Body>, limiter: RateLimiter) -> Response {
+    if !is_limited(req.method(), req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let mut parts = RequestParts::new(req);
+    let addr = match RemoteAddr::from_request(&mut parts).await {
+        Ok(RemoteAddr(v)) => v,
+        Err(e) => return e.into_response(),
+    };
+    let req = parts.try_into_request().expect("body extracted");
+
+    let mut keys = vec![format!("ip:{}", addr)];
+
+    let body_len = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+    let req = match body_len {
+        Some(len) if len == 0 || len > MAX_BODY_LEN => req,
+        _ => {
+            let (parts, body) = req.into_parts();
+            let body = match read_body(body, MAX_BODY_LEN).await {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    return Status::new(StatusCode::PAYLOAD_TOO_LARGE, "request body is too large")
+                        .into_response()
+                }
+                Err(e) => {
+                    return Status::new(StatusCode::BAD_REQUEST, e.to_string()).into_response()
+                }
+            };
+            if let Some(account) = account(parts.headers.get(CONTENT_TYPE), &body) {
+                keys.push(format!("account:{}", account));
+            }
+
+            Request::from_parts(parts, Body::from(body))
+        }
+    };
+
+    for key in keys {
+        if let Some(wait) = limiter.acquire(&key).await {
+            return too_many_requests(wait);
+        }
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_logins_and_token_endpoints() {
+        assert!(is_limited(&Method::POST, "/v1/session"));
+        assert!(is_limited(&Method::POST, "/v1/session/mfa"));
+        assert!(is_limited(&Method::POST, "/v1/login/email"));
+        assert!(is_limited(&Method::POST, "/v1/token/client-credentials"));
+        assert!(is_limited(&Method::POST, "/oauth/token"));
+        assert!(is_limited(&Method::GET, "/v1/sso/github/authorized"));
+        assert!(is_limited(&Method::POST, "/v1/sso/apple/authorized"));
+
+        assert!(!is_limited(&Method::GET, "/v1/session"));
+        assert!(!is_limited(&Method::GET, "/v1/token"));
+        assert!(!is_limited(&Method::GET, "/v1/sso/github/authorize"));
+        assert!(!is_limited(&Method::POST, "/v1/tokens"));
+        assert!(!is_limited(&Method::POST, "/v1/user"));
+    }
+
+    #[tokio::test]
+    async fn reads_bodies_without_length() {
+        let chunks = vec![Ok::<_, std::io::Error>("{\"email\":"), Ok(" \"a@b.c\"}")];
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+
+        let body = read_body(body, MAX_BODY_LEN).await.unwrap().unwrap();
+        assert_eq!(
+            account(Some(&HeaderValue::from_static("application/json")), &body),
+            Some("a@b.c".to_string())
+        );
+
+        let body = Body::wrap_stream(futures::stream::iter(vec![
+            Ok::<_, std::io::Error>("x".repeat(8)),
+            Ok("x".repeat(8)),
+        ]));
+        assert!(read_body(body, 10).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn allows_bursts_at_the_rate() {
+        let (interval, tolerance) = (1000, 2000);
+
+        let mut tat = None;
+        for _ in 0..3 {
+            tat = Some(gcra(tat, 0, interval, tolerance).unwrap());
+        }
+        assert_eq!(gcra(tat, 0, interval, tolerance), Err(1000));
+        assert_eq!(gcra(tat, 500, interval, tolerance), Err(500));
+        assert_eq!(gcra(tat, 1000, interval, tolerance), Ok(4000));
+
+        // An idle key starts over with its full burst
+        assert_eq!(gcra(tat, 10_000, interval, tolerance), Ok(11_000));
+    }
+
+    #[tokio::test]
+    async fn limits_each_key() {
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                requests: 2,
+                window: Duration::from_secs(60),
+                burst: 2,
+            },
+            Store::Memory(MemoryStore::default()),
+        );
+
+        assert!(limiter.acquire("ip:127.0.0.1").await.is_none());
+        assert!(limiter.acquire("ip:127.0.0.1").await.is_none());
+        let wait = limiter.acquire("ip:127.0.0.1").await.unwrap();
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+
+        assert!(limiter.acquire("account:user@example.com").await.is_none());
+    }
+
+    #[test]
+    fn finds_the_account() {
+        let json = HeaderValue::from_static("application/json");
+        let form = HeaderValue::from_static("application/x-www-form-urlencoded");
+
+        assert_eq!(
+            account(
+                Some(&json),
+                br#"{"email":" User@Example.com","password":"x"}"#
+            )
+            .as_deref(),
+            Some("user@example.com")
+        );
+        assert_eq!(
+            account(Some(&form), b"grant_type=authorization_code&client_id=app").as_deref(),
+            Some("app")
+        );
+        assert_eq!(account(None, b"email=user@example.com"), None);
+    }
+}
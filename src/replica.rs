@@ -0,0 +1,103 @@
+//! Read-only replica mode, serving reads from a nearby database replica while all
+//! mutations are left to the primary instance
+
+use crate::model::Status;
+
+use axum::{
+    extract::OriginalUri,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::{
+    header::{HeaderValue, LOCATION},
+    Method, Request, StatusCode,
+};
+use reqwest::Url;
+
+/// Reads that still write, e.g. to record a session or the last issued token
+const WRITING_READS: &[&str] = &[
+    "/v1/session",
+    "/v1/token",
+    "/v1/action",
+    "/v1/sso",
+    "/v1/user/verify",
+];
+
+/// Posts that only read, e.g. token introspection
+const READING_POSTS: &[&str] = &["/v1/admin/verify-token"];
+
+/// Checks if the request only reads and can be served by a replica
+fn is_read(method: &Method, path: &str) -> bool {
+    let matches = |prefixes: &[&str]| {
+        prefixes.iter().any(|p| {
+            path.strip_prefix(p)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    };
+
+    match *method {
+        Method::GET | Method::HEAD => !matches(WRITING_READS),
+        Method::POST => matches(READING_POSTS),
+        _ => false,
+    }
+}
+
+/// Middleware that rejects mutating requests with a pointer to the primary
+pub async fn reject_writes<B>(req: Request<B>, next: Next<B>, primary: Url) -> Response {
+    if is_read(req.method(), req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    // Paths below a base path are only complete in the original URI
+    let uri = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri(), |uri| &uri.0);
+    let mut location = primary;
+    location.set_path(uri.path());
+    location.set_query(uri.query());
+
+    let mut res = Status::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        format!("read-only replica, send the request to {}", location),
+    )
+    .into_response();
+    if let Ok(v) = HeaderValue::from_str(location.as_str()) {
+        res.headers_mut().insert(LOCATION, v);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_reads() {
+        assert!(is_read(&Method::GET, "/v1/user/123"));
+        assert!(is_read(&Method::GET, "/v1/user/me/security-events"));
+        assert!(is_read(&Method::HEAD, "/v1/client"));
+        assert!(is_read(&Method::GET, "/v1/tokens"));
+        assert!(is_read(&Method::POST, "/v1/admin/verify-token"));
+    }
+
+    #[test]
+    fn rejects_writes() {
+        assert!(!is_read(&Method::POST, "/v1/user"));
+        assert!(!is_read(&Method::PATCH, "/v1/client/123"));
+        assert!(!is_read(&Method::DELETE, "/v1/service/123"));
+        assert!(!is_read(&Method::PUT, "/chaos"));
+        assert!(!is_read(&Method::DELETE, "/v1/admin/verify-token"));
+        assert!(!is_read(&Method::POST, "/v1/admin/verify-tokens"));
+    }
+
+    #[test]
+    fn rejects_reads_that_write() {
+        assert!(!is_read(&Method::GET, "/v1/session"));
+        assert!(!is_read(&Method::GET, "/v1/token"));
+        assert!(!is_read(&Method::GET, "/v1/action/verify"));
+        assert!(!is_read(&Method::GET, "/v1/sso/github/authorized"));
+        assert!(!is_read(&Method::GET, "/v1/user/verify"));
+    }
+}
@@ -0,0 +1,499 @@
+//! Change history of users, clients and services
+
+use crate::{
+    database::Database,
+    error,
+    model::{ListOptions, Status},
+    session::Resource,
+    Result,
+};
+
+use std::sync::{Arc, Mutex};
+
+use axum::{middleware::Next, response::Response};
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use hyper::{Request, StatusCode};
+use mongodb::{
+    bson::{
+        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_document, Bson,
+        Document,
+    },
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+tokio::task_local! {
+    static ACTOR: Arc<Mutex<Option<String>>>;
+}
+
+/// Fields that change without an update of the resource itself
+const IGNORED: &[&str] = &[
+    "_id",
+    "lastModified",
+    "lastSessions",
+    "lastIssued",
+    "failedLogins",
+    "lockedUntil",
+];
+
+/// Fields whose values are never part of the history
+const REDACTED: &[&str] = &["password", "secret", "providerTokens", "mfa"];
+
+const REDACTED_VALUE: &str = "redacted";
+
+/// Fields a revert may restore. Credentials, revocations and lock state are left out, restoring
+/// them would make revoked tokens valid again or undo a lock.
+const REVERTIBLE: &[&str] = &[
+    // Users
+    "name",
+    "profile",
+    "roles",
+    "flags",
+    // Clients
+    "scope",
+    "labels",
+    "description",
+    "contact",
+    "documentationUrl",
+    "redirectUris",
+    "schedule",
+    "legacyTokenDisabled",
+    // Services
+    "audience",
+    "scopeDefault",
+    "scopePrivileged",
+    "healthUrl",
+    "profileFields",
+    "minAge",
+];
+
+/// Revisions older than this can no longer be reverted
+const REVERT_WINDOW_DAYS: i64 = 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RevisionError {
+    #[error("revision not found")]
+    NotFound,
+    #[error("revision id is invalid")]
+    InvalidId,
+    #[error("revision is too old to be reverted")]
+    Expired,
+    #[error("revision changed fields that can not be reverted: {}", .0.join(", "))]
+    NotRevertible(Vec<String>),
+    #[error("fields were changed again since the revision: {}", .0.join(", "))]
+    Conflict(Vec<String>),
+}
+
+impl error::ErrorResponse for RevisionError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RevisionError::NotFound => StatusCode::NOT_FOUND,
+            RevisionError::InvalidId => StatusCode::BAD_REQUEST,
+            RevisionError::Expired | RevisionError::NotRevertible(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            RevisionError::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Records the subject of the validated token of the current request as actor
+pub fn record_actor(sub: &str) {
+    let _ = ACTOR.try_with(|a| *a.lock().unwrap() = Some(sub.to_string()));
+}
+
+pub fn current_actor() -> Option<String> {
+    ACTOR.try_with(|a| a.lock().unwrap().clone()).ok().flatten()
+}
+
+/// Middleware that tracks the actor of a request for the revisions it causes
+pub async fn track_actor<B>(req: Request<B>, next: Next<B>) -> Response {
+    ACTOR.scope(Default::default(), next.run(req)).await
+}
+
+/// Change of a single top-level field, a missing value means the field was absent
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub field: String,
+    pub from: Option<Bson>,
+    pub to: Option<Bson>,
+}
+
+/// Compares the top-level fields of two versions of a document
+pub fn diff(before: &Document, after: &Document) -> Vec<Change> {
+    let redact = |field: &str, v: Option<&Bson>| {
+        if REDACTED.contains(&field) {
+            v.map(|_| Bson::String(REDACTED_VALUE.to_string()))
+        } else {
+            v.cloned()
+        }
+    };
+
+    let fields = before
+        .keys()
+        .chain(after.keys().filter(|k| !before.contains_key(k)))
+        .filter(|k| !IGNORED.contains(&k.as_str()));
+
+    fields
+        .filter_map(|field| {
+            let (from, to) = (before.get(field), after.get(field));
+            if from == to {
+                return None;
+            }
+
+            Some(Change {
+                field: field.clone(),
+                from: redact(field, from),
+                to: redact(field, to),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub resource: Resource,
+    pub document: ObjectId,
+    /// Subject of the session that caused the change, not set for internal changes
+    pub actor: Option<String>,
+    pub changes: Vec<Change>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub date: DateTime<Utc>,
+}
+
+impl RevisionDocument {
+    fn fields(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().map(|c| c.field.as_str())
+    }
+
+    /// Returns the update operators that restore the values before the revision
+    fn revert_update(&self, now: DateTime<Utc>) -> Result<Document> {
+        if now - self.date > Duration::days(REVERT_WINDOW_DAYS) {
+            return Err(RevisionError::Expired.into());
+        }
+        let fixed = self
+            .fields()
+            .filter(|f| !REVERTIBLE.contains(f))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if !fixed.is_empty() {
+            return Err(RevisionError::NotRevertible(fixed).into());
+        }
+
+        let (mut set, mut unset) = (Document::new(), Document::new());
+        for change in &self.changes {
+            match &change.from {
+                Some(v) => set.insert(&change.field, v.clone()),
+                None => unset.insert(&change.field, ""),
+            };
+        }
+
+        let mut update = Document::new();
+        if !set.is_empty() {
+            update.insert("$set", set);
+        }
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+
+        Ok(update)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeResponse {
+    pub field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionResponse {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    pub changes: Vec<ChangeResponse>,
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+}
+
+impl From<RevisionDocument> for RevisionResponse {
+    fn from(doc: RevisionDocument) -> Self {
+        let changes = doc
+            .changes
+            .into_iter()
+            .map(|c| ChangeResponse {
+                field: c.field,
+                from: c.from.map(Bson::into_relaxed_extjson),
+                to: c.to.map(Bson::into_relaxed_extjson),
+            })
+            .collect();
+
+        Self {
+            id: doc.id.to_hex(),
+            actor: doc.actor,
+            changes,
+            date: doc.date,
+        }
+    }
+}
+
+const COLLECTION: &str = "revisions";
+
+impl Database {
+    /// Records the changes between two versions of a resource, if there are any
+    pub async fn record_revision<T>(
+        &self,
+        resource: Resource,
+        id: ObjectId,
+        before: &T,
+        after: &T,
+    ) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let changes = diff(&to_document(before).unwrap(), &to_document(after).unwrap());
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let doc = RevisionDocument {
+            id: ObjectId::new(),
+            resource,
+            document: id,
+            actor: current_actor(),
+            changes,
+            date: self.clock().now(),
+        };
+
+        self.collection::<RevisionDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the update operators that revert a revision of a resource.
+    ///
+    /// Fails if one of the fields was changed again by a later revision.
+    pub async fn revert_update(
+        &self,
+        resource: Resource,
+        id: ObjectId,
+        revision: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Document> {
+        let revision = ObjectId::parse_str(revision).map_err(|_| RevisionError::InvalidId)?;
+        let coll = self.collection::<RevisionDocument>(COLLECTION);
+
+        let filter = doc! {
+            "_id": revision,
+            "resource": mongodb::bson::to_bson(&resource).unwrap(),
+            "document": id,
+        };
+        let revision = match coll.find_one(filter, None).await? {
+            Some(v) => v,
+            None => return Err(RevisionError::NotFound.into()),
+        };
+
+        let update = revision.revert_update(now)?;
+
+        // Revisions of the same instant count as later, their order is unknown
+        let fields = revision.fields().collect::<Vec<_>>();
+        let filter = doc! {
+            "_id": { "$ne": revision.id },
+            "resource": mongodb::bson::to_bson(&resource).unwrap(),
+            "document": id,
+            "date": { "$gte": mongodb::bson::DateTime::from_chrono(revision.date) },
+            "changes.field": { "$in": fields.clone() },
+        };
+        let later: Vec<RevisionDocument> = coll.find(filter, None).await?.try_collect().await?;
+
+        if !later.is_empty() {
+            let mut conflicts = later
+                .iter()
+                .flat_map(|r| r.fields())
+                .filter(|f| fields.contains(f))
+                .map(String::from)
+                .collect::<Vec<_>>();
+            conflicts.sort();
+            conflicts.dedup();
+
+            return Err(RevisionError::Conflict(conflicts).into());
+        }
+
+        Ok(update)
+    }
+
+    /// Returns the revisions of a resource, newest first
+    pub async fn get_revisions(
+        &self,
+        resource: Resource,
+        id: ObjectId,
+        opts: ListOptions,
+    ) -> Result<(Vec<RevisionDocument>, u64)> {
+        let filter =
+            doc! { "resource": mongodb::bson::to_bson(&resource).unwrap(), "document": id };
+        let coll = self.collection::<RevisionDocument>(COLLECTION);
+
+        let total = coll.count_documents(filter.clone(), None).await?;
+
+        if total == 0 {
+            return Ok((Vec::new(), 0));
+        }
+
+        let opts = FindOptions::builder()
+            .batch_size(opts.limit as u32)
+            .skip(opts.offset)
+            .limit(opts.limit)
+            .sort(doc! { "date": -1 })
+            .build();
+
+        let cursor = coll.find(filter, opts).await?;
+
+        let revisions = cursor.try_collect().await?;
+
+        Ok((revisions, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_top_level_fields() {
+        let before =
+            doc! { "_id": 1, "name": "a", "scope": ["x"], "unlocked": true, "lastModified": 1 };
+        let after =
+            doc! { "_id": 1, "name": "a", "scope": ["x", "y"], "user": 2, "lastModified": 2 };
+
+        assert_eq!(
+            diff(&before, &after),
+            [
+                Change {
+                    field: "scope".into(),
+                    from: Some(Bson::Array(vec!["x".into()])),
+                    to: Some(Bson::Array(vec!["x".into(), "y".into()])),
+                },
+                Change {
+                    field: "unlocked".into(),
+                    from: Some(Bson::Boolean(true)),
+                    to: None,
+                },
+                Change {
+                    field: "user".into(),
+                    from: None,
+                    to: Some(Bson::Int32(2)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn redacts_secrets() {
+        let before = doc! { "secret": "old" };
+        let after = doc! { "secret": "new" };
+
+        let changes = diff(&before, &after);
+
+        assert_eq!(changes[0].from, Some(Bson::String(REDACTED_VALUE.into())));
+        assert_eq!(changes[0].to, Some(Bson::String(REDACTED_VALUE.into())));
+    }
+
+    fn revision(changes: Vec<Change>, date: DateTime<Utc>) -> RevisionDocument {
+        RevisionDocument {
+            id: ObjectId::new(),
+            resource: Resource::Client,
+            document: ObjectId::new(),
+            actor: None,
+            changes,
+            date,
+        }
+    }
+
+    #[test]
+    fn reverts_changes() {
+        let before = doc! { "scope": ["x"], "labels": { "team": "a" } };
+        let after = doc! { "scope": ["x", "y"], "description": "d" };
+        let now = Utc::now();
+
+        let update = revision(diff(&before, &after), now)
+            .revert_update(now)
+            .unwrap();
+
+        assert_eq!(
+            update,
+            doc! {
+                "$set": { "scope": ["x"], "labels": { "team": "a" } },
+                "$unset": { "description": "" },
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_redacted_reverts() {
+        let changes = diff(&doc! { "secret": "old" }, &doc! { "secret": "new" });
+        let now = Utc::now();
+
+        assert!(matches!(
+            revision(changes, now).revert_update(now),
+            Err(crate::error::Error::Revision(RevisionError::NotRevertible(f))) if f == ["secret"]
+        ));
+    }
+
+    #[test]
+    fn rejects_revocation_reverts() {
+        let before = doc! { "name": "a", "tokenEpoch": 1, "canLogin": false };
+        let after = doc! { "name": "b", "tokenEpoch": 2, "canLogin": true };
+        let now = Utc::now();
+
+        assert!(matches!(
+            revision(diff(&before, &after), now).revert_update(now),
+            Err(crate::error::Error::Revision(RevisionError::NotRevertible(f)))
+                if f == ["tokenEpoch", "canLogin"]
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_reverts() {
+        let changes = diff(&doc! { "name": "a" }, &doc! { "name": "b" });
+        let now = Utc::now();
+        let date = now - Duration::days(REVERT_WINDOW_DAYS + 1);
+
+        assert!(matches!(
+            revision(changes, date).revert_update(now),
+            Err(crate::error::Error::Revision(RevisionError::Expired))
+        ));
+    }
+
+    #[tokio::test]
+    async fn actor_only_in_scope() {
+        record_actor("outside");
+        assert_eq!(current_actor(), None);
+
+        let actor = ACTOR
+            .scope(Default::default(), async {
+                record_actor("user");
+                current_actor()
+            })
+            .await;
+
+        assert_eq!(actor.as_deref(), Some("user"));
+    }
+}
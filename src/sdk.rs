@@ -0,0 +1,199 @@
+//! Typed client of the public API for Rust services.
+//!
+//! The request and response types are the ones of the server, so both sides can't drift
+//! apart. Build with `default-features = false, features = ["sdk"]`.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), identity_server::sdk::SdkError> {
+//! use identity_server::sdk::{Client, LoginRequest};
+//!
+//! let client = Client::new("https://id.example.com".parse().unwrap());
+//! let session = client
+//!     .login(&LoginRequest {
+//!         email: "user@example.com".into(),
+//!         password: "secret".into(),
+//!     })
+//!     .await?;
+//!
+//! let user = client.with_token(session.token).user(&session.user).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::http::HttpClient;
+
+pub use crate::models::{
+    client::{
+        ClientResponse, CreateRequest as CreateClientRequest, UpdateRequest as UpdateClientRequest,
+    },
+    session::{CreateRequest as LoginRequest, ExchangeRequest as RefreshRequest, SessionResponse},
+    List, Status, UserResponse,
+};
+
+use reqwest::{Method, RequestBuilder, Url};
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SdkError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error {}: {}", .0.code, .0.message)]
+    Api(Status),
+    #[error("request requires a session token")]
+    Unauthenticated,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: HttpClient,
+    base: Url,
+    token: Option<String>,
+}
+
+impl Client {
+    /// Client of the instance at the base URL, e.g. `https://id.example.com`
+    pub fn new(mut base: Url) -> Self {
+        // Joined paths must not replace the last segment
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Self {
+            http: HttpClient::default(),
+            base,
+            token: None,
+        }
+    }
+
+    /// Authenticates the requests with the session token
+    pub fn with_token<S>(mut self, token: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> Url {
+        self.base.join("v1/").unwrap().join(path).unwrap()
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, self.url(path))
+    }
+
+    fn authorized(&self, method: Method, path: &str) -> Result<RequestBuilder, SdkError> {
+        let token = self.token.as_ref().ok_or(SdkError::Unauthenticated)?;
+
+        Ok(self.request(method, path).bearer_auth(token))
+    }
+
+    async fn send<T>(req: RequestBuilder) -> Result<T, SdkError>
+    where
+        T: DeserializeOwned,
+    {
+        let res = req.send().await?;
+
+        if res.status().is_success() {
+            Ok(res.json().await?)
+        } else {
+            Err(SdkError::Api(res.json().await?))
+        }
+    }
+
+    async fn send_json<B, T>(req: RequestBuilder, body: &B) -> Result<T, SdkError>
+    where
+        B: Serialize,
+        T: DeserializeOwned,
+    {
+        Self::send(req.json(body)).await
+    }
+
+    /// Creates a session with email and password
+    pub async fn login(&self, body: &LoginRequest) -> Result<SessionResponse, SdkError> {
+        Self::send_json(self.request(Method::POST, "session"), body).await
+    }
+
+    /// Validates the session token and issues a new one with a later expiration
+    pub async fn renew(&self) -> Result<SessionResponse, SdkError> {
+        Self::send(self.authorized(Method::GET, "session")?).await
+    }
+
+    /// Exchanges a refresh token for a new session
+    pub async fn refresh(&self, refresh_token: &str) -> Result<SessionResponse, SdkError> {
+        let body = RefreshRequest {
+            refresh_token: refresh_token.to_string(),
+        };
+
+        Self::send_json(self.request(Method::POST, "session/refresh"), &body).await
+    }
+
+    /// Revokes the session token
+    pub async fn logout(&self) -> Result<Status, SdkError> {
+        Self::send(self.authorized(Method::DELETE, "session")?).await
+    }
+
+    pub async fn user(&self, id: &str) -> Result<UserResponse, SdkError> {
+        Self::send(self.authorized(Method::GET, &format!("user/{}", id))?).await
+    }
+
+    pub async fn clients(&self, limit: i64, offset: u64) -> Result<List<ClientResponse>, SdkError> {
+        let req = self
+            .authorized(Method::GET, "client")?
+            .query(&[("limit", limit.to_string()), ("offset", offset.to_string())]);
+
+        Self::send(req).await
+    }
+
+    pub async fn client(&self, id: &str) -> Result<ClientResponse, SdkError> {
+        Self::send(self.authorized(Method::GET, &format!("client/{}", id))?).await
+    }
+
+    pub async fn create_client(
+        &self,
+        body: &CreateClientRequest,
+    ) -> Result<ClientResponse, SdkError> {
+        Self::send_json(self.authorized(Method::POST, "client")?, body).await
+    }
+
+    pub async fn update_client(
+        &self,
+        id: &str,
+        body: &UpdateClientRequest,
+    ) -> Result<ClientResponse, SdkError> {
+        Self::send_json(
+            self.authorized(Method::PATCH, &format!("client/{}", id))?,
+            body,
+        )
+        .await
+    }
+
+    pub async fn delete_client(&self, id: &str) -> Result<Status, SdkError> {
+        Self::send(self.authorized(Method::DELETE, &format!("client/{}", id))?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_api_paths() {
+        let client = Client::new("https://id.example.com/identity".parse().unwrap());
+
+        assert_eq!(
+            client.url("session/refresh").as_str(),
+            "https://id.example.com/identity/v1/session/refresh"
+        );
+    }
+
+    #[test]
+    fn requires_token() {
+        let client = Client::new("https://id.example.com".parse().unwrap());
+
+        assert!(matches!(
+            client.authorized(Method::GET, "client"),
+            Err(SdkError::Unauthenticated)
+        ));
+    }
+}
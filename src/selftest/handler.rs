@@ -0,0 +1,281 @@
+use crate::{
+    authentication::{
+        token::{TokenClaims, TokenConfig},
+        AuthenticationError,
+    },
+    config::GlobalConfig,
+    database::Database,
+    extract::{ClientInfo, TokenData},
+    http::{HttpClient, SendTimed},
+    model::Response,
+    oauth::OPENID_SCOPE,
+    session::{ActiveSessionDocument, SessionClaims},
+    user::UserError,
+};
+
+use super::{register_client, SelfTestAddr, SelfTestReport, REDIRECT_URI};
+
+use std::time::Instant;
+
+use axum::extract::{Extension, TypedHeader};
+use chrono::Duration;
+use headers::{authorization::Bearer, Authorization};
+use hyper::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// Lifetime of the test session, it is revoked once the test is done
+const SESSION_MIN: i64 = 5;
+
+fn random_string() -> String {
+    base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizeResponse {
+    redirect_uri: Url,
+}
+
+/// Authorizes the client with the test session, returns the code of the redirect
+async fn authorize(
+    http: &HttpClient,
+    base: &str,
+    session: &str,
+    client: ObjectId,
+    scope: Option<&str>,
+    verifier: &str,
+) -> Result<String, String> {
+    let state = random_string();
+    let challenge =
+        base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+
+    let res = http
+        .post(format!("{}/oauth/authorize", base))
+        .bearer_auth(session)
+        .json(&json!({
+            "response_type": "code",
+            "client_id": client.to_hex(),
+            "redirect_uri": REDIRECT_URI,
+            "scope": scope,
+            "state": state,
+            "code_challenge": challenge,
+            "code_challenge_method": "S256",
+        }))
+        .send_timed()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let redirect = res
+        .json::<AuthorizeResponse>()
+        .await
+        .map_err(|e| e.to_string())?
+        .redirect_uri;
+
+    let param = |name| {
+        redirect
+            .query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    if param("state").as_deref() != Some(state.as_str()) {
+        return Err("state of the redirect does not match".to_string());
+    }
+
+    param("code").ok_or_else(|| format!("redirect to {} has no code", redirect))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Exchanges the code for an access token of the client
+async fn exchange(
+    http: &HttpClient,
+    base: &str,
+    client: ObjectId,
+    code: &str,
+    verifier: &str,
+) -> Result<String, String> {
+    let client = client.to_hex();
+    let res = http
+        .post(format!("{}/oauth/token", base))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("client_id", &client),
+            ("code", code),
+            ("redirect_uri", REDIRECT_URI),
+            ("code_verifier", verifier),
+        ])
+        .send_timed()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+
+    res.json::<TokenResponse>()
+        .await
+        .map(|t| t.access_token)
+        .map_err(|e| e.to_string())
+}
+
+/// Checks that the userinfo of the access token is the one of the user
+async fn userinfo(
+    http: &HttpClient,
+    base: &str,
+    token: &str,
+    user: ObjectId,
+) -> Result<(), String> {
+    let res = http
+        .get(format!("{}/oauth/userinfo", base))
+        .bearer_auth(token)
+        .send_timed()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let info = res.json::<Value>().await.map_err(|e| e.to_string())?;
+
+    if info["sub"] != user.to_hex() {
+        return Err("subject does not match".to_string());
+    }
+
+    Ok(())
+}
+
+/// Verifies the access token against the database
+async fn introspect(
+    http: &HttpClient,
+    base: &str,
+    session: &str,
+    token: &str,
+) -> Result<(), String> {
+    let res = http
+        .post(format!("{}/v1/admin/verify-token", base))
+        .bearer_auth(session)
+        .json(&json!({ "token": token }))
+        .send_timed()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let report = res.json::<Value>().await.map_err(|e| e.to_string())?;
+
+    if report["valid"] != true {
+        return Err(format!("token diverges: {}", report["divergences"]));
+    }
+
+    Ok(())
+}
+
+/// Revokes the test session with the session of the caller and checks that its token is rejected
+async fn revoke(
+    http: &HttpClient,
+    base: &str,
+    caller: &str,
+    session: &str,
+    user: ObjectId,
+    jti: &str,
+) -> Result<(), String> {
+    http.delete(format!("{}/v1/user/{}/sessions/{}", base, user, jti))
+        .bearer_auth(caller)
+        .send_timed()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+
+    let res = http
+        .get(format!("{}/v1/session", base))
+        .bearer_auth(session)
+        .send_timed()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status() != StatusCode::UNAUTHORIZED {
+        return Err(format!(
+            "revoked session is answered with {} instead of {}",
+            res.status(),
+            StatusCode::UNAUTHORIZED
+        ));
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn oidc(
+    info: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+    Extension(addr): Extension<SelfTestAddr>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SelfTestReport>> {
+    if !claims.is_admin() {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+    let user = db.get_user(doc! { "_id": user_id }).await?;
+    let client = register_client(&db, user_id).await?;
+
+    // Revoking the session of the request would end the session of the caller
+    let now = config.clock.now();
+    let mut test = SessionClaims::issue_for_user(&config, &user, now).await?;
+    test.set_expiration(test.exp.min(now + Duration::minutes(SESSION_MIN)));
+    let session = test.encode(&config)?;
+    db.insert_session(&ActiveSessionDocument::new(
+        user_id, &test, "selftest", &info,
+    ))
+    .await?;
+
+    let http = HttpClient::allow_http();
+    let base = format!("http://{}{}", addr.0, global.base_path);
+    let openid = global.issuer_url.is_some();
+    let verifier = random_string();
+    let mut report = SelfTestReport::default();
+
+    let start = Instant::now();
+    let scope = openid.then(|| OPENID_SCOPE);
+    let result = authorize(&http, &base, &session, client.id, scope, &verifier).await;
+    let code = report.record("authorize", start, result);
+
+    let token = match code {
+        Some(code) => {
+            let start = Instant::now();
+            let result = exchange(&http, &base, client.id, &code, &verifier).await;
+            report.record("token", start, result)
+        }
+        None => {
+            report.skip("token", "no code was issued");
+            None
+        }
+    };
+
+    match &token {
+        Some(token) if openid => {
+            let start = Instant::now();
+            let result = userinfo(&http, &base, token, user_id).await;
+            report.record("userinfo", start, result);
+        }
+        Some(_) => report.skip("userinfo", "there is no issuer URL for OpenID Connect"),
+        None => report.skip("userinfo", "no token was issued"),
+    }
+
+    match &token {
+        Some(token) => {
+            let start = Instant::now();
+            let result = introspect(&http, &base, &session, token).await;
+            report.record("introspect", start, result);
+        }
+        None => report.skip("introspect", "no token was issued"),
+    }
+
+    let start = Instant::now();
+    let jti = test.jti.as_deref().unwrap_or_default();
+    let result = revoke(&http, &base, bearer.token(), &session, user_id, jti).await;
+    report.record("revoke", start, result);
+
+    Ok(Response::new(report.finish()))
+}
@@ -0,0 +1,185 @@
+//! Self-test of the login cycle for post-deploy verification.
+//!
+//! The steps run against the own HTTP server, so routing, middleware and key configuration are
+//! exercised as they are deployed. A separate session of the caller authorizes an internal
+//! client with PKCE, the code is exchanged for a token, which is used for the userinfo and
+//! verified against the database. The test session is revoked at the end, other sessions of
+//! the caller are kept. Without an issuer URL there is no userinfo and the step is skipped.
+
+mod handler;
+mod routes;
+
+use crate::{client::ClientDocument, database::Database, service::ServiceDocument};
+
+use std::{net::SocketAddr, time::Instant};
+
+use chrono::{TimeZone, Utc};
+use mongodb::bson::oid::ObjectId;
+use reqwest::Url;
+use serde::Serialize;
+
+pub use routes::routes;
+
+/// Address the self-test connects to
+#[derive(Debug, Clone, Copy)]
+pub struct SelfTestAddr(pub SocketAddr);
+
+impl SelfTestAddr {
+    /// Connects via loopback if the server listens on all interfaces
+    pub fn new(mut addr: SocketAddr) -> Self {
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+
+        Self(addr)
+    }
+}
+
+/// Name of the internal service and clients of the self-test
+const NAME: &str = "selftest";
+
+/// Redirect URI of the internal clients, never requested, the code is read from the response
+const REDIRECT_URI: &str = "http://localhost/selftest/callback";
+
+/// Internal client of the user, registered on the first self-test
+async fn register_client(db: &Database, user: ObjectId) -> crate::Result<ClientDocument> {
+    let svc = db
+        .insert_service_if_new(&ServiceDocument {
+            id: ObjectId::new(),
+            name: NAME.to_string(),
+            audience: Vec::new(),
+            scope: Vec::new(),
+            scope_default: Vec::new(),
+            scope_privileged: Vec::new(),
+            secret: None,
+            labels: Default::default(),
+            health_url: None,
+            profile_fields: Vec::new(),
+            min_age: None,
+            last_modified: Utc::now(),
+        })
+        .await?;
+
+    db.insert_client_if_new(&ClientDocument {
+        id: ObjectId::new(),
+        user,
+        service: svc.id,
+        name: NAME.to_string(),
+        scope: Vec::new(),
+        unlocked: true,
+        labels: Default::default(),
+        description: Some("Internal client of the self-test".to_string()),
+        contact: None,
+        documentation_url: None,
+        redirect_uris: vec![Url::parse(REDIRECT_URI).unwrap()],
+        schedule: None,
+        confirmed_at: Utc::now(),
+        unconfirmed: false,
+        legacy_token_disabled: false,
+        canary: false,
+        secret: None,
+        last_issued: Utc.timestamp(0, 0),
+        last_modified: Utc::now(),
+    })
+    .await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepResult {
+    pub name: &'static str,
+    pub status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    pub steps: Vec<StepResult>,
+}
+
+impl SelfTestReport {
+    fn skip(&mut self, name: &'static str, reason: &str) {
+        self.steps.push(StepResult {
+            name,
+            status: StepStatus::Skipped,
+            detail: Some(reason.to_string()),
+            duration_ms: 0,
+        });
+    }
+
+    /// Records the result of a step, returns the value if it passed
+    fn record<T>(
+        &mut self,
+        name: &'static str,
+        start: Instant,
+        result: Result<T, String>,
+    ) -> Option<T> {
+        let (status, detail, value) = match result {
+            Ok(v) => (StepStatus::Passed, None, Some(v)),
+            Err(e) => (StepStatus::Failed, Some(e), None),
+        };
+
+        self.steps.push(StepResult {
+            name,
+            status,
+            detail,
+            duration_ms: start.elapsed().as_millis(),
+        });
+
+        value
+    }
+
+    fn finish(mut self) -> Self {
+        self.passed = self.steps.iter().all(|s| s.status != StepStatus::Failed);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_steps_pass() {
+        let mut report = SelfTestReport::default();
+        report.skip("authorize", "not supported");
+        report.record("token", Instant::now(), Ok(()));
+
+        assert!(report.finish().passed);
+    }
+
+    #[test]
+    fn failed_step_fails() {
+        let mut report = SelfTestReport::default();
+        assert_eq!(
+            report.record::<()>("token", Instant::now(), Err("401".into())),
+            None
+        );
+        report.record("userinfo", Instant::now(), Ok(()));
+
+        let report = report.finish();
+        assert!(!report.passed);
+        assert_eq!(report.steps[0].detail.as_deref(), Some("401"));
+    }
+
+    #[test]
+    fn connects_via_loopback() {
+        let addr = SelfTestAddr::new(([0, 0, 0, 0], 8080).into());
+
+        assert_eq!(addr.0, ([127, 0, 0, 1], 8080).into());
+    }
+}
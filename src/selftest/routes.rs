@@ -0,0 +1,8 @@
+use super::handler;
+
+use axum::routing::post;
+
+/// Self-test routes
+pub fn routes() -> axum::Router {
+    axum::Router::new().route("/oidc", post(handler::oidc))
+}
@@ -0,0 +1,937 @@
+use crate::{
+    action, audit,
+    authentication::{
+        credential::{CredentialHasher, Pepper},
+        jwks::JwksCache,
+        password::{Hibp, PasswordPolicy},
+        rotation::KeyRotation,
+        signature,
+        token::TokenConfig,
+    },
+    bulk, client,
+    config::{
+        self, AppConfig, GlobalConfig, Live, RateLimitConfig, Reloadable, Reloader, SecretFile,
+    },
+    cors,
+    database::{self, Database, Realms},
+    epoch::{self, EpochStore},
+    error::{self, handle_error},
+    health::{self, Probes},
+    http::HttpClient,
+    inspect, integrity,
+    keys::{self, KeyAlerts},
+    mail,
+    metrics::Metrics,
+    migration, oauth, openapi, outbox, portal,
+    ratelimit::{self, RateLimiter},
+    replica, revision, service,
+    session::{self, Fallback},
+    smoke,
+    sso::{self, ProviderCircuits, Providers},
+    state::{self, AppState},
+    telemetry::{MakeRequestSpan, RecordResponse, Telemetry},
+    timing::{self, DatabaseTimings, SlowRequestConfig},
+    token, user,
+    utils::{self, crypto::Aead256},
+    well_known, Result,
+};
+
+#[cfg(feature = "cache")]
+use crate::cache::Cache;
+#[cfg(feature = "chaos")]
+use crate::chaos;
+#[cfg(feature = "debug-endpoints")]
+use crate::debug;
+#[cfg(feature = "federation")]
+use crate::federation;
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, MetricsToken};
+#[cfg(feature = "selftest")]
+use crate::selftest;
+
+use std::{env, iter::once, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{error_handling::HandleErrorLayer, Router, Server as HttpServer};
+use hyper::header::AUTHORIZATION;
+use mongodb::options::{ClientOptions, Tls, TlsOptions};
+use tower::ServiceBuilder;
+use tower_http::{
+    sensitive_headers::SetSensitiveHeadersLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+
+type Vars = Vec<(String, String)>;
+
+/// Sibling regions may rotate their keys, tokens with a new key are rejected until the next refresh
+const SIBLING_KEYS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs the server or the command given as first argument
+pub async fn run() -> Result<()> {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "info");
+    }
+    let telemetry = Telemetry::init();
+
+    let mut args: Vec<String> = env::args().collect();
+    let config_file = config::take_file_path(&mut args)
+        .map_err(|e| error::Error::Config(e.to_string()))?
+        .or_else(|| env::var_os(config::FILE_VAR).map(Into::into));
+
+    // Needs no configuration, so that the schema can be exported before a rollout
+    if args.get(1).map(String::as_str) == Some("config-schema") {
+        println!("{:#}", config::schema());
+        return Ok(());
+    }
+    // Describes the API of the enabled features, e.g. to generate client SDKs
+    if args.get(1).map(String::as_str) == Some("openapi") {
+        println!("{:#}", openapi::spec());
+        return Ok(());
+    }
+    // Runs against another instance, the own configuration is not needed
+    if args.get(1).map(String::as_str) == Some("smoke") {
+        return smoke::run(args.into_iter().skip(2)).await;
+    }
+
+    // Variables of a .env file don't override the environment, neither override the file
+    dotenv::dotenv().ok();
+
+    if let Some(cmd) = args.get(1) {
+        let (app_config, _) = load_config(None, config_file.as_ref())?;
+        let (db, realms) = connect(&app_config, &Metrics::default()).await?;
+
+        return match cmd.as_str() {
+            "reencrypt" => {
+                let aead = aead(&app_config)?;
+                for db in std::iter::once(&db).chain(realms.databases()) {
+                    migration::reencrypt(db, &aead).await?;
+                }
+                Ok(())
+            }
+            "fsck" => {
+                let fix = args.get(2).map(String::as_str) == Some("--fix");
+                for db in std::iter::once(&db).chain(realms.databases()) {
+                    integrity::fsck(db, fix).await?;
+                }
+                Ok(())
+            }
+            _ => Err(error::Error::Config(format!("unknown command \"{}\"", cmd))),
+        };
+    }
+
+    let mut builder = Server::builder();
+    builder.config_file = config_file;
+    builder.telemetry = Some(telemetry.clone());
+    builder.build().await?.serve().await?;
+
+    telemetry.flush().await;
+
+    Ok(())
+}
+
+/// Loads the configuration from the variables, or those of the environment, with the ones of the
+/// config file and the secret files
+fn load_config(
+    vars: Option<Vars>,
+    config_file: Option<&PathBuf>,
+) -> Result<(AppConfig, Vec<SecretFile>)> {
+    let vars = vars.unwrap_or_else(|| env::vars().collect());
+    let vars = match config_file {
+        Some(path) => config::merge_file(path.clone(), vars)
+            .map_err(|e| error::Error::Config(e.to_string()))?,
+        None => vars,
+    };
+    let (vars, secret_files) =
+        config::resolve_files(vars).map_err(|e| error::Error::Config(e.to_string()))?;
+
+    Ok((config::load(vars)?, secret_files))
+}
+
+/// Connects to the main database and the ones of the realms
+async fn connect(app_config: &AppConfig, metrics: &Metrics) -> Result<(Database, Realms)> {
+    let mut mongo_opts = ClientOptions::parse(&app_config.mongo_uri).await?;
+
+    if app_config.mongo_tls {
+        let opts = TlsOptions::builder()
+            .cert_key_file_path(app_config.mongo_cert_key.clone())
+            .ca_file_path(app_config.mongo_ca.clone());
+
+        mongo_opts.tls = Some(Tls::Enabled(opts.build()));
+    }
+    mongo_opts.command_event_handler = Some(Arc::new(DatabaseTimings(metrics.clone())));
+
+    let realms =
+        Realms::connect(app_config.realms.clone(), &mongo_opts, &app_config.quotas).await?;
+    let db =
+        Database::new(mongo_opts, &app_config.mongo_db)?.with_quotas(app_config.quotas.clone());
+
+    Ok((db, realms))
+}
+
+fn aead(app_config: &AppConfig) -> Result<Aead256> {
+    let mut aead = Aead256::new(app_config.crypto_key_version, &app_config.crypto_key)?;
+    for (version, key) in &app_config.crypto_previous_keys {
+        aead.add_previous_key(*version, key)?;
+    }
+
+    Ok(aead)
+}
+
+/// Connects the mail transport of the configuration, SMTP if a host is set and Mailgun otherwise
+#[cfg_attr(
+    not(all(feature = "mail-mailgun", feature = "mail-smtp")),
+    allow(unused_variables)
+)]
+fn mail_transport(
+    app_config: &AppConfig,
+    client: &HttpClient,
+    probes: &mut Probes,
+) -> Result<mail::Client> {
+    #[cfg(feature = "mail-smtp")]
+    if let Some(host) = &app_config.smtp_host {
+        let dir = app_config.mail_template_dir.as_ref().ok_or_else(|| {
+            error::Error::Config("mail template directory is required for SMTP".into())
+        })?;
+        let templates = mail::Templates::load(dir).map_err(|e| {
+            error::Error::Config(format!(
+                "mail templates of {} can not be loaded: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        let mut smtp = mail::Smtp::new(
+            host,
+            app_config.smtp_port,
+            app_config.smtp_tls,
+            &app_config.mail_from,
+            templates,
+        )?;
+        if let (Some(username), Some(password)) =
+            (&app_config.smtp_username, &app_config.smtp_password)
+        {
+            smtp = smtp.with_credentials(username, password);
+        }
+        probes.set_smtp(smtp.clone());
+
+        return Ok(mail::Client::new(smtp));
+    }
+
+    #[cfg(feature = "mail-mailgun")]
+    return match (
+        &app_config.mg_key,
+        &app_config.mg_region,
+        &app_config.mg_domain,
+    ) {
+        (Some(key), Some(region), Some(domain)) => {
+            let mut mailgun =
+                mail::Mailgun::new(key, *region, domain, &app_config.mail_from, client.clone())?;
+            if let Some(url) = &app_config.mg_base_url {
+                mailgun = mailgun.with_base_url(url.clone())?;
+            }
+
+            Ok(mail::Client::new(mailgun))
+        }
+        _ => Err(error::Error::Config(
+            "Mailgun configuration is incomplete and no SMTP host is set".into(),
+        )),
+    };
+
+    #[cfg(not(feature = "mail-mailgun"))]
+    Err(error::Error::Config(
+        "no mail transport is configured, which is only allowed in sandbox mode".into(),
+    ))
+}
+
+/// Builds a [`Server`] from the configuration, see [`Server::builder`]
+pub struct ServerBuilder {
+    vars: Option<Vars>,
+    config_file: Option<PathBuf>,
+    bootstrap: bool,
+    jobs: bool,
+    telemetry: Option<Telemetry>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            vars: None,
+            config_file: None,
+            bootstrap: true,
+            jobs: true,
+            telemetry: None,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Reads the configuration from these variables instead of the environment
+    pub fn vars<I>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        self.vars = Some(vars.into_iter().collect());
+        self
+    }
+
+    /// Adds the variables of the TOML file that the environment doesn't set
+    pub fn config_file<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Creates the indexes and applies the migrations of the databases, enabled by default.
+    ///
+    /// Instances that share their databases with one that does can skip it.
+    pub fn bootstrap(mut self, enabled: bool) -> Self {
+        self.bootstrap = enabled;
+        self
+    }
+
+    /// Runs the maintenance jobs, enabled by default: client attestations, service health
+    /// checks, integrity checks, key expiry alerts, elevation expiry and quota alerts.
+    ///
+    /// Jobs that the routes depend on, like the refresh of token epochs and the rotation of
+    /// keys, always run.
+    pub fn jobs(mut self, enabled: bool) -> Self {
+        self.jobs = enabled;
+        self
+    }
+
+    /// Connects to the databases and puts the routes together
+    pub async fn build(self) -> Result<Server> {
+        let (app_config, secret_files) = load_config(self.vars, self.config_file.as_ref())?;
+        let client = HttpClient::default();
+        let reloader = Reloader::new(
+            Reloadable::from_config(&app_config, &client).await?,
+            self.config_file.clone(),
+            client.clone(),
+        );
+
+        let slow_requests = app_config
+            .slow_request_threshold_ms
+            .map(|ms| SlowRequestConfig {
+                threshold: Duration::from_millis(ms),
+                sample_rate: app_config.slow_request_sample_rate,
+            });
+        let metrics = Metrics::default();
+
+        let (db, realms) = connect(&app_config, &metrics).await?;
+        let aead = aead(&app_config)?;
+        #[cfg(feature = "cache")]
+        let cache = match &app_config.cache_url {
+            Some(url) => Some(Cache::connect(url, app_config.cache_pool_size).await?),
+            None => None,
+        };
+        let (db, realms) = match app_config.timestamp_flush_ms {
+            Some(ms) => {
+                let interval = Duration::from_millis(ms);
+                (
+                    db.with_timestamps(interval, metrics.clone()),
+                    realms.with_timestamps(interval, &metrics),
+                )
+            }
+            None => (db, realms),
+        };
+        #[cfg(feature = "cache")]
+        let (db, realms) = match &cache {
+            Some(cache) => (db.with_cache(cache.clone()), realms.with_cache(cache)),
+            None => (db, realms),
+        };
+
+        let mut probes = Probes::new(&db, realms.databases());
+        let inbox = app_config.sandbox.then(mail::Inbox::default);
+        let mail = match &inbox {
+            Some(inbox) => mail::Client::new(inbox.clone()),
+            None => mail_transport(&app_config, &client, &mut probes)?,
+        }
+        .with_retries(app_config.mail_attempts, Duration::from_secs(1));
+
+        if let (Some(telemetry), Some(endpoint)) =
+            (&self.telemetry, app_config.otlp_endpoint.clone())
+        {
+            telemetry.start(
+                endpoint,
+                app_config.otlp_sample_ratio,
+                HttpClient::allow_http(),
+            );
+        }
+        let mut token_config =
+            TokenConfig::from_secret(app_config.jwt_secret.as_bytes(), app_config.jwt_audience)
+                .with_metrics(metrics.clone());
+        // Revisions are dated by the database, their revert window checked against the same clock
+        let (db, realms) = (
+            db.with_clock(token_config.clock.clone()),
+            realms.with_clock(&token_config.clock),
+        );
+        match (app_config.jwt_signing_key, app_config.jwt_key_rotation_days) {
+            (Some(_), Some(_)) => {
+                return Err(error::Error::Config(
+                    "a signing key can not be combined with key rotation".into(),
+                ))
+            }
+            (Some(key), None) => {
+                token_config = token_config
+                    .with_signing_key(&key, app_config.jwt_signing_algorithm)
+                    .map_err(|e| error::Error::Config(e.to_string()))?;
+            }
+            (None, Some(_)) => token_config = token_config.with_key_rotation(),
+            (None, None) => {}
+        }
+        token_config = token_config
+            .with_session_limits(app_config.session_limits)
+            .with_claim_rules(app_config.claim_rules);
+        if app_config.sandbox {
+            tracing::warn!("sandbox mode, any email address can sign in");
+            token_config = token_config.with_sandbox();
+        }
+        match app_config.region {
+            Some(region) => {
+                let siblings = app_config
+                    .region_siblings
+                    .into_iter()
+                    .map(|(r, s)| (r, s.unwrap_or_else(|| app_config.jwt_secret.clone())))
+                    .collect();
+                let sets = app_config
+                    .region_sibling_keys
+                    .into_iter()
+                    .map(|(r, uri)| {
+                        let set = JwksCache::new(uri, client.clone());
+                        set.spawn_refresh(SIBLING_KEYS_REFRESH_INTERVAL);
+                        (r, set)
+                    })
+                    .collect();
+                token_config = token_config
+                    .with_region(region, siblings)
+                    .with_sibling_keys(sets);
+            }
+            None if !app_config.region_siblings.is_empty()
+                || !app_config.region_sibling_keys.is_empty() =>
+            {
+                return Err(error::Error::Config(
+                    "sibling regions require a region".into(),
+                ))
+            }
+            None => {}
+        }
+
+        if self.bootstrap {
+            for db in std::iter::once(&db).chain(realms.databases()) {
+                if !app_config.signing_keys.is_empty() {
+                    db.init_signatures().await?;
+                }
+                if !app_config.read_only {
+                    migration::migrate(db).await?;
+                    db.init_revocations().await?;
+                    db.init_authorization_codes().await?;
+                    db.init_device_codes().await?;
+                    db.init_consents().await?;
+                    db.init_sessions().await?;
+                    db.init_sso_states().await?;
+                }
+            }
+        }
+        let epochs =
+            EpochStore::load(db.clone(), realms.clone(), token_config.epoch.clone()).await?;
+        epochs.clone().spawn_refresh();
+
+        if let Some(days) = app_config.jwt_key_rotation_days {
+            let rotation = KeyRotation::new(
+                db.clone(),
+                token_config.clone(),
+                aead.clone(),
+                days,
+                !app_config.read_only,
+            );
+            rotation.refresh().await?;
+            rotation.spawn();
+        }
+
+        let hasher = {
+            let pepper = app_config.pepper.map(|secret| Pepper {
+                version: app_config.pepper_version,
+                secret: secret.into_bytes(),
+            });
+            let previous = app_config
+                .previous_peppers
+                .into_iter()
+                .map(|(version, secret)| Pepper {
+                    version,
+                    secret: secret.into_bytes(),
+                })
+                .collect();
+
+            CredentialHasher::new(pepper, previous)
+                .map_err(|e| error::Error::Config(format!("pepper is invalid: {}", e)))?
+        };
+        let hibp = Hibp::with_client(client.clone());
+        #[cfg(feature = "federation")]
+        let federation =
+            federation::Federation::new(app_config.federation_issuers, client.clone()).await?;
+        #[cfg(feature = "wasm-policies")]
+        if let Some(dir) = app_config.policy_dir {
+            let policies = crate::policy::WasmPolicies::load(dir)?;
+            policies
+                .clone()
+                .spawn_reload(Duration::from_secs(app_config.policy_reload_secs));
+            crate::hooks::register(policies);
+        }
+        let ci_trust = token::CiTrust::new(app_config.ci_policies, client.clone()).await?;
+        // Replicas leave the job to the primary, it writes
+        match app_config.client_attestation_months {
+            Some(months) if self.jobs && !app_config.read_only => {
+                for db in std::iter::once(&db).chain(realms.databases()) {
+                    client::spawn_attestation(
+                        db.clone(),
+                        mail.clone(),
+                        months,
+                        token_config.clock.clone(),
+                    )
+                }
+            }
+            _ => {}
+        }
+        if self.jobs && !app_config.read_only {
+            service::spawn_health_checks(db.clone(), token_config.clock.clone());
+        }
+        #[cfg(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord"))]
+        if let Some(hours) = app_config
+            .connection_refresh_hours
+            .filter(|_| self.jobs && !app_config.read_only)
+        {
+            for db in std::iter::once(&db).chain(realms.databases()) {
+                sso::spawn_connection_refresh(
+                    db.clone(),
+                    aead.clone(),
+                    reloader.providers.clone(),
+                    Duration::from_secs(hours * 60 * 60),
+                );
+            }
+        }
+        if let Some(hours) = app_config.integrity_check_hours.filter(|_| self.jobs) {
+            // Replicas only count, the primary removes
+            let fix = app_config.integrity_fix && !app_config.read_only;
+            for db in std::iter::once(&db).chain(realms.databases()) {
+                integrity::spawn_integrity_checks(
+                    db.clone(),
+                    metrics.clone(),
+                    Duration::from_secs(hours * 60 * 60),
+                    fix,
+                );
+            }
+        }
+        let portal_settings = portal::Settings {
+            swagger_ui: app_config.openapi_docs,
+            key_alerts: app_config.key_alert_webhook.is_some(),
+        };
+        let key_alerts = KeyAlerts::new(
+            app_config.key_expiry,
+            app_config.editor_mail_address.clone(),
+            app_config.key_alert_webhook,
+            mail.clone(),
+            client.clone(),
+        );
+        if self.jobs && !app_config.read_only && !key_alerts.is_empty() {
+            key_alerts
+                .clone()
+                .spawn(db.clone(), token_config.clock.clone());
+        }
+        let security_contacts = if app_config.security_contacts.is_empty() {
+            app_config.editor_mail_address.clone()
+        } else {
+            app_config.security_contacts
+        };
+        let security_notifier = user::SecurityNotifier::new(security_contacts, mail.clone());
+        if self.jobs && !app_config.read_only {
+            for db in std::iter::once(&db).chain(realms.databases()) {
+                user::spawn_elevation_expiry(
+                    db.clone(),
+                    security_notifier.clone(),
+                    token_config.clock.clone(),
+                );
+                database::spawn_quota_alerts(db.clone(), security_notifier.clone());
+            }
+        }
+        // Endpoints of the discovery document are relative to the issuer
+        if let Some(issuer) = &app_config.issuer_url {
+            if !issuer
+                .path()
+                .trim_end_matches('/')
+                .ends_with(&app_config.base_path)
+            {
+                return Err(error::Error::Config(format!(
+                    "issuer URL has to end with the base path {}",
+                    app_config.base_path
+                )));
+            }
+        }
+        let base_path = app_config.base_path.clone();
+        let global_config = GlobalConfig {
+            allowed_domains: reloader.allowed_domains.clone(),
+            hibp_check_enabled: app_config.hibp_check,
+            password_policy: PasswordPolicy {
+                min_length: app_config.password_min_length,
+                require_classes: app_config.password_require_classes,
+                min_score: app_config.password_min_score,
+                max_failures: app_config.login_max_failures,
+                lockout: chrono::Duration::minutes(app_config.login_lockout_minutes.into()),
+            },
+            editor_mail_addrs: app_config.editor_mail_address,
+            sensitive_roles: app_config.sensitive_roles,
+            canary_auto_lock: app_config.canary_auto_lock,
+            issuer_url: app_config.issuer_url,
+            base_path: app_config.base_path,
+            device_verification_url: app_config.device_verification_url,
+            profile_url: app_config.profile_url,
+            rate_limit: app_config
+                .rate_limit_requests
+                .filter(|_| !app_config.sandbox)
+                .map(|requests| RateLimitConfig {
+                    requests,
+                    window: Duration::from_secs(app_config.rate_limit_window_secs),
+                    burst: app_config.rate_limit_burst.unwrap_or(requests),
+                }),
+            cors: reloader.cors.clone(),
+        };
+        let rate_limit = global_config.rate_limit;
+        let cors = global_config.cors.clone();
+        let providers = reloader.providers.clone();
+
+        let circuits = ProviderCircuits::default();
+        let fallback = Fallback::new(
+            app_config.sso_fallback,
+            app_config.break_glass_accounts,
+            circuits.clone(),
+        );
+
+        let app_state = AppState {
+            global: global_config,
+            epochs,
+            db,
+            token: token_config,
+            aead,
+            hasher,
+            hibp,
+            mail,
+            security_notifier,
+            fallback,
+            metrics: metrics.clone(),
+            #[cfg(feature = "metrics")]
+            metrics_token: MetricsToken(app_config.metrics_token),
+            ci_trust,
+            key_alerts,
+            #[cfg(feature = "federation")]
+            federation,
+            #[cfg(feature = "selftest")]
+            selftest_addr: selftest::SelfTestAddr::new(SocketAddr::from((
+                app_config.server_addr,
+                app_config.server_port,
+            ))),
+        };
+
+        let middleware = ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_error))
+            .load_shed()
+            .concurrency_limit(1024)
+            .timeout(Duration::from_secs(60))
+            .layer(SetSensitiveHeadersLayer::new(once(AUTHORIZATION)))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(MakeRequestSpan)
+                    .on_response(RecordResponse(
+                        DefaultOnResponse::new()
+                            .include_headers(true)
+                            .latency_unit(LatencyUnit::Micros),
+                    )),
+            )
+            .layer(axum::middleware::from_fn(move |req, next| {
+                state::provide(req, next, app_state.clone())
+            }));
+
+        let routes = api_routes(
+            providers,
+            app_config.sandbox,
+            app_config.openapi_docs,
+            inbox,
+        )
+        .merge(health::routes(probes));
+
+        #[cfg(feature = "metrics")]
+        let routes = routes.route("/metrics", axum::routing::get(metrics::handler));
+
+        let routes = if app_config.docs_portal {
+            routes.nest("/docs", portal::routes(portal_settings))
+        } else {
+            routes
+        };
+
+        #[cfg(feature = "debug-endpoints")]
+        let routes = routes.nest("/debug", debug::routes());
+
+        #[cfg(feature = "chaos")]
+        let routes = routes.nest("/chaos", chaos::routes());
+
+        let routes = routes.layer(axum::middleware::from_fn(revision::track_actor));
+
+        let routes = if app_config.signing_keys.is_empty() {
+            routes
+        } else {
+            let keys = app_config.signing_keys;
+            routes.layer(axum::middleware::from_fn(move |req, next| {
+                signature::authenticate_signed(req, next, keys.clone())
+            }))
+        };
+
+        let routes = if app_config.read_only {
+            let primary = app_config.primary_url.ok_or_else(|| {
+                error::Error::Config("read-only mode requires a primary URL".into())
+            })?;
+            routes.layer(axum::middleware::from_fn(move |req, next| {
+                replica::reject_writes(req, next, primary.clone())
+            }))
+        } else {
+            routes
+        };
+
+        let routes = match rate_limit {
+            Some(config) => {
+                #[cfg(feature = "cache")]
+                let store = match cache {
+                    Some(cache) => ratelimit::Store::Cache(ratelimit::CacheStore::new(cache)),
+                    None => ratelimit::Store::Memory(Default::default()),
+                };
+                #[cfg(not(feature = "cache"))]
+                let store = ratelimit::Store::Memory(Default::default());
+
+                let limiter = RateLimiter::new(config, store);
+                routes.layer(axum::middleware::from_fn(move |req, next| {
+                    ratelimit::limit(req, next, limiter.clone())
+                }))
+            }
+            None => routes,
+        };
+
+        let routes = match slow_requests {
+            Some(config) => routes.layer(axum::middleware::from_fn(move |req, next| {
+                timing::trace_slow_requests(req, next, config)
+            })),
+            None => routes,
+        };
+
+        let routes = routes.layer(axum::middleware::from_fn(move |req, next| {
+            sso::track_outages(req, next, circuits.clone())
+        }));
+
+        #[cfg(feature = "metrics")]
+        let routes = routes.layer(axum::middleware::from_fn(move |req, next| {
+            metrics::track_requests(req, next, metrics.clone())
+        }));
+
+        // Outside of the limits and checks, so browsers can read their rejections
+        let routes = routes.layer(axum::middleware::from_fn(move |req, next| {
+            cors::handle(req, next, cors.clone())
+        }));
+
+        // Outside of all other middleware, which should see the database of the realm as well
+        let routes = if realms.is_empty() {
+            routes
+        } else {
+            routes.layer(axum::middleware::from_fn(move |req, next| {
+                database::route_realm(req, next, realms.clone())
+            }))
+        };
+
+        let routes = routes.layer(middleware.into_inner());
+
+        // The middleware sees the paths without the base path
+        let routes = if base_path.is_empty() {
+            routes
+        } else {
+            Router::new().nest(&base_path, routes)
+        };
+
+        Ok(Server {
+            router: routes,
+            addr: SocketAddr::from((app_config.server_addr, app_config.server_port)),
+            reloader,
+            secret_files,
+            secret_file_interval: Duration::from_secs(app_config.secret_file_interval),
+            grace: Duration::from_secs(app_config.shutdown_grace_secs),
+        })
+    }
+}
+
+/// Routes described by the OpenAPI spec, the outbox is only mounted with the inbox of a sandbox
+fn api_routes(
+    providers: Live<Providers>,
+    sandbox: bool,
+    docs: bool,
+    inbox: Option<mail::Inbox>,
+) -> Router {
+    let svc_routes = Router::new()
+        .nest("/user", user::routes())
+        .nest("/client", client::routes())
+        .nest("/session", session::routes())
+        .nest("/login", session::login_routes())
+        .nest("/service", service::routes())
+        .nest("/token", token::routes())
+        .nest("/sso", sso::routes(providers, sandbox))
+        .nest("/action", action::routes())
+        .nest("/audit", audit::routes())
+        .nest("/admin/verify-token", inspect::routes())
+        .nest("/admin/sessions", bulk::routes())
+        .nest("/admin/token-epoch", epoch::routes())
+        .nest("/admin/keys", keys::routes())
+        .merge(openapi::routes(docs));
+
+    let svc_routes = match inbox {
+        Some(inbox) => svc_routes.nest("/admin/outbox", outbox::routes(inbox)),
+        None => svc_routes,
+    };
+
+    #[cfg(feature = "selftest")]
+    let svc_routes = svc_routes.nest("/admin/selftest", selftest::routes());
+
+    Router::new()
+        .nest("/v1", svc_routes)
+        .nest("/.well-known", well_known::routes())
+        .nest("/oauth", oauth::routes())
+}
+
+/// The identity server, with its routes ready to be served or mounted in another application
+pub struct Server {
+    router: Router,
+    addr: SocketAddr,
+    reloader: Reloader,
+    secret_files: Vec<SecretFile>,
+    secret_file_interval: Duration,
+    grace: Duration,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Address of the configuration to serve on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Routes with all middleware, to be nested or merged into the router of another application.
+    ///
+    /// It has to be served with `into_make_service_with_connect_info::<SocketAddr>()` for the
+    /// addresses of clients. Reloads on `SIGHUP` and restarts on rotated secret files are left
+    /// to the application.
+    pub fn into_router(self) -> Router {
+        self.router
+    }
+
+    /// Serves on the address of the configuration until `SIGINT` or `SIGTERM`, or until a secret
+    /// file that can't be reloaded in place was rotated. Reloads the configuration on `SIGHUP`.
+    pub async fn serve(self) -> Result<()> {
+        // Allowed domains, CORS origins and provider credentials are read again on SIGHUP
+        let reloader = self.reloader.clone();
+        self.reloader.spawn();
+
+        tracing::debug!("listening on {}", self.addr);
+        let server = HttpServer::bind(&self.addr).serve(
+            self.router
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        );
+
+        let signal_tx = utils::shutdown_signal(1);
+        config::watch_secret_files(
+            self.secret_files,
+            self.secret_file_interval,
+            reloader,
+            signal_tx.clone(),
+        );
+        let mut signal_rx = signal_tx.subscribe();
+        let server = server.with_graceful_shutdown(async move {
+            signal_rx.recv().await.ok();
+        });
+
+        // New connections are refused once the signal is received, in-flight requests get the
+        // grace period to complete
+        let mut grace_rx = signal_tx.subscribe();
+        let grace = self.grace;
+        let grace_period = async move {
+            grace_rx.recv().await.ok();
+            tracing::info!(grace_secs = grace.as_secs(), "draining connections");
+            tokio::time::sleep(grace).await;
+        };
+
+        tokio::select! {
+            result = server => result?,
+            _ = grace_period => tracing::warn!("grace period elapsed, closing remaining connections"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::authentication::token::TokenConfig;
+
+    use hyper::{Body, Request, StatusCode};
+    use tower::ServiceExt;
+    use tower_http::add_extension::AddExtensionLayer;
+
+    /// Axum can't list its routes, so every documented operation is requested instead. Without
+    /// the state the handlers fail, but not with 404 or 405 unless the route is missing.
+    #[tokio::test]
+    async fn mounts_documented_operations() {
+        let routes = api_routes(
+            Live::new(Providers::default()),
+            true,
+            true,
+            Some(mail::Inbox::default()),
+        )
+        .layer(AddExtensionLayer::new(TokenConfig::from_secret(
+            "secret",
+            ["test"],
+        )));
+
+        let spec = openapi::spec();
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            // Providers are only mounted if they are configured
+            if path.starts_with("/v1/sso/") && path != "/v1/sso/register" {
+                continue;
+            }
+
+            let uri = path
+                .split('/')
+                .map(|s| match s.starts_with('{') {
+                    true => "62a3c0a5e2a1f3b4c5d6e7f8",
+                    false => s,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in item.as_object().unwrap().keys() {
+                let req = Request::builder()
+                    .method(method.to_uppercase().as_str())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap();
+                let res = routes.clone().oneshot(req).await.unwrap();
+
+                assert!(
+                    !matches!(
+                        res.status(),
+                        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                    ),
+                    "{} {} is answered with {}",
+                    method,
+                    path,
+                    res.status()
+                );
+            }
+        }
+    }
+}
@@ -1,19 +1,24 @@
 use crate::{
     authentication::AuthenticationError,
+    client::{ClientUsage, UnusedScopeResponse, UsageReportResponse},
     database::Database,
     error::QueryError,
     extract::{Query, SizedJson, TokenData},
+    label::{self, Labels},
     model::{List, ListOptions, Response, Status},
-    session::{self, SessionClaims},
+    revision::RevisionResponse,
+    session::{Access, Resource, SessionClaims},
+    user::ProfileField,
     utils::crypto::Aead256,
 };
 
-use super::{ServiceDocument, ServiceError};
+use super::{health::StatusResponse, ServiceDocument, ServiceError};
 
 use axum::extract::{Extension, Path};
 use chrono::{serde::ts_seconds, DateTime, Utc};
 use hyper::StatusCode;
-use mongodb::bson::{doc, oid::ObjectId, to_document, Document};
+use mongodb::bson::{doc, oid::ObjectId, to_bson, to_document, Document};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize)]
@@ -24,6 +29,13 @@ pub struct ServiceResponse {
     pub audience: Vec<String>,
     pub scope: Vec<String>,
     pub default_scope: Vec<String>,
+    pub privileged_scope: Vec<String>,
+    pub labels: Labels,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_url: Option<Url>,
+    pub profile_fields: Vec<ProfileField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u8>,
     #[serde(with = "ts_seconds")]
     pub last_modified: DateTime<Utc>,
 }
@@ -36,6 +48,11 @@ impl From<ServiceDocument> for ServiceResponse {
             audience: doc.audience,
             scope: doc.scope,
             default_scope: doc.scope_default,
+            privileged_scope: doc.scope_privileged,
+            labels: doc.labels,
+            health_url: doc.health_url,
+            profile_fields: doc.profile_fields,
+            min_age: doc.min_age,
             last_modified: doc.last_modified,
         }
     }
@@ -48,6 +65,9 @@ pub struct Filter {
     name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     audience: Option<String>,
+    /// Comma-separated `key:value` selectors
+    #[serde(skip_serializing)]
+    label: Option<String>,
 }
 
 pub async fn list(
@@ -56,11 +76,16 @@ pub async fn list(
     Query(opts): Query<ListOptions>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<List<ServiceResponse>>> {
-    if !claims.scope.contains(&session::Scope::ServiceRead) {
+    if !claims.is_global(Resource::Service, Access::Read) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
-    let (services, total) = db.get_services(to_document(&filter).unwrap(), opts).await?;
+    let mut f = to_document(&filter).unwrap();
+    if let Some(v) = filter.label {
+        f.extend(label::filter(&v)?);
+    }
+
+    let (services, total) = db.get_services(f, opts).await?;
     let list = List::new(total, services);
 
     Ok(Response::new(list))
@@ -71,7 +96,7 @@ pub async fn get_by_id(
     TokenData(claims): TokenData<SessionClaims>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<ServiceResponse>> {
-    if !claims.scope.contains(&session::Scope::ServiceRead) {
+    if !claims.is_global(Resource::Service, Access::Read) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
@@ -89,7 +114,23 @@ pub struct CreateRequest {
     audience: Vec<String>,
     scope: Vec<String>,
     scope_default: Vec<String>,
+    #[serde(default)]
+    scope_privileged: Vec<String>,
     secret: Option<String>,
+    #[serde(default)]
+    labels: Labels,
+    health_url: Option<Url>,
+    #[serde(default)]
+    profile_fields: Vec<ProfileField>,
+    min_age: Option<u8>,
+}
+
+fn validate_health_url(url: &Url) -> Result<(), ServiceError> {
+    if !matches!(url.scheme(), "https" | "http") {
+        return Err(ServiceError::InvalidHealthUrl);
+    }
+
+    Ok(())
 }
 
 pub async fn create(
@@ -98,10 +139,15 @@ pub async fn create(
     Extension(db): Extension<Database>,
     Extension(enc): Extension<Aead256>,
 ) -> crate::Result<Response<ServiceResponse>> {
-    if !claims.scope.contains(&session::Scope::ServiceWrite) {
+    if !claims.is_global(Resource::Service, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
+    label::validate(&body.labels)?;
+    if let Some(ref url) = body.health_url {
+        validate_health_url(url)?;
+    }
+
     let secret = if let Some(s) = body.secret {
         base64::encode_config(enc.encrypt(s), base64::STANDARD).into()
     } else {
@@ -114,7 +160,12 @@ pub async fn create(
         audience: body.audience,
         scope: body.scope,
         scope_default: body.scope_default,
+        scope_privileged: body.scope_privileged,
         secret,
+        labels: body.labels,
+        health_url: body.health_url,
+        profile_fields: body.profile_fields,
+        min_age: body.min_age.filter(|v| *v > 0),
         last_modified: Utc::now(),
     };
 
@@ -130,7 +181,13 @@ pub struct UpdateRequest {
     audience: Option<String>,
     scope: Option<Vec<String>>,
     scope_default: Option<Vec<String>>,
+    scope_privileged: Option<Vec<String>>,
     secret: Option<String>,
+    labels: Option<Labels>,
+    health_url: Option<Url>,
+    profile_fields: Option<Vec<ProfileField>>,
+    /// Zero removes the age gate
+    min_age: Option<u8>,
 }
 
 pub async fn update(
@@ -140,7 +197,7 @@ pub async fn update(
     Extension(db): Extension<Database>,
     Extension(enc): Extension<Aead256>,
 ) -> crate::Result<Response<ServiceResponse>> {
-    if !claims.scope.contains(&session::Scope::ServiceWrite) {
+    if !claims.is_global(Resource::Service, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
@@ -162,6 +219,15 @@ pub async fn update(
         }
     }
 
+    let scope = body.scope.as_ref().unwrap_or(&svc.scope);
+    let privileged = body
+        .scope_privileged
+        .as_ref()
+        .unwrap_or(&svc.scope_privileged);
+    if !privileged.iter().all(|s| scope.contains(s)) {
+        return Err(ServiceError::UndefinedScope.into());
+    }
+
     let mut doc = Document::new();
     if let Some(v) = body.name {
         doc.insert("name", v);
@@ -179,6 +245,23 @@ pub async fn update(
     if let Some(v) = body.scope_default {
         doc.insert("scopeDefault", v);
     }
+    if let Some(v) = body.scope_privileged {
+        doc.insert("scopePrivileged", v);
+    }
+    if let Some(v) = body.labels {
+        label::validate(&v)?;
+        doc.insert("labels", to_document(&v).unwrap());
+    }
+    if let Some(v) = body.health_url {
+        validate_health_url(&v)?;
+        doc.insert("healthUrl", v.as_str());
+    }
+    if let Some(v) = body.profile_fields {
+        doc.insert("profileFields", to_bson(&v).unwrap());
+    }
+    if let Some(v) = body.min_age {
+        doc.insert("minAge", (v > 0).then(|| i32::from(v)));
+    }
     if doc.is_empty() {
         return Err(QueryError::InvalidBody.into());
     }
@@ -193,7 +276,7 @@ pub async fn delete(
     TokenData(claims): TokenData<SessionClaims>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Status> {
-    if !claims.scope.contains(&session::Scope::ServiceWrite) {
+    if !claims.is_global(Resource::Service, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
@@ -203,3 +286,119 @@ pub async fn delete(
 
     Ok(Status::new(StatusCode::OK, "service deleted"))
 }
+
+pub async fn status(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<StatusResponse>> {
+    if !claims.is_global(Resource::Service, Access::Read) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ServiceError::InvalidId)?;
+
+    let service = db.get_service(doc! { "_id": id }).await?;
+    let health = match service.health_url {
+        Some(_) => db.get_service_health(id).await?,
+        None => None,
+    };
+
+    Ok(Response::new(StatusResponse::new(health, Utc::now())))
+}
+
+pub async fn history(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(opts): Query<ListOptions>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<RevisionResponse>>> {
+    if !claims.is_global(Resource::Service, Access::Read) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ServiceError::InvalidId)?;
+
+    let (revisions, total) = db.get_revisions(Resource::Service, id, opts).await?;
+
+    Ok(Response::new(List::new(total, revisions)))
+}
+
+pub async fn revert(
+    Path((id, revision)): Path<(String, String)>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<ServiceResponse>> {
+    if !claims.is_global(Resource::Service, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ServiceError::InvalidId)?;
+
+    let update = db
+        .revert_update(Resource::Service, id, &revision, db.clock().now())
+        .await?;
+    let service = db.modify_service(id, update).await?;
+
+    Ok(Response::new(service.into()))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    usage: Vec<ClientUsage>,
+}
+
+pub async fn report_usage(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<UsageReport>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<UsageReportResponse>> {
+    if !claims.is_global(Resource::Service, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ServiceError::InvalidId)?;
+
+    let service = db.get_service(doc! { "_id": id }).await?;
+    let report = db
+        .record_scope_usage(&service, body.usage, Utc::now())
+        .await?;
+
+    Ok(Response::new(report))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnusedScopeQuery {
+    /// Days without usage until a scope counts as unused
+    #[serde(default = "default_unused_days")]
+    days: u32,
+}
+
+fn default_unused_days() -> u32 {
+    30
+}
+
+pub async fn unused_scopes(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(query): Query<UnusedScopeQuery>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<UnusedScopeResponse>>> {
+    if !claims.is_global(Resource::Service, Access::Read)
+        || !claims.is_global(Resource::Client, Access::Read)
+    {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ServiceError::InvalidId)?;
+
+    let service = db.get_service(doc! { "_id": id }).await?;
+    let report = db
+        .get_unused_scopes(&service, query.days.into(), Utc::now())
+        .await?;
+
+    Ok(Response::new(List::new(report.len() as u64, report)))
+}
@@ -0,0 +1,285 @@
+//! Health checks of services with a registered health URL, so that tokens aren't issued for
+//! services that are gone
+
+use crate::{
+    clock::SharedClock,
+    database::Database,
+    http::{HttpClient, SendTimed},
+    utils, Result,
+};
+
+use super::{ServiceDocument, ServiceError, COLLECTION as SERVICES};
+
+use std::time::Duration as StdDuration;
+
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    options::ReplaceOptions,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+const CHECK_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+const PROBE_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+/// Probes running at the same time
+const CONCURRENCY: usize = 8;
+
+/// Services that are unhealthy for this long count as dead and get no new tokens
+const DEAD_AFTER_DAYS: i64 = 14;
+
+/// Result of the latest health checks of a service
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthDocument {
+    #[serde(rename = "_id")]
+    pub service: ObjectId,
+    pub healthy: bool,
+    /// Start of the current healthy or unhealthy period
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub since: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub checked_at: DateTime<Utc>,
+    /// Reason of the latest failed check
+    pub error: Option<String>,
+}
+
+impl HealthDocument {
+    /// Applies the result of a check to the previous state
+    fn next(
+        prev: Option<&Self>,
+        service: ObjectId,
+        result: std::result::Result<(), String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let healthy = result.is_ok();
+        let since = match prev {
+            Some(prev) if prev.healthy == healthy => prev.since,
+            _ => now,
+        };
+
+        Self {
+            service,
+            healthy,
+            since,
+            checked_at: now,
+            error: result.err(),
+        }
+    }
+
+    pub fn is_dead(&self, now: DateTime<Utc>) -> bool {
+        !self.healthy && now - self.since > Duration::days(DEAD_AFTER_DAYS)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    /// No health URL is registered or it was not checked yet
+    Unknown,
+    Healthy,
+    Unhealthy,
+    /// Unhealthy for so long that no tokens are issued
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusResponse {
+    pub status: Health,
+    #[serde(skip_serializing_if = "Option::is_none", with = "ts_seconds_option")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "ts_seconds_option")]
+    pub checked_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+mod ts_seconds_option {
+    use super::*;
+
+    use serde::Serializer;
+
+    pub fn serialize<S>(v: &Option<DateTime<Utc>>, s: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match v {
+            Some(v) => ts_seconds::serialize(v, s),
+            None => s.serialize_none(),
+        }
+    }
+}
+
+impl StatusResponse {
+    pub fn new(health: Option<HealthDocument>, now: DateTime<Utc>) -> Self {
+        match health {
+            Some(doc) => Self {
+                status: match (doc.healthy, doc.is_dead(now)) {
+                    (true, _) => Health::Healthy,
+                    (false, true) => Health::Dead,
+                    (false, false) => Health::Unhealthy,
+                },
+                since: Some(doc.since),
+                checked_at: Some(doc.checked_at),
+                error: doc.error,
+            },
+            None => Self {
+                status: Health::Unknown,
+                since: None,
+                checked_at: None,
+                error: None,
+            },
+        }
+    }
+}
+
+/// Checks the health URLs of all services periodically
+pub fn spawn_health_checks(db: Database, clock: SharedClock) {
+    // Health endpoints are often internal and served without TLS
+    let client = HttpClient::allow_http();
+
+    utils::spawn_named("service-health", async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = check_all(&db, &client, &clock).await {
+                error!(error = %e, "service health check failed");
+            }
+        }
+    });
+}
+
+async fn check_all(db: &Database, client: &HttpClient, clock: &SharedClock) -> Result<()> {
+    let services = db
+        .collection::<ServiceDocument>(SERVICES)
+        .find(doc! { "healthUrl": { "$exists": true } }, None)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    stream::iter(services)
+        .map(|svc| async move {
+            let result = probe(client, &svc).await;
+            if let Err(e) = db.record_health(&svc, result, clock.now()).await {
+                error!(service = %svc.id, error = %e, "service health could not be recorded");
+            }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect::<()>()
+        .await;
+
+    Ok(())
+}
+
+async fn probe(client: &HttpClient, svc: &ServiceDocument) -> std::result::Result<(), String> {
+    let url = match &svc.health_url {
+        Some(url) => url.clone(),
+        None => return Ok(()),
+    };
+
+    match client.get(url).timeout(PROBE_TIMEOUT).send_timed().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => Err(format!("status {}", res.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+const COLLECTION: &str = "service_health";
+
+impl Database {
+    pub async fn get_service_health(&self, service: ObjectId) -> Result<Option<HealthDocument>> {
+        let health = self
+            .collection::<HealthDocument>(COLLECTION)
+            .find_one(doc! { "_id": service }, None)
+            .await?;
+
+        Ok(health)
+    }
+
+    /// Fails if the health URL of the service was unhealthy for too long
+    pub async fn ensure_service_alive(
+        &self,
+        svc: &ServiceDocument,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if svc.health_url.is_none() {
+            return Ok(());
+        }
+
+        match self.get_service_health(svc.id).await? {
+            Some(health) if health.is_dead(now) => Err(ServiceError::Unavailable.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn record_health(
+        &self,
+        svc: &ServiceDocument,
+        result: std::result::Result<(), String>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let prev = self.get_service_health(svc.id).await?;
+        let next = HealthDocument::next(prev.as_ref(), svc.id, result, now);
+
+        match &prev {
+            Some(prev) if prev.healthy && !next.healthy => {
+                warn!(service = %svc.id, name = %svc.name, error = ?next.error, "service became unhealthy")
+            }
+            Some(prev) if !prev.is_dead(now) && next.is_dead(now) => {
+                warn!(service = %svc.id, name = %svc.name, "service is dead, no more tokens are issued")
+            }
+            _ => {}
+        }
+
+        self.collection::<HealthDocument>(COLLECTION)
+            .replace_one(
+                doc! { "_id": svc.id },
+                next,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_period_start() {
+        let service = ObjectId::new();
+        let start = Utc::now();
+        let later = start + Duration::minutes(1);
+
+        let first = HealthDocument::next(None, service, Err("timeout".into()), start);
+        let second = HealthDocument::next(Some(&first), service, Err("timeout".into()), later);
+        assert_eq!(second.since, start);
+        assert_eq!(second.checked_at, later);
+
+        let third = HealthDocument::next(Some(&second), service, Ok(()), later);
+        assert!(third.healthy);
+        assert_eq!(third.since, later);
+        assert_eq!(third.error, None);
+    }
+
+    #[test]
+    fn dies_after_long_outage() {
+        let service = ObjectId::new();
+        let start = Utc::now();
+
+        let down = HealthDocument::next(None, service, Err("timeout".into()), start);
+        assert!(!down.is_dead(start + Duration::days(1)));
+        assert!(down.is_dead(start + Duration::days(DEAD_AFTER_DAYS + 1)));
+
+        let up = HealthDocument::next(None, service, Ok(()), start);
+        assert!(!up.is_dead(start + Duration::days(DEAD_AFTER_DAYS + 1)));
+    }
+}
@@ -1,10 +1,15 @@
 mod handler;
+mod health;
 mod routes;
 
 use crate::{
     database::Database,
     error,
+    label::Labels,
     model::{ListOptions, Status},
+    session::Resource,
+    user::ProfileField,
+    utils::crypto::{Aead256, CryptoError},
     Result,
 };
 
@@ -15,8 +20,10 @@ use mongodb::{
     bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document},
     options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
 };
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
+pub use health::spawn_health_checks;
 pub use routes::routes;
 
 #[derive(Debug, thiserror::Error)]
@@ -27,6 +34,10 @@ pub enum ServiceError {
     InvalidId,
     #[error("scope is not defined")]
     UndefinedScope,
+    #[error("health URL is invalid")]
+    InvalidHealthUrl,
+    #[error("service is unavailable")]
+    Unavailable,
 }
 
 impl error::ErrorResponse for ServiceError {
@@ -35,7 +46,10 @@ impl error::ErrorResponse for ServiceError {
     fn status_code(&self) -> StatusCode {
         match self {
             ServiceError::NotFound => StatusCode::NOT_FOUND,
-            ServiceError::InvalidId | ServiceError::UndefinedScope => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidId
+            | ServiceError::UndefinedScope
+            | ServiceError::InvalidHealthUrl => StatusCode::BAD_REQUEST,
+            ServiceError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -54,12 +68,38 @@ pub struct ServiceDocument {
     pub audience: Vec<String>,
     pub scope: Vec<String>,
     pub scope_default: Vec<String>,
+    /// High-privilege scopes, reported when clients hold them without using them
+    #[serde(default)]
+    pub scope_privileged: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub secret: Option<String>,
+    #[serde(default)]
+    pub labels: Labels,
+    /// Probed periodically, tokens are refused once it was unhealthy for too long
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_url: Option<Url>,
+    /// Users fill these in before authorizing clients of the service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profile_fields: Vec<ProfileField>,
+    /// Users younger are refused, their birthdate is required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u8>,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub last_modified: DateTime<Utc>,
 }
 
+impl ServiceDocument {
+    /// Profile fields users fill in before authorizing clients, including those of the age gate
+    pub fn required_profile_fields(&self) -> Vec<ProfileField> {
+        let mut fields = self.profile_fields.clone();
+        if self.min_age.is_some() && !fields.contains(&ProfileField::Birthdate) {
+            fields.push(ProfileField::Birthdate);
+        }
+
+        fields
+    }
+}
+
 const COLLECTION: &str = "services";
 
 impl Database {
@@ -111,11 +151,30 @@ impl Database {
         Ok(service.unwrap())
     }
 
+    /// All services by name, e.g. for the scope catalog
+    pub async fn get_all_services(&self) -> Result<Vec<ServiceDocument>> {
+        let opts = FindOptions::builder().sort(doc! { "name": 1 }).build();
+
+        let cursor = self
+            .collection::<ServiceDocument>(COLLECTION)
+            .find(None, opts)
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
     async fn insert_service(&self, doc: &ServiceDocument) -> Result<()> {
-        if !doc.scope_default.iter().all(|s| doc.scope.contains(s)) {
+        if !doc
+            .scope_default
+            .iter()
+            .chain(&doc.scope_privileged)
+            .all(|s| doc.scope.contains(s))
+        {
             return Err(ServiceError::UndefinedScope.into());
         }
 
+        self.check_quota(Resource::Service).await?;
+
         self.collection::<ServiceDocument>(COLLECTION)
             .insert_one(doc, None)
             .await?;
@@ -123,26 +182,85 @@ impl Database {
         Ok(())
     }
 
+    /// Inserts the service unless one with its name exists, returns the stored one. Used for
+    /// internal services, which don't count towards the quota.
+    #[cfg(feature = "selftest")]
+    pub async fn insert_service_if_new(&self, doc: &ServiceDocument) -> Result<ServiceDocument> {
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let service = self
+            .collection::<ServiceDocument>(COLLECTION)
+            .find_one_and_update(
+                doc! { "name": &doc.name },
+                doc! { "$setOnInsert": mongodb::bson::to_document(doc).unwrap() },
+                opts,
+            )
+            .await?;
+
+        service.ok_or_else(|| ServiceError::NotFound.into())
+    }
+
     async fn update_service(&self, id: ObjectId, update: Document) -> Result<ServiceDocument> {
-        let doc = doc! {
-            "$currentDate": { "lastModified": true },
-            "$set": update,
-        };
+        self.modify_service(id, doc! { "$set": update }).await
+    }
+
+    /// Applies update operators to the service and records the revision
+    async fn modify_service(&self, id: ObjectId, mut doc: Document) -> Result<ServiceDocument> {
+        doc.insert("$currentDate", doc! { "lastModified": true });
 
         let opts = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
+            .return_document(ReturnDocument::Before)
             .build();
 
-        let result = self
+        let before = match self
             .collection::<ServiceDocument>(COLLECTION)
             .find_one_and_update(doc! { "_id": id }, doc, opts)
+            .await?
+        {
+            Some(v) => v,
+            None => return Err(ServiceError::NotFound.into()),
+        };
+
+        let after = self.get_service(doc! { "_id": id }).await?;
+
+        self.record_revision(Resource::Service, id, &before, &after)
             .await?;
 
-        if result.is_none() {
-            return Err(ServiceError::NotFound.into());
+        Ok(after)
+    }
+
+    /// Re-encrypts the secrets of all services with the current key
+    pub async fn reencrypt_service_secrets(&self, enc: &Aead256) -> Result<u64> {
+        let coll = self.collection::<ServiceDocument>(COLLECTION);
+        let mut cursor = coll
+            .find(doc! { "secret": { "$exists": true } }, None)
+            .await?;
+
+        let mut count = 0;
+        while let Some(svc) = cursor.try_next().await? {
+            let secret = match svc.secret {
+                Some(secret) => secret,
+                None => continue,
+            };
+            let secret = base64::decode_config(secret, base64::STANDARD).map_err(|_| {
+                CryptoError::InvalidEncoding(format!("secret of service {}", svc.id))
+            })?;
+
+            if let Some(secret) = enc.reencrypt(secret)? {
+                let secret = base64::encode_config(secret, base64::STANDARD);
+                coll.update_one(
+                    doc! { "_id": svc.id },
+                    doc! { "$set": { "secret": secret } },
+                    None,
+                )
+                .await?;
+                count += 1;
+            }
         }
 
-        Ok(result.unwrap())
+        Ok(count)
     }
 
     async fn delete_service(&self, id: ObjectId) -> Result<()> {
@@ -1,6 +1,6 @@
 use super::handler;
 
-use axum::routing::get;
+use axum::routing::{get, post};
 
 /// User routes
 pub fn routes() -> axum::Router {
@@ -12,4 +12,9 @@ pub fn routes() -> axum::Router {
                 .patch(handler::update)
                 .delete(handler::delete),
         )
+        .route("/:id/status", get(handler::status))
+        .route("/:id/history", get(handler::history))
+        .route("/:id/revert/:revision", post(handler::revert))
+        .route("/:id/usage", post(handler::report_usage))
+        .route("/:id/unused-scopes", get(handler::unused_scopes))
 }
@@ -0,0 +1,332 @@
+//! Active sessions of users, so that they can see where they are logged in and end single
+//! sessions.
+//!
+//! A session starts with a login and lasts as long as its refresh tokens. Exchanging a refresh
+//! token replaces the session token, the session then tracks the ID of the new one.
+
+use crate::{database::Database, extract::ClientInfo, Result};
+
+use super::SessionClaims;
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, Document},
+    options::{FindOptions, IndexOptions},
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSessionDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    /// ID of the current session token
+    pub jti: String,
+    /// Family of the refresh tokens, fallback sessions have none
+    pub family: Option<ObjectId>,
+    pub method: String,
+    /// Audience of the session token
+    #[serde(default)]
+    pub audience: Vec<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub last_used_at: DateTime<Utc>,
+    /// Expiration of the current session token
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub token_expires_at: DateTime<Utc>,
+    /// Expiration of the session, once neither its token nor its refresh token is valid
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ActiveSessionDocument {
+    pub fn new(user: ObjectId, claims: &SessionClaims, method: &str, client: &ClientInfo) -> Self {
+        Self {
+            id: ObjectId::new(),
+            user,
+            jti: claims.jti.clone().unwrap_or_default(),
+            family: None,
+            method: method.to_string(),
+            audience: claims.aud.clone(),
+            ip: client.addr.map(|v| v.to_string()),
+            user_agent: client.user_agent.clone(),
+            created_at: claims.iat,
+            last_used_at: claims.iat,
+            token_expires_at: claims.exp,
+            expires_at: claims.exp,
+        }
+    }
+
+    /// Continues the session with refresh tokens of the family
+    pub fn with_family(mut self, family: ObjectId, expires_at: DateTime<Utc>) -> Self {
+        self.family = Some(family);
+        self.expires_at = self.expires_at.max(expires_at);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSessionResponse {
+    pub jti: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(with = "ts_seconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub last_used_at: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+    /// Session of the request
+    pub current: bool,
+}
+
+impl ActiveSessionResponse {
+    pub fn new(doc: ActiveSessionDocument, current: Option<&str>) -> Self {
+        Self {
+            current: current == Some(doc.jti.as_str()),
+            jti: doc.jti,
+            method: doc.method,
+            ip: doc.ip,
+            user_agent: doc.user_agent,
+            created_at: doc.created_at,
+            last_used_at: doc.last_used_at,
+            expires_at: doc.expires_at,
+        }
+    }
+}
+
+const COLLECTION: &str = "sessions";
+
+impl Database {
+    /// Removes sessions once they are expired
+    pub async fn init_sessions(&self) -> Result<()> {
+        let indexes = vec![
+            IndexModel::builder()
+                .keys(doc! { "expiresAt": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(std::time::Duration::ZERO)
+                        .build(),
+                )
+                .build(),
+            IndexModel::builder()
+                .keys(doc! { "user": 1, "jti": 1 })
+                .build(),
+            IndexModel::builder().keys(doc! { "family": 1 }).build(),
+        ];
+
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .create_indexes(indexes, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Finds sessions by the ID of their current token, which is unique
+    pub async fn index_session_tokens(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "jti": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn insert_session(&self, doc: &ActiveSessionDocument) -> Result<()> {
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces the token of the session after an exchange of its refresh token
+    pub async fn rotate_session(
+        &self,
+        family: ObjectId,
+        jti: &str,
+        token_expires_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let update = doc! {
+            "$set": {
+                "jti": jti,
+                "tokenExpiresAt": token_expires_at,
+                "lastUsedAt": now,
+            },
+            "$max": { "expiresAt": expires_at },
+        };
+
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .update_one(doc! { "family": family }, update, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Extends the token of the session, its ID stays the same
+    pub async fn extend_session(
+        &self,
+        jti: &str,
+        token_expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let update = doc! {
+            "$set": { "tokenExpiresAt": token_expires_at, "lastUsedAt": now },
+            "$max": { "expiresAt": token_expires_at },
+        };
+
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .update_one(doc! { "jti": jti }, update, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_sessions(
+        &self,
+        user: ObjectId,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ActiveSessionDocument>> {
+        let opts = FindOptions::builder()
+            .sort(doc! { "lastUsedAt": -1 })
+            .build();
+
+        let sessions = self
+            .collection::<ActiveSessionDocument>(COLLECTION)
+            .find(doc! { "user": user, "expiresAt": { "$gt": now } }, opts)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(sessions)
+    }
+
+    /// Removes the session and returns it, if there was one
+    pub async fn end_session(
+        &self,
+        user: ObjectId,
+        jti: &str,
+    ) -> Result<Option<ActiveSessionDocument>> {
+        let session = self
+            .collection::<ActiveSessionDocument>(COLLECTION)
+            .find_one_and_delete(doc! { "user": user, "jti": jti }, None)
+            .await?;
+
+        Ok(session)
+    }
+
+    /// Sessions matching the filter in the order of their IDs, starting after `after`
+    pub async fn find_sessions(
+        &self,
+        mut filter: Document,
+        after: Option<ObjectId>,
+        limit: i64,
+    ) -> Result<Vec<ActiveSessionDocument>> {
+        if let Some(id) = after {
+            filter.insert("_id", doc! { "$gt": id });
+        }
+        let opts = FindOptions::builder()
+            .sort(doc! { "_id": 1 })
+            .limit(limit)
+            .build();
+
+        let sessions = self
+            .collection::<ActiveSessionDocument>(COLLECTION)
+            .find(filter, opts)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(sessions)
+    }
+
+    pub async fn count_sessions(&self, filter: Document) -> Result<u64> {
+        let count = self
+            .collection::<ActiveSessionDocument>(COLLECTION)
+            .count_documents(filter, None)
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Revokes the token and the refresh tokens of the session and removes it
+    pub async fn revoke_active_session(
+        &self,
+        session: &ActiveSessionDocument,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        self.revoke_token(&session.jti, session.user, session.token_expires_at, now)
+            .await?;
+        if let Some(family) = session.family {
+            self.revoke_refresh_family(family).await?;
+        }
+
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .delete_one(doc! { "_id": session.id }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn end_user_sessions(&self, user: ObjectId) -> Result<()> {
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .delete_many(doc! { "user": user }, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn end_all_sessions(&self) -> Result<()> {
+        self.collection::<ActiveSessionDocument>(COLLECTION)
+            .delete_many(doc! {}, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::Duration;
+
+    #[test]
+    fn lasts_as_long_as_its_tokens() {
+        let now = Utc::now();
+        let client = ClientInfo {
+            addr: Some([127, 0, 0, 1].into()),
+            user_agent: None,
+        };
+        let mut claims = SessionClaims::new(vec!["aud".to_string()], "user", now);
+        claims.jti = Some("jti".to_string());
+        claims.set_expiration(now + Duration::hours(1));
+
+        let session = ActiveSessionDocument::new(ObjectId::new(), &claims, "password", &client);
+        assert_eq!(session.expires_at, now + Duration::hours(1));
+        assert_eq!(session.audience, vec!["aud".to_string()]);
+        assert_eq!(session.ip.as_deref(), Some("127.0.0.1"));
+
+        let session = session.with_family(ObjectId::new(), now + Duration::days(30));
+        assert_eq!(session.expires_at, now + Duration::days(30));
+
+        let response = ActiveSessionResponse::new(session, Some("jti"));
+        assert!(response.current);
+    }
+}
@@ -0,0 +1,108 @@
+//! Logins that stand in for SSO while a provider is unavailable.
+//!
+//! Each method is opt-in and only accepted while the circuit of an SSO provider is open.
+//! Sessions from a fallback login are marked in their claims, so that clients can show a
+//! banner, and come without a refresh token.
+
+use crate::sso::ProviderCircuits;
+
+use std::{sync::Arc, time::Instant};
+
+use serde::Deserialize;
+
+/// Password login of accounts that have to log in through a provider
+pub const PASSWORD_FALLBACK_LOGIN: &str = "password-fallback";
+/// Login with a recovery code of the authenticator app alone
+pub const RECOVERY_CODE_LOGIN: &str = "recovery-code";
+/// Password login of a configured emergency account
+pub const BREAK_GLASS_LOGIN: &str = "break-glass";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FallbackMethod {
+    Password,
+    RecoveryCode,
+    BreakGlass,
+}
+
+/// Checks if the login method is a fallback
+pub fn is_fallback(method: &str) -> bool {
+    [
+        PASSWORD_FALLBACK_LOGIN,
+        RECOVERY_CODE_LOGIN,
+        BREAK_GLASS_LOGIN,
+    ]
+    .contains(&method)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Fallback {
+    methods: Arc<Vec<FallbackMethod>>,
+    /// Email addresses of the break-glass accounts
+    break_glass: Arc<Vec<String>>,
+    circuits: ProviderCircuits,
+}
+
+impl Fallback {
+    pub fn new(
+        methods: Vec<FallbackMethod>,
+        break_glass: Vec<String>,
+        circuits: ProviderCircuits,
+    ) -> Self {
+        Self {
+            methods: Arc::new(methods),
+            break_glass: Arc::new(break_glass),
+            circuits,
+        }
+    }
+
+    fn is_active(&self, method: FallbackMethod, now: Instant) -> bool {
+        self.methods.contains(&method) && self.circuits.any_open(now)
+    }
+
+    /// Login method of a password login that would have to go through a provider instead
+    pub fn password_method(&self, email: &str, now: Instant) -> Option<&'static str> {
+        if self.is_active(FallbackMethod::BreakGlass, now)
+            && self.break_glass.iter().any(|a| a == email)
+        {
+            Some(BREAK_GLASS_LOGIN)
+        } else if self.is_active(FallbackMethod::Password, now) {
+            Some(PASSWORD_FALLBACK_LOGIN)
+        } else {
+            None
+        }
+    }
+
+    pub fn allows_recovery_code(&self, now: Instant) -> bool {
+        self.is_active(FallbackMethod::RecoveryCode, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_during_outage() {
+        let circuits = ProviderCircuits::default();
+        let fallback = Fallback::new(
+            vec![FallbackMethod::BreakGlass],
+            vec!["admin@example.com".to_string()],
+            circuits.clone(),
+        );
+        let now = Instant::now();
+
+        assert_eq!(fallback.password_method("admin@example.com", now), None);
+
+        for _ in 0..10 {
+            circuits.record("github", false, now);
+        }
+        assert_eq!(
+            fallback.password_method("admin@example.com", now),
+            Some(BREAK_GLASS_LOGIN)
+        );
+        assert_eq!(fallback.password_method("user@example.com", now), None);
+        assert!(!fallback.allows_recovery_code(now));
+        assert!(is_fallback(BREAK_GLASS_LOGIN));
+    }
+}
@@ -1,41 +1,45 @@
 use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
     authentication::{
-        password,
+        credential::CredentialHasher,
         token::{TokenClaims, TokenConfig},
+        AuthenticationError,
     },
+    config::GlobalConfig,
     database::Database,
     error::Error,
-    extract::{SizedJson, TokenData},
-    model::Response,
-    session::{Scope, SessionClaims, SessionError},
-    user::UserError,
+    extract::{ClientInfo, SizedJson, TokenData},
+    mail,
+    model::{List, Response, Status},
+    models::session::{
+        CreateRequest, EmailLoginRequest, ExchangeRequest, MfaRequest, RecoveryLoginRequest,
+        SessionResponse,
+    },
+    session::{
+        check_access, check_schedule, issue_fallback_session, issue_session, send_login_mail,
+        Access, ActiveSessionResponse, Challenge, Fallback, LoginLinkClaims, Resource,
+        SessionClaims, SessionError, EMAIL_LOGIN, PASSWORD_LOGIN, RECOVERY_CODE_LOGIN,
+    },
+    user::{verify_mfa_code, verify_recovery_code, AccountFlag, MfaClaims, UserError},
+    utils::crypto::Aead256,
 };
 
-use axum::extract::Extension;
-use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use super::start_session;
+
+use std::time::Instant;
+
+use axum::extract::{Extension, Path};
 use hyper::StatusCode;
 use mongodb::bson::{doc, oid::ObjectId};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SessionResponse {
-    pub user: String,
-    pub token: String,
-    #[serde(with = "ts_seconds")]
-    pub expires_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct CreateRequest {
-    email: String,
-    password: String,
-}
+use tracing::warn;
 
 pub async fn create(
+    client: ClientInfo,
     SizedJson(body): SizedJson<CreateRequest>,
     Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(hasher): Extension<CredentialHasher>,
+    Extension(fallback): Extension<Fallback>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<SessionResponse>> {
     let user = match db.get_user(doc! {"email": body.email }).await {
@@ -49,7 +53,7 @@ pub async fn create(
         },
     };
 
-    let password = user.password.ok_or(SessionError::BadCredentials)?;
+    let password = user.password.as_ref().ok_or(SessionError::BadCredentials)?;
 
     if !user.verified {
         return Err(SessionError::NotAuthorized("user is not verified".to_string()).into());
@@ -60,23 +64,89 @@ pub async fn create(
         );
     }
 
-    if password::verify_password(&body.password, &password).is_err() {
-        return Err(SessionError::BadCredentials.into());
+    let now = config.clock.now();
+    if user.is_locked(now) {
+        return Err(SessionError::Locked.into());
     }
 
-    let audience = config.validation.aud.clone().unwrap();
-    let scope = Scope::from_roles(user.roles);
-    let claims = SessionClaims::with_scope(audience, &user.id.to_hex(), scope);
+    let rehash = match hasher.verify(&body.password, password) {
+        Ok(v) => v,
+        Err(_) => {
+            let policy = &global.password_policy;
+            let event = SecurityEvent::LoginFailed {
+                method: PASSWORD_LOGIN.to_string(),
+            };
+            db.insert_audit_event(&AuditEventDocument::new(user.id, event, &client))
+                .await?;
 
-    let token = claims.encode(&config)?;
+            if db.record_login_failure(user.id, policy, now).await? {
+                warn!(user = %user.id, "password logins locked after failed attempts");
+                let event = AuditEventDocument::new(user.id, SecurityEvent::LoginLocked, &client);
+                db.insert_audit_event(&event).await?;
+            }
+            return Err(SessionError::BadCredentials.into());
+        }
+    };
 
-    let response = SessionResponse {
-        user: user.id.to_hex(),
-        token,
-        expires_at: claims.exp,
+    if user.failed_logins > 0 || user.locked_until.is_some() {
+        db.reset_login_failures(user.id).await?;
+    }
+
+    if let Some(hash) = rehash {
+        db.update_user_by_id(user.id, doc! { "password": hash })
+            .await?;
+    }
+
+    // Accounts bound to a provider may fall back to their password during an outage
+    let method = if user.flags.contains(&AccountFlag::RelinkRequired) {
+        fallback
+            .password_method(&user.email, Instant::now())
+            .unwrap_or(PASSWORD_LOGIN)
+    } else {
+        PASSWORD_LOGIN
     };
+    if method != PASSWORD_LOGIN {
+        warn!(user = %user.id, method, "fallback login during SSO outage");
+    }
 
-    db.set_user_session(user.id).await?;
+    let response = issue_session(&db, &config, &user, &client, method).await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+/// Logs in with a recovery code alone while an SSO provider is unavailable
+pub async fn login_with_recovery_code(
+    client: ClientInfo,
+    SizedJson(body): SizedJson<RecoveryLoginRequest>,
+    Extension(db): Extension<Database>,
+    Extension(fallback): Extension<Fallback>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    if !fallback.allows_recovery_code(Instant::now()) {
+        return Err(SessionError::FallbackUnavailable.into());
+    }
+
+    let user = match db.get_user(doc! {"email": body.email }).await {
+        Ok(u) => u,
+        Err(Error::User(UserError::NotFound)) => return Err(SessionError::BadCredentials.into()),
+        Err(e) => return Err(e),
+    };
+
+    if !user.verified || !user.can_login {
+        return Err(
+            SessionError::NotAuthorized("user is not authorized to log in".to_string()).into(),
+        );
+    }
+
+    let now = config.clock.now();
+    if user.is_locked(now) {
+        return Err(SessionError::Locked.into());
+    }
+
+    verify_recovery_code(&db, &user, &body.code, &client, now).await?;
+
+    let response =
+        issue_fallback_session(&db, &config, &user, &client, RECOVERY_CODE_LOGIN).await?;
 
     Ok(Response::with_status(StatusCode::CREATED, response))
 }
@@ -100,17 +170,248 @@ pub async fn refresh(
     }
 
     let mut claims = claims;
-    claims.set_expiration(Utc::now() + Duration::minutes(SessionClaims::DEFAULT_EXP_MIN));
+    let now = config.clock.now();
+    let lifetime = config.session_limits.token_lifetime(&user.roles);
+    claims.set_expiration(now + lifetime);
+
+    let token = claims.encode(&config)?;
+    if let Some(jti) = &claims.jti {
+        db.extend_session(jti, claims.exp, now).await?;
+    }
+
+    let response = SessionResponse {
+        user: user.id.to_hex(),
+        token,
+        refresh_token: None,
+        expires_at: claims.exp,
+    };
+
+    db.set_user_session(user.id, config.region.as_deref())
+        .await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+/// Revokes the session token of the request
+pub async fn logout(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    let jti = claims.jti.as_ref().ok_or(SessionError::MissingTokenId)?;
+    let user = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    db.revoke_token(jti, user, claims.exp, config.clock.now())
+        .await?;
+    if let Some(session) = db.end_session(user, jti).await? {
+        if let Some(family) = session.family {
+            db.revoke_refresh_family(family).await?;
+        }
+    }
+
+    Ok(Status::new(StatusCode::OK, "session revoked"))
+}
+
+/// Exchanges a refresh token for a new session token and rotates the refresh token
+pub async fn exchange(
+    client: ClientInfo,
+    SizedJson(body): SizedJson<ExchangeRequest>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    let now = config.clock.now();
+
+    let refresh = db
+        .use_refresh_token(&body.refresh_token, now, &client)
+        .await?;
+
+    let user = match db.get_user(doc! { "_id": refresh.user }).await {
+        Ok(u) => u,
+        Err(Error::User(UserError::NotFound)) => {
+            return Err(SessionError::InvalidRefreshToken.into())
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Revoking all sessions also revokes the refresh tokens issued before
+    if matches!(user.sessions_valid_after, Some(v) if refresh.issued_at <= v.to_chrono()) {
+        return Err(SessionError::InvalidRefreshToken.into());
+    }
+    if !user.verified {
+        return Err(SessionError::NotAuthorized("user is not verified".to_string()).into());
+    }
+    check_access(&user, config.clock.now())?;
+    if user.flags.contains(&AccountFlag::PasswordResetRequired) {
+        return Err(SessionError::ChallengeRequired(Challenge::PasswordReset).into());
+    }
+    check_schedule(&db, &config, &user, &client).await?;
+
+    let claims = SessionClaims::issue_for_user(&config, &user, now).await?;
 
     let token = claims.encode(&config)?;
+    let lifetime = config.session_limits.session_lifetime(&user.roles);
+    let (refresh_token, next) = db
+        .issue_refresh_token(user.id, Some(refresh.family), now, lifetime)
+        .await?;
+    db.rotate_session(
+        refresh.family,
+        claims.jti.as_deref().unwrap_or_default(),
+        claims.exp,
+        next.expires_at,
+        now,
+    )
+    .await?;
 
     let response = SessionResponse {
         user: user.id.to_hex(),
         token,
+        refresh_token: Some(refresh_token),
         expires_at: claims.exp,
     };
 
-    db.set_user_session(user.id).await?;
+    db.set_user_session(user.id, config.region.as_deref())
+        .await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+/// Completes a login that requires two-factor authentication, authorized by its challenge token
+pub async fn verify_mfa(
+    client: ClientInfo,
+    TokenData(claims): TokenData<MfaClaims>,
+    SizedJson(body): SizedJson<MfaRequest>,
+    Extension(db): Extension<Database>,
+    Extension(aead): Extension<Aead256>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! {"_id": user_id }).await?;
+
+    if !user.can_login {
+        return Err(
+            SessionError::NotAuthorized("user is not authorized to log in".to_string()).into(),
+        );
+    }
+
+    verify_mfa_code(&db, &aead, &user, &body.code, &client, config.clock.now()).await?;
+
+    let response = start_session(&db, &config, &user, &client, &claims.method).await?;
 
     Ok(Response::with_status(StatusCode::CREATED, response))
 }
+
+/// Sends a login link to the address, if it belongs to a verified user who may log in
+pub async fn request_login_link(
+    SizedJson(body): SizedJson<EmailLoginRequest>,
+    Extension(db): Extension<Database>,
+    Extension(mail): Extension<mail::Client>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    let user = match db.get_user(doc! { "email": &body.email }).await {
+        Ok(u) => Some(u),
+        Err(Error::User(UserError::NotFound)) => None,
+        Err(e) => return Err(e),
+    };
+
+    // Same response for unknown addresses, so that they can't be probed
+    if let Some(user) = user.filter(|u| u.verified && u.can_login) {
+        send_login_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+    }
+
+    Ok(Status::new(
+        StatusCode::ACCEPTED,
+        "login link sent if the address is registered",
+    ))
+}
+
+/// Exchanges the token of a login link for a session, each link works once
+pub async fn login_with_link(
+    client: ClientInfo,
+    TokenData(claims): TokenData<LoginLinkClaims>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let now = config.clock.now();
+    if !db
+        .consume_token(&claims.jti, user_id, claims.exp, now)
+        .await?
+    {
+        return Err(SessionError::InvalidLoginLink.into());
+    }
+
+    let user = match db.get_user(doc! { "_id": user_id }).await {
+        Ok(u) => u,
+        Err(Error::User(UserError::NotFound)) => return Err(SessionError::InvalidLoginLink.into()),
+        Err(e) => return Err(e),
+    };
+
+    if user.email != claims.email {
+        return Err(SessionError::InvalidLoginLink.into());
+    }
+    if !user.verified {
+        return Err(SessionError::NotAuthorized("user is not verified".to_string()).into());
+    }
+    if !user.can_login {
+        return Err(
+            SessionError::NotAuthorized("user is not authorized to log in".to_string()).into(),
+        );
+    }
+
+    let response = issue_session(&db, &config, &user, &client, EMAIL_LOGIN).await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+pub async fn list_sessions(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<List<ActiveSessionResponse>>> {
+    if !claims.is_permitted(Resource::User, Access::Read, &id) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let user_id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let current = claims.jti.as_deref().filter(|_| claims.sub == id);
+    let sessions: Vec<_> = db
+        .get_sessions(user_id, config.clock.now())
+        .await?
+        .into_iter()
+        .map(|s| ActiveSessionResponse::new(s, current))
+        .collect();
+
+    Ok(Response::new(List::new(sessions.len() as u64, sessions)))
+}
+
+/// Ends a single session, its token is revoked and its refresh tokens can't be exchanged
+pub async fn revoke_session(
+    info: ClientInfo,
+    Path((id, jti)): Path<(String, String)>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    if !claims.is_permitted(Resource::User, Access::Write, &id) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let user_id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let session = db
+        .end_session(user_id, &jti)
+        .await?
+        .ok_or(SessionError::NotFound)?;
+    db.revoke_active_session(&session, config.clock.now())
+        .await?;
+
+    let event = SecurityEvent::SessionRevoked { jti };
+    db.insert_audit_event(&AuditEventDocument::new(user_id, event, &info))
+        .await?;
+
+    Ok(Status::new(StatusCode::OK, "session revoked"))
+}
@@ -0,0 +1,183 @@
+//! Ceilings of the lifetime and scope of sessions, by the roles of the user
+
+use crate::{models::AccessSchedule, user::Role};
+
+use super::{Scope, SessionClaims};
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer};
+
+/// Limits of the sessions of users with a role
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleLimit {
+    /// Maximum lifetime of the login in seconds, refresh tokens don't extend it past that
+    pub max_session_secs: Option<i64>,
+    /// Maximum lifetime of a session token in seconds
+    pub max_token_secs: Option<i64>,
+    /// Scope the role grants at most
+    pub max_scope: Option<Vec<Scope>>,
+    /// Window in which users with the role may access, sessions are rejected outside of it
+    pub schedule: Option<AccessSchedule>,
+}
+
+/// Configured limits of all roles, roles without limits get the defaults and their full scope
+#[derive(Debug, Clone, Default)]
+pub struct SessionLimits {
+    roles: Arc<HashMap<Role, RoleLimit>>,
+}
+
+impl SessionLimits {
+    pub fn new(roles: HashMap<Role, RoleLimit>) -> Self {
+        Self {
+            roles: Arc::new(roles),
+        }
+    }
+
+    /// Shortest limit of any of the roles
+    fn min_secs<F>(&self, roles: &[Role], limit: F) -> Option<Duration>
+    where
+        F: Fn(&RoleLimit) -> Option<i64>,
+    {
+        roles
+            .iter()
+            .filter_map(|r| limit(self.roles.get(r)?))
+            .map(|v| Duration::seconds(v.max(1)))
+            .min()
+    }
+
+    /// Lifetime of a login with the roles, including all refreshed tokens.
+    ///
+    /// Without a limit, refresh tokens extend the login indefinitely.
+    pub fn session_lifetime(&self, roles: &[Role]) -> Option<Duration> {
+        self.min_secs(roles, |l| l.max_session_secs)
+    }
+
+    /// Lifetime of a session token with the roles, limits never extend the default
+    pub fn token_lifetime(&self, roles: &[Role]) -> Duration {
+        let default = Duration::minutes(SessionClaims::DEFAULT_EXP_MIN);
+        self.min_secs(roles, |l| l.max_token_secs)
+            .map_or(default, |v| v.min(default))
+    }
+
+    /// First of the roles whose schedule doesn't allow access at `now`
+    pub fn outside_schedule<'a>(&self, roles: &'a [Role], now: DateTime<Utc>) -> Option<&'a Role> {
+        roles.iter().find(|r| {
+            matches!(
+                self.roles.get(r).and_then(|l| l.schedule.as_ref()),
+                Some(s) if !s.allows(now)
+            )
+        })
+    }
+
+    /// Scope of a session with the roles, each role grants at most its ceiling
+    pub fn scope(&self, roles: &[Role]) -> Vec<Scope> {
+        let mut scope = roles
+            .iter()
+            .flat_map(|r| {
+                let scope: Vec<Scope> = r.clone().into();
+                match self.roles.get(r).and_then(|l| l.max_scope.as_ref()) {
+                    Some(max) => scope.into_iter().filter(|s| max.contains(s)).collect(),
+                    None => scope,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        scope.sort_unstable();
+        scope.dedup();
+
+        scope
+    }
+}
+
+/// Deserializes the limits from a JSON object by role
+pub fn de_limits<'de, D>(d: D) -> std::result::Result<SessionLimits, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let roles: HashMap<Role, RoleLimit> =
+        serde_json::from_str(&input).map_err(serde::de::Error::custom)?;
+
+    Ok(SessionLimits::new(roles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use serde::de::value::{Error as DeError, StrDeserializer};
+    use serde::de::IntoDeserializer;
+
+    fn parse(input: &str) -> SessionLimits {
+        let de: StrDeserializer<DeError> = input.into_deserializer();
+        de_limits(de).unwrap()
+    }
+
+    #[test]
+    fn shortest_lifetime_applies() {
+        let limits = parse(
+            r#"{"userEditor":{"maxTokenSecs":900,"maxSessionSecs":28800},"clientEditor":{"maxTokenSecs":1800}}"#,
+        );
+
+        assert_eq!(limits.token_lifetime(&[]), Duration::minutes(60));
+        assert_eq!(
+            limits.token_lifetime(&[Role::ClientEditor]),
+            Duration::minutes(30)
+        );
+        assert_eq!(
+            limits.token_lifetime(&[Role::ClientEditor, Role::UserEditor]),
+            Duration::minutes(15)
+        );
+        assert_eq!(limits.session_lifetime(&[Role::ClientEditor]), None);
+        assert_eq!(
+            limits.session_lifetime(&[Role::UserEditor]),
+            Some(Duration::hours(8))
+        );
+
+        let limits = parse(r#"{"userEditor":{"maxTokenSecs":86400}}"#);
+        assert_eq!(
+            limits.token_lifetime(&[Role::UserEditor]),
+            Duration::minutes(60)
+        );
+    }
+
+    #[test]
+    fn scope_is_capped_per_role() {
+        let limits = parse(r#"{"userEditor":{"maxScope":["userRead"]}}"#);
+
+        assert_eq!(limits.scope(&[Role::UserEditor]), vec![Scope::UserRead]);
+        assert_eq!(
+            limits.scope(&[Role::UserEditor, Role::ClientEditor]),
+            vec![Scope::UserRead, Scope::ClientRead, Scope::ClientWrite]
+        );
+        assert_eq!(
+            SessionLimits::default().scope(&[Role::UserEditor]),
+            Scope::from_roles([Role::UserEditor])
+        );
+    }
+
+    #[test]
+    fn rejects_roles_outside_schedule() {
+        let limits = parse(
+            r#"{"clientEditor":{"schedule":{"days":["Mon"],"start":"08:00:00","end":"18:00:00"}}}"#,
+        );
+        // Monday, 2022-06-06
+        let monday = Utc.ymd(2022, 6, 6).and_hms(10, 0, 0);
+        let roles = [Role::UserEditor, Role::ClientEditor];
+
+        assert_eq!(limits.outside_schedule(&roles, monday), None);
+        assert_eq!(
+            limits.outside_schedule(&roles, monday + Duration::days(1)),
+            Some(&Role::ClientEditor)
+        );
+        assert_eq!(
+            limits.outside_schedule(&[Role::UserEditor], monday + Duration::days(1)),
+            None
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Passwordless logins with a link sent to the verified address of the user.
+//!
+//! The link carries a short-lived token, which is exchanged for a session once. Its ID is
+//! added to the denylist on use, so that a second use fails.
+
+use crate::{
+    authentication::token::{TokenClaims, TokenConfig, TokenType},
+    mail,
+};
+
+use std::collections::HashMap;
+
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+/// Login method of sessions from a login link
+pub const EMAIL_LOGIN: &str = "email";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginLinkClaims {
+    pub aud: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    pub exp: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub iat: DateTime<Utc>,
+    pub sub: String,
+    pub jti: String,
+    /// Address the link was sent to, it is invalid once the address changed
+    pub email: String,
+    token_type: TokenType,
+}
+
+impl LoginLinkClaims {
+    pub const DEFAULT_EXP_MIN: i64 = 15;
+
+    pub fn new<A>(aud: A, sub: &str, email: &str, now: DateTime<Utc>) -> Self
+    where
+        A: IntoIterator<Item = String>,
+    {
+        Self {
+            aud: aud.into_iter().collect(),
+            exp: now + Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
+            sub: sub.into(),
+            jti: ObjectId::new().to_hex(),
+            email: email.into(),
+            token_type: Self::TOKEN_TYPE,
+        }
+    }
+}
+
+impl TokenClaims for LoginLinkClaims {
+    const TOKEN_TYPE: TokenType = TokenType::LoginLink;
+
+    fn get_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    fn subject(&self) -> Option<&str> {
+        Some(&self.sub)
+    }
+}
+
+pub async fn send_login_mail(
+    addr: &str,
+    user_id: &str,
+    client: mail::Client,
+    config: TokenConfig,
+) -> crate::Result<()> {
+    let audience = config.validation.aud.clone().unwrap();
+    let claims = LoginLinkClaims::new(audience, user_id, addr, config.clock.now());
+
+    let token = claims.encode(&config)?;
+
+    const TEMPLATE_NAME: &str = "identity.action.login";
+    const SUBJECT: &str = "Sign in";
+
+    let mut vars = HashMap::with_capacity(1);
+    vars.insert("token".to_string(), token);
+
+    client
+        .send_template(addr, SUBJECT, TEMPLATE_NAME, vars)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::authentication::token::TokenError;
+
+    #[test]
+    fn link_is_short_lived() {
+        let config = TokenConfig::from_secret(b"secret", ["test"]);
+        let issued = Utc::now() - Duration::minutes(LoginLinkClaims::DEFAULT_EXP_MIN + 1);
+
+        let claims = LoginLinkClaims::new(["test".to_string()], "user", "a@b.c", issued);
+        let token = claims.encode(&config).unwrap();
+
+        assert!(matches!(
+            config.decode::<LoginLinkClaims>(&token).unwrap_err(),
+            TokenError::Expired
+        ));
+
+        let claims = LoginLinkClaims::new(["test".to_string()], "user", "a@b.c", Utc::now());
+        let token = claims.encode(&config).unwrap();
+        let decoded = config.decode::<LoginLinkClaims>(&token).unwrap();
+        assert_eq!(decoded.claims.jti, claims.jti);
+    }
+}
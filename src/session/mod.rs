@@ -1,19 +1,42 @@
+mod active;
+mod fallback;
 mod handler;
+mod limits;
+mod link;
+mod policy;
+mod refresh;
+mod revocation;
 mod routes;
 
 use crate::{
-    authentication::token::{TokenClaims, TokenType},
-    error,
+    audit::{AuditEventDocument, SecurityEvent},
+    authentication::{
+        token::{TokenClaims, TokenConfig, TokenError, TokenType},
+        AuthenticationError,
+    },
+    database::Database,
+    epoch,
+    error::{self, Error},
+    extract::ClientInfo,
     model::Status,
-    user::Role,
+    user::{AccountFlag, MfaClaims, MfaError, Role, UserDocument, UserError},
 };
 
+use axum::async_trait;
 use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
 use hyper::StatusCode;
+use mongodb::bson::{doc, oid::ObjectId};
 use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
 
-pub use handler::SessionResponse;
-pub use routes::routes;
+pub use crate::models::session::SessionResponse;
+pub use active::{ActiveSessionDocument, ActiveSessionResponse};
+pub use fallback::{Fallback, FallbackMethod, RECOVERY_CODE_LOGIN};
+pub use handler::{list_sessions, revoke_session};
+pub use limits::{de_limits as de_session_limits, SessionLimits};
+pub use link::{send_login_mail, LoginLinkClaims, EMAIL_LOGIN};
+pub use policy::{Access, Resource};
+pub use routes::{login_routes, routes};
 
 #[derive(Debug, thiserror::Error)]
 pub enum SessionError {
@@ -23,6 +46,42 @@ pub enum SessionError {
     NotAuthorized(String),
     #[error("login is required")]
     LoginRequired,
+    #[error("challenge required: {0}")]
+    ChallengeRequired(Challenge),
+    #[error("refresh token is invalid")]
+    InvalidRefreshToken,
+    #[error("refresh token was already used")]
+    RefreshTokenReused,
+    #[error("token has no ID and can not be revoked")]
+    MissingTokenId,
+    #[error("too many failed logins, try again later")]
+    Locked,
+    #[error("login link is invalid or was already used")]
+    InvalidLoginLink,
+    #[error("access is not allowed at this time")]
+    OutsideSchedule,
+    #[error("login method is only available while an SSO provider is unavailable")]
+    FallbackUnavailable,
+    #[error("user is banned until {0}")]
+    Banned(DateTime<Utc>),
+    #[error("session not found")]
+    NotFound,
+}
+
+/// Action the user has to complete before a session is issued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Challenge {
+    PasswordReset,
+    Relink,
+}
+
+impl std::fmt::Display for Challenge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Challenge::PasswordReset => write!(f, "passwordReset"),
+            Challenge::Relink => write!(f, "relink"),
+        }
+    }
 }
 
 impl error::ErrorResponse for SessionError {
@@ -30,8 +89,19 @@ impl error::ErrorResponse for SessionError {
 
     fn status_code(&self) -> StatusCode {
         match self {
-            SessionError::BadCredentials | SessionError::LoginRequired => StatusCode::UNAUTHORIZED,
-            SessionError::NotAuthorized(_) => StatusCode::FORBIDDEN,
+            SessionError::BadCredentials
+            | SessionError::LoginRequired
+            | SessionError::InvalidRefreshToken
+            | SessionError::RefreshTokenReused
+            | SessionError::InvalidLoginLink => StatusCode::UNAUTHORIZED,
+            SessionError::NotAuthorized(_)
+            | SessionError::ChallengeRequired(_)
+            | SessionError::OutsideSchedule
+            | SessionError::FallbackUnavailable
+            | SessionError::Banned(_) => StatusCode::FORBIDDEN,
+            SessionError::MissingTokenId => StatusCode::BAD_REQUEST,
+            SessionError::NotFound => StatusCode::NOT_FOUND,
+            SessionError::Locked => StatusCode::TOO_MANY_REQUESTS,
         }
     }
 
@@ -40,6 +110,179 @@ impl error::ErrorResponse for SessionError {
     }
 }
 
+/// Login method of password based sessions
+pub const PASSWORD_LOGIN: &str = "password";
+
+/// Issues a new session token for the user and records the session.
+///
+/// Fails with a challenge if the account has to complete an action first. Users with
+/// two-factor authentication get a challenge token instead, see [`MfaClaims`].
+/// Rejects users that were locked or banned by an admin
+pub fn check_access(user: &UserDocument, now: DateTime<Utc>) -> Result<(), SessionError> {
+    if !user.can_login {
+        return Err(SessionError::NotAuthorized(
+            "user is not authorized to log in".to_string(),
+        ));
+    }
+    match user.banned_until {
+        Some(v) if user.is_banned(now) => Err(SessionError::Banned(v.to_chrono())),
+        _ => Ok(()),
+    }
+}
+
+pub async fn issue_session(
+    db: &Database,
+    config: &TokenConfig,
+    user: &UserDocument,
+    client: &ClientInfo,
+    method: &str,
+) -> crate::Result<SessionResponse> {
+    check_access(user, config.clock.now())?;
+    if user.flags.contains(&AccountFlag::PasswordResetRequired) {
+        return Err(SessionError::ChallengeRequired(Challenge::PasswordReset).into());
+    }
+    if user.flags.contains(&AccountFlag::RelinkRequired) {
+        if method == PASSWORD_LOGIN || method == EMAIL_LOGIN {
+            return Err(SessionError::ChallengeRequired(Challenge::Relink).into());
+        }
+
+        // The relink is still due once the provider is available again
+        if !fallback::is_fallback(method) {
+            db.remove_user_flag(user.id, AccountFlag::RelinkRequired)
+                .await?;
+        }
+    }
+
+    if user.mfa.as_ref().map_or(false, |m| m.enabled) {
+        let audience = config.validation.aud.clone().unwrap();
+        let claims = MfaClaims::new(audience, &user.id.to_hex(), method, config.clock.now());
+
+        return Err(MfaError::Required {
+            token: claims.encode(config)?,
+            expires_at: claims.exp,
+        }
+        .into());
+    }
+
+    start_session(db, config, user, client, method).await
+}
+
+/// Issues a session for a fallback login that replaces the second factor as well, e.g. a
+/// recovery code
+pub async fn issue_fallback_session(
+    db: &Database,
+    config: &TokenConfig,
+    user: &UserDocument,
+    client: &ClientInfo,
+    method: &str,
+) -> crate::Result<SessionResponse> {
+    if user.flags.contains(&AccountFlag::PasswordResetRequired) {
+        return Err(SessionError::ChallengeRequired(Challenge::PasswordReset).into());
+    }
+
+    warn!(user = %user.id, method, "fallback login during SSO outage");
+
+    start_session(db, config, user, client, method).await
+}
+
+/// Rejects users with a role whose access schedule doesn't allow access now, the rejection
+/// is recorded as security event
+pub async fn check_schedule(
+    db: &Database,
+    config: &TokenConfig,
+    user: &UserDocument,
+    client: &ClientInfo,
+) -> crate::Result<()> {
+    let role = match config
+        .session_limits
+        .outside_schedule(&user.roles, config.clock.now())
+    {
+        Some(r) => r.clone(),
+        None => return Ok(()),
+    };
+
+    warn!(user = %user.id, role = ?role, "access outside of schedule rejected");
+
+    let event = SecurityEvent::OutsideSchedule {
+        role: Some(role),
+        client: None,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user.id, event, client))
+        .await?;
+
+    Err(SessionError::OutsideSchedule.into())
+}
+
+/// Issues the session once all challenges are completed
+async fn start_session(
+    db: &Database,
+    config: &TokenConfig,
+    user: &UserDocument,
+    client: &ClientInfo,
+    method: &str,
+) -> crate::Result<SessionResponse> {
+    check_access(user, config.clock.now())?;
+    check_schedule(db, config, user, client).await?;
+    #[cfg(feature = "hooks")]
+    crate::hooks::post_login(user, method).await?;
+
+    let mut claims = SessionClaims::issue_for_user(config, user, config.clock.now()).await?;
+    claims.fallback = fallback::is_fallback(method);
+
+    let token = claims.encode(config)?;
+    let mut session = ActiveSessionDocument::new(user.id, &claims, method, client);
+    // Fallback sessions end with the token, the next login goes through the provider again
+    let refresh_token = if claims.fallback {
+        None
+    } else {
+        let lifetime = config.session_limits.session_lifetime(&user.roles);
+        let (token, refresh) = db
+            .issue_refresh_token(user.id, None, claims.iat, lifetime)
+            .await?;
+        session = session.with_family(refresh.family, refresh.expires_at);
+        Some(token)
+    };
+    db.insert_session(&session).await?;
+
+    let response = SessionResponse {
+        user: user.id.to_hex(),
+        token,
+        refresh_token,
+        expires_at: claims.exp,
+    };
+
+    record_login(
+        db.clone(),
+        user.id,
+        config.region.clone(),
+        method.to_string(),
+        client.clone(),
+    );
+
+    Ok(response)
+}
+
+/// Records the last sessions and the login event of the user after the response, the session
+/// doesn't depend on them
+fn record_login(
+    db: Database,
+    user: ObjectId,
+    region: Option<String>,
+    method: String,
+    client: ClientInfo,
+) {
+    crate::utils::spawn_named("login-records", async move {
+        let (sessions, event) = tokio::join!(
+            db.set_user_session(user, region.as_deref()),
+            db.record_login(user, &method, &client)
+        );
+
+        for e in [sessions.err(), event.err()].into_iter().flatten() {
+            error!(user = %user, error = %e, "login can not be recorded");
+        }
+    });
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SessionClaims {
@@ -49,50 +292,158 @@ pub struct SessionClaims {
     #[serde(with = "ts_seconds")]
     pub iat: DateTime<Utc>,
     pub sub: String,
+    /// Only tokens issued before the denylist have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
     #[serde(default)]
     pub scope: Vec<Scope>,
+    /// Issued by a fallback login during an SSO outage
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fallback: bool,
+    /// Token epoch of the deployment at issuance
+    #[serde(default, skip_serializing_if = "epoch::is_initial")]
+    pub epoch: u32,
+    /// Token epoch of the user at issuance
+    #[serde(default, skip_serializing_if = "epoch::is_initial")]
+    pub user_epoch: u32,
     token_type: TokenType,
+    /// Claims added by hooks of the deployment
+    #[cfg(feature = "hooks")]
+    #[serde(flatten)]
+    pub custom: crate::hooks::Claims,
 }
 
 impl SessionClaims {
     pub const DEFAULT_EXP_MIN: i64 = 60;
 
-    pub fn new<A>(aud: A, sub: &str) -> Self
+    pub fn new<A>(aud: A, sub: &str, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
     {
         Self {
             aud: aud.into_iter().collect(),
-            exp: Utc::now() + Duration::minutes(Self::DEFAULT_EXP_MIN),
-            iat: Utc::now(),
+            exp: now + Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
             sub: sub.into(),
+            jti: Some(ObjectId::new().to_hex()),
             scope: Vec::default(),
+            fallback: false,
+            epoch: 0,
+            user_epoch: 0,
             token_type: Self::TOKEN_TYPE,
+            #[cfg(feature = "hooks")]
+            custom: Default::default(),
         }
     }
 
-    pub fn with_scope<A, S>(aud: A, sub: &str, scope: S) -> Self
+    pub fn with_scope<A, S>(aud: A, sub: &str, scope: S, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
         S: IntoIterator<Item = Scope>,
     {
-        let mut claims = Self::new(aud, sub);
+        let mut claims = Self::new(aud, sub, now);
         claims.scope = scope.into_iter().collect();
 
         claims
     }
 
+    /// Session of the user with the scope of its roles, within the configured limits
+    pub fn for_user(config: &TokenConfig, user: &UserDocument, now: DateTime<Utc>) -> Self {
+        let audience = config.validation.aud.clone().unwrap();
+        let limits = &config.session_limits;
+
+        let mut claims =
+            Self::with_scope(audience, &user.id.to_hex(), limits.scope(&user.roles), now);
+        claims.set_expiration(now + limits.token_lifetime(&user.roles));
+        claims.epoch = config.epoch.current();
+        claims.user_epoch = user.token_epoch;
+
+        claims
+    }
+
+    /// Session of the user like [`Self::for_user`], with the claims of the hooks
+    pub async fn issue_for_user(
+        config: &TokenConfig,
+        user: &UserDocument,
+        now: DateTime<Utc>,
+    ) -> crate::Result<Self> {
+        #[allow(unused_mut)]
+        let mut claims = Self::for_user(config, user, now);
+        #[cfg(feature = "hooks")]
+        {
+            claims.custom = crate::hooks::pre_issuance(user).await?;
+        }
+
+        Ok(claims)
+    }
+
     pub fn set_expiration(&mut self, date: DateTime<Utc>) {
         self.exp = date;
     }
+
+    /// Checks if the session may access the resources of all users
+    pub fn is_global(&self, resource: Resource, access: Access) -> bool {
+        policy::is_global(&self.scope, resource, access)
+    }
+
+    /// Checks if the session has write access to all resources
+    pub fn is_admin(&self) -> bool {
+        [Resource::User, Resource::Client, Resource::Service]
+            .into_iter()
+            .all(|r| self.is_global(r, Access::Write))
+    }
+
+    /// Checks if the session may access a resource owned by `owner`
+    pub fn is_permitted(&self, resource: Resource, access: Access, owner: &str) -> bool {
+        policy::is_permitted(&self.scope, &self.sub, resource, access, owner)
+    }
 }
 
+#[async_trait]
 impl TokenClaims for SessionClaims {
     const TOKEN_TYPE: TokenType = TokenType::Session;
 
     fn get_type(&self) -> &TokenType {
         &self.token_type
     }
+
+    fn subject(&self) -> Option<&str> {
+        Some(&self.sub)
+    }
+
+    async fn validate(
+        &self,
+        db: &Database,
+        config: &TokenConfig,
+        client: &ClientInfo,
+    ) -> crate::Result<()> {
+        let id = ObjectId::parse_str(&self.sub)
+            .map_err(|_| AuthenticationError::from(TokenError::Invalid))?;
+        let user = match db.get_user(doc! { "_id": id }).await {
+            Ok(user) => user,
+            Err(Error::User(UserError::NotFound)) => {
+                return Err(AuthenticationError::from(TokenError::Invalid).into())
+            }
+            Err(e) => return Err(e),
+        };
+
+        if matches!(user.sessions_valid_after, Some(v) if self.iat <= v.to_chrono()) {
+            return Err(AuthenticationError::from(TokenError::Revoked).into());
+        }
+        if !config.epoch.is_valid(self.epoch) || self.user_epoch < user.token_epoch {
+            return Err(AuthenticationError::from(TokenError::Revoked).into());
+        }
+        if check_access(&user, config.clock.now()).is_err() {
+            return Err(AuthenticationError::from(TokenError::Revoked).into());
+        }
+        if let Some(jti) = &self.jti {
+            if db.is_token_revoked(jti, config.clock.now()).await? {
+                return Err(AuthenticationError::from(TokenError::Revoked).into());
+            }
+        }
+
+        check_schedule(db, config, &user, client).await
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -138,3 +489,64 @@ impl From<Role> for Vec<Scope> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::MockClock;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn session_expires_relative_to_clock() {
+        let clock = MockClock::new(Utc::now());
+        let config =
+            TokenConfig::from_secret(b"secret", ["test"]).with_clock(Arc::new(clock.clone()));
+
+        clock.set(Utc::now() - Duration::minutes(SessionClaims::DEFAULT_EXP_MIN + 1));
+
+        let claims = SessionClaims::new(["test".to_string()], "user", config.clock.now());
+        let token = claims.encode(&config).unwrap();
+
+        let err = config.decode::<SessionClaims>(&token).unwrap_err();
+
+        assert!(matches!(err, TokenError::Expired));
+    }
+
+    #[test]
+    fn banned_until_end() {
+        let now = Utc::now();
+        let user = UserDocument {
+            banned_until: Some(mongodb::bson::DateTime::from_chrono(
+                now + Duration::hours(1),
+            )),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            check_access(&user, now),
+            Err(SessionError::Banned(_))
+        ));
+        assert!(check_access(&user, now + Duration::hours(2)).is_ok());
+
+        let user = UserDocument {
+            can_login: false,
+            ..Default::default()
+        };
+        assert!(check_access(&user, now).is_err());
+    }
+
+    #[test]
+    fn session_has_unique_id() {
+        let config = TokenConfig::from_secret(b"secret", ["test"]);
+
+        let a = SessionClaims::new(["test".to_string()], "user", Utc::now());
+        let b = SessionClaims::new(["test".to_string()], "user", Utc::now());
+        assert_ne!(a.jti, b.jti);
+
+        let token = a.encode(&config).unwrap();
+        let decoded = config.decode::<SessionClaims>(&token).unwrap();
+        assert_eq!(decoded.claims.jti, a.jti);
+    }
+}
@@ -0,0 +1,205 @@
+use super::Scope;
+
+use serde::{Deserialize, Serialize};
+
+/// Kind of resource a session acts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    User,
+    Client,
+    Service,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Resource {
+    /// Scope that grants access to the resources of all users
+    pub const fn scope(self, access: Access) -> Scope {
+        match (self, access) {
+            (Resource::User, Access::Read) => Scope::UserRead,
+            (Resource::User, Access::Write) => Scope::UserWrite,
+            (Resource::Client, Access::Read) => Scope::ClientRead,
+            (Resource::Client, Access::Write) => Scope::ClientWrite,
+            (Resource::Service, Access::Read) => Scope::ServiceRead,
+            (Resource::Service, Access::Write) => Scope::ServiceWrite,
+        }
+    }
+
+    /// Services have no owner and can only be accessed with the matching scope
+    const fn has_owner(self) -> bool {
+        !matches!(self, Resource::Service)
+    }
+}
+
+/// Checks if the scope grants access to the resources of all users
+pub fn is_global(scope: &[Scope], resource: Resource, access: Access) -> bool {
+    scope.contains(&resource.scope(access))
+}
+
+/// Checks if a session of `sub` may access a resource owned by `owner`.
+///
+/// Without the matching scope only the owner itself has access.
+pub fn is_permitted(
+    scope: &[Scope],
+    sub: &str,
+    resource: Resource,
+    access: Access,
+    owner: &str,
+) -> bool {
+    is_global(scope, resource, access) || (resource.has_owner() && sub == owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::user::Role;
+
+    use proptest::{collection::vec, prelude::*, sample::select};
+
+    const RESOURCES: [Resource; 3] = [Resource::User, Resource::Client, Resource::Service];
+    const ROLES: [Role; 6] = [
+        Role::UserEditor,
+        Role::UserViewer,
+        Role::ClientEditor,
+        Role::ClientViewer,
+        Role::ServiceEditor,
+        Role::ServiceViewer,
+    ];
+
+    fn resource() -> impl Strategy<Value = Resource> {
+        select(&RESOURCES[..])
+    }
+
+    fn access() -> impl Strategy<Value = Access> {
+        select(&[Access::Read, Access::Write][..])
+    }
+
+    fn roles() -> impl Strategy<Value = Vec<Role>> {
+        vec(select(&ROLES[..]), 0..8)
+    }
+
+    /// Small id space so that subject and owner collide regularly
+    fn id() -> impl Strategy<Value = &'static str> {
+        select(&["a", "b", "c"][..])
+    }
+
+    proptest! {
+        #[test]
+        fn client_write_required_for_foreign_clients(roles in roles(), sub in id(), owner in id()) {
+            let scope = Scope::from_roles(roles);
+
+            prop_assert!(
+                scope.contains(&Scope::ClientWrite)
+                    || sub == owner
+                    || !is_permitted(&scope, sub, Resource::Client, Access::Write, owner)
+            );
+        }
+
+        #[test]
+        fn owner_always_permitted(
+            roles in roles(),
+            sub in id(),
+            resource in resource(),
+            access in access(),
+        ) {
+            let scope = Scope::from_roles(roles);
+
+            prop_assert!(
+                resource == Resource::Service || is_permitted(&scope, sub, resource, access, sub)
+            );
+        }
+
+        #[test]
+        fn services_ignore_ownership(
+            roles in roles(),
+            sub in id(),
+            owner in id(),
+            access in access(),
+        ) {
+            let scope = Scope::from_roles(roles);
+
+            prop_assert_eq!(
+                is_permitted(&scope, sub, Resource::Service, access, owner),
+                is_global(&scope, Resource::Service, access)
+            );
+        }
+
+        #[test]
+        fn no_scope_only_owner(
+            sub in id(),
+            owner in id(),
+            resource in resource(),
+            access in access(),
+        ) {
+            prop_assert_eq!(
+                is_permitted(&[], sub, resource, access, owner),
+                resource != Resource::Service && sub == owner
+            );
+        }
+
+        #[test]
+        fn write_implies_read(roles in roles(), resource in resource()) {
+            let scope = Scope::from_roles(roles);
+
+            prop_assert!(
+                !is_global(&scope, resource, Access::Write)
+                    || is_global(&scope, resource, Access::Read)
+            );
+        }
+
+        #[test]
+        fn more_roles_never_revoke(
+            roles in roles(),
+            extra in roles(),
+            sub in id(),
+            owner in id(),
+            resource in resource(),
+            access in access(),
+        ) {
+            let scope = Scope::from_roles(roles.clone());
+            let wider = Scope::from_roles(roles.into_iter().chain(extra));
+
+            prop_assert!(
+                !is_permitted(&scope, sub, resource, access, owner)
+                    || is_permitted(&wider, sub, resource, access, owner)
+            );
+        }
+
+        #[test]
+        fn scope_independent_of_role_order(roles in roles()) {
+            let mut reversed = roles.clone();
+            reversed.reverse();
+
+            prop_assert_eq!(Scope::from_roles(roles), Scope::from_roles(reversed));
+        }
+    }
+
+    #[test]
+    fn scope_granted_only_by_matching_role() {
+        for role in ROLES {
+            let scope = Scope::from_roles([role.clone()]);
+
+            for resource in RESOURCES {
+                for access in [Access::Read, Access::Write] {
+                    let expected = matches!(
+                        (&role, resource, access),
+                        (Role::UserEditor, Resource::User, _)
+                            | (Role::UserViewer, Resource::User, Access::Read)
+                            | (Role::ClientEditor, Resource::Client, _)
+                            | (Role::ClientViewer, Resource::Client, Access::Read)
+                            | (Role::ServiceEditor, Resource::Service, _)
+                            | (Role::ServiceViewer, Resource::Service, Access::Read)
+                    );
+
+                    assert_eq!(is_global(&scope, resource, access), expected);
+                }
+            }
+        }
+    }
+}
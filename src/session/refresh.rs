@@ -0,0 +1,265 @@
+//! Opaque refresh tokens of sessions.
+//!
+//! Only the hash of a token is stored. Every token can be used once and is rotated on use,
+//! all tokens descending from the same login form a family. Using a token twice means it was
+//! leaked, so the whole family is revoked.
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    database::Database,
+    extract::ClientInfo,
+    Result,
+};
+
+use super::SessionError;
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::bson::{self, doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    /// First token of the login, shared by all rotated tokens
+    pub family: ObjectId,
+    pub hash: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub issued_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub used_at: Option<bson::DateTime>,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+impl RefreshTokenDocument {
+    pub const DEFAULT_EXP_DAYS: i64 = 30;
+
+    /// Without a `lifetime`, every rotation extends the login
+    fn new(
+        user: ObjectId,
+        family: Option<ObjectId>,
+        hash: String,
+        now: DateTime<Utc>,
+        lifetime: Option<Duration>,
+    ) -> Self {
+        let id = ObjectId::new();
+
+        let mut expires_at = now + Duration::days(Self::DEFAULT_EXP_DAYS);
+        if let Some(lifetime) = lifetime {
+            // The family is the id of the first token, created at the login
+            let started = family.map_or(now, |f| f.timestamp().to_chrono());
+            expires_at = expires_at.min(started + lifetime);
+        }
+
+        Self {
+            id,
+            user,
+            family: family.unwrap_or(id),
+            hash,
+            issued_at: now,
+            expires_at,
+            used_at: None,
+            revoked: false,
+        }
+    }
+
+    /// Checks if the token may be exchanged
+    fn check(&self, now: DateTime<Utc>) -> std::result::Result<(), SessionError> {
+        if self.used_at.is_some() {
+            return Err(SessionError::RefreshTokenReused);
+        }
+        if self.revoked || self.expires_at <= now {
+            return Err(SessionError::InvalidRefreshToken);
+        }
+
+        Ok(())
+    }
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+const COLLECTION: &str = "refresh_tokens";
+
+impl Database {
+    /// Issues a new refresh token for the user, continuing the family if given.
+    ///
+    /// The token expires at the latest once the family reaches the `lifetime`.
+    pub async fn issue_refresh_token(
+        &self,
+        user: ObjectId,
+        family: Option<ObjectId>,
+        now: DateTime<Utc>,
+        lifetime: Option<Duration>,
+    ) -> Result<(String, RefreshTokenDocument)> {
+        let token = base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD);
+        let doc = RefreshTokenDocument::new(user, family, hash(&token), now, lifetime);
+
+        self.collection::<RefreshTokenDocument>(COLLECTION)
+            .insert_one(&doc, None)
+            .await?;
+
+        Ok((token, doc))
+    }
+
+    /// Revokes all refresh tokens of the family
+    pub async fn revoke_refresh_family(&self, family: ObjectId) -> Result<()> {
+        self.collection::<RefreshTokenDocument>(COLLECTION)
+            .update_many(
+                doc! { "family": family },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_user_refresh_tokens(&self, user: ObjectId) -> Result<()> {
+        self.collection::<RefreshTokenDocument>(COLLECTION)
+            .update_many(
+                doc! { "user": user, "revoked": false },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn revoke_all_refresh_tokens(&self) -> Result<()> {
+        self.collection::<RefreshTokenDocument>(COLLECTION)
+            .update_many(
+                doc! { "revoked": false },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks the refresh token as used and returns it.
+    ///
+    /// Fails if the token is unknown, expired or revoked. If it was used before, its family is
+    /// revoked.
+    pub async fn use_refresh_token(
+        &self,
+        token: &str,
+        now: DateTime<Utc>,
+        client: &ClientInfo,
+    ) -> Result<RefreshTokenDocument> {
+        let coll = self.collection::<RefreshTokenDocument>(COLLECTION);
+
+        let doc = coll
+            .find_one(doc! { "hash": hash(token) }, None)
+            .await?
+            .ok_or(SessionError::InvalidRefreshToken)?;
+
+        let result = match doc.check(now) {
+            Ok(()) => {
+                // Claimed atomically, a concurrent use counts as reuse
+                let claimed = coll
+                    .find_one_and_update(
+                        doc! { "_id": doc.id, "usedAt": { "$exists": false } },
+                        doc! { "$set": { "usedAt": bson::DateTime::from_chrono(now) } },
+                        None,
+                    )
+                    .await?;
+
+                claimed.map(|_| ()).ok_or(SessionError::RefreshTokenReused)
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => Ok(doc),
+            Err(SessionError::RefreshTokenReused) => {
+                warn!(user = %doc.user, family = %doc.family, "refresh token reused, family revoked");
+
+                self.revoke_refresh_family(doc.family).await?;
+
+                let event =
+                    AuditEventDocument::new(doc.user, SecurityEvent::RefreshTokenReused, client);
+                self.insert_audit_event(&event).await?;
+
+                Err(SessionError::RefreshTokenReused.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_used_token() {
+        let now = Utc::now();
+        let mut doc = RefreshTokenDocument::new(ObjectId::new(), None, hash("token"), now, None);
+        assert!(doc.check(now).is_ok());
+        assert_eq!(doc.family, doc.id);
+
+        doc.used_at = Some(bson::DateTime::from_chrono(now));
+        assert!(matches!(
+            doc.check(now),
+            Err(SessionError::RefreshTokenReused)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_and_revoked_token() {
+        let now = Utc::now();
+        let family = ObjectId::new();
+        let mut doc =
+            RefreshTokenDocument::new(ObjectId::new(), Some(family), hash("token"), now, None);
+        assert_eq!(doc.family, family);
+
+        let later = now + Duration::days(RefreshTokenDocument::DEFAULT_EXP_DAYS);
+        assert!(matches!(
+            doc.check(later),
+            Err(SessionError::InvalidRefreshToken)
+        ));
+
+        doc.revoked = true;
+        assert!(matches!(
+            doc.check(now),
+            Err(SessionError::InvalidRefreshToken)
+        ));
+    }
+
+    #[test]
+    fn lifetime_caps_family() {
+        let now = Utc::now();
+        let first = RefreshTokenDocument::new(
+            ObjectId::new(),
+            None,
+            hash("token"),
+            now,
+            Some(Duration::hours(8)),
+        );
+        assert_eq!(first.expires_at, now + Duration::hours(8));
+
+        // Rotations don't extend the login past the lifetime
+        let later = now + Duration::hours(6);
+        let rotated = RefreshTokenDocument::new(
+            ObjectId::new(),
+            Some(first.family),
+            hash("token"),
+            later,
+            Some(Duration::hours(8)),
+        );
+        assert!(rotated.expires_at <= now + Duration::hours(8));
+        assert!(rotated.check(later).is_ok());
+        assert!(rotated.check(now + Duration::hours(8)).is_err());
+    }
+}
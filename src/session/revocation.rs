@@ -0,0 +1,167 @@
+use crate::{database::Database, Result};
+
+#[cfg(feature = "cache")]
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    error::{ErrorKind, WriteFailure},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cache")]
+use tracing::warn;
+
+/// Session token that was revoked before its expiration
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RevokedTokenDocument {
+    /// ID of the token
+    #[serde(rename = "_id")]
+    id: String,
+    user: ObjectId,
+    /// Expiration of the token, it doesn't need to be denied afterwards
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    expires_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "revoked_tokens";
+
+/// Code of a write error on a duplicate key
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Time a token is cached as not revoked, a revocation the cache missed applies afterwards
+#[cfg(feature = "cache")]
+const NOT_REVOKED_TTL: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "cache")]
+fn revocation_key(jti: &str) -> String {
+    format!("revoked:{}", jti)
+}
+
+impl Database {
+    /// Removes revoked tokens once they are expired
+    pub async fn init_revocations(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        self.collection::<RevokedTokenDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Adds the token to the denylist, revoking it twice is no error
+    pub async fn revoke_token(
+        &self,
+        jti: &str,
+        user: ObjectId,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        self.consume_token(jti, user, expires_at, now).await?;
+
+        Ok(())
+    }
+
+    /// Adds a single-use token to the denylist, returns `false` if it was already used
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    pub async fn consume_token(
+        &self,
+        jti: &str,
+        user: ObjectId,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let doc = RevokedTokenDocument {
+            id: jti.to_string(),
+            user,
+            expires_at,
+        };
+
+        let consumed = match self
+            .collection::<RevokedTokenDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == DUPLICATE_KEY => {
+                    false
+                }
+                _ => return Err(e.into()),
+            },
+        };
+
+        #[cfg(feature = "cache")]
+        self.cache_revocation(jti, expires_at, now).await;
+
+        Ok(consumed)
+    }
+
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    pub async fn is_token_revoked(&self, jti: &str, now: DateTime<Utc>) -> Result<bool> {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.cache() {
+            match cache
+                .get::<bool>(&self.cache_key(&revocation_key(jti)))
+                .await
+            {
+                Ok(Some(revoked)) => return Ok(revoked),
+                Ok(None) => {}
+                Err(e) => warn!(error = %e, "revocation could not be looked up in the cache"),
+            }
+        }
+
+        let doc = self
+            .collection::<RevokedTokenDocument>(COLLECTION)
+            .find_one(doc! { "_id": jti }, None)
+            .await?;
+
+        #[cfg(feature = "cache")]
+        match &doc {
+            Some(doc) => self.cache_revocation(jti, doc.expires_at, now).await,
+            None => self.cache_not_revoked(jti).await,
+        }
+
+        Ok(doc.is_some())
+    }
+
+    #[cfg(feature = "cache")]
+    async fn cache_revocation(&self, jti: &str, expires_at: DateTime<Utc>, now: DateTime<Utc>) {
+        let cache = match self.cache() {
+            Some(v) => v,
+            None => return,
+        };
+        let ttl = match (expires_at - now).to_std() {
+            Ok(v) => v,
+            // Expired tokens are rejected anyway
+            Err(_) => return,
+        };
+
+        let key = self.cache_key(&revocation_key(jti));
+        if let Err(e) = cache.set(&key, &true, ttl).await {
+            warn!(error = %e, "revocation could not be cached");
+        }
+    }
+
+    /// Doesn't replace a revocation cached in the meantime
+    #[cfg(feature = "cache")]
+    async fn cache_not_revoked(&self, jti: &str) {
+        if let Some(cache) = self.cache() {
+            let key = self.cache_key(&revocation_key(jti));
+            if let Err(e) = cache.insert(&key, &false, NOT_REVOKED_TTL).await {
+                warn!(error = %e, "revocation could not be cached");
+            }
+        }
+    }
+}
@@ -4,5 +4,22 @@ use axum::routing::post;
 
 /// Session routes
 pub fn routes() -> axum::Router {
-    axum::Router::new().route("/", post(handler::create).get(handler::refresh))
+    axum::Router::new()
+        .route(
+            "/",
+            post(handler::create)
+                .get(handler::refresh)
+                .delete(handler::logout),
+        )
+        .route("/refresh", post(handler::exchange))
+        .route("/mfa", post(handler::verify_mfa))
+}
+
+/// Login routes, by credential type
+pub fn login_routes() -> axum::Router {
+    axum::Router::new()
+        .route("/", post(handler::create))
+        .route("/email", post(handler::request_login_link))
+        .route("/email/callback", post(handler::login_with_link))
+        .route("/recovery", post(handler::login_with_recovery_code))
 }
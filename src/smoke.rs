@@ -0,0 +1,357 @@
+//! Smoke test against a running instance, meant as a release gate.
+//!
+//! `identity-server smoke --url https://id.example.com [options]`
+//!
+//! - `--audience <aud>` and `--user <id>`: signs a session token with the JWT secret of the
+//!   instance and checks that it is accepted. The secret is read from `--key-file <path>` or
+//!   `IDENTITY_JWT_SECRET`, so that it doesn't show up in the process list
+//! - `--sso <provider>`: starts a login at the provider, e.g. `github` or `oidc/staging`. The
+//!   login through `sandbox` is completed, as it needs no browser
+//!
+//! Exits with an error if a check fails.
+
+use crate::{
+    authentication::token::{TokenClaims, TokenConfig},
+    error::Error,
+    http::HttpClient,
+    session::{SessionClaims, SessionResponse},
+    Result,
+};
+
+use std::{env, fs, path::PathBuf, process, time::Instant};
+
+use chrono::Utc;
+use http::{
+    header::{COOKIE, LOCATION, SET_COOKIE},
+    StatusCode,
+};
+use reqwest::Url;
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    url: Option<Url>,
+    key_file: Option<PathBuf>,
+    audience: Option<String>,
+    user: Option<String>,
+    sso: Option<String>,
+}
+
+impl Options {
+    fn parse<I>(args: I) -> std::result::Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut opts = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} requires a value", arg));
+
+            match arg.as_str() {
+                "--url" => {
+                    let url = value()?;
+                    // Joined paths must not replace the last segment
+                    let url = format!("{}/", url.trim_end_matches('/'));
+                    opts.url =
+                        Some(Url::parse(&url).map_err(|e| format!("URL is invalid: {}", e))?);
+                }
+                "--key-file" => opts.key_file = Some(value()?.into()),
+                "--audience" => opts.audience = Some(value()?),
+                "--user" => opts.user = Some(value()?),
+                "--sso" => opts.sso = Some(value()?),
+                _ => return Err(format!("unknown option \"{}\"", arg)),
+            }
+        }
+
+        if opts.url.is_none() {
+            return Err("--url is required".to_string());
+        }
+        if opts.key_file.is_some() && (opts.audience.is_none() || opts.user.is_none()) {
+            return Err("--key-file requires --audience and --user".to_string());
+        }
+
+        Ok(opts)
+    }
+}
+
+enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(&'static str),
+}
+
+/// Runs the checks and prints the results, exits with an error if one failed
+pub async fn run<I>(args: I) -> Result<()>
+where
+    I: IntoIterator<Item = String>,
+{
+    let opts = Options::parse(args).map_err(Error::Config)?;
+    let url = opts.url.clone().unwrap();
+    let key = match &opts.key_file {
+        Some(path) => {
+            let key = fs::read_to_string(path).map_err(|e| {
+                Error::Config(format!(
+                    "key file {} can not be read: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            Some(key.trim_end_matches(['\n', '\r']).to_string())
+        }
+        None => env::var("IDENTITY_JWT_SECRET").ok(),
+    };
+    let client = HttpClient::allow_http();
+
+    let mut passed = true;
+    println!("{:<18} {:>8}  result", "check", "time");
+
+    macro_rules! check {
+        ($name:expr, $check:expr) => {
+            let start = Instant::now();
+            let outcome = $check.await;
+            let elapsed = start.elapsed();
+
+            let result = match outcome {
+                Outcome::Passed => "ok".to_string(),
+                Outcome::Failed(e) => {
+                    passed = false;
+                    format!("FAILED: {}", e)
+                }
+                Outcome::Skipped(reason) => format!("skipped: {}", reason),
+            };
+
+            println!("{:<18} {:>8.2?}  {}", $name, elapsed, result);
+        };
+    }
+
+    check!("reachable", reachable(&client, &url));
+    check!("jwks", jwks(&client, &url));
+    check!("foreign token", foreign_token(&client, &url));
+    check!(
+        "token validation",
+        token_validation(&client, &url, key.as_deref(), &opts)
+    );
+    check!("sso authorize", sso_authorize(&client, &url, &opts));
+    check!("sso callback", sso_callback(&client, &url, &opts));
+
+    if !passed {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn expect_status(res: reqwest::Result<reqwest::Response>, expected: StatusCode) -> Outcome {
+    match res {
+        Ok(res) if res.status() == expected => Outcome::Passed,
+        Ok(res) => Outcome::Failed(format!("status {}, expected {}", res.status(), expected)),
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// The instance answers unauthenticated requests with its own error
+async fn reachable(client: &HttpClient, url: &Url) -> Outcome {
+    let res = client.get(url.join("v1/user").unwrap()).send().await;
+
+    expect_status(res, StatusCode::UNAUTHORIZED)
+}
+
+async fn jwks(client: &HttpClient, url: &Url) -> Outcome {
+    let res = match client
+        .get(url.join(".well-known/jwks.json").unwrap())
+        .send()
+        .await
+    {
+        Ok(res) if res.status() == StatusCode::NOT_FOUND => {
+            return Outcome::Skipped("not served by this instance")
+        }
+        Ok(res) => res,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    match res.error_for_status() {
+        Ok(res) => match res.json::<serde_json::Value>().await {
+            Ok(body) if body["keys"].is_array() => Outcome::Passed,
+            Ok(_) => Outcome::Failed("key set has no keys".to_string()),
+            Err(e) => Outcome::Failed(e.to_string()),
+        },
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+fn session_token(key: &[u8], audience: &str, user: &str) -> String {
+    let config = TokenConfig::from_secret(key, [audience]);
+    let claims = SessionClaims::new([audience.to_string()], user, Utc::now());
+
+    claims.encode(&config).unwrap()
+}
+
+/// Tokens signed with an unknown key are rejected
+async fn foreign_token(client: &HttpClient, url: &Url) -> Outcome {
+    let key = rand::random::<[u8; 32]>();
+    let token = session_token(&key, "smoke", "000000000000000000000000");
+
+    let res = client
+        .get(url.join("v1/user/000000000000000000000000").unwrap())
+        .bearer_auth(token)
+        .send()
+        .await;
+
+    expect_status(res, StatusCode::UNAUTHORIZED)
+}
+
+async fn token_validation(
+    client: &HttpClient,
+    url: &Url,
+    key: Option<&str>,
+    opts: &Options,
+) -> Outcome {
+    let (key, audience, user) = match (key, &opts.audience, &opts.user) {
+        (Some(k), Some(a), Some(u)) => (k, a, u),
+        (None, Some(_), Some(_)) => return Outcome::Skipped("no JWT secret given"),
+        _ => return Outcome::Skipped("no --audience and --user given"),
+    };
+
+    let token = session_token(key.as_bytes(), audience, user);
+
+    let res = client
+        .get(url.join(&format!("v1/user/{}", user)).unwrap())
+        .bearer_auth(token)
+        .send()
+        .await;
+
+    expect_status(res, StatusCode::OK)
+}
+
+/// Account the sandbox provider signs in
+const SANDBOX_EMAIL: &str = "smoke@example.com";
+
+/// Requests the redirect to the provider, with the account to sign in for the sandbox
+async fn authorize(
+    client: &HttpClient,
+    url: &Url,
+    provider: &str,
+) -> reqwest::Result<reqwest::Response> {
+    let mut req = client.get(url.join(&format!("v1/sso/{}/authorize", provider)).unwrap());
+    if provider == "sandbox" {
+        req = req.query(&[("email", SANDBOX_EMAIL)]);
+    }
+
+    req.send().await
+}
+
+/// Starts a login, only the sandbox provider continues without a browser
+async fn sso_authorize(client: &HttpClient, url: &Url, opts: &Options) -> Outcome {
+    let provider = match &opts.sso {
+        Some(p) => p,
+        None => return Outcome::Skipped("no --sso given"),
+    };
+
+    match authorize(client, url, provider).await {
+        Ok(res) if res.status().is_redirection() => {
+            if !res.headers().contains_key(LOCATION) {
+                Outcome::Failed("redirect has no location".to_string())
+            } else if !res.headers().contains_key(SET_COOKIE) {
+                Outcome::Failed("state cookie is missing".to_string())
+            } else {
+                Outcome::Passed
+            }
+        }
+        Ok(res) => Outcome::Failed(format!("status {}, expected a redirect", res.status())),
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+/// Completes a login of the sandbox provider and uses the issued session
+async fn sso_callback(client: &HttpClient, url: &Url, opts: &Options) -> Outcome {
+    match opts.sso.as_deref() {
+        Some("sandbox") => {}
+        Some(_) => return Outcome::Skipped("needs a browser at the provider"),
+        None => return Outcome::Skipped("no --sso given"),
+    }
+
+    let res = match authorize(client, url, "sandbox").await {
+        Ok(res) => res,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let (location, cookie) = match (res.headers().get(LOCATION), res.headers().get(SET_COOKIE)) {
+        (Some(location), Some(cookie)) => (location, cookie),
+        _ => return Outcome::Failed("redirect or state cookie is missing".to_string()),
+    };
+    let callback = match location.to_str().ok().and_then(|l| url.join(l).ok()) {
+        Some(callback) => callback,
+        None => return Outcome::Failed("redirect location is invalid".to_string()),
+    };
+    // The cookie is sent back without its attributes
+    let cookie = cookie.to_str().unwrap_or_default();
+    let cookie = cookie.split(';').next().unwrap_or_default();
+
+    let res = match client.get(callback).header(COOKIE, cookie).send().await {
+        Ok(res) if res.status() == StatusCode::CREATED => res,
+        Ok(res) => return Outcome::Failed(format!("callback answered {}", res.status())),
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+    let session = match res.json::<SessionResponse>().await {
+        Ok(session) => session,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    let res = client
+        .get(url.join(&format!("v1/user/{}", session.user)).unwrap())
+        .bearer_auth(session.token)
+        .send()
+        .await;
+
+    expect_status(res, StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn parses_options() {
+        let opts = Options::parse(args(&[
+            "--url",
+            "https://id.example.com/base",
+            "--sso",
+            "github",
+        ]))
+        .unwrap();
+
+        assert_eq!(opts.url.unwrap().as_str(), "https://id.example.com/base/");
+        assert_eq!(opts.sso.as_deref(), Some("github"));
+
+        let opts = Options::parse(args(&[
+            "--url",
+            "https://id.example.com",
+            "--key-file",
+            "/run/secrets/jwt",
+            "--audience",
+            "smoke",
+            "--user",
+            "000000000000000000000000",
+        ]))
+        .unwrap();
+
+        assert_eq!(opts.key_file, Some(PathBuf::from("/run/secrets/jwt")));
+    }
+
+    #[test]
+    fn rejects_incomplete_options() {
+        assert!(Options::parse(args(&["--sso", "github"])).is_err());
+        assert!(Options::parse(args(&["--url"])).is_err());
+        assert!(Options::parse(args(&[
+            "--url",
+            "https://id.example.com",
+            "--key-file",
+            "/run/secrets/jwt"
+        ]))
+        .is_err());
+        assert!(Options::parse(args(&["--url", "https://id.example.com", "--verbose"])).is_err());
+    }
+}
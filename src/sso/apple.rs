@@ -0,0 +1,403 @@
+use crate::{
+    authentication::{
+        jwks::JwksCache,
+        token::{TokenConfig, TokenError},
+    },
+    config::GlobalConfig,
+    database::Database,
+    error::{self, Error},
+    extract::ClientInfo,
+    http::{HttpClient, SendTimed},
+    model::{Response, Status},
+    session::{issue_session, SessionResponse},
+    user::{Connection, UserError},
+    utils::crypto::Aead256,
+    Result,
+};
+
+use super::{
+    connect_user, get_or_create_user,
+    state::{exchange_with_state, issue_state},
+    with_provider_token, SsoError,
+};
+
+use std::path::Path;
+
+use axum::{
+    extract::{Extension, Form, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use headers::Cookie;
+use http::{header::SET_COOKIE, StatusCode};
+use hyper::Uri;
+use jsonwebtoken::{Algorithm, EncodingKey, Header, Validation};
+use mongodb::bson::doc;
+use reqwest::IntoUrl;
+use serde::{Deserialize, Deserializer, Serialize};
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppleError {
+    #[error("private key is invalid")]
+    InvalidKey,
+    #[error("token request failed: {0}")]
+    TokenRequest(String),
+}
+
+impl error::ErrorResponse for AppleError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppleError::TokenRequest(_) => StatusCode::UNAUTHORIZED,
+            AppleError::InvalidKey => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct Apple {
+    client_id: String,
+    team_id: String,
+    key_id: String,
+    key: EncodingKey,
+    redirect_uri: Url,
+    keys: JwksCache,
+    client: HttpClient,
+}
+
+impl Apple {
+    const ISSUER: &'static str = "https://appleid.apple.com";
+    const KEYS_URL: &'static str = "https://appleid.apple.com/auth/keys";
+    const TOKEN_URL: &'static str = "https://appleid.apple.com/auth/token";
+
+    /// Lifetime of the generated client secret, Apple allows up to six months
+    const SECRET_EXP_MIN: i64 = 5;
+
+    pub fn new<U>(
+        client_id: String,
+        team_id: String,
+        key_id: String,
+        key_path: &Path,
+        redirect: U,
+        client: HttpClient,
+    ) -> Result<Self>
+    where
+        U: IntoUrl,
+    {
+        let pem = std::fs::read(key_path).map_err(|e| {
+            tracing::error!(error = %e, "error while reading Apple private key");
+            SsoError::from(AppleError::InvalidKey)
+        })?;
+        let key =
+            EncodingKey::from_ec_pem(&pem).map_err(|_| SsoError::from(AppleError::InvalidKey))?;
+        let keys = JwksCache::new(Url::parse(Self::KEYS_URL).unwrap(), client.clone());
+
+        Ok(Self {
+            client_id,
+            team_id,
+            key_id,
+            key,
+            redirect_uri: redirect.into_url()?,
+            keys,
+            client,
+        })
+    }
+
+    /// Generates the client secret, a JWT signed with the private key of the team
+    fn client_secret(&self) -> Result<String> {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(self.key_id.clone());
+
+        let claims = ClientSecretClaims {
+            iss: &self.team_id,
+            iat: Utc::now(),
+            exp: Utc::now() + Duration::minutes(Self::SECRET_EXP_MIN),
+            aud: Self::ISSUER,
+            sub: &self.client_id,
+        };
+
+        let secret = jsonwebtoken::encode(&header, &claims, &self.key).map_err(TokenError::from)?;
+
+        Ok(secret)
+    }
+
+    async fn get_tokens(&self, code: &str) -> Result<TokenResponse> {
+        let client_secret = self.client_secret()?;
+        let form = TokenRequest {
+            client_id: &self.client_id,
+            client_secret: &client_secret,
+            code,
+            grant_type: "authorization_code",
+            redirect_uri: &self.redirect_uri,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<TokenErrorResponse>().await?;
+            return Err(SsoError::from(AppleError::TokenRequest(body.error)).into());
+        }
+
+        let body = res.error_for_status()?.json::<TokenResponse>().await?;
+
+        Ok(body)
+    }
+
+    /// Redeems a stored refresh token, returns the current connection. Apple doesn't rotate
+    /// refresh tokens.
+    pub(super) async fn refresh_connection(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(Connection, Option<String>)> {
+        let client_secret = self.client_secret()?;
+        let form = RefreshRequest {
+            client_id: &self.client_id,
+            client_secret: &client_secret,
+            grant_type: "refresh_token",
+            refresh_token,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<TokenErrorResponse>().await?;
+            return Err(SsoError::from(AppleError::TokenRequest(body.error)).into());
+        }
+
+        let tokens = res.error_for_status()?.json::<TokenResponse>().await?;
+        let claims = self.verify_id_token(&tokens.id_token).await?;
+
+        let connection = Connection::Apple {
+            user_id: claims.sub,
+            private_email: claims.is_private_email,
+        };
+
+        Ok((connection, None))
+    }
+
+    async fn verify_id_token(&self, token: &str) -> Result<IdTokenClaims> {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[Self::ISSUER]);
+
+        self.keys.decode(token, &validation).await
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClientSecretClaims<'a> {
+    iss: &'a str,
+    #[serde(with = "ts_seconds")]
+    iat: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    exp: DateTime<Utc>,
+    aud: &'a str,
+    sub: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    grant_type: &'a str,
+    redirect_uri: &'a Url,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    grant_type: &'a str,
+    refresh_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default, deserialize_with = "de_bool")]
+    email_verified: bool,
+    #[serde(default, deserialize_with = "de_bool")]
+    is_private_email: bool,
+}
+
+/// User information, only sent on the first authorization
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+    name: Option<Name>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Name {
+    first_name: Option<String>,
+    last_name: Option<String>,
+}
+
+impl Name {
+    fn full_name(self) -> Option<String> {
+        let name = [self.first_name, self.last_name]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// Apple sends boolean claims either as boolean or as string
+fn de_bool<'de, D>(d: D) -> std::result::Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Bool {
+        Bool(bool),
+        String(String),
+    }
+
+    match Bool::deserialize(d)? {
+        Bool::Bool(v) => Ok(v),
+        Bool::String(v) => Ok(v == "true"),
+    }
+}
+
+pub(super) async fn authorize(
+    Extension(apple): Extension<Apple>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    let state = issue_state(&config)?;
+
+    let pq = format!(
+        "/auth/authorize?client_id={client_id}&redirect_uri={redirect_uri}&response_type=code&response_mode=form_post&scope={scope}&state={state}",
+        client_id = apple.client_id,
+        redirect_uri = apple.redirect_uri,
+        scope = ["name", "email"].join("%20"),
+        state = state,
+    );
+
+    let uri = Uri::builder()
+        .scheme("https")
+        .authority("appleid.apple.com")
+        .path_and_query(pq)
+        .build()?;
+
+    let mut redirect = Redirect::to(&uri.to_string()).into_response();
+    // The callback is a cross-site form post, so the cookie can't be restricted to same-site requests
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/apple; SameSite=None; Secure; HttpOnly",
+        state, global.base_path
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizedForm {
+    code: String,
+    state: String,
+    user: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn authorized(
+    client: ClientInfo,
+    Form(form): Form<AuthorizedForm>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(apple): Extension<Apple>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(enc): Extension<Aead256>,
+) -> crate::Result<Response<SessionResponse>> {
+    let TokenResponse {
+        id_token,
+        refresh_token,
+    } = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        Some(&form.state),
+        apple.get_tokens(&form.code),
+    )
+    .await?;
+    let claims = apple.verify_id_token(&id_token).await?;
+
+    let name = form
+        .user
+        .and_then(|v| serde_json::from_str::<User>(&v).ok())
+        .and_then(|u| u.name)
+        .and_then(Name::full_name);
+
+    let connection = Connection::Apple {
+        user_id: claims.sub,
+        private_email: claims.is_private_email,
+    };
+
+    // Apple only sends the email address on some logins, the account is matched by its id first
+    let query = doc! { "connections": { "$elemMatch": connection.account_filter() } };
+    let mut doc = match db.get_user(query).await {
+        Ok(doc) => connect_user(&db, doc, connection.clone()).await?,
+        Err(Error::User(UserError::NotFound)) => {
+            let email = match claims.email {
+                Some(addr) if claims.email_verified => addr,
+                _ => return Err(SsoError::EmailInvalid.into()),
+            };
+
+            get_or_create_user(&db, &global, email, connection.clone()).await?
+        }
+        Err(e) => return Err(e),
+    };
+
+    // The name is only sent on the first authorization and has to be persisted right away
+    if let Some(name) = name {
+        if doc.name.is_none() {
+            doc = db.update_user_by_id(doc.id, doc! { "name": name }).await?;
+        }
+    }
+
+    let session = issue_session(&db, &config, &doc, &client, "apple");
+    let response =
+        with_provider_token(&db, &enc, &doc, &connection, refresh_token, session).await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
@@ -0,0 +1,137 @@
+//! Outage detection of SSO providers.
+//!
+//! The circuit of a provider opens after consecutive callbacks failed with a server error,
+//! e.g. because the provider API timed out. It stays open for a while and closes again with
+//! the next successful callback afterwards.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{middleware::Next, response::Response};
+use hyper::Request;
+use tracing::{info, warn};
+
+/// Consecutive failures that open the circuit
+const FAILURE_THRESHOLD: u32 = 5;
+/// Time the circuit stays open at least
+const OPEN_DURATION: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProviderCircuits(Arc<Mutex<HashMap<String, Circuit>>>);
+
+impl ProviderCircuits {
+    pub fn record(&self, provider: &str, success: bool, now: Instant) {
+        let mut circuits = self.0.lock().unwrap();
+        let circuit = circuits.entry(provider.to_string()).or_default();
+
+        if success {
+            if circuit.opened_at.take().is_some() {
+                info!(provider, "SSO provider recovered");
+            }
+            circuit.failures = 0;
+            return;
+        }
+
+        circuit.failures += 1;
+        if circuit.failures >= FAILURE_THRESHOLD && circuit.opened_at.is_none() {
+            warn!(
+                provider,
+                failures = circuit.failures,
+                "SSO provider unavailable"
+            );
+            circuit.opened_at = Some(now);
+        }
+    }
+
+    /// Checks if the circuit of any provider is open
+    pub fn any_open(&self, now: Instant) -> bool {
+        self.0.lock().unwrap().values().any(|c| {
+            c.opened_at
+                .map_or(false, |t| now.saturating_duration_since(t) < OPEN_DURATION)
+        })
+    }
+}
+
+/// Provider of an SSO callback path, e.g. `github` of `/v1/sso/github/authorized`
+pub fn callback_provider(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix("/v1/sso/")?.strip_suffix("/authorized")?;
+
+    match rest.split_once('/') {
+        Some(("oidc", name)) if !name.contains('/') => Some(name),
+        None => Some(rest),
+        _ => None,
+    }
+}
+
+/// Middleware that records the results of SSO callbacks, client errors are not counted
+pub async fn track_outages<B>(
+    req: Request<B>,
+    next: Next<B>,
+    circuits: ProviderCircuits,
+) -> Response {
+    let provider = callback_provider(req.uri().path()).map(ToString::to_string);
+
+    let res = next.run(req).await;
+
+    if let Some(provider) = provider {
+        let status = res.status();
+        if status.is_server_error() {
+            circuits.record(&provider, false, Instant::now());
+        } else if !status.is_client_error() {
+            circuits.record(&provider, true, Instant::now());
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let circuits = ProviderCircuits::default();
+        let now = Instant::now();
+
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            circuits.record("github", false, now);
+        }
+        circuits.record("github", true, now);
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            circuits.record("github", false, now);
+        }
+        assert!(!circuits.any_open(now));
+
+        circuits.record("github", false, now);
+        assert!(circuits.any_open(now));
+        assert!(!circuits.any_open(now + OPEN_DURATION));
+
+        circuits.record("github", false, now);
+        circuits.record("github", true, now);
+        assert!(!circuits.any_open(now));
+    }
+
+    #[test]
+    fn detects_callbacks() {
+        assert_eq!(
+            callback_provider("/v1/sso/github/authorized"),
+            Some("github")
+        );
+        assert_eq!(
+            callback_provider("/v1/sso/oidc/okta/authorized"),
+            Some("okta")
+        );
+        assert_eq!(callback_provider("/v1/sso/github/authorize"), None);
+        assert_eq!(callback_provider("/v1/sso/a/b/c/authorized"), None);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    authentication::token::TokenConfig,
+    config::GlobalConfig,
+    database::Database,
+    error,
+    extract::{ClientInfo, Query},
+    http::{HttpClient, SendTimed},
+    model::{Response, Status},
+    session::{issue_session, SessionResponse},
+    user::Connection,
+    utils::crypto::Aead256,
+    Result,
+};
+
+use super::{
+    get_or_create_user,
+    state::{exchange_with_state, issue_state},
+    with_provider_token, SsoError,
+};
+
+use axum::{
+    extract::{Extension, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use headers::Cookie;
+use http::{header::SET_COOKIE, StatusCode};
+use hyper::Uri;
+use reqwest::IntoUrl;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiscordError {
+    #[error("access token error: {0}")]
+    TokenAccess(String),
+}
+
+impl error::ErrorResponse for DiscordError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DiscordError::TokenAccess(_) => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Discord {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: Url,
+    client: HttpClient,
+}
+
+impl Discord {
+    const TOKEN_URL: &'static str = "https://discord.com/api/oauth2/token";
+    const API_URL: &'static str = "https://discord.com/api/v10/";
+
+    pub fn new<U>(
+        client_id: String,
+        client_secret: String,
+        redirect: U,
+        client: HttpClient,
+    ) -> Result<Self>
+    where
+        U: IntoUrl,
+    {
+        Ok(Self {
+            client_id,
+            client_secret,
+            redirect_uri: redirect.into_url()?,
+            client,
+        })
+    }
+
+    async fn get_access_token(&self, code: &str) -> Result<TokenResponse> {
+        let form = TokenRequest {
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            code,
+            grant_type: "authorization_code",
+            redirect_uri: &self.redirect_uri,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<ErrorResponse>().await?;
+            let message = body.error_description.unwrap_or(body.error);
+            return Err(SsoError::from(DiscordError::TokenAccess(message)).into());
+        }
+
+        let body = res.error_for_status()?.json::<TokenResponse>().await?;
+
+        Ok(body)
+    }
+
+    /// Redeems a stored refresh token, returns the current connection and the rotated token
+    pub(super) async fn refresh_connection(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(Connection, Option<String>)> {
+        let form = RefreshRequest {
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            grant_type: "refresh_token",
+            refresh_token,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<ErrorResponse>().await?;
+            let message = body.error_description.unwrap_or(body.error);
+            return Err(SsoError::from(DiscordError::TokenAccess(message)).into());
+        }
+
+        let tokens = res.error_for_status()?.json::<TokenResponse>().await?;
+        let user = self.get_current_user(&tokens.access_token).await?;
+
+        Ok((user.into(), tokens.refresh_token))
+    }
+
+    async fn get_current_user(&self, access_token: &str) -> Result<User> {
+        let url = Url::parse(Self::API_URL)
+            .unwrap()
+            .join("users/@me")
+            .unwrap();
+
+        let res = self
+            .client
+            .get(url)
+            .bearer_auth(access_token)
+            .send_timed()
+            .await?;
+        let user = res.error_for_status()?.json().await?;
+
+        Ok(user)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    grant_type: &'a str,
+    redirect_uri: &'a Url,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    grant_type: &'a str,
+    refresh_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+    username: String,
+    /// `"0"` for accounts that migrated to unique usernames
+    discriminator: String,
+    /// Only present if the `email` scope was granted
+    email: Option<String>,
+    #[serde(default)]
+    verified: bool,
+    #[serde(default)]
+    mfa_enabled: bool,
+}
+
+impl From<User> for Connection {
+    fn from(user: User) -> Self {
+        Connection::Discord {
+            user_id: user.id,
+            username: user.username,
+            discriminator: user.discriminator,
+            mfa_enabled: user.mfa_enabled,
+        }
+    }
+}
+
+pub(super) async fn authorize(
+    Extension(discord): Extension<Discord>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    let state = issue_state(&config)?;
+
+    let pq = format!(
+        "/oauth2/authorize?client_id={client_id}&redirect_uri={redirect_uri}&response_type=code&scope={scope}&state={state}",
+        client_id = discord.client_id,
+        redirect_uri = discord.redirect_uri,
+        scope = "identify%20email",
+        state = state,
+    );
+
+    let uri = Uri::builder()
+        .scheme("https")
+        .authority("discord.com")
+        .path_and_query(pq)
+        .build()?;
+
+    let mut redirect = Redirect::to(&uri.to_string()).into_response();
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/discord; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizedParams {
+    code: String,
+    state: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn authorized(
+    client: ClientInfo,
+    Query(params): Query<AuthorizedParams>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(discord): Extension<Discord>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(enc): Extension<Aead256>,
+) -> crate::Result<Response<SessionResponse>> {
+    let TokenResponse {
+        access_token,
+        refresh_token,
+    } = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        Some(&params.state),
+        discord.get_access_token(&params.code),
+    )
+    .await?;
+
+    let user = discord.get_current_user(&access_token).await?;
+
+    // Unverified addresses could claim the account of another user
+    let email = match user.email.clone() {
+        Some(email) if user.verified => email,
+        _ => return Err(SsoError::EmailInvalid.into()),
+    };
+
+    let connection = Connection::from(user);
+
+    let doc = get_or_create_user(&db, &global, email, connection.clone()).await?;
+
+    let session = issue_session(&db, &config, &doc, &client, "discord");
+    let response =
+        with_provider_token(&db, &enc, &doc, &connection, refresh_token, session).await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
@@ -1,17 +1,21 @@
 use crate::{
-    authentication::token::{TokenClaims, TokenConfig, TokenError},
+    authentication::token::TokenConfig,
     config::GlobalConfig,
     database::Database,
-    error::{self, Error},
-    extract::Query,
-    http::HttpClient,
+    error,
+    extract::{ClientInfo, Query},
+    http::{HttpClient, SendTimed},
     model::{Response, Status},
-    session::{Scope, SessionClaims, SessionResponse},
-    user::{Connection, UserDocument, UserError},
-    utils, Result,
+    session::{issue_session, SessionResponse},
+    user::Connection,
+    Result,
 };
 
-use super::{oauth::StateClaims, SsoError};
+use super::{
+    get_or_create_user,
+    state::{exchange_with_state, issue_state},
+    SsoError,
+};
 
 use axum::{
     extract::{Extension, TypedHeader},
@@ -108,7 +112,7 @@ impl GitHub {
             .post(url)
             .header(ACCEPT, HeaderValue::from_static("application/json"))
             .form(&form)
-            .send()
+            .send_timed()
             .await?;
         let body = res.error_for_status()?.json::<TokenResponse>().await?;
 
@@ -153,7 +157,7 @@ impl GitHub {
             format!("token {}", access_token).parse().unwrap(),
         );
 
-        let res = self.client.get(url).headers(headers).send().await?;
+        let res = self.client.get(url).headers(headers).send_timed().await?;
         let body = res.error_for_status()?.json().await?;
 
         Ok(body)
@@ -168,18 +172,11 @@ struct TokenRequest<'a> {
     redirect_uri: &'a Url,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum TokenType {
-    Bearer,
-}
-
 #[derive(Debug, Deserialize)]
 struct TokenResponse {
     access_token: Option<String>,
-    token_type: Option<TokenType>,
-    scope: Option<String>,
-
+    // token_type: String,
+    // scope: String,
     #[serde(flatten)]
     error: Option<TokenAccessError>,
 }
@@ -232,17 +229,15 @@ struct Email {
     address: String,
     verified: bool,
     primary: bool,
-    visibility: Option<String>,
+    // visibility: String,
 }
 
 pub(super) async fn authorize(
     Extension(gh): Extension<GitHub>,
     Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
 ) -> crate::Result<axum::response::Response> {
-    let header = jsonwebtoken::Header::new(config.alg);
-    let claims = StateClaims::new(config.validation.aud.clone().unwrap());
-    let state =
-        jsonwebtoken::encode(&header, &claims, &config.enc_key).map_err(TokenError::from)?;
+    let state = issue_state(&config)?;
 
     let pq = format!(
         "/login/oauth/authorize?client_id={client_id}&redirect_uri={redirect_uri}&scope={scope}&state={state}",
@@ -260,8 +255,8 @@ pub(super) async fn authorize(
 
     let mut redirect = Redirect::to(&uri.to_string()).into_response();
     let cookie = format!(
-        "state={}; Path=/v1/sso/github; SameSite=Lax; Secure; HttpOnly",
-        state
+        "state={}; Path={}/v1/sso/github; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path
     )
     .parse()
     .unwrap();
@@ -277,6 +272,7 @@ pub struct AuthorizedParams {
 }
 
 pub(super) async fn authorized(
+    client: ClientInfo,
     Query(params): Query<AuthorizedParams>,
     TypedHeader(cookies): TypedHeader<Cookie>,
     Extension(gh): Extension<GitHub>,
@@ -284,16 +280,14 @@ pub(super) async fn authorized(
     Extension(global): Extension<GlobalConfig>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<SessionResponse>> {
-    let state = cookies.get("state").ok_or(SsoError::StateMissing)?;
-
-    if state != params.state {
-        return Err(SsoError::InvalidState.into());
-    }
-
-    let _claims = jsonwebtoken::decode::<StateClaims>(state, &config.dec_key, &config.validation)
-        .map_err(|_| SsoError::InvalidState)?;
-
-    let TokenResponse { access_token, .. } = gh.get_access_token(&params.code).await?;
+    let TokenResponse { access_token, .. } = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        Some(&params.state),
+        gh.get_access_token(&params.code),
+    )
+    .await?;
     let access_token = access_token.ok_or_else(|| {
         tracing::error!("missing access token field");
         SsoError::from(GitHubError::UnknownError)
@@ -315,61 +309,9 @@ pub(super) async fn authorized(
         two_factor_enabled: user.two_factor_authentication,
     };
 
-    let query = doc! {"$or": [
-        {"connections": { "$elemMatch": { "type": "github", "userId": user.id } }},
-        {"email": &email.address },
-    ]};
-
-    let doc = match db.get_user(query).await {
-        Ok(doc) => {
-            if let Some(c) = doc.connections.iter().find(|&c| c.is_github()) {
-                if c != &connection {
-                    db.update_user_connection(doc.id, connection).await?
-                } else {
-                    doc
-                }
-            } else {
-                db.insert_user_connection(doc.id, connection).await?
-            }
-        }
-        Err(e) => match e {
-            Error::User(e) if e == UserError::NotFound => {
-                let domain =
-                    utils::get_email_domain(&email.address).ok_or(UserError::InvalidAddr)?;
-
-                if !global.is_allowed_domain(domain) {
-                    return Err(UserError::DomainNotAllowed.into());
-                }
-
-                let doc = UserDocument {
-                    email: email.address,
-                    connections: vec![connection],
-                    can_login: true,
-                    verified: true,
-                    ..Default::default()
-                };
-
-                db.insert_user(&doc).await?;
-
-                doc
-            }
-            _ => return Err(e),
-        },
-    };
-
-    let audience = config.validation.aud.clone().unwrap();
-    let scope = Scope::from_roles(doc.roles);
-    let claims = SessionClaims::with_scope(audience, &doc.id.to_hex(), scope);
-
-    let token = claims.encode(&config)?;
-
-    let response = SessionResponse {
-        user: doc.id.to_hex(),
-        token,
-        expires_at: claims.exp,
-    };
+    let doc = get_or_create_user(&db, &global, email.address, connection).await?;
 
-    db.set_user_session(doc.id).await?;
+    let response = issue_session(&db, &config, &doc, &client, "github").await?;
 
     Ok(Response::with_status(StatusCode::CREATED, response))
 }
@@ -1,15 +1,75 @@
+#[cfg(feature = "sso-apple")]
+mod apple;
+mod circuit;
+#[cfg(feature = "sso-discord")]
+mod discord;
+#[cfg(feature = "sso-github")]
 mod github;
 mod oauth;
+#[cfg(feature = "sso-oidc")]
+mod oidc;
+#[cfg(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord"))]
+mod refresh;
+mod registration;
 mod routes;
+mod sandbox;
+mod state;
+#[cfg(feature = "sso-steam")]
+mod steam;
+#[cfg(feature = "sso-twitch")]
+mod twitch;
 
-use crate::{error, model::Status};
+use crate::{
+    authentication::token::{TokenClaims, TokenConfig, TokenError},
+    config::GlobalConfig,
+    database::Database,
+    error::{self, Error},
+    model::{Response, Status},
+    session::SessionResponse,
+    user::{Connection, ProviderToken, UserDocument, UserError},
+    utils::{self, crypto::Aead256},
+    Result,
+};
 
+#[cfg(feature = "sso-apple")]
+use self::apple::AppleError;
+#[cfg(feature = "sso-discord")]
+use self::discord::DiscordError;
+#[cfg(feature = "sso-github")]
 use self::github::GitHubError;
+use self::oauth::RegistrationClaims;
+#[cfg(feature = "sso-oidc")]
+use self::oidc::OidcError;
+#[cfg(feature = "sso-steam")]
+use self::steam::SteamError;
+#[cfg(feature = "sso-twitch")]
+use self::twitch::TwitchError;
 
+use std::future::Future;
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
 use http::StatusCode;
+use mongodb::bson::doc;
+use serde::Serialize;
 
+#[cfg(feature = "sso-apple")]
+pub use apple::Apple;
+#[cfg(feature = "metrics")]
+pub use circuit::callback_provider;
+pub use circuit::{track_outages, ProviderCircuits};
+#[cfg(feature = "sso-discord")]
+pub use discord::Discord;
+#[cfg(feature = "sso-github")]
 pub use github::GitHub;
-pub use routes::routes;
+#[cfg(feature = "sso-oidc")]
+pub use oidc::{de_providers as de_oidc_providers, Oidc, ProviderConfig as OidcProviderConfig};
+#[cfg(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord"))]
+pub use refresh::spawn_connection_refresh;
+pub use routes::{routes, Providers};
+#[cfg(feature = "sso-steam")]
+pub use steam::Steam;
+#[cfg(feature = "sso-twitch")]
+pub use twitch::Twitch;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SsoError {
@@ -17,10 +77,30 @@ pub enum SsoError {
     StateMissing,
     #[error("wrong state value")]
     InvalidState,
+    #[error("state was already used")]
+    StateReused,
     #[error("email address doesn't meet the requirements")]
     EmailInvalid,
+    #[error("registration token is invalid: {0}")]
+    InvalidRegistration(TokenError),
+    #[cfg(feature = "sso-github")]
     #[error("GitHub returned an error: {0}")]
     GitHub(#[from] GitHubError),
+    #[cfg(feature = "sso-apple")]
+    #[error("Apple returned an error: {0}")]
+    Apple(#[from] AppleError),
+    #[cfg(feature = "sso-twitch")]
+    #[error("Twitch returned an error: {0}")]
+    Twitch(#[from] TwitchError),
+    #[cfg(feature = "sso-steam")]
+    #[error("Steam returned an error: {0}")]
+    Steam(#[from] SteamError),
+    #[cfg(feature = "sso-discord")]
+    #[error("Discord returned an error: {0}")]
+    Discord(#[from] DiscordError),
+    #[cfg(feature = "sso-oidc")]
+    #[error("OpenID Connect provider returned an error: {0}")]
+    Oidc(#[from] OidcError),
 }
 
 impl error::ErrorResponse for SsoError {
@@ -29,9 +109,21 @@ impl error::ErrorResponse for SsoError {
     fn status_code(&self) -> StatusCode {
         match self {
             SsoError::StateMissing => StatusCode::BAD_REQUEST,
-            SsoError::InvalidState => StatusCode::UNAUTHORIZED,
+            SsoError::InvalidState | SsoError::StateReused => StatusCode::UNAUTHORIZED,
             SsoError::EmailInvalid => StatusCode::UNPROCESSABLE_ENTITY,
+            SsoError::InvalidRegistration(_) => StatusCode::UNAUTHORIZED,
+            #[cfg(feature = "sso-github")]
             SsoError::GitHub(e) => e.status_code(),
+            #[cfg(feature = "sso-apple")]
+            SsoError::Apple(e) => e.status_code(),
+            #[cfg(feature = "sso-twitch")]
+            SsoError::Twitch(e) => e.status_code(),
+            #[cfg(feature = "sso-steam")]
+            SsoError::Steam(e) => e.status_code(),
+            #[cfg(feature = "sso-discord")]
+            SsoError::Discord(e) => e.status_code(),
+            #[cfg(feature = "sso-oidc")]
+            SsoError::Oidc(e) => e.status_code(),
         }
     }
 
@@ -39,3 +131,144 @@ impl error::ErrorResponse for SsoError {
         Status::new(self.status_code(), self.to_string())
     }
 }
+
+/// Returns the user with the connected account or email address.
+///
+/// The connection of an existing user is added or updated if necessary.
+/// A new user is created if no user matches and the email domain is allowed. Concurrent logins
+/// of the same account end with the same user, the first one creates it.
+pub(crate) async fn get_or_create_user(
+    db: &Database,
+    global: &GlobalConfig,
+    email: String,
+    connection: Connection,
+) -> Result<UserDocument> {
+    let query = doc! {"$or": [
+        {"connections": { "$elemMatch": connection.account_filter() }},
+        {"email": &email },
+    ]};
+
+    match db.get_user(query).await {
+        Ok(doc) => connect_user(db, doc, connection).await,
+        Err(Error::User(UserError::NotFound)) => {
+            let domain = utils::get_email_domain(&email).ok_or(UserError::InvalidAddr)?;
+
+            if !global.is_allowed_domain(domain) {
+                return Err(UserError::DomainNotAllowed.into());
+            }
+
+            let mut doc = UserDocument {
+                email,
+                connections: vec![connection.clone()],
+                can_login: true,
+                verified: true,
+                ..Default::default()
+            };
+            doc.flags = doc.connection_flags();
+
+            match db.insert_user_if_new(&doc).await? {
+                None => Ok(doc),
+                // Created by a concurrent login in the meantime
+                Some(existing) => connect_user(db, existing, connection).await,
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Claims the user if it was pre-registered and adds or refreshes the connection
+async fn connect_user(
+    db: &Database,
+    user: UserDocument,
+    connection: Connection,
+) -> Result<UserDocument> {
+    let user = if user.pending {
+        match db.claim_user(user.id).await {
+            Ok(user) => user,
+            // Claimed by a concurrent login
+            Err(Error::User(UserError::NotFound)) => db.get_user(doc! { "_id": user.id }).await?,
+            Err(e) => return Err(e),
+        }
+    } else {
+        user
+    };
+
+    match user
+        .connections
+        .iter()
+        .find(|c| c.is_same_provider(&connection))
+    {
+        Some(c) if c == &connection => Ok(user),
+        // Connection data is refreshed on every login, so the flags follow the provider state
+        Some(_) => {
+            let user = db.update_user_connection(user.id, connection).await?;
+            db.refresh_user_flags(user).await
+        }
+        None => {
+            let user = db.insert_user_connection(user.id, connection).await?;
+            db.refresh_user_flags(user).await
+        }
+    }
+}
+
+/// Stores the refresh token of the provider while the session is issued, the session doesn't
+/// depend on it. The token is stored even if a second factor is required first.
+#[cfg_attr(
+    not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+    allow(dead_code)
+)]
+pub(crate) async fn with_provider_token<F>(
+    db: &Database,
+    enc: &Aead256,
+    user: &UserDocument,
+    connection: &Connection,
+    refresh_token: Option<String>,
+    session: F,
+) -> Result<SessionResponse>
+where
+    F: Future<Output = Result<SessionResponse>>,
+{
+    let store = async {
+        match refresh_token {
+            Some(token) => {
+                let token = ProviderToken::new(enc, &token);
+                db.set_user_provider_token(user.id, connection, token)
+                    .await
+                    .map(drop)
+            }
+            None => Ok(()),
+        }
+    };
+
+    let (stored, session) = tokio::join!(store, session);
+    stored?;
+
+    session
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResponse {
+    pub token: String,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Defers the registration of an account whose provider doesn't share an email address.
+///
+/// The returned token has to be sent together with an email address to complete the registration.
+#[cfg_attr(not(feature = "sso-steam"), allow(dead_code))]
+pub(crate) fn pending_registration(
+    config: &TokenConfig,
+    connection: Connection,
+) -> Result<Response<RegistrationResponse>> {
+    let audience = config.validation.aud.clone().unwrap();
+    let claims = RegistrationClaims::new(audience, connection, config.clock.now());
+
+    let response = RegistrationResponse {
+        token: claims.encode(config)?,
+        expires_at: claims.exp,
+    };
+
+    Ok(Response::with_status(StatusCode::ACCEPTED, response))
+}
@@ -1,28 +1,45 @@
-use chrono::{serde::ts_seconds, DateTime, Utc};
+use crate::{
+    authentication::token::{TokenClaims, TokenType},
+    user::Connection,
+};
 
+use chrono::{serde::ts_seconds, DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+/// Claims of a pending registration of an account without email address
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct StateClaims {
+pub struct RegistrationClaims {
     pub aud: Vec<String>,
     #[serde(with = "ts_seconds")]
     pub exp: DateTime<Utc>,
     #[serde(with = "ts_seconds")]
     pub iat: DateTime<Utc>,
+    pub connection: Connection,
+    token_type: TokenType,
 }
 
-impl StateClaims {
-    pub const DEFAULT_EXP_MIN: i64 = 60;
+impl RegistrationClaims {
+    pub const DEFAULT_EXP_MIN: i64 = 30;
 
-    pub(super) fn new<A>(aud: A) -> Self
+    pub(super) fn new<A>(aud: A, connection: Connection, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
     {
         Self {
             aud: aud.into_iter().collect(),
-            exp: Utc::now() + chrono::Duration::minutes(Self::DEFAULT_EXP_MIN),
-            iat: Utc::now(),
+            exp: now + chrono::Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
+            connection,
+            token_type: Self::TOKEN_TYPE,
         }
     }
 }
+
+impl TokenClaims for RegistrationClaims {
+    const TOKEN_TYPE: TokenType = TokenType::Registration;
+
+    fn get_type(&self) -> &TokenType {
+        &self.token_type
+    }
+}
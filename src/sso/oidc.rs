@@ -0,0 +1,409 @@
+//! Generic OpenID Connect provider, e.g. Keycloak, Okta or Azure AD.
+//!
+//! Providers are configured with their issuer, the endpoints and keys are discovered via
+//! the OpenID configuration of the issuer.
+
+use crate::{
+    authentication::{
+        jwks::{self, JwksCache},
+        token::TokenConfig,
+    },
+    config::GlobalConfig,
+    database::Database,
+    error,
+    extract::{ClientInfo, Query},
+    http::{HttpClient, SendTimed},
+    model::{Response, Status},
+    session::{issue_session, SessionResponse},
+    user::Connection,
+    Result,
+};
+
+use super::{
+    get_or_create_user,
+    state::{exchange_with_state, issue_state},
+    SsoError,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::{Extension, Path, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use headers::Cookie;
+use http::{header::SET_COOKIE, StatusCode};
+use jsonwebtoken::{Algorithm, Validation};
+use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("provider is unknown")]
+    UnknownProvider,
+    #[error("access token error: {0}")]
+    TokenAccess(String),
+    #[error("ID token nonce does not match")]
+    InvalidNonce,
+    #[error("claim \"{0}\" is missing or invalid")]
+    InvalidClaim(String),
+}
+
+impl error::ErrorResponse for OidcError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            OidcError::UnknownProvider => StatusCode::NOT_FOUND,
+            OidcError::TokenAccess(_) | OidcError::InvalidNonce => StatusCode::UNAUTHORIZED,
+            OidcError::InvalidClaim(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+fn default_scope() -> String {
+    "openid email".to_string()
+}
+
+/// Configuration of an OpenID Connect provider
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderConfig {
+    /// Name of the provider in the routes, e.g. `/v1/sso/oidc/{name}/authorize`
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: Url,
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+/// Names of the claims used to map the identity to a user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ClaimMapping {
+    pub email: String,
+    /// The email address is trusted as verified if not set
+    pub email_verified: Option<String>,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            email: "email".to_string(),
+            email_verified: Some("email_verified".to_string()),
+        }
+    }
+}
+
+/// Deserializes the provider configurations from a JSON string
+pub fn de_providers<'de, D>(d: D) -> std::result::Result<Vec<ProviderConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    serde_json::from_str(&input).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize)]
+struct Configuration {
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    jwks_uri: Url,
+}
+
+struct Provider {
+    config: ProviderConfig,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    keys: JwksCache,
+    validation: Validation,
+}
+
+#[derive(Clone)]
+pub struct Oidc {
+    providers: Arc<HashMap<String, Provider>>,
+    client: HttpClient,
+}
+
+impl Oidc {
+    const ALGORITHMS: [Algorithm; 7] = [
+        Algorithm::RS256,
+        Algorithm::RS384,
+        Algorithm::RS512,
+        Algorithm::PS256,
+        Algorithm::PS384,
+        Algorithm::ES256,
+        Algorithm::ES384,
+    ];
+
+    const LEEWAY: u64 = 10;
+
+    pub async fn new(configs: Vec<ProviderConfig>, client: HttpClient) -> Result<Self> {
+        let mut providers = HashMap::with_capacity(configs.len());
+
+        for config in configs {
+            let discovered = jwks::discover::<Configuration>(&config.issuer, &client).await?;
+
+            let keys = JwksCache::new(discovered.jwks_uri, client.clone());
+            keys.spawn_refresh(JwksCache::REFRESH_INTERVAL);
+
+            let mut validation = Validation::default();
+            validation.algorithms = Self::ALGORITHMS.to_vec();
+            validation.leeway = Self::LEEWAY;
+            validation.set_audience(&[&config.client_id]);
+            validation.set_issuer(&[&config.issuer]);
+
+            let provider = Provider {
+                authorization_endpoint: discovered.authorization_endpoint,
+                token_endpoint: discovered.token_endpoint,
+                keys,
+                validation,
+                config,
+            };
+
+            providers.insert(provider.config.name.clone(), provider);
+        }
+
+        Ok(Self {
+            providers: Arc::new(providers),
+            client,
+        })
+    }
+
+    fn provider(&self, name: &str) -> Result<&Provider> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| SsoError::from(OidcError::UnknownProvider).into())
+    }
+
+    async fn get_id_token(&self, provider: &Provider, code: &str) -> Result<String> {
+        let form = TokenRequest {
+            client_id: &provider.config.client_id,
+            client_secret: &provider.config.client_secret,
+            code,
+            grant_type: "authorization_code",
+            redirect_uri: &provider.config.redirect_uri,
+        };
+
+        let res = self
+            .client
+            .post(provider.token_endpoint.clone())
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<ErrorResponse>().await?;
+            let message = body.error_description.unwrap_or(body.error);
+            return Err(SsoError::from(OidcError::TokenAccess(message)).into());
+        }
+
+        let body = res.error_for_status()?.json::<TokenResponse>().await?;
+
+        Ok(body.id_token)
+    }
+}
+
+/// Binds the ID token to the state of the browser that started the login
+fn nonce(state: &str) -> String {
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+/// Maps the claims of a validated ID token to the email address and connection
+fn identity(
+    issuer: &str,
+    mapping: &ClaimMapping,
+    claims: &Map<String, Value>,
+) -> std::result::Result<(String, Connection), SsoError> {
+    let get_str = |name: &str| {
+        claims
+            .get(name)
+            .and_then(Value::as_str)
+            .map(ToString::to_string)
+            .ok_or_else(|| OidcError::InvalidClaim(name.to_string()))
+    };
+
+    let subject = get_str("sub")?;
+    let email = get_str(&mapping.email)?;
+
+    if let Some(name) = &mapping.email_verified {
+        if claims.get(name).and_then(Value::as_bool) != Some(true) {
+            return Err(SsoError::EmailInvalid);
+        }
+    }
+
+    let connection = Connection::Federated {
+        issuer: issuer.to_string(),
+        subject,
+    };
+
+    Ok((email, connection))
+}
+
+#[derive(Debug, Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    grant_type: &'a str,
+    redirect_uri: &'a Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+pub(super) async fn authorize(
+    Path(name): Path<String>,
+    Extension(oidc): Extension<Oidc>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    let provider = oidc.provider(&name)?;
+
+    let state = issue_state(&config)?;
+
+    let mut uri = provider.authorization_endpoint.clone();
+    uri.query_pairs_mut()
+        .append_pair("client_id", &provider.config.client_id)
+        .append_pair("redirect_uri", provider.config.redirect_uri.as_str())
+        .append_pair("response_type", "code")
+        .append_pair("scope", &provider.config.scope)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce(&state));
+
+    let mut redirect = Redirect::to(uri.as_str()).into_response();
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/oidc/{}; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path, name
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizedParams {
+    code: String,
+    state: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn authorized(
+    Path(name): Path<String>,
+    client: ClientInfo,
+    Query(params): Query<AuthorizedParams>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(oidc): Extension<Oidc>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    let provider = oidc.provider(&name)?;
+
+    let id_token = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        Some(&params.state),
+        oidc.get_id_token(provider, &params.code),
+    )
+    .await?;
+
+    let claims = provider
+        .keys
+        .decode::<Map<String, Value>>(&id_token, &provider.validation)
+        .await?;
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce(&params.state).as_str()) {
+        return Err(SsoError::from(OidcError::InvalidNonce).into());
+    }
+
+    let (email, connection) = identity(&provider.config.issuer, &provider.config.claims, &claims)?;
+
+    let doc = get_or_create_user(&db, &global, email, connection).await?;
+
+    let response = issue_session(&db, &config, &doc, &client, "oidc").await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    const ISSUER: &str = "https://sso.example.com/realms/tarkov";
+
+    fn claims(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn maps_identity() {
+        let claims = claims(json!({
+            "sub": "f1b2",
+            "email": "user@example.com",
+            "email_verified": true
+        }));
+
+        let (email, connection) = identity(ISSUER, &ClaimMapping::default(), &claims).unwrap();
+
+        assert_eq!(email, "user@example.com");
+        assert_eq!(
+            connection,
+            Connection::Federated {
+                issuer: ISSUER.to_string(),
+                subject: "f1b2".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn maps_custom_claims() {
+        let mapping = ClaimMapping {
+            email: "upn".to_string(),
+            email_verified: None,
+        };
+        let claims = claims(json!({ "sub": "f1b2", "upn": "user@example.com" }));
+
+        let (email, _) = identity(ISSUER, &mapping, &claims).unwrap();
+
+        assert_eq!(email, "user@example.com");
+    }
+
+    #[test]
+    fn rejects_unverified_email() {
+        let claims = claims(json!({
+            "sub": "f1b2",
+            "email": "user@example.com",
+            "email_verified": false
+        }));
+
+        assert!(matches!(
+            identity(ISSUER, &ClaimMapping::default(), &claims),
+            Err(SsoError::EmailInvalid)
+        ));
+    }
+}
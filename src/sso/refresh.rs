@@ -0,0 +1,118 @@
+//! Refresh of the connection data with the stored provider tokens.
+//!
+//! Connection data is otherwise only updated on logins, so flags derived from it, e.g. a
+//! disabled second factor at the provider, would be stale for users who stay signed in.
+
+use crate::{
+    config::Live,
+    database::Database,
+    user::{Connection, ProviderToken, UserDocument},
+    utils::{self, crypto::Aead256},
+    Result,
+};
+
+use super::Providers;
+
+use std::{future::Future, time::Duration};
+
+use tracing::{error, warn};
+
+/// Refreshes the connections of all providers periodically, with the providers configured at
+/// the time
+pub fn spawn_connection_refresh(
+    db: Database,
+    enc: Aead256,
+    providers: Live<Providers>,
+    interval: Duration,
+) {
+    utils::spawn_named("connection-refresh", async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let providers = providers.get();
+            #[cfg(feature = "sso-apple")]
+            if let Some(apple) = &providers.apple {
+                refresh_all(&db, &enc, "apple", |token| async move {
+                    apple.refresh_connection(&token).await
+                })
+                .await;
+            }
+            #[cfg(feature = "sso-twitch")]
+            if let Some(twitch) = &providers.twitch {
+                refresh_all(&db, &enc, "twitch", |token| async move {
+                    twitch.refresh_connection(&token).await
+                })
+                .await;
+            }
+            #[cfg(feature = "sso-discord")]
+            if let Some(discord) = &providers.discord {
+                refresh_all(&db, &enc, "discord", |token| async move {
+                    discord.refresh_connection(&token).await
+                })
+                .await;
+            }
+        }
+    });
+}
+
+async fn refresh_all<F, Fut>(db: &Database, enc: &Aead256, provider: &str, refresh: F)
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(Connection, Option<String>)>>,
+{
+    let users = match db.get_users_with_provider_token(provider).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!(provider, error = %e, "provider tokens could not be loaded");
+            return;
+        }
+    };
+
+    // One at a time, providers rate limit their token endpoints
+    for user in users {
+        let id = user.id;
+        if let Err(e) = refresh_user(db, enc, provider, user, &refresh).await {
+            warn!(provider, user = %id, error = %e, "connection could not be refreshed");
+        }
+    }
+}
+
+async fn refresh_user<F, Fut>(
+    db: &Database,
+    enc: &Aead256,
+    provider: &str,
+    user: UserDocument,
+    refresh: &F,
+) -> Result<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<(Connection, Option<String>)>>,
+{
+    let token = match user.provider_refresh_token(enc, provider)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    let (connection, rotated) = refresh(token).await?;
+
+    // The previous token is invalid once the provider rotated it
+    if let Some(token) = rotated {
+        let token = ProviderToken::new(enc, &token);
+        db.set_user_provider_token(user.id, &connection, token)
+            .await?;
+    }
+
+    // The connection may have been replaced by another account since the token was stored
+    let current = user
+        .connections
+        .iter()
+        .find(|c| c.account_filter() == connection.account_filter());
+    if matches!(current, Some(c) if c != &connection) {
+        let user = db.update_user_connection(user.id, connection).await?;
+        db.refresh_user_flags(user).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use crate::{
+    action::send_verification_mail,
+    authentication::token::{TokenClaims, TokenConfig, TokenError},
+    config::GlobalConfig,
+    database::Database,
+    extract::SizedJson,
+    mail,
+    model::Status,
+    user::{UserDocument, UserError},
+    utils,
+};
+
+use super::{oauth::RegistrationClaims, SsoError};
+
+use axum::extract::Extension;
+use hyper::StatusCode;
+use mongodb::bson::doc;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterRequest {
+    token: String,
+    email: String,
+}
+
+/// Completes a pending registration with an email address that has to be verified
+pub(super) async fn register(
+    SizedJson(body): SizedJson<RegisterRequest>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(mail): Extension<mail::Client>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    let claims = config
+        .decode::<RegistrationClaims>(&body.token)
+        .map_err(SsoError::InvalidRegistration)?
+        .claims;
+
+    if claims.get_type() != &RegistrationClaims::TOKEN_TYPE {
+        return Err(SsoError::InvalidRegistration(TokenError::WrongType).into());
+    }
+
+    let domain = utils::get_email_domain(&body.email).ok_or(UserError::InvalidAddr)?;
+
+    if !global.is_allowed_domain(domain) {
+        return Err(UserError::DomainNotAllowed.into());
+    }
+
+    let query = doc! {"$or": [
+        {"connections": { "$elemMatch": claims.connection.account_filter() }},
+        {"email": &body.email },
+    ]};
+
+    if db.get_user(query).await.is_ok() {
+        return Err(UserError::AlreadyExists.into());
+    }
+
+    let user = UserDocument {
+        email: body.email,
+        connections: vec![claims.connection],
+        ..Default::default()
+    };
+
+    db.insert_user(&user).await?;
+
+    send_verification_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+
+    Ok(Status::new(StatusCode::CREATED, "user registered"))
+}
@@ -1,14 +1,316 @@
+#[cfg(feature = "sso-apple")]
+use super::{apple, Apple};
+#[cfg(feature = "sso-discord")]
+use super::{discord, Discord};
+#[cfg(feature = "sso-github")]
 use super::{github, GitHub};
+#[cfg(feature = "sso-oidc")]
+use super::{oidc, Oidc};
+use super::{registration, sandbox};
+#[cfg(feature = "sso-steam")]
+use super::{steam, Steam};
+#[cfg(feature = "sso-twitch")]
+use super::{twitch, Twitch};
+use crate::{
+    config::{AppConfig, Live},
+    http::HttpClient,
+};
 
-use axum::{routing::get, Router};
-use tower_http::add_extension::AddExtensionLayer;
+use axum::{
+    body::Body,
+    middleware::Next,
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use http::Request;
 
-/// SSO routes
-pub fn routes(gh: GitHub) -> Router {
-    let github_svc = Router::new()
-        .route("/authorize", get(github::authorize))
-        .route("/authorized", get(github::authorized))
-        .layer(AddExtensionLayer::new(gh));
+/// Configured SSO providers, each one is optional at compile time
+#[derive(Default)]
+pub struct Providers {
+    #[cfg(feature = "sso-github")]
+    pub github: Option<GitHub>,
+    #[cfg(feature = "sso-apple")]
+    pub apple: Option<Apple>,
+    #[cfg(feature = "sso-twitch")]
+    pub twitch: Option<Twitch>,
+    #[cfg(feature = "sso-steam")]
+    pub steam: Option<Steam>,
+    #[cfg(feature = "sso-discord")]
+    pub discord: Option<Discord>,
+    #[cfg(feature = "sso-oidc")]
+    pub oidc: Option<Oidc>,
+}
+
+impl Providers {
+    /// Providers of the configuration, the ones of optional features if they are configured
+    #[cfg_attr(
+        not(any(
+            feature = "sso-github",
+            feature = "sso-apple",
+            feature = "sso-twitch",
+            feature = "sso-steam",
+            feature = "sso-discord",
+            feature = "sso-oidc"
+        )),
+        allow(unused_variables)
+    )]
+    pub async fn from_config(config: &AppConfig, client: &HttpClient) -> crate::Result<Self> {
+        #[allow(unused_mut)]
+        let mut providers = Self::default();
+        #[cfg(feature = "sso-github")]
+        {
+            providers.github = Some(GitHub::new(
+                config.gh_client_id.clone(),
+                config.gh_client_secret.clone(),
+                config.gh_redirect_uri.clone(),
+                client.clone(),
+            )?);
+        }
+        #[cfg(feature = "sso-apple")]
+        {
+            providers.apple = match (
+                &config.apple_client_id,
+                &config.apple_team_id,
+                &config.apple_key_id,
+                &config.apple_key_path,
+                &config.apple_redirect_uri,
+            ) {
+                (
+                    Some(client_id),
+                    Some(team_id),
+                    Some(key_id),
+                    Some(key_path),
+                    Some(redirect_uri),
+                ) => Some(Apple::new(
+                    client_id.clone(),
+                    team_id.clone(),
+                    key_id.clone(),
+                    key_path,
+                    redirect_uri.clone(),
+                    client.clone(),
+                )?),
+                (None, None, None, None, None) => None,
+                _ => {
+                    return Err(crate::error::Error::Config(
+                        "Apple configuration is incomplete".into(),
+                    ))
+                }
+            };
+        }
+        #[cfg(feature = "sso-twitch")]
+        {
+            providers.twitch = match (
+                &config.twitch_client_id,
+                &config.twitch_client_secret,
+                &config.twitch_redirect_uri,
+            ) {
+                (Some(client_id), Some(client_secret), Some(redirect_uri)) => Some(Twitch::new(
+                    client_id.clone(),
+                    client_secret.clone(),
+                    redirect_uri.clone(),
+                    client.clone(),
+                )?),
+                (None, None, None) => None,
+                _ => {
+                    return Err(crate::error::Error::Config(
+                        "Twitch configuration is incomplete".into(),
+                    ))
+                }
+            };
+        }
+        #[cfg(feature = "sso-steam")]
+        {
+            providers.steam = config
+                .steam_redirect_uri
+                .clone()
+                .map(|uri| Steam::new(uri, client.clone()))
+                .transpose()?;
+        }
+        #[cfg(feature = "sso-discord")]
+        {
+            providers.discord = match (
+                &config.discord_client_id,
+                &config.discord_client_secret,
+                &config.discord_redirect_uri,
+            ) {
+                (Some(client_id), Some(client_secret), Some(redirect_uri)) => Some(Discord::new(
+                    client_id.clone(),
+                    client_secret.clone(),
+                    redirect_uri.clone(),
+                    client.clone(),
+                )?),
+                (None, None, None) => None,
+                _ => {
+                    return Err(crate::error::Error::Config(
+                        "Discord configuration is incomplete".into(),
+                    ))
+                }
+            };
+        }
+        #[cfg(feature = "sso-oidc")]
+        if !config.oidc_providers.is_empty() {
+            providers.oidc = Some(Oidc::new(config.oidc_providers.clone(), client.clone()).await?);
+        }
+
+        Ok(providers)
+    }
+
+    /// Names of the configured providers
+    pub fn names(&self) -> Vec<&'static str> {
+        let configured: &[(&'static str, bool)] = &[
+            #[cfg(feature = "sso-github")]
+            ("github", self.github.is_some()),
+            #[cfg(feature = "sso-apple")]
+            ("apple", self.apple.is_some()),
+            #[cfg(feature = "sso-twitch")]
+            ("twitch", self.twitch.is_some()),
+            #[cfg(feature = "sso-steam")]
+            ("steam", self.steam.is_some()),
+            #[cfg(feature = "sso-discord")]
+            ("discord", self.discord.is_some()),
+            #[cfg(feature = "sso-oidc")]
+            ("oidc", self.oidc.is_some()),
+        ];
+
+        configured
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// Adds the provider of the current configuration to the request, its routes are only mounted
+/// if it is configured and it stays configured on reloads
+#[cfg_attr(
+    not(any(
+        feature = "sso-github",
+        feature = "sso-apple",
+        feature = "sso-twitch",
+        feature = "sso-steam",
+        feature = "sso-discord",
+        feature = "sso-oidc"
+    )),
+    allow(dead_code)
+)]
+async fn current<T>(
+    mut req: Request<Body>,
+    next: Next<Body>,
+    providers: Live<Providers>,
+    provider: fn(&Providers) -> Option<&T>,
+) -> Response
+where
+    T: Clone + Send + Sync + 'static,
+{
+    if let Some(provider) = provider(&providers.get()) {
+        req.extensions_mut().insert(provider.clone());
+    }
+
+    next.run(req).await
+}
+
+/// SSO routes, with the fake provider of sandbox deployments if `sandbox` is set
+#[cfg_attr(
+    not(any(
+        feature = "sso-github",
+        feature = "sso-apple",
+        feature = "sso-twitch",
+        feature = "sso-steam",
+        feature = "sso-discord",
+        feature = "sso-oidc"
+    )),
+    allow(unused_variables)
+)]
+pub fn routes(providers: Live<Providers>, sandbox: bool) -> Router {
+    let configured = providers.get();
+    let mut router = Router::new().route("/register", post(registration::register));
+
+    #[cfg(feature = "sso-github")]
+    if configured.github.is_some() {
+        let github_svc = Router::new()
+            .route("/authorize", get(github::authorize))
+            .route("/authorized", get(github::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.github.as_ref())
+            }));
+
+        router = router.nest("/github", github_svc);
+    }
+
+    #[cfg(feature = "sso-apple")]
+    if configured.apple.is_some() {
+        let apple_svc = Router::new()
+            .route("/authorize", get(apple::authorize))
+            .route("/authorized", post(apple::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.apple.as_ref())
+            }));
+
+        router = router.nest("/apple", apple_svc);
+    }
+
+    #[cfg(feature = "sso-twitch")]
+    if configured.twitch.is_some() {
+        let twitch_svc = Router::new()
+            .route("/authorize", get(twitch::authorize))
+            .route("/authorized", get(twitch::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.twitch.as_ref())
+            }));
+
+        router = router.nest("/twitch", twitch_svc);
+    }
+
+    #[cfg(feature = "sso-steam")]
+    if configured.steam.is_some() {
+        let steam_svc = Router::new()
+            .route("/authorize", get(steam::authorize))
+            .route("/authorized", get(steam::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.steam.as_ref())
+            }));
+
+        router = router.nest("/steam", steam_svc);
+    }
+
+    #[cfg(feature = "sso-discord")]
+    if configured.discord.is_some() {
+        let discord_svc = Router::new()
+            .route("/authorize", get(discord::authorize))
+            .route("/authorized", get(discord::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.discord.as_ref())
+            }));
+
+        router = router.nest("/discord", discord_svc);
+    }
+
+    #[cfg(feature = "sso-oidc")]
+    if configured.oidc.is_some() {
+        let oidc_svc = Router::new()
+            .route("/:provider/authorize", get(oidc::authorize))
+            .route("/:provider/authorized", get(oidc::authorized))
+            .layer(axum::middleware::from_fn({
+                let providers = providers.clone();
+                move |req, next| current(req, next, providers.clone(), |p| p.oidc.as_ref())
+            }));
+
+        router = router.nest("/oidc", oidc_svc);
+    }
+
+    if sandbox {
+        let sandbox_svc = Router::new()
+            .route("/authorize", get(sandbox::authorize))
+            .route("/authorized", get(sandbox::authorized));
+
+        router = router.nest("/sandbox", sandbox_svc);
+    }
 
-    Router::new().nest("/github", github_svc)
+    router
 }
@@ -0,0 +1,93 @@
+//! Fake provider of sandbox deployments.
+//!
+//! It runs the flow of the real providers, state cookie included, but signs in any email address
+//! it is asked for, so integrators can automate logins against a sandbox. It is only mounted in
+//! sandbox mode.
+
+use crate::{
+    authentication::token::TokenConfig,
+    config::GlobalConfig,
+    database::Database,
+    extract::{ClientInfo, Query},
+    model::Response,
+    session::{issue_session, SessionResponse},
+    user::{Connection, UserError},
+    utils,
+};
+
+use super::{
+    get_or_create_user,
+    state::{consume_state, issue_state},
+};
+
+use axum::{
+    extract::{Extension, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use headers::Cookie;
+use http::{header::SET_COOKIE, StatusCode};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeParams {
+    /// Account the provider signs in
+    email: String,
+}
+
+/// Approves at once and redirects to the callback, with the email address as code
+pub(super) async fn authorize(
+    Query(params): Query<AuthorizeParams>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    utils::get_email_domain(&params.email).ok_or(UserError::InvalidAddr)?;
+
+    let state = issue_state(&config)?;
+    let query: String = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("code", &params.email)
+        .append_pair("state", &state)
+        .finish();
+
+    let mut redirect = Redirect::to(&format!(
+        "{}/v1/sso/sandbox/authorized?{}",
+        global.base_path, query
+    ))
+    .into_response();
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/sandbox; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizedParams {
+    code: String,
+    state: String,
+}
+
+pub(super) async fn authorized(
+    client: ClientInfo,
+    Query(params): Query<AuthorizedParams>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    consume_state(&db, &config, &cookies, Some(&params.state)).await?;
+
+    let email = params.code.trim().to_lowercase();
+    let connection = Connection::Sandbox {
+        user_id: email.clone(),
+    };
+
+    let doc = get_or_create_user(&db, &global, email, connection).await?;
+
+    let response = issue_session(&db, &config, &doc, &client, "sandbox").await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
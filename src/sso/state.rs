@@ -0,0 +1,209 @@
+//! State of the SSO flows, against CSRF and replays.
+//!
+//! The state is a signed token that is set as cookie when a flow starts and sent back by the
+//! provider to the callback. Its nonce is consumed by the callback, so that a captured state can
+//! be used once only, even within its validity.
+
+use crate::{
+    authentication::token::{TokenConfig, TokenError},
+    database::Database,
+    Result,
+};
+
+use super::SsoError;
+
+use std::future::Future;
+
+use chrono::{serde::ts_seconds, DateTime, Utc};
+use headers::Cookie;
+use mongodb::{
+    bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime},
+    error::{ErrorKind, WriteFailure},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateClaims {
+    pub aud: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    pub exp: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub iat: DateTime<Utc>,
+    /// Nonce that is consumed by the callback
+    pub jti: String,
+}
+
+impl StateClaims {
+    pub const DEFAULT_EXP_MIN: i64 = 60;
+
+    pub(super) fn new<A>(aud: A, now: DateTime<Utc>) -> Self
+    where
+        A: IntoIterator<Item = String>,
+    {
+        Self {
+            aud: aud.into_iter().collect(),
+            exp: now + chrono::Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
+            jti: ObjectId::new().to_hex(),
+        }
+    }
+}
+
+/// Starts a flow, returns the state to send to the provider and to set as cookie
+pub(super) fn issue_state(config: &TokenConfig) -> std::result::Result<String, TokenError> {
+    let claims = StateClaims::new(config.validation.aud.clone().unwrap(), config.clock.now());
+
+    config.encode(&claims)
+}
+
+/// Consumes the state like [`consume_state`] while the code is exchanged with the provider,
+/// saving a round trip to the database. A state that doesn't match the cookie fails before the
+/// exchange starts.
+#[cfg_attr(
+    not(any(
+        feature = "sso-github",
+        feature = "sso-apple",
+        feature = "sso-twitch",
+        feature = "sso-steam",
+        feature = "sso-discord",
+        feature = "sso-oidc"
+    )),
+    allow(dead_code)
+)]
+pub(super) async fn exchange_with_state<F, T, E>(
+    db: &Database,
+    config: &TokenConfig,
+    cookies: &Cookie,
+    state: Option<&str>,
+    exchange: F,
+) -> Result<T>
+where
+    F: Future<Output = std::result::Result<T, E>>,
+    crate::error::Error: From<E>,
+{
+    let (_, value) = tokio::try_join!(consume_state(db, config, cookies, state), async {
+        Ok(exchange.await?)
+    })?;
+
+    Ok(value)
+}
+
+/// Checks the state of a callback against the cookie of the browser that started the flow and
+/// consumes it
+pub(super) async fn consume_state(
+    db: &Database,
+    config: &TokenConfig,
+    cookies: &Cookie,
+    state: Option<&str>,
+) -> Result<StateClaims> {
+    let cookie = cookies.get("state").ok_or(SsoError::StateMissing)?;
+    if Some(cookie) != state {
+        return Err(SsoError::InvalidState.into());
+    }
+
+    let claims = config
+        .decode::<StateClaims>(cookie)
+        .map_err(|_| SsoError::InvalidState)?
+        .claims;
+
+    if !db
+        .consume_state_nonce(&claims.jti, claims.exp, config.clock.now())
+        .await?
+    {
+        return Err(SsoError::StateReused.into());
+    }
+
+    Ok(claims)
+}
+
+/// Nonce of a consumed state, kept until the state expired
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StateNonceDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    expires_at: DateTime<Utc>,
+}
+
+const COLLECTION: &str = "sso_states";
+
+/// Code of a write error on a duplicate key
+const DUPLICATE_KEY: i32 = 11000;
+
+impl Database {
+    /// Removes nonces once their state is expired
+    pub async fn init_sso_states(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expiresAt": 1 })
+            .options(
+                IndexOptions::builder()
+                    .expire_after(std::time::Duration::ZERO)
+                    .build(),
+            )
+            .build();
+
+        self.collection::<StateNonceDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records the nonce in the cache if there is one, returns `false` if it was used before
+    #[cfg_attr(not(feature = "cache"), allow(unused_variables))]
+    async fn consume_state_nonce(
+        &self,
+        nonce: &str,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        #[cfg(feature = "cache")]
+        if let Some(cache) = self.cache() {
+            let ttl = (expires_at - now).to_std().unwrap_or_default();
+            let key = self.cache_key(&format!("sso-state:{}", nonce));
+
+            return Ok(cache.insert(&key, &true, ttl).await?);
+        }
+
+        let doc = StateNonceDocument {
+            id: nonce.to_string(),
+            expires_at,
+        };
+
+        match self
+            .collection::<StateNonceDocument>(COLLECTION)
+            .insert_one(doc, None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) => match *e.kind {
+                ErrorKind::Write(WriteFailure::WriteError(ref w)) if w.code == DUPLICATE_KEY => {
+                    Ok(false)
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_have_distinct_nonces() {
+        let now = Utc::now();
+        let a = StateClaims::new(vec!["aud".to_string()], now);
+        let b = StateClaims::new(vec!["aud".to_string()], now);
+
+        assert_ne!(a.jti, b.jti);
+        assert_eq!(
+            a.exp,
+            now + chrono::Duration::minutes(StateClaims::DEFAULT_EXP_MIN)
+        );
+    }
+}
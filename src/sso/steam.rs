@@ -0,0 +1,196 @@
+use crate::{
+    authentication::token::TokenConfig,
+    config::GlobalConfig,
+    database::Database,
+    error::{self, Error},
+    extract::{ClientInfo, Query},
+    http::{HttpClient, SendTimed},
+    model::{Response, Status},
+    session::{issue_session, SessionError},
+    user::{Connection, UserError},
+    Result,
+};
+
+use super::{
+    pending_registration,
+    state::{exchange_with_state, issue_state},
+    SsoError,
+};
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Extension, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use headers::Cookie;
+use http::{header::SET_COOKIE, StatusCode};
+use mongodb::bson::doc;
+use reqwest::IntoUrl;
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SteamError {
+    #[error("assertion is invalid")]
+    InvalidAssertion,
+}
+
+impl error::ErrorResponse for SteamError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SteamError::InvalidAssertion => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Steam OpenID 2.0 provider
+#[derive(Debug, Clone)]
+pub struct Steam {
+    redirect_uri: Url,
+    client: HttpClient,
+}
+
+impl Steam {
+    const LOGIN_URL: &'static str = "https://steamcommunity.com/openid/login";
+    const IDENTITY_PREFIX: &'static str = "https://steamcommunity.com/openid/id/";
+    const NS: &'static str = "http://specs.openid.net/auth/2.0";
+    const IDENTIFIER_SELECT: &'static str = "http://specs.openid.net/auth/2.0/identifier_select";
+
+    pub fn new<U>(redirect: U, client: HttpClient) -> Result<Self>
+    where
+        U: IntoUrl,
+    {
+        Ok(Self {
+            redirect_uri: redirect.into_url()?,
+            client,
+        })
+    }
+
+    fn login_url(&self, state: &str) -> Url {
+        let mut return_to = self.redirect_uri.clone();
+        return_to.query_pairs_mut().append_pair("state", state);
+
+        let realm = self.redirect_uri.origin().ascii_serialization();
+
+        Url::parse_with_params(
+            Self::LOGIN_URL,
+            &[
+                ("openid.ns", Self::NS),
+                ("openid.mode", "checkid_setup"),
+                ("openid.return_to", return_to.as_str()),
+                ("openid.realm", &realm),
+                ("openid.identity", Self::IDENTIFIER_SELECT),
+                ("openid.claimed_id", Self::IDENTIFIER_SELECT),
+            ],
+        )
+        .unwrap()
+    }
+
+    /// Verifies the positive assertion with Steam and returns the Steam ID
+    async fn verify(&self, params: &HashMap<String, String>) -> Result<String> {
+        let get = |k: &str| params.get(k).map(String::as_str);
+
+        if get("openid.mode") != Some("id_res")
+            || get("openid.op_endpoint") != Some(Self::LOGIN_URL)
+        {
+            return Err(SsoError::from(SteamError::InvalidAssertion).into());
+        }
+
+        match get("openid.return_to") {
+            Some(v) if v.starts_with(self.redirect_uri.as_str()) => {}
+            _ => return Err(SsoError::from(SteamError::InvalidAssertion).into()),
+        }
+
+        let steam_id = get("openid.claimed_id")
+            .and_then(|v| v.strip_prefix(Self::IDENTITY_PREFIX))
+            .filter(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()))
+            .ok_or(SsoError::from(SteamError::InvalidAssertion))?;
+
+        let form = params
+            .iter()
+            .filter(|(k, _)| k.starts_with("openid.") && k.as_str() != "openid.mode")
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .chain(std::iter::once(("openid.mode", "check_authentication")))
+            .collect::<Vec<_>>();
+
+        let body = self
+            .client
+            .post(Self::LOGIN_URL)
+            .form(&form)
+            .send_timed()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        if !body.lines().any(|l| l.trim() == "is_valid:true") {
+            return Err(SsoError::from(SteamError::InvalidAssertion).into());
+        }
+
+        Ok(steam_id.to_string())
+    }
+}
+
+pub(super) async fn authorize(
+    Extension(steam): Extension<Steam>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    let state = issue_state(&config)?;
+
+    let mut redirect = Redirect::to(steam.login_url(&state).as_str()).into_response();
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/steam; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+pub(super) async fn authorized(
+    client: ClientInfo,
+    Query(params): Query<HashMap<String, String>>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(steam): Extension<Steam>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<axum::response::Response> {
+    let steam_id = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        params.get("state").map(String::as_str),
+        steam.verify(&params),
+    )
+    .await?;
+
+    let connection = Connection::Steam { steam_id };
+
+    let query = doc! { "connections": { "$elemMatch": connection.account_filter() } };
+
+    // Steam provides no email address, so unknown accounts have to complete the registration
+    let doc = match db.get_user(query).await {
+        Ok(doc) => doc,
+        Err(Error::User(UserError::NotFound)) => {
+            return Ok(pending_registration(&config, connection)?.into_response())
+        }
+        Err(e) => return Err(e),
+    };
+
+    if !doc.verified {
+        return Err(SessionError::NotAuthorized("user is not verified".to_string()).into());
+    }
+
+    let response = issue_session(&db, &config, &doc, &client, "steam").await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response).into_response())
+}
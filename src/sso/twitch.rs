@@ -0,0 +1,302 @@
+use crate::{
+    authentication::token::TokenConfig,
+    config::GlobalConfig,
+    database::Database,
+    error,
+    extract::{ClientInfo, Query},
+    http::{HttpClient, SendTimed},
+    model::{Response, Status},
+    session::{issue_session, SessionResponse},
+    user::Connection,
+    utils::crypto::Aead256,
+    Result,
+};
+
+use super::{
+    get_or_create_user,
+    state::{exchange_with_state, issue_state},
+    with_provider_token, SsoError,
+};
+
+use axum::{
+    extract::{Extension, TypedHeader},
+    response::{IntoResponse, Redirect},
+};
+use headers::{Cookie, HeaderMap};
+use http::{
+    header::{AUTHORIZATION, SET_COOKIE},
+    StatusCode,
+};
+use hyper::Uri;
+use reqwest::IntoUrl;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use url::Url;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TwitchError {
+    #[error("access token error: {0}")]
+    TokenAccess(String),
+    #[error("user not found")]
+    UserNotFound,
+}
+
+impl error::ErrorResponse for TwitchError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TwitchError::TokenAccess(_) => StatusCode::UNAUTHORIZED,
+            TwitchError::UserNotFound => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Twitch {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: Url,
+    client: HttpClient,
+}
+
+impl Twitch {
+    const TOKEN_URL: &'static str = "https://id.twitch.tv/oauth2/token";
+    const API_URL: &'static str = "https://api.twitch.tv/helix/";
+
+    pub fn new<U>(
+        client_id: String,
+        client_secret: String,
+        redirect: U,
+        client: HttpClient,
+    ) -> Result<Self>
+    where
+        U: IntoUrl,
+    {
+        Ok(Self {
+            client_id,
+            client_secret,
+            redirect_uri: redirect.into_url()?,
+            client,
+        })
+    }
+
+    async fn get_access_token(&self, code: &str) -> Result<TokenResponse> {
+        let form = TokenRequest {
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            code,
+            grant_type: "authorization_code",
+            redirect_uri: &self.redirect_uri,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<ErrorResponse>().await?;
+            return Err(SsoError::from(TwitchError::TokenAccess(body.message)).into());
+        }
+
+        let body = res.error_for_status()?.json::<TokenResponse>().await?;
+
+        Ok(body)
+    }
+
+    /// Redeems a stored refresh token, returns the current connection and the rotated token
+    pub(super) async fn refresh_connection(
+        &self,
+        refresh_token: &str,
+    ) -> Result<(Connection, Option<String>)> {
+        let form = RefreshRequest {
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            grant_type: "refresh_token",
+            refresh_token,
+        };
+
+        let res = self
+            .client
+            .post(Self::TOKEN_URL)
+            .form(&form)
+            .send_timed()
+            .await?;
+
+        if res.status() == StatusCode::BAD_REQUEST {
+            let body = res.json::<ErrorResponse>().await?;
+            return Err(SsoError::from(TwitchError::TokenAccess(body.message)).into());
+        }
+
+        let tokens = res.error_for_status()?.json::<TokenResponse>().await?;
+        let user = self.get_current_user(&tokens.access_token).await?;
+
+        Ok((user.into(), tokens.refresh_token))
+    }
+
+    async fn get_current_user(&self, access_token: &str) -> Result<User> {
+        let res = self.api_get::<Data<User>>("users", access_token).await?;
+
+        let user = res
+            .data
+            .into_iter()
+            .next()
+            .ok_or(TwitchError::UserNotFound)
+            .map_err(SsoError::from)?;
+
+        Ok(user)
+    }
+
+    #[inline]
+    async fn api_get<T>(&self, path: &str, access_token: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let url = Url::parse(Self::API_URL).unwrap().join(path).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Client-Id", self.client_id.parse().unwrap());
+        headers.insert(
+            AUTHORIZATION,
+            format!("Bearer {}", access_token).parse().unwrap(),
+        );
+
+        let res = self.client.get(url).headers(headers).send_timed().await?;
+        let body = res.error_for_status()?.json().await?;
+
+        Ok(body)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    code: &'a str,
+    grant_type: &'a str,
+    redirect_uri: &'a Url,
+}
+
+#[derive(Debug, Serialize)]
+struct RefreshRequest<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    grant_type: &'a str,
+    refresh_token: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Data<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+    login: String,
+    /// Only present if verified and the `user:read:email` scope was granted
+    email: Option<String>,
+}
+
+impl From<User> for Connection {
+    fn from(user: User) -> Self {
+        Connection::Twitch {
+            user_id: user.id,
+            login: user.login,
+        }
+    }
+}
+
+pub(super) async fn authorize(
+    Extension(twitch): Extension<Twitch>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(global): Extension<GlobalConfig>,
+) -> crate::Result<axum::response::Response> {
+    let state = issue_state(&config)?;
+
+    let pq = format!(
+        "/oauth2/authorize?client_id={client_id}&redirect_uri={redirect_uri}&response_type=code&scope={scope}&state={state}",
+        client_id = twitch.client_id,
+        redirect_uri = twitch.redirect_uri,
+        scope = "user:read:email",
+        state = state,
+    );
+
+    let uri = Uri::builder()
+        .scheme("https")
+        .authority("id.twitch.tv")
+        .path_and_query(pq)
+        .build()?;
+
+    let mut redirect = Redirect::to(&uri.to_string()).into_response();
+    let cookie = format!(
+        "state={}; Path={}/v1/sso/twitch; SameSite=Lax; Secure; HttpOnly",
+        state, global.base_path
+    )
+    .parse()
+    .unwrap();
+    redirect.headers_mut().insert(SET_COOKIE, cookie);
+
+    Ok(redirect)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizedParams {
+    code: String,
+    state: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn authorized(
+    client: ClientInfo,
+    Query(params): Query<AuthorizedParams>,
+    TypedHeader(cookies): TypedHeader<Cookie>,
+    Extension(twitch): Extension<Twitch>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+    Extension(enc): Extension<Aead256>,
+) -> crate::Result<Response<SessionResponse>> {
+    let TokenResponse {
+        access_token,
+        refresh_token,
+    } = exchange_with_state(
+        &db,
+        &config,
+        &cookies,
+        Some(&params.state),
+        twitch.get_access_token(&params.code),
+    )
+    .await?;
+
+    let user = twitch.get_current_user(&access_token).await?;
+
+    let email = user.email.clone().ok_or(SsoError::EmailInvalid)?;
+
+    let connection = Connection::from(user);
+
+    let doc = get_or_create_user(&db, &global, email, connection.clone()).await?;
+
+    let session = issue_session(&db, &config, &doc, &client, "twitch");
+    let response =
+        with_provider_token(&db, &enc, &doc, &connection, refresh_token, session).await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
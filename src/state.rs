@@ -0,0 +1,112 @@
+//! Components shared by the handlers of all routes.
+//!
+//! They are put together in [`AppState`] at startup, so leaving one out is a compile error
+//! rather than a rejected request, and [`provide`] adds each of them to the extensions of the
+//! requests, where handlers take them with `Extension`. This includes the components of single
+//! routes whose routers are built elsewhere, like the key alerts of the admin routes. Only
+//! components passed to the constructor of their router, like the SSO providers or the probes
+//! of the health routes, stay extensions of that router.
+
+use crate::{
+    authentication::{credential::CredentialHasher, password::Hibp, token::TokenConfig},
+    config::GlobalConfig,
+    database::Database,
+    epoch::EpochStore,
+    keys::KeyAlerts,
+    mail,
+    metrics::Metrics,
+    session::Fallback,
+    token::CiTrust,
+    user::SecurityNotifier,
+    utils::crypto::Aead256,
+};
+
+#[cfg(feature = "federation")]
+use crate::federation::Federation;
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsToken;
+#[cfg(feature = "selftest")]
+use crate::selftest::SelfTestAddr;
+
+use axum::{middleware::Next, response::Response};
+use http::{Extensions, Request};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub global: GlobalConfig,
+    pub epochs: EpochStore,
+    /// Database of the main realm, requests of other realms get theirs by host
+    pub db: Database,
+    pub token: TokenConfig,
+    pub aead: Aead256,
+    pub hasher: CredentialHasher,
+    pub hibp: Hibp,
+    pub mail: mail::Client,
+    pub security_notifier: SecurityNotifier,
+    pub fallback: Fallback,
+    pub metrics: Metrics,
+    #[cfg(feature = "metrics")]
+    pub metrics_token: MetricsToken,
+    pub ci_trust: CiTrust,
+    pub key_alerts: KeyAlerts,
+    #[cfg(feature = "federation")]
+    pub federation: Federation,
+    #[cfg(feature = "selftest")]
+    pub selftest_addr: SelfTestAddr,
+}
+
+impl AppState {
+    /// Adds every component, the state itself isn't added as its database might not be the one
+    /// of the request
+    fn insert_into(self, extensions: &mut Extensions) {
+        // Without a rest pattern, so that new components can't be forgotten
+        let Self {
+            global,
+            epochs,
+            db,
+            token,
+            aead,
+            hasher,
+            hibp,
+            mail,
+            security_notifier,
+            fallback,
+            metrics,
+            #[cfg(feature = "metrics")]
+            metrics_token,
+            ci_trust,
+            key_alerts,
+            #[cfg(feature = "federation")]
+            federation,
+            #[cfg(feature = "selftest")]
+            selftest_addr,
+        } = self;
+
+        extensions.insert(global);
+        extensions.insert(epochs);
+        extensions.insert(db);
+        extensions.insert(token);
+        extensions.insert(aead);
+        extensions.insert(hasher);
+        extensions.insert(hibp);
+        extensions.insert(mail);
+        extensions.insert(security_notifier);
+        extensions.insert(fallback);
+        extensions.insert(metrics);
+        #[cfg(feature = "metrics")]
+        extensions.insert(metrics_token);
+        extensions.insert(ci_trust);
+        extensions.insert(key_alerts);
+        #[cfg(feature = "federation")]
+        extensions.insert(federation);
+        #[cfg(feature = "selftest")]
+        extensions.insert(selftest_addr);
+    }
+}
+
+/// Middleware that provides the components of the state to the handlers
+pub async fn provide<B>(mut req: Request<B>, next: Next<B>, state: AppState) -> Response {
+    state.insert_into(req.extensions_mut());
+
+    next.run(req).await
+}
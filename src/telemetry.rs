@@ -0,0 +1,515 @@
+//! Export of spans to an OpenTelemetry collector.
+//!
+//! Spans of this crate get W3C trace context: requests continue the trace of an incoming
+//! `traceparent` header and upstream calls pass it on, so that e.g. SSO calls to GitHub show up
+//! in the trace of the login. Sampled spans are batched and posted as OTLP/HTTP JSON once an
+//! endpoint is configured. Without an endpoint only the sampling decision of callers is
+//! passed on.
+
+use crate::http::HttpClient;
+
+use std::{
+    fmt::{self, Write},
+    mem,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{Request, Response};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tower_http::trace::{DefaultOnResponse, MakeSpan, OnResponse};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    warn, Level, Span, Subscriber,
+};
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+pub const TRACEPARENT: &str = "traceparent";
+
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Finished spans kept until the next export, further spans are dropped
+const MAX_QUEUE: usize = 4096;
+
+/// Fields that are only read by the exporter or too large to export
+const SKIPPED_FIELDS: [&str; 3] = [TRACEPARENT, "headers", "otel.kind"];
+
+/// Trace context of a span as in the W3C `traceparent` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` header of version 00, other versions are read as far as known
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let mut ctx = Self {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            sampled: false,
+        };
+        hex::decode_to_slice(trace_id, &mut ctx.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut ctx.span_id).ok()?;
+        let mut flags_byte = [0; 1];
+        hex::decode_to_slice(flags, &mut flags_byte).ok()?;
+        ctx.sampled = flags_byte[0] & 1 == 1;
+
+        if ctx.trace_id == [0; 16] || ctx.span_id == [0; 8] {
+            return None;
+        }
+
+        Some(ctx)
+    }
+
+    pub fn header(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id),
+            self.sampled as u8
+        )
+    }
+}
+
+/// Samples the share of new traces given by the ratio, deterministic by trace ID
+fn sample(trace_id: &[u8; 16], ratio: f64) -> bool {
+    let mut low = [0; 8];
+    low.copy_from_slice(&trace_id[8..]);
+
+    ratio >= 1.0 || (u64::from_be_bytes(low) as f64) < ratio * u64::MAX as f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone)]
+struct SpanData {
+    context: TraceContext,
+    parent_span_id: Option<[u8; 8]>,
+    name: &'static str,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: bool,
+}
+
+impl SpanData {
+    fn encode(&self, end: SystemTime) -> Value {
+        let attributes: Vec<_> = self
+            .attributes
+            .iter()
+            .map(|(k, v)| json!({ "key": k, "value": { "stringValue": v } }))
+            .collect();
+
+        let mut span = json!({
+            "traceId": hex::encode(self.context.trace_id),
+            "spanId": hex::encode(self.context.span_id),
+            "name": self.name,
+            "kind": self.kind as u8,
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(end).to_string(),
+            "attributes": attributes,
+            "status": { "code": if self.error { 2 } else { 0 } },
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = hex::encode(parent).into();
+        }
+
+        span
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    remote: Option<TraceContext>,
+    kind: Option<SpanKind>,
+    attributes: Vec<(&'static str, String)>,
+    error: bool,
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            TRACEPARENT => self.remote = TraceContext::parse(&value),
+            "otel.kind" => {
+                self.kind = match value.as_str() {
+                    "server" => Some(SpanKind::Server),
+                    "client" => Some(SpanKind::Client),
+                    _ => Some(SpanKind::Internal),
+                }
+            }
+            "status" if value.parse::<u16>().map_or(false, |s| s >= 500) => self.error = true,
+            _ => {}
+        }
+
+        if !SKIPPED_FIELDS.contains(&field.name()) {
+            self.attributes.push((field.name(), value));
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let mut out = String::new();
+        let _ = write!(out, "{:?}", value);
+        self.record(field, out);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Exporter {
+    /// Sampling ratio of new traces, nothing is sampled before the exporter is started
+    ratio: Option<f64>,
+    /// Collector the spans are posted to once started
+    target: Option<(HttpClient, Url)>,
+    queue: Vec<Value>,
+    dropped: usize,
+}
+
+/// Handle of the span exporter
+#[derive(Debug, Clone, Default)]
+pub struct Telemetry(Arc<Mutex<Exporter>>);
+
+impl Telemetry {
+    /// Installs the global subscriber that logs by `RUST_LOG` and collects spans of this crate
+    pub fn init() -> Self {
+        let telemetry = Self::default();
+
+        let spans = Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG);
+
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env()))
+            .with(SpanLayer(telemetry.clone()).with_filter(spans))
+            .init();
+
+        telemetry
+    }
+
+    /// Starts to sample new traces and posts the spans to the OTLP/HTTP endpoint
+    pub fn start(&self, endpoint: Url, ratio: f64, client: HttpClient) {
+        {
+            let mut exporter = self.0.lock().unwrap();
+            exporter.ratio = Some(ratio);
+            exporter.target = Some((client.clone(), endpoint.clone()));
+        }
+
+        let telemetry = self.clone();
+        crate::utils::spawn_named("otlp-export", async move {
+            let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                telemetry.export(&client, &endpoint).await;
+            }
+        });
+    }
+
+    /// Exports the queued spans, e.g. before the server exits
+    pub async fn flush(&self) {
+        let target = self.0.lock().unwrap().target.clone();
+        if let Some((client, endpoint)) = target {
+            self.export(&client, &endpoint).await;
+        }
+    }
+
+    async fn export(&self, client: &HttpClient, endpoint: &Url) {
+        let (spans, dropped) = {
+            let mut exporter = self.0.lock().unwrap();
+            (
+                mem::take(&mut exporter.queue),
+                mem::take(&mut exporter.dropped),
+            )
+        };
+
+        if dropped > 0 {
+            warn!(dropped, "spans dropped before export");
+        }
+        if spans.is_empty() {
+            return;
+        }
+
+        // Not sent timed, so that the export isn't traced itself
+        let result = client
+            .post(endpoint.clone())
+            .json(&encode_request(spans))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+
+        if let Err(e) = result {
+            warn!(error = %e, "span export failed");
+        }
+    }
+
+    fn ratio(&self) -> Option<f64> {
+        self.0.lock().unwrap().ratio
+    }
+
+    fn push(&self, span: Value) {
+        let mut exporter = self.0.lock().unwrap();
+
+        if exporter.queue.len() < MAX_QUEUE {
+            exporter.queue.push(span);
+        } else {
+            exporter.dropped += 1;
+        }
+    }
+}
+
+fn encode_request(spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": env!("CARGO_PKG_NAME") },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Layer that keeps the trace context of spans and queues sampled spans once they close
+struct SpanLayer(Telemetry);
+
+impl<S> Layer<S> for SpanLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(s) => s,
+            None => return,
+        };
+
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+
+        let parent = span
+            .parent()
+            .and_then(|p| p.extensions().get::<SpanData>().map(|d| d.context));
+        let span_id = rand::random();
+
+        let (context, parent_span_id) = match (parent, visitor.remote) {
+            (Some(parent), _) => (TraceContext { span_id, ..parent }, Some(parent.span_id)),
+            (None, Some(remote)) => (TraceContext { span_id, ..remote }, Some(remote.span_id)),
+            (None, None) => {
+                let trace_id = rand::random();
+                let sampled = self.0.ratio().map_or(false, |r| sample(&trace_id, r));
+                (
+                    TraceContext {
+                        trace_id,
+                        span_id,
+                        sampled,
+                    },
+                    None,
+                )
+            }
+        };
+
+        span.extensions_mut().insert(SpanData {
+            context,
+            parent_span_id,
+            name: attrs.metadata().name(),
+            kind: visitor.kind.unwrap_or(SpanKind::Internal),
+            start: SystemTime::now(),
+            attributes: visitor.attributes,
+            error: visitor.error,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(s) => s,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        let data = match extensions.get_mut::<SpanData>() {
+            Some(d) => d,
+            None => return,
+        };
+
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+
+        data.attributes.append(&mut visitor.attributes);
+        data.error |= visitor.error;
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(s) => s,
+            None => return,
+        };
+
+        let data = span.extensions_mut().remove::<SpanData>();
+        if let Some(data) = data {
+            if data.context.sampled && self.0.ratio().is_some() {
+                self.0.push(data.encode(SystemTime::now()));
+            }
+        }
+    }
+}
+
+/// Trace context of the current span, to be passed on to upstream calls
+pub fn current_context() -> Option<TraceContext> {
+    Span::current()
+        .with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            let span = registry.span(id)?;
+
+            let context = span
+                .scope()
+                .find_map(|s| s.extensions().get::<SpanData>().map(|d| d.context));
+            context
+        })
+        .flatten()
+}
+
+/// Creates the span of a request, which continues the trace of the caller
+#[derive(Debug, Clone, Copy)]
+pub struct MakeRequestSpan;
+
+impl<B> MakeSpan<B> for MakeRequestSpan {
+    fn make_span(&mut self, req: &Request<B>) -> Span {
+        let traceparent = req
+            .headers()
+            .get(TRACEPARENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        tracing::debug_span!(
+            "request",
+            otel.kind = "server",
+            method = %req.method(),
+            uri = %req.uri(),
+            version = ?req.version(),
+            headers = ?req.headers(),
+            traceparent,
+            status = tracing::field::Empty,
+        )
+    }
+}
+
+/// Records the status of a request on its span before it is logged
+#[derive(Debug, Clone)]
+pub struct RecordResponse(pub DefaultOnResponse);
+
+impl<B> OnResponse<B> for RecordResponse {
+    fn on_response(self, res: &Response<B>, latency: Duration, span: &Span) {
+        span.record("status", &res.status().as_u16());
+        self.0.on_response(res, latency, span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parses_traceparent() {
+        let ctx = TraceContext::parse(PARENT).unwrap();
+        assert!(ctx.sampled);
+        assert_eq!(ctx.header(), PARENT);
+
+        assert!(
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7").is_none()
+        );
+        assert!(
+            TraceContext::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                .is_none()
+        );
+        assert!(
+            TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+                .is_none()
+        );
+        assert!(
+            TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-x")
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn samples_by_ratio() {
+        let low = [0; 16];
+        let mut high = [0; 16];
+        high[8] = 0xff;
+
+        assert!(sample(&low, 0.5));
+        assert!(!sample(&high, 0.5));
+        assert!(sample(&high, 1.0));
+        assert!(!sample(&low, 0.0));
+    }
+
+    #[test]
+    fn continues_remote_trace() {
+        let telemetry = Telemetry::default();
+        telemetry.0.lock().unwrap().ratio = Some(0.0);
+        let subscriber = tracing_subscriber::registry().with(SpanLayer(telemetry.clone()));
+
+        let upstream = tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!(
+                "request",
+                otel.kind = "server",
+                traceparent = PARENT,
+                status = tracing::field::Empty,
+            );
+            let _guard = request.enter();
+            request.record("status", &503);
+
+            tracing::info_span!("upstream request", otel.kind = "client").in_scope(current_context)
+        });
+
+        let remote = TraceContext::parse(PARENT).unwrap();
+        let upstream = upstream.unwrap();
+        assert_eq!(upstream.trace_id, remote.trace_id);
+        assert_ne!(upstream.span_id, remote.span_id);
+        assert!(upstream.sampled);
+
+        let queue = mem::take(&mut telemetry.0.lock().unwrap().queue);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue[0]["kind"], 3);
+        assert_eq!(queue[1]["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(queue[1]["status"]["code"], 2);
+        assert_eq!(queue[1]["attributes"][0]["value"]["stringValue"], "503");
+    }
+}
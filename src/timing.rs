@@ -0,0 +1,160 @@
+//! Traces of slow requests with the time spent in upstream calls and database operations
+
+use crate::metrics::Metrics;
+
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{middleware::Next, response::Response};
+use hyper::Request;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandSucceededEvent};
+use tracing::warn;
+
+tokio::task_local! {
+    static TIMINGS: Timings;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlowRequestConfig {
+    pub threshold: Duration,
+    /// Share of slow requests that are traced, from 0 to 1
+    pub sample_rate: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Timings(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    subject: Option<String>,
+    region: Option<String>,
+    upstream: Vec<(String, Duration)>,
+    database: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    fn record<F>(f: F)
+    where
+        F: FnOnce(&mut Inner),
+    {
+        // Outside of a traced request there is nothing to record
+        let _ = TIMINGS.try_with(|t| f(&mut t.0.lock().unwrap()));
+    }
+}
+
+/// Records the subject of the validated token of the current request
+pub fn record_subject(sub: &str) {
+    Timings::record(|t| t.subject = Some(sub.to_string()));
+}
+
+/// Records the region that issued the validated token of the current request
+pub fn record_region(region: &str) {
+    Timings::record(|t| t.region = Some(region.to_string()));
+}
+
+/// Records the duration of an upstream call of the current request
+pub fn record_upstream(host: &str, duration: Duration) {
+    Timings::record(|t| t.upstream.push((host.to_string(), duration)));
+}
+
+fn record_database(command: &str, duration: Duration) {
+    Timings::record(|t| t.database.push((command.to_string(), duration)));
+}
+
+/// Records the duration of database commands in the metrics and, as the driver runs them in
+/// the task of the request, for slow request traces
+#[derive(Debug)]
+pub struct DatabaseTimings(pub Metrics);
+
+impl CommandEventHandler for DatabaseTimings {
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.0
+            .record_database(&event.command_name, true, event.duration);
+        record_database(&event.command_name, event.duration);
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        self.0
+            .record_database(&event.command_name, false, event.duration);
+        record_database(&event.command_name, event.duration);
+    }
+}
+
+/// Middleware that traces requests exceeding the latency threshold.
+///
+/// Tokens and bodies are never part of the trace, only the subject of a valid token.
+pub async fn trace_slow_requests<B>(
+    req: Request<B>,
+    next: Next<B>,
+    config: SlowRequestConfig,
+) -> Response {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+
+    let timings = Timings::default();
+    let start = Instant::now();
+    let res = TIMINGS.scope(timings.clone(), next.run(req)).await;
+    let latency = start.elapsed();
+
+    if latency < config.threshold || rand::random::<f64>() >= config.sample_rate {
+        return res;
+    }
+
+    let timings = timings.0.lock().unwrap();
+
+    warn!(
+        %method,
+        %path,
+        status = res.status().as_u16(),
+        latency_ms = latency.as_millis() as u64,
+        subject = timings.subject.as_deref().unwrap_or("-"),
+        token_region = timings.region.as_deref().unwrap_or("-"),
+        upstream = %format_timings(&timings.upstream),
+        database = %format_timings(&timings.database),
+        "slow request"
+    );
+
+    res
+}
+
+fn format_timings(timings: &[(String, Duration)]) -> String {
+    let mut out = String::new();
+
+    for (i, (name, duration)) in timings.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let _ = write!(out, "{}={}ms", name, duration.as_millis());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records_only_in_scope() {
+        record_upstream("outside", Duration::from_millis(1));
+
+        let timings = Timings::default();
+        TIMINGS
+            .scope(timings.clone(), async {
+                record_subject("user");
+                record_region("eu");
+                record_upstream("api.github.com", Duration::from_millis(120));
+                record_database("find", Duration::from_millis(3));
+            })
+            .await;
+
+        let timings = timings.0.lock().unwrap();
+        assert_eq!(timings.subject.as_deref(), Some("user"));
+        assert_eq!(timings.region.as_deref(), Some("eu"));
+        assert_eq!(format_timings(&timings.upstream), "api.github.com=120ms");
+        assert_eq!(format_timings(&timings.database), "find=3ms");
+    }
+}
@@ -0,0 +1,243 @@
+//! Exchange of OIDC tokens of CI pipelines, e.g. GitHub Actions or GitLab CI, for
+//! short-lived tokens, so that pipelines need no static secrets
+
+use crate::{
+    authentication::jwks::{self, JwksCache},
+    http::HttpClient,
+    session::Scope,
+    Result,
+};
+
+use super::TokenError;
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::Duration;
+use jsonwebtoken::{Algorithm, Validation};
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+
+const fn default_ttl() -> i64 {
+    15 * 60
+}
+
+/// Trust policy for the tokens of a CI issuer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CiPolicy {
+    pub issuer: String,
+    pub audience: String,
+    /// Claims that have to match, a trailing `*` matches any suffix
+    pub conditions: HashMap<String, String>,
+    pub grant: Grant,
+    /// Lifetime of the issued token in seconds
+    #[serde(default = "default_ttl")]
+    pub ttl_secs: i64,
+}
+
+/// Token issued for a matching CI token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Grant {
+    /// Session of a user, limited to the scope and the roles of the user
+    Session { user: String, scope: Vec<Scope> },
+    /// Token of a client to request service tokens with
+    Client(String),
+}
+
+impl CiPolicy {
+    const MAX_TTL_SECS: i64 = 60 * 60;
+
+    pub fn ttl(&self) -> Duration {
+        Duration::seconds(self.ttl_secs.clamp(1, Self::MAX_TTL_SECS))
+    }
+
+    fn matches(&self, claims: &Map<String, Value>) -> bool {
+        let audience = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == &self.audience,
+            Some(Value::Array(aud)) => aud.iter().any(|a| a.as_str() == Some(&self.audience)),
+            _ => false,
+        };
+
+        audience
+            && self.conditions.iter().all(|(name, pattern)| {
+                let value = match claims.get(name) {
+                    Some(Value::String(v)) => v.clone(),
+                    Some(Value::Bool(v)) => v.to_string(),
+                    _ => return false,
+                };
+
+                match pattern.strip_suffix('*') {
+                    Some(prefix) => value.starts_with(prefix),
+                    None => value == *pattern,
+                }
+            })
+    }
+}
+
+/// Deserializes the policies from a JSON string
+pub fn de_policies<'de, D>(d: D) -> std::result::Result<Vec<CiPolicy>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let policies: Vec<CiPolicy> = serde_json::from_str(&input).map_err(serde::de::Error::custom)?;
+
+    // Without conditions every pipeline of the issuer would match
+    if policies.iter().any(|p| p.conditions.is_empty()) {
+        return Err(serde::de::Error::custom("CI policy without conditions"));
+    }
+
+    Ok(policies)
+}
+
+#[derive(Clone, Default)]
+pub struct CiTrust {
+    issuers: Arc<HashMap<String, JwksCache>>,
+    policies: Arc<Vec<CiPolicy>>,
+}
+
+impl CiTrust {
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::RS256, Algorithm::RS384, Algorithm::RS512];
+
+    const LEEWAY: u64 = 10;
+
+    pub async fn new(policies: Vec<CiPolicy>, client: HttpClient) -> Result<Self> {
+        let mut issuers = HashMap::new();
+
+        for policy in &policies {
+            if issuers.contains_key(&policy.issuer) {
+                continue;
+            }
+
+            let uri = jwks::discover_uri(&policy.issuer, &client).await?;
+            let keys = JwksCache::new(uri, client.clone());
+            keys.spawn_refresh(JwksCache::REFRESH_INTERVAL);
+
+            issuers.insert(policy.issuer.clone(), keys);
+        }
+
+        Ok(Self {
+            issuers: Arc::new(issuers),
+            policies: Arc::new(policies),
+        })
+    }
+
+    /// Validates a CI token and returns the first policy it matches with its claims
+    pub async fn verify(&self, token: &str) -> Result<(&CiPolicy, Map<String, Value>)> {
+        let iss = jwks::peek_issuer(token)?;
+        let keys = self.issuers.get(&iss).ok_or(TokenError::UntrustedIssuer)?;
+
+        let mut validation = Validation::default();
+        validation.algorithms = Self::ALGORITHMS.to_vec();
+        validation.leeway = Self::LEEWAY;
+        validation.set_issuer(&[&iss]);
+        // Each policy checks its own audience again
+        let audience = self
+            .policies
+            .iter()
+            .filter(|p| p.issuer == iss)
+            .map(|p| p.audience.as_str())
+            .collect::<Vec<_>>();
+        validation.set_audience(&audience);
+
+        let claims = keys
+            .decode::<Map<String, Value>>(token, &validation)
+            .await?;
+
+        let policy = self
+            .policies
+            .iter()
+            .find(|p| p.issuer == iss && p.matches(&claims))
+            .ok_or(TokenError::NoMatchingPolicy)?;
+
+        Ok((policy, claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn policy(conditions: Value) -> CiPolicy {
+        serde_json::from_value(json!({
+            "issuer": "https://token.actions.githubusercontent.com",
+            "audience": "identity",
+            "conditions": conditions,
+            "grant": { "client": "62a3c0a5e2a1f3b4c5d6e7f8" }
+        }))
+        .unwrap()
+    }
+
+    fn claims(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn matches_conditions() {
+        let policy = policy(json!({
+            "repository": "tarkov-database/api",
+            "ref": "refs/heads/*"
+        }));
+
+        assert!(policy.matches(&claims(json!({
+            "aud": "identity",
+            "repository": "tarkov-database/api",
+            "ref": "refs/heads/main"
+        }))));
+        assert!(!policy.matches(&claims(json!({
+            "aud": "identity",
+            "repository": "tarkov-database/api",
+            "ref": "refs/tags/v1"
+        }))));
+        assert!(!policy.matches(&claims(json!({
+            "aud": "identity",
+            "repository": "tarkov-database/api-fork",
+            "ref": "refs/heads/main"
+        }))));
+        assert!(!policy.matches(&claims(json!({
+            "aud": "identity",
+            "ref": "refs/heads/main"
+        }))));
+    }
+
+    #[test]
+    fn requires_audience() {
+        let policy = policy(json!({ "project_path": "tarkov/api" }));
+
+        assert!(policy.matches(&claims(json!({
+            "aud": ["other", "identity"],
+            "project_path": "tarkov/api"
+        }))));
+        assert!(!policy.matches(&claims(json!({
+            "aud": "other",
+            "project_path": "tarkov/api"
+        }))));
+    }
+
+    #[test]
+    fn limits_ttl() {
+        let mut policy = policy(json!({ "repository": "tarkov-database/api" }));
+
+        assert_eq!(policy.ttl(), Duration::minutes(15));
+
+        policy.ttl_secs = 24 * 60 * 60;
+        assert_eq!(policy.ttl(), Duration::hours(1));
+    }
+
+    #[test]
+    fn rejects_policies_without_conditions() {
+        let input = json!([{
+            "issuer": "https://gitlab.com",
+            "audience": "identity",
+            "conditions": {},
+            "grant": { "session": { "user": "62a3c0a5e2a1f3b4c5d6e7f8", "scope": ["clientRead"] } }
+        }])
+        .to_string();
+
+        assert!(de_policies(Value::String(input)).is_err());
+    }
+}
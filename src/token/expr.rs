@@ -0,0 +1,733 @@
+//! Claim expressions of services, a small CEL-like language to derive claims of service tokens
+//! for cases that don't need a WASM policy:
+//!
+//! ```text
+//! roles.contains('userEditor') ? 'internal' : 'external'
+//! ```
+//!
+//! Expressions see the `sub`, `client`, `email` and `roles` of the subject and the granted
+//! `scope`. Clients have no email and no roles. Values are booleans, integers, strings and
+//! lists of strings, with the usual operators, `in` and the methods `contains`, `startsWith`,
+//! `endsWith` and `size`. Expressions are parsed and type checked when the configuration is
+//! loaded, so that they can't fail while tokens are issued.
+
+use std::{collections::BTreeMap, collections::HashMap, fmt, sync::Arc};
+
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value as Json};
+
+/// Claims of service tokens that expressions can't set
+const RESERVED_CLAIMS: [&str; 9] = [
+    "aud", "exp", "iat", "nbf", "iss", "sub", "azp", "jti", "scope",
+];
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ExprError(String);
+
+impl ExprError {
+    fn at(pos: usize, message: impl fmt::Display) -> Self {
+        Self(format!("{} at {}", message, pos))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Bool,
+    Int,
+    String,
+    List,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::String => "string",
+            Type::List => "list",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Sub,
+    Client,
+    Email,
+    Roles,
+    Scope,
+}
+
+impl Var {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sub" => Some(Var::Sub),
+            "client" => Some(Var::Client),
+            "email" => Some(Var::Email),
+            "roles" => Some(Var::Roles),
+            "scope" => Some(Var::Scope),
+            _ => None,
+        }
+    }
+
+    fn ty(&self) -> Type {
+        match self {
+            Var::Sub | Var::Client | Var::Email => Type::String,
+            Var::Roles | Var::Scope => Type::List,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Contains,
+    StartsWith,
+    EndsWith,
+    Size,
+}
+
+impl Method {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "contains" => Some(Method::Contains),
+            "startsWith" => Some(Method::StartsWith),
+            "endsWith" => Some(Method::EndsWith),
+            "size" => Some(Method::Size),
+            _ => None,
+        }
+    }
+
+    /// Result type for the receiver and the argument types, if the call is valid
+    fn ty(&self, receiver: Type, args: &[Type]) -> Option<Type> {
+        match (self, receiver, args) {
+            (Method::Contains, Type::String | Type::List, [Type::String]) => Some(Type::Bool),
+            (Method::StartsWith | Method::EndsWith, Type::String, [Type::String]) => {
+                Some(Type::Bool)
+            }
+            (Method::Size, Type::String | Type::List, []) => Some(Type::Int),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Add,
+    Sub,
+}
+
+impl Op {
+    fn ty(&self, lhs: Type, rhs: Type) -> Option<Type> {
+        match (self, lhs, rhs) {
+            (Op::Or | Op::And, Type::Bool, Type::Bool) => Some(Type::Bool),
+            (Op::Eq | Op::Ne, l, r) if l == r => Some(Type::Bool),
+            (Op::Lt | Op::Le | Op::Gt | Op::Ge, Type::Int, Type::Int)
+            | (Op::Lt | Op::Le | Op::Gt | Op::Ge, Type::String, Type::String) => Some(Type::Bool),
+            (Op::In, Type::String, Type::List) => Some(Type::Bool),
+            (Op::Add, l, r) if l == r && l != Type::Bool => Some(l),
+            (Op::Sub, Type::Int, Type::Int) => Some(Type::Int),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<Expr>),
+    Var(Var),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Method, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(v) => write!(f, "\"{}\"", v),
+            Token::Str(v) => write!(f, "'{}'", v),
+            Token::Int(v) => write!(f, "{}", v),
+            Token::Punct(v) => write!(f, "\"{}\"", v),
+        }
+    }
+}
+
+const PUNCTS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "[", "]", ",", ".", "?", ":", "!", "<", ">", "+",
+    "-",
+];
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, v)) => value.push(v),
+                        None => return Err(ExprError::at(pos, "unterminated string")),
+                    },
+                    Some((_, v)) if v == c => break,
+                    Some((_, v)) => value.push(v),
+                    None => return Err(ExprError::at(pos, "unterminated string")),
+                }
+            }
+            tokens.push((pos, Token::Str(value)));
+        } else if c.is_ascii_digit() {
+            let mut value = String::new();
+            while let Some(&(_, d)) = chars.peek().filter(|(_, d)| d.is_ascii_digit()) {
+                value.push(d);
+                chars.next();
+            }
+            let value = value
+                .parse()
+                .map_err(|_| ExprError::at(pos, "integer is too large"))?;
+            tokens.push((pos, Token::Int(value)));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut value = String::new();
+            while let Some(&(_, d)) = chars
+                .peek()
+                .filter(|(_, d)| d.is_ascii_alphanumeric() || *d == '_')
+            {
+                value.push(d);
+                chars.next();
+            }
+            tokens.push((pos, Token::Ident(value)));
+        } else {
+            let punct = PUNCTS
+                .iter()
+                .find(|p| input[pos..].starts_with(*p))
+                .ok_or_else(|| ExprError::at(pos, format!("unexpected \"{}\"", c)))?;
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+            tokens.push((pos, Token::Punct(punct)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser with the precedence of CEL
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ExprError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> ExprError {
+        match self.peek() {
+            Some(t) => ExprError::at(self.offset(), format!("unexpected {}", t)),
+            None => ExprError::at(self.offset(), "unexpected end"),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ExprError> {
+        let cond = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+
+        let then = self.expr()?;
+        self.expect(":")?;
+        let otherwise = self.expr()?;
+
+        Ok(Expr::Cond(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Operators by precedence, from the loosest
+    const LEVELS: [&'static [(&'static str, Op)]; 4] = [
+        &[("||", Op::Or)],
+        &[("&&", Op::And)],
+        &[
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+            ("in", Op::In),
+        ],
+        &[("+", Op::Add), ("-", Op::Sub)],
+    ];
+
+    fn binary(&mut self, level: usize) -> Result<Expr, ExprError> {
+        if level == Self::LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = Self::LEVELS[level].iter().find(|(s, _)| match self.peek() {
+                Some(Token::Punct(p)) => p == s,
+                Some(Token::Ident(v)) => v == s,
+                _ => false,
+            });
+            let op = match op {
+                Some((_, op)) => *op,
+                None => return Ok(lhs),
+            };
+            self.pos += 1;
+
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+
+        let mut expr = self.primary()?;
+        while self.eat(".") {
+            let offset = self.offset();
+            let method = match self.peek() {
+                Some(Token::Ident(v)) => Method::parse(v)
+                    .ok_or_else(|| ExprError::at(offset, format!("unknown method \"{}\"", v)))?,
+                _ => return Err(self.unexpected()),
+            };
+            self.pos += 1;
+
+            self.expect("(")?;
+            let args = self.list(")")?;
+            expr = Expr::Call(Box::new(expr), method, args);
+        }
+
+        Ok(expr)
+    }
+
+    /// Comma separated expressions up to the closing punctuation
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>, ExprError> {
+        let mut items = Vec::new();
+        if self.eat(close) {
+            return Ok(items);
+        }
+
+        loop {
+            items.push(self.expr()?);
+            if self.eat(close) {
+                return Ok(items);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        let offset = self.offset();
+        let token = match self.tokens.get(self.pos) {
+            Some((_, t)) => t.clone(),
+            None => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Int(v) => Ok(Expr::Int(v)),
+            Token::Str(v) => Ok(Expr::Str(v)),
+            Token::Ident(v) if v == "true" => Ok(Expr::Bool(true)),
+            Token::Ident(v) if v == "false" => Ok(Expr::Bool(false)),
+            Token::Ident(v) => Var::parse(&v)
+                .map(Expr::Var)
+                .ok_or_else(|| ExprError::at(offset, format!("unknown variable \"{}\"", v))),
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => Ok(Expr::List(self.list("]")?)),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected())
+            }
+        }
+    }
+}
+
+fn check(expr: &Expr) -> Result<Type, ExprError> {
+    let mismatch = |what: String| ExprError(format!("type mismatch: {}", what));
+
+    match expr {
+        Expr::Bool(_) => Ok(Type::Bool),
+        Expr::Int(_) => Ok(Type::Int),
+        Expr::Str(_) => Ok(Type::String),
+        Expr::List(items) => {
+            for item in items {
+                let ty = check(item)?;
+                if ty != Type::String {
+                    return Err(mismatch(format!("list of {}", ty)));
+                }
+            }
+            Ok(Type::List)
+        }
+        Expr::Var(v) => Ok(v.ty()),
+        Expr::Not(v) => match check(v)? {
+            Type::Bool => Ok(Type::Bool),
+            ty => Err(mismatch(format!("!{}", ty))),
+        },
+        Expr::Neg(v) => match check(v)? {
+            Type::Int => Ok(Type::Int),
+            ty => Err(mismatch(format!("-{}", ty))),
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (check(lhs)?, check(rhs)?);
+            op.ty(lhs, rhs)
+                .ok_or_else(|| mismatch(format!("{} {:?} {}", lhs, op, rhs)))
+        }
+        Expr::Cond(cond, then, otherwise) => {
+            let cond = check(cond)?;
+            if cond != Type::Bool {
+                return Err(mismatch(format!("condition of {}", cond)));
+            }
+            let (then, otherwise) = (check(then)?, check(otherwise)?);
+            if then != otherwise {
+                return Err(mismatch(format!("branches of {} and {}", then, otherwise)));
+            }
+            Ok(then)
+        }
+        Expr::Call(receiver, method, args) => {
+            let receiver = check(receiver)?;
+            let args = args.iter().map(check).collect::<Result<Vec<_>, _>>()?;
+            method
+                .ty(receiver, &args)
+                .ok_or_else(|| mismatch(format!("{:?} of {}", method, receiver)))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<String>),
+}
+
+impl From<Value> for Json {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(v) => v.into(),
+            Value::Int(v) => v.into(),
+            Value::Str(v) => v.into(),
+            Value::List(v) => v.into(),
+        }
+    }
+}
+
+/// Subject of a service token, as seen by expressions
+#[derive(Debug, Clone, Copy)]
+pub struct ClaimContext<'a> {
+    pub sub: &'a str,
+    pub client: &'a str,
+    pub email: &'a str,
+    pub roles: &'a [String],
+    pub scope: &'a [String],
+}
+
+/// Type checked expression
+#[derive(Debug, Clone)]
+pub struct Expression(Expr);
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+            end: input.len(),
+        };
+
+        let expr = parser.expr()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected());
+        }
+        check(&expr)?;
+
+        Ok(Self(expr))
+    }
+
+    pub fn eval(&self, ctx: &ClaimContext) -> Json {
+        eval(&self.0, ctx).into()
+    }
+}
+
+/// Evaluates a type checked expression, which can't fail
+fn eval(expr: &Expr, ctx: &ClaimContext) -> Value {
+    let bool = |e: &Expr| matches!(eval(e, ctx), Value::Bool(true));
+    let int = |e: &Expr| match eval(e, ctx) {
+        Value::Int(v) => v,
+        _ => 0,
+    };
+
+    match expr {
+        Expr::Bool(v) => Value::Bool(*v),
+        Expr::Int(v) => Value::Int(*v),
+        Expr::Str(v) => Value::Str(v.clone()),
+        Expr::List(items) => Value::List(
+            items
+                .iter()
+                .filter_map(|e| match eval(e, ctx) {
+                    Value::Str(v) => Some(v),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        Expr::Var(var) => match var {
+            Var::Sub => Value::Str(ctx.sub.to_string()),
+            Var::Client => Value::Str(ctx.client.to_string()),
+            Var::Email => Value::Str(ctx.email.to_string()),
+            Var::Roles => Value::List(ctx.roles.to_vec()),
+            Var::Scope => Value::List(ctx.scope.to_vec()),
+        },
+        Expr::Not(v) => Value::Bool(!bool(v)),
+        Expr::Neg(v) => Value::Int(int(v).saturating_neg()),
+        Expr::Binary(Op::Or, lhs, rhs) => Value::Bool(bool(lhs) || bool(rhs)),
+        Expr::Binary(Op::And, lhs, rhs) => Value::Bool(bool(lhs) && bool(rhs)),
+        Expr::Binary(op, lhs, rhs) => match (op, eval(lhs, ctx), eval(rhs, ctx)) {
+            (Op::Eq, l, r) => Value::Bool(l == r),
+            (Op::Ne, l, r) => Value::Bool(l != r),
+            (Op::Lt, Value::Int(l), Value::Int(r)) => Value::Bool(l < r),
+            (Op::Le, Value::Int(l), Value::Int(r)) => Value::Bool(l <= r),
+            (Op::Gt, Value::Int(l), Value::Int(r)) => Value::Bool(l > r),
+            (Op::Ge, Value::Int(l), Value::Int(r)) => Value::Bool(l >= r),
+            (Op::Lt, Value::Str(l), Value::Str(r)) => Value::Bool(l < r),
+            (Op::Le, Value::Str(l), Value::Str(r)) => Value::Bool(l <= r),
+            (Op::Gt, Value::Str(l), Value::Str(r)) => Value::Bool(l > r),
+            (Op::Ge, Value::Str(l), Value::Str(r)) => Value::Bool(l >= r),
+            (Op::In, Value::Str(l), Value::List(r)) => Value::Bool(r.contains(&l)),
+            (Op::Add, Value::Int(l), Value::Int(r)) => Value::Int(l.saturating_add(r)),
+            (Op::Add, Value::Str(l), Value::Str(r)) => Value::Str(l + &r),
+            (Op::Add, Value::List(l), Value::List(r)) => Value::List([l, r].concat()),
+            (Op::Sub, Value::Int(l), Value::Int(r)) => Value::Int(l.saturating_sub(r)),
+            _ => Value::Bool(false),
+        },
+        Expr::Cond(cond, then, otherwise) => {
+            if bool(cond) {
+                eval(then, ctx)
+            } else {
+                eval(otherwise, ctx)
+            }
+        }
+        Expr::Call(receiver, method, args) => {
+            let arg = args.first().map(|e| eval(e, ctx));
+            match (method, eval(receiver, ctx), arg) {
+                (Method::Contains, Value::Str(v), Some(Value::Str(a))) => {
+                    Value::Bool(v.contains(&a))
+                }
+                (Method::Contains, Value::List(v), Some(Value::Str(a))) => {
+                    Value::Bool(v.contains(&a))
+                }
+                (Method::StartsWith, Value::Str(v), Some(Value::Str(a))) => {
+                    Value::Bool(v.starts_with(&a))
+                }
+                (Method::EndsWith, Value::Str(v), Some(Value::Str(a))) => {
+                    Value::Bool(v.ends_with(&a))
+                }
+                (Method::Size, Value::Str(v), _) => Value::Int(v.chars().count() as i64),
+                (Method::Size, Value::List(v), _) => Value::Int(v.len() as i64),
+                _ => Value::Bool(false),
+            }
+        }
+    }
+}
+
+/// Claim expressions by service
+#[derive(Debug, Clone, Default)]
+pub struct ClaimRules(Arc<HashMap<ObjectId, Vec<(String, Expression)>>>);
+
+impl ClaimRules {
+    /// Derived claims of a token of the service
+    pub fn apply(&self, service: ObjectId, ctx: &ClaimContext) -> Map<String, Json> {
+        self.0
+            .get(&service)
+            .into_iter()
+            .flatten()
+            .map(|(name, expr)| (name.clone(), expr.eval(ctx)))
+            .collect()
+    }
+}
+
+/// Deserializes the expressions from a JSON object of claims by service ID
+pub fn de_rules<'de, D>(d: D) -> std::result::Result<ClaimRules, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    let services: HashMap<ObjectId, BTreeMap<String, String>> =
+        serde_json::from_str(&input).map_err(serde::de::Error::custom)?;
+
+    let mut rules = HashMap::new();
+    for (service, claims) in services {
+        let mut exprs = Vec::new();
+        for (name, source) in claims {
+            if RESERVED_CLAIMS.contains(&name.as_str()) {
+                return Err(serde::de::Error::custom(format!(
+                    "claim \"{}\" of service {} is reserved",
+                    name, service
+                )));
+            }
+
+            let expr = Expression::parse(&source).map_err(|e| {
+                serde::de::Error::custom(format!(
+                    "claim \"{}\" of service {} is invalid: {}",
+                    name, service, e
+                ))
+            })?;
+            exprs.push((name, expr));
+        }
+        rules.insert(service, exprs);
+    }
+
+    Ok(ClaimRules(Arc::new(rules)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(input: &str) -> Json {
+        let roles = vec!["userEditor".to_string()];
+        let scope = vec!["read".to_string(), "write".to_string()];
+        let ctx = ClaimContext {
+            sub: "5f1c0e0e0e0e0e0e0e0e0e0e",
+            client: "5f1c0e0e0e0e0e0e0e0e0e0f",
+            email: "staff@example.com",
+            roles: &roles,
+            scope: &scope,
+        };
+
+        Expression::parse(input).unwrap().eval(&ctx)
+    }
+
+    #[test]
+    fn evaluates() {
+        assert_eq!(
+            eval("roles.contains('userEditor') ? 'internal' : 'external'"),
+            "internal"
+        );
+        assert_eq!(
+            eval("email.endsWith('@example.com') && !('admin' in scope)"),
+            true
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 == 3 || false"), true);
+        assert_eq!(eval("false ? 1 : true ? 2 : 3"), 2);
+        assert_eq!(
+            eval("scope + ['admin']"),
+            serde_json::json!(["read", "write", "admin"])
+        );
+        assert_eq!(eval("-(scope.size() - 5)"), 3);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for input in [
+            "roles.contains(1)",
+            "true ? 'a' : 1",
+            "email + 1",
+            "unknown == 'a'",
+            "sub.length()",
+            "'a' ==",
+            "('a'",
+            "'a' 'b'",
+            "[1, 2]",
+            "scope.size() ? 1 : 2",
+        ] {
+            assert!(Expression::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rules_by_service() {
+        use serde::de::value::{Error as DeError, StrDeserializer};
+        use serde::de::IntoDeserializer;
+
+        let input =
+            r#"{"5f1c0e0e0e0e0e0e0e0e0e0e":{"tier":"scope.size() > 1 ? 'gold' : 'basic'"}}"#;
+        let de: StrDeserializer<DeError> = input.into_deserializer();
+        let rules = de_rules(de).unwrap();
+
+        let scope = vec!["read".to_string(), "write".to_string()];
+        let ctx = ClaimContext {
+            sub: "5f1c0e0e0e0e0e0e0e0e0e0f",
+            client: "5f1c0e0e0e0e0e0e0e0e0e0f",
+            email: "",
+            roles: &[],
+            scope: &scope,
+        };
+        let service = ObjectId::parse_str("5f1c0e0e0e0e0e0e0e0e0e0e").unwrap();
+
+        assert_eq!(rules.apply(service, &ctx)["tier"], "gold");
+        assert!(rules.apply(ObjectId::new(), &ctx).is_empty());
+
+        let reserved = r#"{"5f1c0e0e0e0e0e0e0e0e0e0e":{"sub":"'admin'"}}"#;
+        let de: StrDeserializer<DeError> = reserved.into_deserializer();
+        assert!(de_rules(de).is_err());
+    }
+}
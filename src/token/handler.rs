@@ -1,26 +1,38 @@
 use crate::{
+    action::ActionError,
+    audit::{AuditEventDocument, SecurityEvent},
     authentication::{
-        self,
+        client_secret,
+        credential::CredentialHasher,
         token::{TokenClaims, TokenConfig},
+        AuthenticationError,
     },
-    client::ClientError,
+    client::{self, ClientError},
+    config::GlobalConfig,
     database::Database,
-    extract::{SizedJson, TokenData},
+    error::Error,
+    extract::{ClientInfo, ContentLengthLimit, Json, SizedJson, TokenData},
+    metrics::Metrics,
     model::Response,
-    session::SessionClaims,
-    token::{ClientClaims, ServiceClaims},
-    user::UserError,
+    service::ServiceDocument,
+    session::{check_access, check_schedule, Access, Resource, SessionClaims, SessionError},
+    token::{ci::Grant, issue_service_token, CiTrust, ClientClaims},
+    user::{SecurityNotifier, UserError},
     utils::crypto::Aead256,
 };
-
-use std::iter::FromIterator;
+#[cfg(feature = "federation")]
+use crate::{
+    federation::{Federation, Identity},
+    session::{issue_session, SessionResponse},
+    sso::get_or_create_user,
+};
 
 use axum::extract::Extension;
 use chrono::{serde::ts_seconds, DateTime, Utc};
 use hyper::StatusCode;
-use jsonwebtoken::{encode, EncodingKey};
 use mongodb::bson::{doc, oid::ObjectId};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -30,47 +42,147 @@ pub struct TokenResponse {
     expires_at: DateTime<Utc>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn get(
+    info: ClientInfo,
     TokenData(claims): TokenData<ClientClaims>,
     Extension(db): Extension<Database>,
     Extension(enc): Extension<Aead256>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(metrics): Extension<Metrics>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<TokenResponse>> {
     let client_id = ObjectId::parse_str(&claims.sub).map_err(|_| ClientError::InvalidId)?;
 
     let client = db.get_client(doc! { "_id": client_id }).await?;
+    if client.canary {
+        client::trip_canary(
+            &db,
+            &notifier,
+            &client,
+            &info,
+            global.canary_auto_lock,
+            config.clock.now(),
+        )
+        .await?;
+        return Err(ClientError::Locked.into());
+    }
     let svc = db.get_service(doc! { "_id": client.service }).await?;
 
     if !client.unlocked {
         return Err(ClientError::Locked.into());
     }
+    if client.legacy_token_disabled {
+        return Err(ClientError::LegacyFlowDisabled.into());
+    }
+    db.check_client_schedule(&client, &info, config.clock.now())
+        .await?;
 
-    let header = jsonwebtoken::Header::new(config.alg);
+    db.ensure_service_alive(&svc, config.clock.now()).await?;
 
-    let key = if let Some(s) = svc.secret {
-        let secret = enc.decrypt(base64::decode_config(&s, base64::STANDARD).unwrap());
-        EncodingKey::from_secret(&secret)
-    } else {
-        config.enc_key
-    };
+    let response = service_token(&enc, &config, svc, &claims.sub, client.scope)?;
+
+    db.set_client_issued(client_id).await?;
+    metrics.record_legacy_token(&claims.sub, "service");
 
-    let audience = if !svc.audience.is_empty() {
-        svc.audience
-    } else {
-        Vec::from_iter(config.validation.aud.to_owned().unwrap())
+    let event = SecurityEvent::TokenIssued {
+        client: claims.sub,
+        grant: "service".to_string(),
     };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, &info))
+        .await?;
 
-    let claims = ServiceClaims::with_scope(audience, &claims.sub, client.scope);
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
 
-    let token = encode(&header, &claims, &key).map_err(authentication::token::TokenError::from)?;
+/// Issues a token of the service with the scope of the client
+fn service_token(
+    enc: &Aead256,
+    config: &TokenConfig,
+    svc: ServiceDocument,
+    client: &str,
+    scope: Vec<String>,
+) -> crate::Result<TokenResponse> {
+    let (token, expires_at) = issue_service_token(enc, config, svc, client, None, None, scope)?;
 
-    let response = TokenResponse {
-        token,
-        expires_at: claims.exp,
+    Ok(TokenResponse { token, expires_at })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCredentialsRequest {
+    client: String,
+    secret: String,
+}
+
+/// Issues a service token for the secret of a client, for machine-to-machine use
+#[allow(clippy::too_many_arguments)]
+pub async fn client_credentials(
+    info: ClientInfo,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<Json<ClientCredentialsRequest>, 1024>,
+    Extension(db): Extension<Database>,
+    Extension(enc): Extension<Aead256>,
+    Extension(hasher): Extension<CredentialHasher>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<TokenResponse>> {
+    let client_id =
+        ObjectId::parse_str(&body.client).map_err(|_| AuthenticationError::BadClientCredentials)?;
+
+    let client = match db.get_client(doc! { "_id": client_id }).await {
+        Ok(c) => c,
+        Err(Error::Client(ClientError::NotFound)) => {
+            return Err(AuthenticationError::BadClientCredentials.into())
+        }
+        Err(e) => return Err(e),
     };
 
+    let rehash = client_secret::verify(&hasher, &body.secret, client.secret.as_deref())?;
+    if let Some(hash) = rehash {
+        db.rehash_client_secret(client_id, hash).await?;
+    }
+
+    if client.canary {
+        client::trip_canary(
+            &db,
+            &notifier,
+            &client,
+            &info,
+            global.canary_auto_lock,
+            config.clock.now(),
+        )
+        .await?;
+        return Err(ClientError::Locked.into());
+    }
+    if !client.unlocked {
+        return Err(ClientError::Locked.into());
+    }
+    let now = config.clock.now();
+    db.check_client_schedule(&client, &info, now).await?;
+
+    // The owner may have been locked or lost the verified address since the secret was issued
+    let owner = db.get_user(doc! { "_id": client.user }).await?;
+    if !owner.verified {
+        return Err(ActionError::NotVerified.into());
+    }
+    check_access(&owner, now)?;
+
+    let svc = db.get_service(doc! { "_id": client.service }).await?;
+    db.ensure_service_alive(&svc, now).await?;
+
+    let response = service_token(&enc, &config, svc, &body.client, client.scope)?;
+
     db.set_client_issued(client_id).await?;
 
+    let event = SecurityEvent::TokenIssued {
+        client: body.client,
+        grant: "clientCredentials".to_string(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(client.user, event, &info))
+        .await?;
+
     Ok(Response::with_status(StatusCode::CREATED, response))
 }
 
@@ -80,25 +192,57 @@ pub struct CreateRequest {
     client: String,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create(
+    info: ClientInfo,
     TokenData(claims): TokenData<SessionClaims>,
     SizedJson(body): SizedJson<CreateRequest>,
     Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(metrics): Extension<Metrics>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<TokenResponse>> {
-    let client_id = ObjectId::parse_str(&claims.sub).map_err(|_| ClientError::InvalidId)?;
+    let client_id = ObjectId::parse_str(&body.client).map_err(|_| ClientError::InvalidId)?;
     let user_id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
 
     let client = db
         .get_client(doc! {"_id": client_id, "user": user_id })
         .await?;
+    if client.canary {
+        client::trip_canary(
+            &db,
+            &notifier,
+            &client,
+            &info,
+            global.canary_auto_lock,
+            config.clock.now(),
+        )
+        .await?;
+        return Err(ClientError::Locked.into());
+    }
 
     if !client.unlocked {
         return Err(ClientError::Locked.into());
     }
+    if client.legacy_token_disabled {
+        return Err(ClientError::LegacyFlowDisabled.into());
+    }
+
+    // The address may have changed since the login
+    let user = db.get_user(doc! { "_id": user_id }).await?;
+    if !user.verified {
+        return Err(ActionError::NotVerified.into());
+    }
 
     let audience = config.validation.aud.clone().unwrap();
-    let claims = ClientClaims::new(audience, &body.client, &claims.sub);
+    let claims = ClientClaims::new(
+        audience,
+        &body.client,
+        &claims.sub,
+        config.clock.now(),
+        config.epoch.current(),
+    );
 
     let token = claims.encode(&config)?;
 
@@ -108,6 +252,150 @@ pub async fn create(
     };
 
     db.set_client_issued(client_id).await?;
+    metrics.record_legacy_token(&body.client, "client");
+
+    let event = SecurityEvent::TokenIssued {
+        client: body.client,
+        grant: "client".to_string(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user_id, event, &info))
+        .await?;
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+/// Issues a token of a canary client, to be planted where leaked credentials would be used
+pub async fn create_canary(
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<CreateRequest>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<TokenResponse>> {
+    if !claims.is_global(Resource::Client, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let client_id = ObjectId::parse_str(&body.client).map_err(|_| ClientError::InvalidId)?;
+    let client = db
+        .get_client(doc! { "_id": client_id, "canary": true })
+        .await?;
+
+    let audience = config.validation.aud.clone().unwrap();
+    let claims = ClientClaims::new(
+        audience,
+        &body.client,
+        &client.user.to_hex(),
+        config.clock.now(),
+        config.epoch.current(),
+    );
+
+    let response = TokenResponse {
+        token: claims.encode(&config)?,
+        expires_at: claims.exp,
+    };
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CiRequest {
+    token: String,
+}
+
+/// Exchanges the OIDC token of a CI pipeline for a short-lived token
+pub async fn exchange_ci(
+    info: ClientInfo,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<Json<CiRequest>, 8192>,
+    Extension(db): Extension<Database>,
+    Extension(ci): Extension<CiTrust>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<TokenResponse>> {
+    let (policy, ci_claims) = ci.verify(&body.token).await?;
+
+    let now = config.clock.now();
+    let audience = config.validation.aud.clone().unwrap();
+
+    let (token, expires_at) = match &policy.grant {
+        Grant::Session { user, scope } => {
+            let user_id = ObjectId::parse_str(user).map_err(|_| UserError::InvalidId)?;
+            let user = db.get_user(doc! { "_id": user_id }).await?;
+
+            if !user.can_login {
+                return Err(SessionError::NotAuthorized(
+                    "user is not authorized to log in".to_string(),
+                )
+                .into());
+            }
+
+            check_schedule(&db, &config, &user, &info).await?;
+
+            let mut claims = SessionClaims::issue_for_user(&config, &user, now).await?;
+            claims.scope.retain(|s| scope.contains(s));
+            claims.set_expiration(claims.exp.min(now + policy.ttl()));
+
+            (claims.encode(&config)?, claims.exp)
+        }
+        Grant::Client(client) => {
+            let client_id = ObjectId::parse_str(client).map_err(|_| ClientError::InvalidId)?;
+            let target = db.get_client(doc! { "_id": client_id }).await?;
+
+            if !target.unlocked {
+                return Err(ClientError::Locked.into());
+            }
+            if !db.get_user(doc! { "_id": target.user }).await?.verified {
+                return Err(ActionError::NotVerified.into());
+            }
+            db.check_client_schedule(&target, &info, now).await?;
+
+            let mut claims = ClientClaims::new(
+                audience,
+                &client_id.to_hex(),
+                &target.user.to_hex(),
+                now,
+                config.epoch.current(),
+            );
+            claims.exp = now + policy.ttl();
+
+            db.set_client_issued(client_id).await?;
+
+            (claims.encode(&config)?, claims.exp)
+        }
+    };
+
+    info!(
+        issuer = %policy.issuer,
+        subject = ci_claims.get("sub").and_then(|v| v.as_str()).unwrap_or("-"),
+        "CI token exchanged"
+    );
+
+    let response = TokenResponse { token, expires_at };
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+#[cfg(feature = "federation")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FederateRequest {
+    token: String,
+}
+
+#[cfg(feature = "federation")]
+pub async fn federate(
+    client: ClientInfo,
+    ContentLengthLimit(Json(body)): ContentLengthLimit<Json<FederateRequest>, 8192>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(federation): Extension<Federation>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<SessionResponse>> {
+    let Identity { email, connection } = federation.verify(&body.token).await?;
+
+    let method = connection.type_name();
+    let user = get_or_create_user(&db, &global, email, connection).await?;
+
+    let response = issue_session(&db, &config, &user, &client, method).await?;
 
     Ok(Response::with_status(StatusCode::CREATED, response))
 }
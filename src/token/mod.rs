@@ -1,26 +1,50 @@
+mod ci;
+mod expr;
 mod handler;
 mod routes;
 
 use crate::{
-    authentication::token::{TokenClaims, TokenType},
-    error,
+    authentication::{
+        token::{self, TokenClaims, TokenConfig, TokenType},
+        AuthenticationError,
+    },
+    database::Database,
+    epoch, error,
+    extract::ClientInfo,
     model::Status,
+    service::ServiceDocument,
+    user::{age_over_claim, UserDocument},
+    utils::crypto::Aead256,
 };
 
+use axum::async_trait;
 use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
 use hyper::StatusCode;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 pub use routes::routes;
 
+pub use ci::{de_policies as de_ci_policies, CiPolicy, CiTrust};
+pub use expr::{de_rules as de_claim_rules, ClaimContext, ClaimRules};
+
 #[derive(Debug, thiserror::Error)]
-pub enum TokenError {}
+pub enum TokenError {
+    #[error("issuer is not trusted")]
+    UntrustedIssuer,
+    #[error("token matches no policy")]
+    NoMatchingPolicy,
+}
 
 impl error::ErrorResponse for TokenError {
     type Response = Status;
 
     fn status_code(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            TokenError::UntrustedIssuer => StatusCode::UNAUTHORIZED,
+            TokenError::NoMatchingPolicy => StatusCode::FORBIDDEN,
+        }
     }
 
     fn error_response(&self) -> Self::Response {
@@ -38,33 +62,55 @@ pub struct ClientClaims {
     pub iat: DateTime<Utc>,
     pub sub: String,
     pub iss: String,
+    /// Token epoch of the deployment at issuance
+    #[serde(default, skip_serializing_if = "epoch::is_initial")]
+    pub epoch: u32,
     token_type: TokenType,
 }
 
 impl ClientClaims {
     pub const DEFAULT_EXP_DAYS: i64 = 365;
 
-    fn new<A>(aud: A, sub: &str, iss: &str) -> Self
+    fn new<A>(aud: A, sub: &str, iss: &str, now: DateTime<Utc>, epoch: u32) -> Self
     where
         A: IntoIterator<Item = String>,
     {
         Self {
             aud: aud.into_iter().collect(),
-            exp: Utc::now() + Duration::days(Self::DEFAULT_EXP_DAYS),
-            iat: Utc::now(),
+            exp: now + Duration::days(Self::DEFAULT_EXP_DAYS),
+            iat: now,
             sub: sub.into(),
             iss: iss.into(),
+            epoch,
             token_type: Self::TOKEN_TYPE,
         }
     }
 }
 
+#[async_trait]
 impl TokenClaims for ClientClaims {
     const TOKEN_TYPE: TokenType = TokenType::Client;
 
     fn get_type(&self) -> &TokenType {
         &self.token_type
     }
+
+    fn subject(&self) -> Option<&str> {
+        Some(&self.sub)
+    }
+
+    async fn validate(
+        &self,
+        _db: &Database,
+        config: &TokenConfig,
+        _client: &ClientInfo,
+    ) -> crate::Result<()> {
+        if !config.epoch.is_valid(self.epoch) {
+            return Err(AuthenticationError::from(token::TokenError::Revoked).into());
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -76,34 +122,96 @@ pub struct ServiceClaims {
     #[serde(with = "ts_seconds")]
     pub iat: DateTime<Utc>,
     pub sub: String,
+    /// Client acting on behalf of the subject
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub azp: Option<String>,
     #[serde(default)]
     pub scope: Vec<String>,
+    /// Claims derived by the expressions of the service and `age_over_<n>` of its age gate
+    #[serde(flatten)]
+    pub custom: Map<String, Value>,
 }
 
 impl ServiceClaims {
     pub const DEFAULT_EXP_MIN: i64 = 30;
 
-    fn new<A>(aud: A, sub: &str) -> Self
+    fn new<A>(aud: A, sub: &str, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
     {
         Self {
             aud: aud.into_iter().collect(),
-            exp: Utc::now() + Duration::minutes(Self::DEFAULT_EXP_MIN),
-            iat: Utc::now(),
+            exp: now + Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
             sub: sub.into(),
+            azp: None,
             scope: Vec::default(),
+            custom: Map::new(),
         }
     }
 
-    fn with_scope<A, S>(aud: A, sub: &str, scope: S) -> Self
+    fn with_scope<A, S>(aud: A, sub: &str, scope: S, now: DateTime<Utc>) -> Self
     where
         A: IntoIterator<Item = String>,
         S: IntoIterator<Item = String>,
     {
-        let mut claims = Self::new(aud, sub);
+        let mut claims = Self::new(aud, sub, now);
         claims.scope = scope.into_iter().collect();
 
         claims
     }
 }
+
+/// Issues a token of the service with the scope granted to the subject, which is the user if
+/// a client acts on its behalf
+pub fn issue_service_token(
+    enc: &Aead256,
+    config: &TokenConfig,
+    svc: ServiceDocument,
+    sub: &str,
+    azp: Option<&str>,
+    user: Option<&UserDocument>,
+    scope: Vec<String>,
+) -> crate::Result<(String, DateTime<Utc>)> {
+    let audience = if !svc.audience.is_empty() {
+        svc.audience
+    } else {
+        Vec::from_iter(config.validation.aud.to_owned().unwrap())
+    };
+
+    let mut claims = ServiceClaims::with_scope(audience, sub, scope, config.clock.now());
+    claims.azp = azp.map(String::from);
+
+    let roles: Vec<String> = user
+        .and_then(|u| serde_json::to_value(&u.roles).ok())
+        .and_then(|v| serde_json::from_value(v).ok())
+        .unwrap_or_default();
+    let ctx = ClaimContext {
+        sub,
+        client: azp.unwrap_or(sub),
+        email: user.map_or("", |u| u.email.as_str()),
+        roles: &roles,
+        scope: &claims.scope,
+    };
+    claims.custom = config.claim_rules.apply(svc.id, &ctx);
+    if let (Some(min_age), Some(age)) = (
+        svc.min_age,
+        user.and_then(|u| u.age(claims.iat.date().naive_utc())),
+    ) {
+        claims
+            .custom
+            .insert(age_over_claim(min_age), (age >= min_age.into()).into());
+    }
+
+    // Services with their own secret verify with it, regardless of the signing key
+    let token = if let Some(s) = svc.secret {
+        let secret = enc.decrypt(base64::decode_config(&s, base64::STANDARD).unwrap())?;
+        let header = Header::new(Algorithm::HS256);
+        encode(&header, &claims, &EncodingKey::from_secret(&secret))
+            .map_err(token::TokenError::from)?
+    } else {
+        config.encode(&claims)?
+    };
+
+    Ok((token, claims.exp))
+}
@@ -1,8 +1,17 @@
 use super::handler;
 
-use axum::routing::get;
+use axum::routing::{get, post};
 
 /// Token routes
 pub fn routes() -> axum::Router {
-    axum::Router::new().route("/", get(handler::get).post(handler::create))
+    let router = axum::Router::new()
+        .route("/", get(handler::get).post(handler::create))
+        .route("/canary", post(handler::create_canary))
+        .route("/client-credentials", post(handler::client_credentials))
+        .route("/ci", post(handler::exchange_ci));
+
+    #[cfg(feature = "federation")]
+    let router = router.route("/federate", post(handler::federate));
+
+    router
 }
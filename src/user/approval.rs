@@ -0,0 +1,323 @@
+//! Four-eyes principle for sensitive roles.
+//!
+//! Granting a configured sensitive role doesn't apply it, the grant waits in a queue until a
+//! second admin approves or rejects it. This holds for updates, temporary elevations and
+//! reverts of the role history alike. Every step is recorded as security event of the user.
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    database::Database,
+    error,
+    extract::ClientInfo,
+    model::Status,
+    models::user::ApprovalResponse,
+    Result,
+};
+
+use super::{ElevationDocument, Role, SecurityNotifier};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use futures::stream::TryStreamExt;
+use hyper::StatusCode;
+use mongodb::{
+    bson::{
+        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_bson,
+        DateTime as BsonDateTime,
+    },
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApprovalError {
+    #[error("approval not found")]
+    NotFound,
+    #[error("approval id is invalid")]
+    InvalidId,
+    #[error("role changes can't be approved by their requester")]
+    SelfApproval,
+}
+
+impl error::ErrorResponse for ApprovalError {
+    type Response = Status;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApprovalError::NotFound => StatusCode::NOT_FOUND,
+            ApprovalError::InvalidId => StatusCode::BAD_REQUEST,
+            ApprovalError::SelfApproval => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        Status::new(self.status_code(), self.to_string())
+    }
+}
+
+/// Roles of `requested` that are sensitive and not held yet, they need an approval
+pub fn sensitive_additions(sensitive: &[Role], current: &[Role], requested: &[Role]) -> Vec<Role> {
+    requested
+        .iter()
+        .filter(|r| sensitive.contains(r) && !current.contains(r))
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalState {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApprovalDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    /// Roles added once approved
+    pub roles: Vec<Role>,
+    pub requested_by: ObjectId,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub requested_at: DateTime<Utc>,
+    pub state: ApprovalState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_by: Option<ObjectId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_at: Option<BsonDateTime>,
+    /// Temporary grant, approving it starts the elevation instead of adding the roles for good
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elevation: Option<ElevationDocument>,
+}
+
+impl ApprovalDocument {
+    pub fn new(
+        user: ObjectId,
+        roles: Vec<Role>,
+        requested_by: ObjectId,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: ObjectId::new(),
+            user,
+            roles,
+            requested_by,
+            requested_at: now,
+            state: ApprovalState::Pending,
+            decided_by: None,
+            decided_at: None,
+            elevation: None,
+        }
+    }
+
+    /// Approval of a temporary grant, it expires as requested no matter when it is approved
+    pub fn elevation(elevation: ElevationDocument, now: DateTime<Utc>) -> Self {
+        let mut approval = Self::new(
+            elevation.user,
+            vec![elevation.role.clone()],
+            elevation.granted_by,
+            now,
+        );
+        approval.elevation = Some(elevation);
+
+        approval
+    }
+}
+
+impl From<ApprovalDocument> for ApprovalResponse {
+    fn from(doc: ApprovalDocument) -> Self {
+        Self {
+            id: doc.id.to_hex(),
+            user: doc.user.to_hex(),
+            roles: doc.roles,
+            requested_by: doc.requested_by.to_hex(),
+            requested_at: doc.requested_at,
+            expires_at: doc.elevation.map(|e| e.expires_at),
+        }
+    }
+}
+
+/// Queues the grant, the security contacts are asked to decide
+pub async fn request(
+    db: &Database,
+    notifier: &SecurityNotifier,
+    approval: ApprovalDocument,
+    client: &ClientInfo,
+) -> Result<ApprovalDocument> {
+    let (user, requested_by) = (approval.user, approval.requested_by);
+    db.insert_approval(&approval).await?;
+
+    let event = SecurityEvent::RoleChangeRequested {
+        roles: approval.roles.clone(),
+    };
+    db.insert_audit_event(&AuditEventDocument::new(user, event, client))
+        .await?;
+
+    info!(user = %user, roles = ?approval.roles, by = %requested_by, "role change requested");
+    notify(notifier, &approval).await;
+
+    Ok(approval)
+}
+
+/// Failures are only logged, the request itself is recorded already
+pub async fn notify(notifier: &SecurityNotifier, approval: &ApprovalDocument) {
+    const TEMPLATE_NAME: &str = "identity.security.role-approval";
+
+    let subject = match approval.state {
+        ApprovalState::Pending => "Role change awaits approval",
+        ApprovalState::Approved => "Role change approved",
+        ApprovalState::Rejected => "Role change rejected",
+    };
+
+    let roles = approval
+        .roles
+        .iter()
+        .map(|r| format!("{:?}", r))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut vars = HashMap::with_capacity(5);
+    vars.insert("approval".to_string(), approval.id.to_hex());
+    vars.insert("user".to_string(), approval.user.to_hex());
+    vars.insert("roles".to_string(), roles);
+    vars.insert("requestedBy".to_string(), approval.requested_by.to_hex());
+    if let Some(v) = approval.decided_by {
+        vars.insert("decidedBy".to_string(), v.to_hex());
+    }
+
+    notifier.send(subject, TEMPLATE_NAME, vars).await;
+}
+
+const COLLECTION: &str = "role_approvals";
+
+impl Database {
+    pub async fn insert_approval(&self, approval: &ApprovalDocument) -> Result<()> {
+        self.collection::<ApprovalDocument>(COLLECTION)
+            .insert_one(approval, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Pending approvals, oldest first
+    pub async fn get_pending_approvals(&self) -> Result<Vec<ApprovalDocument>> {
+        let opts = FindOptions::builder()
+            .sort(doc! { "requestedAt": 1 })
+            .build();
+
+        let cursor = self
+            .collection::<ApprovalDocument>(COLLECTION)
+            .find(doc! { "state": "pending" }, opts)
+            .await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Decides a pending approval, approved roles are added to the user or elevated
+    pub async fn decide_approval(
+        &self,
+        id: ObjectId,
+        approver: ObjectId,
+        state: ApprovalState,
+        now: DateTime<Utc>,
+    ) -> Result<ApprovalDocument> {
+        let coll = self.collection::<ApprovalDocument>(COLLECTION);
+
+        let approval = coll
+            .find_one(doc! { "_id": id, "state": "pending" }, None)
+            .await?
+            .ok_or(ApprovalError::NotFound)?;
+        if approval.requested_by == approver {
+            return Err(ApprovalError::SelfApproval.into());
+        }
+
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        // Only one admin decides, a concurrent decision finds it no longer pending
+        let approval = coll
+            .find_one_and_update(
+                doc! { "_id": id, "state": "pending" },
+                doc! { "$set": {
+                    "state": to_bson(&state).unwrap(),
+                    "decidedBy": approver,
+                    "decidedAt": BsonDateTime::from_chrono(now),
+                } },
+                opts,
+            )
+            .await?
+            .ok_or(ApprovalError::NotFound)?;
+
+        if state != ApprovalState::Approved {
+            return Ok(approval);
+        }
+
+        if let Some(elevation) = &approval.elevation {
+            self.grant_elevation(elevation).await?;
+        } else {
+            let update = doc! {
+                "$addToSet": { "roles": { "$each": to_bson(&approval.roles).unwrap() } }
+            };
+            self.modify_user(doc! { "_id": approval.user }, update)
+                .await?;
+        }
+
+        Ok(approval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn only_new_sensitive_roles_need_approval() {
+        let sensitive = [Role::UserEditor, Role::ServiceEditor];
+
+        assert_eq!(
+            sensitive_additions(
+                &sensitive,
+                &[Role::UserViewer],
+                &[Role::UserViewer, Role::UserEditor, Role::ClientEditor]
+            ),
+            vec![Role::UserEditor]
+        );
+        assert!(sensitive_additions(
+            &sensitive,
+            &[Role::UserEditor],
+            &[Role::UserEditor, Role::ClientViewer]
+        )
+        .is_empty());
+        assert!(sensitive_additions(&[], &[], &[Role::UserEditor]).is_empty());
+    }
+
+    #[test]
+    fn elevation_keeps_its_expiry() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let elevation = ElevationDocument::new(
+            ObjectId::new(),
+            Role::UserEditor,
+            "incident INC-42 cleanup".to_string(),
+            ObjectId::new(),
+            chrono::Duration::hours(2),
+            now,
+        )
+        .unwrap();
+
+        let approval = ApprovalDocument::elevation(elevation.clone(), now);
+        assert_eq!(approval.user, elevation.user);
+        assert_eq!(approval.roles, vec![Role::UserEditor]);
+        assert_eq!(approval.requested_by, elevation.granted_by);
+        assert_eq!(approval.state, ApprovalState::Pending);
+
+        let response = ApprovalResponse::from(approval);
+        assert_eq!(response.expires_at, Some(now + chrono::Duration::hours(2)));
+    }
+}
@@ -0,0 +1,240 @@
+//! Just-in-time access: roles granted for a bounded time with a recorded justification.
+//!
+//! A background job takes expired grants back. Security contacts are mailed when a role is
+//! granted and when it is taken back.
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    clock::SharedClock,
+    database::Database,
+    extract::ClientInfo,
+    models::user::ElevationResponse,
+    utils, Result,
+};
+
+use super::{Role, SecurityNotifier, UserError};
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_bson},
+    options::FindOneAndUpdateOptions,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Longest duration of a grant
+pub const MAX_DURATION_HOURS: i64 = 24;
+const MIN_JUSTIFICATION_LEN: usize = 10;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElevationDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user: ObjectId,
+    pub role: Role,
+    pub justification: String,
+    pub granted_by: ObjectId,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub granted_at: DateTime<Utc>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub expires_at: DateTime<Utc>,
+    /// Set once the role was taken back
+    #[serde(default)]
+    pub reverted: bool,
+}
+
+impl ElevationDocument {
+    pub fn new(
+        user: ObjectId,
+        role: Role,
+        justification: String,
+        granted_by: ObjectId,
+        duration: Duration,
+        now: DateTime<Utc>,
+    ) -> std::result::Result<Self, UserError> {
+        if duration <= Duration::zero() || duration > Duration::hours(MAX_DURATION_HOURS) {
+            return Err(UserError::InvalidElevation("duration"));
+        }
+        if justification.trim().len() < MIN_JUSTIFICATION_LEN {
+            return Err(UserError::InvalidElevation("justification"));
+        }
+
+        Ok(Self {
+            id: ObjectId::new(),
+            user,
+            role,
+            justification,
+            granted_by,
+            granted_at: now,
+            expires_at: now + duration,
+            reverted: false,
+        })
+    }
+}
+
+impl From<ElevationDocument> for ElevationResponse {
+    fn from(doc: ElevationDocument) -> Self {
+        Self {
+            id: doc.id.to_hex(),
+            user: doc.user.to_hex(),
+            role: doc.role,
+            justification: doc.justification,
+            granted_by: doc.granted_by.to_hex(),
+            granted_at: doc.granted_at,
+            expires_at: doc.expires_at,
+        }
+    }
+}
+
+/// Failures are only logged, the grant itself is recorded already
+pub async fn notify(notifier: &SecurityNotifier, elevation: &ElevationDocument, reverted: bool) {
+    const TEMPLATE_NAME: &str = "identity.security.elevation";
+
+    let subject = if reverted {
+        format!("Temporary role {:?} expired", elevation.role)
+    } else {
+        format!("Temporary role {:?} granted", elevation.role)
+    };
+
+    let mut vars = HashMap::with_capacity(6);
+    vars.insert("user".to_string(), elevation.user.to_hex());
+    vars.insert("role".to_string(), format!("{:?}", elevation.role));
+    vars.insert("justification".to_string(), elevation.justification.clone());
+    vars.insert("grantedBy".to_string(), elevation.granted_by.to_hex());
+    vars.insert("expiresAt".to_string(), elevation.expires_at.to_rfc3339());
+    vars.insert("reverted".to_string(), reverted.to_string());
+
+    notifier.send(&subject, TEMPLATE_NAME, vars).await;
+}
+
+/// Takes back expired grants periodically
+pub fn spawn_expiry(db: Database, notifier: SecurityNotifier, clock: SharedClock) {
+    utils::spawn_named("elevation-expiry", async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match revert_expired(&db, &notifier, clock.now()).await {
+                Ok(0) => {}
+                Ok(count) => info!(count, "expired elevations reverted"),
+                Err(e) => error!(error = %e, "elevation expiry failed"),
+            }
+        }
+    });
+}
+
+async fn revert_expired(
+    db: &Database,
+    notifier: &SecurityNotifier,
+    now: DateTime<Utc>,
+) -> Result<u64> {
+    let mut count = 0;
+
+    // Grants are claimed one at a time, so that concurrent instances never revert twice
+    while let Some(elevation) = db.claim_expired_elevation(now).await? {
+        db.remove_user_role(elevation.user, &elevation.role).await?;
+
+        let event = SecurityEvent::ElevationExpired {
+            role: elevation.role.clone(),
+        };
+        let audit = AuditEventDocument::new(elevation.user, event, &ClientInfo::default());
+        db.insert_audit_event(&audit).await?;
+
+        notify(notifier, &elevation, true).await;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+const COLLECTION: &str = "elevations";
+
+impl Database {
+    /// Records the grant and adds the role, fails if the user holds the role already
+    pub async fn grant_elevation(&self, elevation: &ElevationDocument) -> Result<()> {
+        let user = self.get_user(doc! { "_id": elevation.user }).await?;
+        if user.roles.contains(&elevation.role) {
+            return Err(UserError::RoleAlreadyGranted.into());
+        }
+
+        self.collection::<ElevationDocument>(COLLECTION)
+            .insert_one(elevation, None)
+            .await?;
+
+        let update = doc! { "$addToSet": { "roles": to_bson(&elevation.role).unwrap() } };
+        self.modify_user(doc! { "_id": elevation.user }, update)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn claim_expired_elevation(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Option<ElevationDocument>> {
+        let filter = doc! {
+            "reverted": false,
+            "expiresAt": { "$lte": bson::DateTime::from_chrono(now) },
+        };
+        let opts = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "expiresAt": 1 })
+            .build();
+
+        let elevation = self
+            .collection::<ElevationDocument>(COLLECTION)
+            .find_one_and_update(filter, doc! { "$set": { "reverted": true } }, opts)
+            .await?;
+
+        Ok(elevation)
+    }
+
+    async fn remove_user_role(&self, user_id: ObjectId, role: &Role) -> Result<()> {
+        let update = doc! { "$pull": { "roles": to_bson(role).unwrap() } };
+
+        match self.modify_user(doc! { "_id": user_id }, update).await {
+            // The user was deleted meanwhile
+            Ok(_) | Err(crate::error::Error::User(UserError::NotFound)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    #[test]
+    fn grant_is_bounded() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let new = |duration, justification: &str| {
+            ElevationDocument::new(
+                ObjectId::new(),
+                Role::UserEditor,
+                justification.to_string(),
+                ObjectId::new(),
+                duration,
+                now,
+            )
+        };
+
+        let elevation = new(Duration::hours(2), "incident INC-42 cleanup").unwrap();
+        assert_eq!(elevation.expires_at, now + Duration::hours(2));
+        assert!(!elevation.reverted);
+
+        assert!(new(
+            Duration::hours(MAX_DURATION_HOURS + 1),
+            "incident INC-42 cleanup"
+        )
+        .is_err());
+        assert!(new(Duration::zero(), "incident INC-42 cleanup").is_err());
+        assert!(new(Duration::hours(2), "   fix   ").is_err());
+    }
+}
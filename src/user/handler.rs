@@ -1,48 +1,68 @@
 use crate::{
-    action::send_verification_mail,
+    action::{send_reset_mail, send_verification_mail, ActionError},
+    audit::{AuditEventDocument, AuditEventResponse, SecurityEvent},
     authentication::{
+        credential::CredentialHasher,
         password::{self, Hibp},
-        token::TokenConfig,
+        token::{TokenClaims, TokenConfig},
+        totp::Totp,
         AuthenticationError,
     },
+    config::GlobalConfig,
     database::Database,
     error::QueryError,
-    extract::{Query, SizedJson, TokenData},
+    extract::{ClientInfo, Query, SizedJson, TokenData},
     mail,
     model::{List, ListOptions, Response, Status},
-    session::{self, SessionClaims},
-    utils, GlobalConfig,
+    models::user::{
+        ApprovalResponse, BanRequest, ElevateRequest, ElevationResponse, ProfileRequest,
+        RecoveryCodesResponse, SessionResponse, TotpEnrollmentResponse, UserResponse,
+    },
+    revision::RevisionResponse,
+    session::{
+        Access, ActiveSessionDocument, Resource, SessionClaims, SessionResponse as IssuedSession,
+    },
+    utils::{self, crypto::Aead256},
+};
+
+use super::{
+    approval::{self, ApprovalDocument, ApprovalState},
+    elevation,
+    mfa::{self, MfaDocument, MfaError},
+    profile, AccountFlag, ApprovalError, ElevationDocument, Role, SecurityNotifier,
+    SessionDocument, UserDocument, UserError,
 };
 
-use super::{Connection, Role, SessionDocument, UserDocument, UserError};
+use std::collections::HashMap;
 
 use axum::extract::{Extension, Path};
-use chrono::{serde::ts_seconds, DateTime, Utc};
+use chrono::{Duration, Utc};
 use hyper::StatusCode;
-use mongodb::bson::{doc, oid::ObjectId, to_bson, to_document, Document};
+use mongodb::bson::{
+    doc, from_bson, oid::ObjectId, to_bson, to_document, DateTime as BsonDateTime, Document,
+};
 use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct UserResponse {
-    pub id: String,
-    pub email: String,
-    pub roles: Vec<Role>,
-    pub verified: bool,
-    pub connections: Vec<Connection>,
-    pub last_sessions: Vec<SessionResponse>,
-    #[serde(with = "ts_seconds")]
-    pub last_modified: DateTime<Utc>,
-}
+use tracing::info;
 
 impl From<UserDocument> for UserResponse {
     fn from(doc: UserDocument) -> Self {
+        let locked = doc.is_locked(Utc::now());
+
         Self {
             id: doc.id.to_hex(),
             email: doc.email,
+            name: doc.name,
+            country: doc.profile.country,
+            locale: doc.profile.locale,
             verified: doc.verified,
+            pending: doc.pending,
             roles: doc.roles,
             connections: doc.connections,
+            locked,
+            can_login: doc.can_login,
+            banned_until: doc.banned_until.map(|v| v.to_chrono()),
+            flags: doc.flags,
+            mfa_enabled: doc.mfa.map_or(false, |m| m.enabled),
             last_sessions: doc
                 .last_sessions
                 .into_iter()
@@ -53,16 +73,12 @@ impl From<UserDocument> for UserResponse {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SessionResponse {
-    #[serde(with = "ts_seconds")]
-    pub date: DateTime<Utc>,
-}
-
 impl From<SessionDocument> for SessionResponse {
     fn from(doc: SessionDocument) -> Self {
-        Self { date: doc.date }
+        Self {
+            date: doc.date,
+            region: doc.region,
+        }
     }
 }
 
@@ -74,7 +90,11 @@ pub struct Filter {
     #[serde(skip_serializing_if = "Option::is_none")]
     verified: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     role: Option<Role>,
+    #[serde(skip_serializing)]
+    locked: Option<bool>,
 }
 
 pub async fn list(
@@ -83,10 +103,23 @@ pub async fn list(
     Query(opts): Query<ListOptions>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<List<UserResponse>>> {
-    let filter = if !claims.scope.contains(&session::Scope::UserRead) {
+    let filter = if !claims.is_global(Resource::User, Access::Read) {
         doc! { "_id": ObjectId::parse_str(&claims.sub).unwrap() }
     } else {
-        to_document(&filter).unwrap()
+        let mut f = to_document(&filter).unwrap();
+        let now = BsonDateTime::from_chrono(Utc::now());
+        match filter.locked {
+            Some(true) => f.insert("lockedUntil", doc! { "$gt": now }),
+            Some(false) => f.insert(
+                "$or",
+                vec![
+                    doc! { "lockedUntil": { "$exists": false } },
+                    doc! { "lockedUntil": { "$lte": now } },
+                ],
+            ),
+            None => None,
+        };
+        f
     };
 
     let (users, total) = db.get_users(filter, opts).await?;
@@ -101,7 +134,7 @@ pub async fn get_by_id(
     TokenData(claims): TokenData<SessionClaims>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Response<UserResponse>> {
-    if !claims.scope.contains(&session::Scope::UserRead) && claims.sub != id {
+    if !claims.is_permitted(Resource::User, Access::Read, &id) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
@@ -116,24 +149,31 @@ pub async fn get_by_id(
 #[serde(rename_all = "camelCase")]
 pub struct CreateRequest {
     email: String,
-    password: String,
+    /// Pre-registers the user if not set, the record is claimed by the first SSO login
+    password: Option<String>,
     #[serde(default)]
     roles: Vec<Role>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn create(
+    client: ClientInfo,
     TokenData(claims): TokenData<SessionClaims>,
     SizedJson(body): SizedJson<CreateRequest>,
     Extension(db): Extension<Database>,
     Extension(global): Extension<GlobalConfig>,
     Extension(hibp): Extension<Hibp>,
+    Extension(hasher): Extension<CredentialHasher>,
     Extension(mail): Extension<mail::Client>,
+    Extension(notifier): Extension<SecurityNotifier>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<UserResponse>> {
-    if !claims.scope.contains(&session::Scope::UserWrite) {
+    if !claims.is_global(Resource::User, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
+    let requested_by = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
     let domain = utils::get_email_domain(&body.email).ok_or(UserError::InvalidAddr)?;
 
     if !global.is_allowed_domain(domain) {
@@ -144,28 +184,66 @@ pub async fn create(
         return Err(UserError::AlreadyExists.into());
     }
 
-    let password_hash = password::validate_and_hash(&body.password)?;
+    let password_hash = match &body.password {
+        Some(password) => {
+            let hash = password::validate_and_hash(&hasher, &global.password_policy, password)?;
 
-    if global.hibp_check_enabled {
-        hibp.check_password(&body.password).await?;
-    }
+            if global.hibp_check_enabled {
+                hibp.check_password(password).await?;
+            }
+
+            Some(hash)
+        }
+        None => None,
+    };
+
+    let (pending, roles) = split_sensitive(&global, &[], body.roles);
 
     let user = UserDocument {
         id: ObjectId::new(),
         email: body.email,
-        password: Some(password_hash),
-        roles: body.roles,
+        pending: password_hash.is_none(),
+        password: password_hash,
+        roles,
         last_modified: Utc::now(),
         ..Default::default()
     };
 
     db.insert_user(&user).await?;
 
-    send_verification_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+    if !pending.is_empty() {
+        let approval = ApprovalDocument::new(user.id, pending, requested_by, config.clock.now());
+        approval::request(&db, &notifier, approval, &client).await?;
+    }
+
+    // Pre-registered users verify their address with the provider on the first login
+    if !user.pending {
+        send_verification_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+    }
 
     Ok(Response::with_status(StatusCode::CREATED, user.into()))
 }
 
+/// Sends a new verification mail to the address of the session user
+pub async fn resend_verification(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(mail): Extension<mail::Client>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Status> {
+    let id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! { "_id": id }).await?;
+
+    if user.verified {
+        return Err(ActionError::AlreadyVerified.into());
+    }
+
+    send_verification_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+
+    Ok(Status::new(StatusCode::OK, "verification email sent"))
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateRequest {
@@ -173,39 +251,61 @@ pub struct UpdateRequest {
     password: Option<String>,
     verified: Option<bool>,
     roles: Option<Vec<Role>>,
+    /// Replaces the flags, e.g. to clear a flag after a review
+    flags: Option<Vec<AccountFlag>>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn update(
+    client: ClientInfo,
     Path(id): Path<String>,
     TokenData(claims): TokenData<SessionClaims>,
     SizedJson(body): SizedJson<UpdateRequest>,
     Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(hasher): Extension<CredentialHasher>,
     Extension(mail): Extension<mail::Client>,
+    Extension(notifier): Extension<SecurityNotifier>,
     Extension(config): Extension<TokenConfig>,
 ) -> crate::Result<Response<UserResponse>> {
-    if !claims.scope.contains(&session::Scope::UserWrite) && claims.sub != id {
+    if !claims.is_permitted(Resource::User, Access::Write, &id) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
     let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
 
+    let now = config.clock.now();
+    let mut events = Vec::new();
+    let mut pending = Vec::new();
+
     let mut doc = Document::new();
     if let Some(v) = body.email {
         send_verification_mail(&v, &id.to_hex(), mail, config).await?;
         doc.insert("verified", false);
         doc.insert("email", v);
+        events.push(SecurityEvent::EmailChanged);
     }
     if let Some(v) = body.password {
-        let hash = password::validate_and_hash(&v)?;
+        let hash = password::validate_and_hash(&hasher, &global.password_policy, &v)?;
         doc.insert("password", hash);
+        events.push(SecurityEvent::PasswordChanged);
     }
 
-    if claims.scope.contains(&session::Scope::UserWrite) {
+    if claims.is_global(Resource::User, Access::Write) {
         if let Some(v) = body.verified {
             doc.insert("verified", v);
         }
         if let Some(v) = body.roles {
-            doc.insert("roles", to_bson(&v).unwrap());
+            let current = db.get_user(doc! { "_id": id }).await?.roles;
+            let (sensitive, roles) = split_sensitive(&global, &current, v);
+            pending = sensitive;
+            doc.insert("roles", to_bson(&roles).unwrap());
+            if roles != current {
+                events.push(SecurityEvent::RolesChanged { roles });
+            }
+        }
+        if let Some(v) = body.flags {
+            doc.insert("flags", to_bson(&v).unwrap());
         }
     }
 
@@ -213,17 +313,344 @@ pub async fn update(
         return Err(QueryError::InvalidBody.into());
     }
 
-    let doc = db.update_user_by_id(id, doc).await?;
+    let mut doc = db.update_user_by_id(id, doc).await?;
+
+    if events.contains(&SecurityEvent::PasswordChanged) {
+        doc = db
+            .remove_user_flag(id, AccountFlag::PasswordResetRequired)
+            .await?;
+    }
+
+    for event in events {
+        db.insert_audit_event(&AuditEventDocument::new(id, event, &client))
+            .await?;
+    }
+
+    // The other changes are applied, sensitive roles follow once approved
+    if !pending.is_empty() {
+        let requested_by = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+        let approval = ApprovalDocument::new(id, pending, requested_by, now);
+        approval::request(&db, &notifier, approval, &client).await?;
+
+        return Ok(Response::with_status(StatusCode::ACCEPTED, doc.into()));
+    }
 
     Ok(Response::new(doc.into()))
 }
 
+/// Fills in profile fields, e.g. from the form of fields required by a service
+pub async fn update_profile(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<ProfileRequest>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_permitted(Resource::User, Access::Write, &id) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    // Age gates would be bypassed by changing it
+    if body.birthdate.is_some() && !claims.is_global(Resource::User, Access::Write) {
+        let user = db.get_user(doc! { "_id": id }).await?;
+        if user.profile.birthdate.is_some() {
+            return Err(UserError::BirthdateSet.into());
+        }
+    }
+
+    let today = config.clock.now().date().naive_utc();
+    let doc = profile::update_document(body, today)?;
+    if doc.is_empty() {
+        return Err(QueryError::InvalidBody.into());
+    }
+
+    let user = db.update_user_by_id(id, doc).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Splits requested roles into sensitive additions, which need an approval, and the rest
+fn split_sensitive(
+    global: &GlobalConfig,
+    current: &[Role],
+    requested: Vec<Role>,
+) -> (Vec<Role>, Vec<Role>) {
+    let pending = approval::sensitive_additions(&global.sensitive_roles, current, &requested);
+    let roles = requested
+        .into_iter()
+        .filter(|r| !pending.contains(r))
+        .collect();
+
+    (pending, roles)
+}
+
+/// Requires the user to reset the password before the next login
+pub async fn force_password_reset(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(mail): Extension<mail::Client>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    db.add_user_flag(id, AccountFlag::PasswordResetRequired)
+        .await?;
+    // Existing sessions could belong to whoever knows the current password
+    let user = db.revoke_user_sessions(id, config.clock.now()).await?;
+
+    send_reset_mail(&user.email, &user.id.to_hex(), mail, config).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Requires the next login of the user to happen through a connected provider
+pub async fn force_relink(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! { "_id": id }).await?;
+
+    if user.connections.is_empty() {
+        return Err(UserError::NoConnection.into());
+    }
+
+    let user = db.add_user_flag(id, AccountFlag::RelinkRequired).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Rejects all logins of the user and revokes the sessions until the user is unlocked
+pub async fn lock(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    db.update_user_by_id(id, doc! { "canLogin": false }).await?;
+    let user = db.revoke_user_sessions(id, config.clock.now()).await?;
+
+    let event = AuditEventDocument::new(id, SecurityEvent::AccountLocked, &client);
+    db.insert_audit_event(&event).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Rejects all logins of the user until the given date and revokes the sessions
+pub async fn ban(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<BanRequest>,
+    Extension(db): Extension<Database>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    if body.until <= config.clock.now() {
+        return Err(UserError::InvalidBan.into());
+    }
+
+    let until = BsonDateTime::from_chrono(body.until);
+    db.update_user_by_id(id, doc! { "bannedUntil": until })
+        .await?;
+    let user = db.revoke_user_sessions(id, config.clock.now()).await?;
+
+    let event = SecurityEvent::AccountBanned { until: body.until };
+    db.insert_audit_event(&AuditEventDocument::new(id, event, &client))
+        .await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Lifts a lock or ban of the user
+pub async fn unlock(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db
+        .modify_user(
+            doc! { "_id": id },
+            doc! {
+                "$set": { "canLogin": true },
+                "$unset": { "bannedUntil": "" },
+            },
+        )
+        .await?;
+
+    let event = AuditEventDocument::new(id, SecurityEvent::AccountUnlocked, &client);
+    db.insert_audit_event(&event).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+/// Grants a role for a limited time, it is taken back automatically.
+///
+/// Sensitive roles wait for an approval like any other grant of them.
+#[allow(clippy::too_many_arguments)]
+pub async fn elevate(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<ElevateRequest>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<ElevationResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+    let granted_by = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let elevation = ElevationDocument::new(
+        id,
+        body.role,
+        body.justification,
+        granted_by,
+        Duration::minutes(body.duration_min),
+        config.clock.now(),
+    )?;
+
+    let current = db.get_user(doc! { "_id": id }).await?.roles;
+    let (pending, _) = split_sensitive(&global, &current, vec![elevation.role.clone()]);
+    if !pending.is_empty() {
+        let approval = ApprovalDocument::elevation(elevation.clone(), config.clock.now());
+        approval::request(&db, &notifier, approval, &client).await?;
+
+        return Ok(Response::with_status(
+            StatusCode::ACCEPTED,
+            elevation.into(),
+        ));
+    }
+
+    db.grant_elevation(&elevation).await?;
+
+    let event = SecurityEvent::RoleElevated {
+        role: elevation.role.clone(),
+        expires_at: elevation.expires_at,
+    };
+    db.insert_audit_event(&AuditEventDocument::new(id, event, &client))
+        .await?;
+
+    info!(user = %id, role = ?elevation.role, by = %granted_by, "role elevated");
+    elevation::notify(&notifier, &elevation, false).await;
+
+    Ok(Response::with_status(StatusCode::CREATED, elevation.into()))
+}
+
+/// Role changes waiting for a decision, oldest first
+pub async fn list_approvals(
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<ApprovalResponse>>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let approvals = db.get_pending_approvals().await?;
+
+    Ok(Response::new(List::new(approvals.len() as u64, approvals)))
+}
+
+pub async fn approve(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<ApprovalResponse>> {
+    let state = ApprovalState::Approved;
+    decide_approval(client, id, claims, db, notifier, config, state).await
+}
+
+pub async fn reject(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<ApprovalResponse>> {
+    let state = ApprovalState::Rejected;
+    decide_approval(client, id, claims, db, notifier, config, state).await
+}
+
+async fn decide_approval(
+    client: ClientInfo,
+    id: String,
+    claims: SessionClaims,
+    db: Database,
+    notifier: SecurityNotifier,
+    config: TokenConfig,
+    state: ApprovalState,
+) -> crate::Result<Response<ApprovalResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| ApprovalError::InvalidId)?;
+    let decided_by = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let approval = db
+        .decide_approval(id, decided_by, state, config.clock.now())
+        .await?;
+
+    let roles = approval.roles.clone();
+    let event = match state {
+        ApprovalState::Approved => SecurityEvent::RoleChangeApproved { roles },
+        _ => SecurityEvent::RoleChangeRejected { roles },
+    };
+    db.insert_audit_event(&AuditEventDocument::new(approval.user, event, &client))
+        .await?;
+
+    info!(user = %approval.user, roles = ?approval.roles, by = %decided_by, state = ?state, "role change decided");
+    approval::notify(&notifier, &approval).await;
+
+    Ok(Response::new(approval.into()))
+}
+
 pub async fn delete(
     Path(id): Path<String>,
     TokenData(claims): TokenData<SessionClaims>,
     Extension(db): Extension<Database>,
 ) -> crate::Result<Status> {
-    if !claims.scope.contains(&session::Scope::UserWrite) {
+    if !claims.is_global(Resource::User, Access::Write) {
         return Err(AuthenticationError::InsufficientPermission.into());
     }
 
@@ -233,3 +660,287 @@ pub async fn delete(
 
     Ok(Status::new(StatusCode::OK, "user deleted"))
 }
+
+pub async fn security_events(
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(opts): Query<ListOptions>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<AuditEventResponse>>> {
+    let id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    let (events, total) = db.get_audit_events(doc! { "user": id }, opts).await?;
+
+    let list = List::new(total, events);
+
+    Ok(Response::new(list))
+}
+
+pub async fn history(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Query(opts): Query<ListOptions>,
+    Extension(db): Extension<Database>,
+) -> crate::Result<Response<List<RevisionResponse>>> {
+    if !claims.is_permitted(Resource::User, Access::Read, &id) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let (revisions, total) = db.get_revisions(Resource::User, id, opts).await?;
+
+    Ok(Response::new(List::new(total, revisions)))
+}
+
+/// Restores the values before a revision, sensitive roles it would restore wait for an approval
+pub async fn revert(
+    client: ClientInfo,
+    Path((id, revision)): Path<(String, String)>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(global): Extension<GlobalConfig>,
+    Extension(notifier): Extension<SecurityNotifier>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_global(Resource::User, Access::Write) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let mut update = db
+        .revert_update(Resource::User, id, &revision, db.clock().now())
+        .await?;
+
+    let mut pending = Vec::new();
+    if let Ok(set) = update.get_document_mut("$set") {
+        if let Some(v) = set.get("roles") {
+            let requested = from_bson::<Vec<Role>>(v.clone()).unwrap_or_default();
+            let current = db.get_user(doc! { "_id": id }).await?.roles;
+            let (sensitive, roles) = split_sensitive(&global, &current, requested);
+            pending = sensitive;
+            set.insert("roles", to_bson(&roles).unwrap());
+        }
+    }
+
+    let user = db.modify_user(doc! { "_id": id }, update).await?;
+
+    if !pending.is_empty() {
+        let requested_by = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+        let approval = ApprovalDocument::new(id, pending, requested_by, config.clock.now());
+        approval::request(&db, &notifier, approval, &client).await?;
+
+        return Ok(Response::with_status(StatusCode::ACCEPTED, user.into()));
+    }
+
+    Ok(Response::new(user.into()))
+}
+
+/// Issuer shown in authenticator apps
+const TOTP_ISSUER: &str = "Tarkov Database";
+
+/// Starts the enrollment of an authenticator app, it is enabled by confirming a first code
+pub async fn enroll_totp(
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    Extension(db): Extension<Database>,
+    Extension(aead): Extension<Aead256>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<TotpEnrollmentResponse>> {
+    // Nobody else may hold the secret
+    if claims.sub != id {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! { "_id": id }).await?;
+
+    if user.mfa.as_ref().map_or(false, |m| m.enabled) {
+        return Err(MfaError::AlreadyEnabled.into());
+    }
+
+    let totp = Totp::generate();
+    db.enroll_mfa(id, &MfaDocument::new(&aead, &totp, config.clock.now()))
+        .await?;
+
+    let response = TotpEnrollmentResponse {
+        secret: totp.encoded_secret(),
+        uri: totp.uri(TOTP_ISSUER, &user.email).to_string(),
+    };
+
+    Ok(Response::with_status(StatusCode::CREATED, response))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmTotpRequest {
+    code: String,
+}
+
+/// Enables the enrolled authenticator app, the recovery codes are only returned once
+pub async fn confirm_totp(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<ConfirmTotpRequest>,
+    Extension(db): Extension<Database>,
+    Extension(aead): Extension<Aead256>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<RecoveryCodesResponse>> {
+    if claims.sub != id {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! { "_id": id }).await?;
+
+    let mfa = user.mfa.as_ref().ok_or(MfaError::NotEnrolled)?;
+    if mfa.enabled {
+        return Err(MfaError::AlreadyEnabled.into());
+    }
+
+    let step = mfa
+        .totp(&aead)?
+        .verify(body.code.trim(), config.clock.now().timestamp())
+        .ok_or(MfaError::InvalidCode)?;
+
+    let (recovery_codes, hashes) = mfa::generate_recovery_codes();
+    db.enable_mfa(id, step, hashes).await?;
+
+    let event = AuditEventDocument::new(id, SecurityEvent::MfaEnabled, &client);
+    db.insert_audit_event(&event).await?;
+
+    Ok(Response::new(RecoveryCodesResponse { recovery_codes }))
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DisableTotpRequest {
+    /// Code or recovery code, not required for admins disabling it for another user
+    code: Option<String>,
+}
+
+pub async fn disable_totp(
+    client: ClientInfo,
+    Path(id): Path<String>,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<DisableTotpRequest>,
+    Extension(db): Extension<Database>,
+    Extension(aead): Extension<Aead256>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<UserResponse>> {
+    if !claims.is_permitted(Resource::User, Access::Write, &id) {
+        return Err(AuthenticationError::InsufficientPermission.into());
+    }
+
+    let is_owner = claims.sub == id;
+    let id = ObjectId::parse_str(&id).map_err(|_| UserError::InvalidId)?;
+
+    let user = db.get_user(doc! { "_id": id }).await?;
+
+    if user.mfa.is_none() {
+        return Err(MfaError::NotEnrolled.into());
+    }
+
+    // A stolen session alone must not be enough to remove the second factor
+    if is_owner && user.mfa.as_ref().map_or(false, |m| m.enabled) {
+        let code = body.code.as_deref().ok_or(MfaError::InvalidCode)?;
+        let now = config.clock.now();
+        mfa::verify_code(&db, &aead, &user, code, &client, now).await?;
+    }
+
+    let user = db.disable_mfa(id).await?;
+
+    let event = AuditEventDocument::new(id, SecurityEvent::MfaDisabled, &client);
+    db.insert_audit_event(&event).await?;
+
+    Ok(Response::new(user.into()))
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RevokeSessionsRequest {
+    /// Issues a new token for the current session, so it stays signed in
+    exclude_current: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeSessionsResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<IssuedSession>,
+}
+
+pub async fn revoke_sessions(
+    client: ClientInfo,
+    TokenData(claims): TokenData<SessionClaims>,
+    SizedJson(body): SizedJson<RevokeSessionsRequest>,
+    Extension(db): Extension<Database>,
+    Extension(mail): Extension<mail::Client>,
+    Extension(config): Extension<TokenConfig>,
+) -> crate::Result<Response<RevokeSessionsResponse>> {
+    let id = ObjectId::parse_str(&claims.sub).map_err(|_| UserError::InvalidId)?;
+
+    // The current session continues with a new refresh token
+    let current = match &claims.jti {
+        Some(jti) if body.exclude_current => db.end_session(id, jti).await?,
+        _ => None,
+    };
+    let user = db.revoke_user_sessions(id, config.clock.now()).await?;
+
+    let event = AuditEventDocument::new(id, SecurityEvent::SessionsRevoked, &client);
+    db.insert_audit_event(&event).await?;
+
+    send_sessions_revoked_mail(&user.email, mail).await?;
+
+    let session = if body.exclude_current {
+        let mut claims = claims;
+        // Tokens of the second of the cutoff are revoked as well, the new one follows it
+        let now = match user.sessions_valid_after {
+            Some(v) => v.to_chrono() + Duration::seconds(1),
+            None => config.clock.now(),
+        };
+        claims.iat = now;
+        let limits = &config.session_limits;
+        claims.set_expiration(now + limits.token_lifetime(&user.roles));
+        let lifetime = limits.session_lifetime(&user.roles);
+
+        let token = claims.encode(&config)?;
+        let (refresh_token, refresh) = db.issue_refresh_token(id, None, now, lifetime).await?;
+
+        let session = match current {
+            Some(mut s) => {
+                s.last_used_at = now;
+                s.token_expires_at = claims.exp;
+                s
+            }
+            None => ActiveSessionDocument::new(id, &claims, "unknown", &client),
+        };
+        db.insert_session(&session.with_family(refresh.family, refresh.expires_at))
+            .await?;
+
+        Some(IssuedSession {
+            user: claims.sub.clone(),
+            token,
+            refresh_token: Some(refresh_token),
+            expires_at: claims.exp,
+        })
+    } else {
+        None
+    };
+
+    Ok(Response::new(RevokeSessionsResponse { session }))
+}
+
+async fn send_sessions_revoked_mail(addr: &str, client: mail::Client) -> crate::Result<()> {
+    const TEMPLATE_NAME: &str = "identity.security.sessions-revoked";
+    const SUBJECT: &str = "All sessions have been signed out";
+
+    client
+        .send_template(addr, SUBJECT, TEMPLATE_NAME, HashMap::new())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,448 @@
+//! Two-factor authentication with authenticator apps.
+//!
+//! Enrollment stores a pending secret, which is enabled by confirming a first code. Logins of
+//! users with an enabled secret return a short-lived challenge token instead of a session,
+//! it is exchanged for the session together with a code or one of the recovery codes.
+
+use crate::{
+    audit::{AuditEventDocument, SecurityEvent},
+    authentication::{
+        token::{TokenClaims, TokenType},
+        totp::{self, Totp},
+    },
+    database::Database,
+    error,
+    extract::ClientInfo,
+    model::Status,
+    models::session::MfaChallengeResponse,
+    utils::crypto::Aead256,
+    Result,
+};
+
+use super::{UserDocument, UserError, COLLECTION};
+
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{serde::ts_seconds, DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use hyper::StatusCode;
+use mongodb::{
+    bson::{
+        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_bson,
+        DateTime as BsonDateTime,
+    },
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// Recovery codes issued when two-factor authentication is enabled
+const RECOVERY_CODES: usize = 10;
+/// Invalid codes after which verification is locked
+const MAX_FAILURES: i32 = 5;
+const LOCK_MIN: i64 = 15;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MfaError {
+    #[error("two-factor authentication is not enrolled")]
+    NotEnrolled,
+    #[error("two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error("code is invalid")]
+    InvalidCode,
+    #[error("too many invalid codes, try again later")]
+    Locked,
+    #[error("challenge required: mfa")]
+    Required {
+        token: String,
+        expires_at: DateTime<Utc>,
+    },
+}
+
+impl error::ErrorResponse for MfaError {
+    type Response = Response;
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MfaError::NotEnrolled => StatusCode::UNPROCESSABLE_ENTITY,
+            MfaError::AlreadyEnabled => StatusCode::CONFLICT,
+            MfaError::InvalidCode => StatusCode::UNAUTHORIZED,
+            MfaError::Locked => StatusCode::TOO_MANY_REQUESTS,
+            MfaError::Required { .. } => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> Self::Response {
+        let status = Status::new(self.status_code(), self.to_string());
+
+        match self {
+            MfaError::Required { token, expires_at } => {
+                let body = MfaChallengeResponse {
+                    status,
+                    mfa_token: token.clone(),
+                    expires_at: *expires_at,
+                };
+
+                (self.status_code(), Json(body)).into_response()
+            }
+            _ => status.into_response(),
+        }
+    }
+}
+
+/// Authenticator app of a user
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaDocument {
+    /// TOTP secret, encrypted at rest
+    secret: String,
+    /// Set by confirming the first code, logins require a code from then on
+    pub enabled: bool,
+    /// Hashes of the unused recovery codes
+    #[serde(default)]
+    recovery_codes: Vec<String>,
+    /// Time step of the last accepted code, so that a code can't be used twice
+    #[serde(default)]
+    last_step: i64,
+    /// Invalid codes since the last valid one
+    #[serde(default)]
+    failures: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locked_until: Option<BsonDateTime>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub enrolled_at: DateTime<Utc>,
+}
+
+impl MfaDocument {
+    pub fn new(enc: &Aead256, totp: &Totp, now: DateTime<Utc>) -> Self {
+        Self {
+            secret: base64::encode_config(enc.encrypt(totp.secret()), base64::STANDARD),
+            enabled: false,
+            recovery_codes: Vec::new(),
+            last_step: 0,
+            failures: 0,
+            locked_until: None,
+            enrolled_at: now,
+        }
+    }
+
+    pub fn totp(&self, enc: &Aead256) -> Result<Totp> {
+        let secret = base64::decode_config(&self.secret, base64::STANDARD).unwrap();
+
+        Ok(Totp::from_secret(enc.decrypt(secret)?))
+    }
+
+    /// Re-encrypts the secret with the current key, returns `false` if it already uses the current key
+    fn reencrypt(&mut self, enc: &Aead256) -> Result<bool> {
+        let secret = base64::decode_config(&self.secret, base64::STANDARD).unwrap();
+
+        match enc.reencrypt(secret)? {
+            Some(secret) => {
+                self.secret = base64::encode_config(secret, base64::STANDARD);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Generates new recovery codes, returns them with their hashes
+pub fn generate_recovery_codes() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let encoded = totp::base32(&rand::random::<[u8; 10]>()).to_lowercase();
+            let code = format!(
+                "{}-{}-{}-{}",
+                &encoded[..4],
+                &encoded[4..8],
+                &encoded[8..12],
+                &encoded[12..]
+            );
+            let hash = hash_recovery_code(&code);
+
+            (code, hash)
+        })
+        .unzip()
+}
+
+/// Hashes a recovery code regardless of case and separators, they are random enough for SHA-256
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// Claims of the challenge token of a login that requires a code
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaClaims {
+    pub aud: Vec<String>,
+    #[serde(with = "ts_seconds")]
+    pub exp: DateTime<Utc>,
+    #[serde(with = "ts_seconds")]
+    pub iat: DateTime<Utc>,
+    pub sub: String,
+    /// Login method of the session
+    pub method: String,
+    token_type: TokenType,
+}
+
+impl MfaClaims {
+    pub const DEFAULT_EXP_MIN: i64 = 5;
+
+    pub fn new<A>(aud: A, sub: &str, method: &str, now: DateTime<Utc>) -> Self
+    where
+        A: IntoIterator<Item = String>,
+    {
+        Self {
+            aud: aud.into_iter().collect(),
+            exp: now + Duration::minutes(Self::DEFAULT_EXP_MIN),
+            iat: now,
+            sub: sub.into(),
+            method: method.into(),
+            token_type: Self::TOKEN_TYPE,
+        }
+    }
+}
+
+impl TokenClaims for MfaClaims {
+    const TOKEN_TYPE: TokenType = TokenType::Mfa;
+
+    fn get_type(&self) -> &TokenType {
+        &self.token_type
+    }
+
+    fn subject(&self) -> Option<&str> {
+        Some(&self.sub)
+    }
+}
+
+/// Verifies a code or recovery code of a user with enabled two-factor authentication.
+///
+/// Every code is accepted once, verification is locked for a while after repeated failures.
+pub async fn verify_code(
+    db: &Database,
+    enc: &Aead256,
+    user: &UserDocument,
+    code: &str,
+    client: &ClientInfo,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let mfa = enabled_mfa(user, now)?;
+
+    let valid = match mfa.totp(enc)?.verify(code.trim(), now.timestamp()) {
+        Some(step) => db.use_mfa_step(user.id, step).await?,
+        None => use_recovery_code(db, user, code, client).await?,
+    };
+
+    check_valid(db, user, valid, now).await
+}
+
+/// Verifies only a recovery code, for logins without another factor
+pub async fn verify_recovery_code(
+    db: &Database,
+    user: &UserDocument,
+    code: &str,
+    client: &ClientInfo,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    enabled_mfa(user, now)?;
+
+    let valid = use_recovery_code(db, user, code, client).await?;
+
+    check_valid(db, user, valid, now).await
+}
+
+fn enabled_mfa(user: &UserDocument, now: DateTime<Utc>) -> Result<&MfaDocument> {
+    let mfa = user
+        .mfa
+        .as_ref()
+        .filter(|m| m.enabled)
+        .ok_or(MfaError::NotEnrolled)?;
+
+    if matches!(mfa.locked_until, Some(v) if now < v.to_chrono()) {
+        return Err(MfaError::Locked.into());
+    }
+
+    Ok(mfa)
+}
+
+async fn use_recovery_code(
+    db: &Database,
+    user: &UserDocument,
+    code: &str,
+    client: &ClientInfo,
+) -> Result<bool> {
+    let used = db
+        .use_recovery_code(user.id, &hash_recovery_code(code))
+        .await?;
+
+    if used {
+        let event = AuditEventDocument::new(user.id, SecurityEvent::RecoveryCodeUsed, client);
+        db.insert_audit_event(&event).await?;
+    }
+
+    Ok(used)
+}
+
+async fn check_valid(
+    db: &Database,
+    user: &UserDocument,
+    valid: bool,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    if !valid {
+        db.record_mfa_failure(user.id, now).await?;
+        return Err(MfaError::InvalidCode.into());
+    }
+
+    Ok(())
+}
+
+impl Database {
+    /// Stores a pending enrollment, replacing a previous one that was not confirmed
+    pub async fn enroll_mfa(&self, user_id: ObjectId, mfa: &MfaDocument) -> Result<UserDocument> {
+        let filter = doc! { "_id": user_id, "mfa.enabled": { "$ne": true } };
+
+        self.update_user(filter, doc! { "mfa": to_bson(mfa).unwrap() })
+            .await
+    }
+
+    /// Enables a pending enrollment with the first code and the hashes of the recovery codes
+    pub async fn enable_mfa(
+        &self,
+        user_id: ObjectId,
+        step: i64,
+        recovery_codes: Vec<String>,
+    ) -> Result<UserDocument> {
+        let filter = doc! { "_id": user_id, "mfa.enabled": false };
+        let update = doc! {
+            "mfa.enabled": true,
+            "mfa.lastStep": step,
+            "mfa.recoveryCodes": recovery_codes,
+        };
+
+        self.update_user(filter, update).await
+    }
+
+    pub async fn disable_mfa(&self, user_id: ObjectId) -> Result<UserDocument> {
+        let update = doc! { "$unset": { "mfa": "" } };
+
+        self.modify_user(doc! { "_id": user_id }, update).await
+    }
+
+    /// Records the time step of an accepted code, returns `false` if it or a later one was used
+    async fn use_mfa_step(&self, user_id: ObjectId, step: i64) -> Result<bool> {
+        let filter = doc! { "_id": user_id, "mfa.lastStep": { "$lt": step } };
+        let update = doc! { "$set": { "mfa.lastStep": step, "mfa.failures": 0 } };
+
+        let result = self
+            .collection::<UserDocument>(COLLECTION)
+            .update_one(filter, update, None)
+            .await?;
+
+        Ok(result.modified_count == 1)
+    }
+
+    /// Removes a recovery code, returns `false` if it is unknown or was used
+    async fn use_recovery_code(&self, user_id: ObjectId, hash: &str) -> Result<bool> {
+        let filter = doc! { "_id": user_id, "mfa.recoveryCodes": hash };
+        let update = doc! {
+            "$pull": { "mfa.recoveryCodes": hash },
+            "$set": { "mfa.failures": 0 },
+        };
+
+        let result = self
+            .collection::<UserDocument>(COLLECTION)
+            .update_one(filter, update, None)
+            .await?;
+
+        Ok(result.modified_count == 1)
+    }
+
+    /// Counts an invalid code and locks verification once there are too many
+    async fn record_mfa_failure(&self, user_id: ObjectId, now: DateTime<Utc>) -> Result<()> {
+        let coll = self.collection::<UserDocument>(COLLECTION);
+
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let user = coll
+            .find_one_and_update(
+                doc! { "_id": user_id },
+                doc! { "$inc": { "mfa.failures": 1 } },
+                opts,
+            )
+            .await?
+            .ok_or(UserError::NotFound)?;
+
+        let failures = user.mfa.map_or(0, |m| m.failures);
+        if failures >= MAX_FAILURES {
+            warn!(user = %user_id, failures, "two-factor verification locked");
+
+            let until = BsonDateTime::from_chrono(now + Duration::minutes(LOCK_MIN));
+            coll.update_one(
+                doc! { "_id": user_id },
+                doc! { "$set": { "mfa.lockedUntil": until, "mfa.failures": 0 } },
+                None,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts the TOTP secrets of all users with the current key
+    pub async fn reencrypt_mfa_secrets(&self, enc: &Aead256) -> Result<u64> {
+        let coll = self.collection::<UserDocument>(COLLECTION);
+        let mut cursor = coll.find(doc! { "mfa": { "$exists": true } }, None).await?;
+
+        let mut count = 0;
+        while let Some(user) = cursor.try_next().await? {
+            let mut mfa = match user.mfa {
+                Some(v) => v,
+                None => continue,
+            };
+
+            if mfa.reencrypt(enc)? {
+                coll.update_one(
+                    doc! { "_id": user.id },
+                    doc! { "$set": { "mfa.secret": &mfa.secret } },
+                    None,
+                )
+                .await?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_match_their_hashes() {
+        let (codes, hashes) = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        assert_eq!(codes[0].len(), 19);
+        for (code, hash) in codes.iter().zip(&hashes) {
+            assert_eq!(&hash_recovery_code(code), hash);
+            assert_eq!(
+                &hash_recovery_code(&code.to_uppercase().replace('-', "")),
+                hash
+            );
+        }
+        assert_ne!(codes[0], codes[1]);
+    }
+}
@@ -1,24 +1,44 @@
+mod approval;
+mod elevation;
 mod handler;
+mod mfa;
+mod notifier;
+mod profile;
 mod routes;
 
 use crate::{
-    database::Database,
+    authentication::password::PasswordPolicy,
+    database::{Database, MAX_SESSIONS},
     error,
     model::{ListOptions, Status},
+    session::Resource,
+    utils::crypto::{Aead256, CryptoError},
     Result,
 };
 
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
 use hyper::StatusCode;
 use mongodb::{
     bson::{
-        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_bson, Document,
+        doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime, to_bson, to_document,
+        DateTime as BsonDateTime, Document,
     },
-    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+    options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument},
+    IndexModel,
 };
 use serde::{Deserialize, Serialize};
 
+pub use crate::models::user::{AccountFlag, Connection, ProfileField, Role};
+pub use approval::ApprovalError;
+pub use elevation::{spawn_expiry as spawn_elevation_expiry, ElevationDocument};
+pub use mfa::{
+    verify_code as verify_mfa_code, verify_recovery_code, MfaClaims, MfaDocument, MfaError,
+};
+pub use notifier::SecurityNotifier;
+pub use profile::{age_over_claim, Profile};
 pub use routes::routes;
 
 #[derive(Debug, PartialEq, thiserror::Error)]
@@ -33,6 +53,18 @@ pub enum UserError {
     InvalidAddr,
     #[error("email address not allowed")]
     DomainNotAllowed,
+    #[error("user has no connection")]
+    NoConnection,
+    #[error("user already has the role")]
+    RoleAlreadyGranted,
+    #[error("{0} of the elevation is invalid")]
+    InvalidElevation(&'static str),
+    #[error("end of the ban has to be in the future")]
+    InvalidBan,
+    #[error("{0} of the profile is invalid")]
+    InvalidProfile(&'static str),
+    #[error("birthdate can only be changed by an admin")]
+    BirthdateSet,
 }
 
 impl error::ErrorResponse for UserError {
@@ -44,7 +76,13 @@ impl error::ErrorResponse for UserError {
             UserError::AlreadyExists | UserError::InvalidAddr | UserError::InvalidId => {
                 StatusCode::BAD_REQUEST
             }
-            UserError::DomainNotAllowed => StatusCode::UNPROCESSABLE_ENTITY,
+            UserError::DomainNotAllowed | UserError::NoConnection => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            UserError::RoleAlreadyGranted | UserError::BirthdateSet => StatusCode::CONFLICT,
+            UserError::InvalidElevation(_)
+            | UserError::InvalidBan
+            | UserError::InvalidProfile(_) => StatusCode::BAD_REQUEST,
         }
     }
 
@@ -53,29 +91,49 @@ impl error::ErrorResponse for UserError {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub enum Role {
-    UserEditor,
-    UserViewer,
-    ClientEditor,
-    ClientViewer,
-    ServiceEditor,
-    ServiceViewer,
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserDocument {
     #[serde(rename = "_id")]
     pub id: ObjectId,
     pub email: String,
+    pub name: Option<String>,
+    /// Fields that services may require, see [`profile`]
+    #[serde(default)]
+    pub profile: Profile,
     pub password: Option<String>,
     pub roles: Vec<Role>,
     pub verified: bool,
+    /// Unset if an admin locked the account
     pub can_login: bool,
+    /// Logins are rejected until then, set by an admin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banned_until: Option<BsonDateTime>,
+    /// Pre-registered by an admin and not yet claimed by a login
+    #[serde(default)]
+    pub pending: bool,
     pub connections: Vec<Connection>,
+    #[serde(default)]
+    pub flags: Vec<AccountFlag>,
+    /// Tokens of the connections, keyed by provider type
+    #[serde(default)]
+    pub provider_tokens: HashMap<String, ProviderToken>,
     pub last_sessions: Vec<SessionDocument>,
+    /// Sessions issued before are revoked
+    #[serde(default)]
+    pub sessions_valid_after: Option<BsonDateTime>,
+    /// Session tokens of earlier epochs are invalid, see [`crate::epoch`]
+    #[serde(default)]
+    pub token_epoch: u32,
+    /// Failed password logins since the last successful one
+    #[serde(default)]
+    pub failed_logins: u32,
+    /// Password logins are rejected until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<BsonDateTime>,
+    /// Authenticator app, required for logins once enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mfa: Option<MfaDocument>,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub last_modified: DateTime<Utc>,
 }
@@ -85,53 +143,242 @@ impl Default for UserDocument {
         Self {
             id: Default::default(),
             email: Default::default(),
+            name: Default::default(),
+            profile: Default::default(),
             password: Default::default(),
             roles: Default::default(),
             verified: false,
             can_login: true,
+            banned_until: None,
+            pending: false,
             connections: Default::default(),
+            flags: Default::default(),
+            provider_tokens: Default::default(),
             last_sessions: Default::default(),
+            sessions_valid_after: None,
+            token_epoch: 0,
+            failed_logins: 0,
+            locked_until: None,
+            mfa: None,
             last_modified: Utc::now(),
         }
     }
 }
 
+impl UserDocument {
+    /// Returns `true` if an admin banned the user until after `now`
+    pub fn is_banned(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.banned_until, Some(v) if now < v.to_chrono())
+    }
+
+    /// Returns `true` if password logins are locked after failed attempts
+    pub fn is_locked(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.locked_until, Some(v) if now < v.to_chrono())
+    }
+
+    /// Decrypts the stored refresh token of the provider, none if there is no token
+    #[cfg_attr(
+        not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+        allow(dead_code)
+    )]
+    pub fn provider_refresh_token(&self, enc: &Aead256, provider: &str) -> Result<Option<String>> {
+        self.provider_tokens
+            .get(provider)
+            .map(|token| token.decrypt(enc, self.id))
+            .transpose()
+    }
+
+    /// Evaluates the account flags derived from the connection data
+    pub fn connection_flags(&self) -> Vec<AccountFlag> {
+        let mut flags = Vec::new();
+
+        if self
+            .connections
+            .iter()
+            .any(|c| c.two_factor_enabled() == Some(false))
+        {
+            flags.push(AccountFlag::ProviderTwoFactorDisabled);
+        }
+
+        flags
+    }
+}
+
+impl AccountFlag {
+    /// Returns `true` if the flag is derived from the connection data
+    fn is_connection_flag(&self) -> bool {
+        match self {
+            AccountFlag::ProviderTwoFactorDisabled => true,
+            AccountFlag::PasswordResetRequired | AccountFlag::RelinkRequired => false,
+        }
+    }
+}
+
+/// Provider token, encrypted at rest
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct SessionDocument {
+pub struct ProviderToken {
+    refresh_token: String,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
-    pub date: DateTime<Utc>,
+    pub last_modified: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase", tag = "type")]
-pub enum Connection {
-    #[serde(rename_all = "camelCase")]
-    GitHub {
-        user_id: i64,
-        login: String,
-        two_factor_enabled: bool,
-    },
+impl ProviderToken {
+    #[cfg_attr(
+        not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+        allow(dead_code)
+    )]
+    pub fn new(enc: &Aead256, refresh_token: &str) -> Self {
+        Self {
+            refresh_token: base64::encode_config(enc.encrypt(refresh_token), base64::STANDARD),
+            last_modified: Utc::now(),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+        allow(dead_code)
+    )]
+    fn decrypt(&self, enc: &Aead256, user_id: ObjectId) -> Result<String> {
+        let token = base64::decode_config(&self.refresh_token, base64::STANDARD).map_err(|_| {
+            CryptoError::InvalidEncoding(format!("provider token of user {}", user_id))
+        })?;
+        let token = enc.decrypt(token)?;
+
+        Ok(String::from_utf8(token).map_err(|_| CryptoError::Decryption)?)
+    }
+
+    /// Re-encrypts the token with the current key, returns `false` if it already uses the current key
+    fn reencrypt(&mut self, enc: &Aead256, user_id: ObjectId) -> Result<bool> {
+        let token = base64::decode_config(&self.refresh_token, base64::STANDARD).map_err(|_| {
+            CryptoError::InvalidEncoding(format!("provider token of user {}", user_id))
+        })?;
+
+        match enc.reencrypt(token)? {
+            Some(token) => {
+                self.refresh_token = base64::encode_config(token, base64::STANDARD);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionDocument {
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub date: DateTime<Utc>,
+    /// Region that issued the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 impl Connection {
     pub fn type_name(&self) -> &'static str {
         match self {
             Connection::GitHub { .. } => "github",
+            Connection::Apple { .. } => "apple",
+            Connection::Twitch { .. } => "twitch",
+            Connection::Steam { .. } => "steam",
+            Connection::Discord { .. } => "discord",
+            Connection::Federated { .. } => "federated",
+            Connection::Sandbox { .. } => "sandbox",
         }
     }
 
-    /// Returns `true` if the connection is [`GitHub`].
-    ///
-    /// [`GitHub`]: Connection::GitHub
-    pub fn is_github(&self) -> bool {
-        matches!(self, Self::GitHub { .. })
+    /// Returns `true` if both connections belong to the same provider
+    pub fn is_same_provider(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::GitHub { .. }, Self::GitHub { .. }) => true,
+            (Self::Apple { .. }, Self::Apple { .. }) => true,
+            (Self::Twitch { .. }, Self::Twitch { .. }) => true,
+            (Self::Steam { .. }, Self::Steam { .. }) => true,
+            (Self::Discord { .. }, Self::Discord { .. }) => true,
+            (Self::Sandbox { .. }, Self::Sandbox { .. }) => true,
+            (Self::Federated { issuer: a, .. }, Self::Federated { issuer: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Two-factor status at the provider, `None` if the provider doesn't share it
+    pub fn two_factor_enabled(&self) -> Option<bool> {
+        match self {
+            Connection::GitHub {
+                two_factor_enabled, ..
+            } => Some(*two_factor_enabled),
+            Connection::Discord { mfa_enabled, .. } => Some(*mfa_enabled),
+            _ => None,
+        }
+    }
+
+    /// Query matching connections of the same provider
+    pub fn provider_filter(&self) -> Document {
+        match self {
+            Connection::GitHub { .. }
+            | Connection::Apple { .. }
+            | Connection::Twitch { .. }
+            | Connection::Steam { .. }
+            | Connection::Discord { .. }
+            | Connection::Sandbox { .. } => doc! { "type": self.type_name() },
+            Connection::Federated { issuer, .. } => {
+                doc! { "type": self.type_name(), "issuer": issuer }
+            }
+        }
+    }
+
+    /// Query matching the account of the connection
+    pub fn account_filter(&self) -> Document {
+        match self {
+            Connection::GitHub { user_id, .. } => {
+                doc! { "type": self.type_name(), "userId": user_id }
+            }
+            Connection::Apple { user_id, .. }
+            | Connection::Twitch { user_id, .. }
+            | Connection::Discord { user_id, .. }
+            | Connection::Sandbox { user_id } => {
+                doc! { "type": self.type_name(), "userId": user_id }
+            }
+            Connection::Steam { steam_id } => {
+                doc! { "type": self.type_name(), "steamId": steam_id }
+            }
+            Connection::Federated { issuer, subject } => {
+                doc! { "type": self.type_name(), "issuer": issuer, "subject": subject }
+            }
+        }
     }
 }
 
 const COLLECTION: &str = "users";
 
 impl Database {
+    /// Makes email addresses unique, fails while users share one
+    pub async fn index_user_emails(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "email": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+
+        self.collection::<UserDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Finds users by the accounts of their connections
+    pub async fn index_user_connections(&self) -> Result<()> {
+        let index = IndexModel::builder()
+            .keys(doc! { "connections.type": 1, "connections.userId": 1 })
+            .build();
+
+        self.collection::<UserDocument>(COLLECTION)
+            .create_index(index, None)
+            .await?;
+
+        Ok(())
+    }
+
     async fn get_users<F>(&self, filter: F, opts: ListOptions) -> Result<(Vec<UserDocument>, u64)>
     where
         F: Into<Option<Document>>,
@@ -163,6 +410,19 @@ impl Database {
         Ok((users, total))
     }
 
+    /// IDs of all users with the role
+    pub async fn get_user_ids_with_role(&self, role: &Role) -> Result<Vec<ObjectId>> {
+        let ids = self
+            .collection::<UserDocument>(COLLECTION)
+            .distinct("_id", doc! { "roles": to_bson(role).unwrap() }, None)
+            .await?
+            .into_iter()
+            .filter_map(|v| v.as_object_id())
+            .collect();
+
+        Ok(ids)
+    }
+
     pub async fn get_user(&self, filter: Document) -> Result<UserDocument> {
         let user = self
             .collection::<UserDocument>(COLLECTION)
@@ -177,6 +437,10 @@ impl Database {
     }
 
     pub async fn insert_user(&self, doc: &UserDocument) -> Result<()> {
+        self.check_quota(Resource::User).await?;
+        #[cfg(feature = "hooks")]
+        crate::hooks::pre_user_create(&doc.email).await?;
+
         self.collection::<UserDocument>(COLLECTION)
             .insert_one(doc, None)
             .await?;
@@ -184,26 +448,57 @@ impl Database {
         Ok(())
     }
 
+    /// Inserts the user unless one with the email address exists, that one is returned instead.
+    ///
+    /// Concurrent calls with the same address insert only one user.
+    pub async fn insert_user_if_new(&self, doc: &UserDocument) -> Result<Option<UserDocument>> {
+        self.check_quota(Resource::User).await?;
+        #[cfg(feature = "hooks")]
+        crate::hooks::pre_user_create(&doc.email).await?;
+
+        let opts = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+        let existing = self
+            .collection::<UserDocument>(COLLECTION)
+            .find_one_and_update(
+                doc! { "email": &doc.email },
+                doc! { "$setOnInsert": to_document(doc).unwrap() },
+                opts,
+            )
+            .await?;
+
+        Ok(existing)
+    }
+
     pub async fn update_user(&self, filter: Document, update: Document) -> Result<UserDocument> {
-        let doc = doc! {
-            "$currentDate": { "lastModified": true },
-            "$set": update
-        };
+        self.modify_user(filter, doc! { "$set": update }).await
+    }
+
+    /// Applies update operators to the user matching the filter and records the revision
+    async fn modify_user(&self, filter: Document, mut doc: Document) -> Result<UserDocument> {
+        doc.insert("$currentDate", doc! { "lastModified": true });
 
         let opts = FindOneAndUpdateOptions::builder()
-            .return_document(ReturnDocument::After)
+            .return_document(ReturnDocument::Before)
             .build();
 
-        let result = self
+        let before = match self
             .collection::<UserDocument>(COLLECTION)
             .find_one_and_update(filter, doc, opts)
-            .await?;
+            .await?
+        {
+            Some(v) => v,
+            None => return Err(UserError::NotFound.into()),
+        };
 
-        if result.is_none() {
-            return Err(UserError::NotFound.into());
-        }
+        let after = self.get_user(doc! { "_id": before.id }).await?;
 
-        Ok(result.unwrap())
+        self.record_revision(Resource::User, before.id, &before, &after)
+            .await?;
+
+        Ok(after)
     }
 
     pub async fn update_user_by_id(
@@ -214,15 +509,33 @@ impl Database {
         self.update_user(doc! { "_id": user_id }, update).await
     }
 
+    /// Activates a pre-registered user, the provider has verified the address
+    pub async fn claim_user(&self, user_id: ObjectId) -> Result<UserDocument> {
+        let filter = doc! { "_id": user_id, "pending": true };
+
+        self.update_user(filter, doc! { "pending": false, "verified": true })
+            .await
+    }
+
+    /// Adds the connection, or updates the one of the same provider if a concurrent login added
+    /// it first
     pub async fn insert_user_connection(
         &self,
         user_id: ObjectId,
         connection: Connection,
     ) -> Result<UserDocument> {
-        let filter = doc! { "_id": user_id };
+        let filter = doc! {
+            "_id": user_id,
+            "connections": { "$not": { "$elemMatch": connection.provider_filter() } },
+        };
         let update = doc! {"$push": {"connections": to_bson(&connection).unwrap() } };
 
-        self.update_user(filter, update).await
+        match self.modify_user(filter, update).await {
+            Err(error::Error::User(UserError::NotFound)) => {
+                self.update_user_connection(user_id, connection).await
+            }
+            result => result,
+        }
     }
 
     pub async fn update_user_connection(
@@ -230,10 +543,117 @@ impl Database {
         user_id: ObjectId,
         connection: Connection,
     ) -> Result<UserDocument> {
-        let filter = doc! { "_id": user_id, "connections.type": connection.type_name() };
+        let filter = doc! {
+            "_id": user_id,
+            "connections": { "$elemMatch": connection.provider_filter() },
+        };
         let update = doc! { "$set": { "connections.$": to_bson(&connection).unwrap() } };
 
-        self.update_user(filter, update).await
+        self.modify_user(filter, update).await
+    }
+
+    #[cfg_attr(
+        not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+        allow(dead_code)
+    )]
+    pub async fn set_user_provider_token(
+        &self,
+        user_id: ObjectId,
+        connection: &Connection,
+        token: ProviderToken,
+    ) -> Result<UserDocument> {
+        let field = format!("providerTokens.{}", connection.type_name());
+
+        self.update_user_by_id(user_id, doc! { field: to_bson(&token).unwrap() })
+            .await
+    }
+
+    pub async fn add_user_flag(
+        &self,
+        user_id: ObjectId,
+        flag: AccountFlag,
+    ) -> Result<UserDocument> {
+        let update = doc! { "$addToSet": { "flags": to_bson(&flag).unwrap() } };
+
+        self.modify_user(doc! { "_id": user_id }, update).await
+    }
+
+    pub async fn remove_user_flag(
+        &self,
+        user_id: ObjectId,
+        flag: AccountFlag,
+    ) -> Result<UserDocument> {
+        let update = doc! { "$pull": { "flags": to_bson(&flag).unwrap() } };
+
+        self.modify_user(doc! { "_id": user_id }, update).await
+    }
+
+    /// Returns the users with a stored token of the provider
+    #[cfg_attr(
+        not(any(feature = "sso-apple", feature = "sso-twitch", feature = "sso-discord")),
+        allow(dead_code)
+    )]
+    pub async fn get_users_with_provider_token(&self, provider: &str) -> Result<Vec<UserDocument>> {
+        let filter = doc! { format!("providerTokens.{}", provider): { "$exists": true } };
+
+        let users = self
+            .collection::<UserDocument>(COLLECTION)
+            .find(filter, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(users)
+    }
+
+    /// Re-encrypts the provider tokens of all users with the current key
+    pub async fn reencrypt_provider_tokens(&self, enc: &Aead256) -> Result<u64> {
+        let coll = self.collection::<UserDocument>(COLLECTION);
+        let filter = doc! { "providerTokens": { "$exists": true, "$ne": {} } };
+        let mut cursor = coll.find(filter, None).await?;
+
+        let mut count = 0;
+        while let Some(mut user) = cursor.try_next().await? {
+            let mut update = Document::new();
+            for (provider, token) in user.provider_tokens.iter_mut() {
+                if token.reencrypt(enc, user.id)? {
+                    update.insert(
+                        format!("providerTokens.{}", provider),
+                        to_bson(token).unwrap(),
+                    );
+                }
+            }
+
+            if !update.is_empty() {
+                coll.update_one(doc! { "_id": user.id }, doc! { "$set": update }, None)
+                    .await?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Re-evaluates the connection flags of the user and stores them if they changed
+    pub async fn refresh_user_flags(&self, doc: UserDocument) -> Result<UserDocument> {
+        let mut flags = doc
+            .flags
+            .iter()
+            .copied()
+            .filter(|f| !f.is_connection_flag())
+            .collect::<Vec<_>>();
+        flags.extend(doc.connection_flags());
+
+        if flags == doc.flags {
+            return Ok(doc);
+        }
+
+        for flag in flags.iter().filter(|f| !doc.flags.contains(f)) {
+            tracing::warn!(user = %doc.id, flag = ?flag, "account flagged");
+        }
+
+        self.update_user_by_id(doc.id, doc! { "flags": to_bson(&flags).unwrap() })
+            .await
     }
 
     async fn delete_user(&self, user_id: ObjectId) -> Result<()> {
@@ -249,18 +669,120 @@ impl Database {
         Ok(())
     }
 
-    pub async fn set_user_session(&self, user_id: ObjectId) -> Result<()> {
+    /// Revokes all sessions, refresh tokens and device codes of the user.
+    ///
+    /// Session tokens issued up to the end of the current second are rejected.
+    pub async fn revoke_user_sessions(
+        &self,
+        user_id: ObjectId,
+        now: DateTime<Utc>,
+    ) -> Result<UserDocument> {
+        // Token timestamps have a precision of seconds, the cutoff is rounded up
+        let cutoff = now.timestamp() + i64::from(now.timestamp_subsec_nanos() > 0);
+
+        let user = self
+            .update_user_by_id(
+                user_id,
+                doc! { "sessionsValidAfter": BsonDateTime::from_millis(cutoff * 1000) },
+            )
+            .await?;
+        self.revoke_user_refresh_tokens(user_id).await?;
+        self.delete_user_device_codes(user_id).await?;
+        self.end_user_sessions(user_id).await?;
+
+        Ok(user)
+    }
+
+    /// Invalidates all session tokens of the user, revokes its refresh tokens and ends its
+    /// sessions
+    pub async fn bump_user_token_epoch(&self, user_id: ObjectId) -> Result<UserDocument> {
+        let user = self
+            .modify_user(
+                doc! { "_id": user_id },
+                doc! { "$inc": { "tokenEpoch": 1 } },
+            )
+            .await?;
+        self.revoke_user_refresh_tokens(user_id).await?;
+        self.end_user_sessions(user_id).await?;
+
+        Ok(user)
+    }
+
+    /// Counts a failed password login and locks password logins once there are too many.
+    ///
+    /// Returns `true` if the account was locked.
+    pub async fn record_login_failure(
+        &self,
+        user_id: ObjectId,
+        policy: &PasswordPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let coll = self.collection::<UserDocument>(COLLECTION);
+
+        let opts = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let user = coll
+            .find_one_and_update(
+                doc! { "_id": user_id },
+                doc! { "$inc": { "failedLogins": 1 } },
+                opts,
+            )
+            .await?
+            .ok_or(UserError::NotFound)?;
+
+        if policy.max_failures == 0 || user.failed_logins < policy.max_failures {
+            return Ok(false);
+        }
+
+        let until = BsonDateTime::from_chrono(now + policy.lockout);
+        coll.update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "lockedUntil": until, "failedLogins": 0 } },
+            None,
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Clears the failed logins and an active lockout
+    pub async fn reset_login_failures(&self, user_id: ObjectId) -> Result<()> {
+        self.collection::<UserDocument>(COLLECTION)
+            .update_one(
+                doc! { "_id": user_id },
+                doc! { "$set": { "failedLogins": 0 }, "$unset": { "lockedUntil": "" } },
+                None,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_user_session(&self, user_id: ObjectId, region: Option<&str>) -> Result<()> {
+        if let Some(timestamps) = self.timestamps() {
+            let session = SessionDocument {
+                date: Utc::now(),
+                region: region.map(String::from),
+            };
+            timestamps.user_session(user_id, session);
+            return Ok(());
+        }
+
         let filter = doc! { "_id": user_id };
 
         let UserDocument {
             mut last_sessions, ..
         } = self.get_user(filter.clone()).await?;
 
-        if last_sessions.len() == 5 {
+        if last_sessions.len() == MAX_SESSIONS {
             last_sessions = last_sessions.into_iter().skip(1).collect();
         }
 
-        last_sessions.push(SessionDocument { date: Utc::now() });
+        last_sessions.push(SessionDocument {
+            date: Utc::now(),
+            region: region.map(String::from),
+        });
 
         let doc = doc! {
             "$set": {"lastSessions": to_bson(&last_sessions).unwrap()},
@@ -277,4 +799,57 @@ impl Database {
 
         Ok(())
     }
+
+    /// Appends the sessions to the last ones, written behind of the logins
+    pub async fn push_user_sessions(
+        &self,
+        user_id: ObjectId,
+        sessions: Vec<SessionDocument>,
+    ) -> Result<()> {
+        let update = doc! {
+            "$push": { "lastSessions": {
+                "$each": to_bson(&sessions).unwrap(),
+                "$slice": -(MAX_SESSIONS as i32),
+            } },
+        };
+
+        self.collection::<UserDocument>(COLLECTION)
+            .update_one(doc! { "_id": user_id }, update, None)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AEAD_KEY: &str = "Dhh0uAQDDQO90882bbZbyz1jWf4MrxI2";
+    const AEAD_KEY_NEW: &str = "u7Rk2pQx9WmZc4Lb1NvYs8Ea3TgHd6Jf";
+
+    #[test]
+    fn decrypts_provider_tokens() {
+        let old = Aead256::new(1, AEAD_KEY).unwrap();
+        let mut user = UserDocument::default();
+        user.provider_tokens
+            .insert("discord".into(), ProviderToken::new(&old, "refresh"));
+
+        assert_eq!(
+            user.provider_refresh_token(&old, "discord").unwrap(),
+            Some("refresh".to_string())
+        );
+        assert_eq!(user.provider_refresh_token(&old, "twitch").unwrap(), None);
+
+        // Tokens of a previous key stay readable after a rotation
+        let mut new = Aead256::new(2, AEAD_KEY_NEW).unwrap();
+        new.add_previous_key(1, AEAD_KEY).unwrap();
+        assert_eq!(
+            user.provider_refresh_token(&new, "discord").unwrap(),
+            Some("refresh".to_string())
+        );
+
+        let other = Aead256::new(1, AEAD_KEY_NEW).unwrap();
+        assert!(user.provider_refresh_token(&other, "discord").is_err());
+    }
 }
@@ -0,0 +1,34 @@
+use crate::mail;
+
+use std::{collections::HashMap, sync::Arc};
+
+use tracing::warn;
+
+/// Mails the security contacts about sensitive changes of accounts
+#[derive(Clone)]
+pub struct SecurityNotifier {
+    contacts: Arc<Vec<String>>,
+    mail: mail::Client,
+}
+
+impl SecurityNotifier {
+    pub fn new(contacts: Vec<String>, mail: mail::Client) -> Self {
+        Self {
+            contacts: Arc::new(contacts),
+            mail,
+        }
+    }
+
+    /// Failures are only logged, the change itself is recorded already
+    pub async fn send(&self, subject: &str, template: &str, vars: HashMap<String, String>) {
+        for addr in self.contacts.iter() {
+            if let Err(e) = self
+                .mail
+                .send_template(addr, subject, template, vars.clone())
+                .await
+            {
+                warn!(template, error = %e, "security notification could not be sent");
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Profile fields that services require before users authorize their clients.
+//!
+//! Authorizations of users missing a required field are sent to a hosted form first, which
+//! stores the answers through the profile endpoint and resumes the authorization.
+//!
+//! The birthdate is never shared, services with a minimum age get an `age_over_<n>` claim.
+
+use crate::models::user::{ProfileField, ProfileRequest};
+
+use super::{UserDocument, UserError};
+
+use chrono::{Datelike, NaiveDate};
+use mongodb::bson::Document;
+use serde::{Deserialize, Serialize};
+
+const MAX_NAME_LEN: usize = 64;
+const MAX_LOCALE_LEN: usize = 35;
+/// Earliest accepted birthdate
+const MIN_BIRTH_YEAR: i32 = 1900;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// ISO 3166-1 alpha-2 code, uppercase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// BCP 47 language tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birthdate: Option<NaiveDate>,
+}
+
+impl UserDocument {
+    /// Fields of `required` the user didn't fill in yet
+    pub fn missing_profile_fields(&self, required: &[ProfileField]) -> Vec<ProfileField> {
+        required
+            .iter()
+            .filter(|f| match f {
+                ProfileField::Name => self.name.is_none(),
+                ProfileField::Country => self.profile.country.is_none(),
+                ProfileField::Locale => self.profile.locale.is_none(),
+                ProfileField::Birthdate => self.profile.birthdate.is_none(),
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Age in full years on `today`, if the birthdate is known
+    pub fn age(&self, today: NaiveDate) -> Option<u32> {
+        let birthdate = self.profile.birthdate?;
+
+        let mut years = today.year() - birthdate.year();
+        if (today.month(), today.day()) < (birthdate.month(), birthdate.day()) {
+            years -= 1;
+        }
+
+        u32::try_from(years).ok()
+    }
+}
+
+/// Name of the claim derived from the birthdate for a minimum age
+pub fn age_over_claim(min_age: u8) -> String {
+    format!("age_over_{}", min_age)
+}
+
+impl ProfileField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProfileField::Name => "name",
+            ProfileField::Country => "country",
+            ProfileField::Locale => "locale",
+            ProfileField::Birthdate => "birthdate",
+        }
+    }
+}
+
+fn validate_name(v: &str) -> Result<String, UserError> {
+    let v = v.trim();
+    if v.is_empty() || v.chars().count() > MAX_NAME_LEN || v.chars().any(char::is_control) {
+        return Err(UserError::InvalidProfile("name"));
+    }
+
+    Ok(v.to_string())
+}
+
+fn validate_country(v: &str) -> Result<String, UserError> {
+    if v.len() != 2 || !v.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(UserError::InvalidProfile("country"));
+    }
+
+    Ok(v.to_ascii_uppercase())
+}
+
+/// Checks the shape of a language tag, e.g. `en` or `de-AT`, not the registry of subtags
+fn validate_locale(v: &str) -> Result<String, UserError> {
+    let mut subtags = v.split('-');
+    let language = subtags.next().unwrap_or_default();
+
+    let valid = v.len() <= MAX_LOCALE_LEN
+        && (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !valid {
+        return Err(UserError::InvalidProfile("locale"));
+    }
+
+    Ok(v.to_string())
+}
+
+fn validate_birthdate(v: NaiveDate, today: NaiveDate) -> Result<String, UserError> {
+    if v > today || v.year() < MIN_BIRTH_YEAR {
+        return Err(UserError::InvalidProfile("birthdate"));
+    }
+
+    Ok(v.to_string())
+}
+
+/// Validates the request, returns the update of the user document
+pub fn update_document(req: ProfileRequest, today: NaiveDate) -> Result<Document, UserError> {
+    let mut doc = Document::new();
+    if let Some(v) = req.name {
+        doc.insert("name", validate_name(&v)?);
+    }
+    if let Some(v) = req.country {
+        doc.insert("profile.country", validate_country(&v)?);
+    }
+    if let Some(v) = req.locale {
+        doc.insert("profile.locale", validate_locale(&v)?);
+    }
+    if let Some(v) = req.birthdate {
+        doc.insert("profile.birthdate", validate_birthdate(v, today)?);
+    }
+
+    Ok(doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_missing_fields() {
+        let user = UserDocument {
+            name: Some("Nikita".into()),
+            profile: Profile {
+                locale: Some("en".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            user.missing_profile_fields(&[
+                ProfileField::Name,
+                ProfileField::Country,
+                ProfileField::Locale
+            ]),
+            vec![ProfileField::Country]
+        );
+        assert!(user.missing_profile_fields(&[]).is_empty());
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn validates_fields() {
+        let today = date(2022, 6, 1);
+
+        let doc = update_document(
+            ProfileRequest {
+                name: Some(" Nikita ".into()),
+                country: Some("de".into()),
+                locale: Some("de-AT".into()),
+                birthdate: Some(date(2004, 6, 2)),
+            },
+            today,
+        )
+        .unwrap();
+        assert_eq!(doc.get_str("name").unwrap(), "Nikita");
+        assert_eq!(doc.get_str("profile.country").unwrap(), "DE");
+        assert_eq!(doc.get_str("profile.locale").unwrap(), "de-AT");
+        assert_eq!(doc.get_str("profile.birthdate").unwrap(), "2004-06-02");
+
+        let invalid = |req| update_document(req, today).unwrap_err();
+        assert_eq!(
+            invalid(ProfileRequest {
+                name: Some(" ".into()),
+                ..Default::default()
+            }),
+            UserError::InvalidProfile("name")
+        );
+        assert_eq!(
+            invalid(ProfileRequest {
+                country: Some("DEU".into()),
+                ..Default::default()
+            }),
+            UserError::InvalidProfile("country")
+        );
+        assert_eq!(
+            invalid(ProfileRequest {
+                locale: Some("english".into()),
+                ..Default::default()
+            }),
+            UserError::InvalidProfile("locale")
+        );
+        assert_eq!(
+            invalid(ProfileRequest {
+                birthdate: Some(date(2022, 6, 2)),
+                ..Default::default()
+            }),
+            UserError::InvalidProfile("birthdate")
+        );
+    }
+
+    #[test]
+    fn counts_full_years() {
+        let mut user = UserDocument::default();
+        assert_eq!(user.age(date(2022, 6, 1)), None);
+
+        user.profile.birthdate = Some(date(2004, 6, 2));
+        assert_eq!(user.age(date(2022, 6, 1)), Some(17));
+        assert_eq!(user.age(date(2022, 6, 2)), Some(18));
+
+        // Leap day birthdays count on March 1 in common years
+        user.profile.birthdate = Some(date(2004, 2, 29));
+        assert_eq!(user.age(date(2022, 2, 28)), Some(17));
+        assert_eq!(user.age(date(2022, 3, 1)), Some(18));
+    }
+}
@@ -1,15 +1,47 @@
 use super::handler;
 
-use axum::routing::get;
+use crate::{action, oauth, session};
+
+use axum::routing::{delete, get, patch, post};
 
 /// User routes
 pub fn routes() -> axum::Router {
     axum::Router::new()
         .route("/", get(handler::list).post(handler::create))
+        .route(
+            "/verify",
+            get(action::verify_email).post(handler::resend_verification),
+        )
+        .route("/approvals", get(handler::list_approvals))
+        .route("/approvals/:id/approve", post(handler::approve))
+        .route("/approvals/:id/reject", post(handler::reject))
+        .route("/me/security-events", get(handler::security_events))
+        .route("/me/sessions/revoke-all", post(handler::revoke_sessions))
+        .route("/me/consents", get(oauth::list_consents))
+        .route("/me/consents/:client", delete(oauth::revoke_consent))
         .route(
             "/:id",
             get(handler::get_by_id)
                 .patch(handler::update)
                 .delete(handler::delete),
         )
+        .route(
+            "/:id/force-password-reset",
+            post(handler::force_password_reset),
+        )
+        .route("/:id/force-relink", post(handler::force_relink))
+        .route("/:id/lock", post(handler::lock))
+        .route("/:id/unlock", post(handler::unlock))
+        .route("/:id/ban", post(handler::ban))
+        .route("/:id/elevate", post(handler::elevate))
+        .route("/:id/profile", patch(handler::update_profile))
+        .route(
+            "/:id/mfa/totp",
+            post(handler::enroll_totp).delete(handler::disable_totp),
+        )
+        .route("/:id/mfa/totp/confirm", post(handler::confirm_totp))
+        .route("/:id/sessions", get(session::list_sessions))
+        .route("/:id/sessions/:jti", delete(session::revoke_session))
+        .route("/:id/history", get(handler::history))
+        .route("/:id/revert/:revision", post(handler::revert))
 }
@@ -2,24 +2,64 @@ use aes_gcm_siv::{
     aead::{Aead, NewAead},
     Aes256GcmSiv, Key, Nonce,
 };
-use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Deserializer};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CryptoError {
     #[error("key has an invalid size")]
     InvalidKeySize,
+    #[error("key version {0} is unknown")]
+    UnknownKeyVersion(u8),
+    #[error("decryption failed")]
+    Decryption,
+    #[error("{0} is not valid base64")]
+    InvalidEncoding(String),
 }
 
+/// AES-256-GCM-SIV with versioned keys.
+///
+/// Values are encrypted with the current key and prefixed with its version, previous keys
+/// are only used for decryption. Values without a version prefix were encrypted before keys
+/// were versioned and are decrypted with any matching key.
 #[derive(Clone)]
 pub struct Aead256 {
-    cipher: Aes256GcmSiv,
+    version: u8,
+    ciphers: Vec<(u8, Aes256GcmSiv)>,
 }
 
 impl Aead256 {
     const KEY_SIZE: usize = 256 / 8;
     const NONCE_SIZE: usize = 96 / 8;
 
-    pub fn new<K>(key: K) -> Result<Self, CryptoError>
+    /// Marks versioned values, unversioned values always start with an alphanumeric nonce
+    const VERSION_MARKER: u8 = 0x00;
+    const HEADER_SIZE: usize = 2;
+
+    pub fn new<K>(version: u8, key: K) -> Result<Self, CryptoError>
+    where
+        K: AsRef<[u8]>,
+    {
+        Ok(Self {
+            version,
+            ciphers: vec![(version, Self::cipher(key)?)],
+        })
+    }
+
+    /// Adds a previous key that is only used for decryption
+    pub fn add_previous_key<K>(&mut self, version: u8, key: K) -> Result<(), CryptoError>
+    where
+        K: AsRef<[u8]>,
+    {
+        let cipher = Self::cipher(key)?;
+
+        if !self.ciphers.iter().any(|(v, _)| *v == version) {
+            self.ciphers.push((version, cipher));
+        }
+
+        Ok(())
+    }
+
+    fn cipher<K>(key: K) -> Result<Aes256GcmSiv, CryptoError>
     where
         K: AsRef<[u8]>,
     {
@@ -29,61 +69,143 @@ impl Aead256 {
             return Err(CryptoError::InvalidKeySize);
         }
 
-        let key = Key::from_slice(key);
-        let cipher = Aes256GcmSiv::new(key);
+        Ok(Aes256GcmSiv::new(Key::from_slice(key)))
+    }
 
-        Ok(Self { cipher })
+    fn get_cipher(&self, version: u8) -> Result<&Aes256GcmSiv, CryptoError> {
+        self.ciphers
+            .iter()
+            .find(|(v, _)| *v == version)
+            .map(|(_, c)| c)
+            .ok_or(CryptoError::UnknownKeyVersion(version))
     }
 
     pub fn encrypt<P>(&self, plaintext: P) -> Vec<u8>
     where
         P: AsRef<[u8]>,
     {
-        let nonce = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(Self::NONCE_SIZE)
-            .map(char::from)
-            .collect::<String>();
+        let nonce = rand::random::<[u8; Self::NONCE_SIZE]>();
 
         let mut ciphertext: Vec<u8> = self
-            .cipher
-            .encrypt(Nonce::from_slice(nonce.as_bytes()), plaintext.as_ref())
+            .get_cipher(self.version)
+            .unwrap()
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
             .expect("encyrption failed");
 
-        let mut nc = Vec::with_capacity(nonce.len() + ciphertext.len());
-        nc.extend_from_slice(nonce.as_bytes());
+        let mut nc = Vec::with_capacity(Self::HEADER_SIZE + nonce.len() + ciphertext.len());
+        nc.extend_from_slice(&[Self::VERSION_MARKER, self.version]);
+        nc.extend_from_slice(&nonce);
         nc.append(&mut ciphertext);
 
         nc
     }
 
-    pub fn decrypt<C>(&self, nonce_ciphertext: C) -> Vec<u8>
+    pub fn decrypt<C>(&self, nonce_ciphertext: C) -> Result<Vec<u8>, CryptoError>
+    where
+        C: AsRef<[u8]>,
+    {
+        match Self::version_of(nonce_ciphertext.as_ref()) {
+            Some(version) => {
+                let nc = &nonce_ciphertext.as_ref()[Self::HEADER_SIZE..];
+                Self::open(self.get_cipher(version)?, nc)
+            }
+            None => self
+                .ciphers
+                .iter()
+                .find_map(|(_, c)| Self::open(c, nonce_ciphertext.as_ref()).ok())
+                .ok_or(CryptoError::Decryption),
+        }
+    }
+
+    /// Re-encrypts a value with the current key, returns `None` if it already uses the current key
+    pub fn reencrypt<C>(&self, nonce_ciphertext: C) -> Result<Option<Vec<u8>>, CryptoError>
     where
         C: AsRef<[u8]>,
     {
-        let nc = nonce_ciphertext.as_ref();
+        if Self::version_of(nonce_ciphertext.as_ref()) == Some(self.version) {
+            return Ok(None);
+        }
+
+        let plaintext = self.decrypt(nonce_ciphertext)?;
+
+        Ok(Some(self.encrypt(plaintext)))
+    }
+
+    fn version_of(nonce_ciphertext: &[u8]) -> Option<u8> {
+        match nonce_ciphertext {
+            [Self::VERSION_MARKER, version, ..] => Some(*version),
+            _ => None,
+        }
+    }
+
+    fn open(cipher: &Aes256GcmSiv, nc: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if nc.len() < Self::NONCE_SIZE {
+            return Err(CryptoError::Decryption);
+        }
+
         let nonce = Nonce::from_slice(&nc[..Self::NONCE_SIZE]);
 
-        self.cipher.decrypt(nonce, &nc[Self::NONCE_SIZE..]).unwrap()
+        cipher
+            .decrypt(nonce, &nc[Self::NONCE_SIZE..])
+            .map_err(|_| CryptoError::Decryption)
     }
 }
 
+/// Deserializes a comma separated list of versioned keys in the format `<version>:<key>`
+pub fn de_keys<'de, D>(d: D) -> Result<Vec<(u8, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let input = String::deserialize(d)?;
+
+    input
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let (version, key) = v
+                .split_once(':')
+                .ok_or_else(|| serde::de::Error::custom("key version is missing"))?;
+            let version = version.parse().map_err(serde::de::Error::custom)?;
+
+            Ok((version, key.to_string()))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     const AEAD_KEY: &str = "Dhh0uAQDDQO90882bbZbyz1jWf4MrxI2";
+    const AEAD_KEY_NEW: &str = "u7Rk2pQx9WmZc4Lb1NvYs8Ea3TgHd6Jf";
 
     #[test]
     fn encrypt_decrypt() {
-        let aead = Aead256::new(AEAD_KEY).unwrap();
+        let aead = Aead256::new(1, AEAD_KEY).unwrap();
 
         let input = "foo bar";
 
         let nonce_cipher = aead.encrypt(input);
 
-        let plaintext = aead.decrypt(nonce_cipher);
+        let plaintext = aead.decrypt(nonce_cipher).unwrap();
 
         assert_eq!(input.as_bytes(), plaintext);
     }
+
+    #[test]
+    fn rotate_key() {
+        let old = Aead256::new(1, AEAD_KEY).unwrap();
+        let mut new = Aead256::new(2, AEAD_KEY_NEW).unwrap();
+        new.add_previous_key(1, AEAD_KEY).unwrap();
+
+        let nonce_cipher = old.encrypt("foo bar");
+        assert_eq!(new.decrypt(&nonce_cipher).unwrap(), b"foo bar");
+
+        let reencrypted = new.reencrypt(&nonce_cipher).unwrap().unwrap();
+        assert!(new.reencrypt(&reencrypted).unwrap().is_none());
+        assert!(matches!(
+            old.decrypt(&reencrypted),
+            Err(CryptoError::UnknownKeyVersion(2))
+        ));
+    }
 }
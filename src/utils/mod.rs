@@ -1,16 +1,38 @@
 pub(crate) mod crypto;
 
+use std::future::Future;
+
 use tokio::{
     signal::unix::{signal, SignalKind},
     sync::broadcast::{self, Sender},
+    task::JoinHandle,
 };
+use tracing::Instrument;
+
+/// Spawns a background task with a name.
+///
+/// The name is visible in tokio-console if built with `--cfg tokio_unstable` and is
+/// always attached to the events of the task.
+pub fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let future = future.instrument(tracing::info_span!("task", name));
+
+    #[cfg(tokio_unstable)]
+    return tokio::task::Builder::new().name(name).spawn(future);
+
+    #[cfg(not(tokio_unstable))]
+    tokio::spawn(future)
+}
 
 pub fn shutdown_signal(rx_count: usize) -> Sender<()> {
     let (tx, _) = broadcast::channel(rx_count);
 
     let tx2 = tx.clone();
 
-    tokio::spawn(async move {
+    spawn_named("shutdown-signal", async move {
         let mut sig_int = signal(SignalKind::interrupt()).unwrap();
         let mut sig_term = signal(SignalKind::terminate()).unwrap();
 
@@ -28,3 +50,17 @@ pub fn shutdown_signal(rx_count: usize) -> Sender<()> {
 pub fn get_email_domain(addr: &str) -> Option<&str> {
     addr.splitn(2, '@').last()
 }
+
+/// Pushes the text with the HTML special characters escaped
+pub fn push_escaped(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Verification of session tokens against the published key set, e.g. in edge workers.
+//!
+//! Builds for `wasm32-unknown-unknown` with `default-features = false, features = ["verify"]`.
+//! There is no clock on that target, so the caller passes the current time:
+//!
+//! ```no_run
+//! # fn example(jwks: &str, token: &str, now: u64) -> Result<(), identity_server::verify::VerifyError> {
+//! use identity_server::verify::Verifier;
+//!
+//! // Body of /.well-known/jwks.json, cached by the worker
+//! let verifier = Verifier::from_jwks(jwks, &["api.example.com"])?;
+//! let session = verifier.verify(token, now)?;
+//! println!("{} with {:?}", session.sub, session.scope);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum VerifyError {
+    #[error("key set is invalid: {0}")]
+    InvalidKeySet(String),
+    #[error("token is signed with an unknown key")]
+    UnknownKey,
+    #[error("token is invalid")]
+    Invalid,
+    #[error("token has wrong type")]
+    WrongType,
+    #[error("token is expired")]
+    Expired,
+}
+
+/// Claims of a verified session token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionToken {
+    pub sub: String,
+    pub aud: Vec<String>,
+    /// Expiration in seconds since the epoch
+    pub exp: u64,
+    /// Issuance in seconds since the epoch
+    pub iat: u64,
+    #[serde(default)]
+    pub jti: Option<String>,
+    #[serde(default)]
+    pub scope: Vec<String>,
+    /// Issued by a fallback login during an SSO outage, clients may show a banner
+    #[serde(default)]
+    pub fallback: bool,
+    token_type: String,
+}
+
+#[derive(Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kid: String,
+    alg: Algorithm,
+    kty: String,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+impl Jwk {
+    fn decoding_key(&self) -> Result<DecodingKey, String> {
+        let decode = |v: &Option<String>, name| {
+            let v = v
+                .as_ref()
+                .ok_or(format!("key {} has no {}", self.kid, name))?;
+            base64::decode_config(v, base64::URL_SAFE_NO_PAD)
+                .map_err(|e| format!("key {} has an invalid {}: {}", self.kid, name, e))
+        };
+
+        match (self.kty.as_str(), self.crv.as_deref(), self.alg) {
+            ("EC", Some("P-256"), Algorithm::ES256) => {
+                // Uncompressed point, as expected by ring
+                let point = [vec![0x04], decode(&self.x, "x")?, decode(&self.y, "y")?].concat();
+                Ok(DecodingKey::from_ec_der(&point))
+            }
+            ("RSA", _, Algorithm::RS256) => Ok(DecodingKey::from_rsa_raw_components(
+                &decode(&self.n, "n")?,
+                &decode(&self.e, "e")?,
+            )),
+            ("OKP", Some("Ed25519"), Algorithm::EdDSA) => {
+                Ok(DecodingKey::from_ed_der(&decode(&self.x, "x")?))
+            }
+            (kty, _, alg) => Err(format!(
+                "key {} of type {} with {:?} is not supported",
+                self.kid, kty, alg
+            )),
+        }
+    }
+}
+
+/// Verifies session tokens without calling the server
+pub struct Verifier {
+    keys: HashMap<String, (Algorithm, DecodingKey)>,
+    audience: Vec<String>,
+}
+
+impl Verifier {
+    /// Same leeway as the server
+    const LEEWAY: u64 = 10;
+
+    /// Parses the key set, tokens must be issued for one of the audiences
+    pub fn from_jwks(jwks: &str, audience: &[&str]) -> Result<Self, VerifyError> {
+        let set: JwkSet =
+            serde_json::from_str(jwks).map_err(|e| VerifyError::InvalidKeySet(e.to_string()))?;
+
+        let keys = set
+            .keys
+            .iter()
+            .map(|jwk| Ok((jwk.kid.clone(), (jwk.alg, jwk.decoding_key()?))))
+            .collect::<Result<_, String>>()
+            .map_err(VerifyError::InvalidKeySet)?;
+
+        Ok(Self {
+            keys,
+            audience: audience.iter().map(ToString::to_string).collect(),
+        })
+    }
+
+    /// Verifies the signature and claims of a session token at `now`, in seconds since the
+    /// epoch. Revocation is not checked, it requires the server.
+    pub fn verify(&self, token: &str, now: u64) -> Result<SessionToken, VerifyError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| VerifyError::Invalid)?;
+        let kid = header.kid.ok_or(VerifyError::UnknownKey)?;
+        let (alg, key) = self.keys.get(&kid).ok_or(VerifyError::UnknownKey)?;
+
+        // Pinned per key, like on the server
+        if header.alg != *alg {
+            return Err(VerifyError::Invalid);
+        }
+
+        // `jsonwebtoken::decode` reads the system clock, which panics on wasm32
+        let (message, signature) = token.rsplit_once('.').ok_or(VerifyError::Invalid)?;
+        let valid = jsonwebtoken::crypto::verify(signature, message.as_bytes(), key, *alg)
+            .map_err(|_| VerifyError::Invalid)?;
+        if !valid {
+            return Err(VerifyError::Invalid);
+        }
+
+        let payload = message.split('.').nth(1).ok_or(VerifyError::Invalid)?;
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| VerifyError::Invalid)?;
+        let claims: SessionToken =
+            serde_json::from_slice(&payload).map_err(|_| VerifyError::Invalid)?;
+
+        if claims.token_type != "session" {
+            return Err(VerifyError::WrongType);
+        }
+        if !claims.aud.iter().any(|a| self.audience.contains(a)) {
+            return Err(VerifyError::Invalid);
+        }
+        if claims.exp + Self::LEEWAY <= now {
+            return Err(VerifyError::Expired);
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+
+    use crate::{
+        authentication::token::{TokenClaims, TokenConfig},
+        session::SessionClaims,
+    };
+
+    use chrono::{Duration, Utc};
+
+    fn config() -> TokenConfig {
+        TokenConfig::from_secret("secret", ["test"])
+            .with_signing_key(
+                include_str!("authentication/testdata/rsa-2048.pem"),
+                Algorithm::RS256,
+            )
+            .unwrap()
+    }
+
+    fn verifier(config: &TokenConfig, audience: &str) -> Verifier {
+        let jwks = serde_json::to_string(&config.jwks()).unwrap();
+
+        Verifier::from_jwks(&jwks, &[audience]).unwrap()
+    }
+
+    #[test]
+    fn verifies_session_token() {
+        let config = config();
+        let issued = Utc::now();
+        let token = SessionClaims::new(["test".to_string()], "user", issued)
+            .encode(&config)
+            .unwrap();
+
+        let now = issued.timestamp() as u64;
+        let session = verifier(&config, "test").verify(&token, now).unwrap();
+        assert_eq!(session.sub, "user");
+
+        let later = (issued + Duration::minutes(SessionClaims::DEFAULT_EXP_MIN)).timestamp();
+        assert!(matches!(
+            verifier(&config, "test").verify(&token, later as u64 + Verifier::LEEWAY),
+            Err(VerifyError::Expired)
+        ));
+        assert!(matches!(
+            verifier(&config, "other").verify(&token, now),
+            Err(VerifyError::Invalid)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let legacy = TokenConfig::from_secret("secret", ["test"]);
+        let token = SessionClaims::new(["test".to_string()], "user", Utc::now())
+            .encode(&legacy)
+            .unwrap();
+
+        assert!(matches!(
+            verifier(&config(), "test").verify(&token, 0),
+            Err(VerifyError::UnknownKey)
+        ));
+    }
+}
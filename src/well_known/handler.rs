@@ -0,0 +1,26 @@
+use super::Configuration;
+
+use crate::{
+    authentication::token::{JwkSet, TokenConfig},
+    config::GlobalConfig,
+    model::{Response, Status},
+};
+
+use axum::extract::Extension;
+use hyper::StatusCode;
+
+/// Public keys of issued tokens, empty if they are signed with the shared secret
+pub async fn jwks(Extension(config): Extension<TokenConfig>) -> Response<JwkSet> {
+    Response::new(config.jwks())
+}
+
+/// Discovery document, only served if an issuer URL is configured
+pub async fn openid_configuration(
+    Extension(global): Extension<GlobalConfig>,
+    Extension(config): Extension<TokenConfig>,
+) -> Result<Response<Configuration>, Status> {
+    let configuration = super::configuration(&global, &config)
+        .ok_or_else(|| Status::new(StatusCode::NOT_FOUND, "OpenID Connect is not enabled"))?;
+
+    Ok(Response::new(configuration))
+}
@@ -0,0 +1,73 @@
+//! Documents under `/.well-known` for resource servers that verify issued tokens, and for
+//! relying parties of OpenID Connect
+
+mod handler;
+mod routes;
+
+use crate::{authentication::token::TokenConfig, config::GlobalConfig, oauth::DEVICE_CODE_GRANT};
+
+use reqwest::Url;
+use serde::Serialize;
+
+pub use routes::routes;
+
+/// OpenID provider metadata, named as in OpenID Connect Discovery 1.0
+#[derive(Debug, Clone, Serialize)]
+pub struct Configuration {
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    userinfo_endpoint: Url,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_authorization_endpoint: Option<Url>,
+    jwks_uri: Url,
+    scopes_supported: [&'static str; 1],
+    response_types_supported: [&'static str; 1],
+    grant_types_supported: Vec<&'static str>,
+    subject_types_supported: [&'static str; 1],
+    id_token_signing_alg_values_supported: Vec<String>,
+    code_challenge_methods_supported: [&'static str; 1],
+    token_endpoint_auth_methods_supported: [&'static str; 2],
+    claims_supported: [&'static str; 7],
+}
+
+/// Provider metadata of the configured issuer, OpenID Connect is disabled without one
+pub fn configuration(global: &GlobalConfig, config: &TokenConfig) -> Option<Configuration> {
+    let issuer = global.issuer_url.as_ref()?;
+
+    // Endpoints are relative to the issuer, which may have a path
+    let mut base = issuer.clone();
+    if !base.path().ends_with('/') {
+        base.set_path(&format!("{}/", base.path()));
+    }
+    let endpoint = |path: &str| base.join(path).unwrap();
+    let device = global.device_verification_url.is_some();
+
+    let configuration = Configuration {
+        issuer: issuer.as_str().trim_end_matches('/').to_string(),
+        authorization_endpoint: endpoint("oauth/authorize"),
+        token_endpoint: endpoint("oauth/token"),
+        userinfo_endpoint: endpoint("oauth/userinfo"),
+        device_authorization_endpoint: device.then(|| endpoint("oauth/device/code")),
+        jwks_uri: endpoint(".well-known/jwks.json"),
+        scopes_supported: ["openid"],
+        response_types_supported: ["code"],
+        grant_types_supported: if device {
+            vec!["authorization_code", DEVICE_CODE_GRANT]
+        } else {
+            vec!["authorization_code"]
+        },
+        subject_types_supported: ["public"],
+        id_token_signing_alg_values_supported: config
+            .validation
+            .algorithms
+            .iter()
+            .map(|a| format!("{:?}", a))
+            .collect(),
+        code_challenge_methods_supported: ["S256"],
+        token_endpoint_auth_methods_supported: ["none", "client_secret_post"],
+        claims_supported: ["iss", "sub", "aud", "exp", "iat", "email", "email_verified"],
+    };
+
+    Some(configuration)
+}
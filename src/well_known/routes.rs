@@ -0,0 +1,10 @@
+use super::handler;
+
+use axum::routing::get;
+
+/// Well-known routes
+pub fn routes() -> axum::Router {
+    axum::Router::new()
+        .route("/jwks.json", get(handler::jwks))
+        .route("/openid-configuration", get(handler::openid_configuration))
+}
@@ -0,0 +1,155 @@
+use crate::harness::{json, session_token, TestEnv};
+
+use hyper::StatusCode;
+use jsonwebtoken::{DecodingKey, Validation};
+use serde_json::{json, Value};
+
+const EDITOR_SCOPE: [&str; 4] = ["clientRead", "clientWrite", "serviceRead", "serviceWrite"];
+
+#[tokio::test]
+async fn client_lifecycle_and_token_issuance() {
+    let env = TestEnv::start().await;
+
+    let editor = env
+        .insert_user("editor@example.com", &["clientEditor", "serviceEditor"])
+        .await;
+    let editor = session_token(&editor, &EDITOR_SCOPE);
+    let owner = env.insert_user("owner@example.com", &[]).await;
+    let owner = session_token(&owner, &[]);
+    let other = env.insert_user("other@example.com", &[]).await;
+    let other = session_token(&other, &[]);
+
+    let res = env
+        .http
+        .post(env.url("/service"))
+        .bearer_auth(&editor)
+        .json(&json!({
+            "name": "api",
+            "audience": ["api"],
+            "scope": ["read", "write"],
+            "scopeDefault": ["read"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let service = json(res).await["id"].as_str().unwrap().to_string();
+
+    let res = env
+        .http
+        .post(env.url("/client"))
+        .bearer_auth(&owner)
+        .json(&json!({ "name": "cli", "service": service }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let client = json(res).await;
+    assert_eq!(client["unlocked"], false);
+    assert_eq!(client["scope"], json!(["read"]));
+    let client = client["id"].as_str().unwrap().to_string();
+    let client_url = env.url(&format!("/client/{}", client));
+
+    let res = env
+        .http
+        .get(&client_url)
+        .bearer_auth(&other)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = env
+        .http
+        .post(env.url("/token"))
+        .bearer_auth(&owner)
+        .json(&json!({ "client": client }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Only editors can unlock clients, the owner update is ignored
+    let res = env
+        .http
+        .patch(&client_url)
+        .bearer_auth(&owner)
+        .json(&json!({ "name": "renamed", "unlocked": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated = json(res).await;
+    assert_eq!(updated["name"], "renamed");
+    assert_eq!(updated["unlocked"], false);
+
+    let res = env
+        .http
+        .patch(&client_url)
+        .bearer_auth(&editor)
+        .json(&json!({ "unlocked": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = env
+        .http
+        .post(env.url("/token"))
+        .bearer_auth(&owner)
+        .json(&json!({ "client": client }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let client_token = json(res).await["token"].as_str().unwrap().to_string();
+
+    let res = env
+        .http
+        .get(env.url("/token"))
+        .bearer_auth(&client_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let service_token = json(res).await["token"].as_str().unwrap().to_string();
+
+    let mut validation = Validation::default();
+    validation.set_audience(&["api"]);
+    let claims = jsonwebtoken::decode::<Value>(
+        &service_token,
+        &DecodingKey::from_secret(b"e2e-secret"),
+        &validation,
+    )
+    .unwrap()
+    .claims;
+    assert_eq!(claims["sub"], client.as_str());
+    assert_eq!(claims["scope"], json!(["read"]));
+
+    let res = env
+        .http
+        .delete(&client_url)
+        .bearer_auth(&owner)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = env
+        .http
+        .delete(&client_url)
+        .bearer_auth(&editor)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = env
+        .http
+        .get(&client_url)
+        .bearer_auth(&editor)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,263 @@
+//! Runs the server binary against a MongoDB container and a mock Mailgun API, builds with the
+//! `cache` feature get a Redis container as well.
+//!
+//! Containers are run with the `docker` CLI rather than a container library, the suite only
+//! needs to start a container, read its published port and remove it.
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use axum::{routing::post, Json, Router, Server};
+use chrono::Utc;
+use jsonwebtoken::{EncodingKey, Header};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime as BsonDateTime},
+    Client,
+};
+use serde_json::{json, Value};
+
+pub const AUDIENCE: &str = "e2e";
+const JWT_SECRET: &str = "e2e-secret";
+const CRYPTO_KEY: &str = "0123456789abcdef0123456789abcdef";
+const MONGO_IMAGE: &str = "mongo:5";
+#[cfg(feature = "cache")]
+const REDIS_IMAGE: &str = "redis:7";
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Container that is removed on drop
+struct Container {
+    id: String,
+    /// Published address of the port
+    addr: String,
+}
+
+impl Container {
+    fn start(image: &str, port: u16) -> Self {
+        let output = Command::new("docker")
+            .args(["run", "-d", "--rm", "-p"])
+            .arg(format!("127.0.0.1::{}", port))
+            .arg(image)
+            .output()
+            .expect("docker is not available");
+        assert!(
+            output.status.success(),
+            "could not start {} container",
+            image
+        );
+        let id = String::from_utf8(output.stdout).unwrap().trim().to_string();
+
+        let output = Command::new("docker")
+            .args(["port", &id, &format!("{}/tcp", port)])
+            .output()
+            .unwrap();
+        let addr = String::from_utf8(output.stdout).unwrap();
+        let addr = addr
+            .lines()
+            .next()
+            .expect("container port is not published")
+            .trim()
+            .to_string();
+
+        Self { id, addr }
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        let _ = Command::new("docker")
+            .args(["rm", "-f", &self.id])
+            .stdout(Stdio::null())
+            .status();
+    }
+}
+
+/// Server process that is killed on drop
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+pub struct TestEnv {
+    pub base: String,
+    pub http: reqwest::Client,
+    pub db: mongodb::Database,
+    addr: SocketAddr,
+    // Dropped in order, the server has to stop before its database
+    _server: Process,
+    _mongo: Container,
+    #[cfg(feature = "cache")]
+    _redis: Container,
+}
+
+impl TestEnv {
+    pub async fn start() -> Self {
+        Self::start_with(&[]).await
+    }
+
+    /// Starts the server with additional environment variables, e.g. for sandbox mode
+    pub async fn start_with(vars: &[(&str, &str)]) -> Self {
+        let mongo = Container::start(MONGO_IMAGE, 27017);
+        let mongo_uri = format!("mongodb://{}", mongo.addr);
+        let db = Client::with_uri_str(&mongo_uri)
+            .await
+            .unwrap()
+            .database("identity");
+        wait_for(|| async { db.run_command(doc! { "ping": 1 }, None).await.is_ok() }).await;
+
+        let mail = mock_mailgun();
+        let port = free_port();
+        let workdir = std::env::temp_dir();
+
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_identity-server"));
+        #[cfg(feature = "cache")]
+        let redis = {
+            let redis = Container::start(REDIS_IMAGE, 6379);
+            let addr = redis.addr.clone();
+            wait_for(|| async { TcpStream::connect(&addr).is_ok() }).await;
+            cmd.env("IDENTITY_CACHE_URL", format!("redis://{}", redis.addr));
+            redis
+        };
+
+        let child = cmd
+            .current_dir(workdir)
+            .envs(vars.iter().copied())
+            .env("IDENTITY_SERVER_PORT", port.to_string())
+            .env("IDENTITY_MONGO_URI", &mongo_uri)
+            .env("IDENTITY_MONGO_DB", "identity")
+            .env("IDENTITY_MAIL_FROM", "identity@example.com")
+            .env("IDENTITY_MG_REGION", "eu")
+            .env("IDENTITY_MG_DOMAIN", "example.com")
+            .env("IDENTITY_MG_KEY", "key")
+            .env("IDENTITY_MG_BASE_URL", format!("http://{}/", mail))
+            .env("IDENTITY_GH_CLIENT_ID", "id")
+            .env("IDENTITY_GH_CLIENT_SECRET", "secret")
+            .env("IDENTITY_GH_REDIRECT_URI", "http://localhost/callback")
+            .env("IDENTITY_JWT_SECRET", JWT_SECRET)
+            .env("IDENTITY_JWT_AUDIENCE", AUDIENCE)
+            .env("IDENTITY_CRYPTO_KEY", CRYPTO_KEY)
+            .env("IDENTITY_EDITOR_MAIL_ADDRESS", "editor@example.com")
+            .env("IDENTITY_ALLOWED_DOMAINS", "example.com")
+            .env("IDENTITY_HIBP_CHECK", "false")
+            .spawn()
+            .expect("server binary could not be started");
+        let server = Process(child);
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        wait_for(|| async move { TcpStream::connect(addr).is_ok() }).await;
+
+        Self {
+            base: format!("http://{}/v1", addr),
+            http: reqwest::Client::new(),
+            db,
+            addr,
+            _server: server,
+            _mongo: mongo,
+            #[cfg(feature = "cache")]
+            _redis: redis,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    /// URL of a redirect location, which is a path of the server
+    pub fn location(&self, location: &str) -> String {
+        format!("http://{}{}", self.addr, location)
+    }
+
+    /// Inserts a verified user with the given roles
+    pub async fn insert_user(&self, email: &str, roles: &[&str]) -> String {
+        let id = ObjectId::new();
+        let user = doc! {
+            "_id": id,
+            "email": email,
+            "name": null,
+            "password": null,
+            "roles": roles,
+            "verified": true,
+            "canLogin": true,
+            "connections": [],
+            "lastSessions": [],
+            "lastModified": BsonDateTime::now(),
+        };
+
+        self.db
+            .collection("users")
+            .insert_one(user, None)
+            .await
+            .unwrap();
+
+        id.to_hex()
+    }
+}
+
+/// Creates a session token like the server would issue it
+pub fn session_token(user: &str, scope: &[&str]) -> String {
+    // Issued in the past, so a revocation in the same second covers the token
+    let iat = Utc::now().timestamp() - 5;
+    let claims = json!({
+        "aud": [AUDIENCE],
+        "exp": iat + 3600,
+        "iat": iat,
+        "sub": user,
+        "scope": scope,
+        "tokenType": "session",
+    });
+
+    jsonwebtoken::encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+/// Serves a Mailgun API that accepts every message
+fn mock_mailgun() -> SocketAddr {
+    let app = Router::new().route(
+        "/v3/:domain/messages",
+        post(|| async { Json(json!({ "id": "<e2e>", "message": "Queued. Thank you." })) }),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+
+    addr
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn wait_for<F, Fut>(check: F)
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = bool>,
+{
+    let start = Instant::now();
+
+    while !check().await {
+        assert!(start.elapsed() < STARTUP_TIMEOUT, "startup timed out");
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+pub async fn json(res: reqwest::Response) -> Value {
+    res.json().await.unwrap()
+}
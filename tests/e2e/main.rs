@@ -0,0 +1,9 @@
+//! End-to-end tests against the server binary.
+//!
+//! Requires Docker to start the MongoDB container, and the Redis container in builds with the
+//! `cache` feature, run with `cargo test --features e2e`.
+
+mod client;
+mod harness;
+mod session;
+mod sso;
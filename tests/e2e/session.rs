@@ -0,0 +1,54 @@
+use crate::harness::{json, session_token, TestEnv};
+
+use hyper::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn revoke_all_sessions_keeps_current() {
+    let env = TestEnv::start().await;
+
+    let user = env.insert_user("user@example.com", &[]).await;
+    let token = session_token(&user, &[]);
+    let user_url = env.url(&format!("/user/{}", user));
+
+    let res = env
+        .http
+        .get(&user_url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = env
+        .http
+        .post(env.url("/user/me/sessions/revoke-all"))
+        .bearer_auth(&token)
+        .json(&json!({ "excludeCurrent": true }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let current = json(res).await["session"]["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let res = env
+        .http
+        .get(&user_url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = env
+        .http
+        .get(&user_url)
+        .bearer_auth(&current)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
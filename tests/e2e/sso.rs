@@ -0,0 +1,114 @@
+use crate::harness::{json, TestEnv};
+
+use futures::TryStreamExt;
+use hyper::{
+    header::{COOKIE, LOCATION, SET_COOKIE},
+    StatusCode,
+};
+use mongodb::bson::{doc, Document};
+use reqwest::redirect::Policy;
+use serde_json::Value;
+
+const EMAIL: &str = "player@example.com";
+
+/// Runs the login of the sandbox provider up to the redirect, returns the callback and its cookie
+async fn authorize(env: &TestEnv, http: &reqwest::Client) -> (String, String) {
+    let res = http
+        .get(env.url("/sso/sandbox/authorize"))
+        .query(&[("email", EMAIL)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+
+    let callback = env.location(res.headers()[LOCATION].to_str().unwrap());
+    let cookie = res.headers()[SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap().to_string();
+
+    (callback, cookie)
+}
+
+async fn callback(http: &reqwest::Client, callback: &str, cookie: &str) -> reqwest::Response {
+    http.get(callback)
+        .header(COOKIE, cookie)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sandbox_login_issues_session() {
+    let env = TestEnv::start_with(&[("IDENTITY_SANDBOX", "true")]).await;
+    let http = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap();
+
+    let (url, cookie) = authorize(&env, &http).await;
+    let res = callback(&http, &url, &cookie).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let session: Value = json(res).await;
+    let user = session["user"].as_str().unwrap();
+
+    let res = env
+        .http
+        .get(env.url(&format!("/user/{}", user)))
+        .bearer_auth(session["token"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json(res).await["email"], EMAIL);
+
+    // The state is valid once
+    let res = callback(&http, &url, &cookie).await;
+    assert!(res.status().is_client_error());
+
+    // The next login signs in the same user
+    let (url, cookie) = authorize(&env, &http).await;
+    let res = callback(&http, &url, &cookie).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(json(res).await["user"], user);
+
+    let users: Vec<Document> = env
+        .db
+        .collection("users")
+        .find(doc! { "email": EMAIL }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].get_array("connections").unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn concurrent_logins_create_one_user() {
+    let env = TestEnv::start_with(&[("IDENTITY_SANDBOX", "true")]).await;
+    let http = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap();
+
+    // Both callbacks race to create the user of the same address
+    let (a, b) = tokio::join!(authorize(&env, &http), authorize(&env, &http));
+    let (a, b) = tokio::join!(callback(&http, &a.0, &a.1), callback(&http, &b.0, &b.1));
+    assert_eq!(a.status(), StatusCode::CREATED);
+    assert_eq!(b.status(), StatusCode::CREATED);
+
+    let (a, b) = tokio::join!(json(a), json(b));
+    assert_eq!(a["user"], b["user"]);
+
+    let users: Vec<Document> = env
+        .db
+        .collection("users")
+        .find(doc! { "email": EMAIL }, None)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].get_array("connections").unwrap().len(), 1);
+}